@@ -0,0 +1,182 @@
+// Reports which build profile is being compiled. Build scripts run before
+// linking, so actual sizes come from the linked image, e.g.
+//
+//     cargo build --release && espflash save-image --chip esp32c3 target/riscv32imc-unknown-none-elf/release/esp32c3_embedded-tls full.bin
+//     cargo build --release --no-default-features --features minimal && espflash save-image ... minimal.bin
+//
+// and comparing the two `.bin` sizes.
+//
+// It passes on esp-wifi's heap size from cfg.toml as WIFI_HEAP_SIZE, for
+// src/wifiheap.rs.
+//
+// With `dev` it warns on every build, so a bench build is hard to mistake
+// for one to ship; with `release-prod` it fails on what only a bench build
+// should have, like the placeholder OTA signing key.
+//
+// It also fills in src/buildinfo.rs through `BUILD_*` variables for `env!`.
+// They come out the same for the same source: the time is
+// SOURCE_DATE_EPOCH if set, else the commit's, never the clock's, and the
+// feature list is whatever Cargo enabled. Without git, the commit fields
+// say `unknown`.
+
+use std::process::Command;
+use std::{env, fs};
+
+fn main() {
+    let enabled = |feature: &str| env::var_os(format!("CARGO_FEATURE_{}", feature)).is_some();
+
+    build_info();
+    wifi_heap_size();
+
+    let profile = match (enabled("MINIMAL"), enabled("FULL")) {
+        (true, false) => "minimal",
+        (false, true) => "full",
+        // main.rs refuses to compile these with a clearer message.
+        _ => return,
+    };
+
+    // Every feature enabled but the profiles.
+    let mut optional: Vec<String> = features()
+        .into_iter()
+        .filter(|feature| !["default", "minimal", "full"].contains(&feature.as_str()))
+        .collect();
+    if optional.is_empty() {
+        optional.push("none".into());
+    }
+
+    println!(
+        "cargo:warning=building the `{}` profile (optional modules: {})",
+        profile,
+        optional.join(", ")
+    );
+
+    if enabled("DEV") {
+        println!("cargo:warning=Running in DEV mode - not for production!");
+    }
+
+    if enabled("OTA") {
+        println!("cargo:rerun-if-changed=keys/ota_signing.pub");
+        let placeholder =
+            fs::read("keys/ota_signing.pub").map_or(true, |key| key.iter().all(|&b| b == 0));
+        if placeholder && enabled("RELEASE_PROD") {
+            panic!("keys/ota_signing.pub is the all-zero placeholder; a `release-prod` build needs the real key");
+        }
+        if placeholder {
+            println!("cargo:warning=keys/ota_signing.pub is the all-zero placeholder: every update will be refused");
+        }
+    }
+}
+
+fn build_info() {
+    // `--dirty` depends on every tracked file, not just the commit.
+    for path in ["build.rs", "Cargo.toml", "src", "keys"] {
+        println!("cargo:rerun-if-changed={}", path);
+    }
+    if let Some(git_dir) = git(&["rev-parse", "--git-dir"]) {
+        for path in ["HEAD", "index", "refs"] {
+            println!("cargo:rerun-if-changed={}/{}", git_dir, path);
+        }
+    }
+    println!("cargo:rerun-if-env-changed=SOURCE_DATE_EPOCH");
+
+    let describe = git(&["describe", "--always", "--dirty", "--tags"]);
+    let hash = git(&["rev-parse", "HEAD"]);
+    let epoch = env::var("SOURCE_DATE_EPOCH")
+        .ok()
+        .or_else(|| git(&["log", "-1", "--format=%ct"]))
+        .and_then(|s| s.trim().parse::<i64>().ok())
+        .unwrap_or(0);
+    let rustc = Command::new(env::var("RUSTC").unwrap_or_else(|_| "rustc".into()))
+        .arg("-V")
+        .output()
+        .ok()
+        .and_then(|out| String::from_utf8(out.stdout).ok());
+
+    let features = features();
+
+    let vars = [
+        (
+            "BUILD_GIT_DESCRIBE",
+            describe.as_deref().unwrap_or("unknown"),
+        ),
+        ("BUILD_GIT_HASH", hash.as_deref().unwrap_or("unknown")),
+        ("BUILD_TIMESTAMP", &utc(epoch)),
+        ("BUILD_RUSTC", rustc.as_deref().unwrap_or("unknown").trim()),
+        ("BUILD_FEATURES", &features.join(",")),
+    ];
+    for (name, value) in vars {
+        println!("cargo:rustc-env={}={}", name, plain(value));
+    }
+}
+
+/// The features Cargo enabled, by their names in Cargo.toml, sorted.
+fn features() -> Vec<String> {
+    let mut features: Vec<String> = env::vars()
+        .filter_map(|(key, _)| key.strip_prefix("CARGO_FEATURE_").map(str::to_owned))
+        .map(|name| name.to_lowercase().replace('_', "-"))
+        .collect();
+    features.sort();
+    features
+}
+
+/// `heap_size` under `[esp-wifi]` in cfg.toml, which is where esp-wifi's
+/// toml-cfg takes it from, or esp-wifi's default.
+fn wifi_heap_size() {
+    println!("cargo:rerun-if-changed=cfg.toml");
+    let text = fs::read_to_string("cfg.toml").unwrap_or_default();
+    let mut section = "";
+    let mut size = None;
+    for line in text.lines().map(str::trim) {
+        if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            section = name.trim();
+        } else if let Some((key, value)) = line.split_once('=') {
+            if section == "esp-wifi" && key.trim() == "heap_size" {
+                size = value.trim().parse::<u32>().ok();
+            }
+        }
+    }
+    println!("cargo:rustc-env=WIFI_HEAP_SIZE={}", size.unwrap_or(65536));
+}
+
+fn git(args: &[&str]) -> Option<String> {
+    let out = Command::new("git").args(args).output().ok()?;
+    let text = String::from_utf8(out.stdout).ok()?;
+    (out.status.success() && !text.trim().is_empty()).then(|| text.trim().to_owned())
+}
+
+/// Printable ASCII without `"` and `\`, so the values go into JSON and the
+/// banner as they are.
+fn plain(value: &str) -> String {
+    value
+        .chars()
+        .map(|c| match c {
+            ' '..='~' if c != '"' && c != '\\' => c,
+            _ => '?',
+        })
+        .collect()
+}
+
+/// `seconds` since the Unix epoch as ISO 8601 UTC.
+fn utc(seconds: i64) -> String {
+    let days = seconds.div_euclid(86_400);
+    let time = seconds.rem_euclid(86_400);
+    // Howard Hinnant's days_from_civil, backwards.
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        time / 3600,
+        time / 60 % 60,
+        time % 60
+    )
+}
@@ -0,0 +1,280 @@
+// The firmware's decoders of what arrives from the network, built for the
+// host so that cargo-fuzz can run them, and the other modules that tests/
+// checks. Each module is the firmware's own source file, included by its
+// path: they use nothing but `core`, heapless, embassy-time's `Instant`,
+// embassy-sync, critical-section, embedded-hal's traits and one another,
+// so they build here as they are. `settings`, `sntp` and `power` stand in
+// for the little the modules take from the firmware's: a constant, the
+// fields the maintenance window reads, the wall clock and the PMIC's
+// status; `integrity` for the SHA accelerator, and `rom` for the CRC-32 of
+// the chip's ROM, as `esp_hal::rom`.
+//
+// One function per target in fuzz_targets/. Each feeds the fuzzer's bytes
+// to a decoder and checks what comes back against the limits it promises,
+// so a run finds a decoder that breaks one as well as one that panics:
+//
+//     cargo fuzz run http_request
+//
+// corpus/<target>/ holds the inputs each one starts from, written by hand
+// after real traffic, and as `regression-*` the inputs that got past a
+// decoder before it was fixed; `-runs=0` replays them once as a check:
+//
+//     cargo fuzz run http_request -- -runs=0
+//
+// tests/ holds plain `cargo test` tests over these modules, one file per
+// firmware module, and regressions.rs for the `regression-*` inputs.
+
+// The firmware is built with a mid-2024 toolchain, which has no
+// `is_multiple_of` for the shared sources to use.
+#![allow(clippy::manual_is_multiple_of)]
+
+#[path = "../../src/batch.rs"]
+pub mod batch;
+#[path = "../../src/canaryscan.rs"]
+pub mod canaryscan;
+#[path = "../../src/clock.rs"]
+pub mod clock;
+#[path = "../../src/codec.rs"]
+pub mod codec;
+#[path = "../../src/crashrecord.rs"]
+pub mod crashrecord;
+#[path = "../../src/deflate.rs"]
+pub mod deflate;
+#[path = "../../src/dns.rs"]
+pub mod dns;
+#[path = "../../src/fleetorder.rs"]
+pub mod fleetorder;
+#[path = "../../src/headers.rs"]
+pub mod headers;
+#[path = "../../src/http.rs"]
+pub mod http;
+#[path = "../../src/httpsbody.rs"]
+pub mod httpsbody;
+#[path = "../../src/json.rs"]
+pub mod json;
+#[path = "../../src/maintenance.rs"]
+pub mod maintenance;
+#[path = "../../src/monotimeanchor.rs"]
+pub mod monotimeanchor;
+#[path = "../../src/multipart.rs"]
+pub mod multipart;
+#[path = "../../src/ntp.rs"]
+pub mod ntp;
+#[path = "../../src/oneshotline.rs"]
+pub mod oneshotline;
+#[path = "../../src/otaprogress.rs"]
+pub mod otaprogress;
+#[path = "../../src/priority.rs"]
+pub mod priority;
+#[path = "../../src/quiethours.rs"]
+pub mod quiethours;
+#[path = "../../src/replayrecord.rs"]
+pub mod replayrecord;
+#[path = "../../src/restartnote.rs"]
+pub mod restartnote;
+#[path = "../../src/rollup.rs"]
+pub mod rollup;
+#[path = "../../src/selftestloopback.rs"]
+pub mod selftestloopback;
+#[path = "../../src/selftestverdict.rs"]
+pub mod selftestverdict;
+#[path = "../../src/sequencecounter.rs"]
+pub mod sequencecounter;
+#[path = "../../src/sntpselect.rs"]
+pub mod sntpselect;
+#[path = "../../src/telemetryschema.rs"]
+pub mod telemetryschema;
+#[path = "../../src/timerqueueentries.rs"]
+pub mod timerqueueentries;
+#[path = "../../src/uploadanswer.rs"]
+pub mod uploadanswer;
+#[path = "../../src/wire.rs"]
+pub mod wire;
+#[path = "../../src/x509.rs"]
+pub mod x509;
+
+extern crate self as esp_hal;
+
+pub mod rom {
+    pub mod crc {
+        /// The ROM's: CRC-32 as zlib computes it, continuing from `crc`.
+        pub fn crc32_le(crc: u32, data: &[u8]) -> u32 {
+            let mut crc = !crc;
+            for &byte in data {
+                crc ^= byte as u32;
+                for _ in 0..8 {
+                    crc = (crc >> 1) ^ (0xEDB8_8320 & (crc & 1).wrapping_neg());
+                }
+            }
+            !crc
+        }
+    }
+}
+
+pub mod integrity {
+    use sha2::Digest;
+
+    pub const HASH_LEN: usize = 32;
+
+    #[derive(Debug)]
+    pub struct IntegrityError;
+
+    /// The accelerator's, in software.
+    pub struct Sha256(sha2::Sha256);
+
+    impl Sha256 {
+        pub fn new() -> Result<Self, IntegrityError> {
+            Ok(Self(sha2::Sha256::new()))
+        }
+
+        pub fn update(&mut self, data: &[u8]) {
+            self.0.update(data);
+        }
+
+        pub fn finish(self) -> [u8; HASH_LEN] {
+            self.0.finalize().into()
+        }
+    }
+
+    pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+        a == b
+    }
+}
+
+pub mod power {
+    /// The PMIC's status, as src/power.rs gives it.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct PowerStatus {
+        pub battery_mv: u32,
+        pub charging: bool,
+    }
+}
+
+pub mod sntp {
+    /// No wall clock here but `Manual`'s.
+    pub fn now_unix_ms() -> Option<u64> {
+        None
+    }
+}
+
+pub mod settings {
+    use crate::maintenance::{NoClockPolicy, Window};
+
+    pub const MAX_URL_LEN: usize = 128;
+
+    pub struct Settings {
+        pub maintenance_window: Option<Window>,
+        pub no_clock_policy: NoClockPolicy,
+    }
+}
+
+/// The host every DNS response is checked against; the corpus asks for it.
+const HOST: &str = "example.com";
+
+/// A response to a query for `HOST`, with whatever ID it carries.
+pub fn dns_response(data: &[u8]) {
+    let id = match data {
+        [a, b, ..] => u16::from_be_bytes([*a, *b]),
+        _ => 0,
+    };
+    let _ = dns::check_response(data, id, HOST);
+}
+
+/// A name at every position in a message, as mDNS reads its questions.
+pub fn dns_name(data: &[u8]) {
+    let mut name = dns::Name::new();
+    for pos in 0..data.len() {
+        if let Ok(end) = dns::read_name(data, pos, &mut name) {
+            assert!(end <= data.len());
+            assert!(name.split(|&b| b == b'.').all(|label| label.len() <= 63));
+        }
+    }
+}
+
+pub fn ntp_response(data: &[u8]) {
+    if ntp::parse_response(data).is_ok() {
+        // Both timestamps are set.
+        assert!(data[32..40] != [0; 8] && data[40..48] != [0; 8]);
+    }
+}
+
+pub fn http_response(data: &[u8]) {
+    let Ok((status, body_start)) = http::parse_head(data) else {
+        return;
+    };
+    assert!(status <= 999 && body_start <= data.len());
+    let Ok(head) = core::str::from_utf8(&data[..body_start]) else {
+        return;
+    };
+    let lines = head
+        .split("\r\n")
+        .skip(1)
+        .take_while(|line| !line.is_empty());
+    assert!(lines.count() <= http::MAX_HEADERS);
+    let _ = http::header(head, "Content-Type");
+    let _ = http::content_length(head);
+    let _ = http::is_chunked(head);
+}
+
+pub fn http_request(data: &[u8]) {
+    let Ok(head) = core::str::from_utf8(data) else {
+        return;
+    };
+    let Ok(parsed) = http::parse_request(head) else {
+        return;
+    };
+    assert!(parsed.target.starts_with('/'));
+    // Every `Content-Length` says the same, in digits only.
+    let mut lengths = head.split("\r\n").skip(1).filter_map(|line| {
+        let (name, value) = line.split_once(':')?;
+        name.eq_ignore_ascii_case("Content-Length")
+            .then(|| value.trim())
+    });
+    assert!(lengths.all(|value| {
+        value.bytes().all(|b| b.is_ascii_digit()) && value.parse() == Ok(parsed.content_length)
+    }));
+}
+
+pub fn dechunk(data: &[u8]) {
+    let mut body = data.to_vec();
+    if let Ok(len) = http::dechunk(&mut body) {
+        assert!(len <= data.len());
+    }
+}
+
+/// `data` is a content type, a line feed, and the body.
+pub fn multipart(data: &[u8]) {
+    let Some(split) = data.iter().position(|&b| b == b'\n') else {
+        return;
+    };
+    let (Ok(content_type), body) = (core::str::from_utf8(&data[..split]), &data[split + 1..])
+    else {
+        return;
+    };
+    let Some(boundary) = multipart::boundary(content_type) else {
+        return;
+    };
+    let mut parser = multipart::MultipartParser::new(boundary, body);
+    let mut parts = 0;
+    while let Some((_, content)) = parser.next_part() {
+        assert!(content.len() <= body.len());
+        // Every part takes at least its delimiter's bytes.
+        parts += 1;
+        assert!(parts <= body.len());
+    }
+}
+
+pub fn json(data: &[u8]) {
+    let _ = json::fields(data, |key, value| {
+        assert!(value.len() <= settings::MAX_URL_LEN && key.len() <= data.len());
+        Ok(())
+    });
+}
+
+pub fn x509(data: &[u8]) {
+    let Ok(cert) = x509::Certificate::parse(data) else {
+        return;
+    };
+    let _ = cert.common_name();
+    assert!(cert.alt_names().count() <= data.len());
+}
@@ -0,0 +1,165 @@
+// Telemetry batching: the policy's settings text, which limit triggers a
+// flush, how much goes out in one, and the size estimate against real
+// batches put together the way the uploader does.
+
+use esp32c3_fuzz::batch::{
+    self, BatchFormat, BatchPolicy, FlushReason, MAX_BATCH_BYTES, MAX_BATCH_COUNT,
+};
+
+fn policy(format: BatchFormat, max_count: u16, max_bytes: u16, max_age_s: u32) -> BatchPolicy {
+    BatchPolicy {
+        format,
+        max_count,
+        max_bytes,
+        max_age_s,
+    }
+}
+
+#[test]
+fn parses_and_prints() {
+    assert_eq!(
+        BatchPolicy::parse("ndjson,8,900,60"),
+        Some(policy(BatchFormat::Ndjson, 8, 900, 60))
+    );
+    let array = BatchPolicy::parse(" array , 16 , 1024 , 0 ").unwrap();
+    assert_eq!(array, policy(BatchFormat::JsonArray, 16, 1024, 0));
+    assert_eq!(array.to_string(), "array,16,1024,0");
+    assert_eq!(BatchPolicy::parse(&array.to_string()), Some(array));
+
+    for bad in [
+        "",
+        "ndjson",
+        "ndjson,8,900",
+        "ndjson,8,900,60,1",
+        "csv,8,900,60",
+        "ndjson,0,900,60",
+        "ndjson,17,900,60",
+        "ndjson,8,0,60",
+        "ndjson,8,1025,60",
+        "ndjson,-1,900,60",
+        "ndjson,8,900,soon",
+    ] {
+        assert_eq!(BatchPolicy::parse(bad), None, "{bad}");
+    }
+    assert_eq!(MAX_BATCH_COUNT, 16);
+    assert_eq!(MAX_BATCH_BYTES, 1024);
+}
+
+#[test]
+fn first_limit_reached_triggers() {
+    let policy = policy(BatchFormat::Ndjson, 4, 500, 30);
+    assert_eq!(policy.flush_reason(0, 0, u64::MAX), None);
+    assert_eq!(policy.flush_reason(3, 499, 29_999), None);
+    assert_eq!(policy.flush_reason(4, 0, 0), Some(FlushReason::Count));
+    assert_eq!(policy.flush_reason(1, 500, 0), Some(FlushReason::Size));
+    assert_eq!(policy.flush_reason(1, 10, 30_000), Some(FlushReason::Age));
+    // All at once: count, then size, then age.
+    assert_eq!(
+        policy.flush_reason(9, 9000, 90_000),
+        Some(FlushReason::Count)
+    );
+    assert_eq!(
+        policy.flush_reason(2, 9000, 90_000),
+        Some(FlushReason::Size)
+    );
+
+    // An age of 0 sends every reading as soon as it is there.
+    let eager = self::policy(BatchFormat::JsonArray, 16, 1024, 0);
+    assert_eq!(eager.flush_reason(1, 100, 0), Some(FlushReason::Age));
+}
+
+/// A batch of `readings` as the uploader writes it.
+fn serialize(format: BatchFormat, readings: &[String]) -> String {
+    match format {
+        BatchFormat::Ndjson => readings.iter().map(|r| format!("{r}\n")).collect(),
+        BatchFormat::JsonArray => format!("[{}]", readings.join(",")),
+    }
+}
+
+fn readings(lens: &[usize]) -> Vec<String> {
+    lens.iter()
+        .map(|&len| format!("{{\"v\":\"{}\"}}", "x".repeat(len - 8)))
+        .collect()
+}
+
+#[test]
+fn encoded_len_is_the_serialized_size() {
+    for format in [BatchFormat::Ndjson, BatchFormat::JsonArray] {
+        assert_eq!(batch::encoded_len(format, [].into_iter()), 0);
+        for lens in [&[8][..], &[100, 8], &[50, 60, 70, 80, 90]] {
+            let readings = readings(lens);
+            assert_eq!(
+                batch::encoded_len(format, readings.iter().map(String::len)),
+                serialize(format, &readings).len(),
+                "{format:?} {lens:?}"
+            );
+        }
+    }
+}
+
+#[test]
+fn batch_len_fits_both_limits() {
+    let lens = [100, 100, 100, 100, 100, 100];
+    // NDJSON: 101 bytes a reading.
+    let ndjson = policy(BatchFormat::Ndjson, 16, 404, 60);
+    assert_eq!(ndjson.batch_len(lens.into_iter()), 4);
+    let ndjson = policy(BatchFormat::Ndjson, 16, 403, 60);
+    assert_eq!(ndjson.batch_len(lens.into_iter()), 3);
+    // An array: 2 brackets and a comma between two.
+    let array = policy(BatchFormat::JsonArray, 16, 405, 60);
+    assert_eq!(array.batch_len(lens.into_iter()), 4);
+    let array = policy(BatchFormat::JsonArray, 16, 404, 60);
+    assert_eq!(array.batch_len(lens.into_iter()), 3);
+
+    let few = policy(BatchFormat::Ndjson, 2, 1024, 60);
+    assert_eq!(few.batch_len(lens.into_iter()), 2);
+    assert_eq!(few.batch_len([].into_iter()), 0);
+}
+
+#[test]
+fn a_reading_too_large_goes_out_alone() {
+    let policy = policy(BatchFormat::JsonArray, 16, 200, 60);
+    assert_eq!(policy.batch_len([900, 10, 10].into_iter()), 1);
+    // The one after it is not held back by it either.
+    assert_eq!(policy.batch_len([10, 900, 10].into_iter()), 1);
+}
+
+#[test]
+fn what_batch_len_takes_is_within_the_limit() {
+    for format in [BatchFormat::Ndjson, BatchFormat::JsonArray] {
+        for max_bytes in [64, 200, 333, 1024] {
+            let policy = policy(format, 16, max_bytes, 60);
+            let lens: Vec<usize> = (0..20).map(|i| 8 + (i * 29) % 90).collect();
+            let count = policy.batch_len(lens.iter().copied());
+            let batch = serialize(format, &readings(&lens[..count]));
+            assert!(count >= 1);
+            assert!(count == 1 || batch.len() <= max_bytes as usize);
+            // One more would not have fitted, or been one too many.
+            let more = serialize(format, &readings(&lens[..count + 1]));
+            assert!(
+                count == 16 || more.len() > max_bytes as usize,
+                "{format:?} {max_bytes}"
+            );
+        }
+    }
+}
+
+#[test]
+fn stats() {
+    assert_eq!(batch::stats().average_tenths(), 0);
+    batch::record_flush(FlushReason::Count, 4);
+    batch::record_flush(FlushReason::Age, 1);
+    batch::record_flush(FlushReason::Age, 2);
+    let stats = batch::stats();
+    assert_eq!(
+        (
+            stats.flushes,
+            stats.readings,
+            stats.by_count,
+            stats.by_size,
+            stats.by_age
+        ),
+        (3, 7, 1, 0, 2)
+    );
+    assert_eq!(stats.average_tenths(), 23);
+}
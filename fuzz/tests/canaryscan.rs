@@ -0,0 +1,133 @@
+// The stack scanning math: how deep a poll went in a painted area, what
+// counts as untouched, each task's worst so far and whom an overwritten
+// canary is blamed on.
+
+use esp32c3_fuzz::canaryscan::{
+    self, StackOverflowReport, TaskStack, CANARY_WORDS, PATTERN, UNTOUCHED_RUN,
+};
+use heapless::Vec;
+
+const USED: u32 = 0xdead_beef;
+
+/// An area read from the top down: `used` written words, then paint.
+fn area(used: usize, painted: usize) -> Vec<u32, 256> {
+    let mut words = Vec::new();
+    words.extend((0..used).map(|_| USED));
+    words.extend((0..painted).map(|_| PATTERN));
+    words
+}
+
+#[test]
+fn used_words_stops_at_a_painted_run() {
+    assert_eq!(canaryscan::used_words(area(0, 64).into_iter()), 0);
+    assert_eq!(canaryscan::used_words(area(10, 64).into_iter()), 10);
+    // Nothing painted left: all of it was used.
+    assert_eq!(canaryscan::used_words(area(40, 0).into_iter()), 40);
+    assert_eq!(canaryscan::used_words(core::iter::empty()), 0);
+    // A shorter painted run where the area ends is not used either.
+    assert_eq!(
+        canaryscan::used_words(area(10, UNTOUCHED_RUN - 1).into_iter()),
+        10
+    );
+}
+
+#[test]
+fn short_painted_runs_count_as_used() {
+    // A buffer on the stack that the poll never wrote looks painted.
+    let mut words = area(10, UNTOUCHED_RUN - 1);
+    words.extend((0..5).map(|_| USED));
+    words.extend((0..UNTOUCHED_RUN).map(|_| PATTERN));
+    words.extend((0..20).map(|_| USED));
+    assert_eq!(
+        canaryscan::used_words(words.into_iter()),
+        10 + UNTOUCHED_RUN - 1 + 5
+    );
+
+    // Exactly `UNTOUCHED_RUN` is enough, wherever it starts.
+    for gap in [1, 3, UNTOUCHED_RUN - 1] {
+        let mut words = area(4, gap);
+        words.extend((0..2).map(|_| USED));
+        words.extend((0..UNTOUCHED_RUN).map(|_| PATTERN));
+        assert_eq!(canaryscan::used_words(words.into_iter()), 4 + gap + 2);
+    }
+}
+
+#[test]
+fn untouched_prefix() {
+    let canary = [PATTERN; CANARY_WORDS];
+    assert_eq!(
+        canaryscan::untouched_prefix(canary.into_iter()),
+        CANARY_WORDS
+    );
+    for hit in 0..CANARY_WORDS {
+        let mut canary = canary;
+        canary[hit] = PATTERN ^ 1;
+        assert_eq!(canaryscan::untouched_prefix(canary.into_iter()), hit);
+    }
+}
+
+#[test]
+fn each_task_keeps_its_worst_poll() {
+    let mut tasks: Vec<TaskStack, 2> = Vec::new();
+    canaryscan::record(&mut tasks, "net", 400, 9000, false);
+    canaryscan::record(&mut tasks, "main", 1200, 7000, false);
+    canaryscan::record(&mut tasks, "net", 100, 8000, false);
+    canaryscan::record(&mut tasks, "net", 800, 9500, false);
+    canaryscan::record(&mut tasks, "main", 10, 7100, true);
+    assert_eq!(
+        tasks,
+        [
+            TaskStack {
+                name: "net",
+                deepest: 800,
+                headroom: 8000,
+                done: false
+            },
+            TaskStack {
+                name: "main",
+                deepest: 1200,
+                headroom: 7000,
+                done: true
+            },
+        ]
+    );
+
+    // A task past the table's room goes unrecorded, not over another.
+    canaryscan::record(&mut tasks, "console", 5000, 100, false);
+    assert_eq!(tasks.len(), 2);
+    assert!(tasks.iter().all(|task| task.name != "console"));
+}
+
+#[test]
+fn blame_goes_to_the_least_headroom() {
+    let tasks = [
+        TaskStack {
+            name: "net",
+            deepest: 3000,
+            headroom: 900,
+            done: false,
+        },
+        TaskStack {
+            name: "uploader",
+            deepest: 1500,
+            headroom: 120,
+            done: false,
+        },
+        TaskStack {
+            name: "main",
+            deepest: 4000,
+            headroom: 2000,
+            done: true,
+        },
+    ];
+    let report = StackOverflowReport::new("main stack", &tasks);
+    assert_eq!(report.deepest, Some(tasks[1]));
+    assert_eq!(
+        report.to_string(),
+        "main stack canary clobbered; deepest task uploader, 1500 bytes in a poll, 120 left"
+    );
+
+    let report = StackOverflowReport::new("tls rx", &[]);
+    assert_eq!(report.deepest, None);
+    assert_eq!(report.to_string(), "tls rx canary clobbered");
+}
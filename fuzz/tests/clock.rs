@@ -0,0 +1,58 @@
+// The clock the checks here step by hand: it moves only when told to, its
+// wall clock is unset until it is set, and once set it runs with it.
+
+use esp32c3_fuzz::clock::{Clock, Manual};
+
+#[test]
+fn stands_still_between_advances() {
+    let clock = Manual::new();
+    assert_eq!(
+        (clock.now_ms(), clock.now_s(), clock.unix_ms()),
+        (0, 0, None)
+    );
+    clock.advance(999);
+    assert_eq!((clock.now_ms(), clock.now_s()), (999, 0));
+    assert_eq!(clock.now_ms(), 999);
+    clock.advance(1);
+    assert_eq!((clock.now_ms(), clock.now_s()), (1_000, 1));
+    clock.advance(0);
+    assert_eq!(clock.now_ms(), 1_000);
+    // Still no wall clock, however long it has been up.
+    clock.advance(86_400_000);
+    assert_eq!(clock.unix_ms(), None);
+}
+
+#[test]
+fn the_wall_clock_runs_on_from_where_it_was_set() {
+    const SET_MS: u64 = 1_717_243_200_000;
+    let clock = Manual::default();
+    clock.advance(5_000);
+    clock.set_unix_ms(SET_MS);
+    assert_eq!(clock.unix_ms(), Some(SET_MS));
+    clock.advance(250);
+    assert_eq!(clock.unix_ms(), Some(SET_MS + 250));
+    assert_eq!(clock.now_ms(), 5_250);
+
+    // Set again, as a later sync would: the wall clock jumps, either way,
+    // and the time since boot does not.
+    clock.set_unix_ms(SET_MS - 60_000);
+    assert_eq!(clock.unix_ms(), Some(SET_MS - 60_000));
+    clock.set_unix_ms(SET_MS + 3_600_000);
+    assert_eq!(clock.unix_ms(), Some(SET_MS + 3_600_000));
+    assert_eq!(clock.now_ms(), 5_250);
+    clock.advance(1_000);
+    assert_eq!(clock.unix_ms(), Some(SET_MS + 3_601_000));
+}
+
+/// Code that takes a clock takes it by reference, as `impl Clock`.
+fn elapsed_since(clock: &impl Clock, start_ms: u64) -> u64 {
+    clock.now_ms() - start_ms
+}
+
+#[test]
+fn shared_by_reference() {
+    let clock = Manual::new();
+    let start_ms = clock.now_ms();
+    clock.advance(1_234);
+    assert_eq!(elapsed_since(&clock, start_ms), 1_234);
+}
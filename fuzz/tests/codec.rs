@@ -0,0 +1,314 @@
+// The text encodings: known vectors, round trips over every length, input
+// no encoder would write, buffers one byte short, and hexdump lines.
+
+use esp32c3_fuzz::codec::{
+    self, Base64, CodecError, Component, HEXDUMP_ROW, STANDARD, STANDARD_NO_PAD, URL_SAFE,
+    URL_SAFE_NO_PAD,
+};
+
+/// Bytes that differ in every bit, so that round trips use the whole alphabet.
+fn bytes(len: usize) -> Vec<u8> {
+    (0..len).map(|i| (i * 37 + 11) as u8).collect()
+}
+
+#[test]
+fn hex() {
+    let mut out = [0; 8];
+    assert_eq!(
+        codec::hex_encode(&[0x00, 0x9f, 0xa5, 0xff], &mut out),
+        Ok("009fa5ff")
+    );
+    let mut back = [0; 4];
+    assert_eq!(
+        codec::hex_decode(b"009FA5ff", &mut back),
+        Ok(&[0x00, 0x9f, 0xa5, 0xff][..])
+    );
+
+    for len in 0..64 {
+        let input = bytes(len);
+        let mut text = vec![0; codec::hex_encoded_len(len)];
+        let text = codec::hex_encode(&input, &mut text).unwrap().to_owned();
+        let mut back = vec![0; len];
+        assert_eq!(
+            codec::hex_decode(text.as_bytes(), &mut back),
+            Ok(&input[..])
+        );
+    }
+}
+
+#[test]
+fn hex_rejects() {
+    let mut out = [0; 4];
+    assert_eq!(
+        codec::hex_decode(b"abc", &mut out),
+        Err(CodecError::Invalid(2))
+    );
+    assert_eq!(
+        codec::hex_decode(b"0g", &mut out),
+        Err(CodecError::Invalid(1))
+    );
+    assert_eq!(
+        codec::hex_decode(b"00 1", &mut out),
+        Err(CodecError::Invalid(2))
+    );
+    assert_eq!(
+        codec::hex_decode(b"+1", &mut out),
+        Err(CodecError::Invalid(0))
+    );
+}
+
+/// RFC 4648 section 10.
+const VECTORS: &[(&str, &str)] = &[
+    ("", ""),
+    ("f", "Zg=="),
+    ("fo", "Zm8="),
+    ("foo", "Zm9v"),
+    ("foob", "Zm9vYg=="),
+    ("fooba", "Zm9vYmE="),
+    ("foobar", "Zm9vYmFy"),
+];
+
+#[test]
+fn base64_vectors() {
+    for &(plain, encoded) in VECTORS {
+        let mut out = [0; 8];
+        assert_eq!(STANDARD.encode(plain.as_bytes(), &mut out), Ok(encoded));
+        let unpadded = encoded.trim_end_matches('=');
+        assert_eq!(
+            STANDARD_NO_PAD.encode(plain.as_bytes(), &mut out),
+            Ok(unpadded)
+        );
+
+        let mut back = [0; 6];
+        assert_eq!(
+            STANDARD.decode(encoded.as_bytes(), &mut back),
+            Ok(plain.as_bytes())
+        );
+        assert_eq!(
+            STANDARD_NO_PAD.decode(unpadded.as_bytes(), &mut back),
+            Ok(plain.as_bytes())
+        );
+    }
+
+    // The two alphabets differ in their last two characters only.
+    let mut out = [0; 4];
+    assert_eq!(STANDARD.encode(&[0xfb, 0xff], &mut out), Ok("+/8="));
+    assert_eq!(URL_SAFE.encode(&[0xfb, 0xff], &mut out), Ok("-_8="));
+    assert_eq!(URL_SAFE_NO_PAD.encode(&[0xfb, 0xff], &mut out), Ok("-_8"));
+}
+
+#[test]
+fn base64_round_trips() {
+    for flavour in [STANDARD, STANDARD_NO_PAD, URL_SAFE, URL_SAFE_NO_PAD] {
+        for len in 0..100 {
+            let input = bytes(len);
+            let mut text = vec![0; flavour.encoded_len(len)];
+            let text = flavour.encode(&input, &mut text).unwrap().to_owned();
+            assert_eq!(text.len(), flavour.encoded_len(len));
+            assert_eq!(flavour.decoded_len(text.as_bytes()), Ok(len));
+            let mut back = vec![0; len];
+            assert_eq!(flavour.decode(text.as_bytes(), &mut back), Ok(&input[..]));
+        }
+    }
+}
+
+fn decode(flavour: Base64, text: &str) -> Result<Vec<u8>, CodecError> {
+    let mut out = [0; 16];
+    flavour
+        .decode(text.as_bytes(), &mut out)
+        .map(<[u8]>::to_vec)
+}
+
+#[test]
+fn base64_rejects() {
+    // Not a multiple of four, or padding where none belongs.
+    assert_eq!(decode(STANDARD, "Zm9"), Err(CodecError::Invalid(3)));
+    assert_eq!(decode(STANDARD, "Zg="), Err(CodecError::Invalid(3)));
+    assert_eq!(decode(STANDARD, "Zm9v===="), Err(CodecError::Invalid(4)));
+    assert_eq!(decode(STANDARD, "Zg=a"), Err(CodecError::Invalid(2)));
+    assert_eq!(decode(STANDARD_NO_PAD, "Zg=="), Err(CodecError::Invalid(2)));
+    // A lone character is no byte.
+    assert_eq!(
+        decode(STANDARD_NO_PAD, "Zm9vY"),
+        Err(CodecError::Invalid(4))
+    );
+    // The other alphabet, and what is in neither.
+    assert_eq!(decode(URL_SAFE, "+/8="), Err(CodecError::Invalid(0)));
+    assert_eq!(decode(STANDARD, "-_8="), Err(CodecError::Invalid(0)));
+    assert_eq!(decode(STANDARD, "Zm 9"), Err(CodecError::Invalid(2)));
+    // Bits past the last byte set: "Zh==" would decode as "Zg==" does.
+    assert_eq!(decode(STANDARD, "Zh=="), Err(CodecError::Invalid(1)));
+    assert_eq!(decode(STANDARD_NO_PAD, "Zm9"), Err(CodecError::Invalid(2)));
+}
+
+#[test]
+fn percent() {
+    let mut out = [0; 64];
+    let encode = |component, out: &mut [u8; 64]| {
+        codec::percent_encode(b"a b/c&d=e+f?g@h:i~", component, out)
+            .unwrap()
+            .to_owned()
+    };
+    assert_eq!(
+        encode(Component::Unreserved, &mut out),
+        "a%20b%2Fc%26d%3De%2Bf%3Fg%40h%3Ai~"
+    );
+    assert_eq!(
+        encode(Component::PathSegment, &mut out),
+        "a%20b%2Fc&d=e+f%3Fg@h:i~"
+    );
+    assert_eq!(encode(Component::Path, &mut out), "a%20b/c&d=e+f%3Fg@h:i~");
+    assert_eq!(
+        encode(Component::QueryParam, &mut out),
+        "a%20b/c%26d%3De%2Bf?g@h:i~"
+    );
+    assert_eq!(
+        encode(Component::Userinfo, &mut out),
+        "a%20b%2Fc&d=e+f%3Fg%40h:i~"
+    );
+
+    let all: Vec<u8> = (0..=255).collect();
+    for component in [
+        Component::Unreserved,
+        Component::PathSegment,
+        Component::Path,
+        Component::QueryParam,
+        Component::Userinfo,
+    ] {
+        let mut text = vec![0; codec::percent_encoded_len(&all, component)];
+        let text = codec::percent_encode(&all, component, &mut text)
+            .unwrap()
+            .to_owned();
+        let mut back = vec![0; all.len()];
+        assert_eq!(
+            codec::percent_decode(text.as_bytes(), &mut back),
+            Ok(&all[..])
+        );
+    }
+}
+
+#[test]
+fn percent_decode_leaves_plus_and_rejects_broken_escapes() {
+    let mut out = [0; 16];
+    assert_eq!(
+        codec::percent_decode(b"a+b%2b%2B", &mut out),
+        Ok(&b"a+b++"[..])
+    );
+    for (bad, at) in [("%", 0), ("a%2", 1), ("%g0", 0), ("ab%0x", 2)] {
+        assert_eq!(
+            codec::percent_decode(bad.as_bytes(), &mut out),
+            Err(CodecError::Invalid(at)),
+            "{bad}"
+        );
+    }
+}
+
+#[test]
+fn buffer_too_small_gives_the_size_needed() {
+    let mut short = [0; 7];
+    assert_eq!(
+        codec::hex_encode(&[1, 2, 3, 4], &mut short),
+        Err(CodecError::BufferTooSmall(8))
+    );
+    assert_eq!(
+        codec::hex_decode(b"0102030405060708", &mut short),
+        Err(CodecError::BufferTooSmall(8))
+    );
+    assert_eq!(
+        STANDARD.encode(b"fooba", &mut short),
+        Err(CodecError::BufferTooSmall(8))
+    );
+    // Exactly the size needed is enough.
+    assert_eq!(
+        URL_SAFE_NO_PAD.decode(b"Zm9vYmFyYg", &mut short),
+        Ok(&b"foobarb"[..])
+    );
+    let mut shorter = [0; 6];
+    assert_eq!(
+        STANDARD.decode(b"Zm9vYmFyYg==", &mut shorter),
+        Err(CodecError::BufferTooSmall(7))
+    );
+    assert_eq!(
+        codec::percent_encode(b"a b", Component::Path, &mut [0; 4]),
+        Err(CodecError::BufferTooSmall(5))
+    );
+    assert_eq!(
+        codec::percent_decode(b"%41%42%43", &mut [0; 2]),
+        Err(CodecError::BufferTooSmall(3))
+    );
+}
+
+#[test]
+fn json_strings() {
+    let mut out = String::new();
+    codec::write_json_str(&mut out, "a\"b\\c\n\u{1f}é").unwrap();
+    assert_eq!(out, "\"a\\\"b\\\\c\\u000a\\u001fé\"");
+}
+
+#[test]
+fn uuids() {
+    let uuid = [
+        0x12, 0x3e, 0x45, 0x67, 0xe8, 0x9b, 0x42, 0xd3, 0xa4, 0x56, 0x42, 0x66, 0x14, 0x17, 0x40,
+        0x00,
+    ];
+    assert_eq!(
+        codec::uuid_to_string(&uuid),
+        "123e4567-e89b-42d3-a456-426614174000"
+    );
+    assert_eq!(
+        codec::uuid_to_string(&[0xff; 16]),
+        "ffffffff-ffff-ffff-ffff-ffffffffffff"
+    );
+}
+
+#[test]
+fn hexdump_full_row() {
+    let mut out = [0; codec::hexdump_line_len(HEXDUMP_ROW)];
+    assert_eq!(
+        codec::hexdump_line(0x10, b"HTTP/1.1 200 OK\r", &mut out),
+        Ok("00000010  48 54 54 50 2f 31 2e 31  20 32 30 30 20 4f 4b 0d  |HTTP/1.1 200 OK.|")
+    );
+    let bytes: Vec<u8> = (0xf0..=0xff).collect();
+    assert_eq!(
+        codec::hexdump_line(0xdead_beef, &bytes, &mut out),
+        Ok("deadbeef  f0 f1 f2 f3 f4 f5 f6 f7  f8 f9 fa fb fc fd fe ff  |................|")
+    );
+}
+
+#[test]
+fn hexdump_short_rows_line_up() {
+    let full = codec::hexdump_line_len(HEXDUMP_ROW);
+    let gutter = full - HEXDUMP_ROW - 2;
+    for len in 0..=HEXDUMP_ROW {
+        let row: Vec<u8> = (0..len as u8).map(|i| b'a' + i).collect();
+        let mut out = vec![0; codec::hexdump_line_len(len)];
+        let line = codec::hexdump_line(0, &row, &mut out).unwrap();
+        assert_eq!(line.len(), codec::hexdump_line_len(len));
+        // The gutter starts in the same column whatever the length.
+        assert_eq!(line.find('|'), Some(gutter), "{len}");
+        assert_eq!(
+            &line[gutter + 1..line.len() - 1],
+            std::str::from_utf8(&row).unwrap()
+        );
+        assert!(line.ends_with('|'));
+    }
+    let mut out = [0; 128];
+    assert_eq!(
+        codec::hexdump_line(0x1234, b"GET /\r\n\0\x7f ~", &mut out),
+        Ok("00001234  47 45 54 20 2f 0d 0a 00  7f 20 7e                 |GET /.... ~|")
+    );
+}
+
+#[test]
+fn hexdump_limits() {
+    let mut out = [0; 128];
+    assert_eq!(
+        codec::hexdump_line(0, &[0; HEXDUMP_ROW + 1], &mut out),
+        Err(CodecError::Invalid(HEXDUMP_ROW))
+    );
+    let mut short = vec![0; codec::hexdump_line_len(4) - 1];
+    assert_eq!(
+        codec::hexdump_line(0, b"abcd", &mut short),
+        Err(CodecError::BufferTooSmall(codec::hexdump_line_len(4)))
+    );
+}
@@ -0,0 +1,228 @@
+// What more than one test file needs; each uses only some of it.
+#![allow(dead_code)]
+
+use std::future::Future;
+use std::pin::pin;
+use std::task::{Context, Poll, Waker};
+
+/// Runs `future`, which the mocks it awaits never leave pending, to the end.
+pub fn block_on<F: Future>(future: F) -> F::Output {
+    let mut future = pin!(future);
+    let mut cx = Context::from_waker(Waker::noop());
+    loop {
+        if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+            return output;
+        }
+    }
+}
+
+/// The members of the JSON object `text` is, in order, once the whole of
+/// it has been checked to be JSON (RFC 8259). String values come
+/// unescaped, the others as they are written.
+pub fn json_object(text: &str) -> Vec<(String, String)> {
+    let mut json = Json {
+        bytes: text.as_bytes(),
+        pos: 0,
+    };
+    json.space();
+    let members = json
+        .object()
+        .unwrap_or_else(|at| panic!("not JSON at {at}: {text}"));
+    json.space();
+    assert_eq!(json.pos, text.len(), "trailing bytes: {text}");
+    members
+}
+
+/// `json_object`'s keys alone.
+pub fn json_keys(text: &str) -> Vec<String> {
+    json_object(text).into_iter().map(|(key, _)| key).collect()
+}
+
+struct Json<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl Json<'_> {
+    fn peek(&self) -> Option<u8> {
+        self.bytes.get(self.pos).copied()
+    }
+
+    fn space(&mut self) {
+        while matches!(self.peek(), Some(b' ' | b'\t' | b'\n' | b'\r')) {
+            self.pos += 1;
+        }
+    }
+
+    fn eat(&mut self, byte: u8) -> Result<(), usize> {
+        self.space();
+        if self.peek() != Some(byte) {
+            return Err(self.pos);
+        }
+        self.pos += 1;
+        Ok(())
+    }
+
+    fn object(&mut self) -> Result<Vec<(String, String)>, usize> {
+        self.eat(b'{')?;
+        let mut members = Vec::new();
+        self.space();
+        if self.peek() == Some(b'}') {
+            self.pos += 1;
+            return Ok(members);
+        }
+        loop {
+            self.space();
+            let key = self.string()?;
+            self.eat(b':')?;
+            self.space();
+            let start = self.pos;
+            let value = match self.peek() {
+                Some(b'"') => self.string()?,
+                _ => {
+                    self.value()?;
+                    String::from_utf8(self.bytes[start..self.pos].to_vec()).unwrap()
+                }
+            };
+            members.push((key, value));
+            self.space();
+            match self.peek() {
+                Some(b',') => self.pos += 1,
+                Some(b'}') => {
+                    self.pos += 1;
+                    return Ok(members);
+                }
+                _ => return Err(self.pos),
+            }
+        }
+    }
+
+    fn array(&mut self) -> Result<(), usize> {
+        self.eat(b'[')?;
+        self.space();
+        if self.peek() == Some(b']') {
+            self.pos += 1;
+            return Ok(());
+        }
+        loop {
+            self.value()?;
+            self.space();
+            match self.peek() {
+                Some(b',') => self.pos += 1,
+                Some(b']') => {
+                    self.pos += 1;
+                    return Ok(());
+                }
+                _ => return Err(self.pos),
+            }
+        }
+    }
+
+    fn value(&mut self) -> Result<(), usize> {
+        self.space();
+        match self.peek() {
+            Some(b'{') => self.object().map(drop),
+            Some(b'[') => self.array(),
+            Some(b'"') => self.string().map(drop),
+            Some(b'-' | b'0'..=b'9') => self.number(),
+            _ => {
+                for word in ["true", "false", "null"] {
+                    if self.bytes[self.pos..].starts_with(word.as_bytes()) {
+                        self.pos += word.len();
+                        return Ok(());
+                    }
+                }
+                Err(self.pos)
+            }
+        }
+    }
+
+    fn string(&mut self) -> Result<String, usize> {
+        if self.peek() != Some(b'"') {
+            return Err(self.pos);
+        }
+        self.pos += 1;
+        let mut out = String::new();
+        loop {
+            let start = self.pos;
+            let Some(byte) = self.peek() else {
+                return Err(start);
+            };
+            self.pos += 1;
+            match byte {
+                b'"' => return Ok(out),
+                b'\\' => {
+                    let escaped = match self.peek().ok_or(self.pos)? {
+                        b'"' => '"',
+                        b'\\' => '\\',
+                        b'/' => '/',
+                        b'b' => '\u{8}',
+                        b'f' => '\u{c}',
+                        b'n' => '\n',
+                        b'r' => '\r',
+                        b't' => '\t',
+                        b'u' => {
+                            let hex = self.bytes.get(self.pos + 1..self.pos + 5).ok_or(self.pos)?;
+                            let hex = std::str::from_utf8(hex).map_err(|_| self.pos)?;
+                            let code = u32::from_str_radix(hex, 16).map_err(|_| self.pos)?;
+                            self.pos += 4;
+                            char::from_u32(code).unwrap_or('\u{fffd}')
+                        }
+                        _ => return Err(self.pos),
+                    };
+                    self.pos += 1;
+                    out.push(escaped);
+                }
+                0..=0x1f => return Err(start),
+                _ => {
+                    // The input is a `str`, so this is a whole character.
+                    let len = match byte {
+                        0xf0.. => 4,
+                        0xe0.. => 3,
+                        0xc0.. => 2,
+                        _ => 1,
+                    };
+                    let end = start + len;
+                    out.push_str(std::str::from_utf8(&self.bytes[start..end]).unwrap());
+                    self.pos = end;
+                }
+            }
+        }
+    }
+
+    fn number(&mut self) -> Result<(), usize> {
+        let start = self.pos;
+        if self.peek() == Some(b'-') {
+            self.pos += 1;
+        }
+        match self.peek() {
+            Some(b'0') => self.pos += 1,
+            Some(b'1'..=b'9') => self.digits(),
+            _ => return Err(start),
+        }
+        if self.peek() == Some(b'.') {
+            self.pos += 1;
+            if !matches!(self.peek(), Some(b'0'..=b'9')) {
+                return Err(self.pos);
+            }
+            self.digits();
+        }
+        if matches!(self.peek(), Some(b'e' | b'E')) {
+            self.pos += 1;
+            if matches!(self.peek(), Some(b'+' | b'-')) {
+                self.pos += 1;
+            }
+            if !matches!(self.peek(), Some(b'0'..=b'9')) {
+                return Err(self.pos);
+            }
+            self.digits();
+        }
+        Ok(())
+    }
+
+    fn digits(&mut self) {
+        while matches!(self.peek(), Some(b'0'..=b'9')) {
+            self.pos += 1;
+        }
+    }
+}
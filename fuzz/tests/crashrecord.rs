@@ -0,0 +1,184 @@
+// The crash record: its layout through RTC memory, what it makes of memory
+// it did not write, what a second panic keeps, and that only an accepted
+// upload clears it, against a mock transport.
+
+mod common;
+
+use esp32c3_fuzz::crashrecord::{self, Crash, Record, FILE_LEN, MESSAGE_LEN, RECORD_LEN};
+use esp32c3_fuzz::rom::crc::crc32_le;
+
+use common::json_object;
+
+fn crash() -> Crash {
+    Crash::new(
+        Some("uploader"),
+        format_args!(
+            "index out of bounds: the len is {} but the index is {}",
+            4, 7
+        ),
+        Some(("src/uploader.rs", 417)),
+    )
+}
+
+#[test]
+fn round_trip() {
+    let crash = crash();
+    assert_eq!(
+        crash.message,
+        "uploader: index out of bounds: the len is 4 but the index is 7"
+    );
+    assert_eq!(
+        (crash.file.as_str(), crash.line, crash.count),
+        ("src/uploader.rs", 417, 1)
+    );
+    assert_eq!(Crash::decode(&crash.encode()), Some(crash));
+
+    let bare = Crash::new(None, "explicit panic", None);
+    assert_eq!(
+        (bare.message.as_str(), bare.file.as_str(), bare.line),
+        ("explicit panic", "", 0)
+    );
+    assert_eq!(Crash::decode(&bare.encode()), Some(bare));
+}
+
+#[test]
+fn long_text_is_cut_at_a_character() {
+    // Two-byte characters after five bytes, so that the limit falls
+    // inside one.
+    let message = "é".repeat(MESSAGE_LEN);
+    let file = "src/".to_owned() + &"ü".repeat(FILE_LEN);
+    let crash = Crash::new(Some("net"), &message, Some((&file, 1)));
+    assert_eq!(crash.message.len(), MESSAGE_LEN - 1);
+    assert!(("net: ".to_owned() + &message).starts_with(crash.message.as_str()));
+    assert_eq!(crash.file.len(), FILE_LEN);
+    assert!(file.starts_with(crash.file.as_str()));
+    assert_eq!(Crash::decode(&crash.encode()), Some(crash));
+}
+
+/// `raw` with its CRC made right again.
+fn resealed(mut raw: Record) -> Record {
+    let crc = crc32_le(0, &raw[..RECORD_LEN - 4]);
+    raw[RECORD_LEN - 4..].copy_from_slice(&crc.to_le_bytes());
+    raw
+}
+
+#[test]
+fn rejects_what_it_did_not_write() {
+    assert_eq!(Crash::decode(&[0; RECORD_LEN]), None);
+    assert_eq!(Crash::decode(&[0xff; RECORD_LEN]), None);
+    let pseudo_random: Vec<u8> = (0..RECORD_LEN as u32)
+        .map(|i| (i.wrapping_mul(2_654_435_761) >> 13) as u8)
+        .collect();
+    assert_eq!(Crash::decode(&pseudo_random.try_into().unwrap()), None);
+
+    let raw = crash().encode();
+    for i in 0..RECORD_LEN {
+        for bit in 0..8 {
+            let mut flipped = raw;
+            flipped[i] ^= 1 << bit;
+            assert_eq!(Crash::decode(&flipped), None, "byte {i} bit {bit}");
+        }
+    }
+
+    // A right CRC over lengths past the fields, or over text that is not
+    // UTF-8, is still not a record.
+    let mut long = raw;
+    long[12] = MESSAGE_LEN as u8 + 1;
+    assert_eq!(Crash::decode(&resealed(long)), None);
+    let mut long = raw;
+    long[13] = FILE_LEN as u8 + 1;
+    assert_eq!(Crash::decode(&resealed(long)), None);
+    let mut garbled = raw;
+    garbled[16] = 0xff;
+    assert_eq!(Crash::decode(&resealed(garbled)), None);
+}
+
+#[test]
+fn a_second_panic_keeps_the_first() {
+    let mut record = [0xa5; RECORD_LEN];
+    crashrecord::keep(&mut record, crash());
+    assert_eq!(Crash::decode(&record), Some(crash()));
+
+    for count in 2..=4 {
+        let later = Crash::new(Some("net"), "later", Some(("src/net.rs", 1)));
+        crashrecord::keep(&mut record, later);
+        let kept = Crash::decode(&record).unwrap();
+        assert_eq!(kept, Crash { count, ..crash() });
+    }
+}
+
+#[test]
+fn json() {
+    let crash = Crash::new(Some("main"), "\"quoted\"\n\\", Some(("src/main.rs", 9)));
+    let mut out = String::new();
+    crash.write_json(&mut out).unwrap();
+    assert_eq!(
+        json_object(&out),
+        [
+            ("message".into(), "main: \"quoted\"\n\\".into()),
+            ("file".into(), "src/main.rs".into()),
+            ("line".into(), "9".into()),
+            ("count".into(), "1".into()),
+        ]
+    );
+}
+
+/// A server that answers each upload as a test scripts: `Err` for a
+/// request that got no answer, else the status and whether it came before
+/// the whole body.
+struct MockServer {
+    answers: Vec<Result<(u16, bool), ()>>,
+    received: Vec<String>,
+}
+
+impl MockServer {
+    fn post(&mut self, body: String) -> Result<(u16, bool), ()> {
+        self.received.push(body);
+        self.answers.remove(0)
+    }
+}
+
+/// What the uploader does with each reading: the record as `crash` if
+/// there is one, and settling it by the answer.
+fn upload(record: &mut Record, server: &mut MockServer) {
+    let mut body = String::from("{\"seq\":1");
+    if let Some(crash) = Crash::decode(record) {
+        body.push_str(",\"crash\":");
+        crash.write_json(&mut body).unwrap();
+    }
+    body.push('}');
+    if let Ok((status, early)) = server.post(body) {
+        crashrecord::settle(record, status, early);
+    }
+}
+
+#[test]
+fn cleared_only_once_an_upload_is_accepted() {
+    let mut record = [0; RECORD_LEN];
+    crashrecord::keep(&mut record, crash());
+    let mut server = MockServer {
+        answers: vec![
+            Err(()),
+            Ok((500, false)),
+            Ok((200, true)),
+            Ok((413, false)),
+            Ok((302, false)),
+            Ok((204, false)),
+            Ok((200, false)),
+        ],
+        received: Vec::new(),
+    };
+    for _ in 0..7 {
+        upload(&mut record, &mut server);
+    }
+    let carried: Vec<bool> = server
+        .received
+        .iter()
+        .map(|body| json_object(body).iter().any(|(key, _)| key == "crash"))
+        .collect();
+    assert_eq!(carried, [true, true, true, true, true, true, false]);
+    assert_eq!(Crash::decode(&record), None);
+
+    assert!(crashrecord::accepted(200, false) && crashrecord::accepted(299, false));
+    assert!(!crashrecord::accepted(199, false) && !crashrecord::accepted(300, false));
+}
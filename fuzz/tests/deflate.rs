@@ -0,0 +1,164 @@
+// The gzip encoder against a decompressor it shares no code with: bodies
+// of every kind come back as they went in, through output buffers of any
+// size, and a body that would not shrink is left plain.
+
+use esp32c3_fuzz::deflate::{self, MAX_INPUT_LEN};
+use esp32c3_fuzz::rom::crc::crc32_le;
+
+/// What a gzip member decodes to, once its header and trailer check out.
+fn gunzip(data: &[u8]) -> Vec<u8> {
+    assert!(data.len() >= 18, "{} bytes", data.len());
+    let (header, rest) = data.split_at(10);
+    // Deflate, no flags, no time.
+    assert_eq!(header[..4], [0x1f, 0x8b, 8, 0]);
+    let (deflated, trailer) = rest.split_at(rest.len() - 8);
+    let out = miniz_oxide::inflate::decompress_to_vec(deflated).unwrap();
+    assert_eq!(trailer[..4], crc32_le(0, &out).to_le_bytes());
+    assert_eq!(trailer[4..], (out.len() as u32).to_le_bytes());
+    out
+}
+
+/// All of `input` gzipped, read through a buffer of `chunk`.
+fn compress(input: &[u8], chunk: usize) -> Vec<u8> {
+    let gzipped = deflate::gzip(input).unwrap();
+    let mut encoder = gzipped.encoder();
+    let mut out = Vec::new();
+    let mut buf = vec![0; chunk];
+    loop {
+        match encoder.read(&mut buf) {
+            0 => break,
+            n => out.extend_from_slice(&buf[..n]),
+        }
+    }
+    assert_eq!(out.len(), gzipped.compressed_len());
+    out
+}
+
+/// xorshift: the same bytes every run, and nothing to match in them.
+fn noise(len: usize) -> Vec<u8> {
+    let mut state = 0x2545_f491_u32;
+    (0..len)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            state as u8
+        })
+        .collect()
+}
+
+fn readings(count: usize) -> Vec<u8> {
+    (0..count)
+        .map(|i| {
+            format!(
+                "{{\"schema_version\":2,\"seq\":{},\"uptime_ms\":{},\"temperature_c\":{}.{},\
+                 \"battery_mv\":{}}}\n",
+                1000 + i,
+                60_000 * i,
+                20 + i % 5,
+                i % 10,
+                3900 - i % 17
+            )
+        })
+        .collect::<String>()
+        .into_bytes()
+}
+
+#[test]
+fn the_crc_is_zlibs() {
+    assert_eq!(crc32_le(0, b"123456789"), 0xcbf4_3926);
+}
+
+#[test]
+fn round_trips() {
+    let mut bodies: Vec<(&str, Vec<u8>)> = vec![
+        ("empty", Vec::new()),
+        ("one byte", b"x".to_vec()),
+        ("shorter than a match", b"ab".to_vec()),
+        ("every byte", (0..=255).collect()),
+        ("readings", readings(40)),
+        ("noise", noise(5000)),
+    ];
+    // Runs either side of the longest match, and a run that is all match.
+    for len in [3, 4, 257, 258, 259, 260, 516, 517, 10_000] {
+        bodies.push(("run", vec![b'a'; len]));
+    }
+    // The same bytes again at the farthest distance a match reaches, and
+    // just past it.
+    for gap in [32 * 1024 - 16, 32 * 1024 - 15, 32 * 1024, 40_000] {
+        let block = noise(16);
+        let mut body = block.clone();
+        body.extend(noise(gap));
+        body.extend(&block);
+        bodies.push(("far repeat", body));
+    }
+    for (what, body) in &bodies {
+        assert_eq!(
+            gunzip(&compress(body, 4096)),
+            *body,
+            "{what} of {}",
+            body.len()
+        );
+    }
+}
+
+#[test]
+fn the_longest_body() {
+    let body: Vec<u8> = readings(700)
+        .into_iter()
+        .cycle()
+        .take(MAX_INPUT_LEN)
+        .collect();
+    assert_eq!(gunzip(&compress(&body, 256)), body);
+    assert!(deflate::gzip(&body[..MAX_INPUT_LEN]).is_some());
+    let longer = vec![b'a'; MAX_INPUT_LEN + 1];
+    assert!(deflate::gzip(&longer).is_none());
+    assert!(deflate::gzip_smaller(&longer).is_none());
+}
+
+#[test]
+fn any_buffer_gives_the_same_bytes() {
+    let body = readings(25);
+    let whole = compress(&body, 1 << 16);
+    for chunk in [1, 2, 3, 7, 64, 256, 1000] {
+        assert_eq!(compress(&body, chunk), whole, "{chunk}");
+    }
+    // And an encoder started afresh gives them again.
+    assert_eq!(compress(&body, 64), whole);
+}
+
+#[test]
+fn readings_shrink_by_half() {
+    for count in [5, 16, 100] {
+        let body = readings(count);
+        let gzipped = deflate::gzip_smaller(&body).unwrap();
+        assert!(
+            2 * gzipped.compressed_len() <= body.len(),
+            "{count}: {} of {}",
+            gzipped.compressed_len(),
+            body.len()
+        );
+    }
+}
+
+#[test]
+fn what_does_not_shrink_goes_plain() {
+    for len in [0, 1, 17, 18, 500, 5000] {
+        let body = noise(len);
+        assert!(deflate::gzip(&body).is_some());
+        assert!(deflate::gzip_smaller(&body).is_none(), "{len}");
+    }
+    // Already gzipped, and with nothing to find in it.
+    let twice = compress(&noise(2000), 4096);
+    assert!(deflate::gzip_smaller(&twice).is_none());
+    // The fallback is exactly "not shorter".
+    let body = b"aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa";
+    for len in 1..body.len() {
+        let gzipped = deflate::gzip(&body[..len]).unwrap();
+        assert_eq!(
+            deflate::gzip_smaller(&body[..len]).is_some(),
+            gzipped.compressed_len() < len,
+            "{len}"
+        );
+    }
+}
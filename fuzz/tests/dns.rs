@@ -0,0 +1,392 @@
+// DNS: the query the resolver sends, and what `check_response` makes of
+// genuine answers, of the responses an off-path attacker would spoof, and
+// of broken messages, truncated at every length.
+
+use esp32c3_fuzz::dns::{self, Answer, Name, Reject, HEADER_LEN, MAX_CNAME_DEPTH};
+
+const ID: u16 = 0x1a2b;
+const HOST: &str = "example.com";
+
+const A: u16 = 1;
+const CNAME: u16 = 5;
+const AAAA: u16 = 28;
+const IN: u16 = 1;
+/// A response, recursion desired and available.
+const FLAGS: u16 = 0x8180;
+
+/// `name` as labels, uncompressed.
+fn name(name: &str) -> Vec<u8> {
+    let mut out = Vec::new();
+    for label in name.split('.').filter(|label| !label.is_empty()) {
+        out.push(label.len() as u8);
+        out.extend_from_slice(label.as_bytes());
+    }
+    out.push(0);
+    out
+}
+
+struct Record {
+    owner: Vec<u8>,
+    rtype: u16,
+    class: u16,
+    data: Vec<u8>,
+}
+
+fn a(owner: &str, addr: [u8; 4]) -> Record {
+    Record {
+        owner: name(owner),
+        rtype: A,
+        class: IN,
+        data: addr.to_vec(),
+    }
+}
+
+fn cname(owner: &str, target: &str) -> Record {
+    Record {
+        owner: name(owner),
+        rtype: CNAME,
+        class: IN,
+        data: name(target),
+    }
+}
+
+/// A response with the header's counts taken from what follows it.
+fn message(id: u16, flags: u16, question: (&[u8], u16, u16), answers: &[Record]) -> Vec<u8> {
+    let mut out = Vec::new();
+    for word in [id, flags, 1, answers.len() as u16, 0, 0] {
+        out.extend_from_slice(&word.to_be_bytes());
+    }
+    out.extend_from_slice(question.0);
+    out.extend_from_slice(&question.1.to_be_bytes());
+    out.extend_from_slice(&question.2.to_be_bytes());
+    for record in answers {
+        out.extend_from_slice(&record.owner);
+        out.extend_from_slice(&record.rtype.to_be_bytes());
+        out.extend_from_slice(&record.class.to_be_bytes());
+        out.extend_from_slice(&300u32.to_be_bytes());
+        out.extend_from_slice(&(record.data.len() as u16).to_be_bytes());
+        out.extend_from_slice(&record.data);
+    }
+    out
+}
+
+fn response(answers: &[Record]) -> Vec<u8> {
+    message(ID, FLAGS, (&name(HOST), A, IN), answers)
+}
+
+fn check(message: &[u8]) -> Result<Answer, Reject> {
+    dns::check_response(message, ID, HOST)
+}
+
+#[test]
+fn query() {
+    let mut out = [0; 512];
+    let len = dns::encode_query(ID, "example.com.", &mut out).unwrap();
+    let mut expected = vec![0x1a, 0x2b, 0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0];
+    expected.extend(name(HOST));
+    expected.extend([0, 1, 0, 1]);
+    assert_eq!(&out[..len], expected);
+
+    let longest = [
+        "a".repeat(63),
+        "b".repeat(63),
+        "c".repeat(63),
+        "d".repeat(61),
+    ]
+    .join(".");
+    assert_eq!(longest.len(), 253);
+    assert!(dns::encode_query(ID, &longest, &mut out).is_some());
+    let too_long = longest.clone() + "d";
+    let long_label = "a".repeat(64) + ".com";
+    for bad in [
+        "",
+        ".",
+        "example..com",
+        ".example.com",
+        "exa mple.com",
+        "exa.mple.com\0",
+        &long_label,
+        &too_long,
+    ] {
+        assert_eq!(dns::encode_query(ID, bad, &mut out), None, "{bad:?}");
+    }
+    // A buffer too small for the question.
+    assert_eq!(dns::encode_query(ID, HOST, &mut [0; 20]), None);
+}
+
+#[test]
+fn answers() {
+    assert_eq!(
+        check(&response(&[a(HOST, [93, 184, 215, 14])])),
+        Ok(Answer::Address([93, 184, 215, 14]))
+    );
+    // Names match whatever their case, and with a trailing dot.
+    let upper = message(
+        ID,
+        FLAGS,
+        (&name("EXAMPLE.com"), A, IN),
+        &[a("Example.COM", [1, 2, 3, 4])],
+    );
+    assert_eq!(
+        dns::check_response(&upper, ID, "example.com."),
+        Ok(Answer::Address([1, 2, 3, 4]))
+    );
+    // Records of other types for the name are passed over.
+    let mixed = response(&[
+        Record {
+            owner: name(HOST),
+            rtype: AAAA,
+            class: IN,
+            data: vec![0x20, 0x01, 0x0d, 0xb8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1],
+        },
+        a(HOST, [5, 6, 7, 8]),
+    ]);
+    assert_eq!(check(&mixed), Ok(Answer::Address([5, 6, 7, 8])));
+
+    assert_eq!(check(&response(&[])), Ok(Answer::NotFound));
+    let nxdomain = message(ID, FLAGS | 3, (&name(HOST), A, IN), &[]);
+    assert_eq!(check(&nxdomain), Ok(Answer::NotFound));
+    let servfail = message(ID, FLAGS | 2, (&name(HOST), A, IN), &[]);
+    assert_eq!(check(&servfail), Ok(Answer::Failed(2)));
+}
+
+#[test]
+fn compressed_names() {
+    // The answer's owner points at the question's name.
+    let question_end = HEADER_LEN + 13 + 4;
+    let mut message = response(&[]);
+    message[7] = 1;
+    message.extend([0xc0, HEADER_LEN as u8]);
+    // The rest of an A record, past its owner.
+    message.extend(&response(&[a(HOST, [9, 9, 9, 9])])[question_end + 13..]);
+    assert_eq!(check(&message), Ok(Answer::Address([9, 9, 9, 9])));
+
+    let mut out = Name::new();
+    assert_eq!(
+        dns::read_name(&message, HEADER_LEN, &mut out),
+        Ok(HEADER_LEN + 13)
+    );
+    assert_eq!(&out[..], b"example.com");
+    // Past a pointer the name ends after the pointer, not its target.
+    assert_eq!(
+        dns::read_name(&message, question_end, &mut out),
+        Ok(question_end + 2)
+    );
+    assert_eq!(&out[..], b"example.com");
+}
+
+#[test]
+fn cname_chains() {
+    let chain = response(&[
+        cname(HOST, "edge.example.net"),
+        cname("edge.example.net", "pop.cdn.example"),
+        a("pop.cdn.example", [192, 0, 2, 7]),
+    ]);
+    assert_eq!(check(&chain), Ok(Answer::Address([192, 0, 2, 7])));
+    // In any order.
+    let shuffled = response(&[
+        a("pop.cdn.example", [192, 0, 2, 7]),
+        cname("edge.example.net", "pop.cdn.example"),
+        cname(HOST, "edge.example.net"),
+    ]);
+    assert_eq!(check(&shuffled), Ok(Answer::Address([192, 0, 2, 7])));
+
+    // As long as allowed, and one link longer.
+    let links = |count: usize| {
+        let mut records: Vec<Record> = (0..count)
+            .map(|i| {
+                let from = if i == 0 {
+                    HOST.to_owned()
+                } else {
+                    format!("hop{i}.example")
+                };
+                cname(&from, &format!("hop{}.example", i + 1))
+            })
+            .collect();
+        records.push(a(&format!("hop{count}.example"), [10, 0, 0, 1]));
+        response(&records)
+    };
+    assert_eq!(
+        check(&links(MAX_CNAME_DEPTH)),
+        Ok(Answer::Address([10, 0, 0, 1]))
+    );
+    assert_eq!(
+        check(&links(MAX_CNAME_DEPTH + 1)),
+        Err(Reject::UnrelatedAnswer)
+    );
+
+    // A loop ends.
+    let looped = response(&[cname(HOST, "b.example"), cname("b.example", HOST)]);
+    assert_eq!(check(&looped), Err(Reject::UnrelatedAnswer));
+    // A chain that goes nowhere.
+    let dangling = response(&[cname(HOST, "b.example"), a("c.example", [10, 0, 0, 2])]);
+    assert_eq!(check(&dangling), Err(Reject::UnrelatedAnswer));
+}
+
+#[test]
+fn spoofed_responses() {
+    let genuine = response(&[a(HOST, [93, 184, 215, 14])]);
+
+    // A guessed ID, one off and byte-swapped.
+    assert_eq!(
+        dns::check_response(&genuine, ID + 1, HOST),
+        Err(Reject::WrongId)
+    );
+    assert_eq!(
+        dns::check_response(&genuine, ID.swap_bytes(), HOST),
+        Err(Reject::WrongId)
+    );
+
+    // The right ID on a message that is not an answer to the question.
+    let query = message(ID, 0x0100, (&name(HOST), A, IN), &[a(HOST, [6, 6, 6, 6])]);
+    assert_eq!(check(&query), Err(Reject::WrongQuestion));
+    let notify = message(
+        ID,
+        FLAGS | 4 << 11,
+        (&name(HOST), A, IN),
+        &[a(HOST, [6, 6, 6, 6])],
+    );
+    assert_eq!(check(&notify), Err(Reject::WrongQuestion));
+    let other_host = message(
+        ID,
+        FLAGS,
+        (&name("example.org"), A, IN),
+        &[a("example.org", [6, 6, 6, 6])],
+    );
+    assert_eq!(check(&other_host), Err(Reject::WrongQuestion));
+    let suffix = message(
+        ID,
+        FLAGS,
+        (&name("evil-example.com"), A, IN),
+        &[a(HOST, [6, 6, 6, 6])],
+    );
+    assert_eq!(check(&suffix), Err(Reject::WrongQuestion));
+    let other_type = message(ID, FLAGS, (&name(HOST), AAAA, IN), &[a(HOST, [6, 6, 6, 6])]);
+    assert_eq!(check(&other_type), Err(Reject::WrongQuestion));
+    let other_class = message(ID, FLAGS, (&name(HOST), A, 3), &[a(HOST, [6, 6, 6, 6])]);
+    assert_eq!(check(&other_class), Err(Reject::WrongQuestion));
+    let mut two_questions = genuine.clone();
+    two_questions[5] = 2;
+    assert_eq!(check(&two_questions), Err(Reject::WrongQuestion));
+
+    // The question repeated, with an address for some other name: the
+    // classic cache poisoning record.
+    let unrelated = response(&[a("bank.example", [6, 6, 6, 6])]);
+    assert_eq!(check(&unrelated), Err(Reject::UnrelatedAnswer));
+    let wrong_class = response(&[Record {
+        class: 3,
+        ..a(HOST, [6, 6, 6, 6])
+    }]);
+    assert_eq!(check(&wrong_class), Err(Reject::UnrelatedAnswer));
+    // "example.com" as one label with a dot in it.
+    let mut dotted = vec![11];
+    dotted.extend_from_slice(HOST.as_bytes());
+    dotted.push(0);
+    let dotted_owner = response(&[Record {
+        owner: dotted.clone(),
+        ..a(HOST, [6, 6, 6, 6])
+    }]);
+    assert_eq!(check(&dotted_owner), Err(Reject::Malformed));
+    assert_eq!(
+        check(&message(ID, FLAGS, (&dotted, A, IN), &[])),
+        Err(Reject::Malformed)
+    );
+
+    // An A record that is not four bytes.
+    let long_address = response(&[Record {
+        data: vec![6, 6, 6, 6, 6],
+        ..a(HOST, [0; 4])
+    }]);
+    assert_eq!(check(&long_address), Err(Reject::Malformed));
+}
+
+#[test]
+fn malformed() {
+    // A pointer to itself, and a loop between two, in the answer's owner.
+    for pointers in [&[0xc0, 29][..], &[0xc0, 31, 0xc0, 29]] {
+        let mut message = response(&[]);
+        message[7] = 1;
+        message.extend_from_slice(pointers);
+        message.extend([0, 1, 0, 1, 0, 0, 0, 0, 0, 4, 1, 1, 1, 1]);
+        assert_eq!(check(&message), Err(Reject::Malformed), "{pointers:?}");
+    }
+    // A pointer past the end, a label length past 63.
+    let mut message = response(&[]);
+    message[7] = 1;
+    message.extend([0xc0, 0xff]);
+    assert_eq!(check(&message), Err(Reject::Malformed));
+    let mut message = response(&[]);
+    message[HEADER_LEN] = 0x40;
+    assert_eq!(check(&message), Err(Reject::Malformed));
+    // More answers than there are.
+    let mut message = response(&[a(HOST, [1, 1, 1, 1])]);
+    message[7] = 2;
+    message.truncate(message.len() - 4);
+    assert_eq!(check(&message), Err(Reject::Malformed));
+
+    // A name of more than 255 bytes.
+    let mut message = vec![0; HEADER_LEN];
+    for _ in 0..5 {
+        message.push(63);
+        message.extend([b'x'; 63]);
+    }
+    message.push(0);
+    let mut out = Name::new();
+    assert_eq!(
+        dns::read_name(&message, HEADER_LEN, &mut out),
+        Err(Reject::Malformed)
+    );
+}
+
+#[test]
+fn every_truncation_is_rejected() {
+    let chain = response(&[
+        cname(HOST, "edge.example.net"),
+        a("edge.example.net", [192, 0, 2, 7]),
+    ]);
+    let corpus: [&[u8]; 5] = [
+        &chain,
+        include_bytes!("../corpus/dns_response/a-record"),
+        include_bytes!("../corpus/dns_response/cname-chain"),
+        include_bytes!("../corpus/dns_response/nxdomain"),
+        include_bytes!("../corpus/dns_response/pointer-loop"),
+    ];
+    for full in corpus {
+        let id = u16::from_be_bytes([full[0], full[1]]);
+        let answer = dns::check_response(full, id, HOST);
+        for len in 0..full.len() {
+            let cut = dns::check_response(&full[..len], id, HOST);
+            // Only an answer found before the cut survives it.
+            assert!(
+                cut == Err(Reject::Malformed) || cut == answer,
+                "{len} of {}: {cut:?}",
+                full.len()
+            );
+        }
+    }
+}
+
+#[test]
+fn corpus() {
+    let check_id = |message: &[u8]| {
+        dns::check_response(message, u16::from_be_bytes([message[0], message[1]]), HOST)
+    };
+    assert_eq!(
+        check_id(include_bytes!("../corpus/dns_response/a-record")),
+        Ok(Answer::Address([93, 184, 215, 14]))
+    );
+    assert_eq!(
+        check_id(include_bytes!("../corpus/dns_response/cname-chain")),
+        Ok(Answer::Address([192, 0, 2, 7]))
+    );
+    assert_eq!(
+        check_id(include_bytes!("../corpus/dns_response/nxdomain")),
+        Ok(Answer::NotFound)
+    );
+    // The answer's owner points past the loop, at the root; the loop is
+    // where its type would be.
+    let looped = include_bytes!("../corpus/dns_response/pointer-loop");
+    assert_eq!(check_id(looped), Err(Reject::UnrelatedAnswer));
+    let mut out = Name::new();
+    assert_eq!(dns::read_name(looped, 31, &mut out), Err(Reject::Malformed));
+}
@@ -0,0 +1,184 @@
+// Fleet commands as the config poll brings them: parsed from a part, and
+// run once per id across polls of the same answer, a reboot among them
+// resetting the device partway through.
+
+use esp32c3_fuzz::fleetorder::{Answer, Command, Id, Order, Ran, Step, MAX_COMMANDS, MAX_RAN};
+
+/// The KV store's `fleet` key, across resets.
+#[derive(Default)]
+struct Device {
+    stored: Option<Vec<u8>>,
+    /// Every command run, in order.
+    ran: Vec<String>,
+}
+
+impl Device {
+    /// One poll answered with commands of `ids`, as src/fleet.rs goes
+    /// through them. One starting with `reboot` resets the device once it
+    /// runs, and the rest of the answer is not seen. Returns what ran.
+    fn poll(&mut self, ids: &[&str]) -> Vec<String> {
+        let before = self.ran.len();
+        let mut answer = Answer::new(Ran::decode(self.stored.as_deref().unwrap_or(&[])));
+        for id in ids {
+            match answer.next(&Id::try_from(*id).unwrap()) {
+                Step::Run => {
+                    self.stored = Some(answer.kept().encode().into_bytes().to_vec());
+                    self.ran.push(id.to_string());
+                    if id.starts_with("reboot") {
+                        return self.ran[before..].to_vec();
+                    }
+                }
+                Step::Skip | Step::Refuse => {}
+            }
+        }
+        if let Some(ran) = answer.finish() {
+            self.stored = Some(ran.encode().into_bytes().to_vec());
+        }
+        self.ran[before..].to_vec()
+    }
+
+    fn kept(&self) -> Ran {
+        Ran::decode(self.stored.as_deref().unwrap_or(&[]))
+    }
+}
+
+#[test]
+fn two_commands_run_once_over_two_polls() {
+    let mut device = Device::default();
+    assert_eq!(device.poll(&["night", "fw-0.2.0"]), ["night", "fw-0.2.0"]);
+    assert!(device.poll(&["night", "fw-0.2.0"]).is_empty());
+    assert!(device.poll(&["night", "fw-0.2.0"]).is_empty());
+    assert_eq!(device.ran, ["night", "fw-0.2.0"]);
+}
+
+#[test]
+fn a_reboot_does_not_run_what_came_before_it_again() {
+    // Config, then reboot: the reset comes before the answer is done.
+    let mut device = Device::default();
+    let answer = ["night", "reboot-1"];
+    assert_eq!(device.poll(&answer), answer);
+    for _ in 0..3 {
+        assert!(device.poll(&answer).is_empty());
+    }
+
+    // Reboot first: the config runs after the reset, and only then.
+    let mut device = Device::default();
+    let answer = ["reboot-1", "night"];
+    assert_eq!(device.poll(&answer), ["reboot-1"]);
+    assert_eq!(device.poll(&answer), ["night"]);
+    assert!(device.poll(&answer).is_empty());
+    assert_eq!(device.ran, answer);
+}
+
+#[test]
+fn only_the_last_answer_is_kept() {
+    let mut device = Device::default();
+    device.poll(&["a", "b"]);
+    // A new command next to one that has run.
+    assert_eq!(device.poll(&["b", "c"]), ["c"]);
+    assert!(device.poll(&["c", "b"]).is_empty());
+    // Taken out, then put back: it runs again, as a new command.
+    assert!(device.poll(&["c"]).is_empty());
+    assert_eq!(device.poll(&["c", "b"]), ["b"]);
+
+    // An answer with no commands forgets them all.
+    device.poll(&[]);
+    assert!(device.kept().is_empty());
+    assert_eq!(device.poll(&["c"]), ["c"]);
+}
+
+#[test]
+fn a_poll_that_changes_nothing_writes_nothing() {
+    let mut answer = Answer::new(Ran::decode(b"b\na\n"));
+    for id in ["a", "b"] {
+        assert_eq!(answer.next(&Id::try_from(id).unwrap()), Step::Skip);
+    }
+    assert_eq!(answer.finish(), None);
+    assert_eq!(Answer::new(Ran::default()).finish(), None);
+}
+
+#[test]
+fn one_answer_runs_an_id_once_and_so_many_commands() {
+    let mut device = Device::default();
+    assert_eq!(device.poll(&["a", "a", "b", "a"]), ["a", "b"]);
+
+    let ids: Vec<String> = (0..MAX_COMMANDS + 2).map(|i| format!("cmd-{i}")).collect();
+    let ids: Vec<&str> = ids.iter().map(String::as_str).collect();
+    let mut device = Device::default();
+    assert_eq!(device.poll(&ids), ids[..MAX_COMMANDS]);
+    // The ones refused stay refused, and the others do not run again.
+    assert!(device.poll(&ids).is_empty());
+}
+
+#[test]
+fn answers_cut_short_by_reboots_stay_bounded() {
+    // Each answer new, each cut short by its reboot: none is finished, and
+    // what is kept stays bounded, the oldest dropped.
+    let mut device = Device::default();
+    let mut last = Vec::new();
+    for round in 0..10 {
+        last = (0..MAX_COMMANDS - 1)
+            .map(|i| format!("r{round}-{i}"))
+            .chain([format!("reboot-{round}")])
+            .collect();
+        let ids: Vec<&str> = last.iter().map(String::as_str).collect();
+        assert_eq!(device.poll(&ids), ids);
+        assert!(device.kept().len() <= MAX_RAN);
+    }
+    assert_eq!(device.kept().len(), MAX_RAN);
+    // The answer in hand never runs again, and once done is all that is
+    // kept.
+    let ids: Vec<&str> = last.iter().map(String::as_str).collect();
+    assert!(device.poll(&ids).is_empty());
+    assert_eq!(device.kept().len(), MAX_COMMANDS);
+}
+
+#[test]
+fn what_was_stored_before_still_reads() {
+    // The single id the store kept before there were sets of them.
+    let mut device = Device {
+        stored: Some(b"2024-06-01-reboot".to_vec()),
+        ..Device::default()
+    };
+    assert_eq!(device.poll(&["2024-06-01-reboot", "night"]), ["night"]);
+
+    // Anything else is read as far as it makes sense.
+    let long = "x".repeat(40);
+    let ran = Ran::decode(format!("a\n\n{long}\nb\n").as_bytes());
+    assert!(ran.contains("a") && ran.contains("b") && !ran.contains(&long));
+    assert_eq!(ran.len(), 2);
+    assert!(Ran::decode(&[0xff, b'\n']).is_empty());
+    assert_eq!(Ran::decode(ran.encode().as_bytes()), ran);
+}
+
+#[test]
+fn orders_parse() {
+    let order =
+        Order::parse(br#"{"id":"night","cmd":"config","key":"interval_s","value":"600"}"#).unwrap();
+    assert_eq!(order.id, "night");
+    assert_eq!(
+        order.command,
+        Command::Config {
+            key: "interval_s".try_into().unwrap(),
+            value: "600".try_into().unwrap(),
+        }
+    );
+    assert!(order.is_for("00ff"));
+
+    let order = Order::parse(br#"{"id":"r","cmd":"reboot","device":"00FF"}"#).unwrap();
+    assert_eq!(order.command, Command::Reboot);
+    assert!(order.is_for("00ff") && !order.is_for("0100"));
+
+    for (body, error) in [
+        (&br#"{"cmd":"reboot"}"#[..], "an id is required"),
+        (br#"{"id":"a","cmd":"reboot","id":"b"}"#, "duplicate key"),
+        (br#"{"id":"a","cmd":"dance"}"#, "unknown cmd"),
+        (br#"{"id":"a","cmd":"update"}"#, "update needs a url"),
+        (br#"{"id":"a"}"#, "a cmd is required"),
+        (br#"{"id":"a","cmd":"reboot","at":"now"}"#, "unknown key"),
+    ] {
+        assert_eq!(Order::parse(body), Err(error));
+    }
+    let long = format!(r#"{{"id":"{}","cmd":"reboot"}}"#, "x".repeat(33));
+    assert_eq!(Order::parse(long.as_bytes()), Err("id too long"));
+}
@@ -0,0 +1,153 @@
+// The settings' extra request headers: what `add` takes and refuses, the
+// limits on their number and size, and how they merge with a request's
+// own.
+
+use esp32c3_fuzz::headers::{HeaderError, Headers, MAX_COUNT, MAX_LEN, USER_AGENT};
+
+fn headers(lines: &[&str]) -> Headers {
+    let mut headers = Headers::new();
+    for line in lines {
+        headers.add(line).unwrap();
+    }
+    headers
+}
+
+fn merged(headers: &Headers, own: &[(&str, &str)]) -> String {
+    let mut out = String::new();
+    headers.write_merged(own, &mut out).unwrap();
+    out
+}
+
+#[test]
+fn adds_and_reads_back() {
+    let headers = headers(&[
+        "X-Tenant: acme",
+        "Accept:application/json",
+        "X-Trace:\t 1 2 \t",
+    ]);
+    assert_eq!(
+        headers.iter().collect::<Vec<_>>(),
+        [
+            ("X-Tenant", "acme"),
+            ("Accept", "application/json"),
+            ("X-Trace", "1 2")
+        ]
+    );
+    assert_eq!(headers.get("x-tenant"), Some("acme"));
+    assert_eq!(headers.get("ACCEPT"), Some("application/json"));
+    assert_eq!(headers.get("X-Tenan"), None);
+    // A colon in the value is the value's.
+    let headers = self::headers(&["X-Url: https://example.com:8443/a"]);
+    assert_eq!(headers.get("X-Url"), Some("https://example.com:8443/a"));
+}
+
+#[test]
+fn refuses_what_is_not_a_header() {
+    let mut headers = Headers::new();
+    for bad in [
+        "",
+        "X-Tenant",
+        ": acme",
+        "X-Tenant:",
+        "X-Tenant:   ",
+        "X Tenant: acme",
+        "X-Tenant : acme",
+        "X-Ten(ant): acme",
+        "X-Tenant: ac\rme",
+        "X-Tenant: ac\nme",
+        "X-Tenant: acme\r\nHost: evil",
+        "X-Tenant: acmé",
+        "X-Tenant: \0",
+    ] {
+        assert_eq!(headers.add(bad), Err(HeaderError::Malformed), "{bad:?}");
+    }
+    for forbidden in [
+        "Host: example.com",
+        "connection: close",
+        "CONTENT-LENGTH: 0",
+        "Transfer-Encoding: chunked",
+    ] {
+        assert_eq!(headers.add(forbidden), Err(HeaderError::Forbidden));
+    }
+    assert_eq!(headers, Headers::new());
+}
+
+#[test]
+fn a_name_only_once() {
+    let mut headers = headers(&["X-Tenant: acme"]);
+    assert_eq!(headers.add("x-TENANT: other"), Err(HeaderError::Duplicate));
+    assert_eq!(headers.add("X-Tenant: acme"), Err(HeaderError::Duplicate));
+    assert_eq!(headers.iter().count(), 1);
+    assert_eq!(headers.get("X-Tenant"), Some("acme"));
+}
+
+#[test]
+fn limits() {
+    let mut headers = Headers::new();
+    for i in 0..MAX_COUNT {
+        headers.add(&format!("X-{i}: {i}")).unwrap();
+    }
+    let before = headers.clone();
+    assert_eq!(headers.add("X-More: 1"), Err(HeaderError::TooMany));
+    assert_eq!(headers, before);
+
+    // `MAX_LEN` bytes of lines exactly, and one more.
+    let line = |name: &str, len: usize| format!("{name}: {}", "v".repeat(len - name.len() - 4));
+    let mut full = Headers::new();
+    full.add(&line("X-A", 100)).unwrap();
+    full.add(&line("X-B", MAX_LEN - 100)).unwrap();
+    assert_eq!(merged(&full, &[("User-Agent", "t")]).len(), MAX_LEN + 15);
+    let mut over = Headers::new();
+    over.add(&line("X-A", 100)).unwrap();
+    assert_eq!(
+        over.add(&line("X-B", MAX_LEN - 99)),
+        Err(HeaderError::TooLong)
+    );
+    assert_eq!(over.iter().count(), 1);
+    assert_eq!(
+        Headers::new().add(&line("X-A", MAX_LEN + 1)),
+        Err(HeaderError::TooLong)
+    );
+}
+
+#[test]
+fn merge_puts_the_request_first_and_its_headers_win() {
+    let headers = headers(&["X-Tenant: acme", "content-type: text/plain", "Accept: */*"]);
+    assert_eq!(
+        merged(&headers, &[("Content-Type", "application/json")]),
+        format!(
+            "Content-Type: application/json\r\n\
+             X-Tenant: acme\r\n\
+             Accept: */*\r\n\
+             User-Agent: {USER_AGENT}\r\n"
+        )
+    );
+    // Nothing of the request's own: the settings' go as they are.
+    assert_eq!(
+        merged(&headers, &[]),
+        format!(
+            "X-Tenant: acme\r\n\
+             content-type: text/plain\r\n\
+             Accept: */*\r\n\
+             User-Agent: {USER_AGENT}\r\n"
+        )
+    );
+}
+
+#[test]
+fn user_agent_from_either_side_replaces_the_default() {
+    let settings = headers(&["User-Agent: probe/2"]);
+    assert_eq!(merged(&settings, &[]), "User-Agent: probe/2\r\n");
+    assert_eq!(
+        merged(&settings, &[("user-agent", "ota/1")]),
+        "user-agent: ota/1\r\n"
+    );
+    assert_eq!(
+        merged(&Headers::new(), &[("User-Agent", "ota/1")]),
+        "User-Agent: ota/1\r\n"
+    );
+    assert_eq!(
+        merged(&Headers::new(), &[]),
+        format!("User-Agent: {USER_AGENT}\r\n")
+    );
+}
@@ -0,0 +1,643 @@
+// A request and its response against a mock connection: the body sent in
+// pieces, and what happens when the server answers early, say with a 401
+// to a large upload, or resets the connection partway through, and when
+// what it sends is only session tickets; then a response read in pieces,
+// dechunked, and checked against its SHA-256, and a streamed one, which
+// cannot be chunked and is cut short if the connection fails early.
+
+mod common;
+
+use esp32c3_fuzz::httpsbody::{
+    self, Body, Incoming, Outgoing, Records, ResponseError, Sent, Streamed, StreamedError,
+    BODY_CHUNK_LEN, GZIP_CHUNK_LEN, RECORD_HEADER_LEN,
+};
+use esp32c3_fuzz::integrity::HASH_LEN;
+use esp32c3_fuzz::{codec, deflate, http};
+use sha2::Digest;
+
+use common::block_on;
+
+const HEAD: &str = "POST /ingest HTTP/1.1\r\nHost: example.com\r\nContent-Length: 5000\r\n\r\n";
+
+#[derive(Debug, PartialEq)]
+struct Failed;
+
+/// The content types a TLS 1.3 record carries last, inside its encryption.
+const HANDSHAKE: u8 = 0x16;
+const APPLICATION_DATA: u8 = 0x17;
+
+/// A TLS 1.3 record around `data`, unencrypted.
+fn record(content_type: u8, data: &[u8]) -> Vec<u8> {
+    let len = (data.len() + 1) as u16;
+    [
+        &[0x17, 0x03, 0x03],
+        &len.to_be_bytes()[..],
+        data,
+        &[content_type],
+    ]
+    .concat()
+}
+
+/// A NewSessionTicket, as nginx sends two of after the handshake.
+fn ticket() -> Vec<u8> {
+    record(HANDSHAKE, &[0x04; 200])
+}
+
+/// A connection to a server that answers once it has `answer_at` bytes,
+/// and resets the connection, or simply fails, at `reset_at` or
+/// `fail_at`. What it sends back is TLS records, and read as `Link` does:
+/// only those in whole, and only data among them is an answer.
+#[derive(Default)]
+struct Mock {
+    answer_at: Option<usize>,
+    reset_at: Option<usize>,
+    fail_at: Option<usize>,
+    /// What the server got.
+    server: Vec<u8>,
+    /// Written and not yet flushed.
+    pending: Vec<u8>,
+    /// The size of each flush.
+    flushes: Vec<usize>,
+    reset: bool,
+    /// Records sent back and not yet read.
+    incoming: Vec<u8>,
+    records: Records,
+    responded: bool,
+    data_read: bool,
+}
+
+impl Outgoing for Mock {
+    type Error = Failed;
+
+    async fn write_all(&mut self, data: &[u8]) -> Result<(), Failed> {
+        if self.reset {
+            return Err(Failed);
+        }
+        self.pending.extend_from_slice(data);
+        Ok(())
+    }
+
+    async fn flush(&mut self) -> Result<(), Failed> {
+        if self.reset {
+            return Err(Failed);
+        }
+        let after = self.server.len() + self.pending.len();
+        if let Some(at) = self.reset_at.or(self.fail_at).filter(|&at| after > at) {
+            let taken = at - self.server.len();
+            self.server.extend(self.pending.drain(..).take(taken));
+            self.reset = true;
+            return Err(Failed);
+        }
+        self.flushes.push(self.pending.len());
+        self.server.append(&mut self.pending);
+        Ok(())
+    }
+
+    async fn answered(&mut self) -> bool {
+        if !self.responded && self.answer_at.is_some_and(|at| self.server.len() >= at) {
+            self.responded = true;
+            self.incoming
+                .extend(record(APPLICATION_DATA, b"HTTP/1.1 401 Unauthorized\r\n"));
+        }
+        let whole = self.records.whole(&self.incoming);
+        let read: Vec<u8> = self.incoming.drain(..whole).collect();
+        self.records.advance(&read);
+        self.data_read |= content_types(&read).contains(&APPLICATION_DATA);
+        self.data_read || (self.reset && self.reset_at.is_some())
+    }
+}
+
+/// The content type of each of `data`'s records, which are whole.
+fn content_types(mut data: &[u8]) -> Vec<u8> {
+    let mut types = Vec::new();
+    while !data.is_empty() {
+        let len = u16::from_be_bytes([data[3], data[4]]) as usize;
+        let end = RECORD_HEADER_LEN + len;
+        types.push(data[end - 1]);
+        data = &data[end..];
+    }
+    types
+}
+
+fn body(len: usize) -> Vec<u8> {
+    (0..len).map(|i| b"0123456789abcdef"[i % 16]).collect()
+}
+
+fn send(mock: &mut Mock, body: &[u8]) -> Result<Sent, Failed> {
+    block_on(httpsbody::send(mock, HEAD, Some(Body::Plain(body))))
+}
+
+#[test]
+fn the_whole_body_in_pieces() {
+    let body = body(5000);
+    let mut mock = Mock::default();
+    assert_eq!(
+        send(&mut mock, &body),
+        Ok(Sent {
+            bytes: HEAD.len() + 5000,
+            early: false
+        })
+    );
+    assert_eq!(mock.server, [HEAD.as_bytes(), &body].concat());
+    // The head goes with the first piece; the last flush has nothing left.
+    assert_eq!(
+        mock.flushes,
+        [HEAD.len() + BODY_CHUNK_LEN, 1024, 1024, 1024, 904, 0]
+    );
+
+    // No body, and an empty one.
+    for body in [None, Some(Body::Plain(&[]))] {
+        let mut mock = Mock::default();
+        let sent = block_on(httpsbody::send(&mut mock, HEAD, body));
+        assert_eq!(
+            sent,
+            Ok(Sent {
+                bytes: HEAD.len(),
+                early: false
+            })
+        );
+        assert_eq!(mock.server, HEAD.as_bytes());
+    }
+}
+
+#[test]
+fn an_answer_to_the_head_stops_the_body() {
+    // A 401 as soon as the headers are in.
+    let mut mock = Mock {
+        answer_at: Some(HEAD.len()),
+        ..Mock::default()
+    };
+    // The head alone goes out before the first piece, so the answer is
+    // seen only after that one.
+    let sent = send(&mut mock, &body(5000)).unwrap();
+    assert!(sent.early);
+    assert_eq!(sent.bytes, HEAD.len() + BODY_CHUNK_LEN);
+    assert_eq!(mock.server.len(), sent.bytes);
+
+    // One that has come before the body starts.
+    let mut mock = Mock {
+        answer_at: Some(0),
+        ..Mock::default()
+    };
+    let sent = send(&mut mock, &body(5000)).unwrap();
+    assert_eq!(
+        sent,
+        Sent {
+            bytes: HEAD.len(),
+            early: true
+        }
+    );
+    // Nothing went to the server: the head was never flushed.
+    assert!(mock.server.is_empty());
+}
+
+#[test]
+fn an_answer_mid_body_stops_it_at_the_next_piece() {
+    let answer_at = HEAD.len() + 1500;
+    let mut mock = Mock {
+        answer_at: Some(answer_at),
+        ..Mock::default()
+    };
+    let sent = send(&mut mock, &body(5000)).unwrap();
+    assert!(sent.early);
+    assert_eq!(sent.bytes, HEAD.len() + 2 * BODY_CHUNK_LEN);
+    assert_eq!(mock.server.len(), sent.bytes);
+    assert!(mock.pending.is_empty());
+
+    // An answer after the last piece is an ordinary one.
+    let mut mock = Mock {
+        answer_at: Some(HEAD.len() + 5000),
+        ..Mock::default()
+    };
+    assert!(!send(&mut mock, &body(5000)).unwrap().early);
+}
+
+#[test]
+fn session_tickets_are_not_an_answer() {
+    // Two tickets in, and the start of a third, when the request starts.
+    let third = ticket();
+    let mut mock = Mock {
+        incoming: [ticket(), ticket(), third[..100].to_vec()].concat(),
+        ..Mock::default()
+    };
+    let body = body(5000);
+    let sent = send(&mut mock, &body).unwrap();
+    assert_eq!(
+        sent,
+        Sent {
+            bytes: HEAD.len() + 5000,
+            early: false
+        }
+    );
+    assert_eq!(mock.server, [HEAD.as_bytes(), &body].concat());
+    // The whole ones were taken in, the cut one left for later.
+    assert_eq!(mock.incoming, third[..100]);
+
+    // Gzipped, in its smaller pieces.
+    let json: String = (0..100)
+        .map(|i| format!("{{\"seq\":{i},\"temperature_c\":21.5,\"humidity\":40}}\n"))
+        .collect();
+    let gzipped = deflate::gzip(json.as_bytes()).unwrap();
+    let mut mock = Mock {
+        incoming: ticket(),
+        ..Mock::default()
+    };
+    let sent = block_on(httpsbody::send(&mut mock, HEAD, Some(Body::Gzip(&gzipped)))).unwrap();
+    assert!(!sent.early);
+    assert_eq!(sent.bytes, HEAD.len() + gzipped.compressed_len());
+
+    // A response behind the tickets still stops it.
+    let mut mock = Mock {
+        incoming: ticket(),
+        answer_at: Some(HEAD.len() + 1500),
+        ..Mock::default()
+    };
+    let sent = send(&mut mock, &body).unwrap();
+    assert!(sent.early);
+    assert_eq!(sent.bytes, HEAD.len() + 2 * BODY_CHUNK_LEN);
+}
+
+#[test]
+fn records_are_followed_across_reads() {
+    let stream = [
+        ticket(),
+        record(APPLICATION_DATA, b"HTTP/1.1 200 OK\r\n"),
+        ticket(),
+    ]
+    .concat();
+    let first = ticket().len();
+    let second = stream.len() - first - ticket().len();
+    let mut records = Records::default();
+    assert_eq!(records.whole(&stream), stream.len());
+    assert_eq!(records.whole(&stream[..first + 3]), first);
+    assert_eq!(records.whole(&stream[..first - 1]), 0);
+    assert_eq!(records.whole(&[]), 0);
+
+    // Read in uneven pieces: mid-body, mid-header and on the boundary.
+    records.advance(&stream[..50]);
+    assert_eq!(records.whole(&stream[50..]), stream.len() - 50);
+    assert_eq!(records.whole(&stream[50..first]), first - 50);
+    assert_eq!(records.whole(&stream[50..first - 1]), 0);
+    records.advance(&stream[50..first + 2]);
+    // Partway into a header, nothing is known to end anywhere.
+    assert_eq!(records.whole(&stream[first + 2..]), 0);
+    records.advance(&stream[first + 2..first + second]);
+    assert_eq!(records.whole(&stream[first + second..]), ticket().len());
+}
+
+#[test]
+fn a_reset_mid_body_is_an_early_answer() {
+    let mut mock = Mock {
+        reset_at: Some(HEAD.len() + 1500),
+        ..Mock::default()
+    };
+    let sent = send(&mut mock, &body(5000)).unwrap();
+    // The piece that was cut off does not count as sent.
+    assert_eq!(
+        sent,
+        Sent {
+            bytes: HEAD.len() + BODY_CHUNK_LEN,
+            early: true
+        }
+    );
+    assert_eq!(mock.server.len(), HEAD.len() + 1500);
+}
+
+#[test]
+fn a_failure_without_an_answer_is_an_error() {
+    for fail_at in [HEAD.len() + 10, HEAD.len() + 4999] {
+        let mut mock = Mock {
+            fail_at: Some(fail_at),
+            ..Mock::default()
+        };
+        assert_eq!(send(&mut mock, &body(5000)), Err(Failed));
+    }
+    // The head itself not going out.
+    let mut mock = Mock {
+        reset: true,
+        ..Mock::default()
+    };
+    assert_eq!(send(&mut mock, &body(10)), Err(Failed));
+}
+
+#[test]
+fn gzipped_bodies() {
+    let json: String = (0..100)
+        .map(|i| format!("{{\"seq\":{i},\"temperature_c\":21.5,\"humidity\":40}}\n"))
+        .collect();
+    let gzipped = deflate::gzip(json.as_bytes()).unwrap();
+    let mut compressed = vec![0; gzipped.compressed_len()];
+    assert_eq!(gzipped.encoder().read(&mut compressed), compressed.len());
+
+    let mut mock = Mock::default();
+    let sent = block_on(httpsbody::send(&mut mock, HEAD, Some(Body::Gzip(&gzipped))));
+    assert_eq!(
+        sent,
+        Ok(Sent {
+            bytes: HEAD.len() + compressed.len(),
+            early: false
+        })
+    );
+    assert_eq!(mock.server, [HEAD.as_bytes(), &compressed].concat());
+    assert!(mock.flushes[1..].iter().all(|&len| len <= GZIP_CHUNK_LEN));
+    assert_eq!(Body::Gzip(&gzipped).wire_len(), compressed.len());
+
+    // An early answer stops the compressor too.
+    let mut mock = Mock {
+        answer_at: Some(HEAD.len() + 1),
+        ..Mock::default()
+    };
+    let sent = block_on(httpsbody::send(&mut mock, HEAD, Some(Body::Gzip(&gzipped)))).unwrap();
+    assert!(sent.early);
+    assert_eq!(sent.bytes, HEAD.len() + GZIP_CHUNK_LEN);
+}
+
+/// A server's response, handed out in reads of the sizes in `reads`, the
+/// last one repeated, and then the end of it.
+struct Response {
+    data: Vec<u8>,
+    reads: Vec<usize>,
+    pos: usize,
+    /// `started` at each read.
+    started: Vec<bool>,
+    /// Fails the read at this offset.
+    fail_at: Option<usize>,
+}
+
+impl Response {
+    fn new(data: Vec<u8>, reads: &[usize]) -> Self {
+        Self {
+            data,
+            reads: reads.to_vec(),
+            pos: 0,
+            started: Vec::new(),
+            fail_at: None,
+        }
+    }
+}
+
+impl Incoming for Response {
+    type Error = Failed;
+
+    async fn read_part(&mut self, buf: &mut [u8], started: bool) -> Result<usize, Failed> {
+        self.started.push(started);
+        if self.fail_at == Some(self.pos) {
+            return Err(Failed);
+        }
+        let size = match self.reads.len() {
+            0 => usize::MAX,
+            1 => self.reads[0],
+            _ => self.reads.remove(0),
+        };
+        let n = size.min(buf.len()).min(self.data.len() - self.pos);
+        buf[..n].copy_from_slice(&self.data[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+fn sha256(data: &[u8]) -> [u8; HASH_LEN] {
+    sha2::Sha256::digest(data).into()
+}
+
+fn hex(digest: &[u8; HASH_LEN]) -> String {
+    let mut out = [0; 2 * HASH_LEN];
+    codec::hex_encode(digest, &mut out).unwrap().to_owned()
+}
+
+fn base64(digest: &[u8; HASH_LEN]) -> String {
+    let mut out = [0; 44];
+    codec::STANDARD.encode(digest, &mut out).unwrap().to_owned()
+}
+
+fn content_length(body: &[u8], digest_header: &str) -> Vec<u8> {
+    let head = format!(
+        "HTTP/1.1 200 OK\r\nContent-Length: {}\r\n{digest_header}\r\n\r\n",
+        body.len()
+    );
+    [head.as_bytes(), body].concat()
+}
+
+/// `body` in chunks of `sizes`, the last one repeated.
+fn chunked(body: &[u8], sizes: &[usize], digest_header: &str) -> Vec<u8> {
+    let mut out =
+        format!("HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n{digest_header}\r\n\r\n")
+            .into_bytes();
+    let mut rest = body;
+    let mut sizes = sizes
+        .iter()
+        .copied()
+        .chain(std::iter::repeat(sizes[sizes.len() - 1]));
+    while !rest.is_empty() {
+        let (chunk, after) = rest.split_at(sizes.next().unwrap().min(rest.len()));
+        out.extend(format!("{:x}\r\n", chunk.len()).bytes());
+        out.extend_from_slice(chunk);
+        out.extend(b"\r\n");
+        rest = after;
+    }
+    out.extend(b"0\r\nX-Trailer: ignored\r\n\r\n");
+    out
+}
+
+/// What `get_verified` does with `data` coming in as `reads`, into a
+/// buffer of `capacity`: the body as decoded, and the verdict.
+fn get_verified(
+    data: Vec<u8>,
+    reads: &[usize],
+    capacity: usize,
+    expected: Option<&[u8; HASH_LEN]>,
+) -> (Vec<u8>, Result<(), ResponseError>) {
+    let mut link = Response::new(data, reads);
+    let mut buf = vec![0; capacity];
+    let mut received = 0;
+    let mut first = 0;
+    let len = block_on(httpsbody::receive(
+        &mut link,
+        &mut buf,
+        &mut received,
+        || first += 1,
+    ))
+    .unwrap();
+    assert_eq!((received, first), (len, 1));
+    // Only the first read is not `started`.
+    assert!(!link.started[0] && link.started[1..].iter().all(|&s| s));
+    let (_, body_start) = http::parse_head(&buf[..len]).unwrap();
+    let len = httpsbody::decode_body(&mut buf, body_start, len, false).unwrap();
+    let verdict = httpsbody::verify(&buf[..body_start], &buf[body_start..len], expected);
+    (buf[body_start..len].to_vec(), verdict)
+}
+
+fn payload(len: usize) -> Vec<u8> {
+    (0..len as u32)
+        .map(|i| (i.wrapping_mul(2_654_435_761) >> 11) as u8)
+        .collect()
+}
+
+#[test]
+fn hashes_a_body_that_came_in_many_reads() {
+    let body = payload(3000);
+    let digest = sha256(&body);
+    let headers = [
+        format!("X-Content-Sha256: {}", hex(&digest)),
+        format!("X-Content-Sha256: {}", hex(&digest).to_uppercase()),
+        format!("Digest: sha-256={}", base64(&digest)),
+        format!(
+            "Digest: md5=rL0Y20zC+Fzt72VPzMSk2A==, SHA-256={}",
+            base64(&digest)
+        ),
+    ];
+    for header in &headers {
+        for reads in [&[1][..], &[7, 1, 300], &[1460], &[4096]] {
+            let (got, verdict) = get_verified(content_length(&body, header), reads, 4096, None);
+            assert_eq!(got, body);
+            assert_eq!(verdict, Ok(()), "{header} {reads:?}");
+
+            for sizes in [&[1][..], &[512], &[100, 2000, 7], &[3000]] {
+                let response = chunked(&body, sizes, header);
+                let (got, verdict) = get_verified(response, reads, 20_000, None);
+                // The decoded body is what is hashed.
+                assert_eq!(got, body);
+                assert_eq!(verdict, Ok(()), "{header} {reads:?} {sizes:?}");
+            }
+        }
+    }
+    // An empty body.
+    let header = format!("X-Content-Sha256: {}", hex(&sha256(b"")));
+    let (got, verdict) = get_verified(content_length(b"", &header), &[5], 512, None);
+    assert!(got.is_empty());
+    assert_eq!(verdict, Ok(()));
+}
+
+#[test]
+fn a_digest_given_wins_over_the_published_one() {
+    let body = payload(2000);
+    let digest = sha256(&body);
+    let wrong = sha256(b"something else");
+    let response = |digest: &[u8; HASH_LEN]| {
+        chunked(&body, &[333], &format!("X-Content-Sha256: {}", hex(digest)))
+    };
+    assert_eq!(
+        get_verified(response(&wrong), &[64], 4096, Some(&digest)).1,
+        Ok(())
+    );
+    assert_eq!(
+        get_verified(response(&digest), &[64], 4096, Some(&wrong)).1,
+        Err(ResponseError::Mismatch)
+    );
+    // Nothing published is fine with one given.
+    let bare = content_length(&body, "X-Other: 1");
+    assert_eq!(get_verified(bare, &[64], 4096, Some(&digest)).1, Ok(()));
+}
+
+#[test]
+fn mismatches() {
+    let body = payload(2000);
+    let header = format!("Digest: sha-256={}", base64(&sha256(&body)));
+    // A byte changed anywhere in the body, framed either way.
+    for at in [0, 1, 999, 1999] {
+        let mut changed = body.clone();
+        changed[at] ^= 0x20;
+        let (_, verdict) = get_verified(content_length(&changed, &header), &[100], 4096, None);
+        assert_eq!(verdict, Err(ResponseError::Mismatch), "{at}");
+        let (_, verdict) = get_verified(chunked(&changed, &[128], &header), &[100], 4096, None);
+        assert_eq!(verdict, Err(ResponseError::Mismatch), "{at}");
+    }
+    // A body cut short by the buffer.
+    let response = content_length(&body, &header);
+    let (got, verdict) = get_verified(response.clone(), &[100], response.len() - 1, None);
+    assert_eq!(got.len(), body.len() - 1);
+    assert_eq!(verdict, Err(ResponseError::Mismatch));
+    // The hash of the body as it was framed, not as decoded.
+    let framed = chunked(&body, &[500], "X-Other: 1");
+    let raw_body = &framed[framed.windows(4).position(|w| w == b"\r\n\r\n").unwrap() + 4..];
+    let header = format!("X-Content-Sha256: {}", hex(&sha256(raw_body)));
+    let (_, verdict) = get_verified(chunked(&body, &[500], &header), &[100], 4096, None);
+    assert_eq!(verdict, Err(ResponseError::Mismatch));
+}
+
+#[test]
+fn no_usable_digest() {
+    let body = payload(100);
+    let digest = sha256(&body);
+    let short = hex(&digest)[..62].to_owned();
+    for header in [
+        "X-Other: 1".to_owned(),
+        format!("X-Content-Sha256: {short}"),
+        format!("X-Content-Sha256: {}00", hex(&digest)),
+        format!("X-Content-Sha256: {}", base64(&digest)),
+        "Digest: md5=rL0Y20zC+Fzt72VPzMSk2A==".to_owned(),
+        format!("Digest: sha-256={}", hex(&digest)),
+        format!("Digest: sha-512={}", base64(&digest)),
+    ] {
+        let (_, verdict) = get_verified(content_length(&body, &header), &[10], 1024, None);
+        assert_eq!(verdict, Err(ResponseError::NoDigest), "{header}");
+    }
+}
+
+#[test]
+fn receiving() {
+    // A read failing before anything came is the connection's error.
+    let mut link = Response::new(content_length(b"x", "X-Other: 1"), &[10]);
+    link.fail_at = Some(0);
+    let mut buf = [0; 64];
+    let mut received = 0;
+    let result = block_on(httpsbody::receive(
+        &mut link,
+        &mut buf,
+        &mut received,
+        || {},
+    ));
+    assert_eq!(result, Err(Failed));
+    // Partway through, what came is left counted.
+    let mut link = Response::new(content_length(&payload(50), "X-Other: 1"), &[10]);
+    link.fail_at = Some(30);
+    let result = block_on(httpsbody::receive(
+        &mut link,
+        &mut buf,
+        &mut received,
+        || {},
+    ));
+    assert_eq!((result, received), (Err(Failed), 30));
+
+    // The answer to a `HEAD` keeps its chunked framing undecoded.
+    let mut head = *b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n";
+    let len = head.len();
+    assert_eq!(httpsbody::decode_body(&mut head, len, len, true), Ok(len));
+    // A broken chunk is malformed.
+    let mut broken = b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\nzz\r\n".to_vec();
+    let (len, body_start) = (broken.len(), broken.len() - 4);
+    assert_eq!(
+        httpsbody::decode_body(&mut broken, body_start, len, false),
+        Err(ResponseError::Malformed)
+    );
+}
+
+#[test]
+fn streamed_bodies() {
+    let head = "HTTP/1.1 200 OK\r\nContent-Length: 10\r\n\r\n";
+    let mut body = Streamed::new(head).unwrap();
+    assert!(body.cut_short());
+    body.advance(9);
+    // A failure now leaves a byte to ask for again.
+    assert!(body.cut_short());
+    body.advance(1);
+    assert!(!body.cut_short());
+
+    // Without a length, a failure cannot be told from the end.
+    let mut body = Streamed::new("HTTP/1.1 200 OK\r\n\r\n").unwrap();
+    assert!(!body.cut_short());
+    body.advance(100);
+    assert!(!body.cut_short());
+
+    // The framing of a chunked one would go to the sink with the body.
+    for head in [
+        "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n",
+        "HTTP/1.1 200 OK\r\nContent-Length: 10\r\ntransfer-encoding: gzip, Chunked\r\n\r\n",
+    ] {
+        assert_eq!(Streamed::new(head), Err(StreamedError::Chunked), "{head:?}");
+    }
+    assert_eq!(
+        Streamed::new("HTTP/1.1 200 OK\r\nContent-Length: ten\r\n\r\n"),
+        Err(StreamedError::Malformed)
+    );
+}
@@ -0,0 +1,119 @@
+// The maintenance window's math: parsing, windows that cross midnight or
+// never open, and what no wall clock means.
+
+use esp32c3_fuzz::clock::Manual;
+use esp32c3_fuzz::maintenance::{self, NoClockPolicy, Window};
+use esp32c3_fuzz::settings::Settings;
+
+const MS_PER_MINUTE: u64 = 60 * 1000;
+const MS_PER_DAY: u64 = 24 * 60 * MS_PER_MINUTE;
+/// 2023-11-14 00:00 UTC.
+const MIDNIGHT_MS: u64 = 19_675 * MS_PER_DAY;
+
+fn window(text: &str) -> Window {
+    Window::parse(text).unwrap()
+}
+
+fn at(hours: u64, minutes: u64) -> Option<u64> {
+    Some(MIDNIGHT_MS + (hours * 60 + minutes) * MS_PER_MINUTE)
+}
+
+#[test]
+fn parses_and_prints() {
+    assert_eq!(
+        Window::parse("02:00-04:30"),
+        Some(Window {
+            start_minute: 120,
+            end_minute: 270
+        })
+    );
+    assert_eq!(window(" 23:05 - 0:15 ").to_string(), "23:05-00:15");
+    assert_eq!(maintenance::parse_time("00:00"), Some(0));
+    assert_eq!(maintenance::parse_time("23:59"), Some(1439));
+    for bad in ["24:00", "12:60", "1200", "12", ":30", "ab:cd", "-1:00"] {
+        assert_eq!(maintenance::parse_time(bad), None, "{bad}");
+    }
+    for bad in [
+        "02:00",
+        "02:00-",
+        "-04:00",
+        "02:00-04:00-05:00",
+        "02:00 04:00",
+    ] {
+        assert_eq!(Window::parse(bad), None, "{bad}");
+    }
+}
+
+#[test]
+fn start_inclusive_end_exclusive() {
+    let window = window("02:00-04:00");
+    assert!(!window.contains(119));
+    assert!(window.contains(120));
+    assert!(window.contains(239));
+    assert!(!window.contains(240));
+}
+
+#[test]
+fn crossing_midnight() {
+    let window = window("22:00-02:00");
+    for minute in [1320, 1439, 0, 119] {
+        assert!(window.contains(minute), "{minute}");
+    }
+    for minute in [1319, 120, 720] {
+        assert!(!window.contains(minute), "{minute}");
+    }
+}
+
+#[test]
+fn zero_length_never_opens() {
+    for text in ["03:00-03:00", "00:00-00:00"] {
+        let window = window(text);
+        assert!(
+            (0..24 * 60).all(|minute| !window.contains(minute)),
+            "{text}"
+        );
+    }
+}
+
+#[test]
+fn permits() {
+    let window = Some(window("02:00-04:00"));
+    let defer = NoClockPolicy::Defer;
+    assert!(maintenance::permits(None, defer, None));
+    assert!(maintenance::permits(None, defer, at(12, 0)));
+    assert!(maintenance::permits(window, defer, at(2, 0)));
+    assert!(!maintenance::permits(window, defer, at(4, 0)));
+    // Only the time of day counts, whatever the day.
+    assert!(maintenance::permits(
+        window,
+        defer,
+        Some(at(3, 0).unwrap() + 400 * MS_PER_DAY)
+    ));
+    assert!(!maintenance::permits(
+        window,
+        defer,
+        Some(at(4, 0).unwrap() - MS_PER_DAY)
+    ));
+
+    assert!(!maintenance::permits(window, NoClockPolicy::Defer, None));
+    assert!(maintenance::permits(window, NoClockPolicy::Allow, None));
+}
+
+#[test]
+fn is_open_reads_the_settings_and_the_clock() {
+    let settings = Settings {
+        maintenance_window: Some(window("23:00-01:00")),
+        no_clock_policy: NoClockPolicy::Allow,
+    };
+    let clock = Manual::new();
+    assert!(maintenance::is_open(&settings, &clock));
+    clock.set_unix_ms(at(0, 30).unwrap());
+    assert!(maintenance::is_open(&settings, &clock));
+    // Stepped past the window's end, and round to its start.
+    clock.advance(30 * MS_PER_MINUTE);
+    assert!(!maintenance::is_open(&settings, &clock));
+    clock.advance(22 * 60 * MS_PER_MINUTE - 1);
+    assert!(!maintenance::is_open(&settings, &clock));
+    clock.advance(1);
+    assert!(maintenance::is_open(&settings, &clock));
+}
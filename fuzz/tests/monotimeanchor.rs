@@ -0,0 +1,235 @@
+// The uptime that goes on across resets and sleep: when the RTC makes up a
+// gap the `Instant` did not see, the total at boot, the record kept for it,
+// and a device run through passes, sleeps, resets and a power cycle.
+
+use esp32c3_fuzz::monotimeanchor::{self, Anchor, RECORD_LEN};
+
+#[test]
+fn the_rtc_makes_up_only_what_drift_cannot_explain() {
+    // Within an eighth and 100 ms the `Instant` is right.
+    assert_eq!(monotimeanchor::elapsed_ms(0, 0), 0);
+    assert_eq!(monotimeanchor::elapsed_ms(100, 0), 0);
+    assert_eq!(monotimeanchor::elapsed_ms(101, 0), 101);
+    assert_eq!(monotimeanchor::elapsed_ms(11_350, 10_000), 10_000);
+    assert_eq!(monotimeanchor::elapsed_ms(11_351, 10_000), 11_351);
+    // An RTC running slow, or one that stood still, never takes away.
+    assert_eq!(monotimeanchor::elapsed_ms(9_000, 10_000), 10_000);
+    assert_eq!(monotimeanchor::elapsed_ms(0, 10_000), 10_000);
+}
+
+#[test]
+fn a_sleep_within_a_boot() {
+    let anchor = Anchor {
+        total_ms: 50_000,
+        rtc_ms: 60_000,
+        instant_ms: 1_000,
+    };
+    // 10 s awake: the `Instant`'s count, whatever the RC says.
+    assert_eq!(anchor.total_at(60_000 + 10_400, 11_000), 60_000);
+    assert_eq!(anchor.total_at(60_000 + 9_700, 11_000), 60_000);
+    // 10 s awake and 30 s asleep, by an RC 3 % slow: the RTC's 38.8 s.
+    assert_eq!(anchor.total_at(60_000 + 38_800, 11_000), 88_800);
+    // Readings from before the anchor count as none.
+    assert_eq!(anchor.total_at(0, 0), 50_000);
+}
+
+#[test]
+fn a_reset_carries_on_and_a_power_cycle_starts_over() {
+    // Anchored at 5 min by the RTC, then reset 2 s later.
+    assert_eq!(
+        monotimeanchor::total_at_boot(Some((300_000, 310_000)), 312_000),
+        302_000
+    );
+    // The same reading, at once.
+    assert_eq!(
+        monotimeanchor::total_at_boot(Some((300_000, 310_000)), 310_000),
+        300_000
+    );
+    // The RTC went back: it was powered off, and its count is all there is.
+    assert_eq!(
+        monotimeanchor::total_at_boot(Some((300_000, 310_000)), 1_500),
+        1_500
+    );
+    assert_eq!(monotimeanchor::total_at_boot(None, 1_500), 1_500);
+}
+
+#[test]
+fn the_record() {
+    let anchor = Anchor {
+        total_ms: 0x0123_4567_89ab_cdef,
+        rtc_ms: 7_200_000,
+        instant_ms: 42,
+    };
+    let raw = monotimeanchor::encode(&anchor);
+    // The `Instant` is not kept.
+    assert_eq!(
+        monotimeanchor::decode(&raw),
+        Some((anchor.total_ms, anchor.rtc_ms))
+    );
+    // Any byte changed, as RTC memory holds after a power cycle.
+    for at in 0..RECORD_LEN {
+        let mut changed = raw;
+        changed[at] ^= 0x01;
+        assert_eq!(monotimeanchor::decode(&changed), None, "{at}");
+    }
+    assert_eq!(monotimeanchor::decode(&[0; RECORD_LEN]), None);
+    assert_eq!(monotimeanchor::decode(&[0xff; RECORD_LEN]), None);
+}
+
+/// The device as src/monotime.rs drives it, against the true time since
+/// power-up. The RC runs `rc_permille` of real time.
+struct Device {
+    rc_permille: u64,
+    true_ms: u64,
+    /// Since the RTC was last powered.
+    rtc_true_ms: u64,
+    instant_ms: u64,
+    last: Anchor,
+    record: [u8; RECORD_LEN],
+}
+
+impl Device {
+    fn new(rc_permille: u64) -> Self {
+        let mut device = Device {
+            rc_permille,
+            true_ms: 0,
+            rtc_true_ms: 0,
+            instant_ms: 0,
+            last: Anchor::ZERO,
+            record: [0; RECORD_LEN],
+        };
+        device.boot();
+        device
+    }
+
+    fn rtc_ms(&self) -> u64 {
+        self.rtc_true_ms * self.rc_permille / 1000
+    }
+
+    /// `init`.
+    fn boot(&mut self) {
+        let rtc_ms = self.rtc_ms();
+        let total_ms = monotimeanchor::total_at_boot(monotimeanchor::decode(&self.record), rtc_ms);
+        self.last = Anchor {
+            total_ms,
+            rtc_ms,
+            instant_ms: self.instant_ms,
+        };
+        self.record = monotimeanchor::encode(&self.last);
+    }
+
+    /// `uptime_total`.
+    fn total(&self) -> u64 {
+        self.last.total_at(self.rtc_ms(), self.instant_ms)
+    }
+
+    /// A housekeeping pass: `anchor`.
+    fn anchor(&mut self) {
+        self.last = Anchor {
+            total_ms: self.total(),
+            rtc_ms: self.rtc_ms(),
+            instant_ms: self.instant_ms,
+        };
+        self.record = monotimeanchor::encode(&self.last);
+    }
+
+    fn run(&mut self, ms: u64) {
+        self.true_ms += ms;
+        self.rtc_true_ms += ms;
+        self.instant_ms += ms;
+    }
+
+    /// Light sleep: only the RTC counts.
+    fn sleep(&mut self, ms: u64) {
+        self.true_ms += ms;
+        self.rtc_true_ms += ms;
+    }
+
+    /// A reset, `down_ms` long; the RTC and its memory go on.
+    fn reset(&mut self, down_ms: u64) {
+        self.sleep(down_ms);
+        self.instant_ms = 0;
+        self.boot();
+    }
+
+    /// Power off for `off_ms`: the RTC starts over, its memory is gone.
+    fn power_cycle(&mut self, off_ms: u64) {
+        self.true_ms += off_ms;
+        self.rtc_true_ms = 0;
+        self.instant_ms = 0;
+        self.record = [0x5a; RECORD_LEN];
+        self.boot();
+    }
+}
+
+/// Housekeeping passes this far apart.
+const PASS_MS: u64 = 10_000;
+
+/// Checks the count has not gone back since `last_total`, and is within
+/// `allowed_ms` of the truth.
+fn check(device: &Device, last_total: &mut u64, allowed_ms: u64) {
+    let total = device.total();
+    assert!(total >= *last_total, "{}: went back", device.rc_permille);
+    *last_total = total;
+    let error = total.abs_diff(device.true_ms);
+    assert!(
+        error <= allowed_ms,
+        "{}: off by {error} at {}",
+        device.rc_permille,
+        device.true_ms
+    );
+}
+
+#[test]
+fn a_device_through_sleeps_and_resets() {
+    for rc_permille in [950u64, 990, 1000, 1010, 1050] {
+        let rc_off = |ms: u64| ms * rc_permille.abs_diff(1000) / 1000;
+        let mut device = Device::new(rc_permille);
+        let mut last = 0;
+
+        // Awake, by the `Instant` alone.
+        for _ in 0..30 {
+            device.run(PASS_MS);
+            device.anchor();
+            check(&device, &mut last, 0);
+        }
+        // A pass with a long sleep in it goes by the RTC, the time awake
+        // included.
+        device.run(2_000);
+        device.sleep(60_000);
+        device.run(PASS_MS - 2_000);
+        let allowed = rc_off(60_000 + PASS_MS);
+        check(&device, &mut last, allowed);
+        device.anchor();
+
+        // A reset: what the RTC says since the last anchor.
+        device.run(4_000);
+        device.reset(1_500);
+        let allowed = allowed + rc_off(5_500);
+        check(&device, &mut last, allowed);
+        for _ in 0..10 {
+            device.run(PASS_MS);
+            device.anchor();
+            check(&device, &mut last, allowed);
+        }
+        // Several resets in a row, none of them anchored in between.
+        for _ in 0..5 {
+            device.run(300);
+            device.reset(200);
+        }
+        let allowed = allowed + rc_off(2_500);
+        check(&device, &mut last, allowed);
+
+        // A power cycle starts over at the RTC's count, and goes on from
+        // there.
+        device.power_cycle(3_600_000);
+        assert_eq!(device.total(), 0);
+        last = 0;
+        for _ in 0..10 {
+            device.run(PASS_MS);
+            device.anchor();
+            check(&device, &mut last, device.true_ms);
+        }
+        assert_eq!(last, 10 * PASS_MS);
+    }
+}
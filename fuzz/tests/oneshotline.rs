@@ -0,0 +1,107 @@
+// The single-shot mode's result line: valid JSON with its keys in the
+// documented order, for a success, each kind of failure and URLs that need
+// escaping.
+
+mod common;
+
+use esp32c3_fuzz::oneshotline::{self, Completed, Outcome};
+use heapless::String;
+
+use common::{json_keys, json_object};
+
+const COMPLETED: Completed = Completed {
+    status: 200,
+    cipher: "TLS_AES_128_GCM_SHA256",
+    dns_ms: 41,
+    connect_ms: 38,
+    handshake_ms: 1520,
+    first_byte_ms: 95,
+    total_ms: 1694,
+    bytes: 1024,
+};
+
+fn line(url: &str, outcome: &Outcome<'_>, rssi: Option<i8>) -> String<512> {
+    let mut line = String::new();
+    oneshotline::write_result(&mut line, url, outcome, rssi).unwrap();
+    line
+}
+
+fn value<'a>(members: &'a [(std::string::String, std::string::String)], key: &str) -> &'a str {
+    &members.iter().find(|(k, _)| k == key).unwrap().1
+}
+
+#[test]
+fn completed() {
+    let line = line(
+        "https://www.google.com/",
+        &Outcome::Completed(&COMPLETED),
+        Some(-58),
+    );
+    assert_eq!(
+        json_keys(&line),
+        [
+            "v",
+            "ok",
+            "url",
+            "status",
+            "cipher",
+            "dns_ms",
+            "connect_ms",
+            "handshake_ms",
+            "first_byte_ms",
+            "total_ms",
+            "bytes",
+            "rssi",
+            "heap"
+        ]
+    );
+    assert_eq!(
+        line,
+        "{\"v\":1,\"ok\":true,\"url\":\"https://www.google.com/\",\"status\":200,\
+         \"cipher\":\"TLS_AES_128_GCM_SHA256\",\"dns_ms\":41,\"connect_ms\":38,\
+         \"handshake_ms\":1520,\"first_byte_ms\":95,\"total_ms\":1694,\"bytes\":1024,\
+         \"rssi\":-58,\"heap\":null}"
+    );
+}
+
+#[test]
+fn failures_report_where_and_why() {
+    let failed = Outcome::Failed {
+        phase: "handshake",
+        error: "tls_alert",
+    };
+    for (outcome, phase, error) in [
+        (&failed, "handshake", "tls_alert"),
+        (&Outcome::NoNetwork, "wifi", "not_connected"),
+    ] {
+        let line = line("https://example.com/", outcome, None);
+        let members = json_object(&line);
+        let keys: Vec<_> = members.iter().map(|(key, _)| key.as_str()).collect();
+        assert_eq!(keys, ["v", "ok", "url", "phase", "error", "rssi", "heap"]);
+        assert_eq!(value(&members, "ok"), "false");
+        assert_eq!(value(&members, "phase"), phase);
+        assert_eq!(value(&members, "error"), error);
+        assert_eq!(value(&members, "rssi"), "null");
+    }
+}
+
+#[test]
+fn url_is_escaped() {
+    let url = "https://example.com/a\"b\\c\td\u{1}é?q=\u{7f}";
+    let line = line(url, &Outcome::Completed(&COMPLETED), Some(0));
+    assert_eq!(value(&json_object(&line), "url"), url);
+}
+
+#[test]
+fn too_long_a_url_still_gives_a_line() {
+    let url = "https://example.com/".to_owned() + &"x".repeat(300);
+    let mut short: String<256> = String::new();
+    assert!(oneshotline::write_result(&mut short, &url, &Outcome::NoNetwork, None).is_err());
+
+    let mut line: String<512> = String::new();
+    oneshotline::write_url_too_long(&mut line).unwrap();
+    let members = json_object(&line);
+    assert_eq!(value(&members, "url"), "null");
+    assert_eq!(value(&members, "phase"), "url");
+    assert_eq!(value(&members, "error"), "url_too_long");
+}
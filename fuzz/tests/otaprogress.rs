@@ -0,0 +1,287 @@
+// The download's progress and its resuming, against a mock clock and a mock
+// transport that sends what a test scripts and stalls where it says.
+
+mod common;
+
+use std::cell::{Cell, RefCell};
+
+use embassy_time::{Duration, Instant};
+use esp32c3_fuzz::otaprogress::{
+    self, OtaPhase, OtaProgress, OtaState, ProgressReporter, Transfer, STALL_TIMEOUT,
+};
+
+use common::block_on;
+
+thread_local! {
+    static PUBLISHED: RefCell<Vec<OtaProgress>> = const { RefCell::new(Vec::new()) };
+}
+
+fn record(progress: OtaProgress) {
+    PUBLISHED.with(|published| published.borrow_mut().push(progress));
+}
+
+/// What has been published since the last call.
+fn published() -> Vec<OtaProgress> {
+    PUBLISHED.with(|published| published.take())
+}
+
+fn at(ms: u64) -> Instant {
+    Instant::from_millis(ms)
+}
+
+fn progress(bytes_done: u32, total: Option<u32>) -> OtaProgress {
+    OtaProgress {
+        state: OtaState::Running,
+        phase: OtaPhase::Downloading,
+        bytes_done,
+        total,
+    }
+}
+
+#[test]
+fn percent() {
+    assert_eq!(progress(0, Some(0)).percent(), Some(100));
+    assert_eq!(progress(0, Some(1000)).percent(), Some(0));
+    assert_eq!(progress(999, Some(1000)).percent(), Some(99));
+    assert_eq!(progress(1000, Some(1000)).percent(), Some(100));
+    // More than was announced is still done, not more than done.
+    assert_eq!(progress(1500, Some(1000)).percent(), Some(100));
+    assert_eq!(progress(u32::MAX - 1, Some(u32::MAX)).percent(), Some(99));
+    assert_eq!(progress(1000, None).percent(), None);
+}
+
+#[test]
+fn known_size_publishes_every_two_percent() {
+    let mut reporter = ProgressReporter::start(Some(10_000), at(0), record);
+    assert_eq!(published(), [progress(0, Some(10_000))]);
+    // 1% every 100 ms: held back for 500 ms, then every 2% is due.
+    for i in 1..=100 {
+        reporter.advance(100, at(i * 100));
+    }
+    let percents: Vec<_> = published()
+        .iter()
+        .filter_map(OtaProgress::percent)
+        .collect();
+    assert_eq!(percents.first(), Some(&5));
+    assert_eq!(percents.last(), Some(&100));
+    assert!(percents.windows(2).all(|w| w[1] >= w[0] + 2 || w[1] == 100));
+
+    // Many pieces at the same moment are one publish at most, at 2%.
+    let mut reporter = ProgressReporter::start(Some(10_000), at(0), record);
+    published();
+    for _ in 0..100 {
+        reporter.advance(100, at(600));
+    }
+    assert_eq!(published(), [progress(200, Some(10_000))]);
+}
+
+#[test]
+fn unknown_size_publishes_every_two_seconds() {
+    let mut reporter = ProgressReporter::start(None, at(0), record);
+    published();
+    for i in 1..=100 {
+        reporter.advance(100, at(i * 100));
+    }
+    let published = published();
+    assert_eq!(published.len(), 5);
+    assert!(published.iter().all(|p| p.percent().is_none()));
+    assert_eq!(published.last().unwrap().bytes_done, 10_000);
+}
+
+#[test]
+fn phases_keep_the_byte_count() {
+    let mut reporter = ProgressReporter::start(Some(1000), at(0), record);
+    reporter.advance(1000, at(1000));
+    reporter.set_phase(OtaPhase::Verifying, at(2000));
+    let progress = reporter.progress();
+    assert_eq!(progress.phase, OtaPhase::Verifying);
+    assert_eq!((progress.bytes_done, progress.percent()), (1000, Some(100)));
+    reporter.finish(true, at(3000));
+    let last = *published().last().unwrap();
+    assert_eq!((last.state, last.bytes_done), (OtaState::Done, 1000));
+}
+
+#[test]
+fn stall_is_reported_once() {
+    let stall = STALL_TIMEOUT.as_millis();
+    let mut reporter = ProgressReporter::start(None, at(0), record);
+    reporter.advance(100, at(1000));
+    published();
+    assert!(!reporter.check_stall(at(1000 + stall - 1)));
+    assert!(reporter.check_stall(at(1000 + stall)));
+    assert_eq!(reporter.progress().state, OtaState::Stalled);
+    assert_eq!(published().last().unwrap().state, OtaState::Stalled);
+    assert!(!reporter.check_stall(at(1000 + 2 * stall)));
+
+    // Data again ends it, and the clock for the next one starts over.
+    reporter.advance(100, at(100_000));
+    assert_eq!(reporter.progress().state, OtaState::Running);
+    assert_eq!(published().last().unwrap().state, OtaState::Running);
+    assert!(!reporter.check_stall(at(100_000 + stall - 1)));
+    assert!(reporter.check_stall(at(100_000 + stall)));
+}
+
+/// One response, as the mock transport sends it.
+struct Attempt {
+    /// Sent 100 ms apart.
+    pieces: &'static [usize],
+    /// Goes quiet after them instead of ending.
+    stalls: bool,
+}
+
+struct MockTransfer<'c> {
+    clock: &'c Cell<u64>,
+    /// What the first response says.
+    total: Option<u32>,
+    attempts: Vec<Attempt>,
+    /// Where each attempt was asked to start.
+    from: Vec<u32>,
+    reporter: Option<ProgressReporter>,
+}
+
+impl<'c> MockTransfer<'c> {
+    fn new(clock: &'c Cell<u64>, total: Option<u32>, attempts: Vec<Attempt>) -> Self {
+        Self {
+            clock,
+            total,
+            attempts,
+            from: Vec::new(),
+            reporter: None,
+        }
+    }
+}
+
+impl Transfer for MockTransfer<'_> {
+    type Output = u32;
+    type Error = ();
+
+    async fn fetch(&mut self, from: u32) -> Result<Option<u32>, ()> {
+        self.from.push(from);
+        let attempt = self.attempts.remove(0);
+        let clock = self.clock;
+        let total = self.total;
+        let reporter = self
+            .reporter
+            .get_or_insert_with(|| ProgressReporter::start(total, at(clock.get()), record));
+        for &piece in attempt.pieces {
+            clock.set(clock.get() + 100);
+            reporter.advance(piece, at(clock.get()));
+        }
+        if attempt.stalls {
+            clock.set(clock.get() + STALL_TIMEOUT.as_millis());
+            return Ok(None);
+        }
+        Ok(Some(reporter.progress().bytes_done))
+    }
+
+    fn reporter(&mut self) -> Option<&mut ProgressReporter> {
+        self.reporter.as_mut()
+    }
+}
+
+fn run(transfer: &mut MockTransfer<'_>) -> Result<Option<u32>, ()> {
+    let clock = transfer.clock;
+    block_on(otaprogress::resume(transfer, || at(clock.get())))
+}
+
+#[test]
+fn resumes_from_where_it_stalled() {
+    let clock = Cell::new(0);
+    let mut transfer = MockTransfer::new(
+        &clock,
+        Some(1000),
+        vec![
+            Attempt {
+                pieces: &[100, 200],
+                stalls: true,
+            },
+            Attempt {
+                pieces: &[300],
+                stalls: true,
+            },
+            Attempt {
+                pieces: &[400],
+                stalls: false,
+            },
+        ],
+    );
+    assert_eq!(run(&mut transfer), Ok(Some(1000)));
+    assert_eq!(transfer.from, [0, 300, 600]);
+    let states: Vec<_> = published().iter().map(|p| p.state).collect();
+    assert_eq!(
+        states.iter().filter(|&&s| s == OtaState::Stalled).count(),
+        2
+    );
+    assert_eq!(transfer.reporter.unwrap().progress().percent(), Some(100));
+}
+
+#[test]
+fn resumes_without_a_size() {
+    let clock = Cell::new(0);
+    let mut transfer = MockTransfer::new(
+        &clock,
+        None,
+        vec![
+            Attempt {
+                pieces: &[512; 4],
+                stalls: true,
+            },
+            Attempt {
+                pieces: &[512; 4],
+                stalls: false,
+            },
+        ],
+    );
+    assert_eq!(run(&mut transfer), Ok(Some(4096)));
+    assert_eq!(transfer.from, [0, 2048]);
+    let progress = transfer.reporter.unwrap().progress();
+    assert_eq!(
+        (progress.state, progress.percent()),
+        (OtaState::Running, None)
+    );
+}
+
+#[test]
+fn gives_up_on_a_resume_that_brings_nothing() {
+    let clock = Cell::new(0);
+    let mut transfer = MockTransfer::new(
+        &clock,
+        Some(1000),
+        vec![
+            Attempt {
+                pieces: &[250],
+                stalls: true,
+            },
+            Attempt {
+                pieces: &[],
+                stalls: true,
+            },
+        ],
+    );
+    assert_eq!(run(&mut transfer), Ok(None));
+    assert_eq!(transfer.from, [0, 250]);
+    assert_eq!(
+        transfer.reporter.unwrap().progress().state,
+        OtaState::Stalled
+    );
+}
+
+#[test]
+fn quiet_for_less_than_the_timeout_is_not_a_stall() {
+    let clock = Cell::new(0);
+    let mut transfer = MockTransfer::new(
+        &clock,
+        None,
+        vec![Attempt {
+            pieces: &[10],
+            stalls: true,
+        }],
+    );
+    // A transport that gives up before the reporter would: 1 ms after the
+    // last byte is no stall, and nothing is asked for again.
+    let resumed = block_on(otaprogress::resume(&mut transfer, || {
+        at(100) + Duration::from_millis(1)
+    }));
+    assert_eq!(resumed, Ok(None));
+    assert_eq!(transfer.from, [0]);
+}
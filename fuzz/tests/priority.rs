@@ -0,0 +1,210 @@
+// Critical records ahead of bulk telemetry: who gets each request, the
+// retry budget, a record raised twice, and an uploader working through a
+// long backlog when an alert is raised partway through a batch.
+
+mod common;
+
+use common::block_on;
+use esp32c3_fuzz::clock::Manual;
+use esp32c3_fuzz::priority::{
+    self, Alert, CriticalQueue, Outcome, Settled, Slot, CRITICAL_ATTEMPTS,
+};
+
+const LOW: Alert = Alert::BatteryCritical { battery_mv: 3050 };
+
+fn critical(queue: &CriticalQueue, now_ms: u64) -> priority::Critical {
+    match queue.next_slot(now_ms, true) {
+        Some(Slot::Critical(record)) => record,
+        other => panic!("no critical record due at {now_ms}: {other:?}"),
+    }
+}
+
+#[test]
+fn a_due_record_takes_the_slot() {
+    let mut queue = CriticalQueue::new();
+    assert_eq!(queue.next_slot(0, false), None);
+    assert_eq!(queue.next_slot(0, true), Some(Slot::Bulk));
+    assert_eq!(queue.due_ms(), None);
+
+    assert!(queue.push(LOW, 1_000));
+    assert_eq!(queue.due_ms(), Some(1_000));
+    // Due at once, and whether or not bulk has anything.
+    let record = critical(&queue, 1_000);
+    assert_eq!((record.alert, record.raised_ms), (LOW, 1_000));
+    assert_eq!(queue.next_slot(1_000, false), Some(Slot::Critical(record)));
+    // Handing out the slot does not take the record off the queue.
+    assert_eq!(queue.next_slot(5_000, true), Some(Slot::Critical(record)));
+}
+
+#[test]
+fn delivered_refused_and_dropped() {
+    let mut queue = CriticalQueue::new();
+    queue.push(LOW, 1_000);
+    let record = critical(&queue, 1_200);
+    assert_eq!(
+        queue.settle(&record, Outcome::Accepted, 1_750),
+        Settled::Delivered { latency_ms: 750 }
+    );
+    assert_eq!(queue.next_slot(1_750, true), Some(Slot::Bulk));
+    // Settled again, it is gone already.
+    assert_eq!(
+        queue.settle(&record, Outcome::Accepted, 1_800),
+        Settled::Dropped
+    );
+
+    // A non-2xx answer is not tried again.
+    queue.push(LOW, 2_000);
+    let record = critical(&queue, 2_000);
+    assert_eq!(
+        queue.settle(&record, Outcome::Refused, 2_100),
+        Settled::Dropped
+    );
+    assert_eq!(queue.due_ms(), None);
+}
+
+#[test]
+fn failed_tries_back_off_then_give_up() {
+    let mut queue = CriticalQueue::new();
+    queue.push(LOW, 0);
+    let record = critical(&queue, 0);
+    assert_eq!(
+        queue.settle(&record, Outcome::Failed, 300),
+        Settled::Retry { due_ms: 5_300 }
+    );
+    // Bulk goes meanwhile, and the record does not before it is due.
+    assert_eq!(queue.due_ms(), Some(5_300));
+    assert_eq!(queue.next_slot(5_299, true), Some(Slot::Bulk));
+    let record = critical(&queue, 5_300);
+    assert_eq!(record.raised_ms, 0);
+    assert_eq!(
+        queue.settle(&record, Outcome::Failed, 5_500),
+        Settled::Retry { due_ms: 25_500 }
+    );
+    assert_eq!(queue.next_slot(25_499, false), None);
+    let record = critical(&queue, 25_500);
+    assert_eq!(CRITICAL_ATTEMPTS, 3);
+    assert_eq!(
+        queue.settle(&record, Outcome::Failed, 25_600),
+        Settled::GaveUp
+    );
+    assert_eq!(queue.due_ms(), None);
+
+    // One that gets through on its last try counts from when it was raised.
+    queue.push(LOW, 100);
+    for _ in 1..CRITICAL_ATTEMPTS {
+        let record = critical(&queue, queue.due_ms().unwrap());
+        queue.settle(&record, Outcome::Failed, record.raised_ms + 1_000);
+    }
+    let record = critical(&queue, 30_000);
+    assert_eq!(
+        queue.settle(&record, Outcome::Accepted, 30_000),
+        Settled::Delivered { latency_ms: 29_900 }
+    );
+}
+
+#[test]
+fn raised_again_it_stays_as_it_was() {
+    let mut queue = CriticalQueue::new();
+    assert!(queue.push(LOW, 1_000));
+    let record = critical(&queue, 1_000);
+    queue.settle(&record, Outcome::Failed, 1_100);
+
+    // A newer reading of the same alert: the queued one keeps its value,
+    // its time and its backoff.
+    assert!(!queue.push(Alert::BatteryCritical { battery_mv: 2900 }, 2_000));
+    assert_eq!(queue.next_slot(2_000, true), Some(Slot::Bulk));
+    let record = critical(&queue, 6_100);
+    assert_eq!((record.alert, record.raised_ms), (LOW, 1_000));
+
+    // Once it is settled, the alert queues afresh.
+    queue.settle(&record, Outcome::Accepted, 6_200);
+    assert!(queue.push(LOW, 7_000));
+    assert_eq!(critical(&queue, 7_000).raised_ms, 7_000);
+}
+
+/// Requests that take this long, one at a time, as the uploader sends them.
+const BULK_MS: u64 = 900;
+const CRITICAL_MS: u64 = 150;
+
+/// The uploader working through `backlog` bulk requests, with `LOW` raised
+/// at `raise_ms` and its first `failures` tries failing. Returns when it
+/// was delivered, and how many bulk requests went before then.
+fn work_through(backlog: usize, raise_ms: u64, failures: u8) -> (u64, usize) {
+    let mut queue = CriticalQueue::new();
+    let mut now_ms = 0;
+    let mut bulk_sent = 0;
+    let mut raised = false;
+    let mut failed = 0;
+    loop {
+        if !raised && now_ms >= raise_ms {
+            queue.push(LOW, raise_ms);
+            raised = true;
+        }
+        match queue.next_slot(now_ms, bulk_sent < backlog) {
+            Some(Slot::Critical(record)) => {
+                // No bulk request is started while one is due.
+                now_ms += CRITICAL_MS;
+                let outcome = if failed < failures {
+                    failed += 1;
+                    Outcome::Failed
+                } else {
+                    Outcome::Accepted
+                };
+                if let Settled::Delivered { latency_ms } = queue.settle(&record, outcome, now_ms) {
+                    assert_eq!(latency_ms, now_ms - raise_ms);
+                    return (latency_ms, bulk_sent);
+                }
+            }
+            Some(Slot::Bulk) => {
+                // The request in flight when the alert is raised is let
+                // finish; only then does the alert get the slot.
+                now_ms += BULK_MS;
+                bulk_sent += 1;
+                if !raised && now_ms > raise_ms {
+                    queue.push(LOW, raise_ms);
+                    raised = true;
+                }
+            }
+            None => now_ms = queue.due_ms().unwrap_or(raise_ms).max(now_ms + 1),
+        }
+    }
+}
+
+#[test]
+fn an_alert_waits_for_one_bulk_request_at_most() {
+    for raise_ms in [0, 1, 450, 899, 900, 901, 10_000, 44_999] {
+        let (latency_ms, bulk_sent) = work_through(100, raise_ms, 0);
+        assert!(
+            latency_ms <= BULK_MS + CRITICAL_MS,
+            "{raise_ms}: {latency_ms}"
+        );
+        // Only the requests started before it was raised went first.
+        assert_eq!(bulk_sent as u64, raise_ms.div_ceil(BULK_MS), "{raise_ms}");
+    }
+    // With the backlog done, it goes as soon as it is raised.
+    assert_eq!(work_through(3, 10_000, 0), (CRITICAL_MS, 3));
+}
+
+#[test]
+fn bulk_goes_on_between_retries() {
+    // Raised during the first bulk request, tried after it at 900 ms, then
+    // 5 s and 20 s after each failure, each time behind the bulk request
+    // running when it came due: at 6450 ms, and at 27 300 ms when it goes.
+    assert_eq!(work_through(100, 450, 2), (27_000, 30));
+}
+
+#[test]
+fn raise_queues_it_and_wakes_the_uploader() {
+    let clock = Manual::new();
+    clock.advance(4_000);
+    priority::raise(LOW, &clock);
+    block_on(priority::raised());
+    let record = priority::with(|queue| critical(queue, 4_000));
+    assert_eq!(record.raised_ms, 4_000);
+    // Raised again while queued: queued once, and no wake.
+    clock.advance(1_000);
+    priority::raise(LOW, &clock);
+    assert_eq!(priority::with(|queue| critical(queue, 5_000)), record);
+    priority::with(|queue| queue.settle(&record, Outcome::Accepted, 5_000));
+    assert_eq!(priority::with(|queue| queue.due_ms()), None);
+}
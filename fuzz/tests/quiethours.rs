@@ -0,0 +1,59 @@
+// Quiet hours: parsing them, a range within the day and one wrapping past
+// midnight, and what holds readings back by the wall clock.
+
+use esp32c3_fuzz::quiethours::{self, QuietHours};
+
+const HOUR_MS: u64 = 60 * 60 * 1000;
+/// 2024-06-01 00:00 UTC.
+const MIDNIGHT_MS: u64 = 1_717_200_000_000;
+
+fn hours(start_hour: u8, end_hour: u8) -> QuietHours {
+    QuietHours {
+        start_hour,
+        end_hour,
+    }
+}
+
+#[test]
+fn parses() {
+    assert_eq!(QuietHours::parse("22-6"), Some(hours(22, 6)));
+    assert_eq!(QuietHours::parse(" 0 - 23 "), Some(hours(0, 23)));
+    for text in [
+        "", "22", "22-", "-6", "24-6", "22-24", "a-b", "22-6-8", "-1-6",
+    ] {
+        assert_eq!(QuietHours::parse(text), None, "{text:?}");
+    }
+}
+
+#[test]
+fn a_range_within_the_day() {
+    let quiet = hours(1, 5);
+    let held: Vec<u8> = (0..24).filter(|&h| quiet.contains(h)).collect();
+    assert_eq!(held, [1, 2, 3, 4]);
+}
+
+#[test]
+fn a_range_past_midnight() {
+    let quiet = hours(22, 6);
+    let held: Vec<u8> = (0..24).filter(|&h| quiet.contains(h)).collect();
+    assert_eq!(held, [0, 1, 2, 3, 4, 5, 22, 23]);
+    assert!((0..24).all(|h| !hours(7, 7).contains(h)));
+}
+
+#[test]
+fn held_back_by_the_wall_clock() {
+    let quiet = Some(hours(22, 6));
+    let at = |hour: u64, minute: u64| Some(MIDNIGHT_MS + hour * HOUR_MS + minute * 60_000);
+    assert!(quiethours::holds(quiet, at(23, 59)));
+    assert!(quiethours::holds(quiet, at(0, 0)));
+    assert!(quiethours::holds(quiet, at(5, 59)));
+    assert!(!quiethours::holds(quiet, at(6, 0)));
+    assert!(!quiethours::holds(quiet, at(21, 59)));
+    assert!(quiethours::holds(quiet, at(22, 0)));
+    // A day later, the same.
+    assert!(quiethours::holds(quiet, at(24 + 3, 0)));
+
+    // No clock, or no quiet hours: nothing is held back.
+    assert!(!quiethours::holds(quiet, None));
+    assert!(!quiethours::holds(None, at(3, 0)));
+}
@@ -0,0 +1,44 @@
+// The inputs fuzzing and review found getting past a decoder, from
+// corpus/*/regression-*, each fed to the decoder it got past.
+
+use esp32c3_fuzz::http::{self, HttpError};
+use esp32c3_fuzz::ntp::{self, NtpError};
+
+#[test]
+fn signed_chunk_size() {
+    let mut body = include_bytes!("../corpus/dechunk/regression-signed-size").to_vec();
+    assert_eq!(http::dechunk(&mut body), Err(HttpError::Malformed));
+}
+
+#[test]
+fn signed_content_length() {
+    let head = include_str!("../corpus/http_request/regression-signed-content-length");
+    assert!(matches!(
+        http::parse_request(head),
+        Err(HttpError::Malformed)
+    ));
+}
+
+#[test]
+fn duplicate_content_length() {
+    let head = include_str!("../corpus/http_request/regression-duplicate-content-length");
+    assert!(matches!(
+        http::parse_request(head),
+        Err(HttpError::Malformed)
+    ));
+}
+
+#[test]
+fn status_not_three_digits() {
+    let data = include_bytes!("../corpus/http_response/regression-status-not-three-digits");
+    assert_eq!(http::parse_head(data), Err(HttpError::Malformed));
+}
+
+#[test]
+fn zero_transmit_timestamp() {
+    let packet = include_bytes!("../corpus/ntp_response/regression-zero-transmit");
+    assert_eq!(
+        ntp::parse_response(packet).err(),
+        Some(NtpError::BadTimestamp)
+    );
+}
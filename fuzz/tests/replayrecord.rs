@@ -0,0 +1,195 @@
+// The record of a failed request: its layout, and the secrets masked in
+// it, whether they stand in the head, come back in the answer, are
+// percent-encoded in the query or inside a `Basic` header, or are cut in
+// two where the head or the answer is cut to its limit.
+
+use esp32c3_fuzz::codec;
+use esp32c3_fuzz::headers::Headers;
+use esp32c3_fuzz::replayrecord::{self, MAX_HEAD_LEN, MAX_RESPONSE_LEN};
+
+const OUTCOME: &str = "lastfail v1 phase=response cause=- status=401";
+const KEY: &str = "k3y-0123456789abcdef";
+
+fn headers(lines: &[&str]) -> Headers {
+    let mut headers = Headers::new();
+    for line in lines {
+        headers.add(line).unwrap();
+    }
+    headers
+}
+
+fn head(path: &str, lines: &[&str]) -> String {
+    let mut head = format!("POST {path} HTTP/1.1\r\nHost: example.com\r\n");
+    for line in lines {
+        head.push_str(line);
+        head.push_str("\r\n");
+    }
+    head.push_str("\r\n");
+    head
+}
+
+/// The record's head and answer, as text.
+fn build(path: &str, head: &str, response: &[u8], headers: &Headers) -> (String, String) {
+    let record = replayrecord::build(OUTCOME, path, head.as_bytes(), response, headers);
+    let text = String::from_utf8(record.to_vec()).unwrap();
+    let rest = text
+        .strip_prefix(OUTCOME)
+        .unwrap()
+        .strip_prefix('\n')
+        .unwrap();
+    let (line, rest) = rest.split_once('\n').unwrap();
+    let head_len: usize = line.strip_prefix("request ").unwrap().parse().unwrap();
+    let (quoted_head, rest) = rest.split_at(head_len);
+    let rest = rest.strip_prefix("\nresponse ").unwrap();
+    let (len, rest) = rest.split_once('\n').unwrap();
+    let response_len: usize = len.parse().unwrap();
+    assert_eq!(rest.len(), response_len + 1);
+    assert!(rest.ends_with('\n'));
+    (quoted_head.to_owned(), rest[..response_len].to_owned())
+}
+
+fn stars(len: usize) -> String {
+    "*".repeat(len)
+}
+
+#[test]
+fn layout() {
+    let headers = Headers::new();
+    let head = head("/up", &[]);
+    let (quoted, answer) = build("/up", &head, b"HTTP/1.1 401 Unauthorized\r\n\r\n", &headers);
+    assert_eq!(quoted, head);
+    assert_eq!(answer, "HTTP/1.1 401 Unauthorized\r\n\r\n");
+    // Nothing came back.
+    let (_, answer) = build("/up", &head, b"", &headers);
+    assert!(answer.is_empty());
+    // Each cut to its limit.
+    let long = format!("{}{}", head, "x".repeat(400));
+    let (quoted, answer) = build("/up", &long, &[b'y'; 300], &headers);
+    assert_eq!(
+        (quoted.len(), answer.len()),
+        (MAX_HEAD_LEN, MAX_RESPONSE_LEN)
+    );
+}
+
+#[test]
+fn a_secret_in_the_head_and_echoed_in_the_body() {
+    let headers = headers(&[&format!("X-Api-Key: {KEY}"), "X-Id: ab"]);
+    let head = head("/up", &[&format!("X-Api-Key: {KEY}"), "X-Id: ab"]);
+    let response = format!("HTTP/1.1 401 Unauthorized\r\n\r\n{{\"bad_key\":\"{KEY}\"}}");
+    let (quoted, answer) = build("/up", &head, response.as_bytes(), &headers);
+    // Masked byte for byte, so the lengths are those of the wire.
+    assert_eq!(quoted.len(), head.len());
+    assert_eq!(answer.len(), response.len());
+    assert!(quoted.contains(&format!("X-Api-Key: {}\r\n", stars(KEY.len()))));
+    // In the head even a short one is masked where it stands; elsewhere it
+    // would blank out every "ab".
+    assert!(quoted.contains("X-Id: **\r\n"));
+    assert!(answer.ends_with(&format!("{{\"bad_key\":\"{}\"}}", stars(KEY.len()))));
+    assert!(!quoted.contains(KEY) && !answer.contains(KEY));
+    assert!(answer.starts_with("HTTP/1.1 401 Unauthorized"));
+
+    // Each word of a value on its own too: a server that echoes only the
+    // token of a `Bearer` header.
+    let headers = self::headers(&[&format!("Authorization: Bearer {KEY}")]);
+    let response = format!("HTTP/1.1 401 Unauthorized\r\n\r\ntoken {KEY} expired");
+    let (_, answer) = build("/up", &head, response.as_bytes(), &headers);
+    assert!(answer.ends_with(&format!("token {} expired", stars(KEY.len()))));
+    // The scheme is four bytes too; it goes with them.
+    assert!(!answer.contains("Bearer"));
+}
+
+#[test]
+fn a_secret_cut_at_the_end_of_the_answer() {
+    let headers = headers(&[&format!("X-Api-Key: {KEY}")]);
+    let head = head("/up", &[&format!("X-Api-Key: {KEY}")]);
+    // The answer echoes the key so that the cut at `MAX_RESPONSE_LEN`
+    // leaves `kept` bytes of it, however few.
+    for kept in 1..KEY.len() {
+        let prefix = format!("HTTP/1.1 403 Forbidden\r\n\r\n{}", ".".repeat(200));
+        let prefix = &prefix[..MAX_RESPONSE_LEN - kept];
+        let response = format!("{prefix}{KEY} is revoked");
+        let (_, answer) = build("/up", &head, response.as_bytes(), &headers);
+        assert_eq!(answer, format!("{prefix}{}", stars(kept)), "{kept}");
+    }
+    // Not cut: what merely looks like the key's start stays.
+    let response = "HTTP/1.1 403 Forbidden\r\n\r\nk3y";
+    let (_, answer) = build("/up", &head, response.as_bytes(), &headers);
+    assert_eq!(answer, response);
+}
+
+#[test]
+fn a_secret_cut_at_the_end_of_the_head() {
+    // The key of a header the settings have, in a line the request made
+    // itself, for instance a cookie, standing across the cut.
+    let headers = headers(&[&format!("X-Api-Key: {KEY}")]);
+    for kept in [1, 4, KEY.len() - 1] {
+        let before = head("/up", &[&format!("X-Api-Key: {KEY}")]);
+        let before = before.strip_suffix("\r\n").unwrap();
+        let pad = MAX_HEAD_LEN - before.len() - "Cookie: s=".len() - kept;
+        let head = format!(
+            "{before}X-Pad: {}\r\nCookie: s={KEY}\r\n\r\n",
+            "p".repeat(pad - "X-Pad: \r\n".len())
+        );
+        let (quoted, _) = build("/up", &head, b"", &headers);
+        assert_eq!(quoted.len(), MAX_HEAD_LEN);
+        assert!(
+            quoted.ends_with(&format!("Cookie: s={}", stars(kept))),
+            "{kept}"
+        );
+    }
+}
+
+#[test]
+fn percent_encoded_query_values() {
+    const RAW: &str = "s3cr%2Bt%2Fvalue";
+    const DECODED: &str = "s3cr+t/value";
+    let headers = Headers::new();
+    let path = format!("/up?device=dev-7&token={RAW}&n=1");
+    let head = head(&path, &[]);
+    // Echoed as sent, and decoded.
+    let response =
+        format!("HTTP/1.1 400 Bad Request\r\n\r\nno token {DECODED}; raw {RAW}, device dev-7");
+    let (quoted, answer) = build(&path, &head, response.as_bytes(), &headers);
+    // In the request line every value, whatever its length.
+    let line = format!(
+        "POST /up?device=*****&token={}&n=* HTTP/1.1\r\n",
+        stars(RAW.len())
+    );
+    assert!(quoted.starts_with(&line), "{quoted}");
+    assert_eq!(
+        answer,
+        format!(
+            "HTTP/1.1 400 Bad Request\r\n\r\nno token {}; raw {}, device {}",
+            stars(DECODED.len()),
+            stars(RAW.len()),
+            stars(5)
+        )
+    );
+}
+
+#[test]
+fn basic_credentials() {
+    let mut encoded = [0; 64];
+    let credentials = codec::STANDARD
+        .encode(b"sensor-12:hunter22", &mut encoded)
+        .unwrap()
+        .to_owned();
+    let line = format!("Authorization: Basic {credentials}");
+    let headers = headers(&[&line]);
+    let head = head("/up", &[&line]);
+    let response = "HTTP/1.1 401 Unauthorized\r\n\r\nuser sensor-12 with password hunter22 refused";
+    let (quoted, answer) = build("/up", &head, response.as_bytes(), &headers);
+    assert!(quoted.contains(&format!(
+        "Authorization: {}\r\n",
+        stars(6 + credentials.len())
+    )));
+    assert!(!quoted.contains(&credentials));
+    assert_eq!(
+        answer,
+        format!(
+            "HTTP/1.1 401 Unauthorized\r\n\r\nuser {} with password {} refused",
+            stars(9),
+            stars(8)
+        )
+    );
+}
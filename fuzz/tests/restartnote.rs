@@ -0,0 +1,36 @@
+// The note a deliberate restart leaves: every reason comes back from it,
+// and memory that holds anything else, as after power-up, gives none.
+
+use esp32c3_fuzz::restartnote::{Reason, NOTE_LEN};
+
+const REASONS: [Reason; 7] = [
+    Reason::Scheduled(0),
+    Reason::Scheduled(20_000),
+    Reason::Requested,
+    Reason::Update,
+    Reason::SafeModeExit,
+    Reason::WifiStuck(1),
+    Reason::WifiStuck(40),
+];
+
+#[test]
+fn every_reason_comes_back() {
+    for reason in REASONS {
+        assert_eq!(Reason::decode(&reason.encode()), Some(reason));
+    }
+}
+
+#[test]
+fn anything_else_is_no_note() {
+    assert_eq!(Reason::decode(&[0; NOTE_LEN]), None);
+    assert_eq!(Reason::decode(&[0xff; NOTE_LEN]), None);
+    // Any one bit flipped: the magic, the reason, the day or the CRC.
+    for reason in REASONS {
+        let note = reason.encode();
+        for bit in 0..NOTE_LEN * 8 {
+            let mut flipped = note;
+            flipped[bit / 8] ^= 1 << (bit % 8);
+            assert_eq!(Reason::decode(&flipped), None, "{reason:?}, bit {bit}");
+        }
+    }
+}
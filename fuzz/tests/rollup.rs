@@ -0,0 +1,157 @@
+// The metrics rollup: which bucket a latency counts towards, percentiles
+// estimated from the buckets against the samples they came from, and the
+// day's totals rolling over.
+
+use esp32c3_fuzz::dns::Reject;
+use esp32c3_fuzz::rollup::{self, DayTotals, Histogram, Rollup, BUCKETS, BUCKET_BOUNDS_MS, PHASES};
+
+fn histogram(samples: &[u64]) -> Histogram {
+    let mut histogram = Histogram::EMPTY;
+    for &ms in samples {
+        histogram.counts[rollup::bucket_index(ms)] += 1;
+        histogram.sum_ms += ms;
+    }
+    histogram
+}
+
+/// Where bucket `i` starts and ends, the end included.
+fn bounds(i: usize) -> (u32, u32) {
+    let low = if i == 0 { 0 } else { BUCKET_BOUNDS_MS[i - 1] };
+    (low, BUCKET_BOUNDS_MS.get(i).copied().unwrap_or(u32::MAX))
+}
+
+#[test]
+fn bucket_bounds_are_inclusive() {
+    assert_eq!(rollup::bucket_index(0), 0);
+    assert_eq!(rollup::bucket_index(1), 0);
+    assert_eq!(rollup::bucket_index(2), 1);
+    assert_eq!(rollup::bucket_index(24), 9);
+    assert_eq!(rollup::bucket_index(32), 9);
+    assert_eq!(rollup::bucket_index(60_000), BUCKETS - 2);
+    assert_eq!(rollup::bucket_index(60_001), BUCKETS - 1);
+    assert_eq!(rollup::bucket_index(u64::MAX), BUCKETS - 1);
+    for (i, &bound) in BUCKET_BOUNDS_MS.iter().enumerate() {
+        assert_eq!(rollup::bucket_index(bound as u64), i);
+        assert_eq!(rollup::bucket_index(bound as u64 + 1), i + 1);
+    }
+}
+
+#[test]
+fn percentile_interpolates_within_a_bucket() {
+    assert_eq!(Histogram::EMPTY.percentile(50), None);
+
+    // Ten samples in (23, 32].
+    let ten = histogram(&[30; 10]);
+    assert_eq!(ten.percentile(0), Some(23));
+    assert_eq!(ten.percentile(10), Some(23));
+    assert_eq!(ten.percentile(50), Some(27));
+    assert_eq!(ten.percentile(90), Some(31));
+    assert_eq!(ten.percentile(100), Some(32));
+    assert_eq!(ten.percentile(255), Some(32));
+
+    // Anything slower than the last bound is only known to be that slow.
+    assert_eq!(histogram(&[90_000, 200_000]).percentile(99), Some(60_000));
+
+    // Half fast, half slow: the median is the fast half's top.
+    let split = histogram(&[1, 1, 1, 1, 5000, 5000, 5000, 5000]);
+    assert_eq!(split.percentile(50), Some(1));
+    assert_eq!(split.percentile(51), Some(4096 + (5793 - 4096) / 4));
+}
+
+#[test]
+fn estimates_fall_in_the_true_value_s_bucket() {
+    let mut state = 0x2545_f491_u64;
+    let mut next = move || {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        state
+    };
+    for len in [1, 2, 7, 100, 1000] {
+        // Log-uniform from 1 ms to about 100 s, like real latencies.
+        let mut samples: Vec<u64> = (0..len)
+            .map(|_| {
+                let magnitude = 1 << (next() % 17);
+                magnitude + next() % magnitude
+            })
+            .collect();
+        let histogram = histogram(&samples);
+        samples.sort();
+        let mut last = 0;
+        for percent in 0..=100u8 {
+            let estimate = histogram.percentile(percent).unwrap();
+            let rank = (len * percent as usize).div_ceil(100).max(1);
+            let (low, high) = bounds(rollup::bucket_index(samples[rank - 1]));
+            assert!(
+                (low..=high).contains(&estimate),
+                "p{percent} of {len}: {estimate} not in {low}..={high}"
+            );
+            assert!(estimate >= last, "p{percent} of {len} went down");
+            last = estimate;
+        }
+    }
+}
+
+#[test]
+fn merge_adds_and_saturates() {
+    let mut a = histogram(&[1, 30, 30]);
+    a.merge(&histogram(&[30, 70_000]));
+    assert_eq!(a, histogram(&[1, 30, 30, 30, 70_000]));
+    assert_eq!(a.count(), 5);
+
+    let mut full = Histogram::EMPTY;
+    full.counts[3] = u32::MAX;
+    full.merge(&histogram(&[4]));
+    assert_eq!(full.counts[3], u32::MAX);
+    assert_eq!(full.count(), u32::MAX);
+}
+
+fn roll(rollup: &mut Rollup, day: Option<u32>, samples: &[u64], sent: u32) -> bool {
+    let phases = [Histogram::EMPTY; PHASES.len()];
+    let mut discarded = [0; Reject::COUNT];
+    discarded[Reject::WrongId.index()] = 1;
+    rollup.roll_up(
+        day,
+        &histogram(samples),
+        &phases,
+        sent,
+        2 * sent,
+        &discarded,
+    )
+}
+
+#[test]
+fn a_new_day_moves_today_to_yesterday() {
+    let mut rollup = Rollup::default();
+    // Before the clock is set, everything is today.
+    assert!(!roll(&mut rollup, None, &[10], 100));
+    assert!(!roll(&mut rollup, Some(19_675), &[20], 100));
+    assert_eq!(rollup.day, Some(19_675));
+    assert_eq!(rollup.today.latency, histogram(&[10, 20]));
+    assert_eq!(
+        (rollup.today.bytes_sent, rollup.today.bytes_received),
+        (200, 400)
+    );
+    assert_eq!(rollup.today.dns_discarded[Reject::WrongId.index()], 2);
+
+    // A pass without the clock leaves the day as it was.
+    assert!(!roll(&mut rollup, None, &[30], 1));
+    assert_eq!(rollup.day, Some(19_675));
+
+    assert!(roll(&mut rollup, Some(19_676), &[40], 7));
+    assert_eq!(rollup.yesterday.latency, histogram(&[10, 20, 30]));
+    assert_eq!(rollup.yesterday.bytes_sent, 201);
+    assert_eq!(rollup.today.latency, histogram(&[40]));
+    assert_eq!(rollup.today.bytes_sent, 7);
+
+    // Days skipped leave nothing older than yesterday.
+    assert!(roll(&mut rollup, Some(19_680), &[], 0));
+    assert_eq!(rollup.yesterday.latency, histogram(&[40]));
+    assert_eq!(
+        rollup.today,
+        DayTotals {
+            dns_discarded: [0, 1, 0, 0, 0],
+            ..DayTotals::ZERO
+        }
+    );
+}
@@ -0,0 +1,166 @@
+// The SPI loopback against a mock bus that is bridged, open, stuck, slips
+// a byte or fails, and a chip select that records when it moved.
+
+mod common;
+
+use std::cell::{Cell, RefCell};
+use std::convert::Infallible;
+use std::rc::Rc;
+
+use common::block_on;
+use embedded_hal::digital::{self, OutputPin};
+use embedded_hal::spi::{self, ErrorKind};
+use embedded_hal_async::spi::SpiBus;
+use esp32c3_fuzz::selftestloopback::{self, LOOPBACK_LEN};
+
+/// What MISO sees for what went out on MOSI.
+#[derive(Clone, Copy)]
+enum Wiring {
+    Bridged,
+    /// Nothing on MISO: the pull-up reads all ones.
+    Open,
+    /// One line stuck high.
+    StuckBit(u8),
+    /// MISO a byte behind MOSI.
+    Slipped,
+    Broken,
+}
+
+struct Bus {
+    wiring: Wiring,
+    /// Whether chip select is low, as the pin left it.
+    selected: Rc<Cell<bool>>,
+    transfers: Vec<Vec<u8>>,
+}
+
+impl spi::ErrorType for Bus {
+    type Error = ErrorKind;
+}
+
+impl SpiBus for Bus {
+    async fn read(&mut self, _: &mut [u8]) -> Result<(), ErrorKind> {
+        unreachable!("only transfer is used")
+    }
+
+    async fn write(&mut self, _: &[u8]) -> Result<(), ErrorKind> {
+        unreachable!("only transfer is used")
+    }
+
+    async fn transfer(&mut self, read: &mut [u8], write: &[u8]) -> Result<(), ErrorKind> {
+        assert!(self.selected.get(), "transfer with chip select high");
+        assert_eq!(read.len(), write.len());
+        self.transfers.push(write.to_vec());
+        match self.wiring {
+            Wiring::Bridged => read.copy_from_slice(write),
+            Wiring::Open => read.fill(0xFF),
+            Wiring::StuckBit(bit) => {
+                for (read, &sent) in read.iter_mut().zip(write) {
+                    *read = sent | 1 << bit;
+                }
+            }
+            Wiring::Slipped => {
+                read[0] = 0xFF;
+                read[1..].copy_from_slice(&write[..write.len() - 1]);
+            }
+            Wiring::Broken => return Err(ErrorKind::Overrun),
+        }
+        Ok(())
+    }
+
+    async fn transfer_in_place(&mut self, _: &mut [u8]) -> Result<(), ErrorKind> {
+        unreachable!("only transfer is used")
+    }
+
+    async fn flush(&mut self) -> Result<(), ErrorKind> {
+        Ok(())
+    }
+}
+
+struct ChipSelect {
+    selected: Rc<Cell<bool>>,
+    levels: Rc<RefCell<Vec<bool>>>,
+}
+
+impl digital::ErrorType for ChipSelect {
+    type Error = Infallible;
+}
+
+impl OutputPin for ChipSelect {
+    fn set_low(&mut self) -> Result<(), Infallible> {
+        self.selected.set(true);
+        self.levels.borrow_mut().push(false);
+        Ok(())
+    }
+
+    fn set_high(&mut self) -> Result<(), Infallible> {
+        self.selected.set(false);
+        self.levels.borrow_mut().push(true);
+        Ok(())
+    }
+}
+
+/// The check over `wiring`: its result, what went out, and the levels chip
+/// select was set to.
+fn run(wiring: Wiring) -> (Result<(), &'static str>, Vec<Vec<u8>>, Vec<bool>) {
+    let selected = Rc::new(Cell::new(false));
+    let levels = Rc::new(RefCell::new(Vec::new()));
+    let mut bus = Bus {
+        wiring,
+        selected: selected.clone(),
+        transfers: Vec::new(),
+    };
+    let mut cs = ChipSelect {
+        selected,
+        levels: levels.clone(),
+    };
+    let result = block_on(selftestloopback::spi_loopback_test(&mut bus, &mut cs));
+    let levels = levels.borrow().clone();
+    (result, bus.transfers, levels)
+}
+
+#[test]
+fn passes_when_bridged() {
+    let (result, transfers, levels) = run(Wiring::Bridged);
+    assert_eq!(result, Ok(()));
+    assert_eq!(transfers, [selftestloopback::pattern().to_vec()]);
+    assert_eq!(levels, [false, true]);
+}
+
+#[test]
+fn fails_without_the_fixture() {
+    assert_eq!(run(Wiring::Open).0, Err("nothing came back"));
+}
+
+#[test]
+fn a_stuck_line_or_a_slipped_byte_shows() {
+    for bit in 0..8 {
+        let (result, _, levels) = run(Wiring::StuckBit(bit));
+        assert_eq!(result, Err("read back something else"), "bit {bit}");
+        assert_eq!(levels, [false, true]);
+    }
+    assert_eq!(run(Wiring::Slipped).0, Err("read back something else"));
+}
+
+#[test]
+fn a_failed_transfer_releases_chip_select() {
+    let (result, transfers, levels) = run(Wiring::Broken);
+    assert_eq!(result, Err("transfer failed"));
+    assert_eq!(transfers.len(), 1);
+    assert_eq!(levels, [false, true]);
+}
+
+#[test]
+fn the_pattern_drives_every_line_both_ways() {
+    let pattern = selftestloopback::pattern();
+    assert_eq!(pattern.len(), LOOPBACK_LEN);
+    for bit in 0..8 {
+        assert!(pattern.iter().any(|b| b & 1 << bit != 0), "bit {bit} high");
+        assert!(pattern.iter().any(|b| b & 1 << bit == 0), "bit {bit} low");
+    }
+    // A walking one: each line on its own.
+    for bit in 0..8 {
+        assert!(pattern.contains(&(1 << bit)), "bit {bit} alone");
+    }
+    // No two bytes in a row alike, so a slip by one always shows.
+    assert!(pattern.windows(2).all(|w| w[0] != w[1]));
+}
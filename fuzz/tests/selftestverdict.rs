@@ -0,0 +1,121 @@
+// The self-test's verdict: the names a script matches on, which failures
+// end a run, the line itself, and that it fits the buffer it is printed
+// from.
+
+mod common;
+
+use esp32c3_fuzz::selftestverdict::{Check, Verdict, CHECKS};
+
+use common::json_object;
+
+fn line(verdict: &Verdict) -> String {
+    let mut out = String::new();
+    verdict.write(&mut out).unwrap();
+    out
+}
+
+/// A run over `CHECKS` in which the ones in `failing` fail: the verdict,
+/// and the checks that ran.
+fn run(failing: &[Check]) -> (Verdict, Vec<Check>) {
+    let mut verdict = Verdict::default();
+    let mut ran = Vec::new();
+    for &check in CHECKS {
+        ran.push(check);
+        if !verdict.record(check, !failing.contains(&check)) {
+            break;
+        }
+    }
+    (verdict, ran)
+}
+
+#[test]
+fn names_and_order() {
+    let names: Vec<&str> = CHECKS.iter().map(|check| check.as_str()).collect();
+    let mut expected = vec!["rng", "i2c", "adc"];
+    if cfg!(feature = "factory") {
+        expected.push("spi");
+    }
+    expected.extend([
+        "kv",
+        "wifi_scan",
+        "association",
+        "dhcp",
+        "dns",
+        "tls",
+        "sntp",
+    ]);
+    assert_eq!(names, expected);
+
+    let soft: Vec<&str> = CHECKS
+        .iter()
+        .filter(|check| !check.is_hard())
+        .map(|check| check.as_str())
+        .collect();
+    let mut expected = vec!["i2c", "adc"];
+    if cfg!(feature = "factory") {
+        expected.push("spi");
+    }
+    expected.extend(["wifi_scan", "sntp"]);
+    assert_eq!(soft, expected);
+}
+
+#[test]
+fn all_passed() {
+    let (verdict, ran) = run(&[]);
+    assert_eq!(ran, CHECKS);
+    let verdict = Verdict {
+        ms: 6120,
+        ..verdict
+    };
+    let of = CHECKS.len();
+    assert_eq!(
+        line(&verdict),
+        format!("{{\"v\":1,\"ok\":true,\"passed\":{of},\"of\":{of},\"failed\":null,\"ms\":6120}}")
+    );
+}
+
+#[test]
+fn a_hard_failure_ends_the_run() {
+    let (verdict, ran) = run(&[Check::Dns]);
+    assert_eq!(ran.last(), Some(&Check::Dns));
+    assert_eq!(verdict.failed, Some(Check::Dns));
+    assert_eq!(verdict.passed, ran.len() - 1);
+    assert_eq!(
+        json_object(&line(&verdict)),
+        [
+            ("v".into(), "1".into()),
+            ("ok".into(), "false".into()),
+            ("passed".into(), verdict.passed.to_string()),
+            ("of".into(), CHECKS.len().to_string()),
+            ("failed".into(), "dns".into()),
+            ("ms".into(), "0".into()),
+        ]
+    );
+}
+
+#[test]
+fn soft_failures_go_on_and_the_first_is_named() {
+    let (verdict, ran) = run(&[Check::Adc, Check::WifiScan, Check::Sntp]);
+    assert_eq!(ran, CHECKS);
+    assert_eq!(verdict.failed, Some(Check::Adc));
+    assert_eq!(verdict.passed, CHECKS.len() - 3);
+
+    // A hard failure after a soft one still ends the run, and the soft one
+    // is still the one named.
+    let (verdict, ran) = run(&[Check::I2c, Check::Kv]);
+    assert_eq!(ran.last(), Some(&Check::Kv));
+    assert_eq!(verdict.failed, Some(Check::I2c));
+}
+
+#[test]
+fn every_line_fits_the_buffer() {
+    // src/selftest.rs prints from a `String<128>`.
+    for &check in CHECKS {
+        let verdict = Verdict {
+            passed: CHECKS.len(),
+            failed: Some(check),
+            ms: u64::MAX,
+        };
+        assert!(line(&verdict).len() <= 128, "{check:?}");
+    }
+}
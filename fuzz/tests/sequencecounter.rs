@@ -0,0 +1,165 @@
+// Upload sequence numbers across resets: a device that loses power at
+// every point of a block's reservation, against a store that keeps what
+// was last written. No number may come twice, and a reset skips less than
+// a block, or two when it came between a reservation and its first number.
+
+use esp32c3_fuzz::sequencecounter::{Counter, BLOCK};
+
+/// Where in `src/sequence.rs`'s `next` the power goes.
+#[derive(Debug, Clone, Copy)]
+enum Crash {
+    /// Before the new end reaches the store.
+    BeforeWrite,
+    /// After the store has it, before the number goes out.
+    AfterWrite,
+    /// With the number handed out.
+    AfterTake,
+}
+
+struct Device {
+    /// The KV store's `upload.seq`.
+    stored: Option<u64>,
+    counter: Option<Counter>,
+    sent: Vec<u64>,
+}
+
+impl Device {
+    fn new() -> Self {
+        Self {
+            stored: None,
+            counter: None,
+            sent: Vec::new(),
+        }
+    }
+
+    /// `next` as src/sequence.rs runs it, losing power at `crash` among the
+    /// steps; `false` if it did.
+    fn next(&mut self, crash: Option<Crash>) -> bool {
+        let mut counter = self
+            .counter
+            .unwrap_or_else(|| Counter::resume(self.stored.unwrap_or(0)));
+        if let Some(through) = counter.reservation_needed() {
+            if matches!(crash, Some(Crash::BeforeWrite)) {
+                return self.reset();
+            }
+            self.stored = Some(through);
+            if matches!(crash, Some(Crash::AfterWrite)) {
+                return self.reset();
+            }
+            counter.reserved(through);
+        }
+        self.sent.push(counter.take());
+        self.counter = Some(counter);
+        if matches!(crash, Some(Crash::AfterTake)) {
+            return self.reset();
+        }
+        true
+    }
+
+    fn reset(&mut self) -> bool {
+        self.counter = None;
+        false
+    }
+}
+
+#[test]
+fn counts_up_and_reserves_a_block_at_a_time() {
+    let mut device = Device::new();
+    let mut writes = Vec::new();
+    for _ in 0..3 * BLOCK {
+        let before = device.stored;
+        device.next(None);
+        if device.stored != before {
+            writes.push((device.sent.len() - 1, device.stored.unwrap()));
+        }
+    }
+    assert_eq!(device.sent, (0..3 * BLOCK).collect::<Vec<_>>());
+    // Each block's end is written before its first number goes out.
+    assert_eq!(
+        writes,
+        [
+            (0, BLOCK),
+            (BLOCK as usize, 2 * BLOCK),
+            (2 * BLOCK as usize, 3 * BLOCK)
+        ]
+    );
+}
+
+#[test]
+fn resumes_past_the_reserved_block() {
+    let mut counter = Counter::resume(5 * BLOCK);
+    assert_eq!(counter.reservation_needed(), Some(6 * BLOCK));
+    counter.reserved(6 * BLOCK);
+    assert_eq!(counter.reservation_needed(), None);
+    assert_eq!(counter.take(), 5 * BLOCK);
+
+    let mut device = Device::new();
+    for _ in 0..10 {
+        device.next(None);
+    }
+    device.reset();
+    device.next(None);
+    assert_eq!(device.sent.last(), Some(&BLOCK));
+    assert_eq!(device.stored, Some(2 * BLOCK));
+}
+
+#[test]
+fn never_twice_across_crashes_at_every_point() {
+    for crash in [Crash::BeforeWrite, Crash::AfterWrite, Crash::AfterTake] {
+        // Power lost every so many calls, so that over a run it goes at
+        // every number of a block, its first and last included.
+        for period in [1, 2, 3, 7, BLOCK - 1, BLOCK, BLOCK + 1, 2 * BLOCK + 5] {
+            let mut device = Device::new();
+            let mut last = None;
+            let mut resets = 0;
+            for call in 0..20 * BLOCK {
+                let before = device.sent.len();
+                let ok = device.next((call % period == period - 1).then_some(crash));
+                if device.sent.len() > before {
+                    let seq = device.sent[before];
+                    if resets > 0 {
+                        let skipped = seq - last.map_or(0, |last: u64| last + 1);
+                        // Every reset between a block's write and its first
+                        // number costs that block.
+                        let limit = match crash {
+                            Crash::AfterWrite => BLOCK * (resets + 1),
+                            _ => BLOCK,
+                        };
+                        assert!(skipped < limit, "{crash:?} every {period}: {skipped}");
+                    }
+                    last = Some(seq);
+                    resets = 0;
+                }
+                if !ok {
+                    resets += 1;
+                }
+            }
+            // Rising, so never the same twice.
+            assert!(
+                device.sent.windows(2).all(|w| w[0] < w[1]),
+                "{crash:?} every {period}"
+            );
+        }
+    }
+}
+
+#[test]
+fn a_crash_after_the_write_skips_the_whole_block() {
+    let mut device = Device::new();
+    device.next(None);
+    device.reset();
+    // Resumes at 64, reserves up to 128, and loses power before 64 goes out.
+    assert!(!device.next(Some(Crash::AfterWrite)));
+    assert_eq!(device.stored, Some(2 * BLOCK));
+    device.next(None);
+    assert_eq!(device.sent, [0, 2 * BLOCK]);
+
+    // Before the write nothing is lost beyond the usual.
+    let mut device = Device::new();
+    device.next(None);
+    device.reset();
+    assert!(!device.next(Some(Crash::BeforeWrite)));
+    assert_eq!(device.stored, Some(BLOCK));
+    device.next(None);
+    assert_eq!(device.sent, [0, BLOCK]);
+}
@@ -0,0 +1,189 @@
+// From SNTP samples to a time: each server's burst combined, one server
+// that lies outvoted by the others but never by itself, and a correction
+// slewed without the clock going backwards, or stepped.
+
+use esp32c3_fuzz::sntpselect::{
+    self, Estimate, Sample, BURST, MAX_DISAGREEMENT_MS, MAX_SLEW_PPM, STEP_THRESHOLD_MS,
+};
+
+/// 2024-06-01 12:00 UTC less an uptime of 10 s.
+const TRUE_MS: i64 = 1_717_243_200_000 - 10_000;
+
+fn estimate(offset_ms: i64, uncertainty_ms: u32) -> Estimate {
+    Estimate {
+        offset_ms,
+        uncertainty_ms,
+        kept: BURST,
+    }
+}
+
+/// A burst from a server `off_ms` away from the true time, its samples
+/// 40 ms of round trip apart and spread by `jitter_ms`.
+fn burst(off_ms: i64, jitter_ms: &[i64]) -> Vec<Sample> {
+    jitter_ms
+        .iter()
+        .map(|&jitter| Sample {
+            offset_ms: TRUE_MS + off_ms + jitter,
+            delay_ms: 40,
+        })
+        .collect()
+}
+
+#[test]
+fn a_burst_drops_its_outliers() {
+    // All alike: none is an outlier.
+    let alike = burst(0, &[0; BURST]);
+    assert_eq!(
+        sntpselect::combine(&alike),
+        Some(Estimate {
+            offset_ms: TRUE_MS,
+            uncertainty_ms: 20,
+            kept: BURST,
+        })
+    );
+    // One reply that queued behind something: more than 1.5 standard
+    // deviations out, and left out of the mean and the uncertainty.
+    let queued = burst(0, &[-3, 2, 0, 1, -1, 300, 3, -2]);
+    let estimate = sntpselect::combine(&queued).unwrap();
+    assert_eq!(estimate.kept, BURST - 1);
+    assert_eq!(estimate.offset_ms, TRUE_MS);
+    assert_eq!(estimate.uncertainty_ms, 3 + 20);
+
+    assert_eq!(sntpselect::combine(&[]), None);
+    let one = burst(5, &[0]);
+    assert_eq!(sntpselect::combine(&one).map(|e| e.kept), Some(1));
+}
+
+#[test]
+fn one_lying_server_is_outvoted() {
+    for lie_ms in [
+        -3_600_000,
+        // Just too far from the nearer honest one, which is the median.
+        -(MAX_DISAGREEMENT_MS as i64) - 11,
+        MAX_DISAGREEMENT_MS as i64 + 11,
+        86_400_000,
+    ] {
+        for liar in 0..3 {
+            let mut estimates = vec![estimate(TRUE_MS - 10, 30), estimate(TRUE_MS + 10, 50)];
+            estimates.insert(liar, estimate(TRUE_MS + lie_ms, 5));
+            let (chosen, agreed) = sntpselect::select(&estimates).unwrap();
+            assert_eq!(agreed, 2, "{lie_ms} {liar}");
+            // The median of the two left, each one's distance from it
+            // added to its uncertainty.
+            assert_eq!(
+                chosen,
+                Estimate {
+                    offset_ms: TRUE_MS,
+                    uncertainty_ms: 60,
+                    kept: 2 * BURST,
+                }
+            );
+        }
+    }
+    // As far off as may be, it is no liar, and it counts.
+    let edge = MAX_DISAGREEMENT_MS as i64;
+    let estimates = [
+        estimate(TRUE_MS, 20),
+        estimate(TRUE_MS + edge, 20),
+        estimate(TRUE_MS - 5, 20),
+    ];
+    let (chosen, agreed) = sntpselect::select(&estimates).unwrap();
+    assert_eq!((chosen.offset_ms, agreed), (TRUE_MS, 3));
+    assert_eq!(chosen.uncertainty_ms, 20 + edge as u32);
+}
+
+#[test]
+fn a_liar_does_not_carry_the_vote_alone() {
+    // Three all far apart: the liar is the median, and only it agrees
+    // with itself.
+    let estimates = [
+        estimate(TRUE_MS, 20),
+        estimate(TRUE_MS + 400, 20),
+        estimate(TRUE_MS + 800, 20),
+    ];
+    assert_eq!(sntpselect::select(&estimates), None);
+    // With two, there is no telling which one lies.
+    let estimates = [estimate(TRUE_MS, 20), estimate(TRUE_MS + 3_600_000, 20)];
+    assert_eq!(sntpselect::select(&estimates), None);
+    let estimates = [estimate(TRUE_MS + 3_600_000, 20), estimate(TRUE_MS, 20)];
+    assert_eq!(sntpselect::select(&estimates), None);
+    assert_eq!(sntpselect::select(&[]), None);
+
+    // Two that agree, and one alone, are believed.
+    let estimates = [estimate(TRUE_MS, 20), estimate(TRUE_MS + 100, 20)];
+    assert_eq!(
+        sntpselect::select(&estimates).map(|(e, n)| (e.offset_ms, n)),
+        Some((TRUE_MS + 50, 2))
+    );
+    let alone = [estimate(TRUE_MS + 3_600_000, 20)];
+    assert_eq!(sntpselect::select(&alone), Some((alone[0], 1)));
+}
+
+#[test]
+fn a_whole_sync_with_a_liar() {
+    // Three bursts, as a first sync takes them: two honest servers with a
+    // stray reply each, and one a minute fast with no jitter at all.
+    let bursts = [
+        burst(0, &[0, 4, -4, 2, -2, 250, 1, -1]),
+        burst(60_000, &[0; BURST]),
+        burst(6, &[3, 5, 7, 9, 4, 6, 8, -240]),
+    ];
+    let estimates: Vec<Estimate> = bursts
+        .iter()
+        .filter_map(|b| sntpselect::combine(b))
+        .collect();
+    assert_eq!(estimates.len(), 3);
+    let (chosen, agreed) = sntpselect::select(&estimates).unwrap();
+    assert_eq!(agreed, 2);
+    // Halfway between the honest two, 0 and 12 ms off once their stray
+    // replies are dropped.
+    assert_eq!(chosen.offset_ms, TRUE_MS + 6);
+    assert_eq!(chosen.kept, 2 * (BURST - 1));
+    assert!(chosen.uncertainty_ms < 40, "{chosen:?}");
+}
+
+#[test]
+fn small_corrections_slew_and_large_ones_step() {
+    const NOW_MS: u64 = 1_717_243_200_000;
+    assert_eq!(sntpselect::adjust(None, NOW_MS), (NOW_MS, 0));
+    let near = STEP_THRESHOLD_MS;
+    assert_eq!(
+        sntpselect::adjust(Some(NOW_MS - near), NOW_MS),
+        (NOW_MS - near, near as i64)
+    );
+    assert_eq!(
+        sntpselect::adjust(Some(NOW_MS + 7), NOW_MS),
+        (NOW_MS + 7, -7)
+    );
+    assert_eq!(
+        sntpselect::adjust(Some(NOW_MS - near - 1), NOW_MS),
+        (NOW_MS, 0)
+    );
+    assert_eq!(
+        sntpselect::adjust(Some(NOW_MS + 60_000), NOW_MS),
+        (NOW_MS, 0)
+    );
+}
+
+#[test]
+fn a_slew_never_runs_the_clock_backwards() {
+    assert_eq!(MAX_SLEW_PPM, 500);
+    // 0.5 ms a second, either way, and no more than the correction.
+    assert_eq!(sntpselect::slewed(-128, 0), 0);
+    assert_eq!(sntpselect::slewed(-128, 1_999), 0);
+    assert_eq!(sntpselect::slewed(-128, 2_000), -1);
+    assert_eq!(sntpselect::slewed(128, 100_000), 50);
+    assert_eq!(sntpselect::slewed(128, 256_000), 128);
+    assert_eq!(sntpselect::slewed(-128, 3_600_000), -128);
+
+    for slew_ms in [-(STEP_THRESHOLD_MS as i64), -1, 1, STEP_THRESHOLD_MS as i64] {
+        let read = |elapsed_ms: u64| elapsed_ms as i64 + sntpselect::slewed(slew_ms, elapsed_ms);
+        for elapsed_ms in (0..300_000).step_by(7) {
+            assert!(
+                read(elapsed_ms + 1) >= read(elapsed_ms),
+                "{slew_ms} {elapsed_ms}"
+            );
+        }
+        assert_eq!(read(300_000), 300_000 + slew_ms);
+    }
+}
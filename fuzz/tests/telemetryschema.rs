@@ -0,0 +1,220 @@
+// Both layouts of a reading against the fixtures in telemetryschema/, the
+// same values in each whatever the layout, the longest reading fitting,
+// and a version no layout has falling back to the newest.
+
+mod common;
+
+use common::json_object;
+use esp32c3_fuzz::batch::BatchStats;
+use esp32c3_fuzz::power::PowerStatus;
+use esp32c3_fuzz::restartnote::Reason;
+use esp32c3_fuzz::telemetryschema::{self, Formatter, Sample, LATEST, MAX_READING_LEN, V1, V2};
+
+const BARE: Sample = Sample {
+    uptime_ms: 0,
+    uptime_total_ms: 0,
+    config_revision: 0,
+    seq: None,
+    message_id: [0; 16],
+    reset: None,
+    wifi_alloc_failures: 0,
+    power: None,
+    batch: None,
+};
+
+const FULL: Sample = Sample {
+    uptime_ms: 5021,
+    uptime_total_ms: 905_021,
+    config_revision: 7,
+    seq: Some(12),
+    message_id: [
+        0x0f, 0x1e, 0x2d, 0x3c, 0x4b, 0x5a, 0x49, 0x78, 0x87, 0x96, 0xa5, 0xb4, 0xc3, 0xd2, 0xe1,
+        0xf0,
+    ],
+    reset: Some(Reason::WifiStuck(2)),
+    wifi_alloc_failures: 3,
+    power: Some(PowerStatus {
+        battery_mv: 3710,
+        charging: false,
+    }),
+    batch: Some(BatchStats {
+        flushes: 4,
+        readings: 18,
+        by_count: 2,
+        by_size: 1,
+        by_age: 1,
+    }),
+};
+
+/// The largest numbers, a reset without a count, and no batch delivered.
+const LARGEST: Sample = Sample {
+    uptime_ms: 86_400_000,
+    uptime_total_ms: 4_294_967_296_000,
+    config_revision: u32::MAX,
+    seq: Some(u64::MAX),
+    message_id: [
+        0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x4f, 0xff, 0xbf, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+        0xff,
+    ],
+    reset: Some(Reason::Update),
+    wifi_alloc_failures: 0,
+    power: Some(PowerStatus {
+        battery_mv: 4200,
+        charging: true,
+    }),
+    batch: Some(BatchStats {
+        flushes: 0,
+        readings: 0,
+        by_count: 0,
+        by_size: 0,
+        by_age: 0,
+    }),
+};
+
+const GOLDEN: [(&str, Sample, &str, &str); 3] = [
+    (
+        "bare",
+        BARE,
+        include_str!("telemetryschema/bare-v1.json"),
+        include_str!("telemetryschema/bare-v2.json"),
+    ),
+    (
+        "full",
+        FULL,
+        include_str!("telemetryschema/full-v1.json"),
+        include_str!("telemetryschema/full-v2.json"),
+    ),
+    (
+        "largest",
+        LARGEST,
+        include_str!("telemetryschema/largest-v1.json"),
+        include_str!("telemetryschema/largest-v2.json"),
+    ),
+];
+
+#[test]
+fn both_layouts_match_the_fixtures() {
+    for (what, sample, v1, v2) in GOLDEN {
+        assert_eq!(V1::serialize(&sample), v1.trim_end(), "{what}");
+        assert_eq!(V2::serialize(&sample), v2.trim_end(), "{what}");
+        assert_eq!(telemetryschema::serialize(1, &sample), v1.trim_end());
+        assert_eq!(telemetryschema::serialize(2, &sample), v2.trim_end());
+    }
+}
+
+/// The members of a reading, the groups of version 2 taken apart and named
+/// as version 1 names them.
+fn flat(reading: &str) -> Vec<(String, String)> {
+    let mut members = Vec::new();
+    for (key, value) in json_object(reading) {
+        if !value.starts_with('{') || key == "batch" {
+            members.push((key, value));
+            continue;
+        }
+        for (inner, value) in json_object(&value) {
+            let name = match (key.as_str(), inner.as_str()) {
+                ("uptime", "boot_ms") => "uptime_ms",
+                ("uptime", "total_ms") => "uptime_total_ms",
+                ("reset", "reason") => "reset",
+                ("reset", "wifi_resets") => "wifi_resets",
+                ("battery", "mv") => "battery_mv",
+                ("battery", "charging") => "charging",
+                other => panic!("{other:?} in {reading}"),
+            };
+            members.push((name.into(), value));
+        }
+    }
+    members
+}
+
+/// Samples with each field there or not, and each reason.
+fn samples() -> Vec<Sample> {
+    let reasons = [
+        None,
+        Some(Reason::Scheduled(20_000)),
+        Some(Reason::Requested),
+        Some(Reason::Update),
+        Some(Reason::SafeModeExit),
+        Some(Reason::WifiStuck(1)),
+    ];
+    let mut samples = Vec::new();
+    for (i, reset) in reasons.into_iter().enumerate() {
+        for fields in 0..16u32 {
+            samples.push(Sample {
+                seq: (fields & 1 != 0).then_some(i as u64),
+                reset,
+                wifi_alloc_failures: fields & 2,
+                power: (fields & 4 != 0).then_some(PowerStatus {
+                    battery_mv: 3300 + fields,
+                    charging: i % 2 == 0,
+                }),
+                batch: (fields & 8 != 0).then_some(FULL.batch.unwrap()),
+                ..FULL
+            });
+        }
+    }
+    samples
+}
+
+#[test]
+fn both_layouts_carry_the_same_values() {
+    for sample in samples() {
+        let v1 = json_object(&V1::serialize(&sample));
+        let v2 = flat(&V2::serialize(&sample));
+        assert_eq!(v1[0], ("schema_version".into(), "1".into()));
+        assert_eq!(v2[0], ("schema_version".into(), "2".into()));
+        assert_eq!(v1[1..], v2[1..], "{sample:?}");
+    }
+}
+
+#[test]
+fn the_longest_reading_fits() {
+    let longest = Sample {
+        uptime_ms: u64::MAX,
+        uptime_total_ms: u64::MAX,
+        config_revision: u32::MAX,
+        seq: Some(u64::MAX),
+        message_id: [0xff; 16],
+        reset: Some(Reason::WifiStuck(u32::MAX)),
+        wifi_alloc_failures: u32::MAX,
+        power: Some(PowerStatus {
+            battery_mv: u32::MAX,
+            charging: false,
+        }),
+        // An average of u32::MAX tenths.
+        batch: Some(BatchStats {
+            flushes: 10,
+            readings: u32::MAX,
+            by_count: u32::MAX,
+            by_size: u32::MAX,
+            by_age: u32::MAX,
+        }),
+    };
+    let mut lengths = Vec::new();
+    for reset in [Reason::WifiStuck(u32::MAX), Reason::SafeModeExit] {
+        let sample = Sample {
+            reset: Some(reset),
+            ..longest
+        };
+        for reading in [V1::serialize(&sample), V2::serialize(&sample)] {
+            json_object(&reading);
+            lengths.push(reading.len());
+        }
+    }
+    assert!(lengths.iter().all(|&len| len <= MAX_READING_LEN));
+    assert_eq!(lengths.iter().max(), Some(&419));
+}
+
+#[test]
+fn an_unknown_version_falls_back_to_the_newest() {
+    assert_eq!(LATEST, 2);
+    assert_eq!(telemetryschema::negotiate(1), 1);
+    assert_eq!(telemetryschema::negotiate(2), 2);
+    for unknown in [0, 3, 9, u8::MAX] {
+        assert_eq!(telemetryschema::negotiate(unknown), LATEST, "{unknown}");
+        assert_eq!(
+            telemetryschema::serialize(telemetryschema::negotiate(unknown), &FULL),
+            V2::serialize(&FULL)
+        );
+    }
+}
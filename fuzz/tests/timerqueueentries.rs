@@ -0,0 +1,282 @@
+// The timer queue's entries against a clock stepped by hand: one entry per
+// task, what is due woken and the alarm moved on, a full queue waking the
+// task due last, and the busiest the firmware gets fitting with room to
+// spare. Also that src/timerqueue.rs's list of tasks has every task that
+// can wait on a timer.
+
+use std::cell::Cell;
+use std::collections::BTreeSet;
+use std::fs;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::task::{Wake, Waker};
+
+use esp32c3_fuzz::timerqueueentries::{Alarm, Entries};
+
+/// The driver: a clock that moves when told, or by `step` at every read,
+/// and the alarm last set.
+#[derive(Clone, Default)]
+struct Driver {
+    now: Rc<Cell<u64>>,
+    step: Rc<Cell<u64>>,
+    alarm: Rc<Cell<Option<u64>>>,
+}
+
+impl Alarm for Driver {
+    fn now(&self) -> u64 {
+        let now = self.now.get();
+        self.now.set(now + self.step.get());
+        now
+    }
+
+    fn set(&mut self, at: u64) -> bool {
+        if at <= self.now.get() {
+            return false;
+        }
+        self.alarm.set(Some(at));
+        true
+    }
+}
+
+/// A task, as far as the queue sees one: its waker, and how often that
+/// woke it.
+struct Task(AtomicUsize);
+
+impl Wake for Task {
+    fn wake(self: Arc<Self>) {
+        self.0.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+fn task() -> (Arc<Task>, Waker) {
+    let task = Arc::new(Task(AtomicUsize::new(0)));
+    (task.clone(), Waker::from(task))
+}
+
+fn wakes(task: &Task) -> usize {
+    task.0.load(Ordering::Relaxed)
+}
+
+#[test]
+fn one_entry_per_task_at_its_earliest() {
+    let driver = Driver::default();
+    let mut entries: Entries<_, 4> = Entries::new(driver.clone());
+    let (a, waker) = task();
+    // A `select` of three timers, or a timeout around a read.
+    for at in [5_000, 3_000, 4_000] {
+        entries.schedule(at, &waker);
+    }
+    assert_eq!((entries.len(), driver.alarm.get()), (1, Some(3_000)));
+    let (_, other) = task();
+    entries.schedule(2_000, &other);
+    assert_eq!((entries.len(), driver.alarm.get()), (2, Some(2_000)));
+    assert_eq!(wakes(&a), 0);
+}
+
+#[test]
+fn what_is_due_is_woken_and_the_alarm_moves_on() {
+    let driver = Driver::default();
+    let mut entries: Entries<_, 4> = Entries::new(driver.clone());
+    let tasks: Vec<_> = [100, 200, 300].map(|at| (at, task())).into();
+    for (at, (_, waker)) in &tasks {
+        entries.schedule(*at, waker);
+    }
+    assert_eq!(driver.alarm.get(), Some(100));
+
+    // The alarm fires late: both due by then go at once.
+    driver.now.set(250);
+    entries.dispatch();
+    let woken: Vec<_> = tasks.iter().map(|(_, (task, _))| wakes(task)).collect();
+    assert_eq!(woken, [1, 1, 0]);
+    assert_eq!((entries.len(), driver.alarm.get()), (1, Some(300)));
+
+    // A deadline already passed is woken at once and never queued.
+    let (late, waker) = task();
+    entries.schedule(250, &waker);
+    assert_eq!((wakes(&late), entries.len()), (1, 1));
+    assert_eq!(driver.alarm.get(), Some(300));
+}
+
+#[test]
+fn a_deadline_passed_while_dispatching_is_not_missed() {
+    // Each read of the clock finds it 10 ticks on, so the alarm for a
+    // deadline 5 ticks off has passed by the time it is set.
+    let driver = Driver::default();
+    driver.now.set(1_000);
+    driver.step.set(10);
+    let mut entries: Entries<_, 4> = Entries::new(driver.clone());
+    let (task, waker) = task();
+    entries.schedule(1_005, &waker);
+    assert_eq!((wakes(&task), entries.len()), (1, 0));
+    assert_eq!(driver.alarm.get(), None);
+}
+
+#[test]
+fn a_full_queue_wakes_the_task_due_last() {
+    let driver = Driver::default();
+    let mut entries: Entries<_, 4> = Entries::new(driver.clone());
+    let queued: Vec<_> = [100, 400, 200, 300].map(|at| (at, task())).into();
+    for (at, (_, waker)) in &queued {
+        entries.schedule(*at, waker);
+    }
+    assert_eq!((entries.len(), entries.evictions()), (4, 0));
+
+    // An earlier deadline takes the slot of the one due at 400, which is
+    // woken to find its timer not due and queue again.
+    let (new, waker) = task();
+    entries.schedule(250, &waker);
+    let woken: Vec<_> = queued.iter().map(|(_, (task, _))| wakes(task)).collect();
+    assert_eq!(woken, [0, 1, 0, 0]);
+    assert_eq!((wakes(&new), entries.evictions()), (0, 1));
+
+    // One due last itself, or with the latest queued, is woken instead.
+    for at in [500, 300] {
+        let (last, waker) = task();
+        entries.schedule(at, &waker);
+        assert_eq!(wakes(&last), 1, "{at}");
+    }
+    assert_eq!(
+        (entries.len(), entries.peak(), entries.evictions()),
+        (4, 4, 3)
+    );
+    assert_eq!(driver.alarm.get(), Some(100));
+    let woken: Vec<_> = queued.iter().map(|(_, (task, _))| wakes(task)).collect();
+    assert_eq!(woken, [0, 1, 0, 0]);
+}
+
+#[test]
+fn a_task_woken_early_gets_in_once_there_is_room() {
+    // Five tasks in four entries: the one due last is woken each time it
+    // queues, until the first deadline frees an entry.
+    let driver = Driver::default();
+    let mut entries: Entries<_, 4> = Entries::new(driver.clone());
+    let tasks: Vec<_> = [100, 200, 300, 400].map(|at| (at, task())).into();
+    for (at, (_, waker)) in &tasks {
+        entries.schedule(*at, waker);
+    }
+    let (spinning, waker) = task();
+    for poll in 1..=3 {
+        entries.schedule(500, &waker);
+        assert_eq!(wakes(&spinning), poll);
+    }
+    driver.now.set(100);
+    entries.dispatch();
+    assert_eq!(wakes(&tasks[0].1 .0), 1);
+    entries.schedule(500, &waker);
+    assert_eq!((wakes(&spinning), entries.len()), (3, 4));
+    assert_eq!(entries.evictions(), 3);
+
+    // From there every task is woken at its deadline, and none before.
+    for at in [200, 300, 400, 500] {
+        driver.now.set(at);
+        entries.dispatch();
+    }
+    let woken: Vec<_> = tasks.iter().map(|(_, (task, _))| wakes(task)).collect();
+    assert_eq!(woken, [1, 1, 1, 1]);
+    assert_eq!((wakes(&spinning), entries.len()), (4, 0));
+}
+
+/// The entries `fits` keeps free (src/timerqueue.rs).
+const LEN: usize = 32;
+const MARGIN: usize = 8;
+
+/// Runs `count` tasks in a queue of `LEN` for ten simulated minutes, each
+/// waking on its own period and, as a `with_timeout` around a read does,
+/// with a second deadline further off. Returns the entries' peak and
+/// evictions.
+fn busiest(count: usize) -> (usize, u32) {
+    const END: u64 = 600_000;
+    let driver = Driver::default();
+    let mut entries: Entries<_, LEN> = Entries::new(driver.clone());
+    let mut tasks: Vec<_> = (0..count)
+        .map(|i| {
+            let period = 7 + 13 * i as u64;
+            let (task, waker) = task();
+            (task, waker, period, period)
+        })
+        .collect();
+    for (_, waker, _, due) in &tasks {
+        entries.schedule(*due, waker);
+        entries.schedule(*due + 5_000, waker);
+    }
+    let mut seen = vec![0; count];
+    while let Some(at) = driver.alarm.take() {
+        if at > END {
+            break;
+        }
+        driver.now.set(at);
+        entries.dispatch();
+        for (i, (task, waker, period, due)) in tasks.iter_mut().enumerate() {
+            if wakes(task) == seen[i] {
+                // Not woken: it must still have time.
+                assert!(*due > at, "task {i} due at {due} asleep at {at}");
+                continue;
+            }
+            seen[i] = wakes(task);
+            assert!(*due <= at, "task {i} woken early at {at}");
+            *due += *period;
+            entries.schedule(*due, waker);
+            entries.schedule(*due + 5_000, waker);
+        }
+    }
+    (entries.peak(), entries.evictions())
+}
+
+#[test]
+fn the_busiest_build_fits_with_room_to_spare() {
+    let tasks = timer_tasks().len();
+    assert!(tasks + MARGIN <= LEN);
+    let (peak, evictions) = busiest(tasks);
+    assert_eq!((peak, evictions), (tasks, 0));
+    // And with every spare entry taken by a task left off the list.
+    assert_eq!(busiest(LEN), (LEN, 0));
+}
+
+/// The names in `TIMER_TASKS`, with every feature on.
+fn timer_tasks() -> BTreeSet<String> {
+    let source = fs::read_to_string("../src/timerqueue.rs").unwrap();
+    let start = source.find("const TIMER_TASKS").unwrap();
+    let end = start + source[start..].find("];").unwrap();
+    source[start..end]
+        .lines()
+        .map(str::trim)
+        .filter_map(|line| line.strip_prefix('"')?.strip_suffix("\","))
+        .map(String::from)
+        .collect()
+}
+
+/// The names tasks are `canary::tracked` as, anywhere in the firmware.
+fn tracked_tasks() -> BTreeSet<String> {
+    let mut names = BTreeSet::new();
+    for file in fs::read_dir("../src").unwrap() {
+        let source = fs::read_to_string(file.unwrap().path()).unwrap();
+        for (_, rest) in source
+            .match_indices("tracked(\"")
+            .map(|(i, _)| source.split_at(i + 9))
+        {
+            names.insert(rest[..rest.find('"').unwrap()].to_string());
+        }
+    }
+    names
+}
+
+#[test]
+fn every_task_that_waits_on_a_timer_is_listed() {
+    // Waits for an SNTP result, then writes the RTC over I2C without a
+    // timeout.
+    const NO_TIMER: &[&str] = &["rtc"];
+    let listed = timer_tasks();
+    let tracked = tracked_tasks();
+    let unlisted: Vec<_> = tracked
+        .iter()
+        .filter(|name| !listed.contains(*name) && !NO_TIMER.contains(&name.as_str()))
+        .collect();
+    assert!(unlisted.is_empty(), "not in TIMER_TASKS: {unlisted:?}");
+    // And nothing listed that no longer runs; `main` is not tracked.
+    let gone: Vec<_> = listed
+        .iter()
+        .filter(|name| *name != "main" && !tracked.contains(*name))
+        .collect();
+    assert!(gone.is_empty(), "in TIMER_TASKS but not tracked: {gone:?}");
+}
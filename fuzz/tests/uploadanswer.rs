@@ -0,0 +1,111 @@
+// The server's answer to readings: which statuses deliver, refuse or defer
+// them, a queue kept whole through a run of 5xx with the uploads backing
+// off, and one refused for good leaving it.
+
+use std::collections::VecDeque;
+
+use esp32c3_fuzz::uploadanswer::{Backoff, Verdict, FIRST_DELAY_MS, MAX_DELAY_MS};
+
+/// The uploader's queue and backoff, flushing `count` readings at a time
+/// as src/uploader.rs does.
+struct Uploader {
+    queue: VecDeque<u64>,
+    backoff: Backoff,
+    delivered: Vec<u64>,
+}
+
+impl Uploader {
+    fn new(seqs: impl IntoIterator<Item = u64>) -> Self {
+        Self {
+            queue: seqs.into_iter().collect(),
+            backoff: Backoff::new(),
+            delivered: Vec::new(),
+        }
+    }
+
+    /// One flush at `now_ms`, answered `status`. Returns what was sent,
+    /// nothing if the backoff held it back.
+    fn flush(&mut self, count: usize, status: u16, now_ms: u64) -> Vec<u64> {
+        if !self.backoff.ready(now_ms) {
+            return Vec::new();
+        }
+        let sent: Vec<u64> = self.queue.iter().take(count).copied().collect();
+        let verdict = Verdict::of(status);
+        self.backoff.settle(verdict, now_ms);
+        if verdict != Verdict::Deferred {
+            self.queue.drain(..sent.len());
+        }
+        if verdict == Verdict::Delivered {
+            self.delivered.extend(&sent);
+        }
+        sent
+    }
+}
+
+#[test]
+fn statuses() {
+    for status in [200, 201, 202, 204, 299] {
+        assert_eq!(Verdict::of(status), Verdict::Delivered, "{status}");
+    }
+    for status in [400, 401, 403, 404, 409, 413, 415, 422, 499] {
+        assert_eq!(Verdict::of(status), Verdict::Refused, "{status}");
+    }
+    for status in [408, 429, 500, 502, 503, 504, 599, 0, 100, 301, 600] {
+        assert_eq!(Verdict::of(status), Verdict::Deferred, "{status}");
+    }
+}
+
+#[test]
+fn deferred_readings_stay_queued_and_go_once_the_server_takes_them() {
+    let mut uploader = Uploader::new(1..=5);
+    let mut now_ms = 0;
+    for status in [500, 502, 503, 429, 408] {
+        assert_eq!(uploader.flush(3, status, now_ms), [1, 2, 3]);
+        assert_eq!(uploader.queue, [1, 2, 3, 4, 5]);
+        // Not again before the backoff is over.
+        assert!(uploader.flush(3, 200, now_ms + 1).is_empty());
+        now_ms = uploader.backoff.until_ms();
+    }
+    assert!(uploader.delivered.is_empty());
+
+    assert_eq!(uploader.flush(3, 200, now_ms), [1, 2, 3]);
+    assert_eq!(uploader.flush(3, 200, now_ms), [4, 5]);
+    assert_eq!(uploader.delivered, [1, 2, 3, 4, 5]);
+    assert!(uploader.queue.is_empty());
+}
+
+#[test]
+fn refused_readings_leave_the_queue() {
+    let mut uploader = Uploader::new(1..=5);
+    assert_eq!(uploader.flush(3, 400, 0), [1, 2, 3]);
+    assert_eq!(uploader.queue, [4, 5]);
+    // A refusal is an answer too: nothing to wait for.
+    assert!(uploader.backoff.ready(0));
+    assert_eq!(uploader.flush(3, 200, 0), [4, 5]);
+    assert_eq!(uploader.delivered, [4, 5]);
+}
+
+#[test]
+fn the_delay_doubles_up_to_its_ceiling_and_resets_on_an_answer() {
+    let mut backoff = Backoff::new();
+    assert!(backoff.ready(0));
+    let mut delays = Vec::new();
+    for _ in 0..40 {
+        delays.push(backoff.settle(Verdict::Deferred, 1_000));
+    }
+    assert_eq!(
+        delays[..3],
+        [FIRST_DELAY_MS, 2 * FIRST_DELAY_MS, 4 * FIRST_DELAY_MS]
+    );
+    assert!(delays.windows(2).all(|w| w[0] <= w[1]));
+    assert_eq!(delays[39], MAX_DELAY_MS);
+    assert!(!backoff.ready(1_000 + MAX_DELAY_MS - 1));
+    assert!(backoff.ready(1_000 + MAX_DELAY_MS));
+
+    for verdict in [Verdict::Delivered, Verdict::Refused] {
+        backoff.settle(Verdict::Deferred, 0);
+        assert_eq!(backoff.settle(verdict, 0), 0);
+        assert!(backoff.ready(0));
+        assert_eq!(backoff.settle(Verdict::Deferred, 0), FIRST_DELAY_MS);
+    }
+}
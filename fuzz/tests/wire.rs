@@ -0,0 +1,238 @@
+// The wire cursors: byte order, every read and write at every offset of a
+// buffer cut short, what is left in place after a failure, length
+// prefixes at their limits, and back-patched lengths.
+
+use esp32c3_fuzz::wire::{ReadCursor, WireError, WriteCursor};
+
+const BYTES: [u8; 15] = [
+    0x01, 0x23, 0x45, 0x67, 0x89, 0xab, 0xcd, 0xef, 0x10, 0x32, 0x54, 0x76, 0x98, 0xba, 0xdc,
+];
+
+#[test]
+fn reads_big_endian() {
+    let mut read = ReadCursor::new(&BYTES);
+    assert_eq!(read.peek_u8(), Ok(0x01));
+    assert_eq!(read.u8(), Ok(0x01));
+    assert_eq!(read.u16(), Ok(0x2345));
+    assert_eq!(read.u32(), Ok(0x6789_abcd));
+    assert_eq!(read.remaining(), 8);
+    assert_eq!(read.u64(), Ok(0xef10_3254_7698_badc));
+    assert_eq!(read.remaining(), 0);
+    assert_eq!(read.peek_u8(), Err(WireError::Truncated));
+
+    let mut read = ReadCursor::new(&BYTES);
+    assert_eq!(read.u64(), Ok(0x0123_4567_89ab_cdef));
+    assert_eq!(read.u64(), Err(WireError::Truncated));
+    assert_eq!(read.bytes(3), Ok(&BYTES[8..11]));
+}
+
+#[test]
+fn writes_big_endian() {
+    let mut buf = [0; 15];
+    let mut write = WriteCursor::new(&mut buf);
+    assert!(write.is_empty());
+    write.u8(0x01).unwrap();
+    write.u16(0x2345).unwrap();
+    write.u32(0x6789_abcd).unwrap();
+    write.u64(0xef10_3254_7698_badc).unwrap();
+    assert_eq!(write.len(), 15);
+    assert_eq!(write.written(), BYTES);
+    assert_eq!(write.u8(0), Err(WireError::Overflow));
+}
+
+/// Every read, from every offset, on every prefix of `BYTES`; a read that
+/// does not fit leaves the cursor where it was.
+#[test]
+fn reads_at_every_offset_of_every_length() {
+    type Read = fn(&mut ReadCursor) -> Result<Vec<u8>, WireError>;
+    let reads: [(usize, Read); 5] = [
+        (1, |r| r.u8().map(|v| v.to_be_bytes().to_vec())),
+        (2, |r| r.u16().map(|v| v.to_be_bytes().to_vec())),
+        (4, |r| r.u32().map(|v| v.to_be_bytes().to_vec())),
+        (8, |r| r.u64().map(|v| v.to_be_bytes().to_vec())),
+        (3, |r| r.bytes(3).map(<[u8]>::to_vec)),
+    ];
+    for len in 0..=BYTES.len() {
+        let buf = &BYTES[..len];
+        for at in 0..=len {
+            for (width, read) in reads {
+                let mut cursor = ReadCursor::at(buf, at).unwrap();
+                match read(&mut cursor) {
+                    Ok(value) => {
+                        assert!(at + width <= len);
+                        assert_eq!(value, &buf[at..at + width]);
+                        assert_eq!(cursor.position(), at + width);
+                    }
+                    Err(e) => {
+                        assert!(at + width > len, "{width} at {at} of {len}");
+                        assert_eq!(e, WireError::Truncated);
+                        assert_eq!(cursor.position(), at);
+                    }
+                }
+            }
+        }
+        assert_eq!(
+            ReadCursor::at(buf, len + 1).err(),
+            Some(WireError::Truncated)
+        );
+    }
+}
+
+#[test]
+fn huge_lengths_do_not_wrap() {
+    let mut read = ReadCursor::at(&BYTES, 3).unwrap();
+    assert_eq!(read.bytes(usize::MAX), Err(WireError::Truncated));
+    assert_eq!(read.skip(usize::MAX - 2), Err(WireError::Truncated));
+    assert_eq!(read.position(), 3);
+}
+
+#[test]
+fn prefixed_reads() {
+    let message = [3, b'a', b'b', b'c', 0, 0, 2, b'x', b'y', 5, b'z'];
+    let mut read = ReadCursor::new(&message);
+    assert_eq!(read.prefixed_u8(), Ok(&b"abc"[..]));
+    assert_eq!(read.prefixed_u8(), Ok(&b""[..]));
+    assert_eq!(read.prefixed_u16(), Ok(&b"xy"[..]));
+    // A length past the end: the length is not consumed either.
+    assert_eq!(read.prefixed_u8(), Err(WireError::Truncated));
+    assert_eq!(read.position(), 9);
+    assert_eq!(read.prefixed_u16(), Err(WireError::Truncated));
+    assert_eq!(read.position(), 9);
+    assert_eq!(read.u8(), Ok(5));
+
+    let mark = read.mark();
+    assert_eq!(read.u8(), Ok(b'z'));
+    read.rewind(mark);
+    assert_eq!(read.peek_u8(), Ok(b'z'));
+    read.seek(0).unwrap();
+    assert_eq!(read.u8(), Ok(3));
+    assert_eq!(read.seek(message.len() + 1), Err(WireError::Truncated));
+    assert_eq!(read.position(), 1);
+}
+
+/// Every write into a buffer of every size: it either fits whole or
+/// leaves what was written before it as it was.
+#[test]
+fn writes_into_every_size() {
+    type Write = fn(&mut WriteCursor) -> Result<(), WireError>;
+    let writes: [(usize, Write); 7] = [
+        (1, |w| w.u8(0xa1)),
+        (2, |w| w.u16(0xa1a2)),
+        (4, |w| w.u32(0xa1a2_a3a4)),
+        (8, |w| w.u64(0xa1a2_a3a4_a5a6_a7a8)),
+        (3, |w| w.bytes(b"abc")),
+        (4, |w| w.prefixed_u8(b"abc")),
+        (5, |w| w.prefixed_u16(b"abc")),
+    ];
+    for size in 0..16 {
+        for before in 0..=size {
+            for (width, write) in writes {
+                let mut buf = vec![0xee; size];
+                let mut cursor = WriteCursor::new(&mut buf);
+                cursor.bytes(&BYTES[..before]).unwrap();
+                let result = write(&mut cursor);
+                if before + width <= size {
+                    assert_eq!(result, Ok(()));
+                    assert_eq!(cursor.len(), before + width);
+                } else {
+                    assert_eq!(
+                        result,
+                        Err(WireError::Overflow),
+                        "{width} after {before} in {size}"
+                    );
+                    assert_eq!(cursor.written(), &BYTES[..before]);
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn prefixes_at_their_limits() {
+    let mut buf = vec![0; 70_000];
+    let mut write = WriteCursor::new(&mut buf);
+    write.prefixed_u8(&[7; 255]).unwrap();
+    assert_eq!(write.written()[0], 255);
+    assert_eq!(write.prefixed_u8(&[7; 256]), Err(WireError::TooLong));
+    assert_eq!(write.len(), 256);
+
+    let mark = write.mark();
+    write.prefixed_u16(&[7; 65_535]).unwrap();
+    assert_eq!(write.written()[256..258], [0xff, 0xff]);
+    write.rewind(mark);
+    assert_eq!(write.prefixed_u16(&[7; 65_536]), Err(WireError::TooLong));
+    assert_eq!(write.len(), 256);
+}
+
+#[test]
+fn back_patched_lengths() {
+    let mut buf = [0; 16];
+    let mut write = WriteCursor::new(&mut buf);
+    write.u8(0xaa).unwrap();
+    let outer = write.reserve_u16().unwrap();
+    write.bytes(b"xy").unwrap();
+    let inner = write.reserve_u16().unwrap();
+    // Nothing after it yet.
+    write.patch_len_u16(inner).unwrap();
+    assert_eq!(write.written()[5..7], [0, 0]);
+    write.bytes(b"abc").unwrap();
+    write.patch_len_u16(inner).unwrap();
+    write.patch_len_u16(outer).unwrap();
+    assert_eq!(
+        write.written(),
+        [0xaa, 0, 7, b'x', b'y', 0, 3, b'a', b'b', b'c']
+    );
+
+    // What was written reads back through the lengths.
+    let mut read = ReadCursor::new(write.written());
+    assert_eq!(read.u8(), Ok(0xaa));
+    let body = read.prefixed_u16().unwrap();
+    let mut body = ReadCursor::new(body);
+    assert_eq!(body.bytes(2), Ok(&b"xy"[..]));
+    assert_eq!(body.prefixed_u16(), Ok(&b"abc"[..]));
+    assert_eq!(read.remaining(), 0);
+
+    // A placeholder dropped by a rewind cannot be patched.
+    write.rewind(outer);
+    assert_eq!(write.len(), 1);
+    assert_eq!(write.patch_len_u16(outer), Err(WireError::Overflow));
+    assert_eq!(write.patch_u16(outer, 1), Err(WireError::Overflow));
+    // Rewinding forward does nothing.
+    write.rewind(inner);
+    assert_eq!(write.len(), 1);
+}
+
+#[test]
+fn reserve_at_every_offset() {
+    for size in 0..8 {
+        for before in 0..=size {
+            let mut buf = vec![0; size];
+            let mut write = WriteCursor::new(&mut buf);
+            write.bytes(&BYTES[..before]).unwrap();
+            match write.reserve_u16() {
+                Ok(mark) => {
+                    assert!(before + 2 <= size);
+                    let rest = size - before - 2;
+                    write.bytes(&BYTES[..rest]).unwrap();
+                    write.patch_len_u16(mark).unwrap();
+                    assert_eq!(
+                        write.written()[before..before + 2],
+                        (rest as u16).to_be_bytes()
+                    );
+                }
+                Err(e) => {
+                    assert!(before + 2 > size);
+                    assert_eq!(e, WireError::Overflow);
+                    assert_eq!(write.len(), before);
+                }
+            }
+        }
+    }
+
+    // A count past what two bytes hold.
+    let mut buf = vec![0; 70_000];
+    let mut write = WriteCursor::new(&mut buf);
+    let mark = write.reserve_u16().unwrap();
+    write.bytes(&[0; 65_536]).unwrap();
+    assert_eq!(write.patch_len_u16(mark), Err(WireError::TooLong));
+}
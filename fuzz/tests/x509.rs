@@ -0,0 +1,104 @@
+// The names read from real certificates: openssl's, in RSA and EC and with
+// every kind of alternative name, and a public root's. Cut short anywhere,
+// or with a length DER does not allow, each is an error and not a guess.
+
+use esp32c3_fuzz::x509::{AltName, Certificate, DerError};
+
+const EXAMPLE_COM: &[u8] = include_bytes!("../corpus/x509/example-com.der");
+const RSA_LEAF: &[u8] = include_bytes!("../corpus/x509/rsa-leaf.der");
+const ISRG_ROOT_X1: &[u8] = include_bytes!("../corpus/x509/isrg-root-x1.der");
+const NO_EXTENSIONS: &[u8] = include_bytes!("../corpus/x509/no-extensions.der");
+
+const ALL: [&[u8]; 4] = [EXAMPLE_COM, RSA_LEAF, ISRG_ROOT_X1, NO_EXTENSIONS];
+
+fn names(der: &[u8]) -> (Option<&str>, Vec<AltName<'_>>) {
+    let certificate = Certificate::parse(der).unwrap();
+    (certificate.common_name(), certificate.alt_names().collect())
+}
+
+#[test]
+fn an_ec_certificate() {
+    assert_eq!(
+        names(EXAMPLE_COM),
+        (
+            Some("example.com"),
+            vec![
+                AltName::Dns("example.com"),
+                AltName::Dns("*.example.com"),
+                AltName::Ip(&[192, 0, 2, 1]),
+            ]
+        )
+    );
+}
+
+#[test]
+fn an_rsa_certificate_with_every_kind_of_name() {
+    // The common name after the country and organisation, and in UTF-8;
+    // the email and URI names are the kinds not shown.
+    assert_eq!(
+        names(RSA_LEAF),
+        (
+            Some("sensor.zähler.example"),
+            vec![
+                AltName::Dns("upload.example.net"),
+                AltName::Dns("*.upload.example.net"),
+                AltName::Ip(&[0x20, 0x01, 0x0d, 0xb8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x17]),
+                AltName::Other(0x81),
+                AltName::Other(0x86),
+                AltName::Dns("api.example.net"),
+            ]
+        )
+    );
+}
+
+#[test]
+fn a_root_without_alt_names() {
+    // Let's Encrypt's root: a 4096-bit key, critical extensions, none of
+    // them subjectAltName.
+    assert_eq!(names(ISRG_ROOT_X1), (Some("ISRG Root X1"), vec![]));
+}
+
+#[test]
+fn no_common_name_and_no_extensions() {
+    assert_eq!(names(NO_EXTENSIONS), (None, vec![]));
+}
+
+#[test]
+fn cut_short_anywhere() {
+    for der in ALL {
+        for len in 0..der.len() {
+            assert_eq!(
+                Certificate::parse(&der[..len]),
+                Err(DerError::Malformed),
+                "{len} of {}",
+                der.len()
+            );
+        }
+    }
+}
+
+#[test]
+fn lengths_der_does_not_allow() {
+    for der in ALL {
+        // The outer SEQUENCE's length, indefinite or in five bytes.
+        for first in [0x80, 0x85] {
+            let mut der = der.to_vec();
+            der[1] = first;
+            assert_eq!(Certificate::parse(&der), Err(DerError::Unsupported));
+        }
+    }
+}
+
+#[test]
+fn a_name_cut_inside_the_alt_names_ends_them() {
+    // The last DNS name's length, made to run past the extension: the
+    // names before it are still listed.
+    let mut der = RSA_LEAF.to_vec();
+    let name = b"api.example.net";
+    let at = der.windows(name.len()).position(|w| w == name).unwrap();
+    assert_eq!(der[at - 2..at], [0x82, name.len() as u8]);
+    der[at - 1] += 1;
+    let certificate = Certificate::parse(&der).unwrap();
+    assert_eq!(certificate.alt_names().count(), 5);
+    assert_eq!(certificate.common_name(), Some("sensor.zähler.example"));
+}
@@ -0,0 +1,467 @@
+// REST API over src/httpd.rs:
+//
+//     GET  /api/v1/status   uptime, signal, address, firmware, readings
+//     GET  /api/v1/buildinfo  what the firmware was built from (src/buildinfo.rs)
+//     GET  /api/v1/config   the running settings
+//     GET  /api/v1/logs     the newest log entries, as text or MessagePack (src/logring.rs)
+//     GET  /api/v1/tasks    the tasks by name, with their stack use (src/canary.rs)
+//     PUT  /api/v1/config   new settings, as a JSON object
+//     POST /api/v1/reboot   restart
+//     POST /api/v1/ota      {"url":"https://..."}: update from that image
+//     POST /api/v1/tlsinfo  a fresh handshake with the upload server (src/tlsinfo.rs)
+//
+// Replies other than `logs`' are JSON; errors are `{"error":"..."}`. `status` has the fields of
+// a telemetry reading, `rssi` (dBm) and `ip` (both `null` when unknown),
+// `last_acked_seq` once the upload server has sent one (src/sequence.rs),
+// and `firmware`:
+//
+//     {"uptime_ms":81230,"uptime_total_ms":3681230,"config_revision":7,
+//      "battery_mv":3912,"charging":false,"rssi":-58,"ip":"192.168.1.40",
+//      "firmware":"0.1.0"}
+//
+// The write endpoints (PUT and POST) need HTTP Basic auth with the
+// API_USER and API_PASSWORD the firmware was built with, and are refused
+// with 403 if it was built without them. The credentials are compared in
+// constant time. After `MAX_FAILURES` wrong ones within
+// `FAILURE_WINDOW_S`, writes get 429 with `Retry-After` until the window
+// has passed; a request without credentials does not count.
+//
+// `config` uses the keys of the settings document (src/settings.rs); values
+// are JSON strings, or numbers and booleans where the setting is one. A PUT
+// may leave keys out to keep their running values, and without a
+// `revision` it takes the next one. The result is staged as if the config
+// server had sent it: it takes effect inside the maintenance window and is
+// persisted once an upload under it has gone through. The 202 reply lists
+// what changed.
+//
+// `reboot` and `ota` answer 202 first and act once the reply is out; a
+// reboot goes through `restart::shut_down`. An update outside the
+// maintenance window is deferred, as from the console.
+
+use core::fmt::{self, Write};
+use core::sync::atomic::{AtomicU32, AtomicU8, Ordering};
+
+use embassy_time::Duration;
+use esp_println::println;
+use heapless::{String, Vec};
+
+use crate::buildinfo;
+use crate::canary;
+use crate::clock::{self, Clock};
+use crate::codec;
+use crate::httpd::{Handler, Reply, Request};
+use crate::https;
+use crate::integrity;
+use crate::json;
+use crate::logring;
+use crate::monotime;
+use crate::power;
+use crate::restart::{self, Reason};
+use crate::sequence;
+use crate::settings::{
+    self, Changes, MergeError, Settings, SettingsError, MAX_DOCUMENT_LEN, MAX_URL_LEN,
+};
+use crate::station;
+use crate::tlsinfo;
+use crate::NetStack;
+
+const USER: Option<&str> = option_env!("API_USER");
+const PASSWORD: Option<&str> = option_env!("API_PASSWORD");
+
+/// Longest `user:password` accepted.
+const MAX_CREDENTIALS_LEN: usize = 128;
+
+/// Settings keys a PUT may carry, i.e. what `Settings::write_to` writes.
+const MAX_FIELDS: usize = 16;
+
+const MAX_FAILURES: u8 = 5;
+const FAILURE_WINDOW_S: u32 = 60;
+
+/// Wrong credentials in the current window, and when that window began,
+/// in seconds since boot.
+static FAILURES: AtomicU8 = AtomicU8::new(0);
+static WINDOW_START_S: AtomicU32 = AtomicU32::new(0);
+
+/// Longest a reboot waits for work in flight.
+const REBOOT_SETTLE: Duration = Duration::from_secs(30);
+
+/// What to do once the reply has been sent.
+pub enum Action {
+    None,
+    Reboot,
+    #[cfg(feature = "ota")]
+    Update(String<MAX_URL_LEN>),
+}
+
+pub struct Api {
+    stack: &'static NetStack,
+}
+
+impl Api {
+    pub fn new(stack: &'static NetStack) -> Self {
+        Self { stack }
+    }
+}
+
+impl Handler for Api {
+    async fn handle(&mut self, request: &Request<'_>, reply: &mut Reply) -> Action {
+        handle(self.stack, request, reply).await
+    }
+}
+
+async fn handle(stack: &'static NetStack, request: &Request<'_>, reply: &mut Reply) -> Action {
+    let allow = match request.path {
+        "/api/v1/status" | "/api/v1/buildinfo" | "/api/v1/logs" | "/api/v1/tasks" => "GET",
+        "/api/v1/config" => "GET, PUT",
+        "/api/v1/reboot" | "/api/v1/ota" | "/api/v1/tlsinfo" => "POST",
+        _ => {
+            reply.error(404, "no such endpoint");
+            return Action::None;
+        }
+    };
+    let write = matches!(request.method, "PUT" | "POST");
+    if write && allow.contains(request.method) {
+        if let Err((status, message)) = authorize(request, &clock::System) {
+            reply.error(status, message);
+            if status == 429 {
+                let mut retry_after: String<8> = String::new();
+                let _ = write!(retry_after, "{}", FAILURE_WINDOW_S);
+                let _ = reply.add_header("Retry-After", &retry_after);
+            }
+            return Action::None;
+        }
+    }
+    match (request.method, request.path) {
+        ("GET", "/api/v1/status") => status(stack, reply).await,
+        ("GET", "/api/v1/buildinfo") => {
+            // Cannot overflow: `JSON` is far shorter than a reply.
+            let _ = reply.body.push_str(buildinfo::JSON);
+        }
+        ("GET", "/api/v1/config") => config(reply),
+        ("GET", "/api/v1/logs") => logs(request, reply).await,
+        ("GET", "/api/v1/tasks") => tasks(reply),
+        ("PUT", "/api/v1/config") => put_config(request.body, reply),
+        ("POST", "/api/v1/reboot") => {
+            reply.status = 202;
+            let _ = reply.body.push_str("{\"rebooting\":true}");
+            return Action::Reboot;
+        }
+        ("POST", "/api/v1/ota") => return update(request.body, reply),
+        ("POST", "/api/v1/tlsinfo") => tls_info(stack, reply).await,
+        _ => {
+            reply.error(405, "method not allowed");
+            let _ = reply.add_header("Allow", allow);
+        }
+    }
+    Action::None
+}
+
+/// Handshakes with the upload server; the reply waits for it.
+async fn tls_info(stack: &'static NetStack, reply: &mut Reply) {
+    let url = settings::current().upload_url;
+    let result = tlsinfo::run(stack, &url).await;
+    // Cannot overflow: a host name and a few numbers.
+    let _ = tlsinfo::write_json(&mut reply.body, &url, result.as_ref());
+}
+
+/// Carries out what `handle` left for after the reply.
+pub async fn finish(stack: &'static NetStack, action: Action) {
+    match action {
+        Action::None => {}
+        Action::Reboot => restart::shut_down(Reason::Requested, REBOOT_SETTLE).await,
+        #[cfg(feature = "ota")]
+        Action::Update(url) => crate::ota::update(stack, &url).await,
+    }
+    // Only updates use the stack.
+    let _ = stack;
+}
+
+fn authorize(request: &Request<'_>, clock: &impl Clock) -> Result<(), (u16, &'static str)> {
+    let (Some(user), Some(password)) = (USER, PASSWORD) else {
+        return Err((403, "writes are disabled in this build"));
+    };
+    let denied = (401, "credentials required");
+    let now_s = clock.now_s() as u32;
+    if is_limited(now_s) {
+        return Err((429, "too many failed attempts"));
+    }
+    let header = request.header("Authorization").ok_or(denied)?;
+    check_credentials(header, user, password).map_err(|()| {
+        record_failure(now_s);
+        denied
+    })
+}
+
+fn check_credentials(header: &str, user: &str, password: &str) -> Result<(), ()> {
+    let (scheme, encoded) = header.split_once(' ').ok_or(())?;
+    if !scheme.eq_ignore_ascii_case("Basic") {
+        return Err(());
+    }
+    let mut buf = [0u8; MAX_CREDENTIALS_LEN];
+    let given = codec::STANDARD
+        .decode(encoded.trim().as_bytes(), &mut buf)
+        .map_err(|_| ())?;
+    let mut expected: String<MAX_CREDENTIALS_LEN> = String::new();
+    write!(expected, "{}:{}", user, password).map_err(|_| ())?;
+    if !integrity::constant_time_eq(given, expected.as_bytes()) {
+        return Err(());
+    }
+    Ok(())
+}
+
+fn is_limited(now_s: u32) -> bool {
+    let start = WINDOW_START_S.load(Ordering::Relaxed);
+    FAILURES.load(Ordering::Relaxed) >= MAX_FAILURES && now_s.wrapping_sub(start) < FAILURE_WINDOW_S
+}
+
+/// Only the httpd task calls this, so load and store cannot race.
+fn record_failure(now_s: u32) {
+    let start = WINDOW_START_S.load(Ordering::Relaxed);
+    let failures = if now_s.wrapping_sub(start) >= FAILURE_WINDOW_S {
+        WINDOW_START_S.store(now_s, Ordering::Relaxed);
+        1
+    } else {
+        FAILURES.load(Ordering::Relaxed).saturating_add(1)
+    };
+    FAILURES.store(failures, Ordering::Relaxed);
+    if failures == MAX_FAILURES {
+        println!(
+            "api: {} failed logins, refusing writes for {} s",
+            MAX_FAILURES, FAILURE_WINDOW_S
+        );
+    }
+}
+
+/// `{"uploader":{"stack":1840,"left":9000,"done":false},...}`, in the order
+/// the tasks first ran: the most bytes of stack a poll used and the least
+/// left below it. Terse, so that all of `canary::MAX_TASKS` fit a reply.
+fn tasks(reply: &mut Reply) {
+    let out = &mut reply.body;
+    let _ = out.push('{');
+    for (i, task) in canary::tasks().iter().enumerate() {
+        let _ = write!(
+            out,
+            "{}\"{}\":{{\"stack\":{},\"left\":{},\"done\":{}}}",
+            if i > 0 { "," } else { "" },
+            task.name,
+            task.deepest,
+            task.headroom,
+            task.done
+        );
+    }
+    let _ = out.push('}');
+}
+
+async fn status(stack: &'static NetStack, reply: &mut Reply) {
+    let settings = settings::current();
+    let mut controller = station::lock().await;
+    let rssi = match controller.as_mut() {
+        Some(controller) => station::rssi(controller, crate::SSID).await,
+        None => None,
+    };
+    drop(controller);
+
+    let out = &mut reply.body;
+    // Cannot overflow: a few numbers and fixed text.
+    let _ = write!(
+        out,
+        "{{\"uptime_ms\":{},\"uptime_total_ms\":{},\"config_revision\":{}",
+        monotime::since_boot(),
+        monotime::uptime_total(),
+        settings.revision
+    );
+    if let Some(reason) = restart::last() {
+        let _ = write!(out, ",\"reset\":\"{}\"", reason.as_str());
+        if let Reason::WifiStuck(resets) = reason {
+            let _ = write!(out, ",\"wifi_resets\":{}", resets);
+        }
+    }
+    if let Some(status) = power::status() {
+        let _ = write!(
+            out,
+            ",\"battery_mv\":{},\"charging\":{}",
+            status.battery_mv, status.charging
+        );
+    }
+    let _ = match rssi {
+        Some(rssi) => write!(out, ",\"rssi\":{}", rssi),
+        None => out.write_str(",\"rssi\":null"),
+    };
+    let _ = match stack.config_v4() {
+        Some(config) => write!(out, ",\"ip\":\"{}\"", config.address.address()),
+        None => out.write_str(",\"ip\":null"),
+    };
+    if let Some(seq) = sequence::last_acked() {
+        let _ = write!(out, ",\"last_acked_seq\":{}", seq);
+    }
+    let _ = write!(out, ",\"firmware\":\"{}\"}}", env!("CARGO_PKG_VERSION"));
+}
+
+async fn logs(request: &Request<'_>, reply: &mut Reply) {
+    let msgpack = request.header("Accept").is_some_and(|accept| {
+        accept.split(',').any(|range| {
+            let media_type = range.split(';').next().unwrap_or("").trim();
+            media_type.eq_ignore_ascii_case("application/msgpack")
+                || media_type.eq_ignore_ascii_case("application/vnd.msgpack")
+        })
+    });
+    let logs = logring::snapshot(msgpack).await;
+    reply.content_type = logs.content_type();
+    reply.logs = Some(logs);
+    let (text, binary) = logring::encoded_bytes();
+    let mut value: String<10> = String::new();
+    for (name, bytes) in [("X-Log-Text-Bytes", text), ("X-Log-Binary-Bytes", binary)] {
+        value.clear();
+        let _ = write!(value, "{}", bytes);
+        let _ = reply.add_header(name, &value);
+    }
+}
+
+fn config(reply: &mut Reply) {
+    let mut document: String<MAX_DOCUMENT_LEN> = String::new();
+    if settings::current().write_to(&mut document).is_err()
+        || write_config(&mut reply.body, &document).is_err()
+    {
+        reply.error(500, "settings do not fit the reply");
+    }
+}
+
+/// The `key=value` lines of a settings document as one JSON object.
+fn write_config<W: Write>(out: &mut W, document: &str) -> fmt::Result {
+    out.write_char('{')?;
+    for (i, (key, value)) in document
+        .lines()
+        .filter_map(|line| line.split_once('='))
+        .enumerate()
+    {
+        if i > 0 {
+            out.write_char(',')?;
+        }
+        codec::write_json_str(out, key)?;
+        out.write_char(':')?;
+        let bare = matches!(value, "true" | "false")
+            || (!value.is_empty() && value.bytes().all(|b| b.is_ascii_digit()));
+        if bare {
+            out.write_str(value)?;
+        } else {
+            codec::write_json_str(out, value)?;
+        }
+    }
+    out.write_char('}')
+}
+
+fn put_config(body: &[u8], reply: &mut Reply) {
+    let mut fields: Vec<(&str, String<MAX_URL_LEN>), MAX_FIELDS> = Vec::new();
+    let parsed = json::fields(body, |key, value| {
+        if fields.iter().any(|(k, _)| *k == key) {
+            return Err("duplicate key");
+        }
+        fields.push((key, value)).map_err(|_| "too many keys")
+    });
+    if let Err(message) = parsed {
+        reply.error(400, message);
+        return;
+    }
+    if fields.is_empty() {
+        reply.error(400, "no settings given");
+        return;
+    }
+
+    let fields: Vec<(&str, &str), MAX_FIELDS> = fields
+        .iter()
+        .map(|(key, value)| (*key, value.as_str()))
+        .collect();
+    // A PUT without a revision is simply the next one.
+    let document = match settings::merged(&fields) {
+        Ok(document) => document,
+        Err(e) => {
+            match e {
+                MergeError::UnknownKey => reply.error(400, "unknown key"),
+                MergeError::MultiLine => reply.error(400, "values must be one line"),
+                MergeError::TooLong => reply.error(413, "settings too long"),
+            }
+            return;
+        }
+    };
+
+    match settings::stage(&document) {
+        Ok(changes) => {
+            println!("api: settings staged: {:?}", changes);
+            reply.status = 202;
+            let revision = Settings::parse(&document).map_or(0, |s| s.revision);
+            let _ = write_changes(&mut reply.body, revision, &changes);
+        }
+        Err(SettingsError::Missing(key)) | Err(SettingsError::Invalid(key)) => {
+            reply.status = 400;
+            let _ = write!(
+                reply.body,
+                "{{\"error\":\"invalid value\",\"key\":\"{}\"}}",
+                key
+            );
+        }
+        Err(SettingsError::Incompatible(message)) => reply.error(400, message),
+        Err(SettingsError::StaleRevision(_)) => reply.error(409, "revision is not newer"),
+    }
+}
+
+fn write_changes<W: Write>(out: &mut W, revision: u32, changes: &Changes) -> fmt::Result {
+    write!(out, "{{\"revision\":{},\"changed\":[", revision)?;
+    let groups = [
+        ("interval", changes.interval),
+        ("endpoints", changes.endpoints),
+        ("log_level", changes.log_level),
+        ("log_format", changes.log_format),
+        ("quiet_hours", changes.quiet_hours),
+        ("maintenance", changes.maintenance),
+        ("batch", changes.batch),
+        ("allow_downgrade", changes.allow_downgrade),
+        ("reboot_at", changes.reboot_at),
+        ("wifi", changes.wifi),
+        ("headers", changes.headers),
+        ("labels", changes.labels),
+        ("compress", changes.compress),
+        ("schema", changes.schema),
+    ];
+    let mut first = true;
+    for (name, changed) in groups {
+        if changed {
+            write!(out, "{}\"{}\"", if first { "" } else { "," }, name)?;
+            first = false;
+        }
+    }
+    out.write_str("]}")
+}
+
+fn update(body: &[u8], reply: &mut Reply) -> Action {
+    let mut url: Option<String<MAX_URL_LEN>> = None;
+    let parsed = json::fields(body, |key, value| match key {
+        "url" if url.is_none() => {
+            url = Some(value);
+            Ok(())
+        }
+        "url" => Err("duplicate key"),
+        _ => Err("unknown key"),
+    });
+    if let Err(message) = parsed {
+        reply.error(400, message);
+        return Action::None;
+    }
+    let Some(url) = url.filter(|url| https::parse_url(url).is_some()) else {
+        reply.error(400, "an https url is required");
+        return Action::None;
+    };
+    #[cfg(feature = "ota")]
+    {
+        reply.status = 202;
+        let _ = reply.body.write_str("{\"url\":");
+        let _ = codec::write_json_str(&mut reply.body, &url);
+        let _ = reply.body.write_char('}');
+        Action::Update(url)
+    }
+    #[cfg(not(feature = "ota"))]
+    {
+        let _ = url;
+        reply.error(501, "built without ota");
+        Action::None
+    }
+}
@@ -0,0 +1,196 @@
+// Telemetry batching: readings wait in the uploader's queue and go out
+// together, as one POST, once the batch reaches its count or size limit or
+// its oldest reading its maximum age, whichever comes first.
+//
+// The decisions here are pure and take the queue's contents and the time as
+// arguments; the uploader owns the queue and the clock. A batch leaves the
+// queue only once the server has accepted it or refused it for good
+// (src/uploadanswer.rs), so a failed flush leaves it where it was and the
+// next flush sends the same readings again.
+//
+// A batch is either NDJSON (one reading per line) or a JSON array, under
+// `batch=<format>,<count>,<bytes>,<age_s>` in the settings.
+
+use core::cell::Cell;
+use core::fmt;
+
+use critical_section::Mutex;
+
+/// Most readings one batch can hold; also the length of the uploader's
+/// queue.
+pub const MAX_BATCH_COUNT: usize = 16;
+/// Largest `max_bytes` a policy may ask for.
+pub const MAX_BATCH_BYTES: usize = 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BatchFormat {
+    Ndjson,
+    JsonArray,
+}
+
+impl BatchFormat {
+    pub fn content_type(self) -> &'static str {
+        match self {
+            BatchFormat::Ndjson => "application/x-ndjson",
+            BatchFormat::JsonArray => "application/json",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BatchPolicy {
+    pub format: BatchFormat,
+    /// 1 to `MAX_BATCH_COUNT`.
+    pub max_count: u16,
+    /// Serialized size of a batch; a single larger reading goes out alone.
+    pub max_bytes: u16,
+    pub max_age_s: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FlushReason {
+    Count,
+    Size,
+    Age,
+}
+
+impl BatchPolicy {
+    /// Parses `<ndjson|array>,<count>,<bytes>,<age_s>`.
+    pub fn parse(text: &str) -> Option<Self> {
+        let mut fields = text.split(',').map(str::trim);
+        let format = match fields.next()? {
+            "ndjson" => BatchFormat::Ndjson,
+            "array" => BatchFormat::JsonArray,
+            _ => return None,
+        };
+        let policy = Self {
+            format,
+            max_count: fields.next()?.parse().ok()?,
+            max_bytes: fields.next()?.parse().ok()?,
+            max_age_s: fields.next()?.parse().ok()?,
+        };
+        let valid = fields.next().is_none()
+            && (1..=MAX_BATCH_COUNT).contains(&(policy.max_count as usize))
+            && (1..=MAX_BATCH_BYTES).contains(&(policy.max_bytes as usize));
+        valid.then_some(policy)
+    }
+
+    /// Why `count` queued readings, `bytes` long as a batch (`encoded_len`),
+    /// should go out, if they should. `oldest_age_ms` is how long the first
+    /// of them has been waiting.
+    pub fn flush_reason(
+        &self,
+        count: usize,
+        bytes: usize,
+        oldest_age_ms: u64,
+    ) -> Option<FlushReason> {
+        if count == 0 {
+            None
+        } else if count >= self.max_count as usize {
+            Some(FlushReason::Count)
+        } else if bytes >= self.max_bytes as usize {
+            Some(FlushReason::Size)
+        } else if oldest_age_ms >= self.max_age_s as u64 * 1000 {
+            Some(FlushReason::Age)
+        } else {
+            None
+        }
+    }
+
+    /// How many of the oldest readings go into the next batch: as many as fit
+    /// both limits, but at least one.
+    pub fn batch_len(&self, lens: impl Iterator<Item = usize>) -> usize {
+        let mut count = 0;
+        let mut payload = 0;
+        for len in lens.take(self.max_count as usize) {
+            payload += len;
+            if count > 0 && framed_len(self.format, count + 1, payload) > self.max_bytes as usize {
+                break;
+            }
+            count += 1;
+        }
+        count
+    }
+}
+
+impl fmt::Display for BatchPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let format = match self.format {
+            BatchFormat::Ndjson => "ndjson",
+            BatchFormat::JsonArray => "array",
+        };
+        write!(
+            f,
+            "{},{},{},{}",
+            format, self.max_count, self.max_bytes, self.max_age_s
+        )
+    }
+}
+
+/// Serialized size of a batch of readings with the given lengths.
+pub fn encoded_len(format: BatchFormat, lens: impl Iterator<Item = usize>) -> usize {
+    let (count, payload) = lens.fold((0, 0), |(count, payload), len| (count + 1, payload + len));
+    if count == 0 {
+        0
+    } else {
+        framed_len(format, count, payload)
+    }
+}
+
+/// `payload` bytes of `count` readings plus what the format puts around
+/// them: a newline each, or brackets and commas.
+fn framed_len(format: BatchFormat, count: usize, payload: usize) -> usize {
+    match format {
+        BatchFormat::Ndjson => payload + count,
+        BatchFormat::JsonArray => payload + 2 + (count - 1),
+    }
+}
+
+/// Delivered batches since boot, to tune the policy by.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct BatchStats {
+    pub flushes: u32,
+    pub readings: u32,
+    pub by_count: u32,
+    pub by_size: u32,
+    pub by_age: u32,
+}
+
+impl BatchStats {
+    /// Average batch size in tenths of a reading.
+    pub fn average_tenths(&self) -> u32 {
+        match self.flushes {
+            0 => 0,
+            flushes => (self.readings as u64 * 10 / flushes as u64) as u32,
+        }
+    }
+}
+
+static STATS: Mutex<Cell<BatchStats>> = Mutex::new(Cell::new(BatchStats {
+    flushes: 0,
+    readings: 0,
+    by_count: 0,
+    by_size: 0,
+    by_age: 0,
+}));
+
+/// Counts a batch of `readings` the server accepted.
+pub fn record_flush(reason: FlushReason, readings: usize) {
+    critical_section::with(|cs| {
+        let cell = STATS.borrow(cs);
+        let mut stats = cell.get();
+        stats.flushes = stats.flushes.saturating_add(1);
+        stats.readings = stats.readings.saturating_add(readings as u32);
+        let by_reason = match reason {
+            FlushReason::Count => &mut stats.by_count,
+            FlushReason::Size => &mut stats.by_size,
+            FlushReason::Age => &mut stats.by_age,
+        };
+        *by_reason = by_reason.saturating_add(1);
+        cell.set(stats);
+    });
+}
+
+pub fn stats() -> BatchStats {
+    critical_section::with(|cs| STATS.borrow(cs).get())
+}
@@ -0,0 +1,133 @@
+// A UDP broadcast every 30 s that says a device is here, for an installer to
+// find one that has just been powered up on site and has no server yet:
+//
+//     "C3BN" | format (1) | flags | major | minor | patch | 0 | IPv4 address | device fingerprint (32 bytes)
+//
+// 48 bytes, to the subnet's broadcast address and BEACON_PORT at build time,
+// 47474 without. Flag bit 0 is set once the device is provisioned, bit 1
+// while the console asked for it. tools/beacon-listen.sh lists what arrives.
+//
+// Provisioned means the settings have an upload URL or a config URL: a
+// server to hear from it. Until then it beacons by itself. The settings are
+// looked at before every beacon, so none goes out once either is set.
+// `beacon on` makes it beacon anyway, to find a device that is already
+// provisioned; that lasts until `beacon off`, a reset, or provisioning
+// completing while it is on.
+//
+// It carries nothing from the settings or the Wi-Fi and EAP credentials:
+// the fingerprint (src/deviceid.rs) is no secret, and the address and the
+// version are what the network and any update server already see. Nothing
+// is listened for in return.
+
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use embassy_futures::select::select;
+use embassy_net::udp::{PacketMetadata, UdpSocket};
+use embassy_net::Ipv4Address;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::signal::Signal;
+use embassy_time::{Duration, Timer as EmbassyTimer};
+use esp_println::println;
+
+use crate::buildinfo;
+use crate::canary;
+use crate::deviceid;
+use crate::integrity::HASH_LEN;
+use crate::settings::{self, Settings};
+use crate::NetStack;
+
+const MAGIC: &[u8; 4] = b"C3BN";
+const FORMAT: u8 = 1;
+// magic | format | flags | version | 0 | address | fingerprint
+const LEN: usize = 16 + HASH_LEN;
+
+const INTERVAL: Duration = Duration::from_secs(30);
+const DEFAULT_PORT: u16 = 47474;
+const PORT: Option<&str> = option_env!("BEACON_PORT");
+
+const PROVISIONED: u8 = 1;
+const ASKED: u8 = 2;
+
+static ASKED_FOR: AtomicBool = AtomicBool::new(false);
+static CHANGED: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+/// Whether `settings` give the device a server.
+fn is_provisioned(settings: &Settings) -> bool {
+    !settings.upload_url.is_empty() || !settings.config_url.is_empty()
+}
+
+/// Beacons even when provisioned, or only when not; takes effect at once.
+#[cfg(feature = "console")]
+pub fn ask(on: bool) {
+    ASKED_FOR.store(on, Ordering::Relaxed);
+    CHANGED.signal(());
+}
+
+pub fn is_asked() -> bool {
+    ASKED_FOR.load(Ordering::Relaxed)
+}
+
+fn encode(flags: u8, addr: Ipv4Address, fingerprint: &[u8; HASH_LEN]) -> [u8; LEN] {
+    let mut raw = [0u8; LEN];
+    raw[0..4].copy_from_slice(MAGIC);
+    raw[4] = FORMAT;
+    raw[5] = flags;
+    raw[6..9].copy_from_slice(&buildinfo::VERSION);
+    raw[12..16].copy_from_slice(addr.as_bytes());
+    raw[16..].copy_from_slice(fingerprint);
+    raw
+}
+
+fn port() -> u16 {
+    PORT.and_then(|port| port.parse().ok())
+        .unwrap_or(DEFAULT_PORT)
+}
+
+#[embassy_executor::task]
+pub async fn beacon_task(stack: &'static NetStack) {
+    canary::tracked("beacon", run(stack)).await
+}
+
+async fn run(stack: &'static NetStack) -> ! {
+    let mut rx_meta = [PacketMetadata::EMPTY; 1];
+    let mut tx_meta = [PacketMetadata::EMPTY; 1];
+    let mut rx_buf = [0u8; 0];
+    let mut tx_buf = [0u8; LEN];
+    let mut socket = UdpSocket::new(stack, &mut rx_meta, &mut rx_buf, &mut tx_meta, &mut tx_buf);
+    if let Err(e) = socket.bind(0) {
+        println!("beacon: cannot bind: {:?}", e);
+    }
+
+    let mut was_provisioned = is_provisioned(&settings::current());
+    let mut on = false;
+    loop {
+        let provisioned = is_provisioned(&settings::current());
+        if provisioned && !was_provisioned {
+            ASKED_FOR.store(false, Ordering::Relaxed);
+        }
+        was_provisioned = provisioned;
+        let asked = is_asked();
+        if on != (asked || !provisioned) {
+            on = !on;
+            if on {
+                println!("beacon: broadcasting to port {} every 30 s", port());
+            } else {
+                println!("beacon: stopped");
+            }
+        }
+        // Without an address there is nothing to say, nor a subnet.
+        if let (true, Some(config)) = (on, stack.config_v4()) {
+            let flags = if provisioned { PROVISIONED } else { 0 } | if asked { ASKED } else { 0 };
+            let packet = encode(
+                flags,
+                config.address.address(),
+                &deviceid::device_fingerprint(),
+            );
+            let to = config.address.broadcast().unwrap_or(Ipv4Address::BROADCAST);
+            if let Err(e) = socket.send_to(&packet, (to, port())).await {
+                println!("beacon: cannot send: {:?}", e);
+            }
+        }
+        select(EmbassyTimer::after(INTERVAL), CHANGED.wait()).await;
+    }
+}
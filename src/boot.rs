@@ -0,0 +1,413 @@
+// A/B firmware slots and the confirm-or-roll-back state machine around them.
+//
+// The second-stage bootloader starts whichever of `ota_0` and `ota_1` the
+// `otadata` partition selects. Each of its two sectors may hold an entry with
+// a sequence number; the valid entry with the highest one wins, and sequence
+// `n` selects slot `(n - 1) % 2`. A switch writes a new entry to the sector
+// not holding the current one, so until that single write is complete the
+// old selection still stands.
+//
+// Every boot also hashes the running image (`integrity`); a pending image
+// that fails is rolled back straight away rather than given its trial.
+//
+// The stock bootloader is not built with rollback support, so the firmware
+// tracks trials itself under `boot.state`:
+//
+// - `mark_update_pending` selects the new slot and records `Pending`.
+// - The first boot of a pending image arms the RTC watchdog for
+//   `CONFIRM_TIMEOUT`. `mark_update_success` disarms it and records
+//   `Confirmed`.
+// - Any later boot that still finds `Pending`, whether after the watchdog,
+//   a panic or a power cut, calls `rollback_to_previous` and restarts into the
+//   old slot, leaving `Rollback` behind for the status output.
+
+use core::cell::{Cell, RefCell};
+
+use critical_section::Mutex;
+use embassy_time::Duration;
+use embedded_storage::nor_flash::ReadNorFlash;
+use esp_hal::prelude::_fugit_ExtU64;
+use esp_hal::rtc_cntl::Rwdt;
+use esp_println::println;
+use esp_storage::{FlashStorage, FlashStorageError};
+
+use crate::flash;
+use crate::integrity::{self, IntegrityError};
+use crate::kv::{self, KvError};
+use crate::partition::{self, PartitionEntry};
+
+/// How long a freshly updated image has to call `mark_update_success`. A
+/// `dev` build allows for stopping in a debugger on the way.
+pub const CONFIRM_TIMEOUT: Duration = if cfg!(feature = "dev") {
+    Duration::from_secs(30 * 60)
+} else {
+    Duration::from_secs(5 * 60)
+};
+
+const KEY_STATE: &str = "boot.state";
+
+// Offsets from partitions.csv, for when the partition table is unreadable.
+const OTADATA_START: u32 = 0xD000;
+const OTA_0_START: u32 = 0x10000;
+const OTA_1_START: u32 = 0x190000;
+
+const ENTRY_LEN: usize = 32;
+const EMPTY_SEQUENCE: u32 = u32::MAX;
+// `ota_state` as the bootloader's rollback support would use it; left
+// undefined since that support is off.
+const OTA_STATE_UNDEFINED: u32 = u32::MAX;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FlashPartition {
+    Ota0,
+    Ota1,
+}
+
+impl FlashPartition {
+    /// Where the slot starts according to the partition table.
+    pub fn offset(self) -> u32 {
+        let fallback = match self {
+            FlashPartition::Ota0 => OTA_0_START,
+            FlashPartition::Ota1 => OTA_1_START,
+        };
+        self.entry().map_or(fallback, |slot| slot.offset)
+    }
+
+    /// The slot's entry in the partition table, if the table can be read.
+    pub fn entry(self) -> Option<PartitionEntry> {
+        partition::find_ota_partitions()
+            .get(self.index() as usize)
+            .cloned()
+    }
+
+    /// The slot an update to a board running this one goes to.
+    pub fn other(self) -> Self {
+        match self {
+            FlashPartition::Ota0 => FlashPartition::Ota1,
+            FlashPartition::Ota1 => FlashPartition::Ota0,
+        }
+    }
+
+    fn index(self) -> u32 {
+        match self {
+            FlashPartition::Ota0 => 0,
+            FlashPartition::Ota1 => 1,
+        }
+    }
+
+    fn from_sequence(sequence: u32) -> Self {
+        if (sequence - 1) % 2 == 0 {
+            FlashPartition::Ota0
+        } else {
+            FlashPartition::Ota1
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BootState {
+    /// Switched to `BootRecord::partition`, not confirmed yet.
+    Pending,
+    Confirmed,
+    /// The update never confirmed and the previous image runs again.
+    Rollback,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BootRecord {
+    pub state: BootState,
+    /// The slot the update went to.
+    pub partition: FlashPartition,
+    /// The slot that was running before it.
+    pub previous: FlashPartition,
+    /// Boots of the pending image so far.
+    pub boots: u8,
+}
+
+#[derive(Debug)]
+pub enum BootError {
+    Kv(#[allow(dead_code)] KvError),
+    Flash(#[allow(dead_code)] FlashStorageError),
+    /// No update is pending.
+    NotPending,
+}
+
+impl From<KvError> for BootError {
+    fn from(e: KvError) -> Self {
+        BootError::Kv(e)
+    }
+}
+
+impl From<FlashStorageError> for BootError {
+    fn from(e: FlashStorageError) -> Self {
+        BootError::Flash(e)
+    }
+}
+
+/// Armed while a pending image waits for confirmation.
+static WATCHDOG: Mutex<RefCell<Option<Rwdt>>> = Mutex::new(RefCell::new(None));
+static AWAITING_CONFIRM: Mutex<Cell<bool>> = Mutex::new(Cell::new(false));
+
+/// Call early at boot, before anything that might hang.
+pub async fn check(mut rwdt: Rwdt) {
+    let running = match selected_partition() {
+        Ok(Some(slot)) => {
+            println!("boot: otadata selects {:?} at {:#x}", slot, slot.offset());
+            slot
+        }
+        Ok(None) => {
+            println!("boot: otadata selects nothing, running ota_0");
+            FlashPartition::Ota0
+        }
+        Err(e) => {
+            println!("boot: otadata unreadable: {:?}", e);
+            FlashPartition::Ota0
+        }
+    };
+    let intact = image_intact(running);
+
+    let record = match read_record().await {
+        Ok(Some(record)) => record,
+        Ok(None) => return,
+        Err(e) => {
+            println!("boot: state unreadable: {:?}", e);
+            return;
+        }
+    };
+
+    match record.state {
+        BootState::Pending if !intact => {
+            println!(
+                "boot: {:?} is corrupt, rolling back to {:?}",
+                record.partition, record.previous
+            );
+            roll_back_and_reset().await;
+        }
+        BootState::Pending if record.boots == 0 => {
+            let record = BootRecord { boots: 1, ..record };
+            if let Err(e) = write_record(&record).await {
+                // Without the boot count a crash would not roll back.
+                println!("boot: could not count trial boot: {:?}", e);
+            }
+            rwdt.set_timeout(CONFIRM_TIMEOUT.as_micros().micros());
+            rwdt.enable();
+            critical_section::with(|cs| {
+                WATCHDOG.borrow(cs).replace(Some(rwdt));
+                AWAITING_CONFIRM.borrow(cs).set(true);
+            });
+            println!(
+                "boot: trial of {:?}, confirm within {} s",
+                record.partition,
+                CONFIRM_TIMEOUT.as_secs()
+            );
+        }
+        BootState::Pending => {
+            println!(
+                "boot: {:?} was never confirmed, rolling back to {:?}",
+                record.partition, record.previous
+            );
+            roll_back_and_reset().await;
+        }
+        BootState::Rollback => println!(
+            "boot: running {:?} after a failed update to {:?}",
+            record.previous, record.partition
+        ),
+        BootState::Confirmed => {}
+    }
+}
+
+async fn roll_back_and_reset() {
+    match rollback_to_previous().await {
+        Ok(()) => esp_hal::reset::software_reset(),
+        Err(e) => println!("boot: rollback failed: {:?}", e),
+    }
+}
+
+/// Whether `slot` matches the hash appended to its image. An image that
+/// cannot be checked at all, e.g. one built without a hash, counts as
+/// intact; the reason is printed.
+fn image_intact(slot: FlashPartition) -> bool {
+    let Some(entry) = slot.entry() else {
+        println!("boot: {:?} missing from the partition table", slot);
+        return true;
+    };
+    match integrity::verify_partition_hash(&entry) {
+        Ok(()) => true,
+        Err(
+            e @ (IntegrityError::Mismatch | IntegrityError::NoImage | IntegrityError::Truncated),
+        ) => {
+            println!("boot: {:?} failed its integrity check: {:?}", slot, e);
+            false
+        }
+        Err(e) => {
+            println!("boot: could not check {:?}: {:?}", slot, e);
+            true
+        }
+    }
+}
+
+/// Makes `partition`, which must already hold a complete image, the one
+/// the next reset starts, on trial.
+#[cfg(feature = "ota")]
+pub async fn mark_update_pending(partition: FlashPartition) -> Result<(), BootError> {
+    let previous = selected_partition()?.unwrap_or(FlashPartition::Ota0);
+    select(partition).await?;
+    write_record(&BootRecord {
+        state: BootState::Pending,
+        partition,
+        previous,
+        boots: 0,
+    })
+    .await?;
+    Ok(())
+}
+
+/// Keeps the running image for good. Cheap to call when nothing is pending.
+pub async fn mark_update_success() -> Result<(), BootError> {
+    if !critical_section::with(|cs| AWAITING_CONFIRM.borrow(cs).get()) {
+        return Ok(());
+    }
+    let Some(record) = read_record().await? else {
+        return Err(BootError::NotPending);
+    };
+    write_record(&BootRecord {
+        state: BootState::Confirmed,
+        ..record
+    })
+    .await?;
+    critical_section::with(|cs| {
+        AWAITING_CONFIRM.borrow(cs).set(false);
+        if let Some(mut rwdt) = WATCHDOG.borrow(cs).take() {
+            rwdt.disable();
+        }
+    });
+    println!("boot: {:?} confirmed", record.partition);
+    Ok(())
+}
+
+/// Selects the slot that ran before the pending update. Takes effect on the
+/// next reset, which is up to the caller.
+pub async fn rollback_to_previous() -> Result<(), BootError> {
+    let record = match read_record().await? {
+        Some(record) if record.state == BootState::Pending => record,
+        _ => return Err(BootError::NotPending),
+    };
+    select(record.previous).await?;
+    write_record(&BootRecord {
+        state: BootState::Rollback,
+        ..record
+    })
+    .await?;
+    Ok(())
+}
+
+/// The last recorded update, if there has been one.
+pub async fn read_record() -> Result<Option<BootRecord>, KvError> {
+    let mut raw = [0u8; 4];
+    let Some(4) = kv::get(KEY_STATE, &mut raw).await? else {
+        return Ok(None);
+    };
+    let state = match raw[0] {
+        0 => BootState::Pending,
+        1 => BootState::Confirmed,
+        2 => BootState::Rollback,
+        _ => return Ok(None),
+    };
+    let partition = |b: u8| {
+        if b == 0 {
+            FlashPartition::Ota0
+        } else {
+            FlashPartition::Ota1
+        }
+    };
+    Ok(Some(BootRecord {
+        state,
+        partition: partition(raw[1]),
+        previous: partition(raw[2]),
+        boots: raw[3],
+    }))
+}
+
+async fn write_record(record: &BootRecord) -> Result<(), KvError> {
+    let state = match record.state {
+        BootState::Pending => 0,
+        BootState::Confirmed => 1,
+        BootState::Rollback => 2,
+    };
+    let raw = [
+        state,
+        record.partition.index() as u8,
+        record.previous.index() as u8,
+        record.boots,
+    ];
+    kv::set(KEY_STATE, &raw).await
+}
+
+/// The slot `otadata` currently selects, or `None` if it selects nothing,
+/// in which case the bootloader starts `ota_0`.
+pub fn selected_partition() -> Result<Option<FlashPartition>, FlashStorageError> {
+    let mut flash = FlashStorage::new();
+    Ok(newest_entry(&mut flash)?.map(|(_, sequence)| FlashPartition::from_sequence(sequence)))
+}
+
+/// Sector index and sequence number of the winning `otadata` entry.
+fn newest_entry(flash: &mut FlashStorage) -> Result<Option<(u32, u32)>, FlashStorageError> {
+    let mut newest = None;
+    for sector in 0..2 {
+        let mut entry = Entry([0; ENTRY_LEN]);
+        flash.read(OTADATA_START + sector * flash::SECTOR_SIZE, &mut entry.0)?;
+        let sequence = u32::from_le_bytes(entry.0[0..4].try_into().unwrap());
+        let crc = u32::from_le_bytes(entry.0[28..32].try_into().unwrap());
+        if sequence == EMPTY_SEQUENCE || sequence == 0 || crc != sequence_crc(sequence) {
+            continue;
+        }
+        match newest {
+            Some((_, best)) if best >= sequence => {}
+            _ => newest = Some((sector, sequence)),
+        }
+    }
+    Ok(newest)
+}
+
+async fn select(partition: FlashPartition) -> Result<(), FlashStorageError> {
+    let mut flash = FlashStorage::new();
+    let (sector, mut sequence) = match newest_entry(&mut flash)? {
+        Some((sector, sequence)) => (sector ^ 1, sequence + 1),
+        None => (0, 1),
+    };
+    if FlashPartition::from_sequence(sequence) != partition {
+        sequence += 1;
+    }
+    debug_assert_eq!(FlashPartition::from_sequence(sequence), partition);
+
+    let mut entry = Entry([0xFF; ENTRY_LEN]);
+    entry.0[0..4].copy_from_slice(&sequence.to_le_bytes());
+    // 20-byte label stays erased.
+    entry.0[24..28].copy_from_slice(&OTA_STATE_UNDEFINED.to_le_bytes());
+    entry.0[28..32].copy_from_slice(&sequence_crc(sequence).to_le_bytes());
+
+    let start = OTADATA_START + sector * flash::SECTOR_SIZE;
+    flash::erase(&mut flash, start, start + flash::SECTOR_SIZE).await?;
+    flash::write(&mut flash, start, &entry.0).await
+}
+
+#[repr(C, align(4))]
+struct Entry([u8; ENTRY_LEN]);
+
+/// The bootloader's check value: ROM `crc32_le(UINT32_MAX, &seq, 4)`. The
+/// ROM inverts the running value on the way in and out, so this is a
+/// reflected CRC-32 with initial value 0 and final inversion.
+fn sequence_crc(sequence: u32) -> u32 {
+    let mut crc = 0u32;
+    for byte in sequence.to_le_bytes() {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
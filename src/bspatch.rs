@@ -0,0 +1,227 @@
+// bspatch: rebuilds an image from the one in another slot and a bsdiff
+// patch, so a delta update only downloads what changed.
+//
+// Patches are in the streaming layout of bsdiff 4.3 (ENDSLEY/BSDIFF43),
+// without its bzip2 layer; gzip on top is fine, `ota` inflates first:
+//
+//     "ENDSLEY/BSDIFF43" | new image length
+//
+// then, until the new image is complete, blocks of
+//
+//     diff length x | extra length y | seek z | x diff bytes | y extra bytes
+//
+// The next x bytes of the new image are the old image's bytes from the
+// current old position plus the diff bytes (mod 256), the y after them are
+// the extra bytes as they are, and then the old position moves on by
+// x + z. Numbers are 8 bytes: the magnitude little-endian, the sign in the
+// top bit.
+//
+// Nothing in the patch is ever needed twice, so `Patcher` takes it in
+// pieces of any size, the way `GzipDecoder` takes its input, and reads the
+// old image from flash `CHUNK` bytes at a time.
+
+use embedded_storage::nor_flash::ReadNorFlash;
+use esp_storage::{FlashStorage, FlashStorageError};
+
+use crate::partition::PartitionEntry;
+
+pub const MAGIC: &[u8; 16] = b"ENDSLEY/BSDIFF43";
+
+/// Most output one `step` gives, and most old bytes it reads at once.
+pub const CHUNK: usize = 512;
+
+const HEADER_LEN: usize = 24;
+const CONTROL_LEN: usize = 24;
+
+#[derive(Debug)]
+pub enum PatchError {
+    NotPatch,
+    /// A length or position the patch cannot mean.
+    Corrupt,
+    /// The patch ended before the new image was complete.
+    Truncated,
+    Flash(#[allow(dead_code)] FlashStorageError),
+}
+
+impl From<FlashStorageError> for PatchError {
+    fn from(e: FlashStorageError) -> Self {
+        PatchError::Flash(e)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Stage {
+    Header,
+    Control,
+    Diff,
+    Extra,
+    Done,
+}
+
+pub struct Patcher {
+    flash: FlashStorage,
+    old_start: u32,
+    old_size: u32,
+    stage: Stage,
+    /// Header or control bytes collected.
+    field: [u8; HEADER_LEN],
+    have: usize,
+    new_len: u32,
+    new_pos: u32,
+    /// May point outside the old image; bytes there count as zero.
+    old_pos: i64,
+    diff_left: u32,
+    extra_left: u32,
+    seek: i64,
+    buf: [u8; CHUNK],
+}
+
+impl Patcher {
+    /// A patcher against the image in `old`.
+    pub fn new(old: &PartitionEntry) -> Self {
+        Self {
+            flash: FlashStorage::new(),
+            old_start: old.offset,
+            old_size: old.size,
+            stage: Stage::Header,
+            field: [0; HEADER_LEN],
+            have: 0,
+            new_len: 0,
+            new_pos: 0,
+            old_pos: 0,
+            diff_left: 0,
+            extra_left: 0,
+            seek: 0,
+            buf: [0; CHUNK],
+        }
+    }
+
+    /// Takes what it can of `input` and returns how much that was, along with
+    /// the next piece of the new image. Call again with the rest (or more
+    /// input) until both are empty.
+    pub fn step(&mut self, input: &[u8]) -> Result<(usize, &[u8]), PatchError> {
+        let mut used = 0;
+        while matches!(self.stage, Stage::Header | Stage::Control) {
+            let Some(&byte) = input.get(used) else {
+                return Ok((used, &[]));
+            };
+            used += 1;
+            self.field_byte(byte)?;
+        }
+        let rest = &input[used..];
+        match self.stage {
+            Stage::Diff => {
+                let n = rest.len().min(self.diff_left as usize).min(CHUNK);
+                self.read_old(n)?;
+                for (byte, diff) in self.buf[..n].iter_mut().zip(rest) {
+                    *byte = byte.wrapping_add(*diff);
+                }
+                self.diff_left -= n as u32;
+                self.old_pos = self.old_pos.saturating_add(n as i64);
+                self.new_pos += n as u32;
+                if self.diff_left == 0 {
+                    self.next_stage();
+                }
+                Ok((used + n, &self.buf[..n]))
+            }
+            Stage::Extra => {
+                let n = rest.len().min(self.extra_left as usize).min(CHUNK);
+                self.buf[..n].copy_from_slice(&rest[..n]);
+                self.extra_left -= n as u32;
+                self.new_pos += n as u32;
+                if self.extra_left == 0 {
+                    self.next_stage();
+                }
+                Ok((used + n, &self.buf[..n]))
+            }
+            // Anything after the last block is ignored.
+            _ => Ok((input.len(), &[])),
+        }
+    }
+
+    /// Whether the whole new image has come out.
+    pub fn finish(&self) -> Result<(), PatchError> {
+        match self.stage {
+            Stage::Done => Ok(()),
+            _ => Err(PatchError::Truncated),
+        }
+    }
+
+    /// One byte of the header or of a block's control numbers.
+    fn field_byte(&mut self, byte: u8) -> Result<(), PatchError> {
+        self.field[self.have] = byte;
+        self.have += 1;
+        match self.stage {
+            Stage::Header if self.have == HEADER_LEN => {
+                if self.field[..MAGIC.len()] != MAGIC[..] {
+                    return Err(PatchError::NotPatch);
+                }
+                self.new_len =
+                    u32::try_from(offt(&self.field[16..24])).map_err(|_| PatchError::Corrupt)?;
+                self.have = 0;
+                self.stage = Stage::Control;
+                if self.new_len == 0 {
+                    self.stage = Stage::Done;
+                }
+            }
+            Stage::Control if self.have == CONTROL_LEN => {
+                let diff = offt(&self.field[0..8]);
+                let extra = offt(&self.field[8..16]);
+                let left = (self.new_len - self.new_pos) as i64;
+                let fits = diff.checked_add(extra).is_some_and(|len| len <= left);
+                if diff < 0 || extra < 0 || !fits {
+                    return Err(PatchError::Corrupt);
+                }
+                self.diff_left = diff as u32;
+                self.extra_left = extra as u32;
+                self.seek = offt(&self.field[16..24]);
+                self.have = 0;
+                self.next_stage();
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Where a block goes once its diff or extra bytes are used up.
+    fn next_stage(&mut self) {
+        self.stage = if self.diff_left > 0 {
+            Stage::Diff
+        } else if self.extra_left > 0 {
+            Stage::Extra
+        } else {
+            self.old_pos = self.old_pos.saturating_add(self.seek);
+            if self.new_pos == self.new_len {
+                Stage::Done
+            } else {
+                Stage::Control
+            }
+        };
+    }
+
+    /// The `n` old bytes from `old_pos` into `buf`. A valid patch only reads
+    /// inside the old image; elsewhere they are zero, as bspatch has it.
+    fn read_old(&mut self, n: usize) -> Result<(), PatchError> {
+        let buf = &mut self.buf[..n];
+        buf.fill(0);
+        let from = self.old_pos;
+        let low = from.max(0);
+        let high = from.saturating_add(n as i64).min(self.old_size as i64);
+        if low < high {
+            let part = &mut buf[(low - from) as usize..(high - from) as usize];
+            self.flash.read(self.old_start + low as u32, part)?;
+        }
+        Ok(())
+    }
+}
+
+/// bsdiff's sign-and-magnitude 8-byte number.
+fn offt(bytes: &[u8]) -> i64 {
+    let raw = u64::from_le_bytes(bytes.try_into().unwrap());
+    let magnitude = (raw & !(1 << 63)) as i64;
+    if raw >> 63 == 0 {
+        magnitude
+    } else {
+        -magnitude
+    }
+}
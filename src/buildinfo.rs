@@ -0,0 +1,62 @@
+// What this firmware was built from, as build.rs found it.
+//
+// `BANNER` is the boot log's one line of it, `JSON` what the API's
+// `/api/v1/buildinfo` answers and uploads carry as `build` after a boot,
+// until one is accepted (src/uploader.rs); the console's `version` prints the
+// banner. Both are put together at compile time, so no buffer can cut them
+// short, the full commit hash included. `VERSION` is the version alone, as
+// numbers.
+
+/// `version`, `git describe --always --dirty --tags`, the full commit, the
+/// build time (ISO 8601 UTC), `rustc -V` and the enabled Cargo features,
+/// sorted.
+pub const BANNER: &str = concat!(
+    "build: version=",
+    env!("CARGO_PKG_VERSION"),
+    " git=",
+    env!("BUILD_GIT_DESCRIBE"),
+    " commit=",
+    env!("BUILD_GIT_HASH"),
+    " built=",
+    env!("BUILD_TIMESTAMP"),
+    " rustc=\"",
+    env!("BUILD_RUSTC"),
+    "\" features=",
+    env!("BUILD_FEATURES"),
+);
+
+/// The same fields.
+pub const JSON: &str = concat!(
+    "{\"version\":\"",
+    env!("CARGO_PKG_VERSION"),
+    "\",\"git\":\"",
+    env!("BUILD_GIT_DESCRIBE"),
+    "\",\"commit\":\"",
+    env!("BUILD_GIT_HASH"),
+    "\",\"built\":\"",
+    env!("BUILD_TIMESTAMP"),
+    "\",\"rustc\":\"",
+    env!("BUILD_RUSTC"),
+    "\",\"features\":\"",
+    env!("BUILD_FEATURES"),
+    "\"}",
+);
+
+/// `version` as three numbers, for the discovery beacon (src/beacon.rs).
+pub const VERSION: [u8; 3] = [
+    number(env!("CARGO_PKG_VERSION_MAJOR")),
+    number(env!("CARGO_PKG_VERSION_MINOR")),
+    number(env!("CARGO_PKG_VERSION_PATCH")),
+];
+
+const fn number(digits: &str) -> u8 {
+    let digits = digits.as_bytes();
+    let mut value = 0u32;
+    let mut i = 0;
+    while i < digits.len() {
+        value = value * 10 + (digits[i] - b'0') as u32;
+        i += 1;
+    }
+    assert!(value < 256);
+    value as u8
+}
@@ -0,0 +1,13 @@
+// The board's one I2C bus (I2C0, SDA on GPIO4, SCL on GPIO5), shared by the
+// RTC and the PMIC. Drivers lock it per transaction, so a multi-register
+// read from one chip cannot be split by a write to the other.
+
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::mutex::Mutex;
+use esp_hal::i2c::I2C;
+use esp_hal::peripherals::I2C0;
+use esp_hal::Async;
+
+pub type AsyncI2c<'d> = I2C<'d, I2C0, Async>;
+
+pub type SharedI2c = Mutex<CriticalSectionRawMutex, AsyncI2c<'static>>;
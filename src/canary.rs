@@ -0,0 +1,247 @@
+// Stack painting and canaries, to catch overflows before they turn into
+// silent corruption.
+//
+// Every task runs on the one main stack; what Embassy keeps per task in its
+// arena is the future, not a stack. At boot `paint_stack` fills the unused
+// part of the stack with `PATTERN`. The lowest `CANARY_WORDS` of it are the
+// stack's canary: once they change, the stack has reached the statics below
+// it. Large static buffers end in a `Canary` of their own, registered with
+// `register`, which catches a write running off their end.
+//
+// `tracked` wraps a task's body and, after each poll, walks down from where
+// the poll started to the first `UNTOUCHED_RUN` painted words in a row. That
+// is how deep the poll went, give or take a buffer it never wrote; the
+// walked part is painted again for the next poll. The walk and the repaint
+// run with interrupts off, so no handler frame is painted over.
+//
+// `tracked` also names the task for the panic handler (`current_task`), so
+// a crash report says which task panicked, and keeps a list of the tracked
+// tasks for the API's `GET /api/v1/tasks` (`tasks`). A task is there from
+// its first poll, and marked done once its body returns.
+//
+// So a task has no stack size to set or overrun of its own: all of them
+// share the stack's canary. `stack_monitor_task` looks every
+// `MONITOR_INTERVAL` (`check_all_stacks`) and, once a canary is gone,
+// prints the usage and panics, naming the region and the task that has
+// gone deepest. Memory past a canary is already corrupt; the reset stops
+// the firmware running on it, and the crash report (src/crash.rs) takes
+// the message to the server.
+//
+// What the words read mean, how deep a poll went and whom an overwritten
+// canary is blamed on, is src/canaryscan.rs.
+
+use core::cell::{Cell, RefCell};
+use core::future::{poll_fn, Future};
+use core::pin::pin;
+use core::ptr::{self, addr_of};
+
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_time::{Duration, Timer as EmbassyTimer};
+use esp_println::println;
+use heapless::Vec;
+
+use crate::canaryscan::{self, StackOverflowReport, TaskStack, CANARY_WORDS, PATTERN};
+
+pub type Canary = [u32; CANARY_WORDS];
+/// Initial value for a `Canary` field.
+pub const CANARY: Canary = [PATTERN; CANARY_WORDS];
+
+/// Left unpainted below the painting function's own frame.
+const PAINT_MARGIN: usize = 64;
+
+const MAX_REGIONS: usize = 12;
+pub const MAX_TASKS: usize = 16;
+
+const MONITOR_INTERVAL: Duration = Duration::from_secs(5);
+
+extern "C" {
+    // From the linker script: the stack grows down from `_stack_start` to
+    // `_stack_end`.
+    static _stack_start: u32;
+    static _stack_end: u32;
+}
+
+#[derive(Clone, Copy)]
+struct Region {
+    name: &'static str,
+    addr: usize,
+}
+
+static REGIONS: Mutex<CriticalSectionRawMutex, RefCell<Vec<Region, MAX_REGIONS>>> =
+    Mutex::new(RefCell::new(Vec::new()));
+static TASKS: Mutex<CriticalSectionRawMutex, RefCell<Vec<TaskStack, MAX_TASKS>>> =
+    Mutex::new(RefCell::new(Vec::new()));
+/// The tracked task being polled, if any.
+static CURRENT: Mutex<CriticalSectionRawMutex, Cell<Option<&'static str>>> =
+    Mutex::new(Cell::new(None));
+
+/// Watches `canary`, which must sit right after the buffer it guards and
+/// stay where it is for good, i.e. be part of a static.
+pub fn register(name: &'static str, canary: *const Canary) {
+    let region = Region {
+        name,
+        addr: canary as usize,
+    };
+    REGIONS.lock(|regions| {
+        if regions.borrow_mut().push(region).is_err() {
+            println!("canary: no room to watch {}", name);
+        }
+    });
+}
+
+/// Paints the unused part of the stack. Call first thing in `main`.
+pub fn paint_stack() {
+    critical_section::with(|_| paint_below_sp(stack_bottom()));
+}
+
+/// Runs `task` as `name`, recording how much stack each of its polls
+/// takes.
+pub async fn tracked<F: Future>(name: &'static str, task: F) -> F::Output {
+    let mut task = pin!(task);
+    poll_fn(|cx| {
+        let outer = CURRENT.lock(|current| current.replace(Some(name)));
+        let entry = stack_pointer();
+        let result = task.as_mut().poll(cx);
+        measure(name, entry, result.is_ready());
+        CURRENT.lock(|current| current.set(outer));
+        result
+    })
+    .await
+}
+
+/// The name of the tracked task being polled; `None` outside one, as in
+/// the network task or an interrupt handler.
+pub fn current_task() -> Option<&'static str> {
+    CURRENT.lock(|current| current.get())
+}
+
+/// The tracked tasks polled so far, in the order of their first poll.
+#[cfg(feature = "api")]
+pub fn tasks() -> Vec<TaskStack, MAX_TASKS> {
+    TASKS.lock(|tasks| tasks.borrow().clone())
+}
+
+/// Name of the first region whose canary is no longer intact.
+pub fn first_clobbered() -> Option<&'static str> {
+    if !intact(stack_bottom()) {
+        return Some("main stack");
+    }
+    REGIONS.lock(|regions| {
+        regions
+            .borrow()
+            .iter()
+            .find(|region| !intact(region.addr))
+            .map(|region| region.name)
+    })
+}
+
+/// Bytes at the bottom of the stack still painted, canary included.
+fn stack_headroom() -> usize {
+    let bottom = stack_bottom();
+    let words = (stack_top() - bottom) / 4;
+    canaryscan::untouched_prefix(read_up(bottom, words)) * 4
+}
+
+/// Prints the stack's and each task's use of it, and the canaries' state.
+pub fn print_usage() {
+    println!(
+        "stack: {} of {} bytes never used",
+        stack_headroom(),
+        stack_top() - stack_bottom()
+    );
+    // Estimates: a buffer a task never wrote to looks unused.
+    TASKS.lock(|tasks| {
+        for task in tasks.borrow().iter() {
+            println!(
+                "  {:<14} up to {} bytes per poll, {} bytes left below it{}",
+                task.name,
+                task.deepest,
+                task.headroom,
+                if task.done { " (done)" } else { "" }
+            );
+        }
+    });
+    match first_clobbered() {
+        Some(region) => println!("canary: {} clobbered", region),
+        None => println!("canary: all intact"),
+    }
+}
+
+/// The first clobbered region, if there is one, and the task most likely
+/// to blame.
+pub fn check_all_stacks() -> Option<StackOverflowReport> {
+    let region = first_clobbered()?;
+    Some(TASKS.lock(|tasks| StackOverflowReport::new(region, &tasks.borrow())))
+}
+
+/// Panics once `check_all_stacks` finds something.
+#[embassy_executor::task]
+pub async fn stack_monitor_task() {
+    tracked("stack monitor", monitor()).await
+}
+
+async fn monitor() -> ! {
+    loop {
+        EmbassyTimer::after(MONITOR_INTERVAL).await;
+        if let Some(report) = check_all_stacks() {
+            print_usage();
+            panic!("stack overflow: {}", report);
+        }
+    }
+}
+
+#[inline(never)]
+fn measure(name: &'static str, entry: usize, done: bool) {
+    critical_section::with(|_| {
+        let bottom = stack_bottom();
+        let used = canaryscan::used_words(read_down(entry, (entry - bottom) / 4)) * 4;
+        let deepest = entry - used;
+        paint_below_sp(deepest);
+        TASKS.lock(|tasks| {
+            canaryscan::record(&mut tasks.borrow_mut(), name, used, deepest - bottom, done)
+        });
+    });
+}
+
+/// Paints from `from` up to just below this function's own frame. Has to
+/// stay a leaf so nothing of its own lives below the stack pointer.
+#[inline(never)]
+fn paint_below_sp(from: usize) {
+    let to = stack_pointer().saturating_sub(PAINT_MARGIN);
+    let mut addr = from;
+    while addr < to {
+        // Volatile, so the writes to memory nothing reads are not dropped.
+        unsafe { ptr::write_volatile(addr as *mut u32, PATTERN) };
+        addr += 4;
+    }
+}
+
+fn intact(addr: usize) -> bool {
+    canaryscan::untouched_prefix(read_up(addr, CANARY_WORDS)) == CANARY_WORDS
+}
+
+/// `count` words from `addr` upwards.
+fn read_up(addr: usize, count: usize) -> impl Iterator<Item = u32> {
+    (0..count).map(move |i| unsafe { ptr::read_volatile((addr + 4 * i) as *const u32) })
+}
+
+/// `count` words below `addr`, nearest first.
+fn read_down(addr: usize, count: usize) -> impl Iterator<Item = u32> {
+    (1..=count).map(move |i| unsafe { ptr::read_volatile((addr - 4 * i) as *const u32) })
+}
+
+fn stack_bottom() -> usize {
+    unsafe { addr_of!(_stack_end) as usize }
+}
+
+fn stack_top() -> usize {
+    unsafe { addr_of!(_stack_start) as usize }
+}
+
+#[inline(always)]
+fn stack_pointer() -> usize {
+    let sp: usize;
+    unsafe { core::arch::asm!("mv {}, sp", out(reg) sp) };
+    sp
+}
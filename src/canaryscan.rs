@@ -0,0 +1,108 @@
+// What src/canary.rs makes of the words it reads: how much of a painted
+// area a poll left untouched, how deep it went, each task's worst so far,
+// and which task an overwritten canary is blamed on. The reading itself,
+// of the stack and the statics, stays there.
+
+use core::fmt;
+
+use heapless::Vec;
+
+pub const PATTERN: u32 = 0x5AC3_A53C;
+pub const CANARY_WORDS: usize = 8;
+
+/// Painted words in a row taken as the end of what a poll used.
+pub const UNTOUCHED_RUN: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TaskStack {
+    pub name: &'static str,
+    /// Most bytes a single poll used below where it started.
+    pub deepest: usize,
+    /// Least stack there was left below the task in any poll.
+    pub headroom: usize,
+    /// Its body has returned.
+    pub done: bool,
+}
+
+/// A canary found overwritten.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StackOverflowReport {
+    pub region: &'static str,
+    /// The task with the least stack left below it in any poll, the
+    /// likeliest to have run over.
+    pub deepest: Option<TaskStack>,
+}
+
+impl StackOverflowReport {
+    pub fn new(region: &'static str, tasks: &[TaskStack]) -> Self {
+        Self {
+            region,
+            deepest: tasks.iter().min_by_key(|task| task.headroom).copied(),
+        }
+    }
+}
+
+impl fmt::Display for StackOverflowReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} canary clobbered", self.region)?;
+        if let Some(task) = self.deepest {
+            write!(
+                f,
+                "; deepest task {}, {} bytes in a poll, {} left",
+                task.name, task.deepest, task.headroom
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Adds one poll of `name` to `tasks`: `used` bytes below where it
+/// started, with `headroom` bytes of the stack left below that.
+pub fn record<const N: usize>(
+    tasks: &mut Vec<TaskStack, N>,
+    name: &'static str,
+    used: usize,
+    headroom: usize,
+    done: bool,
+) {
+    match tasks.iter_mut().find(|task| task.name == name) {
+        Some(task) => {
+            task.deepest = task.deepest.max(used);
+            task.headroom = task.headroom.min(headroom);
+            task.done = done;
+        }
+        None => {
+            let _ = tasks.push(TaskStack {
+                name,
+                deepest: used,
+                headroom,
+                done,
+            });
+        }
+    }
+}
+
+/// Words at the start of `words` that still hold the pattern.
+pub fn untouched_prefix(words: impl Iterator<Item = u32>) -> usize {
+    words.take_while(|&word| word == PATTERN).count()
+}
+
+/// Of `words`, read from the top of a used area downwards, the number
+/// before the first `UNTOUCHED_RUN` painted ones in a row. Shorter painted
+/// runs are a buffer that was never written and count as used.
+pub fn used_words(words: impl Iterator<Item = u32>) -> usize {
+    let mut seen = 0;
+    let mut run = 0;
+    for word in words {
+        seen += 1;
+        if word != PATTERN {
+            run = 0;
+            continue;
+        }
+        run += 1;
+        if run == UNTOUCHED_RUN {
+            break;
+        }
+    }
+    seen - run
+}
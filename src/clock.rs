@@ -0,0 +1,89 @@
+// Where the time comes from, for code that decides by it.
+//
+// `Clock` has the two clocks the firmware keeps: milliseconds since boot,
+// which only ever go forward, and Unix time, which is there once SNTP or
+// the RTC has set it (src/sntp.rs). `System` reads the real ones. It is a
+// unit struct and the code that takes a clock is generic over it, so on the
+// device this compiles down to the direct calls it replaces.
+//
+// `Manual` is a clock that moves only when told to: a check run off the
+// device can step it past a window or a limit and see what the code then
+// decides, without waiting for it.
+//
+// Most of the scheduling here is already pure, taking the time as an
+// argument (src/batch.rs, src/priority.rs); `Clock` is for the places that
+// read it themselves: the maintenance window, the login limit of the API,
+// the request limit of the debug access point, and the time a critical
+// record is raised at.
+
+use core::cell::Cell;
+
+use embassy_time::Instant;
+
+use crate::sntp;
+
+pub trait Clock {
+    /// Milliseconds since boot.
+    fn now_ms(&self) -> u64;
+
+    /// Unix milliseconds, or `None` while the wall clock is not set.
+    fn unix_ms(&self) -> Option<u64>;
+
+    #[cfg_attr(not(feature = "api"), allow(dead_code))]
+    fn now_s(&self) -> u64 {
+        self.now_ms() / 1000
+    }
+}
+
+/// The firmware's own clocks.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct System;
+
+impl Clock for System {
+    fn now_ms(&self) -> u64 {
+        Instant::now().as_millis()
+    }
+
+    fn unix_ms(&self) -> Option<u64> {
+        sntp::now_unix_ms()
+    }
+}
+
+/// A clock that stands still between `advance`s.
+#[allow(dead_code)]
+#[derive(Debug, Default)]
+pub struct Manual {
+    now_ms: Cell<u64>,
+    /// Unix milliseconds at boot, once set.
+    epoch_ms: Cell<Option<u64>>,
+}
+
+#[allow(dead_code)]
+impl Manual {
+    pub const fn new() -> Self {
+        Self {
+            now_ms: Cell::new(0),
+            epoch_ms: Cell::new(None),
+        }
+    }
+
+    pub fn advance(&self, ms: u64) {
+        self.now_ms.set(self.now_ms.get() + ms);
+    }
+
+    /// Sets the wall clock to `unix_ms` as of now.
+    pub fn set_unix_ms(&self, unix_ms: u64) {
+        self.epoch_ms
+            .set(Some(unix_ms.saturating_sub(self.now_ms.get())));
+    }
+}
+
+impl Clock for Manual {
+    fn now_ms(&self) -> u64 {
+        self.now_ms.get()
+    }
+
+    fn unix_ms(&self) -> Option<u64> {
+        Some(self.epoch_ms.get()? + self.now_ms.get())
+    }
+}
@@ -0,0 +1,377 @@
+// Text encodings (hex, hexdump lines, base64, percent-encoding, JSON
+// strings) into caller buffers, and UUIDs, whose length is fixed, as a
+// `String`.
+//
+// Every encoder has a `*_len` companion giving the exact output size, so a
+// caller can size a buffer at compile time or check before encoding.
+// Encoders return the written part as `&str`; decoders return it as bytes
+// and reject anything that is not exactly what the matching encoder would
+// produce.
+
+// A shared toolbox: not every encoding has a caller in every build.
+#![allow(dead_code)]
+
+use core::fmt::{self, Write};
+use core::str;
+
+use heapless::String;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CodecError {
+    /// `out` is too short; the output has this many bytes.
+    BufferTooSmall(usize),
+    /// Malformed input at this byte offset.
+    Invalid(usize),
+}
+
+const HEX_DIGITS: &[u8; 16] = b"0123456789abcdef";
+
+pub const fn hex_encoded_len(len: usize) -> usize {
+    len * 2
+}
+
+/// Lowercase hex.
+pub fn hex_encode<'a>(input: &[u8], out: &'a mut [u8]) -> Result<&'a str, CodecError> {
+    let out = output(out, hex_encoded_len(input.len()))?;
+    for (pair, byte) in out.chunks_exact_mut(2).zip(input) {
+        pair[0] = HEX_DIGITS[(byte >> 4) as usize];
+        pair[1] = HEX_DIGITS[(byte & 0x0F) as usize];
+    }
+    Ok(ascii(out))
+}
+
+/// Either case; an odd length is invalid at its last digit.
+pub fn hex_decode<'a>(input: &[u8], out: &'a mut [u8]) -> Result<&'a [u8], CodecError> {
+    if input.len() % 2 != 0 {
+        return Err(CodecError::Invalid(input.len() - 1));
+    }
+    let out = output(out, input.len() / 2)?;
+    for (i, byte) in out.iter_mut().enumerate() {
+        let high = hex_value(input[2 * i]).ok_or(CodecError::Invalid(2 * i))?;
+        let low = hex_value(input[2 * i + 1]).ok_or(CodecError::Invalid(2 * i + 1))?;
+        *byte = (high << 4) | low;
+    }
+    Ok(out)
+}
+
+fn hex_value(digit: u8) -> Option<u8> {
+    match digit {
+        b'0'..=b'9' => Some(digit - b'0'),
+        b'a'..=b'f' => Some(digit - b'a' + 10),
+        b'A'..=b'F' => Some(digit - b'A' + 10),
+        _ => None,
+    }
+}
+
+/// `uuid` as `xxxxxxxx-xxxx-xxxx-xxxx-xxxxxxxxxxxx`, in lower case.
+pub fn uuid_to_string(uuid: &[u8; 16]) -> String<36> {
+    let mut out = String::new();
+    for (i, byte) in uuid.iter().enumerate() {
+        if matches!(i, 4 | 6 | 8 | 10) {
+            let _ = out.push('-');
+        }
+        // Cannot overflow: 32 digits and 4 dashes.
+        let _ = write!(out, "{:02x}", byte);
+    }
+    out
+}
+
+/// Bytes per hexdump line.
+pub const HEXDUMP_ROW: usize = 16;
+
+/// A hexdump line of `len` bytes, at most `HEXDUMP_ROW`. Short rows are
+/// padded so the ASCII gutter lines up with full ones.
+pub const fn hexdump_line_len(len: usize) -> usize {
+    // Offset and two spaces, the hex columns with a gap in the middle and
+    // one before the gutter, the gutter.
+    10 + (3 * HEXDUMP_ROW + 2) + (len + 2)
+}
+
+/// One line of a hexdump: `offset` in hex, up to `HEXDUMP_ROW` bytes of
+/// `row` in hex, and the same bytes as ASCII with `.` for anything not
+/// printable, e.g.
+///
+/// `00000010  48 54 54 50 2f 31 2e 31  20 32 30 30 20 4f 4b 0d  |HTTP/1.1 200 OK.|`
+pub fn hexdump_line<'a>(offset: u32, row: &[u8], out: &'a mut [u8]) -> Result<&'a str, CodecError> {
+    if row.len() > HEXDUMP_ROW {
+        return Err(CodecError::Invalid(HEXDUMP_ROW));
+    }
+    let out = output(out, hexdump_line_len(row.len()))?;
+    out.fill(b' ');
+    for (i, digit) in out[..8].iter_mut().enumerate() {
+        *digit = HEX_DIGITS[((offset >> (28 - 4 * i)) & 0x0F) as usize];
+    }
+    for (i, &byte) in row.iter().enumerate() {
+        let at = 10 + 3 * i + i / 8;
+        out[at] = HEX_DIGITS[(byte >> 4) as usize];
+        out[at + 1] = HEX_DIGITS[(byte & 0x0F) as usize];
+    }
+    let gutter = &mut out[10 + 3 * HEXDUMP_ROW + 2..];
+    gutter[0] = b'|';
+    for (c, &byte) in gutter[1..].iter_mut().zip(row) {
+        *c = if byte.is_ascii_graphic() || byte == b' ' {
+            byte
+        } else {
+            b'.'
+        };
+    }
+    gutter[row.len() + 1] = b'|';
+    Ok(ascii(out))
+}
+
+/// A base64 flavour: which alphabet, and whether output is padded with `=`
+/// to a multiple of four (and input must be).
+#[derive(Debug, Clone, Copy)]
+pub struct Base64 {
+    alphabet: &'static [u8; 64],
+    padded: bool,
+}
+
+const STANDARD_ALPHABET: &[u8; 64] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+const URL_SAFE_ALPHABET: &[u8; 64] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+
+/// RFC 4648 section 4, e.g. for HTTP basic auth.
+pub const STANDARD: Base64 = Base64 {
+    alphabet: STANDARD_ALPHABET,
+    padded: true,
+};
+pub const STANDARD_NO_PAD: Base64 = Base64 {
+    alphabet: STANDARD_ALPHABET,
+    padded: false,
+};
+/// RFC 4648 section 5, safe in URLs and file names. JWT segments are
+/// `URL_SAFE_NO_PAD`.
+pub const URL_SAFE: Base64 = Base64 {
+    alphabet: URL_SAFE_ALPHABET,
+    padded: true,
+};
+pub const URL_SAFE_NO_PAD: Base64 = Base64 {
+    alphabet: URL_SAFE_ALPHABET,
+    padded: false,
+};
+
+impl Base64 {
+    pub const fn encoded_len(&self, len: usize) -> usize {
+        if self.padded {
+            len.div_ceil(3) * 4
+        } else {
+            len / 3 * 4
+                + match len % 3 {
+                    0 => 0,
+                    1 => 2,
+                    _ => 3,
+                }
+        }
+    }
+
+    pub fn encode<'a>(&self, input: &[u8], out: &'a mut [u8]) -> Result<&'a str, CodecError> {
+        let out = output(out, self.encoded_len(input.len()))?;
+        let mut o = 0;
+        for group in input.chunks(3) {
+            let b = [
+                group[0],
+                group.get(1).copied().unwrap_or(0),
+                group.get(2).copied().unwrap_or(0),
+            ];
+            let n = ((b[0] as u32) << 16) | ((b[1] as u32) << 8) | b[2] as u32;
+            // One input byte gives two output characters, two give three.
+            let chars = group.len() + 1;
+            for k in 0..4 {
+                if k < chars {
+                    out[o] = self.alphabet[((n >> (18 - 6 * k)) & 0x3F) as usize];
+                    o += 1;
+                } else if self.padded {
+                    out[o] = b'=';
+                    o += 1;
+                }
+            }
+        }
+        Ok(ascii(out))
+    }
+
+    /// Length `decode` will produce for `input`, which must be well formed
+    /// in its length and padding (characters are checked by `decode`).
+    pub fn decoded_len(&self, input: &[u8]) -> Result<usize, CodecError> {
+        let data = self.strip_padding(input)?;
+        match data.len() % 4 {
+            1 => Err(CodecError::Invalid(data.len() - 1)),
+            rem => Ok(data.len() / 4 * 3 + rem.saturating_sub(1)),
+        }
+    }
+
+    pub fn decode<'a>(&self, input: &[u8], out: &'a mut [u8]) -> Result<&'a [u8], CodecError> {
+        let len = self.decoded_len(input)?;
+        let data = self.strip_padding(input)?;
+        let out = output(out, len)?;
+
+        let mut o = 0;
+        for (g, group) in data.chunks(4).enumerate() {
+            let mut n = 0u32;
+            for (k, &c) in group.iter().enumerate() {
+                let value = self.value(c).ok_or(CodecError::Invalid(g * 4 + k))?;
+                n |= (value as u32) << (18 - 6 * k);
+            }
+            let bytes = group.len() - 1;
+            // Bits below the last whole byte must be zero, or two inputs
+            // would decode the same.
+            let unused = n & (0xFF_FFFF >> (8 * bytes));
+            if unused != 0 {
+                return Err(CodecError::Invalid(g * 4 + group.len() - 1));
+            }
+            for k in 0..bytes {
+                out[o] = (n >> (16 - 8 * k)) as u8;
+                o += 1;
+            }
+        }
+        Ok(out)
+    }
+
+    /// `input` without its padding, after checking there is exactly as much
+    /// as this flavour requires.
+    fn strip_padding<'b>(&self, input: &'b [u8]) -> Result<&'b [u8], CodecError> {
+        let pad = input.iter().rev().take_while(|&&c| c == b'=').count();
+        let data = &input[..input.len() - pad];
+        if self.padded {
+            if input.len() % 4 != 0 {
+                return Err(CodecError::Invalid(input.len()));
+            }
+            let expected = match data.len() % 4 {
+                0 => 0,
+                2 => 2,
+                3 => 1,
+                _ => return Err(CodecError::Invalid(data.len() - 1)),
+            };
+            if pad != expected {
+                return Err(CodecError::Invalid(data.len()));
+            }
+        } else if pad > 0 {
+            return Err(CodecError::Invalid(data.len()));
+        }
+        Ok(data)
+    }
+
+    fn value(&self, c: u8) -> Option<u8> {
+        self.alphabet.iter().position(|&a| a == c).map(|i| i as u8)
+    }
+}
+
+/// Which part of a URI a string goes into, per RFC 3986 section 3. Each one
+/// leaves its allowed characters alone and percent-encodes the rest.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Component {
+    /// Only unreserved characters stay, e.g. for values that get signed.
+    Unreserved,
+    /// One path segment: `/` is encoded.
+    PathSegment,
+    /// A whole path: `/` stays.
+    Path,
+    /// One key or value of a `key=value&...` query: `&`, `=`, `+` and `#`
+    /// are encoded.
+    QueryParam,
+    /// User and password before `@`.
+    Userinfo,
+}
+
+impl Component {
+    fn allows(self, c: u8) -> bool {
+        let unreserved = c.is_ascii_alphanumeric() || matches!(c, b'-' | b'.' | b'_' | b'~');
+        let sub_delim = matches!(
+            c,
+            b'!' | b'$' | b'&' | b'\'' | b'(' | b')' | b'*' | b'+' | b',' | b';' | b'='
+        );
+        let pchar = unreserved || sub_delim || matches!(c, b':' | b'@');
+        match self {
+            Component::Unreserved => unreserved,
+            Component::PathSegment => pchar,
+            Component::Path => pchar || c == b'/',
+            Component::QueryParam => {
+                (pchar || matches!(c, b'/' | b'?')) && !matches!(c, b'&' | b'=' | b'+')
+            }
+            Component::Userinfo => unreserved || sub_delim || c == b':',
+        }
+    }
+}
+
+pub fn percent_encoded_len(input: &[u8], component: Component) -> usize {
+    input
+        .iter()
+        .map(|&c| if component.allows(c) { 1 } else { 3 })
+        .sum()
+}
+
+/// Uppercase hex digits, as RFC 3986 recommends.
+pub fn percent_encode<'a>(
+    input: &[u8],
+    component: Component,
+    out: &'a mut [u8],
+) -> Result<&'a str, CodecError> {
+    let out = output(out, percent_encoded_len(input, component))?;
+    let mut o = 0;
+    for &c in input {
+        if component.allows(c) {
+            out[o] = c;
+            o += 1;
+        } else {
+            out[o] = b'%';
+            out[o + 1] = HEX_DIGITS[(c >> 4) as usize].to_ascii_uppercase();
+            out[o + 2] = HEX_DIGITS[(c & 0x0F) as usize].to_ascii_uppercase();
+            o += 3;
+        }
+    }
+    Ok(ascii(out))
+}
+
+/// Decodes `%XX` escapes and leaves everything else as it is; `+` is not
+/// a space here.
+pub fn percent_decode<'a>(input: &[u8], out: &'a mut [u8]) -> Result<&'a [u8], CodecError> {
+    let mut len = 0;
+    let mut i = 0;
+    while i < input.len() {
+        if input[i] == b'%' {
+            let high = input.get(i + 1).and_then(|&d| hex_value(d));
+            let low = input.get(i + 2).and_then(|&d| hex_value(d));
+            let (Some(high), Some(low)) = (high, low) else {
+                return Err(CodecError::Invalid(i));
+            };
+            if let Some(slot) = out.get_mut(len) {
+                *slot = (high << 4) | low;
+            }
+            i += 3;
+        } else {
+            if let Some(slot) = out.get_mut(len) {
+                *slot = input[i];
+            }
+            i += 1;
+        }
+        len += 1;
+    }
+    if len > out.len() {
+        return Err(CodecError::BufferTooSmall(len));
+    }
+    Ok(&out[..len])
+}
+
+/// `text` as a quoted JSON string.
+pub fn write_json_str<W: Write>(out: &mut W, text: &str) -> fmt::Result {
+    out.write_char('"')?;
+    for c in text.chars() {
+        match c {
+            '"' => out.write_str("\\\"")?,
+            '\\' => out.write_str("\\\\")?,
+            c if (c as u32) < 0x20 => write!(out, "\\u{:04x}", c as u32)?,
+            c => out.write_char(c)?,
+        }
+    }
+    out.write_char('"')
+}
+
+fn output(out: &mut [u8], len: usize) -> Result<&mut [u8], CodecError> {
+    out.get_mut(..len).ok_or(CodecError::BufferTooSmall(len))
+}
+
+/// Every encoder writes ASCII only.
+fn ascii(bytes: &[u8]) -> &str {
+    str::from_utf8(bytes).unwrap()
+}
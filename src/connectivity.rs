@@ -0,0 +1,180 @@
+// Connectivity probe: tells "online" apart from a captive portal, the guest
+// Wi-Fi that hands out an address but answers every request with its login
+// page.
+//
+// A portal shows itself in one of three ways, each checked by a pure function
+// below so the heuristics can be judged on canned data:
+//
+// - the probe URL, which should answer an empty 204, returns a page or a
+//   redirect instead (`probe_response_is_portal`);
+// - DNS answers every name with the same private address
+//   (`dns_is_hijacked`);
+// - TLS handshakes get reset, because the portal intercepts port 443 but
+//   cannot finish the handshake (`tls_was_reset`).
+//
+// The result goes into `AppState`. While a portal is in the way the probe
+// repeats every `PORTAL_REPROBE_INTERVAL`; otherwise every
+// `ONLINE_REPROBE_INTERVAL`, or right away when `request_probe` is called.
+
+use embassy_futures::select::select;
+use embassy_net::IpAddress;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::signal::Signal;
+use embassy_time::{Duration, Timer as EmbassyTimer};
+use embedded_io::ErrorKind;
+use embedded_tls::TlsError;
+use esp_println::println;
+
+use crate::https::{self, FetchError};
+use crate::{canary, resolver, state, NetStack};
+
+/// Answers 204 with no body; anything else is someone in the way.
+pub const PROBE_URL: &str = "https://www.gstatic.com/generate_204";
+const PROBE_HOST: &str = "www.gstatic.com";
+/// An unrelated name; a real resolver gives it a different address.
+const CONTROL_HOST: &str = "example.com";
+
+pub const PORTAL_REPROBE_INTERVAL: Duration = Duration::from_secs(60);
+pub const ONLINE_REPROBE_INTERVAL: Duration = Duration::from_secs(15 * 60);
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Connectivity {
+    /// Not probed yet.
+    #[default]
+    Unknown,
+    Online,
+    CaptivePortal(Evidence),
+    /// The probe failed in a way that says nothing about a portal.
+    Offline,
+}
+
+/// Which heuristic caught the portal.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Evidence {
+    PortalPage,
+    DnsHijack,
+    TlsReset,
+}
+
+static PROBE_NOW: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+pub fn is_captive() -> bool {
+    matches!(
+        state::current().connectivity,
+        Connectivity::CaptivePortal(_)
+    )
+}
+
+/// Asks `connectivity_task` to probe again without waiting for its timer,
+/// e.g. after a request failed in a way that looks like a portal.
+pub fn request_probe() {
+    PROBE_NOW.signal(());
+}
+
+#[embassy_executor::task]
+pub async fn connectivity_task(stack: &'static NetStack) {
+    canary::tracked("connectivity", run(stack)).await
+}
+
+async fn run(stack: &'static NetStack) -> ! {
+    loop {
+        let interval = match state::current().connectivity {
+            Connectivity::Online => ONLINE_REPROBE_INTERVAL,
+            _ => PORTAL_REPROBE_INTERVAL,
+        };
+        select(EmbassyTimer::after(interval), PROBE_NOW.wait()).await;
+        probe_and_update(stack).await;
+    }
+}
+
+/// Probes once, records the result in `AppState` and returns it.
+pub async fn probe_and_update(stack: &NetStack) -> Connectivity {
+    let connectivity = probe(stack).await;
+    let before = state::current().connectivity;
+    if connectivity != before {
+        println!("connectivity: {:?}", connectivity);
+    }
+    state::update(|s| s.connectivity = connectivity);
+    connectivity
+}
+
+async fn probe(stack: &NetStack) -> Connectivity {
+    let Some(probe_addr) = resolve(stack, PROBE_HOST).await else {
+        return Connectivity::Offline;
+    };
+    if let Some(control_addr) = resolve(stack, CONTROL_HOST).await {
+        if dns_is_hijacked(&[probe_addr, control_addr]) {
+            return Connectivity::CaptivePortal(Evidence::DnsHijack);
+        }
+    }
+
+    // A portal page may be long; only its head matters.
+    let mut response = [0u8; 512];
+    match https::get(stack, PROBE_URL, &mut response).await {
+        Ok(result) => {
+            let content_type = result.header(&response, "content-type");
+            if probe_response_is_portal(result.status, content_type, result.body(&response)) {
+                Connectivity::CaptivePortal(Evidence::PortalPage)
+            } else {
+                Connectivity::Online
+            }
+        }
+        Err(e) if tls_was_reset(&e) => Connectivity::CaptivePortal(Evidence::TlsReset),
+        Err(_) => Connectivity::Offline,
+    }
+}
+
+async fn resolve(stack: &NetStack, host: &str) -> Option<IpAddress> {
+    resolver::resolve(stack, host)
+        .await
+        .ok()
+        .map(IpAddress::Ipv4)
+}
+
+/// Whether the probe URL's answer is anything but its usual empty 204.
+pub fn probe_response_is_portal(status: u16, content_type: Option<&str>, body: &[u8]) -> bool {
+    match status {
+        204 => false,
+        // Redirect to the login page, or RFC 6585's "Network Authentication
+        // Required".
+        301 | 302 | 303 | 307 | 308 | 511 => true,
+        // A page where there should be nothing. An empty 200 is what some
+        // transparent proxies turn the 204 into, and harmless.
+        200 => {
+            let html = content_type.map_or(false, |t| t.trim_start().starts_with("text/html"));
+            html || body.iter().find(|b| !b.is_ascii_whitespace()) == Some(&b'<')
+        }
+        _ => false,
+    }
+}
+
+/// Whether several unrelated names all resolved to the same private
+/// (RFC 1918 or link-local) address.
+pub fn dns_is_hijacked(answers: &[IpAddress]) -> bool {
+    let Some(first) = answers.first() else {
+        return false;
+    };
+    answers.len() >= 2 && answers.iter().all(|a| a == first) && is_private(first.as_bytes())
+}
+
+/// Whether a request died in the TLS handshake because the peer reset or
+/// closed the connection, as a portal intercepting port 443 does.
+pub fn tls_was_reset(err: &FetchError) -> bool {
+    matches!(
+        err,
+        FetchError::Handshake(
+            TlsError::ConnectionClosed
+                | TlsError::Io(ErrorKind::ConnectionReset | ErrorKind::ConnectionAborted)
+        )
+    )
+}
+
+fn is_private(addr: &[u8]) -> bool {
+    match addr {
+        [10, ..] => true,
+        [172, b, ..] => (16..32).contains(b),
+        [192, 168, ..] => true,
+        [169, 254, ..] => true,
+        _ => false,
+    }
+}
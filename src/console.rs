@@ -0,0 +1,330 @@
+// Line-based command console on UART0.
+//
+// Output goes through `println!` like everything else; this task only owns
+// the receive side.
+
+use core::str;
+
+use esp_hal::peripherals::UART0;
+use esp_hal::uart::UartRx;
+use esp_hal::Async;
+use esp_println::println;
+use heapless::String;
+
+#[cfg(not(feature = "oneshot"))]
+use crate::beacon;
+use crate::enterprise::{self, Field};
+use crate::https::{self, CIPHER_SUITE};
+#[cfg(feature = "ota")]
+use crate::ota;
+#[cfg(feature = "replay")]
+use crate::replay;
+#[cfg(not(feature = "oneshot"))]
+use crate::selftest;
+#[cfg(feature = "wiretrace")]
+use crate::wiretrace;
+use crate::{
+    batch, boot, buildinfo, canary, dryrun, flash, metrics, safemode, settings, sntp, state,
+    tlsinfo, wifiheap, NetStack,
+};
+use crate::{codec, diag};
+
+const MAX_LINE: usize = 128;
+
+// How much of a fetched body to echo back.
+const FETCH_PREVIEW_BYTES: usize = 256;
+
+#[embassy_executor::task]
+pub async fn console_task(rx: UartRx<'static, UART0, Async>, stack: &'static NetStack) {
+    canary::tracked("console", run(rx, stack)).await
+}
+
+async fn run(mut rx: UartRx<'static, UART0, Async>, stack: &'static NetStack) -> ! {
+    let mut line: String<MAX_LINE> = String::new();
+    let mut overflow = false;
+    let mut chunk = [0u8; 32];
+
+    println!("Console ready, type `help` for commands.");
+
+    loop {
+        let n = match rx.read_async(&mut chunk).await {
+            Ok(n) => n,
+            Err(e) => {
+                println!("Console read error: {:?}", e);
+                continue;
+            }
+        };
+
+        for &byte in &chunk[..n] {
+            match byte {
+                b'\r' | b'\n' => {
+                    if overflow {
+                        println!("Line too long (max {} characters).", MAX_LINE);
+                    } else if !line.trim().is_empty() {
+                        run_command(line.trim(), stack).await;
+                    }
+                    line.clear();
+                    overflow = false;
+                }
+                // Backspace / DEL
+                0x08 | 0x7F => {
+                    line.pop();
+                }
+                byte if byte.is_ascii() && !byte.is_ascii_control() => {
+                    if line.push(byte as char).is_err() {
+                        overflow = true;
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+async fn run_command(line: &str, stack: &'static NetStack) {
+    let (command, args) = line.split_once(' ').unwrap_or((line, ""));
+    let args = args.trim();
+
+    match command {
+        "help" => {
+            println!("Commands:");
+            println!("  fetch <url>   run an HTTPS GET and print the result");
+            println!("  status        show device state");
+            println!("  version       show what this firmware was built from");
+            println!("  stack         show stack use per task and the Wi-Fi heap");
+            println!("  dryrun [url]  try the upload URL, or another, without sending readings");
+            println!("  tlsinfo [url] handshake with the upload server, or another, and show it");
+            println!("  metrics       print today's latency histograms for Prometheus");
+            println!("  labels        show the labels readings and metrics carry");
+            println!("  eap           show or set WPA2-Enterprise credentials");
+            println!("  safemode      show the crash-loop count; `safemode exit` to leave");
+            #[cfg(not(feature = "oneshot"))]
+            println!("  selftest      check RNG, I2C, ADC, flash, Wi-Fi, DHCP, DNS, TLS and SNTP");
+            #[cfg(not(feature = "oneshot"))]
+            println!("  beacon        show or set (`on`, `off`) the discovery beacon");
+            #[cfg(feature = "ota")]
+            println!("  ota <url>     download an image and restart into it");
+            #[cfg(feature = "wiretrace")]
+            println!("  wiretrace     hexdump the next request's traffic");
+            #[cfg(feature = "replay")]
+            println!("  lastfail      print the last failed request for a bug report");
+        }
+        "fetch" if !args.is_empty() => fetch(stack, args).await,
+        "fetch" => println!("Usage: fetch <url>"),
+        "status" => status().await,
+        "version" => println!("{}", buildinfo::BANNER),
+        "stack" => {
+            canary::print_usage();
+            wifiheap::print_usage();
+        }
+        "dryrun" if dryrun::request(args) => println!("dryrun: asked the uploader"),
+        "dryrun" => println!("dryrun: URL longer than {} bytes", settings::MAX_URL_LEN),
+        "tlsinfo" if !args.is_empty() => tlsinfo::print(stack, args).await,
+        "tlsinfo" => tlsinfo::print(stack, &settings::current().upload_url).await,
+        "metrics" => metrics::print_prometheus(),
+        "labels" => {
+            for (key, value) in settings::labels().iter() {
+                println!("  {}={}", key, value);
+            }
+        }
+        "eap" => eap(args).await,
+        "safemode" if args == "exit" => safemode::exit().await,
+        "safemode" => print_safe_mode(),
+        #[cfg(not(feature = "oneshot"))]
+        "selftest" => selftest::request(),
+        #[cfg(not(feature = "oneshot"))]
+        "beacon" if args == "on" || args == "off" => {
+            beacon::ask(args == "on");
+            println!("beacon: asked for {}", args);
+        }
+        #[cfg(not(feature = "oneshot"))]
+        "beacon" if beacon::is_asked() => println!("beacon: on until `beacon off`"),
+        #[cfg(not(feature = "oneshot"))]
+        "beacon" => println!("beacon: while unprovisioned; `beacon on` for always"),
+        #[cfg(feature = "ota")]
+        "ota" if !args.is_empty() => ota::update(stack, args).await,
+        #[cfg(feature = "ota")]
+        "ota" => println!("Usage: ota <url>"),
+        #[cfg(feature = "wiretrace")]
+        "wiretrace" => {
+            wiretrace::arm();
+            println!("wiretrace: armed for the next request");
+        }
+        #[cfg(feature = "replay")]
+        "lastfail" => replay::print().await,
+        _ => println!("Unknown command `{}`, type `help`.", command),
+    }
+}
+
+async fn fetch(stack: &'static NetStack, url: &str) {
+    let mut response = [0u8; 1024];
+
+    println!("fetch: GET {}", url);
+    match https::get(stack, url, &mut response).await {
+        Ok(result) => {
+            let t = result.timings;
+            let body = result.body(&response);
+            let preview = &body[..body.len().min(FETCH_PREVIEW_BYTES)];
+
+            println!("fetch: status {}", result.status);
+            println!("fetch: cipher suite {}", CIPHER_SUITE);
+            println!("fetch: certificate fingerprint unavailable (certificates are not verified)");
+            println!(
+                "fetch: dns {} ms, connect {} ms, handshake {} ms, first byte {} ms, total {} ms",
+                t.dns_ms, t.connect_ms, t.handshake_ms, t.first_byte_ms, t.total_ms
+            );
+            println!(
+                "fetch: first {} of {} body bytes read:",
+                preview.len(),
+                body.len()
+            );
+            match str::from_utf8(preview) {
+                Ok(text) => println!("{}", text),
+                Err(_) => {
+                    let mut line = [0u8; codec::hexdump_line_len(codec::HEXDUMP_ROW)];
+                    for (i, row) in preview.chunks(codec::HEXDUMP_ROW).enumerate() {
+                        let offset = (i * codec::HEXDUMP_ROW) as u32;
+                        if let Ok(line) = codec::hexdump_line(offset, row, &mut line) {
+                            println!("{}", line);
+                        }
+                    }
+                }
+            }
+        }
+        Err(e) => {
+            println!(
+                "fetch: failed during {}: {} ({:?})",
+                e.phase().as_str(),
+                diag::classify(&e).describe(),
+                e
+            );
+        }
+    }
+}
+
+/// `eap` shows what is stored, `eap <field> <value>` sets a field and
+/// `eap clear` removes them all; the next boot uses the result.
+async fn eap(args: &str) {
+    let (name, value) = args.split_once(' ').unwrap_or((args, ""));
+    let value = value.trim();
+    let result = match (name, Field::parse(name)) {
+        ("", _) => {
+            for (name, field) in [
+                ("identity", Field::Identity),
+                ("username", Field::Username),
+                ("password", Field::Password),
+            ] {
+                let set = enterprise::is_set(field).await;
+                println!("eap {}: {}", name, if set { "set" } else { "not set" });
+            }
+            if !enterprise::CHECKS_SERVER {
+                println!("eap: server certificate not checked (no EAP_CA_CERT)");
+            }
+            return;
+        }
+        ("clear", _) => enterprise::clear().await,
+        (_, Some(field)) if !value.is_empty() => enterprise::store(field, value).await,
+        _ => {
+            println!("Usage: eap [identity|username|password <value> | clear]");
+            return;
+        }
+    };
+    match result {
+        Ok(()) => println!("eap: stored; takes effect at the next boot"),
+        Err(e) => println!("eap: not stored: {:?}", e),
+    }
+}
+
+async fn status() {
+    print_safe_mode();
+    #[cfg(feature = "ota")]
+    {
+        let (major, minor, patch) = ota::current_firmware_version();
+        println!("firmware: {}.{}.{}", major, minor, patch);
+    }
+    #[cfg(feature = "ota")]
+    match state::current().ota {
+        Some(ota) => match ota.percent() {
+            Some(percent) => println!(
+                "ota: {:?} {:?}, {} of {} bytes ({}%)",
+                ota.state,
+                ota.phase,
+                ota.bytes_done,
+                ota.total.unwrap_or(0),
+                percent
+            ),
+            None => println!(
+                "ota: {:?} {:?}, {} bytes (size unknown)",
+                ota.state, ota.phase, ota.bytes_done
+            ),
+        },
+        None => println!("ota: idle"),
+    };
+    #[cfg(feature = "ota")]
+    {
+        let mut url = [0u8; settings::MAX_URL_LEN];
+        if let Some(url) = ota::deferred_update(&mut url).await {
+            println!(
+                "ota: update from {} waiting for the maintenance window",
+                url
+            );
+        }
+    }
+    #[cfg(not(feature = "ota"))]
+    println!("ota: not built in");
+
+    match boot::read_record().await {
+        Ok(Some(record)) => println!("boot: {:?}", record),
+        Ok(None) => println!("boot: no update recorded"),
+        Err(e) => println!("boot: state unreadable: {:?}", e),
+    }
+    println!("network: {:?}", state::current().connectivity);
+    println!(
+        "flash: longest stall {} ms",
+        flash::longest_stall().as_millis()
+    );
+    match sntp::now() {
+        Some(now) => println!(
+            "clock: {} ms since the Unix epoch, +-{} ms, from {:?}",
+            now.unix_ms, now.uncertainty_ms, now.source
+        ),
+        None => println!("clock: not synced"),
+    }
+    match settings::current().maintenance_window {
+        Some(window) => println!("maintenance window: {} UTC", window),
+        None => println!("maintenance window: always"),
+    }
+    match settings::current().batch {
+        Some(policy) => {
+            let stats = batch::stats();
+            let average = stats.average_tenths();
+            println!(
+                "batch: {}, {} sent, {}.{} readings each; by count {}, size {}, age {}",
+                policy,
+                stats.flushes,
+                average / 10,
+                average % 10,
+                stats.by_count,
+                stats.by_size,
+                stats.by_age
+            );
+        }
+        None => println!("batch: off"),
+    }
+    metrics::print_summary();
+}
+
+fn print_safe_mode() {
+    if safemode::active() {
+        println!(
+            "safe mode: on, after {} abnormal resets in a row",
+            safemode::resets()
+        );
+    } else {
+        println!(
+            "safe mode: off, {} abnormal resets in a row (on at {})",
+            safemode::resets(),
+            safemode::MAX_ABNORMAL_RESETS
+        );
+    }
+}
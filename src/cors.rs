@@ -0,0 +1,132 @@
+// CORS for the API, so a management app served from another origin can
+// call it from a browser.
+//
+// `CorsMiddleware` wraps a `Handler`. It answers `OPTIONS` preflights itself
+// with 200, the CORS headers and no body, and adds the headers to whatever
+// the wrapped handler replies:
+//
+//     Access-Control-Allow-Origin: *
+//     Access-Control-Allow-Methods: GET, PUT, POST
+//     Access-Control-Allow-Headers: Content-Type, Authorization
+//
+// With `allowed_origins` empty any origin may call. A restricted deployment
+// lists its origins in API_CORS_ORIGINS at build time, comma-separated and
+// exactly as browsers send them (`https://admin.example.com`, no path); at
+// most `MAX_ORIGINS` of `MAX_ORIGIN_LEN` bytes, or the build fails.
+// Then only a request whose `Origin` is listed gets the headers, with that
+// origin echoed back and `Vary: Origin`, and preflights from anywhere else
+// get 403. Requests without `Origin` are not from a cross-origin page and
+// pass through unchanged either way.
+
+use heapless::{String, Vec};
+
+use crate::api::Action;
+use crate::httpd::{Handler, Reply, Request};
+
+pub const MAX_ORIGINS: usize = 4;
+pub const MAX_ORIGIN_LEN: usize = 64;
+
+const ALLOW_METHODS: &str = "GET, PUT, POST";
+const ALLOW_HEADERS: &str = "Content-Type, Authorization";
+
+pub struct CorsMiddleware<H> {
+    inner: H,
+    /// Empty allows every origin.
+    pub allowed_origins: Vec<String<MAX_ORIGIN_LEN>, MAX_ORIGINS>,
+}
+
+impl<H: Handler> CorsMiddleware<H> {
+    pub fn new(inner: H, allowed_origins: Vec<String<MAX_ORIGIN_LEN>, MAX_ORIGINS>) -> Self {
+        Self {
+            inner,
+            allowed_origins,
+        }
+    }
+
+    /// What `Access-Control-Allow-Origin` says for a request from `origin`,
+    /// `None` if that origin is not allowed.
+    fn allow_origin<'a>(&self, origin: &'a str) -> Option<&'a str> {
+        if self.allowed_origins.is_empty() {
+            return Some("*");
+        }
+        self.allowed_origins
+            .iter()
+            .any(|allowed| allowed.as_str() == origin)
+            .then_some(origin)
+    }
+
+    fn add_headers(&self, reply: &mut Reply, allow_origin: &str) {
+        // Fits: `MAX_ORIGIN_LEN` keeps the origin short.
+        let _ = reply.add_header("Access-Control-Allow-Origin", allow_origin);
+        let _ = reply.add_header("Access-Control-Allow-Methods", ALLOW_METHODS);
+        let _ = reply.add_header("Access-Control-Allow-Headers", ALLOW_HEADERS);
+        if !self.allowed_origins.is_empty() {
+            let _ = reply.add_header("Vary", "Origin");
+        }
+    }
+}
+
+impl<H: Handler> Handler for CorsMiddleware<H> {
+    async fn handle(&mut self, request: &Request<'_>, reply: &mut Reply) -> Action {
+        let origin = request.header("Origin");
+        let allow_origin = origin.and_then(|origin| self.allow_origin(origin));
+        if request.method == "OPTIONS" {
+            match allow_origin {
+                Some(allow_origin) => self.add_headers(reply, allow_origin),
+                None => reply.status = 403,
+            }
+            return Action::None;
+        }
+        let action = self.inner.handle(request, reply).await;
+        if let Some(allow_origin) = allow_origin {
+            self.add_headers(reply, allow_origin);
+        }
+        action
+    }
+}
+
+/// The origins in API_CORS_ORIGINS; empty (any origin) if it is not set.
+pub fn configured_origins() -> Vec<String<MAX_ORIGIN_LEN>, MAX_ORIGINS> {
+    let mut origins = Vec::new();
+    for origin in CONFIGURED.unwrap_or("").split(',').map(str::trim) {
+        if origin.is_empty() {
+            continue;
+        }
+        // Cannot fail: the list was checked at build time, below.
+        if let Ok(origin) = String::try_from(origin) {
+            let _ = origins.push(origin);
+        }
+    }
+    origins
+}
+
+const CONFIGURED: Option<&str> = option_env!("API_CORS_ORIGINS");
+
+// Dropping an origin that does not fit would leave the list looser, so the
+// build fails instead.
+const _: () = {
+    let list = match CONFIGURED {
+        Some(list) => list.as_bytes(),
+        None => b"",
+    };
+    let (mut i, mut count, mut len) = (0, 0, 0);
+    while i <= list.len() {
+        if i == list.len() || list[i] == b',' {
+            assert!(
+                len <= MAX_ORIGIN_LEN,
+                "an origin in API_CORS_ORIGINS is too long"
+            );
+            if len > 0 {
+                count += 1;
+            }
+            len = 0;
+        } else if list[i] != b' ' {
+            len += 1;
+        }
+        i += 1;
+    }
+    assert!(
+        count <= MAX_ORIGINS,
+        "API_CORS_ORIGINS lists too many origins"
+    );
+};
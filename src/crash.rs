@@ -0,0 +1,66 @@
+// Panic details kept across the reset, for the backend.
+//
+// The panic handler writes the message, file and line into RTC fast
+// memory, which start-up code leaves alone and a software reset keeps; a
+// power cut loses it. A panic in a tracked task (src/canary.rs) has the
+// task's name in front of the message, as `uploader: ...`. The record's
+// layout, and what a second panic keeps of it, are src/crashrecord.rs.
+//
+// If the board panics again before a report got through, the earliest
+// record stays and only its count goes up: the first panic is the one that
+// explains the rest. The uploader attaches the record to every reading as
+// `crash` until the server accepts one with a 2xx, and only then clears it.
+
+use core::panic::PanicInfo;
+use core::ptr::addr_of_mut;
+
+use esp_hal::macros::ram;
+use esp_println::println;
+
+use crate::crashrecord::{self, Crash, Record, RECORD_LEN};
+
+#[ram(rtc_fast, uninitialized)]
+static mut RECORD: Record = [0; RECORD_LEN];
+
+/// The record left by an earlier panic, if there is one.
+pub fn pending() -> Option<Crash> {
+    critical_section::with(|_| Crash::decode(unsafe { &*addr_of_mut!(RECORD) }))
+}
+
+/// After an upload that carried the report was answered.
+pub fn settle(status: u16, early: bool) {
+    critical_section::with(|_| {
+        crashrecord::settle(unsafe { &mut *addr_of_mut!(RECORD) }, status, early)
+    });
+}
+
+/// Prints the record left by an earlier panic, if any. Call at boot.
+pub fn report_at_boot() {
+    if let Some(crash) = pending() {
+        println!(
+            "crash: panicked at {}:{}: {} ({} times before this boot)",
+            crash.file, crash.line, crash.message, crash.count
+        );
+    }
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    let task = crate::canary::current_task();
+    match task {
+        Some(task) => println!("{} in task {}", info, task),
+        None => println!("{}", info),
+    }
+
+    let crash = Crash::new(
+        task,
+        info.message(),
+        info.location()
+            .map(|location| (location.file(), location.line())),
+    );
+    critical_section::with(|_| crashrecord::keep(unsafe { &mut *addr_of_mut!(RECORD) }, crash));
+    crate::safemode::note_panic();
+
+    esp_hal::reset::software_reset();
+    loop {}
+}
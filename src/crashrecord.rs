@@ -0,0 +1,146 @@
+// The crash record (src/crash.rs), apart from the RTC memory it lives in
+// and the panic handler that writes it: its layout, what a second panic
+// keeps, and when a report clears it.
+//
+// The record carries a magic and a CRC so that whatever the memory holds
+// after power-up is not mistaken for one. The message is truncated to
+// `MESSAGE_LEN` bytes and the file to `FILE_LEN`, at a character boundary.
+
+use core::fmt::{self, Display, Write};
+
+use esp_hal::rom::crc::crc32_le;
+use heapless::String;
+
+use crate::codec;
+
+pub const MESSAGE_LEN: usize = 128;
+pub const FILE_LEN: usize = 64;
+
+const MAGIC: u32 = 0x5041_4E43;
+// magic | count | line | message len | file len | 2 unused | message | file | crc
+const HEADER_LEN: usize = 16;
+pub const RECORD_LEN: usize = HEADER_LEN + MESSAGE_LEN + FILE_LEN + 4;
+
+pub type Record = [u8; RECORD_LEN];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Crash {
+    pub message: String<MESSAGE_LEN>,
+    pub file: String<FILE_LEN>,
+    pub line: u32,
+    /// Panics since the last delivered report.
+    pub count: u32,
+}
+
+impl Crash {
+    /// The first panic since the last delivered report; `task` goes in front
+    /// of the message, as `uploader: ...`.
+    pub fn new(task: Option<&str>, message: impl Display, location: Option<(&str, u32)>) -> Self {
+        let mut crash = Crash {
+            message: String::new(),
+            file: String::new(),
+            line: 0,
+            count: 1,
+        };
+        if let Some(task) = task {
+            let _ = write!(Truncate(&mut crash.message), "{}: ", task);
+        }
+        let _ = write!(Truncate(&mut crash.message), "{}", message);
+        if let Some((file, line)) = location {
+            let _ = Truncate(&mut crash.file).write_str(file);
+            crash.line = line;
+        }
+        crash
+    }
+
+    pub fn encode(&self) -> Record {
+        let mut raw = [0u8; RECORD_LEN];
+        raw[0..4].copy_from_slice(&MAGIC.to_le_bytes());
+        raw[4..8].copy_from_slice(&self.count.to_le_bytes());
+        raw[8..12].copy_from_slice(&self.line.to_le_bytes());
+        raw[12] = self.message.len() as u8;
+        raw[13] = self.file.len() as u8;
+        let message = HEADER_LEN;
+        let file = message + MESSAGE_LEN;
+        raw[message..message + self.message.len()].copy_from_slice(self.message.as_bytes());
+        raw[file..file + self.file.len()].copy_from_slice(self.file.as_bytes());
+        let crc = crc32_le(0, &raw[..RECORD_LEN - 4]);
+        raw[RECORD_LEN - 4..].copy_from_slice(&crc.to_le_bytes());
+        raw
+    }
+
+    /// `None` for anything `encode` did not write, random memory included.
+    pub fn decode(raw: &Record) -> Option<Crash> {
+        let word = |at: usize| u32::from_le_bytes(raw[at..at + 4].try_into().unwrap());
+        if word(0) != MAGIC || word(RECORD_LEN - 4) != crc32_le(0, &raw[..RECORD_LEN - 4]) {
+            return None;
+        }
+        let (message_len, file_len) = (raw[12] as usize, raw[13] as usize);
+        if message_len > MESSAGE_LEN || file_len > FILE_LEN {
+            return None;
+        }
+        let message = HEADER_LEN;
+        let file = message + MESSAGE_LEN;
+        Some(Crash {
+            message: text(&raw[message..message + message_len])?,
+            file: text(&raw[file..file + file_len])?,
+            line: word(8),
+            count: word(4),
+        })
+    }
+
+    /// The `crash` object of an upload.
+    pub fn write_json<W: Write>(&self, out: &mut W) -> fmt::Result {
+        out.write_str("{\"message\":")?;
+        codec::write_json_str(out, &self.message)?;
+        out.write_str(",\"file\":")?;
+        codec::write_json_str(out, &self.file)?;
+        write!(out, ",\"line\":{},\"count\":{}}}", self.line, self.count)
+    }
+}
+
+fn text<const N: usize>(bytes: &[u8]) -> Option<String<N>> {
+    String::try_from(core::str::from_utf8(bytes).ok()?).ok()
+}
+
+/// Writes `crash` to `record`, unless it already holds one: then that one
+/// stays, the first panic being the one that explains the rest, and only
+/// its count goes up.
+pub fn keep(record: &mut Record, crash: Crash) {
+    let kept = match Crash::decode(record) {
+        Some(earlier) => Crash {
+            count: earlier.count.saturating_add(1),
+            ..earlier
+        },
+        None => crash,
+    };
+    *record = kept.encode();
+}
+
+/// Whether the server took an upload: a 2xx, and not before it had the
+/// whole body.
+pub fn accepted(status: u16, early: bool) -> bool {
+    (200..300).contains(&status) && !early
+}
+
+/// After an upload that carried the record was answered: clears it if the
+/// server took the upload, and leaves it for the next one otherwise.
+pub fn settle(record: &mut Record, status: u16, early: bool) {
+    if accepted(status, early) {
+        record[0..4].fill(0);
+    }
+}
+
+/// Fills a string up to its capacity and silently drops the rest.
+struct Truncate<'a, const N: usize>(&'a mut String<N>);
+
+impl<const N: usize> Write for Truncate<'_, N> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for c in s.chars() {
+            if self.0.push(c).is_err() {
+                break;
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,218 @@
+// Debug access point: a Wi-Fi network of the device's own to look at it
+// through, while the station stays connected upstream and keeps uploading.
+//
+// With `wifi_debug_ap=<minutes>` in the settings (src/settings.rs), main
+// starts the radio in esp-wifi's mixed mode, access point and station at
+// once, and gives the access point a network stack of its own. The network
+// is `esp32c3-XXXXXX-debug`, the last three bytes of the AP MAC in hex,
+// WPA2 with the DEBUG_AP_PASSWORD the firmware was built with; a build
+// without one refuses the setting. The device is `ADDRESS` on
+// 192.168.4.0/24. There is no DHCP server: a client sets itself a static
+// address in that range, like 192.168.4.2.
+//
+// Port 80 serves `GET /api/v1/status` and `GET /api/v1/buildinfo` from the
+// API (src/api.rs) and nothing else: no writes, and not the config, whose
+// headers may carry credentials. At most `MAX_REQUESTS` in `WINDOW_S`, then
+// 429, since every status answer scans for the signal strength.
+//
+// `minutes` after boot the access point closes. esp-wifi 0.6 cannot leave
+// mixed mode without stopping the radio, which would take the uplink down
+// with it, so instead the SSID is hidden and the passphrase replaced with a
+// random one, which drops whoever is associated, and the server stops. The
+// interface is gone at the first boot without the setting.
+//
+// The cost: there is one radio, and the access point moves to the channel
+// of the network the station is on. Its beacons, and whatever a client
+// sends or fetches, take airtime from the uplink, so uploads get slower
+// while it is up and markedly slower while a client uses it. It also takes
+// a second stack with one socket, and a second set of server buffers.
+
+use core::fmt::Write as _;
+
+use embassy_futures::select::select;
+use embassy_net::{Config, Ipv4Address, Ipv4Cidr, Stack, StaticConfigV4};
+use embassy_time::{Duration, Instant, Timer as EmbassyTimer};
+use esp_println::println;
+use esp_wifi::wifi::{
+    AccessPointConfiguration, AuthMethod, Configuration, WifiApDevice, WifiDevice,
+};
+use heapless::{String, Vec};
+
+use crate::api::{self, Action};
+use crate::canary;
+use crate::clock::{self, Clock};
+use crate::entropy;
+use crate::httpd::{self, Handler, Reply, Request};
+use crate::safemode;
+use crate::settings;
+use crate::station;
+use crate::NetStack;
+
+pub type ApStack = Stack<WifiDevice<'static, WifiApDevice>>;
+
+/// On the access point's stack: the server's one TCP socket.
+pub const SOCKETS: usize = 1;
+
+pub const ADDRESS: Ipv4Address = Ipv4Address::new(192, 168, 4, 1);
+pub const PREFIX_LEN: u8 = 24;
+
+/// Longest WPA2 passphrase.
+const MAX_PASSPHRASE_LEN: usize = 63;
+
+/// DEBUG_AP_PASSWORD, if it is a WPA2 passphrase.
+pub const PASSWORD: Option<&str> = match option_env!("DEBUG_AP_PASSWORD") {
+    Some(password)
+        if password.len() >= station::MIN_PASSPHRASE_LEN
+            && password.len() <= MAX_PASSPHRASE_LEN =>
+    {
+        Some(password)
+    }
+    _ => None,
+};
+
+const MAX_CLIENTS: u16 = 2;
+
+const MAX_REQUESTS: u8 = 30;
+const WINDOW_S: u64 = 60;
+
+const PATHS: [&str; 2] = ["/api/v1/status", "/api/v1/buildinfo"];
+
+/// How many minutes the settings keep the access point up for, if any.
+pub fn requested() -> Option<u16> {
+    // `Settings::validate` refuses the setting without a password.
+    settings::current()
+        .wifi_debug_ap
+        .filter(|_| PASSWORD.is_some() && !safemode::active())
+}
+
+pub fn ssid() -> String<32> {
+    let mut mac = [0; 6];
+    esp_wifi::wifi::get_ap_mac(&mut mac);
+    let mut ssid = String::new();
+    // Cannot overflow: 20 bytes.
+    let _ = write!(
+        ssid,
+        "esp32c3-{:02x}{:02x}{:02x}-debug",
+        mac[3], mac[4], mac[5]
+    );
+    ssid
+}
+
+/// The access point half of `Configuration::Mixed`.
+pub fn access_point() -> AccessPointConfiguration {
+    let mut password = String::new();
+    // Cannot fail: `PASSWORD` is at most 63 bytes.
+    let _ = password.push_str(PASSWORD.unwrap_or(""));
+    AccessPointConfiguration {
+        ssid: ssid(),
+        auth_method: AuthMethod::WPA2Personal,
+        password,
+        max_connections: MAX_CLIENTS,
+        ..Default::default()
+    }
+}
+
+pub fn config() -> Config {
+    Config::ipv4_static(StaticConfigV4 {
+        address: Ipv4Cidr::new(ADDRESS, PREFIX_LEN),
+        gateway: None,
+        dns_servers: Vec::new(),
+    })
+}
+
+#[embassy_executor::task]
+pub async fn ap_net_task(ap_stack: &'static ApStack) {
+    canary::tracked("ap net", ap_stack.run()).await
+}
+
+/// Serves the access point until `minutes` after boot, then closes it.
+#[embassy_executor::task]
+pub async fn debug_ap_task(ap_stack: &'static ApStack, stack: &'static NetStack, minutes: u16) {
+    canary::tracked("debug ap", run(ap_stack, stack, minutes)).await
+}
+
+async fn run(ap_stack: &'static ApStack, stack: &'static NetStack, minutes: u16) -> ! {
+    let handler = ReadOnly {
+        inner: api::Api::new(stack),
+        window_start_s: 0,
+        requests: 0,
+        clock: clock::System,
+    };
+    let until = Instant::from_secs(minutes as u64 * 60);
+    println!(
+        "debug ap: serving port {} until {} min after boot",
+        httpd::PORT,
+        minutes
+    );
+    select(
+        httpd::serve_debug(ap_stack, handler, stack),
+        EmbassyTimer::at(until),
+    )
+    .await;
+    close().await;
+    loop {
+        EmbassyTimer::after(Duration::from_secs(3600)).await;
+    }
+}
+
+/// Hides the access point behind a passphrase nobody knows.
+async fn close() {
+    let mut password: String<64> = String::new();
+    for _ in 0..4 {
+        // Cannot overflow: 32 hex digits.
+        let _ = write!(password, "{:08x}", entropy::random_u32());
+    }
+    let closed = AccessPointConfiguration {
+        ssid: ssid(),
+        ssid_hidden: true,
+        auth_method: AuthMethod::WPA2Personal,
+        password,
+        max_connections: 1,
+        ..Default::default()
+    };
+    let mut controller = station::lock().await;
+    let Some(controller) = controller.as_mut() else {
+        return;
+    };
+    match controller.set_configuration(&Configuration::AccessPoint(closed)) {
+        Ok(()) => println!("debug ap: closed"),
+        Err(e) => println!("debug ap: cannot close: {:?}", e),
+    }
+}
+
+/// Lets through `GET` on `PATHS` only, and at most `MAX_REQUESTS` of
+/// anything per `WINDOW_S`.
+struct ReadOnly<H, C> {
+    inner: H,
+    window_start_s: u64,
+    requests: u8,
+    clock: C,
+}
+
+impl<H: Handler, C: Clock> Handler for ReadOnly<H, C> {
+    async fn handle(&mut self, request: &Request<'_>, reply: &mut Reply) -> Action {
+        let now_s = self.clock.now_s();
+        if now_s - self.window_start_s >= WINDOW_S {
+            self.window_start_s = now_s;
+            self.requests = 0;
+        }
+        if self.requests >= MAX_REQUESTS {
+            reply.error(429, "too many requests");
+            let mut retry_after: String<8> = String::new();
+            let _ = write!(retry_after, "{}", self.window_start_s + WINDOW_S - now_s);
+            let _ = reply.add_header("Retry-After", &retry_after);
+            return Action::None;
+        }
+        self.requests += 1;
+        if !PATHS.contains(&request.path) {
+            reply.error(404, "no such endpoint");
+            return Action::None;
+        }
+        if request.method != "GET" {
+            reply.error(405, "read-only");
+            let _ = reply.add_header("Allow", "GET");
+            return Action::None;
+        }
+        self.inner.handle(request, reply).await
+    }
+}
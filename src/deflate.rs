@@ -0,0 +1,269 @@
+// Gzip (RFC 1952) compression for upload bodies, without an allocator and
+// without a second copy of the body.
+//
+// The deflate data (RFC 1951) is one block with the fixed Huffman codes, so
+// there is no code table to build or to send. LZ77 matches are found
+// through a hash of the next three bytes, `HASH_SIZE` heads each holding the
+// last position with that hash, and no chains. A match points back into the
+// body itself, which the caller keeps until it has all been read out: the
+// body is the window. That leaves much of what zlib would find on the
+// table, but JSON, the same keys over and over, still shrinks by half or
+// more.
+//
+// `Encoder` is read like a stream: each `read` fills a buffer with the next
+// bytes of output, so the output goes out through a buffer of any size, and
+// the same body gives the same bytes every time. `gzip` reads the whole
+// once to learn the length before sending, since a request states its
+// `Content-Length` up front. The state is the heads, 1 KB, and a few words.
+//
+// A body that gzip would not shrink, such as one already compressed or
+// random, goes as it is (`gzip_smaller`): a fixed-code block spends more
+// than 8 bits on a literal byte above 143.
+
+use esp_hal::rom::crc::crc32_le;
+
+/// Longest body an `Encoder` takes; positions are kept in 16 bits.
+pub const MAX_INPUT_LEN: usize = u16::MAX as usize;
+
+const HASH_BITS: u32 = 9;
+const HASH_SIZE: usize = 1 << HASH_BITS;
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+const MAX_DISTANCE: usize = 32 * 1024;
+const END_OF_BLOCK: u16 = 256;
+
+// ID1 ID2, deflate, no flags, no time, no extra flags, unknown OS.
+const HEADER: [u8; 10] = [0x1F, 0x8B, 8, 0, 0, 0, 0, 0, 0, 0xFF];
+const TRAILER_LEN: usize = 8;
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DISTANCE_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Stage {
+    /// The next byte of `HEADER`.
+    Header(usize),
+    Block,
+    Trailer(usize),
+    Done,
+}
+
+pub struct Encoder<'a> {
+    input: &'a [u8],
+    pos: usize,
+    /// Each hash's last position, plus one; 0 for none yet.
+    heads: [u16; HASH_SIZE],
+    /// Output bits not yet in whole bytes, the first in the lowest bit.
+    bits: u64,
+    bit_count: u32,
+    stage: Stage,
+    trailer: [u8; TRAILER_LEN],
+}
+
+impl<'a> Encoder<'a> {
+    /// `None` if `input` is longer than `MAX_INPUT_LEN`.
+    pub fn new(input: &'a [u8]) -> Option<Self> {
+        (input.len() <= MAX_INPUT_LEN).then(|| Self::start(input))
+    }
+
+    fn start(input: &'a [u8]) -> Self {
+        let mut trailer = [0u8; TRAILER_LEN];
+        trailer[..4].copy_from_slice(&crc32_le(0, input).to_le_bytes());
+        trailer[4..].copy_from_slice(&(input.len() as u32).to_le_bytes());
+        Self {
+            input,
+            pos: 0,
+            heads: [0; HASH_SIZE],
+            bits: 0,
+            bit_count: 0,
+            stage: Stage::Header(0),
+            trailer,
+        }
+    }
+
+    /// Fills `out` with the next bytes of the gzip stream and returns how
+    /// many; fewer than `out.len()` only at the end, and 0 after it.
+    pub fn read(&mut self, out: &mut [u8]) -> usize {
+        let mut n = 0;
+        while n < out.len() {
+            if self.bit_count >= 8 {
+                out[n] = self.bits as u8;
+                self.bits >>= 8;
+                self.bit_count -= 8;
+                n += 1;
+                continue;
+            }
+            match self.stage {
+                Stage::Header(i) if i < HEADER.len() => {
+                    out[n] = HEADER[i];
+                    n += 1;
+                    self.stage = Stage::Header(i + 1);
+                }
+                Stage::Header(_) => {
+                    // BFINAL, then BTYPE 01: fixed codes.
+                    self.put_bits(0b011, 3);
+                    self.stage = Stage::Block;
+                }
+                Stage::Block if self.pos < self.input.len() => self.encode_next(),
+                Stage::Block => {
+                    self.put_symbol(END_OF_BLOCK);
+                    // Up to a whole byte; the trailer is byte-aligned.
+                    self.bit_count = self.bit_count.div_ceil(8) * 8;
+                    self.stage = Stage::Trailer(0);
+                }
+                Stage::Trailer(i) if i < TRAILER_LEN => {
+                    out[n] = self.trailer[i];
+                    n += 1;
+                    self.stage = Stage::Trailer(i + 1);
+                }
+                Stage::Trailer(_) | Stage::Done => {
+                    self.stage = Stage::Done;
+                    break;
+                }
+            }
+        }
+        n
+    }
+
+    /// Encodes a match at `pos`, or the byte there if there is none.
+    fn encode_next(&mut self) {
+        let pos = self.pos;
+        let (length, distance) = self.find_match(pos);
+        if length >= MIN_MATCH {
+            self.put_length(length);
+            self.put_distance(distance);
+            // The positions inside the match still go into the heads.
+            for p in pos + 1..pos + length {
+                self.insert(p);
+            }
+            self.pos += length;
+        } else {
+            self.put_symbol(self.input[pos] as u16);
+            self.pos += 1;
+        }
+    }
+
+    /// The match at `pos` against the last position with the same hash,
+    /// as (length, distance); length 0 for none. Records `pos` meanwhile.
+    fn find_match(&mut self, pos: usize) -> (usize, usize) {
+        let Some(hash) = self.hash(pos) else {
+            return (0, 0);
+        };
+        let previous = self.heads[hash] as usize;
+        self.heads[hash] = (pos + 1) as u16;
+        if previous == 0 || pos - (previous - 1) > MAX_DISTANCE {
+            return (0, 0);
+        }
+        let start = previous - 1;
+        let longest = (self.input.len() - pos).min(MAX_MATCH);
+        let length = (0..longest)
+            .take_while(|&i| self.input[start + i] == self.input[pos + i])
+            .count();
+        (length, pos - start)
+    }
+
+    fn insert(&mut self, pos: usize) {
+        if let Some(hash) = self.hash(pos) {
+            self.heads[hash] = (pos + 1) as u16;
+        }
+    }
+
+    fn hash(&self, pos: usize) -> Option<usize> {
+        let bytes = self.input.get(pos..pos + MIN_MATCH)?;
+        let word = (bytes[0] as u32) << 16 | (bytes[1] as u32) << 8 | bytes[2] as u32;
+        Some((word.wrapping_mul(2_654_435_761) >> (32 - HASH_BITS)) as usize)
+    }
+
+    fn put_length(&mut self, length: usize) {
+        let code = LENGTH_BASE
+            .iter()
+            .rposition(|&base| base as usize <= length)
+            .unwrap_or(0);
+        self.put_symbol(257 + code as u16);
+        let extra = (length - LENGTH_BASE[code] as usize) as u64;
+        self.put_bits(extra, LENGTH_EXTRA[code] as u32);
+    }
+
+    fn put_distance(&mut self, distance: usize) {
+        let code = DISTANCE_BASE
+            .iter()
+            .rposition(|&base| base as usize <= distance)
+            .unwrap_or(0);
+        // Distance codes are five bits, all of them.
+        self.put_code(code as u16, 5);
+        let extra = (distance - DISTANCE_BASE[code] as usize) as u64;
+        self.put_bits(extra, DISTANCE_EXTRA[code] as u32);
+    }
+
+    /// A literal/length symbol in its fixed code (RFC 1951 section 3.2.6).
+    fn put_symbol(&mut self, symbol: u16) {
+        match symbol {
+            0..=143 => self.put_code(0x30 + symbol, 8),
+            144..=255 => self.put_code(0x190 + (symbol - 144), 9),
+            256..=279 => self.put_code(symbol - 256, 7),
+            _ => self.put_code(0xC0 + (symbol - 280), 8),
+        }
+    }
+
+    /// Huffman codes go out from their highest bit down.
+    fn put_code(&mut self, code: u16, len: u32) {
+        let reversed = code.reverse_bits() >> (16 - len);
+        self.put_bits(reversed as u64, len);
+    }
+
+    fn put_bits(&mut self, value: u64, count: u32) {
+        self.bits |= value << self.bit_count;
+        self.bit_count += count;
+    }
+}
+
+/// A body and how long it comes out gzipped, to send with that length.
+#[derive(Debug, Clone, Copy)]
+pub struct Gzipped<'a> {
+    input: &'a [u8],
+    len: usize,
+}
+
+impl<'a> Gzipped<'a> {
+    pub fn compressed_len(&self) -> usize {
+        self.len
+    }
+
+    /// A fresh encoder, to read the `len` bytes from.
+    pub fn encoder(&self) -> Encoder<'a> {
+        Encoder::start(self.input)
+    }
+}
+
+/// Reads `input` through an encoder once for its compressed length.
+/// `None` if it is too long to take.
+pub fn gzip(input: &[u8]) -> Option<Gzipped<'_>> {
+    let mut encoder = Encoder::new(input)?;
+    let mut scratch = [0u8; 64];
+    let mut len = 0;
+    loop {
+        match encoder.read(&mut scratch) {
+            0 => return Some(Gzipped { input, len }),
+            n => len += n,
+        }
+    }
+}
+
+/// `gzip`, but `None` too if that would not be shorter than `input`: a
+/// body that does not shrink is better sent as it is.
+pub fn gzip_smaller(input: &[u8]) -> Option<Gzipped<'_>> {
+    gzip(input).filter(|gzipped| gzipped.compressed_len() < input.len())
+}
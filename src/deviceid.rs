@@ -0,0 +1,86 @@
+// A 32-byte identifier for the chip, to register a device under with a
+// server: the SHA-256 of what eFuse holds that is the chip's own, the
+// factory MAC (src/mac.rs) and then the 16-byte `OPTIONAL_UNIQUE_ID` the
+// factory writes to the system data block. The C3 has no field named
+// CHIP_ID; that one is it.
+//
+// eFuse is burned once and firmware cannot change it, so the identifier
+// stays the same across updates, resets and reflashes, and no image can
+// claim another chip's without its eFuse. It is not a secret: anyone who
+// can read the chip's eFuse can work it out.
+//
+// The flash encryption key is left out, although the key blocks are eFuse
+// too. Once burned for use it is read-protected and reads as zeros, so it
+// adds nothing to the hash, and one burned after the identifier was first
+// registered would change it.
+//
+// `init` works it out at boot on the accelerator (src/integrity.rs) and
+// keeps it in RTC fast memory, with a magic and a CRC like the restart note,
+// so that a reset takes it from there rather than hashing again.
+
+use core::cell::Cell;
+use core::ptr::addr_of_mut;
+
+use critical_section::Mutex;
+use esp_hal::efuse::{Efuse, OPTIONAL_UNIQUE_ID};
+use esp_hal::macros::ram;
+use esp_hal::rom::crc::crc32_le;
+
+use crate::integrity::{IntegrityError, Sha256, HASH_LEN};
+use crate::mac;
+
+const MAGIC: u32 = 0x4445_5644;
+// magic | fingerprint | crc
+const RECORD_LEN: usize = 4 + HASH_LEN + 4;
+
+#[ram(rtc_fast, uninitialized)]
+static mut RECORD: [u8; RECORD_LEN] = [0; RECORD_LEN];
+
+static FINGERPRINT: Mutex<Cell<[u8; HASH_LEN]>> = Mutex::new(Cell::new([0; HASH_LEN]));
+
+/// Takes the fingerprint from RTC memory, or works it out and keeps it
+/// there. Call once at boot, after `integrity::init`.
+pub fn init() -> Result<(), IntegrityError> {
+    let kept = decode(unsafe { &*addr_of_mut!(RECORD) });
+    let fingerprint = match kept {
+        Some(fingerprint) => fingerprint,
+        None => {
+            let fingerprint = compute()?;
+            save(&fingerprint);
+            fingerprint
+        }
+    };
+    critical_section::with(|cs| FINGERPRINT.borrow(cs).set(fingerprint));
+    Ok(())
+}
+
+/// The chip's fingerprint; zeros before `init`.
+pub fn device_fingerprint() -> [u8; HASH_LEN] {
+    critical_section::with(|cs| FINGERPRINT.borrow(cs).get())
+}
+
+fn compute() -> Result<[u8; HASH_LEN], IntegrityError> {
+    let unique_id: [u8; 16] = Efuse::read_field_le(OPTIONAL_UNIQUE_ID);
+    let mut sha = Sha256::new()?;
+    sha.update(&mac::read_mac_address());
+    sha.update(&unique_id);
+    Ok(sha.finish())
+}
+
+fn save(fingerprint: &[u8; HASH_LEN]) {
+    let mut raw = [0u8; RECORD_LEN];
+    raw[0..4].copy_from_slice(&MAGIC.to_le_bytes());
+    raw[4..4 + HASH_LEN].copy_from_slice(fingerprint);
+    let crc = crc32_le(0, &raw[..RECORD_LEN - 4]);
+    raw[RECORD_LEN - 4..].copy_from_slice(&crc.to_le_bytes());
+    unsafe { *addr_of_mut!(RECORD) = raw };
+}
+
+/// The fingerprint, or `None` for anything `save` did not write.
+fn decode(raw: &[u8; RECORD_LEN]) -> Option<[u8; HASH_LEN]> {
+    let word = |at: usize| u32::from_le_bytes(raw[at..at + 4].try_into().unwrap());
+    if word(0) != MAGIC || word(RECORD_LEN - 4) != crc32_le(0, &raw[..RECORD_LEN - 4]) {
+        return None;
+    }
+    raw[4..4 + HASH_LEN].try_into().ok()
+}
@@ -0,0 +1,109 @@
+// DHT22 / AM2302 humidity and temperature sensor.
+//
+// The sensor answers a start pulse with 40 bits, each one a ~50 µs low phase
+// followed by a high phase whose width carries the value: ~27 µs for 0 and
+// ~70 µs for 1. The high phases are timed against the SYSTIMER counter.
+//
+// Like `onewire`, this uses an open-drain pin with the input buffer enabled
+// instead of a `Flex` pin, which esp-hal 0.18 does not have.
+//
+// Only built with the `dht22` feature, for a board that has the sensor on
+// it: nothing in the firmware samples one yet.
+
+use embassy_time::{Duration, Timer as EmbassyTimer};
+use esp_hal::gpio::AnyOutputOpenDrain;
+use esp_hal::timer::systimer::SystemTimer;
+
+const START_SIGNAL_MS: u64 = 18;
+
+// Longest phase in the protocol is 80 µs; anything well beyond that means the
+// sensor stopped talking.
+const PHASE_TIMEOUT_US: u64 = 100;
+
+// High phases longer than this are a 1 bit.
+const ONE_THRESHOLD_US: u64 = 40;
+
+const TICKS_PER_US: u64 = SystemTimer::TICKS_PER_SECOND / 1_000_000;
+
+#[derive(Debug)]
+pub enum DhtError {
+    /// The sensor did not acknowledge the start signal.
+    NoResponse,
+    /// The sensor stopped in the middle of a transfer.
+    Timeout,
+    ChecksumMismatch,
+}
+
+pub struct Dht22<'a> {
+    pin: AnyOutputOpenDrain<'a>,
+}
+
+impl<'a> Dht22<'a> {
+    /// The pin must be open-drain with its initial level high. The sensor
+    /// needs about 2 seconds between reads.
+    pub fn new(pin: AnyOutputOpenDrain<'a>) -> Self {
+        Self { pin }
+    }
+
+    /// Returns `(relative humidity in %, temperature in °C)`.
+    pub async fn read(&mut self) -> Result<(f32, f32), DhtError> {
+        self.pin.set_low();
+        EmbassyTimer::after(Duration::from_millis(START_SIGNAL_MS)).await;
+
+        // The whole transfer takes about 5 ms and has to be timed without
+        // interrupts getting in the way.
+        let data = critical_section::with(|_| self.receive())?;
+
+        let sum = data[..4].iter().fold(0u8, |acc, &b| acc.wrapping_add(b));
+        if sum != data[4] {
+            return Err(DhtError::ChecksumMismatch);
+        }
+
+        let humidity = u16::from_be_bytes([data[0], data[1]]) as f32 / 10.0;
+        let magnitude = u16::from_be_bytes([data[2] & 0x7F, data[3]]) as f32 / 10.0;
+        let temperature = if data[2] & 0x80 != 0 {
+            -magnitude
+        } else {
+            magnitude
+        };
+
+        Ok((humidity, temperature))
+    }
+
+    fn receive(&mut self) -> Result<[u8; 5], DhtError> {
+        self.pin.set_high();
+
+        // Response: the sensor pulls low for 80 µs, then high for 80 µs.
+        self.wait_while(true).map_err(|_| DhtError::NoResponse)?;
+        self.wait_while(false).map_err(|_| DhtError::NoResponse)?;
+        self.wait_while(true).map_err(|_| DhtError::NoResponse)?;
+
+        let mut data = [0u8; 5];
+        for i in 0..40 {
+            self.wait_while(false)?;
+            let high_us = self.wait_while(true)?;
+
+            data[i / 8] <<= 1;
+            if high_us > ONE_THRESHOLD_US {
+                data[i / 8] |= 1;
+            }
+        }
+        Ok(data)
+    }
+
+    /// Busy-waits while the line is at `high`, returning how long that took
+    /// in microseconds.
+    fn wait_while(&self, high: bool) -> Result<u64, DhtError> {
+        let start = SystemTimer::now();
+        loop {
+            let elapsed_us =
+                (SystemTimer::now().wrapping_sub(start) & SystemTimer::BIT_MASK) / TICKS_PER_US;
+            if self.pin.is_high() != high {
+                return Ok(elapsed_us);
+            }
+            if elapsed_us > PHASE_TIMEOUT_US {
+                return Err(DhtError::Timeout);
+            }
+        }
+    }
+}
@@ -0,0 +1,116 @@
+// Turns the low-level errors from DNS, TCP and TLS into a short list of
+// causes that mean something to the person reading the console.
+
+use embassy_net::tcp::ConnectError;
+use embedded_io::ErrorKind;
+use embedded_tls::TlsError;
+
+use crate::https::FetchError;
+use crate::resolver::DnsError;
+
+/// Pipeline step a request failed in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Phase {
+    Url,
+    Dns,
+    Connect,
+    Handshake,
+    Request,
+    Response,
+}
+
+impl Phase {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Phase::Url => "url",
+            Phase::Dns => "dns",
+            Phase::Connect => "connect",
+            Phase::Handshake => "handshake",
+            Phase::Request => "request",
+            Phase::Response => "response",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Cause {
+    InvalidUrl,
+    NameNotFound,
+    ConnectionRefused,
+    NoRoute,
+    Timeout,
+    TlsAlert,
+    TlsProtocol,
+    BufferTooSmall,
+    ConnectionClosed,
+    MalformedResponse,
+    DigestMismatch,
+    NoDigest,
+}
+
+impl Cause {
+    /// Stable identifier for machine-readable output.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Cause::InvalidUrl => "invalid_url",
+            Cause::NameNotFound => "name_not_found",
+            Cause::ConnectionRefused => "connection_refused",
+            Cause::NoRoute => "no_route",
+            Cause::Timeout => "timeout",
+            Cause::TlsAlert => "tls_alert",
+            Cause::TlsProtocol => "tls_protocol",
+            Cause::BufferTooSmall => "buffer_too_small",
+            Cause::ConnectionClosed => "connection_closed",
+            Cause::MalformedResponse => "malformed_response",
+            Cause::DigestMismatch => "digest_mismatch",
+            Cause::NoDigest => "no_digest",
+        }
+    }
+
+    pub fn describe(self) -> &'static str {
+        match self {
+            Cause::InvalidUrl => "URL is not of the form https://host[:port][/path]",
+            Cause::NameNotFound => "host name did not resolve",
+            Cause::ConnectionRefused => "server refused or reset the TCP connection",
+            Cause::NoRoute => "no route to host (is the link up?)",
+            Cause::Timeout => "timed out",
+            Cause::TlsAlert => "server aborted the TLS handshake with an alert",
+            Cause::TlsProtocol => "TLS protocol error",
+            Cause::BufferTooSmall => "TLS record did not fit the record buffer",
+            Cause::ConnectionClosed => "connection closed unexpectedly",
+            Cause::MalformedResponse => "response is not valid HTTP",
+            Cause::DigestMismatch => "body does not match its SHA-256",
+            Cause::NoDigest => "response has no SHA-256 to check the body against",
+        }
+    }
+}
+
+pub fn classify(err: &FetchError) -> Cause {
+    match err {
+        FetchError::InvalidUrl => Cause::InvalidUrl,
+        FetchError::Dns(DnsError::InvalidName) => Cause::InvalidUrl,
+        FetchError::Dns(_) => Cause::NameNotFound,
+        FetchError::Connect(ConnectError::NoRoute) => Cause::NoRoute,
+        FetchError::Connect(ConnectError::TimedOut) => Cause::Timeout,
+        FetchError::Connect(_) => Cause::ConnectionRefused,
+        FetchError::Handshake(e) | FetchError::Write(e) | FetchError::Read(e) => classify_tls(e),
+        FetchError::Timeout(_) => Cause::Timeout,
+        FetchError::MalformedResponse => Cause::MalformedResponse,
+        FetchError::IntegrityMismatch => Cause::DigestMismatch,
+        FetchError::NoDigest => Cause::NoDigest,
+    }
+}
+
+fn classify_tls(err: &TlsError) -> Cause {
+    match err {
+        TlsError::HandshakeAborted(..) | TlsError::AbortHandshake(..) => Cause::TlsAlert,
+        TlsError::InsufficientSpace | TlsError::OutOfMemory => Cause::BufferTooSmall,
+        TlsError::ConnectionClosed => Cause::ConnectionClosed,
+        TlsError::Io(ErrorKind::ConnectionReset | ErrorKind::ConnectionAborted) => {
+            Cause::ConnectionRefused
+        }
+        TlsError::Io(ErrorKind::TimedOut) => Cause::Timeout,
+        TlsError::Io(_) | TlsError::IoError => Cause::ConnectionClosed,
+        _ => Cause::TlsProtocol,
+    }
+}
@@ -0,0 +1,229 @@
+// DNS messages (RFC 1035) as the resolver (src/resolver.rs) and the mDNS
+// responder (src/mdns.rs) read and write them, apart from any socket: a
+// query for an A record, and the checks a response to one has to pass.
+//
+// Everything here reads bytes from the network, so every length in a
+// message is checked against the message, and what it may make the
+// decoder do is bounded:
+//
+// - a name decodes to at most `MAX_NAME_LEN` bytes of dotted text, with
+//   labels of at most 63 and no dot inside one;
+// - at most `MAX_POINTERS` compression pointers are followed within one
+//   name, so a pointer loop ends;
+// - a CNAME chain is followed for at most `MAX_CNAME_DEPTH` links, each a
+//   pass over the answer records, which end with the message.
+//
+// Whatever breaks one of them is `Reject::Malformed`, never a panic.
+// fuzz/ has a target for `check_response` and one for `read_name`.
+
+use heapless::Vec;
+
+use crate::wire::{ReadCursor, WireError, WriteCursor};
+
+pub const MAX_CNAME_DEPTH: usize = 8;
+pub const MAX_NAME_LEN: usize = 255;
+/// Compression pointers followed within one name.
+pub const MAX_POINTERS: usize = 16;
+
+pub const HEADER_LEN: usize = 12;
+const FLAG_RESPONSE: u16 = 0x8000;
+const FLAG_RECURSION_DESIRED: u16 = 0x0100;
+const TYPE_A: u16 = 1;
+const TYPE_CNAME: u16 = 5;
+const CLASS_IN: u16 = 1;
+const RCODE_NAME_ERROR: u8 = 3;
+
+/// Why a response was discarded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reject {
+    /// From an address or port that was not queried.
+    WrongSource,
+    WrongId,
+    /// Not a response, or not to the question asked.
+    WrongQuestion,
+    /// No record in it answers for the queried name.
+    UnrelatedAnswer,
+    Malformed,
+}
+
+impl From<WireError> for Reject {
+    fn from(_: WireError) -> Self {
+        Reject::Malformed
+    }
+}
+
+impl Reject {
+    pub const COUNT: usize = 5;
+
+    pub fn index(self) -> usize {
+        self as usize
+    }
+}
+
+/// What a response that passed the checks says.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Answer {
+    Address([u8; 4]),
+    NotFound,
+    Failed(u8),
+}
+
+/// Writes a recursive query for `host`'s A record; its length, or `None` if
+/// `host` is not a valid name.
+pub fn encode_query(id: u16, host: &str, out: &mut [u8]) -> Option<usize> {
+    let host = host.strip_suffix('.').unwrap_or(host);
+    if host.is_empty() || host.len() > MAX_NAME_LEN - 2 {
+        return None;
+    }
+    let mut out = WriteCursor::new(out);
+    // ID, flags, one question, no records.
+    for word in [id, FLAG_RECURSION_DESIRED, 1, 0, 0, 0] {
+        out.u16(word).ok()?;
+    }
+    for label in host.split('.') {
+        let valid = label
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_');
+        if label.is_empty() || label.len() > 63 || !valid {
+            return None;
+        }
+        out.prefixed_u8(label.as_bytes()).ok()?;
+    }
+    out.u8(0).ok()?;
+    out.u16(TYPE_A).ok()?;
+    out.u16(CLASS_IN).ok()?;
+    Some(out.len())
+}
+
+/// Decides whether `message` answers query `id` for `host`, and with what.
+pub fn check_response(message: &[u8], id: u16, host: &str) -> Result<Answer, Reject> {
+    let host = host.strip_suffix('.').unwrap_or(host).as_bytes();
+    let mut header = ReadCursor::new(message);
+    let (got_id, flags, questions, answers) =
+        (header.u16()?, header.u16()?, header.u16()?, header.u16()?);
+    header.skip(4)?;
+    if got_id != id {
+        return Err(Reject::WrongId);
+    }
+    let opcode = (flags >> 11) & 0xf;
+    if flags & FLAG_RESPONSE == 0 || opcode != 0 || questions != 1 {
+        return Err(Reject::WrongQuestion);
+    }
+
+    let mut name = Name::new();
+    let mut question = ReadCursor::at(message, read_name(message, HEADER_LEN, &mut name)?)?;
+    let (qtype, qclass) = (question.u16()?, question.u16()?);
+    if !name.eq_ignore_ascii_case(host) || qtype != TYPE_A || qclass != CLASS_IN {
+        return Err(Reject::WrongQuestion);
+    }
+    let pos = question.position();
+
+    match (flags & 0xf) as u8 {
+        0 => {}
+        RCODE_NAME_ERROR => return Ok(Answer::NotFound),
+        rcode => return Ok(Answer::Failed(rcode)),
+    }
+    if answers == 0 {
+        return Ok(Answer::NotFound);
+    }
+
+    // Follow the chain from the queried name: each pass looks for an
+    // address of `wanted`, then for a CNAME that moves it on.
+    let first = pos;
+    let mut wanted = Name::new();
+    // Cannot fail: `host` matched a decoded name.
+    let _ = wanted.extend_from_slice(host);
+    for _ in 0..=MAX_CNAME_DEPTH {
+        let mut next = None;
+        let mut pos = first;
+        for _ in 0..answers {
+            let record = read_record(message, pos, &mut name)?;
+            pos = record.end;
+            if record.class != CLASS_IN || !name.eq_ignore_ascii_case(&wanted) {
+                continue;
+            }
+            match (record.rtype, record.data(message)) {
+                (TYPE_A, &[a, b, c, d]) => return Ok(Answer::Address([a, b, c, d])),
+                (TYPE_A, _) => return Err(Reject::Malformed),
+                (TYPE_CNAME, _) if next.is_none() => next = Some(record.data_start),
+                _ => {}
+            }
+        }
+        let Some(target) = next else {
+            break;
+        };
+        read_name(message, target, &mut wanted)?;
+    }
+    Err(Reject::UnrelatedAnswer)
+}
+
+pub type Name = Vec<u8, MAX_NAME_LEN>;
+
+struct Record {
+    rtype: u16,
+    class: u16,
+    data_start: usize,
+    end: usize,
+}
+
+impl Record {
+    fn data<'m>(&self, message: &'m [u8]) -> &'m [u8] {
+        &message[self.data_start..self.end]
+    }
+}
+
+/// The resource record at `pos`; its owner name goes into `owner`.
+fn read_record(message: &[u8], pos: usize, owner: &mut Name) -> Result<Record, Reject> {
+    let mut record = ReadCursor::at(message, read_name(message, pos, owner)?)?;
+    let (rtype, class) = (record.u16()?, record.u16()?);
+    // TTL.
+    record.skip(4)?;
+    let data_len = record.prefixed_u16()?.len();
+    let end = record.position();
+    Ok(Record {
+        rtype,
+        class,
+        data_start: end - data_len,
+        end,
+    })
+}
+
+/// Decodes the name at `pos` into `out` as dotted text, following
+/// compression pointers. Returns where the name ends in the message.
+pub fn read_name(message: &[u8], pos: usize, out: &mut Name) -> Result<usize, Reject> {
+    out.clear();
+    let mut name = ReadCursor::at(message, pos)?;
+    let mut end = None;
+    let mut pointers = 0;
+    loop {
+        let len = name.peek_u8()?;
+        match len {
+            0 => {
+                name.skip(1)?;
+                return Ok(end.unwrap_or(name.position()));
+            }
+            len if len & 0xc0 == 0xc0 => {
+                let target = (name.u16()? & 0x3fff) as usize;
+                pointers += 1;
+                if pointers > MAX_POINTERS {
+                    return Err(Reject::Malformed);
+                }
+                end.get_or_insert(name.position());
+                name.seek(target)?;
+            }
+            len if len <= 63 => {
+                let label = name.prefixed_u8()?;
+                // A dot inside a label would pass for two labels.
+                if label.contains(&b'.') {
+                    return Err(Reject::Malformed);
+                }
+                if !out.is_empty() {
+                    out.push(b'.').map_err(|_| Reject::Malformed)?;
+                }
+                out.extend_from_slice(label)
+                    .map_err(|_| Reject::Malformed)?;
+            }
+            _ => return Err(Reject::Malformed),
+        }
+    }
+}
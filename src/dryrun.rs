@@ -0,0 +1,161 @@
+// Dry run of an upload endpoint: everything an upload does up to the
+// server's answer, with a `HEAD` in place of the `POST`, so that an
+// endpoint can be tried before any reading goes to it.
+//
+// `run` sends the `HEAD` with the settings' headers, which carry the
+// credentials, and reports how far it got, one entry per step:
+//
+//     {"v":1,"ok":false,"steps":{"url":"pass","dns":"pass","connect":"timeout",
+//      "handshake":"skip","request":"skip","response":"skip","auth":"skip"},
+//      "status":null,"ms":10034}
+//
+// (on one line). The steps are those of a request (src/diag.rs) and then
+// `auth`. A step that failed has its cause (`diag::Cause::as_str`) in place
+// of `pass`, and the steps after it are `skip`. 401 and 403 fail `auth` as
+// `refused`; any other status passes it, 405 for the `HEAD` included, since
+// the server got as far as the method with the credentials.
+//
+// A dry run sends no reading and reads none: the uploader runs it between
+// cycles, next to its queue rather than through it (src/uploader.rs).
+
+use core::cmp::Ordering;
+use core::fmt::{self, Write};
+
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::signal::Signal;
+use embassy_time::Instant;
+use heapless::String;
+
+use crate::diag::{self, Phase};
+use crate::https::{self, FetchError};
+use crate::settings::MAX_URL_LEN;
+use crate::NetStack;
+
+const FORMAT_VERSION: u32 = 1;
+
+/// Room for the longest report `write_json` writes.
+pub const MAX_REPORT_LEN: usize = 256;
+
+static REQUESTED: Signal<CriticalSectionRawMutex, String<MAX_URL_LEN>> = Signal::new();
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Step {
+    Request(Phase),
+    Auth,
+}
+
+const STEPS: [Step; 7] = [
+    Step::Request(Phase::Url),
+    Step::Request(Phase::Dns),
+    Step::Request(Phase::Connect),
+    Step::Request(Phase::Handshake),
+    Step::Request(Phase::Request),
+    Step::Request(Phase::Response),
+    Step::Auth,
+];
+
+impl Step {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Step::Request(phase) => phase.as_str(),
+            Step::Auth => "auth",
+        }
+    }
+
+    fn index(self) -> usize {
+        STEPS.iter().position(|&step| step == self).unwrap_or(0)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Report {
+    /// The step that did not pass, and why.
+    pub failed: Option<(Step, &'static str)>,
+    /// The server's answer, if there was one.
+    pub status: Option<u16>,
+    pub ms: u64,
+}
+
+impl Report {
+    /// The report for a `HEAD` that was answered `status` or failed.
+    pub fn new(result: Result<u16, &FetchError>, ms: u64) -> Self {
+        let (failed, status) = match result {
+            Ok(status @ (401 | 403)) => (Some((Step::Auth, "refused")), Some(status)),
+            Ok(status) => (None, Some(status)),
+            Err(e) => (
+                Some((Step::Request(e.phase()), diag::classify(e).as_str())),
+                None,
+            ),
+        };
+        Self { failed, status, ms }
+    }
+
+    pub fn ok(&self) -> bool {
+        self.failed.is_none()
+    }
+
+    /// `pass`, the cause `step` failed with, or `skip`.
+    pub fn outcome(&self, step: Step) -> &'static str {
+        match self.failed {
+            None => "pass",
+            Some((failed, why)) => match step.index().cmp(&failed.index()) {
+                Ordering::Less => "pass",
+                Ordering::Equal => why,
+                Ordering::Greater => "skip",
+            },
+        }
+    }
+
+    pub fn write_json<W: Write>(&self, out: &mut W) -> fmt::Result {
+        write!(
+            out,
+            "{{\"v\":{},\"ok\":{},\"steps\":{{",
+            FORMAT_VERSION,
+            self.ok()
+        )?;
+        for (i, step) in STEPS.into_iter().enumerate() {
+            let comma = if i > 0 { "," } else { "" };
+            write!(
+                out,
+                "{}\"{}\":\"{}\"",
+                comma,
+                step.as_str(),
+                self.outcome(step)
+            )?;
+        }
+        out.write_str("},\"status\":")?;
+        match self.status {
+            Some(status) => write!(out, "{}", status)?,
+            None => out.write_str("null")?,
+        }
+        write!(out, ",\"ms\":{}}}", self.ms)
+    }
+}
+
+/// Asks the uploader for a dry run of `url`, or of its upload URL if
+/// `url` is empty. `false` if `url` is too long to be one.
+#[cfg(feature = "console")]
+pub fn request(url: &str) -> bool {
+    match String::try_from(url) {
+        Ok(url) => {
+            REQUESTED.signal(url);
+            true
+        }
+        Err(_) => false,
+    }
+}
+
+/// The URL of the next dry run asked for; empty for the upload URL.
+pub async fn requested() -> String<MAX_URL_LEN> {
+    REQUESTED.wait().await
+}
+
+pub async fn run(stack: &NetStack, url: &str) -> Report {
+    let start = Instant::now();
+    let mut response = [0u8; 512];
+    let result = https::head(stack, url, &mut response).await;
+    Report::new(
+        result.as_ref().map(|answer| answer.status),
+        start.elapsed().as_millis(),
+    )
+}
@@ -0,0 +1,104 @@
+// DS18B20 digital thermometer on a 1-Wire bus.
+//
+// Only built with the `ds18b20` feature, like src/onewire.rs.
+
+use embassy_time::{Duration, Timer as EmbassyTimer};
+use heapless::Vec;
+
+use crate::onewire::{crc8, OneWireBus};
+
+const FAMILY_CODE: u8 = 0x28;
+
+const CMD_CONVERT_T: u8 = 0x44;
+const CMD_READ_SCRATCHPAD: u8 = 0xBE;
+
+// Worst case conversion time at the default 12-bit resolution.
+const CONVERSION_TIME_MS: u64 = 750;
+
+// Scratchpad value after power-on, before any conversion has completed.
+const POWER_ON_RESET_RAW: i16 = 0x0550;
+
+#[derive(Debug)]
+pub enum SensorError {
+    /// Nothing answered the reset pulse.
+    NoPresence,
+    /// The scratchpad CRC did not match; usually a noisy or too long bus.
+    CrcMismatch,
+    /// The sensor returned its power-on value (85 °C), i.e. it browned out
+    /// or never ran a conversion.
+    NotConverted,
+}
+
+pub struct Ds18b20<'a> {
+    bus: OneWireBus<'a>,
+    rom: Option<[u8; 8]>,
+}
+
+impl<'a> Ds18b20<'a> {
+    /// With `rom` set to `None` every command is sent as Skip ROM, which only
+    /// works with a single sensor on the bus.
+    pub fn new(bus: OneWireBus<'a>, rom: Option<[u8; 8]>) -> Self {
+        Self { bus, rom }
+    }
+
+    /// Switches to another sensor on the same bus.
+    pub fn set_rom(&mut self, rom: Option<[u8; 8]>) {
+        self.rom = rom;
+    }
+
+    pub fn bus(&mut self) -> &mut OneWireBus<'a> {
+        &mut self.bus
+    }
+
+    /// Lists the ROM codes of all DS18B20s on the bus, skipping other device
+    /// families.
+    pub fn enumerate(bus: &mut OneWireBus, addrs: &mut Vec<[u8; 8], 8>) {
+        let mut found: Vec<[u8; 8], 8> = Vec::new();
+        bus.search(&mut found);
+
+        addrs.clear();
+        for rom in found.into_iter().filter(|rom| rom[0] == FAMILY_CODE) {
+            // Both vectors have the same capacity.
+            let _ = addrs.push(rom);
+        }
+    }
+
+    /// Starts a conversion and waits until it has finished. Without a ROM
+    /// selected this converts on all sensors at once, so they can be read one
+    /// after the other afterwards.
+    pub async fn start_conversion(&mut self) -> Result<(), SensorError> {
+        if !self.bus.select(self.rom.as_ref()) {
+            return Err(SensorError::NoPresence);
+        }
+        self.bus.write_byte(CMD_CONVERT_T);
+
+        EmbassyTimer::after(Duration::from_millis(CONVERSION_TIME_MS)).await;
+        Ok(())
+    }
+
+    /// Runs a conversion and returns the temperature in °C.
+    pub async fn read_temperature(&mut self) -> Result<f32, SensorError> {
+        self.start_conversion().await?;
+
+        if !self.bus.select(self.rom.as_ref()) {
+            return Err(SensorError::NoPresence);
+        }
+        self.bus.write_byte(CMD_READ_SCRATCHPAD);
+
+        let mut scratchpad = [0u8; 9];
+        self.bus.read_bytes(&mut scratchpad);
+
+        // A bus stuck low reads as all zeros, which has a valid CRC.
+        if crc8(&scratchpad) != 0 || scratchpad.iter().all(|&b| b == 0) {
+            return Err(SensorError::CrcMismatch);
+        }
+
+        let raw = i16::from_le_bytes([scratchpad[0], scratchpad[1]]);
+        if raw == POWER_ON_RESET_RAW {
+            return Err(SensorError::NotConverted);
+        }
+
+        // 12-bit resolution: 1/16 °C per LSB.
+        Ok(raw as f32 / 16.0)
+    }
+}
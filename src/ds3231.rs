@@ -0,0 +1,281 @@
+// DS3231 battery-backed real-time clock on I2C.
+//
+// The chip keeps UTC here, in 24-hour mode. It gives the device a wall clock
+// straight from boot, before (or without) SNTP, and SNTP keeps correcting it.
+//
+// Alarm 1 drives the INT/SQW pin low when it matches, which can wake the chip
+// from deep sleep when that pin is wired to an RTC GPIO.
+
+use esp_hal::i2c::Error as I2cError;
+
+use crate::bus::SharedI2c;
+
+pub const ADDRESS: u8 = 0x68;
+
+const REG_SECONDS: u8 = 0x00;
+const REG_ALARM1: u8 = 0x07;
+const REG_CONTROL: u8 = 0x0E;
+const REG_STATUS: u8 = 0x0F;
+
+// Control: interrupt output instead of square wave, alarm 1 interrupt enable.
+const CONTROL_INTCN: u8 = 1 << 2;
+const CONTROL_A1IE: u8 = 1 << 0;
+// Status: oscillator stopped (time lost), alarm 1 matched.
+const STATUS_OSF: u8 = 1 << 7;
+const STATUS_A1F: u8 = 1 << 0;
+
+// Set in an alarm register to leave that field out of the match.
+const ALARM_MASK: u8 = 1 << 7;
+
+const MONTH_CENTURY: u8 = 1 << 7;
+const HOUR_12H: u8 = 1 << 6;
+
+#[derive(Debug)]
+pub enum RtcError {
+    I2c(#[allow(dead_code)] I2cError),
+    /// The oscillator stopped at some point, e.g. the backup battery ran
+    /// flat, so the stored time is meaningless until `set_time`.
+    TimeLost,
+    /// Registers hold something that is not a valid date.
+    InvalidTime,
+}
+
+impl From<I2cError> for RtcError {
+    fn from(e: I2cError) -> Self {
+        RtcError::I2c(e)
+    }
+}
+
+/// Calendar time in UTC. The DS3231 covers 2000 to 2199.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DateTime {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+}
+
+impl DateTime {
+    pub fn from_unix(seconds: u64) -> Self {
+        let days = (seconds / 86_400) as i64;
+        let rem = seconds % 86_400;
+        let (year, month, day) = civil_from_days(days);
+        Self {
+            year: year as u16,
+            month,
+            day,
+            hour: (rem / 3600) as u8,
+            minute: (rem / 60 % 60) as u8,
+            second: (rem % 60) as u8,
+        }
+    }
+
+    pub fn to_unix(self) -> u64 {
+        let days = days_from_civil(self.year as i64, self.month, self.day);
+        days as u64 * 86_400
+            + self.hour as u64 * 3600
+            + self.minute as u64 * 60
+            + self.second as u64
+    }
+
+    fn is_valid(&self) -> bool {
+        (2000..2200).contains(&self.year)
+            && (1..=12).contains(&self.month)
+            && (1..=days_in_month(self.year, self.month)).contains(&self.day)
+            && self.hour < 24
+            && self.minute < 60
+            && self.second < 60
+    }
+}
+
+pub struct Ds3231<'a> {
+    bus: &'a SharedI2c,
+}
+
+impl<'a> Ds3231<'a> {
+    pub fn new(bus: &'a SharedI2c) -> Self {
+        Self { bus }
+    }
+
+    pub async fn read_time(&mut self) -> Result<DateTime, RtcError> {
+        if self.read_register(REG_STATUS).await? & STATUS_OSF != 0 {
+            return Err(RtcError::TimeLost);
+        }
+
+        let mut regs = [0u8; 7];
+        self.bus
+            .lock()
+            .await
+            .write_read(ADDRESS, &[REG_SECONDS], &mut regs)
+            .await?;
+        // Written by someone else in 12-hour mode; we never set that.
+        if regs[2] & HOUR_12H != 0 {
+            return Err(RtcError::InvalidTime);
+        }
+
+        let century = if regs[5] & MONTH_CENTURY != 0 { 100 } else { 0 };
+        let time = DateTime {
+            year: 2000 + century + from_bcd(regs[6]) as u16,
+            month: from_bcd(regs[5] & 0x1F),
+            day: from_bcd(regs[4] & 0x3F),
+            hour: from_bcd(regs[2] & 0x3F),
+            minute: from_bcd(regs[1] & 0x7F),
+            second: from_bcd(regs[0] & 0x7F),
+        };
+        if !time.is_valid() {
+            return Err(RtcError::InvalidTime);
+        }
+        Ok(time)
+    }
+
+    /// Sets the clock and clears the oscillator-stopped flag.
+    pub async fn set_time(&mut self, time: DateTime) -> Result<(), RtcError> {
+        if !time.is_valid() {
+            return Err(RtcError::InvalidTime);
+        }
+        let century = if time.year >= 2100 { MONTH_CENTURY } else { 0 };
+        // Day of week, 1 to 7 with 1 = Monday. 1970-01-01 was a Thursday.
+        let weekday = ((time.to_unix() / 86_400 + 3) % 7 + 1) as u8;
+        self.bus
+            .lock()
+            .await
+            .write(
+                ADDRESS,
+                &[
+                    REG_SECONDS,
+                    to_bcd(time.second),
+                    to_bcd(time.minute),
+                    to_bcd(time.hour),
+                    weekday,
+                    to_bcd(time.day),
+                    to_bcd(time.month) | century,
+                    to_bcd((time.year % 100) as u8),
+                ],
+            )
+            .await?;
+
+        let status = self.read_register(REG_STATUS).await?;
+        self.write_register(REG_STATUS, status & !STATUS_OSF).await
+    }
+
+    /// Arms alarm 1 to fire every day at the given UTC time and routes it to
+    /// the INT/SQW pin.
+    pub async fn set_daily_alarm(
+        &mut self,
+        hour: u8,
+        minute: u8,
+        second: u8,
+    ) -> Result<(), RtcError> {
+        if hour > 23 || minute > 59 || second > 59 {
+            return Err(RtcError::InvalidTime);
+        }
+        // Match seconds, minutes and hours; ignore the day.
+        self.bus
+            .lock()
+            .await
+            .write(
+                ADDRESS,
+                &[
+                    REG_ALARM1,
+                    to_bcd(second),
+                    to_bcd(minute),
+                    to_bcd(hour),
+                    ALARM_MASK,
+                ],
+            )
+            .await?;
+        self.clear_alarm().await?;
+        let control = self.read_register(REG_CONTROL).await?;
+        self.write_register(REG_CONTROL, control | CONTROL_INTCN | CONTROL_A1IE)
+            .await
+    }
+
+    /// Whether alarm 1 has matched since the last `clear_alarm`.
+    pub async fn alarm_fired(&mut self) -> Result<bool, RtcError> {
+        Ok(self.read_register(REG_STATUS).await? & STATUS_A1F != 0)
+    }
+
+    /// Acknowledges alarm 1, which releases the INT/SQW pin.
+    pub async fn clear_alarm(&mut self) -> Result<(), RtcError> {
+        let status = self.read_register(REG_STATUS).await?;
+        self.write_register(REG_STATUS, status & !STATUS_A1F).await
+    }
+
+    pub async fn disable_alarm(&mut self) -> Result<(), RtcError> {
+        let control = self.read_register(REG_CONTROL).await?;
+        self.write_register(REG_CONTROL, control & !CONTROL_A1IE)
+            .await?;
+        self.clear_alarm().await
+    }
+
+    async fn read_register(&mut self, register: u8) -> Result<u8, RtcError> {
+        let mut value = [0u8];
+        self.bus
+            .lock()
+            .await
+            .write_read(ADDRESS, &[register], &mut value)
+            .await?;
+        Ok(value[0])
+    }
+
+    async fn write_register(&mut self, register: u8, value: u8) -> Result<(), RtcError> {
+        self.bus
+            .lock()
+            .await
+            .write(ADDRESS, &[register, value])
+            .await?;
+        Ok(())
+    }
+}
+
+fn from_bcd(value: u8) -> u8 {
+    (value >> 4) * 10 + (value & 0x0F)
+}
+
+fn to_bcd(value: u8) -> u8 {
+    ((value / 10) << 4) | (value % 10)
+}
+
+fn is_leap_year(year: u16) -> bool {
+    (year % 4 == 0 && year % 100 != 0) || year % 400 == 0
+}
+
+fn days_in_month(year: u16, month: u8) -> u8 {
+    match month {
+        2 if is_leap_year(year) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+// Day conversions after Howard Hinnant's `days_from_civil` and
+// `civil_from_days`, with March as the first month of the year so the leap
+// day comes last.
+
+fn days_from_civil(year: i64, month: u8, day: u8) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let month = month as i64;
+    let day_of_year =
+        (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + day as i64 - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+fn civil_from_days(days: i64) -> (i64, u8, u8) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days - era * 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * mp + 2) / 5 + 1) as u8;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u8;
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
@@ -0,0 +1,88 @@
+// Quadrature rotary encoder.
+//
+// The ESP32-C3 has no PCNT peripheral, so the two phases are decoded in
+// software from GPIO edge interrupts instead. Every edge on either phase is
+// one count (x4 decoding), which is plenty for a hand-turned knob or a slow
+// shaft but will miss steps well before a motor encoder at speed would.
+//
+// Counting happens while a task is inside `wait_for_delta`; a UI loop that
+// always sits in it sees every step. `read_count` only picks up the current
+// pin state on top of that.
+//
+// Only built with the `encoder` feature: nothing in the firmware has a knob
+// to read yet, the touch pad being the factory reset button.
+
+use embassy_futures::select::select;
+use esp_hal::gpio::AnyInput;
+
+// Indexed by `previous << 2 | current`, where each state is `a << 1 | b`.
+// Transitions where both phases changed at once are bounces or missed edges
+// and count as nothing.
+#[rustfmt::skip]
+const TRANSITIONS: [i8; 16] = [
+     0, -1,  1,  0,
+     1,  0,  0, -1,
+    -1,  0,  0,  1,
+     0,  1, -1,  0,
+];
+
+pub struct QuadratureEncoder<'a> {
+    a: AnyInput<'a>,
+    b: AnyInput<'a>,
+    state: u8,
+    count: i32,
+}
+
+impl<'a> QuadratureEncoder<'a> {
+    /// Both pins should be inputs with pull-ups, the encoder switching them
+    /// to ground.
+    pub fn new(a: AnyInput<'a>, b: AnyInput<'a>) -> Self {
+        let mut encoder = Self {
+            a,
+            b,
+            state: 0,
+            count: 0,
+        };
+        encoder.state = encoder.sample();
+        encoder
+    }
+
+    /// Signed count since creation or the last `clear`.
+    pub fn read_count(&mut self) -> i32 {
+        self.step();
+        self.count
+    }
+
+    pub fn clear(&mut self) {
+        self.state = self.sample();
+        self.count = 0;
+    }
+
+    /// Waits until the count has moved `threshold` steps in either direction
+    /// from where it is now, and returns the delta.
+    pub async fn wait_for_delta(&mut self, threshold: i32) -> i32 {
+        let start = self.read_count();
+        let threshold = threshold.unsigned_abs().max(1);
+
+        loop {
+            select(self.a.wait_for_any_edge(), self.b.wait_for_any_edge()).await;
+            self.step();
+
+            let delta = self.count.wrapping_sub(start);
+            if delta.unsigned_abs() >= threshold {
+                return delta;
+            }
+        }
+    }
+
+    fn sample(&self) -> u8 {
+        (self.a.is_high() as u8) << 1 | self.b.is_high() as u8
+    }
+
+    fn step(&mut self) {
+        let current = self.sample();
+        let delta = TRANSITIONS[(self.state << 2 | current) as usize];
+        self.state = current;
+        self.count = self.count.wrapping_add(delta as i32);
+    }
+}
@@ -0,0 +1,248 @@
+// WPA2-Enterprise (802.1X, PEAP with MSCHAPv2 inside) for networks that
+// have no pre-shared key.
+//
+// The credentials live in the KV store under `wifi.eap.*`, set from the
+// console (`eap ...`). With a username and password stored, main
+// associates to SSID as an enterprise network and PASSWORD is not used.
+// The identity, the outer name the RADIUS server sees first, defaults to
+// the username.
+// The KV store is plain flash: keeping the password from a flash dump
+// takes flash encryption on the device.
+//
+// The RADIUS server's certificate is only checked when the firmware is
+// built with EAP_CA_CERT, the PEM text of the CA to check it against. The
+// check skips the validity dates: association comes before SNTP, so there
+// is no clock to check them with.
+//
+// esp-wifi has no API for this; the `enterprise` feature calls the
+// supplicant in the Wi-Fi blobs directly. Without the feature, stored
+// credentials are reported and ignored, and main falls back to the PSK.
+//
+// The password is never printed: `Credentials`' `Debug` leaves it out, and
+// the console only says whether one is set.
+
+use core::fmt;
+
+use esp_println::println;
+use heapless::String;
+
+use crate::kv;
+
+pub const MAX_FIELD_LEN: usize = 64;
+
+const KEY_IDENTITY: &str = "wifi.eap.identity";
+const KEY_USERNAME: &str = "wifi.eap.username";
+const KEY_PASSWORD: &str = "wifi.eap.password";
+
+#[cfg(feature = "enterprise")]
+const CA_PEM: Option<&str> = option_env!("EAP_CA_CERT");
+#[cfg(feature = "enterprise")]
+const CA_LEN: usize = match CA_PEM {
+    Some(pem) => pem.len() + 1,
+    None => 0,
+};
+/// `CA_PEM` with the NUL the certificate parser wants after PEM. Static:
+/// the supplicant keeps the pointer, not a copy.
+#[cfg(feature = "enterprise")]
+static CA_CERT: [u8; CA_LEN] = {
+    let mut cert = [0u8; CA_LEN];
+    if let Some(pem) = CA_PEM {
+        let pem = pem.as_bytes();
+        let mut i = 0;
+        while i < pem.len() {
+            cert[i] = pem[i];
+            i += 1;
+        }
+    }
+    cert
+};
+
+/// `eap_client_get_eap_state` after the EAP exchange was refused.
+#[cfg(feature = "enterprise")]
+const EAP_STATE_FAIL: i32 = 3;
+
+#[cfg(feature = "enterprise")]
+extern "C" {
+    fn esp_eap_client_set_identity(identity: *const u8, len: i32) -> i32;
+    fn esp_eap_client_set_username(username: *const u8, len: i32) -> i32;
+    fn esp_eap_client_set_password(password: *const u8, len: i32) -> i32;
+    fn esp_eap_client_set_ca_cert(ca_cert: *const u8, len: i32) -> i32;
+    fn esp_eap_client_set_disable_time_check(disable: bool) -> i32;
+    fn esp_wifi_sta_enterprise_enable() -> i32;
+    fn eap_client_get_eap_state() -> i32;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EnterpriseError {
+    /// Built without the `enterprise` feature.
+    #[cfg(not(feature = "enterprise"))]
+    Unsupported,
+    /// The supplicant refused a setting; `esp_err_t`.
+    #[cfg(feature = "enterprise")]
+    Supplicant(i32),
+}
+
+impl fmt::Display for EnterpriseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            #[cfg(not(feature = "enterprise"))]
+            EnterpriseError::Unsupported => {
+                f.write_str("this build has no WPA2-Enterprise support (feature `enterprise`)")
+            }
+            #[cfg(feature = "enterprise")]
+            EnterpriseError::Supplicant(code) => write!(f, "supplicant error {:#x}", code),
+        }
+    }
+}
+
+/// A console field name and its KV key.
+#[cfg(feature = "console")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Field {
+    Identity,
+    Username,
+    Password,
+}
+
+#[cfg(feature = "console")]
+impl Field {
+    pub fn parse(name: &str) -> Option<Field> {
+        match name {
+            "identity" => Some(Field::Identity),
+            "username" => Some(Field::Username),
+            "password" => Some(Field::Password),
+            _ => None,
+        }
+    }
+
+    fn key(self) -> &'static str {
+        match self {
+            Field::Identity => KEY_IDENTITY,
+            Field::Username => KEY_USERNAME,
+            Field::Password => KEY_PASSWORD,
+        }
+    }
+}
+
+pub struct Credentials {
+    pub identity: String<MAX_FIELD_LEN>,
+    pub username: String<MAX_FIELD_LEN>,
+    #[cfg_attr(not(feature = "enterprise"), allow(dead_code))]
+    password: String<MAX_FIELD_LEN>,
+}
+
+impl fmt::Debug for Credentials {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Credentials")
+            .field("identity", &self.identity)
+            .field("username", &self.username)
+            .finish_non_exhaustive()
+    }
+}
+
+/// The stored credentials; `None` unless a username and a password are.
+pub async fn load() -> Option<Credentials> {
+    let username = read(KEY_USERNAME).await?;
+    let password = read(KEY_PASSWORD).await?;
+    let identity = read(KEY_IDENTITY).await.unwrap_or_else(|| username.clone());
+    Some(Credentials {
+        identity,
+        username,
+        password,
+    })
+}
+
+async fn read(key: &str) -> Option<String<MAX_FIELD_LEN>> {
+    let mut buf = [0u8; MAX_FIELD_LEN];
+    let len = match kv::get(key, &mut buf).await {
+        Ok(Some(len)) => len,
+        Ok(None) => return None,
+        Err(e) => {
+            println!("enterprise: cannot read {}: {:?}", key, e);
+            return None;
+        }
+    };
+    let text = core::str::from_utf8(&buf[..len]).ok()?;
+    String::try_from(text).ok().filter(|text| !text.is_empty())
+}
+
+/// Stores `value` for `field`; in use from the next association, i.e. the
+/// next boot.
+#[cfg(feature = "console")]
+pub async fn store(field: Field, value: &str) -> Result<(), kv::KvError> {
+    if value.len() > MAX_FIELD_LEN {
+        return Err(kv::KvError::ValueTooLong);
+    }
+    kv::set(field.key(), value.as_bytes()).await
+}
+
+/// Removes all three fields, which makes the next boot use the PSK.
+#[cfg(feature = "console")]
+pub async fn clear() -> Result<(), kv::KvError> {
+    for key in [KEY_IDENTITY, KEY_USERNAME, KEY_PASSWORD] {
+        kv::remove(key).await?;
+    }
+    Ok(())
+}
+
+/// Whether a field is stored, for the console.
+#[cfg(feature = "console")]
+pub async fn is_set(field: Field) -> bool {
+    read(field.key()).await.is_some()
+}
+
+/// Hands `credentials` to the supplicant and turns 802.1X on. Call before
+/// the controller starts.
+#[cfg(feature = "enterprise")]
+pub fn enable(credentials: &Credentials) -> Result<(), EnterpriseError> {
+    let check = |code: i32| match code {
+        0 => Ok(()),
+        code => Err(EnterpriseError::Supplicant(code)),
+    };
+    // The supplicant copies all but the CA certificate.
+    unsafe {
+        let identity = credentials.identity.as_bytes();
+        check(esp_eap_client_set_identity(
+            identity.as_ptr(),
+            identity.len() as i32,
+        ))?;
+        let username = credentials.username.as_bytes();
+        check(esp_eap_client_set_username(
+            username.as_ptr(),
+            username.len() as i32,
+        ))?;
+        let password = credentials.password.as_bytes();
+        check(esp_eap_client_set_password(
+            password.as_ptr(),
+            password.len() as i32,
+        ))?;
+        if CA_PEM.is_some() {
+            check(esp_eap_client_set_ca_cert(CA_CERT.as_ptr(), CA_LEN as i32))?;
+            check(esp_eap_client_set_disable_time_check(true))?;
+        }
+        check(esp_wifi_sta_enterprise_enable())
+    }
+}
+
+#[cfg(not(feature = "enterprise"))]
+pub fn enable(_credentials: &Credentials) -> Result<(), EnterpriseError> {
+    Err(EnterpriseError::Unsupported)
+}
+
+/// Whether the RADIUS server refused the last EAP exchange, as opposed to
+/// the network not being found or not answering.
+#[cfg(feature = "enterprise")]
+pub fn eap_failed() -> bool {
+    unsafe { eap_client_get_eap_state() == EAP_STATE_FAIL }
+}
+
+#[cfg(not(feature = "enterprise"))]
+pub fn eap_failed() -> bool {
+    false
+}
+
+/// Whether the RADIUS server's certificate is checked.
+#[cfg(feature = "enterprise")]
+pub const CHECKS_SERVER: bool = CA_PEM.is_some();
+#[cfg(not(feature = "enterprise"))]
+pub const CHECKS_SERVER: bool = false;
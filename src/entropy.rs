@@ -0,0 +1,64 @@
+// The hardware RNG, for code that cannot be handed the peripheral.
+//
+// The RNG is only random with the radio on, so `init` happens right
+// before esp-wifi's, which turns it on.
+//
+// It also makes the UUIDs that tell messages apart: each reading's
+// `message_id` (src/telemetry.rs) and each request's `X-Request-Id`
+// (src/https.rs), which src/codec.rs writes out.
+
+use core::cell::Cell;
+
+use critical_section::Mutex;
+use esp_hal::rng::Rng;
+use rand_core::{CryptoRng, Error as RandError, RngCore};
+
+static RNG: Mutex<Cell<Option<Rng>>> = Mutex::new(Cell::new(None));
+
+/// Call once at boot, before anything draws from it.
+pub fn init(rng: Rng) {
+    critical_section::with(|cs| RNG.borrow(cs).set(Some(rng)));
+}
+
+pub fn random_u32() -> u32 {
+    critical_section::with(|cs| {
+        let mut rng = RNG.borrow(cs).get().expect("entropy::init not called");
+        rng.random()
+    })
+}
+
+/// `RngCore` over the shared RNG, for the TLS handshake.
+pub struct HardwareRng;
+
+impl RngCore for HardwareRng {
+    fn next_u32(&mut self) -> u32 {
+        random_u32()
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        (random_u32() as u64) << 32 | random_u32() as u64
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        for chunk in dest.chunks_mut(4) {
+            let bytes = random_u32().to_ne_bytes();
+            chunk.copy_from_slice(&bytes[..chunk.len()]);
+        }
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), RandError> {
+        self.fill_bytes(dest);
+        Ok(())
+    }
+}
+
+impl CryptoRng for HardwareRng {}
+
+/// A random UUID, version 4 (RFC 4122 section 4.4).
+pub fn uuid_v4(rng: &mut impl RngCore) -> [u8; 16] {
+    let mut uuid = [0u8; 16];
+    rng.fill_bytes(&mut uuid);
+    uuid[6] = (uuid[6] & 0x0f) | 0x40;
+    uuid[8] = (uuid[8] & 0x3f) | 0x80;
+    uuid
+}
@@ -0,0 +1,116 @@
+// Flash writes in the smallest units the chip has, one sector erase or one
+// page program at a time, with an await after each.
+//
+// Every erase or program turns the flash cache off until it completes, which
+// stalls anything running from flash, the Wi-Fi driver included; a sector
+// erase takes tens of milliseconds. Doing a whole update or compaction in one
+// go is long enough to miss beacons and get disassociated. Yielding between
+// units lets the radio catch up.
+//
+// For the same reason the uploader holds back its traffic during an erase
+// burst (`busy`, `wait_idle`). The esp-wifi version used here only sets
+// modem power save at build time, so deferring is all that can be done at
+// run time.
+
+use core::cell::Cell;
+
+use critical_section::Mutex;
+use embassy_futures::yield_now;
+use embassy_time::{Duration, Instant, Timer as EmbassyTimer};
+use embedded_storage::nor_flash::NorFlash;
+use esp_storage::{FlashStorage, FlashStorageError};
+
+pub const SECTOR_SIZE: u32 = FlashStorage::SECTOR_SIZE;
+/// Largest unit a single program operation covers.
+pub const PAGE_SIZE: u32 = 256;
+
+const IDLE_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Nesting depth of the erase bursts under way.
+static BURSTS: Mutex<Cell<u32>> = Mutex::new(Cell::new(0));
+/// Longest single erase or program so far, in microseconds.
+static LONGEST_STALL_US: Mutex<Cell<u64>> = Mutex::new(Cell::new(0));
+
+/// Marks a run of erases (and the writes that go with them) for as long as
+/// it lives.
+pub struct Burst(());
+
+impl Drop for Burst {
+    fn drop(&mut self) {
+        critical_section::with(|cs| {
+            let bursts = BURSTS.borrow(cs);
+            bursts.set(bursts.get().saturating_sub(1));
+        });
+    }
+}
+
+pub fn burst() -> Burst {
+    critical_section::with(|cs| {
+        let bursts = BURSTS.borrow(cs);
+        bursts.set(bursts.get() + 1);
+    });
+    Burst(())
+}
+
+/// Whether an erase burst is under way.
+pub fn busy() -> bool {
+    critical_section::with(|cs| BURSTS.borrow(cs).get()) > 0
+}
+
+/// Waits until no erase burst is under way, e.g. before sending.
+pub async fn wait_idle() {
+    while busy() {
+        EmbassyTimer::after(IDLE_POLL_INTERVAL).await;
+    }
+}
+
+/// Longest the flash has kept the cache off in one go since boot.
+pub fn longest_stall() -> Duration {
+    Duration::from_micros(critical_section::with(|cs| {
+        LONGEST_STALL_US.borrow(cs).get()
+    }))
+}
+
+/// Erases the sectors covering `from..to`, both sector aligned.
+pub async fn erase(flash: &mut FlashStorage, from: u32, to: u32) -> Result<(), FlashStorageError> {
+    let _burst = burst();
+    let mut sector = from;
+    while sector < to {
+        timed(|| flash.erase(sector, sector + SECTOR_SIZE))?;
+        yield_now().await;
+        sector += SECTOR_SIZE;
+    }
+    Ok(())
+}
+
+/// Writes `data` at `offset` one page at a time. Alignment rules are the
+/// flash's own: word-aligned offset and length.
+pub async fn write(
+    flash: &mut FlashStorage,
+    offset: u32,
+    data: &[u8],
+) -> Result<(), FlashStorageError> {
+    let mut offset = offset;
+    let mut data = data;
+    while !data.is_empty() {
+        // Up to the next page boundary, so no program spans two pages.
+        let room = (PAGE_SIZE - offset % PAGE_SIZE) as usize;
+        let (page, rest) = data.split_at(room.min(data.len()));
+        timed(|| flash.write(offset, page))?;
+        yield_now().await;
+        offset += page.len() as u32;
+        data = rest;
+    }
+    Ok(())
+}
+
+fn timed<T>(op: impl FnOnce() -> T) -> T {
+    let start = Instant::now();
+    let result = op();
+    let us = start.elapsed().as_micros();
+    critical_section::with(|cs| {
+        let longest = LONGEST_STALL_US.borrow(cs);
+        longest.set(longest.get().max(us));
+    });
+    result
+}
@@ -0,0 +1,125 @@
+// Commands to a fleet of devices, riding on the config poll: a part of
+// type `application/json` in its multipart answer (src/uploader.rs) is one
+// command, a flat JSON object:
+//
+//     {"id":"2024-06-01-reboot","cmd":"reboot"}
+//     {"id":"fw-0.2.0","cmd":"update","url":"https://example.com/fw.bin"}
+//     {"id":"night","cmd":"config","key":"interval_s","value":"600"}
+//
+// There is no broker to subscribe to, so there are no topics: a command is
+// for every device that polls a config URL that serves it, unless it has
+// `device`, the hex fingerprint of the one device it is for
+// (src/deviceid.rs). A fleet can then share one answer, with broadcast and
+// targeted commands side by side, and other devices skip theirs.
+//
+// The answer comes again at every poll until the server changes it, so a
+// command runs once per `id`: the ids of the answer's commands are kept
+// in the KV store under `fleet`, and each is stored before it runs, so
+// that a reboot does not run again after the reset (src/fleetorder.rs). A
+// command that has run is skipped quietly; one without an `id` is refused.
+//
+// `reboot` restarts through `restart::shut_down`, `update` updates from the
+// image at `url` as a `text/uri-list` part would (subject to the maintenance
+// window, src/ota.rs), and `config` sets one key of the settings document
+// (src/settings.rs) and stages the result as the next revision, as the
+// API's PUT does. A command is as trusted as the config server, which can
+// already change the settings and start an update.
+
+use embassy_time::Duration;
+use esp_println::println;
+
+use crate::codec;
+use crate::deviceid;
+use crate::fleetorder::{Answer, Command, Order, Ran, Step, MAX_RAN_LEN};
+use crate::integrity::HASH_LEN;
+use crate::kv;
+use crate::restart::{self, Reason};
+use crate::settings::{self, MergeError};
+use crate::NetStack;
+
+const KEY: &str = "fleet";
+const REBOOT_SETTLE: Duration = Duration::from_secs(30);
+
+/// Starts on an answer's commands, from those the last one carried.
+pub async fn begin() -> Answer {
+    let mut stored = [0u8; MAX_RAN_LEN];
+    let ran = match kv::get(KEY, &mut stored).await {
+        Ok(Some(len)) => Ran::decode(&stored[..len]),
+        _ => Ran::default(),
+    };
+    Answer::new(ran)
+}
+
+/// Keeps the ids of the commands `answer` carried.
+pub async fn finish(answer: Answer) {
+    if let Some(ran) = answer.finish() {
+        if let Err(e) = kv::set(KEY, ran.encode().as_bytes()).await {
+            println!("fleet: cannot store the commands run: {:?}", e);
+        }
+    }
+}
+
+/// Runs the command in `body`, the next of `answer`'s, unless it is for
+/// another device or has already run.
+pub async fn handle(stack: &NetStack, answer: &mut Answer, body: &[u8]) {
+    let order = match Order::parse(body) {
+        Ok(order) => order,
+        Err(message) => {
+            println!("fleet: refused a command: {}", message);
+            return;
+        }
+    };
+    let mut fingerprint = [0u8; codec::hex_encoded_len(HASH_LEN)];
+    let fingerprint =
+        codec::hex_encode(&deviceid::device_fingerprint(), &mut fingerprint).unwrap_or("");
+    if !order.is_for(fingerprint) {
+        return;
+    }
+    match answer.next(&order.id) {
+        Step::Run => {}
+        Step::Skip => return,
+        Step::Refuse => {
+            println!(
+                "fleet: refused {}: too many commands in one answer",
+                order.id
+            );
+            return;
+        }
+    }
+    // Before it runs: a reboot does not come back to store it.
+    if let Err(e) = kv::set(KEY, answer.kept().encode().as_bytes()).await {
+        println!("fleet: not running {}: cannot store it: {:?}", order.id, e);
+        return;
+    }
+    println!("fleet: running {}: {:?}", order.id, order.command);
+    run(stack, order.command).await;
+}
+
+async fn run(#[cfg_attr(not(feature = "ota"), allow(unused))] stack: &NetStack, command: Command) {
+    match command {
+        Command::Reboot => restart::shut_down(Reason::Requested, REBOOT_SETTLE).await,
+        Command::Update(url) => {
+            #[cfg(feature = "ota")]
+            crate::ota::update(stack, &url).await;
+            #[cfg(not(feature = "ota"))]
+            println!("fleet: ignoring firmware {}: this build has no OTA", url);
+        }
+        Command::Config { key, value } => {
+            let document = match settings::merged(&[(&key, &value)]) {
+                Ok(document) => document,
+                Err(MergeError::UnknownKey) => {
+                    println!("fleet: no setting {}", key);
+                    return;
+                }
+                Err(e) => {
+                    println!("fleet: cannot set {}: {:?}", key, e);
+                    return;
+                }
+            };
+            match settings::stage(&document) {
+                Ok(changes) => println!("fleet: staged {} ({:?})", key, changes),
+                Err(e) => println!("fleet: rejected {}: {:?}", key, e),
+            }
+        }
+    }
+}
@@ -0,0 +1,217 @@
+// The fleet commands of src/fleet.rs, apart from running them: a command
+// as it comes in a part of the config poll's answer, and which of an
+// answer's commands have run already.
+//
+// The answer comes again at every poll until the server changes it, so a
+// command runs once per `id`. The ids of the commands the last answer
+// carried are kept (`Ran`), and an answer's are gone through in order
+// (`Answer`): one kept, or one the answer has had already, is skipped;
+// one that is new is added and the set stored before it runs, so that a
+// reboot does not run again after the reset, nor the commands before it.
+// Once the answer is done, only the ids it carried are kept, so one that
+// the server takes out and puts back later runs again.
+//
+// An answer carries `MAX_COMMANDS` commands at most; the rest are refused.
+// What is stored before a command runs is the answer's ids so far and
+// then the ones kept before, `MAX_RAN` at most, the oldest dropped first,
+// so that a run of answers each cut short by a reboot cannot outgrow it.
+
+use heapless::{String, Vec};
+
+use crate::json::{self, Value};
+
+pub const MAX_ID_LEN: usize = 32;
+pub const MAX_KEY_LEN: usize = 32;
+/// Of one answer.
+pub const MAX_COMMANDS: usize = 8;
+/// Kept at once.
+pub const MAX_RAN: usize = 3 * MAX_COMMANDS;
+/// The ids, one per line.
+pub const MAX_RAN_LEN: usize = MAX_RAN * (MAX_ID_LEN + 1);
+
+pub type Id = String<MAX_ID_LEN>;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    Reboot,
+    Update(Value),
+    Config {
+        key: String<MAX_KEY_LEN>,
+        value: Value,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Order {
+    pub id: Id,
+    /// The fingerprint of the device it is for; every device's if `None`.
+    pub device: Option<Value>,
+    pub command: Command,
+}
+
+impl Order {
+    /// `body` as an order, or why it is not one.
+    pub fn parse(body: &[u8]) -> Result<Self, &'static str> {
+        let mut id = None;
+        let mut device = None;
+        let mut cmd = None;
+        let mut url = None;
+        let mut key = None;
+        let mut value = None;
+        json::fields(body, |name, field| {
+            let slot = match name {
+                "id" => &mut id,
+                "device" => &mut device,
+                "cmd" => &mut cmd,
+                "url" => &mut url,
+                "key" => &mut key,
+                "value" => &mut value,
+                _ => return Err("unknown key"),
+            };
+            match slot.replace(field) {
+                Some(_) => Err("duplicate key"),
+                None => Ok(()),
+            }
+        })?;
+
+        let id = id.ok_or("an id is required")?;
+        let id = String::try_from(id.as_str()).map_err(|_| "id too long")?;
+        let command = match cmd.as_deref() {
+            Some("reboot") => Command::Reboot,
+            Some("update") => Command::Update(url.ok_or("update needs a url")?),
+            Some("config") => {
+                let key = key.ok_or("config needs a key")?;
+                Command::Config {
+                    key: String::try_from(key.as_str()).map_err(|_| "key too long")?,
+                    value: value.ok_or("config needs a value")?,
+                }
+            }
+            Some(_) => return Err("unknown cmd"),
+            None => return Err("a cmd is required"),
+        };
+        Ok(Self {
+            id,
+            device,
+            command,
+        })
+    }
+
+    /// Whether it is for the device with `fingerprint`, in hex.
+    pub fn is_for(&self, fingerprint: &str) -> bool {
+        match &self.device {
+            Some(device) => device.eq_ignore_ascii_case(fingerprint),
+            None => true,
+        }
+    }
+}
+
+/// The ids of commands that have run, newest first.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Ran(Vec<Id, MAX_RAN>);
+
+impl Ran {
+    /// As `encode` wrote them; what does not fit, or is not an id, is
+    /// left out.
+    pub fn decode(data: &[u8]) -> Self {
+        let mut ran = Self::default();
+        let text = core::str::from_utf8(data).unwrap_or("");
+        for id in text.split('\n').filter(|id| !id.is_empty()) {
+            if let Ok(id) = Id::try_from(id) {
+                if ran.0.push(id).is_err() {
+                    break;
+                }
+            }
+        }
+        ran
+    }
+
+    pub fn encode(&self) -> String<MAX_RAN_LEN> {
+        let mut text = String::new();
+        for id in &self.0 {
+            // Cannot overflow: `MAX_RAN_LEN` has room for `MAX_RAN` ids.
+            let _ = text.push_str(id);
+            let _ = text.push('\n');
+        }
+        text
+    }
+
+    pub fn contains(&self, id: &str) -> bool {
+        self.0.iter().any(|ran| ran == id)
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+/// What to do with one of an answer's commands.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Step {
+    /// Store `Answer::kept` first, then run it.
+    Run,
+    /// It has run already.
+    Skip,
+    /// One too many for the answer.
+    Refuse,
+}
+
+/// One answer's commands, gone through in order.
+#[derive(Debug)]
+pub struct Answer {
+    /// Kept from before it.
+    before: Ran,
+    /// Its own, in the order they came.
+    ids: Vec<Id, MAX_COMMANDS>,
+}
+
+impl Answer {
+    pub fn new(before: Ran) -> Self {
+        Self {
+            before,
+            ids: Vec::new(),
+        }
+    }
+
+    /// What to do with the command with `id`, the next in the answer.
+    pub fn next(&mut self, id: &Id) -> Step {
+        if self.ids.contains(id) {
+            return Step::Skip;
+        }
+        if self.ids.push(id.clone()).is_err() {
+            return Step::Refuse;
+        }
+        if self.before.contains(id) {
+            return Step::Skip;
+        }
+        Step::Run
+    }
+
+    /// The ids to keep once the answer is done, if they differ from
+    /// those kept before it.
+    pub fn finish(self) -> Option<Ran> {
+        let mut ran = Ran::default();
+        for id in self.ids.iter().rev() {
+            // Cannot overflow: `MAX_RAN` is more than `MAX_COMMANDS`.
+            let _ = ran.0.push(id.clone());
+        }
+        let same =
+            ran.len() == self.before.len() && ran.0.iter().all(|id| self.before.contains(id));
+        (!same).then_some(ran)
+    }
+
+    /// The answer's ids so far, newest first, then those kept before it.
+    pub fn kept(&self) -> Ran {
+        let mut ran = Ran::default();
+        let before = self.before.0.iter().filter(|id| !self.ids.contains(id));
+        for id in self.ids.iter().rev().chain(before) {
+            if ran.0.push(id.clone()).is_err() {
+                break;
+            }
+        }
+        ran
+    }
+}
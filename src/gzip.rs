@@ -0,0 +1,246 @@
+// Streaming gzip (RFC 1952) decoding without an allocator.
+//
+// The deflate data is inflated by miniz_oxide's core decompressor, which
+// resolves back-references against the last 32 KB of output. That 32 KB
+// window is also where the output goes: each `step` returns the bytes it
+// just added, valid until the next call. Optional header fields are skipped;
+// the CRC-32 and length in the trailer are checked.
+//
+// The compressing side is not miniz_oxide's: it builds its `deflate` module
+// only with `with-alloc`, and its compressor state comes to some 300 KB,
+// most of the C3's RAM. Uploads have a small one of their own instead
+// (src/deflate.rs).
+
+use esp_hal::rom::crc::crc32_le;
+use miniz_oxide::inflate::core::inflate_flags::TINFL_FLAG_HAS_MORE_INPUT;
+use miniz_oxide::inflate::core::{decompress, DecompressorOxide, TINFL_LZ_DICT_SIZE};
+use miniz_oxide::inflate::TINFLStatus;
+
+pub const MAGIC: [u8; 2] = [0x1F, 0x8B];
+
+const METHOD_DEFLATE: u8 = 8;
+const FLAG_HCRC: u8 = 0x02;
+const FLAG_EXTRA: u8 = 0x04;
+const FLAG_NAME: u8 = 0x08;
+const FLAG_COMMENT: u8 = 0x10;
+const FLAGS_RESERVED: u8 = 0xE0;
+
+const HEADER_LEN: usize = 10;
+const TRAILER_LEN: usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GzipError {
+    NotGzip,
+    /// A compression method or flag this decoder does not know.
+    Unsupported,
+    Corrupt,
+    CrcMismatch,
+    LengthMismatch,
+    /// The input ended before the trailer.
+    Truncated,
+}
+
+/// The decompressor and its window, about 43 KB; meant to live in a static.
+pub struct Inflater {
+    // Built on first use: its constructor is not `const`.
+    decompressor: Option<DecompressorOxide>,
+    window: [u8; TINFL_LZ_DICT_SIZE],
+}
+
+impl Inflater {
+    pub const fn new() -> Self {
+        Self {
+            decompressor: None,
+            window: [0; TINFL_LZ_DICT_SIZE],
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Stage {
+    Header,
+    ExtraLen,
+    Extra,
+    Name,
+    Comment,
+    HeaderCrc,
+    Body,
+    Trailer,
+    Done,
+}
+
+pub struct GzipDecoder<'i> {
+    inflater: &'i mut Inflater,
+    stage: Stage,
+    flags: u8,
+    /// Header or trailer bytes collected, or extra field bytes still to skip.
+    field: [u8; HEADER_LEN],
+    have: usize,
+    skip: usize,
+    /// Where the next output goes in the window.
+    pos: usize,
+    crc: u32,
+    len: u32,
+}
+
+impl<'i> GzipDecoder<'i> {
+    pub fn new(inflater: &'i mut Inflater) -> Self {
+        inflater
+            .decompressor
+            .get_or_insert_with(DecompressorOxide::new)
+            .init();
+        Self {
+            inflater,
+            stage: Stage::Header,
+            flags: 0,
+            field: [0; HEADER_LEN],
+            have: 0,
+            skip: 0,
+            pos: 0,
+            crc: 0,
+            len: 0,
+        }
+    }
+
+    /// Takes what it can of `input` and returns how much that was, along with
+    /// any output. Call again with the rest (or more input) until both are
+    /// empty.
+    pub fn step(&mut self, input: &[u8]) -> Result<(usize, &[u8]), GzipError> {
+        let mut used = 0;
+        while self.stage != Stage::Body {
+            // Anything after the trailer (another member, padding) is ignored.
+            if self.stage == Stage::Done {
+                return Ok((input.len(), &[]));
+            }
+            let Some(&byte) = input.get(used) else {
+                return Ok((used, &[]));
+            };
+            used += 1;
+            self.framing_byte(byte)?;
+        }
+
+        let Inflater {
+            decompressor,
+            window,
+        } = &mut *self.inflater;
+        let decompressor = decompressor.as_mut().unwrap();
+        let (status, consumed, produced) = decompress(
+            decompressor,
+            &input[used..],
+            window,
+            self.pos,
+            TINFL_FLAG_HAS_MORE_INPUT,
+        );
+        let out = &window[self.pos..self.pos + produced];
+        self.pos = (self.pos + produced) % TINFL_LZ_DICT_SIZE;
+        self.crc = crc32_le(self.crc, out);
+        self.len = self.len.wrapping_add(produced as u32);
+        match status {
+            TINFLStatus::Done => {
+                self.stage = Stage::Trailer;
+                self.have = 0;
+            }
+            TINFLStatus::NeedsMoreInput | TINFLStatus::HasMoreOutput => {}
+            _ => return Err(GzipError::Corrupt),
+        }
+        Ok((used + consumed, out))
+    }
+
+    /// Whether the whole stream, trailer included, has been seen.
+    pub fn finish(&self) -> Result<(), GzipError> {
+        match self.stage {
+            Stage::Done => Ok(()),
+            _ => Err(GzipError::Truncated),
+        }
+    }
+
+    /// One byte of the header or trailer.
+    fn framing_byte(&mut self, byte: u8) -> Result<(), GzipError> {
+        match self.stage {
+            Stage::Header => {
+                self.field[self.have] = byte;
+                self.have += 1;
+                if self.have == HEADER_LEN {
+                    if self.field[..2] != MAGIC {
+                        return Err(GzipError::NotGzip);
+                    }
+                    self.flags = self.field[3];
+                    if self.field[2] != METHOD_DEFLATE || self.flags & FLAGS_RESERVED != 0 {
+                        return Err(GzipError::Unsupported);
+                    }
+                    self.have = 0;
+                    self.stage = self.after(Stage::Header);
+                }
+            }
+            Stage::ExtraLen => {
+                self.field[self.have] = byte;
+                self.have += 1;
+                if self.have == 2 {
+                    self.skip = u16::from_le_bytes([self.field[0], self.field[1]]) as usize;
+                    self.have = 0;
+                    self.stage = if self.skip == 0 {
+                        self.after(Stage::Extra)
+                    } else {
+                        Stage::Extra
+                    };
+                }
+            }
+            Stage::Extra => {
+                self.skip -= 1;
+                if self.skip == 0 {
+                    self.stage = self.after(Stage::Extra);
+                }
+            }
+            Stage::Name | Stage::Comment => {
+                if byte == 0 {
+                    self.stage = self.after(self.stage);
+                }
+            }
+            Stage::HeaderCrc => {
+                self.have += 1;
+                if self.have == 2 {
+                    self.stage = Stage::Body;
+                }
+            }
+            Stage::Trailer => {
+                self.field[self.have] = byte;
+                self.have += 1;
+                if self.have == TRAILER_LEN {
+                    let word =
+                        |at: usize| u32::from_le_bytes(self.field[at..at + 4].try_into().unwrap());
+                    if word(0) != self.crc {
+                        return Err(GzipError::CrcMismatch);
+                    }
+                    if word(4) != self.len {
+                        return Err(GzipError::LengthMismatch);
+                    }
+                    self.stage = Stage::Done;
+                }
+            }
+            Stage::Body | Stage::Done => {}
+        }
+        Ok(())
+    }
+
+    /// The stage after `stage`, skipping optional fields the header does not
+    /// have.
+    fn after(&self, stage: Stage) -> Stage {
+        let order = [
+            (Stage::ExtraLen, FLAG_EXTRA),
+            (Stage::Name, FLAG_NAME),
+            (Stage::Comment, FLAG_COMMENT),
+            (Stage::HeaderCrc, FLAG_HCRC),
+        ];
+        let done = match stage {
+            Stage::Header => 0,
+            Stage::ExtraLen | Stage::Extra => 1,
+            Stage::Name => 2,
+            Stage::Comment => 3,
+            _ => 4,
+        };
+        order[done..]
+            .iter()
+            .find(|(_, flag)| self.flags & flag != 0)
+            .map_or(Stage::Body, |(next, _)| *next)
+    }
+}
@@ -0,0 +1,115 @@
+// Headers sent with every HTTPS request, from the settings' `header` lines
+// (src/settings.rs), e.g. `header=X-Tenant: acme`.
+//
+// A request's own headers, like a `POST`'s `Content-Type`, win over one of
+// these with the same name, ASCII case-insensitive. A `User-Agent` here
+// replaces the default `USER_AGENT`. `Host`, `Connection`, `Content-Length`
+// and `Transfer-Encoding` describe the connection and the body, which are
+// the client's business, so `add` refuses them, and so a name that is
+// already there: the server would have to pick one.
+//
+// At most `MAX_COUNT` headers, taking up to `MAX_LEN` bytes on the wire
+// together, so they always fit the request head.
+
+use core::fmt;
+
+use heapless::String;
+
+pub const MAX_COUNT: usize = 8;
+/// Of all the `Name: value\r\n` lines together.
+pub const MAX_LEN: usize = 256;
+
+pub const USER_AGENT: &str = concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION"));
+
+const FORBIDDEN: [&str; 4] = ["Host", "Connection", "Content-Length", "Transfer-Encoding"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HeaderError {
+    /// Not `Name: value`, or a character a header cannot carry.
+    Malformed,
+    Forbidden,
+    Duplicate,
+    TooMany,
+    TooLong,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Headers {
+    /// As they go on the wire: `Name: value\r\n` each.
+    lines: String<MAX_LEN>,
+}
+
+impl Headers {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.lines
+            .split_terminator("\r\n")
+            .filter_map(|line| line.split_once(": "))
+    }
+
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value)
+    }
+
+    /// Adds `line`, `Name: value`. Nothing changes if it fails.
+    pub fn add(&mut self, line: &str) -> Result<(), HeaderError> {
+        let (name, value) = line.split_once(':').ok_or(HeaderError::Malformed)?;
+        let value = value.trim_matches([' ', '\t']);
+        if !is_token(name) || value.is_empty() || !value.bytes().all(is_value_byte) {
+            return Err(HeaderError::Malformed);
+        }
+        if FORBIDDEN.iter().any(|f| f.eq_ignore_ascii_case(name)) {
+            return Err(HeaderError::Forbidden);
+        }
+        if self.get(name).is_some() {
+            return Err(HeaderError::Duplicate);
+        }
+        if self.iter().count() == MAX_COUNT {
+            return Err(HeaderError::TooMany);
+        }
+        if self.lines.len() + name.len() + value.len() + 4 > MAX_LEN {
+            return Err(HeaderError::TooLong);
+        }
+        // Cannot fail: the room was checked above.
+        let _ = write_line(&mut self.lines, name, value);
+        Ok(())
+    }
+
+    /// Writes `own` and then these, leaving out the ones `own` overrides,
+    /// and the default `User-Agent` if neither has one.
+    pub fn write_merged<W: fmt::Write>(&self, own: &[(&str, &str)], out: &mut W) -> fmt::Result {
+        let in_own = |name: &str| own.iter().any(|(n, _)| n.eq_ignore_ascii_case(name));
+        for &(name, value) in own {
+            write_line(out, name, value)?;
+        }
+        for (name, value) in self.iter().filter(|&(name, _)| !in_own(name)) {
+            write_line(out, name, value)?;
+        }
+        if !in_own("User-Agent") && self.get("User-Agent").is_none() {
+            write_line(out, "User-Agent", USER_AGENT)?;
+        }
+        Ok(())
+    }
+}
+
+fn write_line<W: fmt::Write>(out: &mut W, name: &str, value: &str) -> fmt::Result {
+    write!(out, "{}: {}\r\n", name, value)
+}
+
+/// RFC 9110 `token`: what a field name is made of.
+fn is_token(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b))
+}
+
+/// Printable ASCII, space and tab. Obsolete non-ASCII text is left out.
+fn is_value_byte(b: u8) -> bool {
+    b == b'\t' || (b' '..=b'~').contains(&b)
+}
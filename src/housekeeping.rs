@@ -0,0 +1,52 @@
+// Periodic background work, kept off the request path.
+//
+// Everything here is short and synchronous: a pass takes a snapshot or
+// swaps counters inside a critical section and does the rest of its work
+// outside. There is no await inside any lock, and the task yields between
+// jobs so that a pass never holds the executor for long. All tasks run on
+// one thread executor, so that is what low priority amounts to here.
+
+use embassy_futures::yield_now;
+use embassy_time::{Duration, Timer as EmbassyTimer};
+
+use esp_println::println;
+
+#[cfg(feature = "probe")]
+use crate::probe;
+use crate::{canary, metrics, monotime, safemode, timerqueue, uploader, wifiheap};
+
+const INTERVAL: Duration = Duration::from_secs(5);
+/// Passes between two logs of the uploader's queue: a minute.
+const QUEUE_LOG_PASSES: u32 = 12;
+
+#[embassy_executor::task]
+pub async fn housekeeping_task() {
+    canary::tracked("housekeeping", run()).await
+}
+
+async fn run() -> ! {
+    let mut passes = 0;
+    let mut logged = uploader::queue_stats();
+    loop {
+        EmbassyTimer::after(INTERVAL).await;
+        if metrics::roll_up_pending() {
+            metrics::print_summary();
+        }
+        yield_now().await;
+        monotime::anchor();
+        safemode::note_uptime();
+        wifiheap::sample();
+        timerqueue::report();
+        #[cfg(feature = "probe")]
+        probe::sample();
+        passes += 1;
+        if passes == QUEUE_LOG_PASSES {
+            passes = 0;
+            let stats = uploader::queue_stats();
+            if stats != logged {
+                println!("uploader queue, last minute: {}", stats.since(&logged));
+                logged = stats;
+            }
+        }
+    }
+}
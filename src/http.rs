@@ -0,0 +1,225 @@
+// HTTP/1.1 message heads and chunked bodies (RFC 9112), for the client
+// (src/https.rs) and the server (src/httpd.rs), apart from any socket.
+//
+// A head is as long as the buffer it was read into lets it be; within it:
+//
+// - at most `MAX_HEADERS` header lines, each `name: value` with a token
+//   for a name and nothing between the name and its colon;
+// - a status code is three digits, a `Content-Length` digits only, and
+//   repeated `Content-Length`s have to agree, so a request cannot be read
+//   as two different lengths;
+// - a request with a `Transfer-Encoding` is `Unsupported`: the server does
+//   not decode one, and guessing at its framing is how requests get
+//   smuggled.
+//
+// `dechunk` decodes in place and never reads past the body it is given: a
+// chunk size is hex digits only and may not exceed what is left. A head or
+// a chunk that breaks any of this is an `HttpError`, never a panic. The
+// targets in fuzz/ hold the parsers to this.
+
+pub const MAX_HEADERS: usize = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HttpError {
+    /// A start line, header line, length or chunk that does not parse.
+    Malformed,
+    /// More than `MAX_HEADERS` header lines.
+    TooManyHeaders,
+    /// Framing this side does not decode.
+    #[cfg_attr(not(feature = "api"), allow(dead_code))]
+    Unsupported,
+}
+
+#[cfg(feature = "api")]
+pub struct RequestHead<'h> {
+    pub method: &'h str,
+    pub target: &'h str,
+    /// 0 without a `Content-Length`.
+    pub content_length: usize,
+}
+
+/// Value of the first header called `name` (ASCII case-insensitive) in a
+/// head.
+pub fn header<'h>(head: &'h str, name: &str) -> Option<&'h str> {
+    head.split("\r\n").skip(1).find_map(|line| {
+        let (key, value) = line.split_once(':')?;
+        key.trim().eq_ignore_ascii_case(name).then(|| value.trim())
+    })
+}
+
+/// The `Content-Length` of `head`, if it has one.
+#[cfg(any(feature = "api", feature = "ota"))]
+pub fn content_length(head: &str) -> Result<Option<usize>, HttpError> {
+    let mut length = None;
+    for line in head.split("\r\n").skip(1) {
+        let Some((key, value)) = line.split_once(':') else {
+            continue;
+        };
+        if !key.eq_ignore_ascii_case("Content-Length") {
+            continue;
+        }
+        let value = value.trim();
+        if value.is_empty() || !value.bytes().all(|b| b.is_ascii_digit()) {
+            return Err(HttpError::Malformed);
+        }
+        let value = value.parse().map_err(|_| HttpError::Malformed)?;
+        if length
+            .replace(value)
+            .is_some_and(|earlier| earlier != value)
+        {
+            return Err(HttpError::Malformed);
+        }
+    }
+    Ok(length)
+}
+
+/// Whether a response head says its body is chunked: `chunked` is the
+/// last of its transfer codings.
+pub fn is_chunked(head: &str) -> bool {
+    header(head, "Transfer-Encoding").is_some_and(|codings| {
+        codings
+            .rsplit(',')
+            .next()
+            .is_some_and(|last| last.trim().eq_ignore_ascii_case("chunked"))
+    })
+}
+
+/// Returns the status code and the offset of the body. If the head was cut
+/// off by a small buffer the body is empty.
+pub fn parse_head(data: &[u8]) -> Result<(u16, usize), HttpError> {
+    let line_end = data
+        .windows(2)
+        .position(|w| w == b"\r\n")
+        .unwrap_or(data.len());
+    let line = core::str::from_utf8(&data[..line_end]).map_err(|_| HttpError::Malformed)?;
+
+    let mut parts = line.split(' ');
+    if !parts
+        .next()
+        .is_some_and(|version| version.starts_with("HTTP/1."))
+    {
+        return Err(HttpError::Malformed);
+    }
+    let status = parts.next().ok_or(HttpError::Malformed)?;
+    if status.len() != 3 || !status.bytes().all(|b| b.is_ascii_digit()) {
+        return Err(HttpError::Malformed);
+    }
+
+    let body_start = data
+        .windows(4)
+        .position(|w| w == b"\r\n\r\n")
+        .map(|i| i + 4)
+        .unwrap_or(data.len());
+    check_fields(&data[..body_start])?;
+    // Cannot fail: three digits.
+    Ok((status.parse().unwrap_or(0), body_start))
+}
+
+/// The request line and framing of a request head, up to and including
+/// its blank line.
+#[cfg(feature = "api")]
+pub fn parse_request(head: &str) -> Result<RequestHead<'_>, HttpError> {
+    check_fields(head.as_bytes())?;
+    let mut parts = head.split("\r\n").next().unwrap_or("").split(' ');
+    let (Some(method), Some(target), Some(version), None) =
+        (parts.next(), parts.next(), parts.next(), parts.next())
+    else {
+        return Err(HttpError::Malformed);
+    };
+    let token = !method.is_empty() && method.bytes().all(is_tchar);
+    if !token || !version.starts_with("HTTP/1.") || !target.starts_with('/') {
+        return Err(HttpError::Malformed);
+    }
+    if header(head, "Transfer-Encoding").is_some() {
+        return Err(HttpError::Unsupported);
+    }
+    Ok(RequestHead {
+        method,
+        target,
+        content_length: content_length(head)?.unwrap_or(0),
+    })
+}
+
+/// Takes the chunk framing out of `body` in place and returns the length of
+/// the data. A body cut off by the buffer keeps what had arrived of it.
+pub fn dechunk(body: &mut [u8]) -> Result<usize, HttpError> {
+    let mut read = 0;
+    let mut written = 0;
+    loop {
+        let Some(line_len) = body[read..].windows(2).position(|w| w == b"\r\n") else {
+            return Ok(written);
+        };
+        // The size, then extensions, which are ignored.
+        let line =
+            core::str::from_utf8(&body[read..read + line_len]).map_err(|_| HttpError::Malformed)?;
+        let size = line.split(';').next().unwrap_or(line).trim().as_bytes();
+        if size.is_empty() || !size.iter().all(u8::is_ascii_hexdigit) {
+            return Err(HttpError::Malformed);
+        }
+        // Too large for a `usize` is more than any buffer holds.
+        let size = size
+            .iter()
+            .try_fold(0usize, |n, &b| {
+                n.checked_mul(16)?
+                    .checked_add((b as char).to_digit(16)? as usize)
+            })
+            .unwrap_or(usize::MAX);
+        read += line_len + 2;
+        if size == 0 {
+            return Ok(written);
+        }
+        let available = size.min(body.len() - read);
+        body.copy_within(read..read + available, written);
+        written += available;
+        read += available;
+        if available < size || body.len() - read < 2 {
+            return Ok(written);
+        }
+        if &body[read..read + 2] != b"\r\n" {
+            return Err(HttpError::Malformed);
+        }
+        read += 2;
+    }
+}
+
+/// Checks the header lines of `head`, which starts with its start line. A
+/// last line without its CRLF is left alone: the buffer cut it off.
+fn check_fields(head: &[u8]) -> Result<(), HttpError> {
+    let end = head
+        .windows(2)
+        .rposition(|w| w == b"\r\n")
+        .map_or(0, |i| i + 2);
+    let mut rest = &head[..end];
+    let lines = core::iter::from_fn(|| {
+        let i = rest.windows(2).position(|w| w == b"\r\n")?;
+        let line = &rest[..i];
+        rest = &rest[i + 2..];
+        Some(line)
+    });
+    for (count, line) in lines.skip(1).enumerate() {
+        if line.is_empty() {
+            break;
+        }
+        if count == MAX_HEADERS {
+            return Err(HttpError::TooManyHeaders);
+        }
+        // A bare CR or LF would end the line for some readers and not
+        // for others.
+        if line.iter().any(|&b| b == b'\r' || b == b'\n') {
+            return Err(HttpError::Malformed);
+        }
+        let colon = line
+            .iter()
+            .position(|&b| b == b':')
+            .ok_or(HttpError::Malformed)?;
+        if colon == 0 || !line[..colon].iter().copied().all(is_tchar) {
+            return Err(HttpError::Malformed);
+        }
+    }
+    Ok(())
+}
+
+/// RFC 9110 section 5.6.2.
+fn is_tchar(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b)
+}
@@ -0,0 +1,347 @@
+// Plain HTTP/1.1 server for the REST API (src/api.rs) on port 80.
+//
+// One connection at a time and one request per connection; every reply
+// says `Connection: close`. The request head must fit `MAX_HEAD_LEN` and
+// the limits of src/http.rs, and a body, which needs a `Content-Length`,
+// `MAX_BODY_LEN`. Anything else gets 400, 413 or 501 without reaching the
+// API. A client that goes quiet for
+// `TIMEOUT` is dropped, so it cannot hold the server.
+//
+// After the reply the connection is closed and given `DRAIN_TIMEOUT` for
+// the client to acknowledge every byte of it (`drain`). A reboot or an
+// update the request asked for waits for that, so that the reply is not
+// still in the socket's buffer when the device resets, and goes ahead
+// once the time is up either way; the log says when it does.
+//
+// Requests go to a `Handler`: the API behind `CorsMiddleware`
+// (src/cors.rs), so browser apps from other origins can use it.
+//
+// There is no TLS: the server is for the local network, and the API makes
+// writes carry credentials.
+//
+// The debug access point (src/debugap.rs) runs a second server on its own
+// interface through `serve_debug`, with buffers of its own so neither
+// waits for the other.
+
+use core::fmt::{self, Write as _};
+use core::str;
+
+use embassy_net::driver::Driver;
+use embassy_net::tcp::TcpSocket;
+use embassy_net::Stack;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::mutex::Mutex;
+use embassy_time::{with_deadline, with_timeout, Duration, Instant};
+use embedded_io_async::Write;
+use esp_println::println;
+use heapless::String;
+
+use crate::api;
+use crate::canary::{self, Canary, CANARY};
+use crate::cors::{self, CorsMiddleware};
+use crate::http::{self, HttpError};
+use crate::logring;
+use crate::NetStack;
+
+pub const PORT: u16 = 80;
+pub const MAX_HEAD_LEN: usize = 1024;
+pub const MAX_BODY_LEN: usize = 1024;
+pub const MAX_REPLY_LEN: usize = 1024;
+const MAX_HEADERS_LEN: usize = 384;
+
+const JSON: &str = "application/json";
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+const DRAIN_TIMEOUT: Duration = Duration::from_secs(2);
+
+#[repr(C)]
+struct Buffers {
+    request: [u8; MAX_HEAD_LEN + MAX_BODY_LEN],
+    request_end: Canary,
+    socket_rx: [u8; 1024],
+    socket_rx_end: Canary,
+    socket_tx: [u8; 1024],
+    socket_tx_end: Canary,
+}
+
+impl Buffers {
+    const fn new() -> Self {
+        Self {
+            request: [0; MAX_HEAD_LEN + MAX_BODY_LEN],
+            request_end: CANARY,
+            socket_rx: [0; 1024],
+            socket_rx_end: CANARY,
+            socket_tx: [0; 1024],
+            socket_tx_end: CANARY,
+        }
+    }
+}
+
+static BUFFERS: Mutex<CriticalSectionRawMutex, Buffers> = Mutex::new(Buffers::new());
+static DEBUG_BUFFERS: Mutex<CriticalSectionRawMutex, Buffers> = Mutex::new(Buffers::new());
+
+pub struct Request<'a> {
+    pub method: &'a str,
+    /// Without the query string.
+    pub path: &'a str,
+    head: &'a str,
+    pub body: &'a [u8],
+}
+
+impl Request<'_> {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        http::header(self.head, name)
+    }
+}
+
+/// What goes back; the body is JSON, or empty, unless `content_type`
+/// says otherwise.
+pub struct Reply {
+    pub status: u16,
+    pub content_type: &'static str,
+    pub body: String<MAX_REPLY_LEN>,
+    /// Sent after `body`: the log, which does not fit it.
+    pub logs: Option<logring::Snapshot>,
+    /// Header lines beyond the ones every reply has.
+    headers: String<MAX_HEADERS_LEN>,
+}
+
+impl Reply {
+    fn new() -> Self {
+        Self {
+            status: 200,
+            content_type: JSON,
+            body: String::new(),
+            logs: None,
+            headers: String::new(),
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.body.len() + self.logs.as_ref().map_or(0, logring::Snapshot::len)
+    }
+
+    pub fn add_header(&mut self, name: &str, value: &str) -> fmt::Result {
+        write!(self.headers, "{}: {}\r\n", name, value)
+    }
+
+    /// Replaces whatever was written with `{"error":message}`.
+    pub fn error(&mut self, status: u16, message: &str) {
+        self.status = status;
+        self.content_type = JSON;
+        self.body.clear();
+        self.logs = None;
+        // Messages are short fixed text.
+        let _ = write!(self.body, "{{\"error\":\"{}\"}}", message);
+    }
+}
+
+/// Answers requests: the API, or a middleware around it.
+pub trait Handler {
+    async fn handle(&mut self, request: &Request<'_>, reply: &mut Reply) -> api::Action;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum RequestError {
+    /// Closed, reset or timed out before a whole request came in.
+    Gone,
+    Malformed,
+    TooLarge,
+    /// Chunked bodies.
+    Unsupported,
+}
+
+/// Hands the buffers' canaries to `canary`. Call before starting the task.
+pub fn register_canaries() {
+    if let Ok(buffers) = BUFFERS.try_lock() {
+        canary::register("httpd request", &buffers.request_end);
+        canary::register("httpd socket_rx", &buffers.socket_rx_end);
+        canary::register("httpd socket_tx", &buffers.socket_tx_end);
+    }
+    if let Ok(buffers) = DEBUG_BUFFERS.try_lock() {
+        canary::register("debug httpd request", &buffers.request_end);
+        canary::register("debug httpd socket_rx", &buffers.socket_rx_end);
+        canary::register("debug httpd socket_tx", &buffers.socket_tx_end);
+    }
+}
+
+#[embassy_executor::task]
+pub async fn httpd_task(stack: &'static NetStack) {
+    canary::tracked("httpd", run(stack)).await
+}
+
+async fn run(stack: &'static NetStack) -> ! {
+    let handler = CorsMiddleware::new(api::Api::new(stack), cors::configured_origins());
+    println!("httpd: listening on port {}", PORT);
+    serve(stack, &BUFFERS, handler, stack).await
+}
+
+/// Serves `handler` on `PORT` of another interface's stack. `stack` is the
+/// station's, for whatever the handler leaves to `api::finish`.
+pub async fn serve_debug<D: Driver + 'static>(
+    listen: &'static Stack<D>,
+    handler: impl Handler,
+    stack: &'static NetStack,
+) -> ! {
+    serve(listen, &DEBUG_BUFFERS, handler, stack).await
+}
+
+async fn serve<D: Driver + 'static>(
+    listen: &'static Stack<D>,
+    buffers: &Mutex<CriticalSectionRawMutex, Buffers>,
+    mut handler: impl Handler,
+    stack: &'static NetStack,
+) -> ! {
+    let mut buffers = buffers.lock().await;
+    let Buffers {
+        request,
+        socket_rx,
+        socket_tx,
+        ..
+    } = &mut *buffers;
+    loop {
+        let mut socket = TcpSocket::new(listen, socket_rx, socket_tx);
+        socket.set_timeout(Some(TIMEOUT));
+        if let Err(e) = socket.accept(PORT).await {
+            println!("httpd: accept failed: {:?}", e);
+            continue;
+        }
+        let mut reply = Reply::new();
+        let read = with_timeout(TIMEOUT, read_request(&mut socket, request)).await;
+        let action = match read.unwrap_or(Err(RequestError::Gone)) {
+            Ok(request) => handler.handle(&request, &mut reply).await,
+            Err(RequestError::Gone) => {
+                socket.abort();
+                continue;
+            }
+            Err(RequestError::Malformed) => {
+                reply.error(400, "malformed request");
+                api::Action::None
+            }
+            Err(RequestError::TooLarge) => {
+                reply.error(413, "request too large");
+                api::Action::None
+            }
+            Err(RequestError::Unsupported) => {
+                reply.error(501, "chunked bodies are not supported");
+                api::Action::None
+            }
+        };
+        if write_reply(&mut socket, &reply).await.is_err() {
+            println!("httpd: client went away before the reply");
+        }
+        socket.close();
+        if !drain(&mut socket, Instant::now() + DRAIN_TIMEOUT).await {
+            println!("httpd: reply not acknowledged in time, dropping it");
+        }
+        // Only once the client has its answer: a reboot or an update does
+        // not come back.
+        drop(socket);
+        api::finish(stack, action).await;
+    }
+}
+
+/// Waits for what was written to `socket` to be acknowledged, up to
+/// `deadline`; whether it was.
+async fn drain(socket: &mut TcpSocket<'_>, deadline: Instant) -> bool {
+    matches!(with_deadline(deadline, socket.flush()).await, Ok(Ok(())))
+}
+
+/// Reads one request into `buf`: the head, then as much body as
+/// `Content-Length` says.
+async fn read_request<'b>(
+    socket: &mut TcpSocket<'_>,
+    buf: &'b mut [u8],
+) -> Result<Request<'b>, RequestError> {
+    let mut len = 0;
+    let head_len = loop {
+        if let Some(i) = buf[..len].windows(4).position(|w| w == b"\r\n\r\n") {
+            break i + 4;
+        }
+        if len == MAX_HEAD_LEN {
+            return Err(RequestError::TooLarge);
+        }
+        len += receive(socket, &mut buf[len..MAX_HEAD_LEN]).await?;
+    };
+    let head = str::from_utf8(&buf[..head_len]).map_err(|_| RequestError::Malformed)?;
+    let body_len = match http::parse_request(head) {
+        Ok(parsed) => parsed.content_length,
+        Err(HttpError::Unsupported) => return Err(RequestError::Unsupported),
+        Err(_) => return Err(RequestError::Malformed),
+    };
+    if body_len > MAX_BODY_LEN {
+        return Err(RequestError::TooLarge);
+    }
+    let end = head_len + body_len;
+    while len < end {
+        len += receive(socket, &mut buf[len..end]).await?;
+    }
+
+    let buf = &*buf;
+    // Checked above, and the bytes have not changed since.
+    let head = str::from_utf8(&buf[..head_len]).unwrap();
+    let parsed = http::parse_request(head).map_err(|_| RequestError::Malformed)?;
+    Ok(Request {
+        method: parsed.method,
+        path: parsed.target.split('?').next().unwrap_or(parsed.target),
+        head,
+        body: &buf[head_len..end],
+    })
+}
+
+async fn receive(socket: &mut TcpSocket<'_>, buf: &mut [u8]) -> Result<usize, RequestError> {
+    match socket.read(buf).await {
+        Ok(0) | Err(_) => Err(RequestError::Gone),
+        Ok(n) => Ok(n),
+    }
+}
+
+async fn write_reply(socket: &mut TcpSocket<'_>, reply: &Reply) -> Result<(), ()> {
+    let mut head: String<192> = String::new();
+    // Cannot overflow: fixed text and two numbers.
+    let _ = write!(
+        head,
+        "HTTP/1.1 {} {}\r\nContent-Length: {}\r\nConnection: close\r\n",
+        reply.status,
+        reason(reply.status),
+        reply.len()
+    );
+    if reply.len() > 0 {
+        let _ = write!(head, "Content-Type: {}\r\n", reply.content_type);
+    }
+    if reply.status == 401 {
+        let _ = head.push_str("WWW-Authenticate: Basic realm=\"device\"\r\n");
+    }
+    socket.write_all(head.as_bytes()).await.map_err(|_| ())?;
+    socket
+        .write_all(reply.headers.as_bytes())
+        .await
+        .map_err(|_| ())?;
+    socket.write_all(b"\r\n").await.map_err(|_| ())?;
+    socket
+        .write_all(reply.body.as_bytes())
+        .await
+        .map_err(|_| ())?;
+    if let Some(logs) = &reply.logs {
+        logs.write_to(socket).await.map_err(|_| ())?;
+    }
+    Ok(())
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        202 => "Accepted",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        409 => "Conflict",
+        413 => "Content Too Large",
+        429 => "Too Many Requests",
+        500 => "Internal Server Error",
+        501 => "Not Implemented",
+        _ => "",
+    }
+}
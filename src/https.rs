@@ -0,0 +1,830 @@
+// One-shot HTTPS client shared by everything that talks to a server.
+//
+// The TLS and socket buffers are too large to have more than one set, so they
+// live in a single static behind a mutex: a second caller simply waits until
+// the current request has finished. A pool of sets handed out per
+// connection would buy nothing: the sockets' buffers are already static
+// (the server's too, src/httpd.rs), so no connection puts them on the
+// stack, and with one connection at a time a second set would sit idle.
+//
+// Nor is there a blocking form of the calls. The network stack only moves
+// while `net_task` runs on the one executor, so blocking that executor on
+// a request would stop what the request waits on, and before the executor
+// runs there is no network yet.
+//
+// Certificates are not verified (`NoVerify`), and embedded-tls 0.17 does not
+// let a custom verifier see the server certificate either, so there is no
+// fingerprint to report yet, none to pin, and no `notBefore` to take a
+// lower bound on the time from. Pinning, when it comes, wants a set of
+// fingerprints from the start, so the server's next certificate can be
+// pinned before it rotates to it. A verifier that checks validity dates
+// has to refuse to connect until the clock has come from SNTP, rather than
+// judge certificates by a clock it cannot trust.
+//
+// The ClientHello cannot be made to look like anyone else's. embedded-tls
+// 0.17 builds it itself: one suite (`CIPHER_SUITE`), and its extensions in
+// an order the crate fixes. There is nothing to shuffle, and shuffling
+// would not help much anyway: an order no mainstream client sends is a
+// fingerprint of its own, `pre_shared_key` has to stay last, and some
+// middleboxes drop hellos whose order they do not expect. What can vary
+// does: the random and the key share come from the hardware RNG, and the
+// hello goes out `MAX_HELLO_DELAY_MS` or less after the TCP connect, at
+// random, so the two packets do not pair up by timing. That delay is
+// latency on every request, in the total time but not the handshake time.
+//
+// Every request carries the headers from the settings (src/headers.rs)
+// next to its own, and an `X-Request-Id` of its own, a random UUID, unless
+// either of those has one.
+//
+// `post_gzip` sends a body gzipped (src/deflate.rs), compressed into a
+// small buffer as it goes out rather than into a second copy.
+//
+// A body goes out in pieces (src/httpsbody.rs). A server that answers
+// before it has all of it, typically 401 or 413 to a large upload, gets no
+// more: the response is read as it is and marked `early`, so the caller
+// knows the body was not delivered whatever the status says. Before each
+// piece `Link::answered` reads the TLS records already in, and only those,
+// so that session tickets are taken in and only a response counts;
+// `Transport` notes at every write to the socket whether the server has
+// reset or closed the connection, which the TLS connection would keep to
+// itself. A request never shares its connection, so one cut short is
+// closed like any other.
+//
+// Nor does a request get a connection an earlier one left open: each asks
+// for `Connection: close` and connects afresh. So none meets one the server
+// has timed out meanwhile, and a reset or close mid-request is a real
+// failure, reported as such and never replayed, whatever the method.
+//
+// A chunked response body (RFC 9112 section 7.1) is decoded in place once
+// it is in, trailers dropped. Streamed bodies are not decoded, so a chunked
+// one is refused (`StreamError::Chunked`). Heads and chunks are parsed by
+// src/http.rs, within the limits it sets.
+//
+// A streamed body can be asked for from a byte offset on (`Range`), and
+// one that goes quiet for as long as the caller allows ends as
+// `StreamError::Stalled`, not as the end of the body, so that a download
+// can pick up where it stopped. So does one whose connection fails short
+// of its `Content-Length`, as `StreamError::Cut`.
+//
+// `get_verified` also checks that the body hashes to a SHA-256 published
+// out of band, or else to the one in the response's `X-Content-Sha256`
+// (hex) or `Digest: sha-256=` (RFC 3230, base64). The hash is of the
+// decoded body, taken on the accelerator (src/integrity.rs) after the
+// connection is closed, so nothing holds it across an await.
+//
+// With the `wiretrace` feature every connection goes through `Link`, which
+// can hexdump what is written and read (see src/wiretrace.rs).
+//
+// With the `replay` feature a request that fails, or is answered 400 or
+// above, leaves its head and the start of the answer, secrets masked, for
+// the console's `lastfail` (src/replay.rs). The head is written before the
+// connection opens so that it is there to keep, and the answer is kept as
+// it came, before any dechunking.
+
+use core::cell::Cell;
+use core::fmt::Write as _;
+use core::future;
+
+use embassy_futures::select::{select, Either};
+use embassy_net::tcp::{self, ConnectError, TcpSocket};
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::mutex::{Mutex, MutexGuard};
+use embassy_time::{with_timeout, Duration, Instant, Timer};
+use embedded_io_async::{ErrorType, Read, Write};
+use embedded_tls::{Aes128GcmSha256, NoVerify, TlsConfig, TlsConnection, TlsContext, TlsError};
+use heapless::String;
+
+use crate::canary::{self, Canary, CANARY};
+use crate::codec;
+use crate::deflate::Gzipped;
+use crate::diag::Phase;
+use crate::entropy::{self, HardwareRng};
+use crate::headers;
+use crate::http;
+use crate::httpsbody::{self, Body, Incoming, Outgoing, Records, ResponseError};
+#[cfg(feature = "ota")]
+use crate::httpsbody::{Streamed, StreamedError};
+use crate::integrity::HASH_LEN;
+use crate::metrics;
+use crate::probe;
+#[cfg(feature = "replay")]
+use crate::replay;
+use crate::resolver::{self, DnsError};
+use crate::settings;
+#[cfg(feature = "wiretrace")]
+use crate::wiretrace::{self, Direction};
+use crate::NetStack;
+
+pub const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+pub const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(15);
+pub const RESPONSE_TIMEOUT: Duration = Duration::from_secs(10);
+const MAX_HELLO_DELAY_MS: u32 = 100;
+/// The request line, the client's own headers and the request ID, then
+/// the settings'.
+const MAX_REQUEST_HEAD_LEN: usize = 432 + headers::MAX_LEN;
+
+/// The only version embedded-tls speaks.
+#[cfg(any(feature = "api", feature = "console"))]
+pub const TLS_VERSION: &str = "TLS 1.3";
+/// The only suite the client is built with.
+pub const CIPHER_SUITE: &str = "TLS_AES_128_GCM_SHA256";
+
+const DEFAULT_PORT: u16 = 443;
+
+#[derive(Debug, Clone, Copy)]
+pub enum FetchError {
+    InvalidUrl,
+    Dns(DnsError),
+    Connect(ConnectError),
+    Handshake(TlsError),
+    Write(TlsError),
+    Read(TlsError),
+    Timeout(Phase),
+    MalformedResponse,
+    /// The body does not hash to the digest `get_verified` checked it
+    /// against.
+    IntegrityMismatch,
+    /// `get_verified` had no digest to check against: none given and none
+    /// usable in the response.
+    NoDigest,
+}
+
+impl From<ResponseError> for FetchError {
+    fn from(e: ResponseError) -> Self {
+        match e {
+            ResponseError::Malformed => FetchError::MalformedResponse,
+            ResponseError::Mismatch => FetchError::IntegrityMismatch,
+            ResponseError::NoDigest => FetchError::NoDigest,
+        }
+    }
+}
+
+impl FetchError {
+    pub fn phase(&self) -> Phase {
+        match self {
+            FetchError::InvalidUrl => Phase::Url,
+            FetchError::Dns(_) => Phase::Dns,
+            FetchError::Connect(_) => Phase::Connect,
+            FetchError::Handshake(_) => Phase::Handshake,
+            FetchError::Write(_) => Phase::Request,
+            FetchError::Read(_)
+            | FetchError::MalformedResponse
+            | FetchError::IntegrityMismatch
+            | FetchError::NoDigest => Phase::Response,
+            FetchError::Timeout(phase) => *phase,
+        }
+    }
+}
+
+/// Borrowed from the string it was parsed from; nothing is copied.
+pub struct Url<'a> {
+    pub host: &'a str,
+    pub port: u16,
+    pub path: &'a str,
+}
+
+/// Splits `https://host[:port][/path]`. Only HTTPS is supported.
+pub fn parse_url(url: &str) -> Option<Url<'_>> {
+    let rest = url.strip_prefix("https://")?;
+    let (authority, path) = match rest.find('/') {
+        Some(i) => (&rest[..i], &rest[i..]),
+        None => (rest, "/"),
+    };
+    let (host, port) = match authority.rsplit_once(':') {
+        Some((host, port)) => (host, port.parse().ok()?),
+        None => (authority, DEFAULT_PORT),
+    };
+    if host.is_empty() {
+        return None;
+    }
+    Some(Url { host, port, path })
+}
+
+/// Milliseconds spent in each step of a request.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Timings {
+    pub dns_ms: u64,
+    pub connect_ms: u64,
+    pub handshake_ms: u64,
+    pub first_byte_ms: u64,
+    pub total_ms: u64,
+}
+
+pub struct Response {
+    pub status: u16,
+    /// Bytes of the response (head and body) stored in the caller's buffer.
+    pub len: usize,
+    body_start: usize,
+    pub timings: Timings,
+    /// The server answered before the whole request body was sent, and the
+    /// rest was not: it did not get the body, whatever `status` says.
+    pub early: bool,
+}
+
+impl Response {
+    pub fn body<'b>(&self, buf: &'b [u8]) -> &'b [u8] {
+        &buf[self.body_start..self.len]
+    }
+
+    /// Value of the first header called `name` (ASCII case-insensitive), if
+    /// the head fit in the buffer.
+    pub fn header<'b>(&self, buf: &'b [u8], name: &str) -> Option<&'b str> {
+        http::header(core::str::from_utf8(&buf[..self.body_start]).ok()?, name)
+    }
+}
+
+/// Takes a response as it arrives, for bodies too large for any buffer.
+#[cfg(feature = "ota")]
+pub trait BodySink {
+    type Error;
+
+    /// Called once, before any of the body, with the status and the head
+    /// (status line and headers).
+    async fn head(&mut self, status: u16, head: &str) -> Result<(), Self::Error>;
+
+    /// Called with each piece of the body in order.
+    async fn body(&mut self, data: &[u8]) -> Result<(), Self::Error>;
+}
+
+#[cfg(feature = "ota")]
+#[derive(Debug)]
+pub enum StreamError<E> {
+    Fetch(FetchError),
+    Sink(E),
+    /// The body went the `stall` `get_streamed` was given without data.
+    Stalled,
+    /// The connection failed before the body's `Content-Length` had come.
+    Cut,
+    /// The body came chunked, which is not decoded.
+    Chunked,
+}
+
+#[cfg(feature = "ota")]
+impl<E> From<FetchError> for StreamError<E> {
+    fn from(e: FetchError) -> Self {
+        StreamError::Fetch(e)
+    }
+}
+
+/// Most a streamed response's head may take.
+#[cfg(feature = "ota")]
+const STREAM_HEAD_LEN: usize = 1024;
+
+// Each buffer is followed by a canary, in this order.
+#[repr(C)]
+struct Buffers {
+    tls_rx: [u8; 8192],
+    tls_rx_end: Canary,
+    tls_tx: [u8; 8192],
+    tls_tx_end: Canary,
+    socket_rx: [u8; 2048],
+    socket_rx_end: Canary,
+    socket_tx: [u8; 2048],
+    socket_tx_end: Canary,
+    /// Of the connection in flight.
+    peer: Peer,
+}
+
+static BUFFERS: Mutex<CriticalSectionRawMutex, Buffers> = Mutex::new(Buffers {
+    tls_rx: [0; 8192],
+    tls_rx_end: CANARY,
+    tls_tx: [0; 8192],
+    tls_tx_end: CANARY,
+    socket_rx: [0; 2048],
+    socket_rx_end: CANARY,
+    socket_tx: [0; 2048],
+    socket_tx_end: CANARY,
+    peer: Peer::new(),
+});
+
+/// Hands the buffers' canaries to `canary`. Call before the first request.
+pub fn register_canaries() {
+    let Ok(buffers) = BUFFERS.try_lock() else {
+        return;
+    };
+    canary::register("https tls_rx", &buffers.tls_rx_end);
+    canary::register("https tls_tx", &buffers.tls_tx_end);
+    canary::register("https socket_rx", &buffers.socket_rx_end);
+    canary::register("https socket_tx", &buffers.socket_tx_end);
+}
+
+/// Keeps further requests waiting for as long as it lives.
+pub struct Hold(#[allow(dead_code)] MutexGuard<'static, CriticalSectionRawMutex, Buffers>);
+
+/// Waits for the request in flight, if any, and holds off the rest.
+pub async fn hold() -> Hold {
+    Hold(BUFFERS.lock().await)
+}
+
+/// Sends `GET` for `url` and reads the response into `response` until the
+/// server closes the connection or the buffer is full. A response larger than
+/// the buffer is truncated, not an error.
+pub async fn get(stack: &NetStack, url: &str, response: &mut [u8]) -> Result<Response, FetchError> {
+    request(stack, "GET", url, &[], None, response).await
+}
+
+/// Sends `HEAD` for `url`: all of what `get` does but for the body, which a
+/// server does not send for it.
+pub async fn head(
+    stack: &NetStack,
+    url: &str,
+    response: &mut [u8],
+) -> Result<Response, FetchError> {
+    request(stack, "HEAD", url, &[], None, response).await
+}
+
+/// Connects to `url`'s host and completes the handshake, then closes the
+/// connection without a request. `total_ms` is up to the handshake's end.
+#[cfg(any(feature = "api", feature = "console"))]
+pub async fn handshake(stack: &NetStack, url: &str) -> Result<Timings, FetchError> {
+    let url = parse_url(url).ok_or(FetchError::InvalidUrl)?;
+    let mut buffers = BUFFERS.lock().await;
+    let mut timings = Timings::default();
+    let start = Instant::now();
+    let link = open(stack, &url, &mut buffers, &mut timings).await?;
+    timings.total_ms = start.elapsed().as_millis();
+    let _ = link.tls.close().await;
+    Ok(timings)
+}
+
+/// Like `get`, with `headers` on top of the settings' ones.
+#[cfg(feature = "ota")]
+pub async fn get_with_headers(
+    stack: &NetStack,
+    url: &str,
+    headers: &[(&str, &str)],
+    response: &mut [u8],
+) -> Result<Response, FetchError> {
+    request(stack, "GET", url, headers, None, response).await
+}
+
+/// Like `get`, and fails with `IntegrityMismatch` unless the body hashes to
+/// `expected`, or, without one, to the digest the response publishes. A
+/// body cut short by `response` does not match.
+pub async fn get_verified(
+    stack: &NetStack,
+    url: &str,
+    expected: Option<&[u8; HASH_LEN]>,
+    response: &mut [u8],
+) -> Result<Response, FetchError> {
+    let result = request(stack, "GET", url, &[], None, response).await?;
+    httpsbody::verify(
+        &response[..result.body_start],
+        result.body(response),
+        expected,
+    )?;
+    Ok(result)
+}
+
+/// Like `get`, but sends `body` as a `POST` with the given content type.
+pub async fn post(
+    stack: &NetStack,
+    url: &str,
+    content_type: &str,
+    body: &[u8],
+    response: &mut [u8],
+) -> Result<Response, FetchError> {
+    let headers = [("Content-Type", content_type)];
+    request(
+        stack,
+        "POST",
+        url,
+        &headers,
+        Some(Body::Plain(body)),
+        response,
+    )
+    .await
+}
+
+/// Like `post`, with the body gzipped on the way out and
+/// `Content-Encoding: gzip`.
+pub async fn post_gzip(
+    stack: &NetStack,
+    url: &str,
+    content_type: &str,
+    body: &Gzipped<'_>,
+    response: &mut [u8],
+) -> Result<Response, FetchError> {
+    let headers = [("Content-Type", content_type), ("Content-Encoding", "gzip")];
+    request(
+        stack,
+        "POST",
+        url,
+        &headers,
+        Some(Body::Gzip(body)),
+        response,
+    )
+    .await
+}
+
+/// Sends `GET` for `url`, for the body from byte `from` on, and hands the
+/// response to `sink` piece by piece until the server closes the
+/// connection, or sends nothing for `stall`. The head has to fit in
+/// `STREAM_HEAD_LEN` bytes, and the body may not be chunked.
+#[cfg(feature = "ota")]
+pub async fn get_streamed<S: BodySink>(
+    stack: &NetStack,
+    url: &str,
+    from: u32,
+    stall: Duration,
+    sink: &mut S,
+) -> Result<Timings, StreamError<S::Error>> {
+    let url = parse_url(url).ok_or(FetchError::InvalidUrl)?;
+    let mut range: String<24> = String::new();
+    // Cannot overflow: "bytes=" and ten digits.
+    let _ = write!(range, "bytes={}-", from);
+    let headers: &[(&str, &str)] = match from {
+        0 => &[],
+        _ => &[("Range", &range)],
+    };
+    let head = write_head("GET", &url, headers, None)?;
+    let mut buffers = BUFFERS.lock().await;
+    let mut timings = Timings::default();
+    let start = Instant::now();
+    let mut link = open(stack, &url, &mut buffers, &mut timings).await?;
+    let mark = Instant::now();
+    let sent = httpsbody::send(&mut link, &head, None)
+        .await
+        .map_err(FetchError::Write)?
+        .bytes;
+
+    let mut buf = [0u8; STREAM_HEAD_LEN];
+    let mut len = 0;
+    let body_start = loop {
+        if let Some(i) = buf[..len].windows(4).position(|w| w == b"\r\n\r\n") {
+            break i + 4;
+        }
+        if len == buf.len() {
+            return Err(FetchError::MalformedResponse.into());
+        }
+        match read(&mut link, &mut buf[len..], len > 0).await? {
+            0 => return Err(FetchError::MalformedResponse.into()),
+            n => {
+                if len == 0 {
+                    timings.first_byte_ms = mark.elapsed().as_millis();
+                }
+                len += n;
+            }
+        }
+    };
+    let (status, _) = http::parse_head(&buf[..len]).map_err(|_| FetchError::MalformedResponse)?;
+    let head =
+        core::str::from_utf8(&buf[..body_start]).map_err(|_| FetchError::MalformedResponse)?;
+    let mut body = Streamed::new(head).map_err(|e| match e {
+        StreamedError::Malformed => StreamError::Fetch(FetchError::MalformedResponse),
+        StreamedError::Chunked => StreamError::Chunked,
+    })?;
+    sink.head(status, head).await.map_err(StreamError::Sink)?;
+    body.advance(len - body_start);
+    if body_start < len {
+        sink.body(&buf[body_start..len])
+            .await
+            .map_err(StreamError::Sink)?;
+    }
+
+    let mut received = len;
+    loop {
+        match with_timeout(stall, link.read(&mut buf)).await {
+            Ok(Ok(0)) => break,
+            Ok(Err(_)) if body.cut_short() => return Err(StreamError::Cut),
+            // Past the whole body, or with no length to tell.
+            Ok(Err(_)) => break,
+            Ok(Ok(n)) => {
+                received += n;
+                body.advance(n);
+                sink.body(&buf[..n]).await.map_err(StreamError::Sink)?;
+            }
+            Err(_) => return Err(StreamError::Stalled),
+        }
+    }
+    timings.total_ms = start.elapsed().as_millis();
+    metrics::record_request(&timings, sent, received);
+    let _ = link.tls.close().await;
+    Ok(timings)
+}
+
+async fn request(
+    stack: &NetStack,
+    method: &str,
+    url: &str,
+    headers: &[(&str, &str)],
+    body: Option<Body<'_>>,
+    response: &mut [u8],
+) -> Result<Response, FetchError> {
+    let url = parse_url(url).ok_or(FetchError::InvalidUrl)?;
+    let head = write_head(method, &url, headers, body.as_ref().map(Body::wire_len))?;
+    let mut received = 0;
+    let result = exchange(stack, &url, &head, body, response, &mut received).await;
+    #[cfg(feature = "replay")]
+    match &result {
+        Ok(answer) if answer.status >= 400 => {
+            replay::record(url.path, &head, Ok(answer.status), &response[..received]).await
+        }
+        Ok(_) => {}
+        Err(e) => replay::record(url.path, &head, Err(e), &response[..received]).await,
+    }
+    let mut result = result?;
+
+    probe::probe_variable!(PROBE_HTTP_STATUS: u16 = result.status);
+    result.len = httpsbody::decode_body(response, result.body_start, result.len, method == "HEAD")?;
+    Ok(result)
+}
+
+/// Connects, sends `head` and `body`, and reads the response as it came,
+/// counting what has arrived in `received` as it goes.
+async fn exchange(
+    stack: &NetStack,
+    url: &Url<'_>,
+    head: &str,
+    body: Option<Body<'_>>,
+    response: &mut [u8],
+    received: &mut usize,
+) -> Result<Response, FetchError> {
+    let mut buffers = BUFFERS.lock().await;
+    let mut timings = Timings::default();
+    let start = Instant::now();
+    let mut link = open(stack, url, &mut buffers, &mut timings).await?;
+    let mark = Instant::now();
+    let sent = httpsbody::send(&mut link, head, body)
+        .await
+        .map_err(FetchError::Write)?;
+
+    let len = httpsbody::receive(&mut link, response, received, || {
+        timings.first_byte_ms = mark.elapsed().as_millis();
+    })
+    .await?;
+    timings.total_ms = start.elapsed().as_millis();
+    metrics::record_request(&timings, sent.bytes, len);
+
+    // Best effort; the socket is dropped either way.
+    let _ = link.tls.close().await;
+
+    let (status, body_start) =
+        http::parse_head(&response[..len]).map_err(|_| FetchError::MalformedResponse)?;
+    Ok(Response {
+        status,
+        len,
+        body_start,
+        timings,
+        early: sent.early,
+    })
+}
+
+/// What a `Link` and the `Transport` under it share.
+struct Peer {
+    /// Set once the server has answered, or reset or closed the connection.
+    answered: Cell<bool>,
+    probe: Cell<Probe>,
+}
+
+impl Peer {
+    const fn new() -> Self {
+        Self {
+            answered: Cell::new(false),
+            probe: Cell::new(Probe::Off),
+        }
+    }
+}
+
+/// Whether the TLS connection is reading only what has come in whole, for
+/// `Link::answered`, and how much of it is left.
+#[derive(Clone, Copy)]
+enum Probe {
+    Off,
+    Started,
+    /// Bytes of whole records still in the socket.
+    Left(usize),
+}
+
+/// The TCP socket under the TLS connection.
+struct Transport<'b> {
+    socket: TcpSocket<'b>,
+    peer: &'b Peer,
+    /// Of everything read so far.
+    records: Records,
+}
+
+impl ErrorType for Transport<'_> {
+    type Error = tcp::Error;
+}
+
+impl Read for Transport<'_> {
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, tcp::Error> {
+        let len = loop {
+            match self.peer.probe.get() {
+                Probe::Off => break buf.len(),
+                Probe::Started => {
+                    let whole = if self.socket.can_recv() {
+                        let records = &self.records;
+                        self.socket
+                            .read_with(|data| (0, records.whole(data)))
+                            .await?
+                    } else {
+                        0
+                    };
+                    self.peer.probe.set(Probe::Left(whole));
+                }
+                // With no whole record left, the TLS connection is between
+                // two, and is dropped there.
+                Probe::Left(0) => future::pending::<()>().await,
+                Probe::Left(left) => break buf.len().min(left),
+            }
+        };
+        let n = self.socket.read(&mut buf[..len]).await?;
+        if let Probe::Left(left) = self.peer.probe.get() {
+            self.peer.probe.set(Probe::Left(left - n));
+        }
+        self.records.advance(&buf[..n]);
+        Ok(n)
+    }
+}
+
+impl Write for Transport<'_> {
+    async fn write(&mut self, buf: &[u8]) -> Result<usize, tcp::Error> {
+        if !self.socket.may_send() {
+            self.peer.answered.set(true);
+        }
+        self.socket.write(buf).await
+    }
+
+    async fn flush(&mut self) -> Result<(), tcp::Error> {
+        self.socket.flush().await
+    }
+}
+
+type Connection<'b> = TlsConnection<'b, Transport<'b>, Aes128GcmSha256>;
+
+/// An open connection and, if one was armed, its wire trace.
+struct Link<'b> {
+    tls: Connection<'b>,
+    peer: &'b Peer,
+    #[cfg(feature = "wiretrace")]
+    trace: Option<wiretrace::Trace>,
+}
+
+impl<'b> Link<'b> {
+    fn new(tls: Connection<'b>, peer: &'b Peer) -> Self {
+        Self {
+            tls,
+            peer,
+            #[cfg(feature = "wiretrace")]
+            trace: wiretrace::begin(),
+        }
+    }
+
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, TlsError> {
+        let n = self.tls.read(buf).await?;
+        #[cfg(feature = "wiretrace")]
+        if let Some(trace) = &mut self.trace {
+            trace.record(Direction::Received, &buf[..n]);
+        }
+        Ok(n)
+    }
+}
+
+impl Incoming for Link<'_> {
+    type Error = FetchError;
+
+    async fn read_part(&mut self, buf: &mut [u8], started: bool) -> Result<usize, FetchError> {
+        read(self, buf, started).await
+    }
+}
+
+impl Outgoing for Link<'_> {
+    type Error = TlsError;
+
+    async fn write_all(&mut self, data: &[u8]) -> Result<(), TlsError> {
+        #[cfg(feature = "wiretrace")]
+        if let Some(trace) = &mut self.trace {
+            trace.record(Direction::Sent, data);
+        }
+        self.tls.write_all(data).await
+    }
+
+    async fn flush(&mut self) -> Result<(), TlsError> {
+        self.tls.flush().await
+    }
+
+    async fn answered(&mut self) -> bool {
+        if !self.peer.answered.get() {
+            // Takes in the records already in, session tickets and all,
+            // and stops short of one still coming; any data among them is
+            // the response, left to be read.
+            self.peer.probe.set(Probe::Started);
+            match select(self.tls.read_buffered(), future::ready(())).await {
+                Either::First(Ok(data)) => {
+                    data.revert();
+                    self.peer.answered.set(true);
+                }
+                Either::First(Err(_)) => self.peer.answered.set(true),
+                Either::Second(()) => {}
+            }
+            self.peer.probe.set(Probe::Off);
+        }
+        self.peer.answered.get()
+    }
+}
+
+/// Resolves, connects and completes the TLS handshake.
+async fn open<'b>(
+    stack: &'b NetStack,
+    url: &Url<'_>,
+    buffers: &'b mut Buffers,
+    timings: &mut Timings,
+) -> Result<Link<'b>, FetchError> {
+    let Buffers {
+        tls_rx,
+        tls_tx,
+        socket_rx,
+        socket_tx,
+        peer,
+        ..
+    } = buffers;
+    let peer = &*peer;
+    peer.answered.set(false);
+    peer.probe.set(Probe::Off);
+
+    let start = Instant::now();
+    let addr = resolver::resolve(stack, url.host)
+        .await
+        .map_err(|e| match e {
+            DnsError::Timeout => FetchError::Timeout(Phase::Dns),
+            e => FetchError::Dns(e),
+        })?;
+    timings.dns_ms = start.elapsed().as_millis();
+
+    let mark = Instant::now();
+    let mut socket = TcpSocket::new(stack, socket_rx, socket_tx);
+    with_timeout(CONNECT_TIMEOUT, socket.connect((addr, url.port)))
+        .await
+        .map_err(|_| FetchError::Timeout(Phase::Connect))?
+        .map_err(FetchError::Connect)?;
+    timings.connect_ms = mark.elapsed().as_millis();
+
+    let delay = entropy::random_u32() % (MAX_HELLO_DELAY_MS + 1);
+    Timer::after_millis(delay as u64).await;
+
+    let mark = Instant::now();
+    let config: TlsConfig<'_, Aes128GcmSha256> = TlsConfig::new().with_server_name(url.host);
+    let transport = Transport {
+        socket,
+        peer,
+        records: Records::default(),
+    };
+    let mut tls = TlsConnection::new(transport, tls_rx, tls_tx);
+    let mut rng = HardwareRng;
+    with_timeout(
+        HANDSHAKE_TIMEOUT,
+        tls.open::<HardwareRng, NoVerify>(TlsContext::new(&config, &mut rng)),
+    )
+    .await
+    .map_err(|_| FetchError::Timeout(Phase::Handshake))?
+    .map_err(FetchError::Handshake)?;
+    timings.handshake_ms = mark.elapsed().as_millis();
+    Ok(Link::new(tls, peer))
+}
+
+/// The request head, with `headers` merged over the settings' ones, a
+/// request ID, and a `Content-Length` for a body of `body_len` bytes.
+fn write_head(
+    method: &str,
+    url: &Url<'_>,
+    headers: &[(&str, &str)],
+    body_len: Option<usize>,
+) -> Result<String<MAX_REQUEST_HEAD_LEN>, FetchError> {
+    let mut head: String<MAX_REQUEST_HEAD_LEN> = String::new();
+    write!(
+        head,
+        "{} {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n",
+        method, url.path, url.host
+    )
+    .map_err(|_| FetchError::InvalidUrl)?;
+    let settings_headers = settings::headers();
+    settings_headers
+        .write_merged(headers, &mut head)
+        .map_err(|_| FetchError::InvalidUrl)?;
+    let own_id = headers
+        .iter()
+        .any(|(name, _)| name.eq_ignore_ascii_case("X-Request-Id"));
+    if !own_id && settings_headers.get("X-Request-Id").is_none() {
+        let id = codec::uuid_to_string(&entropy::uuid_v4(&mut HardwareRng));
+        write!(head, "X-Request-Id: {}\r\n", id).map_err(|_| FetchError::InvalidUrl)?;
+    }
+    if let Some(len) = body_len {
+        write!(head, "Content-Length: {}\r\n", len).map_err(|_| FetchError::InvalidUrl)?;
+    }
+    head.push_str("\r\n").map_err(|_| FetchError::InvalidUrl)?;
+    Ok(head)
+}
+
+/// One read of the response; 0 once it is over. After the first bytes
+/// (`started`) an error or timeout also just ends it: servers commonly drop
+/// the connection right after the body instead of sending close_notify.
+async fn read(link: &mut Link<'_>, buf: &mut [u8], started: bool) -> Result<usize, FetchError> {
+    match with_timeout(RESPONSE_TIMEOUT, link.read(buf)).await {
+        Ok(Ok(n)) => Ok(n),
+        Ok(Err(_)) | Err(_) if started => Ok(0),
+        Ok(Err(e)) => Err(FetchError::Read(e)),
+        Err(_) => Err(FetchError::Timeout(Phase::Response)),
+    }
+}
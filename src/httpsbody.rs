@@ -0,0 +1,353 @@
+// How src/https.rs sends a request and takes in its response, apart from
+// the TLS connection they go over.
+//
+// The head goes out, then the body `BODY_CHUNK_LEN` bytes at a time, or
+// gzipped through a `GZIP_CHUNK_LEN` buffer, and no more of it once the
+// server has answered or gone. Whether it has is the connection's to say
+// (`Outgoing::answered`); it is asked before each piece, and again when a
+// piece fails to go out, since a reset under the write may still leave an
+// answer to read. Only a response counts as an answer, not just bytes
+// come back: a TLS 1.3 server sends session tickets after the handshake.
+// `Records` follows the record framing, so that the connection can read
+// what has come in whole without waiting on what has not.
+//
+// The response is read into the caller's buffer as it comes, its body
+// dechunked in place once it is all in, and, for `get_verified`, hashed
+// and compared with the digest given or published.
+//
+// A streamed body (`get_streamed`) is handed on as it comes, undecoded, so
+// `Streamed` refuses a chunked one. It also tells a connection that fails
+// short of the `Content-Length` from one that fails after the whole body:
+// the first can be asked for again from where it stopped.
+
+use crate::codec;
+use crate::deflate::Gzipped;
+use crate::http;
+use crate::integrity::{self, Sha256, HASH_LEN};
+
+pub const BODY_CHUNK_LEN: usize = 1024;
+/// Smaller: the compressor's state is on the stack next to it.
+pub const GZIP_CHUNK_LEN: usize = 256;
+
+/// Where a request goes out.
+#[allow(async_fn_in_trait)]
+pub trait Outgoing {
+    type Error;
+
+    async fn write_all(&mut self, data: &[u8]) -> Result<(), Self::Error>;
+
+    async fn flush(&mut self) -> Result<(), Self::Error>;
+
+    /// Whether the server has answered, or reset or closed the connection,
+    /// since the request started. Anything else it has sent is not an
+    /// answer.
+    async fn answered(&mut self) -> bool;
+}
+
+/// What goes out after a request's head.
+#[derive(Clone, Copy)]
+pub enum Body<'a> {
+    Plain(&'a [u8]),
+    /// Compressed on the way out.
+    Gzip(&'a Gzipped<'a>),
+}
+
+impl Body<'_> {
+    /// On the wire, as `Content-Length` gives it.
+    pub fn wire_len(&self) -> usize {
+        match self {
+            Body::Plain(data) => data.len(),
+            Body::Gzip(gzipped) => gzipped.compressed_len(),
+        }
+    }
+}
+
+/// How much of a request went out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Sent {
+    pub bytes: usize,
+    /// The server answered before the body was all sent; see `send`.
+    pub early: bool,
+}
+
+/// Sends `head`, and `body` if there is one, in pieces. Stops early, and
+/// says so, if the server answers or goes before the body is done.
+pub async fn send<O: Outgoing>(
+    out: &mut O,
+    head: &str,
+    body: Option<Body<'_>>,
+) -> Result<Sent, O::Error> {
+    out.write_all(head.as_bytes()).await?;
+    let mut sent = Sent {
+        bytes: head.len(),
+        early: false,
+    };
+    match body {
+        None => {}
+        Some(Body::Plain(data)) => {
+            for chunk in data.chunks(BODY_CHUNK_LEN) {
+                if !send_chunk(out, chunk, &mut sent).await? {
+                    return Ok(sent);
+                }
+            }
+        }
+        Some(Body::Gzip(gzipped)) => {
+            let mut encoder = gzipped.encoder();
+            let mut chunk = [0u8; GZIP_CHUNK_LEN];
+            loop {
+                let n = encoder.read(&mut chunk);
+                if n == 0 {
+                    break;
+                }
+                if !send_chunk(out, &chunk[..n], &mut sent).await? {
+                    return Ok(sent);
+                }
+            }
+        }
+    }
+    out.flush().await?;
+    Ok(sent)
+}
+
+/// Sends one piece of a body. `false` if the server has answered or gone
+/// first, which `sent` then notes; nothing more should go out.
+async fn send_chunk<O: Outgoing>(
+    out: &mut O,
+    chunk: &[u8],
+    sent: &mut Sent,
+) -> Result<bool, O::Error> {
+    if out.answered().await {
+        sent.early = true;
+        return Ok(false);
+    }
+    // The flush is where the socket gets written, and so where the
+    // connection looks for an answer.
+    let result = match out.write_all(chunk).await {
+        Ok(()) => out.flush().await,
+        Err(e) => Err(e),
+    };
+    match result {
+        Ok(()) => {
+            sent.bytes += chunk.len();
+            Ok(true)
+        }
+        // Reset or closed under the write: an answer may be waiting.
+        Err(e) => {
+            if !out.answered().await {
+                return Err(e);
+            }
+            sent.early = true;
+            Ok(false)
+        }
+    }
+}
+
+/// A TLS record's header: type, version and length.
+pub const RECORD_HEADER_LEN: usize = 5;
+
+/// Where a stream of TLS records read off the wire stands.
+#[derive(Debug, Default)]
+pub struct Records {
+    header: [u8; RECORD_HEADER_LEN],
+    /// Of the header being read.
+    have: usize,
+    /// Of the body of the record being read.
+    left: usize,
+}
+
+impl Records {
+    /// Takes in `data`, the next bytes read.
+    pub fn advance(&mut self, mut data: &[u8]) {
+        while !data.is_empty() {
+            if self.left > 0 {
+                let n = self.left.min(data.len());
+                self.left -= n;
+                data = &data[n..];
+                continue;
+            }
+            let n = (RECORD_HEADER_LEN - self.have).min(data.len());
+            self.header[self.have..self.have + n].copy_from_slice(&data[..n]);
+            self.have += n;
+            data = &data[n..];
+            if self.have == RECORD_HEADER_LEN {
+                self.have = 0;
+                self.left = record_len(&self.header);
+            }
+        }
+    }
+
+    /// How many of `data`, the bytes next on the wire, end the record
+    /// being read and the whole records after it. 0 if they do not end
+    /// the record being read, or it is cut in its header.
+    pub fn whole(&self, data: &[u8]) -> usize {
+        if self.have > 0 || data.len() < self.left {
+            return 0;
+        }
+        let mut end = self.left;
+        while let Some(header) = data.get(end..end + RECORD_HEADER_LEN) {
+            let next = end + RECORD_HEADER_LEN + record_len(header);
+            if next > data.len() {
+                break;
+            }
+            end = next;
+        }
+        end
+    }
+}
+
+fn record_len(header: &[u8]) -> usize {
+    u16::from_be_bytes([header[3], header[4]]) as usize
+}
+
+/// Where a response comes in.
+#[allow(async_fn_in_trait)]
+pub trait Incoming {
+    type Error;
+
+    /// One read of the response; 0 once it is over. `started` once some of
+    /// it has come.
+    async fn read_part(&mut self, buf: &mut [u8], started: bool) -> Result<usize, Self::Error>;
+}
+
+/// What is wrong with a response that came in whole.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResponseError {
+    Malformed,
+    /// The body does not hash to the digest it was checked against.
+    Mismatch,
+    /// No digest given and none usable in the response.
+    NoDigest,
+}
+
+/// Reads the response into `response` until the server closes the
+/// connection or the buffer is full, keeping `received` up to date so that
+/// a failure still leaves what came. `first_byte` is called as the first
+/// bytes arrive.
+pub async fn receive<I: Incoming>(
+    link: &mut I,
+    response: &mut [u8],
+    received: &mut usize,
+    mut first_byte: impl FnMut(),
+) -> Result<usize, I::Error> {
+    let mut len = 0;
+    while len < response.len() {
+        match link.read_part(&mut response[len..], len > 0).await? {
+            0 => break,
+            n => {
+                if len == 0 {
+                    first_byte();
+                }
+                len += n;
+                *received = len;
+            }
+        }
+    }
+    Ok(len)
+}
+
+/// Decodes a chunked body in place; the response's length after it.
+/// `head_request` for the answer to a `HEAD`, which has the framing its
+/// body would have had and no body.
+pub fn decode_body(
+    response: &mut [u8],
+    body_start: usize,
+    len: usize,
+    head_request: bool,
+) -> Result<usize, ResponseError> {
+    let chunked =
+        !head_request && core::str::from_utf8(&response[..body_start]).is_ok_and(http::is_chunked);
+    if !chunked {
+        return Ok(len);
+    }
+    let body =
+        http::dechunk(&mut response[body_start..len]).map_err(|_| ResponseError::Malformed)?;
+    Ok(body_start + body)
+}
+
+/// Why a response's body cannot be streamed.
+#[cfg(feature = "ota")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StreamedError {
+    Malformed,
+    /// `Transfer-Encoding: chunked`; the framing would go on with the body.
+    Chunked,
+}
+
+/// A streamed body so far.
+#[cfg(feature = "ota")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Streamed {
+    /// `Content-Length`, if the head has one.
+    len: Option<usize>,
+    received: usize,
+}
+
+#[cfg(feature = "ota")]
+impl Streamed {
+    /// The body of the response with `head`, none of it come yet.
+    pub fn new(head: &str) -> Result<Self, StreamedError> {
+        if http::is_chunked(head) {
+            return Err(StreamedError::Chunked);
+        }
+        let len = http::content_length(head).map_err(|_| StreamedError::Malformed)?;
+        Ok(Self { len, received: 0 })
+    }
+
+    pub fn advance(&mut self, n: usize) {
+        self.received = self.received.saturating_add(n);
+    }
+
+    /// Whether the connection failing now cuts the body short. Without a
+    /// length, its end cannot be told from a failure.
+    pub fn cut_short(&self) -> bool {
+        self.len.is_some_and(|len| self.received < len)
+    }
+}
+
+/// Checks that `body` hashes to `expected`, or without one to the digest
+/// `head` publishes.
+pub fn verify(
+    head: &[u8],
+    body: &[u8],
+    expected: Option<&[u8; HASH_LEN]>,
+) -> Result<(), ResponseError> {
+    let published;
+    let expected = match expected {
+        Some(expected) => expected,
+        None => {
+            let head = core::str::from_utf8(head).map_err(|_| ResponseError::Malformed)?;
+            published = published_digest(head).ok_or(ResponseError::NoDigest)?;
+            &published
+        }
+    };
+    // Only fails before `integrity::init`; fails closed if so.
+    let mut sha = Sha256::new().map_err(|_| ResponseError::Mismatch)?;
+    sha.update(body);
+    if !integrity::constant_time_eq(&sha.finish(), expected) {
+        return Err(ResponseError::Mismatch);
+    }
+    Ok(())
+}
+
+/// The SHA-256 a response head gives for its body: `X-Content-Sha256` in
+/// hex, else `sha-256` in base64 from `Digest`.
+pub fn published_digest(head: &str) -> Option<[u8; HASH_LEN]> {
+    let mut digest = [0u8; HASH_LEN];
+    let decoded = match http::header(head, "X-Content-Sha256") {
+        Some(hex) => codec::hex_decode(hex.as_bytes(), &mut digest).ok()?.len(),
+        None => {
+            let encoded = http::header(head, "Digest")?.split(',').find_map(|item| {
+                let (algorithm, value) = item.split_once('=')?;
+                algorithm
+                    .trim()
+                    .eq_ignore_ascii_case("sha-256")
+                    .then(|| value.trim())
+            })?;
+            codec::STANDARD
+                .decode(encoded.as_bytes(), &mut digest)
+                .ok()?
+                .len()
+        }
+    };
+    (decoded == HASH_LEN).then_some(digest)
+}
@@ -0,0 +1,76 @@
+// What answers on the I2C bus, for checking the wiring on the bench. Bench
+// builds (`dev`) scan at boot and log it:
+//
+//     Found I2C devices at: 0x68 (DS3231), 0x75 (IP5306)
+//
+// Each address from 0x08 to 0x77 gets a write of no bytes, just the address,
+// and counts as present if a chip acknowledges it; the others are reserved.
+// No register address follows, which the usual parts take as nothing at
+// all. The names are a guess from the address alone: several parts
+// share some, and the table has the one most likely on this kind of board.
+
+use esp_println::{print, println};
+use heapless::Vec;
+
+use crate::bus::AsyncI2c;
+
+const FIRST: u8 = 0x08;
+const LAST: u8 = 0x77;
+
+/// Common parts by their usual address.
+const KNOWN: &[(u8, &str)] = &[
+    (0x23, "BH1750"),
+    (0x27, "PCF8574 LCD"),
+    (0x29, "VL53L0X"),
+    (0x38, "AHT20"),
+    (0x3C, "OLED"),
+    (0x3D, "OLED"),
+    (0x40, "INA219"),
+    (0x44, "SHT31"),
+    (0x45, "SHT31"),
+    (0x48, "ADS1115"),
+    (0x53, "ADXL345"),
+    (0x57, "AT24C32"),
+    (0x5A, "MLX90614"),
+    (0x68, "DS3231"),
+    (0x75, "IP5306"),
+    (0x76, "BME280"),
+    (0x77, "BME280"),
+];
+
+/// The addresses that acknowledge, in order.
+pub async fn scan_i2c(i2c: &mut AsyncI2c<'_>) -> Vec<u8, 128> {
+    let mut found = Vec::new();
+    for address in FIRST..=LAST {
+        if i2c.write(address, &[]).await.is_ok() {
+            // Cannot overflow: 112 addresses.
+            let _ = found.push(address);
+        }
+    }
+    found
+}
+
+/// A guess at what sits at `address`.
+fn name(address: u8) -> Option<&'static str> {
+    KNOWN
+        .iter()
+        .find(|(known, _)| *known == address)
+        .map(|(_, name)| *name)
+}
+
+/// Logs `found` on one line, with a name where the table has one.
+pub fn print(found: &[u8]) {
+    if found.is_empty() {
+        println!("No I2C devices found.");
+        return;
+    }
+    print!("Found I2C devices at: ");
+    for (i, &address) in found.iter().enumerate() {
+        let comma = if i > 0 { ", " } else { "" };
+        match name(address) {
+            Some(name) => print!("{}0x{:02X} ({})", comma, address, name),
+            None => print!("{}0x{:02X}", comma, address),
+        }
+    }
+    println!();
+}
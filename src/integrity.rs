@@ -0,0 +1,190 @@
+// SHA-256 check of an application image against the hash appended to it.
+//
+// An app image is a 24-byte header, `segment_count` segments (an 8-byte
+// header and the data each), padding up to a checksum byte at the end of a
+// 16-byte block and, when byte 23 of the header is set, the SHA-256 of all
+// of that. espflash and the IDF tools both append it. Only the image is
+// hashed, not the erased rest of the slot, and it is read in
+// `BLOCK_LEN`-byte blocks through the SHA accelerator.
+//
+// The accelerator is also there for anything else to hash as it goes
+// (`Sha256`), like a response body (src/https.rs). There is one, so one
+// `Sha256` at a time.
+
+use core::cell::RefCell;
+
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use embedded_storage::nor_flash::ReadNorFlash;
+use esp_hal::sha::Sha;
+use esp_hal::Blocking;
+use esp_storage::{FlashStorage, FlashStorageError};
+
+use crate::partition::PartitionEntry;
+
+const BLOCK_LEN: usize = 4096;
+pub const HASH_LEN: usize = 32;
+
+const IMAGE_MAGIC: u8 = 0xE9;
+const HEADER_LEN: u32 = 24;
+const SEGMENT_HEADER_LEN: u32 = 8;
+/// The bootloader's own limit.
+const MAX_SEGMENTS: u8 = 16;
+
+#[derive(Debug)]
+pub enum IntegrityError {
+    Flash(#[allow(dead_code)] FlashStorageError),
+    /// The slot does not start with an image header.
+    NoImage,
+    /// The image was built without an appended hash.
+    NoHash,
+    /// The segments run past the end of the slot.
+    Truncated,
+    Mismatch,
+    /// `init` was not called.
+    NoHasher,
+}
+
+impl From<FlashStorageError> for IntegrityError {
+    fn from(e: FlashStorageError) -> Self {
+        IntegrityError::Flash(e)
+    }
+}
+
+static SHA: Mutex<CriticalSectionRawMutex, RefCell<Option<Sha<'static, Blocking>>>> =
+    Mutex::new(RefCell::new(None));
+
+/// Hands over the SHA peripheral, set up for SHA-256.
+pub fn init(sha: Sha<'static, Blocking>) {
+    SHA.lock(|cell| cell.replace(Some(sha)));
+}
+
+/// SHA-256 fed piece by piece, on the accelerator, which it holds until it
+/// is finished or dropped.
+pub struct Sha256 {
+    sha: Option<Sha<'static, Blocking>>,
+}
+
+impl Sha256 {
+    /// Fails with `NoHasher` if another `Sha256` is running, or before
+    /// `init`.
+    pub fn new() -> Result<Self, IntegrityError> {
+        // Out of the lock for the duration, so hashing a whole slot does not
+        // run with interrupts off.
+        let sha = SHA
+            .lock(|cell| cell.borrow_mut().take())
+            .ok_or(IntegrityError::NoHasher)?;
+        Ok(Self { sha: Some(sha) })
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        let Some(sha) = self.sha.as_mut() else {
+            return;
+        };
+        let mut rest = data;
+        while !rest.is_empty() {
+            // The only error is `WouldBlock`.
+            if let Ok(left) = sha.update(rest) {
+                rest = left;
+            }
+        }
+    }
+
+    pub fn finish(mut self) -> [u8; HASH_LEN] {
+        self.release()
+    }
+
+    /// Finishes, which also readies the accelerator for the next hash, and
+    /// hands it back.
+    fn release(&mut self) -> [u8; HASH_LEN] {
+        let mut digest = [0u8; HASH_LEN];
+        if let Some(mut sha) = self.sha.take() {
+            while sha.finish(&mut digest).is_err() {}
+            SHA.lock(|cell| cell.replace(Some(sha)));
+        }
+        digest
+    }
+}
+
+impl Drop for Sha256 {
+    fn drop(&mut self) {
+        self.release();
+    }
+}
+
+/// Reads every byte of both, whatever their lengths and wherever they
+/// differ, so the time taken does not tell how much of a guess was right.
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    let mut diff = a.len() ^ b.len();
+    for i in 0..a.len().max(b.len()) {
+        let x = a.get(i).copied().unwrap_or(0);
+        let y = b.get(i).copied().unwrap_or(0);
+        diff |= (x ^ y) as usize;
+    }
+    core::hint::black_box(diff) == 0
+}
+
+/// Hashes the image in `partition` and compares it with the hash stored
+/// after it.
+pub fn verify_partition_hash(partition: &PartitionEntry) -> Result<(), IntegrityError> {
+    let mut flash = FlashStorage::new();
+    let len = image_len(&mut flash, partition)?;
+    let actual = hash_range(partition.offset, len)?;
+
+    let mut expected = Aligned([0; HASH_LEN]);
+    flash.read(partition.offset + len, &mut expected.0)?;
+    if actual == expected.0 {
+        Ok(())
+    } else {
+        Err(IntegrityError::Mismatch)
+    }
+}
+
+/// SHA-256 of `len` bytes of flash from `offset`.
+pub fn hash_range(offset: u32, len: u32) -> Result<[u8; HASH_LEN], IntegrityError> {
+    let mut sha = Sha256::new()?;
+    let mut flash = FlashStorage::new();
+    let mut block = Aligned([0; BLOCK_LEN]);
+    let mut done = 0;
+    while done < len {
+        let chunk = &mut block.0[..BLOCK_LEN.min((len - done) as usize)];
+        flash.read(offset + done, chunk)?;
+        sha.update(chunk);
+        done += chunk.len() as u32;
+    }
+    Ok(sha.finish())
+}
+
+/// Length of the image up to and including the checksum byte, which is
+/// where the hash starts.
+fn image_len(flash: &mut FlashStorage, partition: &PartitionEntry) -> Result<u32, IntegrityError> {
+    let mut header = Aligned([0; HEADER_LEN as usize]);
+    flash.read(partition.offset, &mut header.0)?;
+    let header = &header.0;
+    if header[0] != IMAGE_MAGIC || header[1] > MAX_SEGMENTS {
+        return Err(IntegrityError::NoImage);
+    }
+    if header[23] == 0 {
+        return Err(IntegrityError::NoHash);
+    }
+
+    let mut len = HEADER_LEN;
+    for _ in 0..header[1] {
+        let mut segment = Aligned([0; SEGMENT_HEADER_LEN as usize]);
+        flash.read(partition.offset + len, &mut segment.0)?;
+        let data_len = u32::from_le_bytes(segment.0[4..8].try_into().unwrap());
+        len = len
+            .checked_add(SEGMENT_HEADER_LEN + data_len)
+            .filter(|&len| len < partition.size)
+            .ok_or(IntegrityError::Truncated)?;
+    }
+    // Padding so that the checksum byte ends a 16-byte block.
+    len = (len + 1).next_multiple_of(16);
+    if len + HASH_LEN as u32 > partition.size {
+        return Err(IntegrityError::Truncated);
+    }
+    Ok(len)
+}
+
+#[repr(C, align(4))]
+struct Aligned<const N: usize>([u8; N]);
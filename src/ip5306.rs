@@ -0,0 +1,131 @@
+// IP5306 power bank IC (charger plus 5 V boost) on I2C, as found on many
+// LiPo-powered ESP32 boards. Only the I2C variant of the chip answers here.
+//
+// The chip has no battery ADC readable over I2C, just the four-step gauge
+// that drives its LEDs, so the voltage is an estimate: the lower edge of the
+// step the battery is currently in.
+
+use esp_hal::i2c::Error as I2cError;
+
+use crate::bus::SharedI2c;
+
+pub const ADDRESS: u8 = 0x75;
+
+const REG_SYS_CTL0: u8 = 0x00;
+const REG_SYS_CTL2: u8 = 0x02;
+const REG_CHG_DIG_CTL0: u8 = 0x24;
+const REG_READ0: u8 = 0x70;
+const REG_READ1: u8 = 0x71;
+const REG_GAUGE: u8 = 0x78;
+
+// SYS_CTL0: keep the boost output on even with a light load.
+const SYS_CTL0_BOOST_ALWAYS_ON: u8 = 1 << 1;
+// SYS_CTL2: how long a light load has to last before the boost shuts off.
+const SYS_CTL2_LIGHT_LOAD_SHIFT: u8 = 2;
+const SYS_CTL2_LIGHT_LOAD_MASK: u8 = 0b11 << SYS_CTL2_LIGHT_LOAD_SHIFT;
+// READ0: charger active. READ1: battery full.
+const READ0_CHARGING: u8 = 1 << 3;
+const READ1_FULL: u8 = 1 << 3;
+
+// Charge current is 50 mA plus 100 mA per step, in the low five bits.
+const CHARGE_CURRENT_MASK: u8 = 0x1F;
+const CHARGE_CURRENT_BASE_MA: u16 = 50;
+const CHARGE_CURRENT_STEP_MA: u16 = 100;
+
+#[derive(Debug)]
+pub enum PmicError {
+    I2c(#[allow(dead_code)] I2cError),
+    /// Outside what the chip can be set to.
+    OutOfRange,
+}
+
+impl From<I2cError> for PmicError {
+    fn from(e: I2cError) -> Self {
+        PmicError::I2c(e)
+    }
+}
+
+pub struct Ip5306<'a> {
+    bus: &'a SharedI2c,
+}
+
+impl<'a> Ip5306<'a> {
+    pub fn new(bus: &'a SharedI2c) -> Self {
+        Self { bus }
+    }
+
+    /// Estimated battery voltage; see the top of the file.
+    pub async fn battery_voltage_mv(&mut self) -> Result<u32, PmicError> {
+        // Gauge steps, top nibble: each further bit set is one LED fewer.
+        let mv = match self.read_register(REG_GAUGE).await? & 0xF0 {
+            0x00 => 4000,
+            0x80 => 3800,
+            0xC0 => 3600,
+            0xE0 => 3400,
+            _ => 3200,
+        };
+        Ok(mv)
+    }
+
+    /// Whether the charger is running. It stops once the battery is full,
+    /// even with USB still plugged in.
+    pub async fn is_charging(&mut self) -> Result<bool, PmicError> {
+        let active = self.read_register(REG_READ0).await? & READ0_CHARGING != 0;
+        let full = self.read_register(REG_READ1).await? & READ1_FULL != 0;
+        Ok(active && !full)
+    }
+
+    /// Sets the charge current, 50 to 3150 mA in 100 mA steps, rounding
+    /// down.
+    pub async fn set_charging_current(&mut self, ma: u16) -> Result<(), PmicError> {
+        if !(CHARGE_CURRENT_BASE_MA..=3150).contains(&ma) {
+            return Err(PmicError::OutOfRange);
+        }
+        let steps = ((ma - CHARGE_CURRENT_BASE_MA) / CHARGE_CURRENT_STEP_MA) as u8;
+        let value = self.read_register(REG_CHG_DIG_CTL0).await?;
+        self.write_register(REG_CHG_DIG_CTL0, (value & !CHARGE_CURRENT_MASK) | steps)
+            .await
+    }
+
+    /// Lets the boost output switch off after the load has stayed light
+    /// (roughly under 50 mA, e.g. the ESP32 in deep sleep) for `seconds`.
+    /// The chip only knows 8, 16, 32 and 64 s; this picks the shortest that
+    /// is at least `seconds`.
+    pub async fn power_off_after(&mut self, seconds: u32) -> Result<(), PmicError> {
+        let code = match seconds {
+            0..=8 => 0b00,
+            9..=16 => 0b10,
+            17..=32 => 0b01,
+            33..=64 => 0b11,
+            _ => return Err(PmicError::OutOfRange),
+        };
+        let ctl2 = self.read_register(REG_SYS_CTL2).await?;
+        self.write_register(
+            REG_SYS_CTL2,
+            (ctl2 & !SYS_CTL2_LIGHT_LOAD_MASK) | (code << SYS_CTL2_LIGHT_LOAD_SHIFT),
+        )
+        .await?;
+        let ctl0 = self.read_register(REG_SYS_CTL0).await?;
+        self.write_register(REG_SYS_CTL0, ctl0 & !SYS_CTL0_BOOST_ALWAYS_ON)
+            .await
+    }
+
+    async fn read_register(&mut self, register: u8) -> Result<u8, PmicError> {
+        let mut value = [0u8];
+        self.bus
+            .lock()
+            .await
+            .write_read(ADDRESS, &[register], &mut value)
+            .await?;
+        Ok(value[0])
+    }
+
+    async fn write_register(&mut self, register: u8, value: u8) -> Result<(), PmicError> {
+        self.bus
+            .lock()
+            .await
+            .write(ADDRESS, &[register, value])
+            .await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,146 @@
+// Just enough JSON for the bodies the device is sent: one flat object of
+// strings, numbers and booleans, as the API's PUT and POST bodies
+// (src/api.rs) and fleet commands (src/fleet.rs) are.
+//
+// There is no nesting to bound: an object or array as a value is an error,
+// as is a value longer than `MAX_URL_LEN` once unescaped.
+
+use core::fmt::Write;
+
+use heapless::String;
+
+use crate::settings::MAX_URL_LEN;
+
+pub type Value = String<MAX_URL_LEN>;
+
+/// Calls `field` with each key and value of the object in `body`, in
+/// order. Strings are unescaped; numbers and booleans come as their
+/// text.
+pub fn fields<'a>(
+    body: &'a [u8],
+    mut field: impl FnMut(&'a str, Value) -> Result<(), &'static str>,
+) -> Result<(), &'static str> {
+    let text = core::str::from_utf8(body).map_err(|_| "body is not UTF-8")?;
+    let mut p = Parser { text, pos: 0 };
+    p.expect(b'{')?;
+    if p.peek() == Some(b'}') {
+        p.pos += 1;
+    } else {
+        loop {
+            let key = p.key()?;
+            p.expect(b':')?;
+            field(key, p.value()?)?;
+            match p.next() {
+                Some(b',') => continue,
+                Some(b'}') => break,
+                _ => return Err("malformed JSON"),
+            }
+        }
+    }
+    match p.peek() {
+        None => Ok(()),
+        Some(_) => Err("malformed JSON"),
+    }
+}
+
+struct Parser<'a> {
+    text: &'a str,
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    /// The next byte that is not whitespace, left in place.
+    fn peek(&mut self) -> Option<u8> {
+        let rest = &self.text.as_bytes()[self.pos..];
+        self.pos += rest.iter().take_while(|b| b.is_ascii_whitespace()).count();
+        self.text.as_bytes().get(self.pos).copied()
+    }
+
+    fn next(&mut self) -> Option<u8> {
+        let byte = self.peek()?;
+        self.pos += 1;
+        Some(byte)
+    }
+
+    fn expect(&mut self, byte: u8) -> Result<(), &'static str> {
+        match self.next() {
+            Some(b) if b == byte => Ok(()),
+            _ => Err("malformed JSON"),
+        }
+    }
+
+    /// A key, which has no escapes in any of ours.
+    fn key(&mut self) -> Result<&'a str, &'static str> {
+        self.expect(b'"')?;
+        let start = self.pos;
+        let len = self.text[start..].find('"').ok_or("malformed JSON")?;
+        let key = &self.text[start..start + len];
+        if key.contains('\\') {
+            return Err("unknown key");
+        }
+        self.pos = start + len + 1;
+        Ok(key)
+    }
+
+    fn value(&mut self) -> Result<Value, &'static str> {
+        let mut value = Value::new();
+        if self.peek() == Some(b'"') {
+            self.pos += 1;
+            self.string(&mut value)?;
+            return Ok(value);
+        }
+        let rest = &self.text[self.pos..];
+        let len = rest
+            .find(|c: char| !(c.is_ascii_alphanumeric() || matches!(c, '-' | '+' | '.')))
+            .unwrap_or(rest.len());
+        let token = &rest[..len];
+        let number = !token.is_empty()
+            && token
+                .bytes()
+                .all(|b| b.is_ascii_digit() || matches!(b, b'-' | b'+' | b'.' | b'e' | b'E'));
+        if !(number || matches!(token, "true" | "false")) {
+            return Err("values must be strings, numbers or booleans");
+        }
+        value.push_str(token).map_err(|_| "value too long")?;
+        self.pos += len;
+        Ok(value)
+    }
+
+    /// The rest of a string after its opening quote, unescaped.
+    fn string(&mut self, out: &mut Value) -> Result<(), &'static str> {
+        let mut chars = self.text[self.pos..].char_indices();
+        while let Some((i, c)) = chars.next() {
+            let c = match c {
+                '"' => {
+                    self.pos += i + 1;
+                    return Ok(());
+                }
+                '\\' => match chars.next().map(|(_, c)| c) {
+                    Some('"') => '"',
+                    Some('\\') => '\\',
+                    Some('/') => '/',
+                    Some('b') => '\u{8}',
+                    Some('f') => '\u{c}',
+                    Some('n') => '\n',
+                    Some('r') => '\r',
+                    Some('t') => '\t',
+                    Some('u') => {
+                        let mut code = 0;
+                        for _ in 0..4 {
+                            let digit = chars.next().and_then(|(_, c)| c.to_digit(16));
+                            code = code * 16 + digit.ok_or("malformed JSON")?;
+                        }
+                        // Surrogate pairs would only matter for text no
+                        // setting takes.
+                        char::from_u32(code).ok_or("unsupported escape")?
+                    }
+                    _ => return Err("malformed JSON"),
+                },
+                c if (c as u32) < 0x20 => return Err("malformed JSON"),
+                c => c,
+            };
+            out.write_char(c).map_err(|_| "value too long")?;
+        }
+        Err("malformed JSON")
+    }
+}
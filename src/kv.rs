@@ -0,0 +1,548 @@
+// Small key-value store in the `nvs` data partition.
+//
+// Records are appended to one 4 KB bank. When it fills up, the live records
+// are copied to the other bank, which then takes over. A record's header word
+// is written after its payload, and a bank's magic after everything else, so
+// a write cut short by a reset is not there on the next boot. What it did
+// write stays in the way, though, since flash bits only go back to 1 with
+// an erase: a bank whose free space is not all erased is taken as full, so
+// the next write compacts into the other bank instead of programming over
+// the remains.
+//
+// Every record ends in a CRC-32 of its header, key and value, checked on
+// every read, so a bit that flipped in flash is an error rather than a
+// wrong value. A store from before the CRC has banks with the old magic and
+// no trailers; it is compacted into the new layout the first time it is
+// opened.
+//
+// The layout is our own; nothing else on the device reads this partition.
+//
+// All erases and writes go through `flash`, which yields between units, so a
+// compaction does not hold the flash (and with it the Wi-Fi driver) for its
+// whole duration.
+
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::mutex::{Mutex, MutexGuard};
+use embedded_storage::nor_flash::ReadNorFlash;
+use esp_hal::rom::crc::crc32_le;
+use esp_println::println;
+use esp_storage::{FlashStorage, FlashStorageError};
+
+use crate::flash;
+
+/// Start of the `nvs` partition in the default partition table.
+const REGION_START: u32 = 0x9000;
+const BANK_SIZE: u32 = FlashStorage::SECTOR_SIZE;
+const BANKS: [u32; 2] = [REGION_START, REGION_START + BANK_SIZE];
+
+// Bank header: generation word, then magic word.
+const BANK_MAGIC: u32 = u32::from_le_bytes(*b"KVS2");
+/// A bank whose records have no CRC.
+const BANK_MAGIC_UNCHECKED: u32 = u32::from_le_bytes(*b"KVS1");
+const BANK_HEADER_LEN: u32 = 8;
+
+// Record header: marker, key length, value length (u16 LE), then the key and
+// value padded to a whole word, then the CRC word.
+const RECORD_HEADER_LEN: u32 = 4;
+const RECORD_CRC_LEN: u32 = 4;
+const MARKER_LIVE: u8 = 0xA5;
+const MARKER_DELETED: u8 = 0x5A;
+
+const ERASED: u32 = u32::MAX;
+const WORD: u32 = FlashStorage::WORD_SIZE;
+
+pub const MAX_KEY_LEN: usize = 32;
+pub const MAX_VALUE_LEN: usize = 1024;
+
+#[derive(Debug)]
+pub enum KvError {
+    Flash(#[allow(dead_code)] FlashStorageError),
+    KeyTooLong,
+    ValueTooLong,
+    /// The live records do not fit in a bank, even after compaction.
+    Full,
+    /// The caller's buffer is smaller than the stored value, which has this
+    /// many bytes.
+    BufferTooSmall(#[allow(dead_code)] usize),
+    /// The record's CRC does not match what is stored; at this offset.
+    Corrupt(#[allow(dead_code)] u32),
+}
+
+impl From<FlashStorageError> for KvError {
+    fn from(e: FlashStorageError) -> Self {
+        KvError::Flash(e)
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Record {
+    offset: u32,
+    marker: u8,
+    key_len: u8,
+    value_len: u16,
+    /// Whether it ends in a CRC word.
+    checked: bool,
+}
+
+impl Record {
+    fn header(&self) -> u32 {
+        let [len_lo, len_hi] = self.value_len.to_le_bytes();
+        u32::from_le_bytes([self.marker, self.key_len, len_lo, len_hi])
+    }
+
+    fn payload_len(&self) -> u32 {
+        self.key_len as u32 + self.value_len as u32
+    }
+
+    fn len(&self) -> u32 {
+        let crc_len = if self.checked { RECORD_CRC_LEN } else { 0 };
+        RECORD_HEADER_LEN + padded(self.payload_len()) + crc_len
+    }
+
+    fn value_offset(&self) -> u32 {
+        self.offset + RECORD_HEADER_LEN + self.key_len as u32
+    }
+
+    fn crc_offset(&self) -> u32 {
+        self.offset + RECORD_HEADER_LEN + padded(self.payload_len())
+    }
+}
+
+struct Store {
+    flash: FlashStorage,
+    bank: u32,
+    generation: u32,
+    /// Where the next record goes.
+    end: u32,
+    /// The bank's records end in a CRC word.
+    checked: bool,
+}
+
+static STORE: Mutex<CriticalSectionRawMutex, Option<Store>> = Mutex::new(None);
+
+/// Reads the value of `key` into `buf` and returns its length, or `None` if
+/// the key is not set.
+pub async fn get(key: &str, buf: &mut [u8]) -> Result<Option<usize>, KvError> {
+    let mut guard = open().await?;
+    let store = guard.as_mut().unwrap();
+
+    let Some(record) = store.find(key.as_bytes())? else {
+        return Ok(None);
+    };
+    let len = record.value_len as usize;
+    if len > buf.len() {
+        return Err(KvError::BufferTooSmall(len));
+    }
+    store.verify(&record)?;
+    store.flash.read(record.value_offset(), &mut buf[..len])?;
+    Ok(Some(len))
+}
+
+pub async fn set(key: &str, value: &[u8]) -> Result<(), KvError> {
+    if key.len() > MAX_KEY_LEN {
+        return Err(KvError::KeyTooLong);
+    }
+    if value.len() > MAX_VALUE_LEN {
+        return Err(KvError::ValueTooLong);
+    }
+    let mut guard = open().await?;
+    guard
+        .as_mut()
+        .unwrap()
+        .append(MARKER_LIVE, key.as_bytes(), value)
+        .await
+}
+
+/// Removes `key`. Removing a key that is not set is not an error.
+pub async fn remove(key: &str) -> Result<(), KvError> {
+    let mut guard = open().await?;
+    let store = guard.as_mut().unwrap();
+    if store.find(key.as_bytes())?.is_none() {
+        return Ok(());
+    }
+    store.append(MARKER_DELETED, key.as_bytes(), &[]).await
+}
+
+/// Erases every key, e.g. for a factory reset.
+pub async fn clear() -> Result<(), KvError> {
+    let mut guard = STORE.lock().await;
+    let mut flash = FlashStorage::new();
+    for bank in BANKS {
+        flash::erase(&mut flash, bank, bank + BANK_SIZE).await?;
+    }
+    // Formatted again on next use.
+    *guard = None;
+    Ok(())
+}
+
+/// Keeps other callers waiting for as long as it lives.
+pub struct Hold(#[allow(dead_code)] MutexGuard<'static, CriticalSectionRawMutex, Option<Store>>);
+
+/// Waits for the write under way, if any, and holds off the rest.
+pub async fn hold() -> Hold {
+    Hold(STORE.lock().await)
+}
+
+async fn open() -> Result<MutexGuard<'static, CriticalSectionRawMutex, Option<Store>>, KvError> {
+    let mut guard = STORE.lock().await;
+    if guard.is_none() {
+        *guard = Some(Store::open(FlashStorage::new()).await?);
+    }
+    Ok(guard)
+}
+
+impl Store {
+    async fn open(mut flash: FlashStorage) -> Result<Self, KvError> {
+        let mut active = None;
+        for bank in BANKS {
+            let generation = read_word(&mut flash, bank)?;
+            let checked = match read_word(&mut flash, bank + WORD)? {
+                BANK_MAGIC => true,
+                BANK_MAGIC_UNCHECKED => false,
+                _ => continue,
+            };
+            match active {
+                Some((_, newest, _)) if newest >= generation => {}
+                _ => active = Some((bank, generation, checked)),
+            }
+        }
+
+        let mut store = match active {
+            Some((bank, generation, checked)) => Self {
+                flash,
+                bank,
+                generation,
+                end: bank + BANK_HEADER_LEN,
+                checked,
+            },
+            None => {
+                let bank = BANKS[0];
+                format_bank(&mut flash, bank, 1).await?;
+                Self {
+                    flash,
+                    bank,
+                    generation: 1,
+                    end: bank + BANK_HEADER_LEN,
+                    checked: true,
+                }
+            }
+        };
+
+        let mut offset = store.bank + BANK_HEADER_LEN;
+        loop {
+            match store.record_at(offset) {
+                Ok(Some(record)) => offset += record.len(),
+                Ok(None) => break,
+                // Not a record we wrote. Nothing can be appended after it, so
+                // pretend the bank is full and let the next write compact.
+                Err(_) => {
+                    offset = store.bank + BANK_SIZE;
+                    break;
+                }
+            }
+        }
+        // A write cut short leaves its payload without the header in front.
+        if offset < store.bank + BANK_SIZE && !store.erased_from(offset)? {
+            offset = store.bank + BANK_SIZE;
+        }
+        store.end = offset;
+        if !store.checked {
+            println!("kv: adding CRCs to the records");
+            // Until it works, records are read and written the old way.
+            if let Err(e) = store.compact().await {
+                println!("kv: records stay without CRCs for now: {:?}", e);
+            }
+        }
+        Ok(store)
+    }
+
+    /// Returns the record at `offset`, `None` at the end of the written area,
+    /// or `Err(Full)` for something that is not a valid record.
+    fn record_at(&mut self, offset: u32) -> Result<Option<Record>, KvError> {
+        if offset + RECORD_HEADER_LEN > self.bank + BANK_SIZE {
+            return Ok(None);
+        }
+        let header = read_word(&mut self.flash, offset)?;
+        if header == ERASED {
+            return Ok(None);
+        }
+        let [marker, key_len, len_lo, len_hi] = header.to_le_bytes();
+        let record = Record {
+            offset,
+            marker,
+            key_len,
+            value_len: u16::from_le_bytes([len_lo, len_hi]),
+            checked: self.checked,
+        };
+        let valid_marker = marker == MARKER_LIVE || marker == MARKER_DELETED;
+        if !valid_marker
+            || key_len as usize > MAX_KEY_LEN
+            || record.value_len as usize > MAX_VALUE_LEN
+            || offset + record.len() > self.bank + BANK_SIZE
+        {
+            return Err(KvError::Full);
+        }
+        Ok(Some(record))
+    }
+
+    /// Whether the bank reads as erased from `offset` to its end.
+    fn erased_from(&mut self, mut offset: u32) -> Result<bool, KvError> {
+        let bank_end = self.bank + BANK_SIZE;
+        let mut chunk = WordBuf([0; 64]);
+        while offset < bank_end {
+            let n = (bank_end - offset).min(chunk.0.len() as u32);
+            let part = &mut chunk.0[..n as usize];
+            self.flash.read(offset, part)?;
+            if part.iter().any(|&b| b != 0xFF) {
+                return Ok(false);
+            }
+            offset += n;
+        }
+        Ok(true)
+    }
+
+    fn key_matches(&mut self, record: &Record, key: &[u8]) -> Result<bool, KvError> {
+        if record.key_len as usize != key.len() {
+            return Ok(false);
+        }
+        let mut stored = [0u8; MAX_KEY_LEN];
+        let stored = &mut stored[..key.len()];
+        self.flash.read(record.offset + RECORD_HEADER_LEN, stored)?;
+        Ok(stored == key)
+    }
+
+    /// The CRC-32 of `record`'s header, key and value as they are in flash.
+    fn compute_crc(&mut self, record: &Record) -> Result<u32, KvError> {
+        let mut crc = crc32_le(0, &record.header().to_le_bytes());
+        let mut chunk = WordBuf([0; 64]);
+        let mut done = 0;
+        while done < record.payload_len() {
+            let n = (record.payload_len() - done).min(chunk.0.len() as u32);
+            let part = &mut chunk.0[..n as usize];
+            self.flash
+                .read(record.offset + RECORD_HEADER_LEN + done, part)?;
+            crc = crc32_le(crc, part);
+            done += n;
+        }
+        Ok(crc)
+    }
+
+    /// `Err(Corrupt)` if `record` has a CRC and it does not match.
+    fn verify(&mut self, record: &Record) -> Result<(), KvError> {
+        if record.checked
+            && read_word(&mut self.flash, record.crc_offset())? != self.compute_crc(record)?
+        {
+            return Err(KvError::Corrupt(record.offset));
+        }
+        Ok(())
+    }
+
+    /// Latest live record for `key` in the active bank.
+    fn find(&mut self, key: &[u8]) -> Result<Option<Record>, KvError> {
+        let mut found = None;
+        let mut offset = self.bank + BANK_HEADER_LEN;
+        while offset < self.end {
+            let Some(record) = self.record_at(offset)? else {
+                break;
+            };
+            if self.key_matches(&record, key)? {
+                found = Some(record);
+            }
+            offset += record.len();
+        }
+        Ok(found.filter(|r| r.marker == MARKER_LIVE))
+    }
+
+    /// Whether no record after `record` has the same key.
+    fn is_latest(&mut self, record: &Record) -> Result<bool, KvError> {
+        let mut key = [0u8; MAX_KEY_LEN];
+        let key = &mut key[..record.key_len as usize];
+        self.flash.read(record.offset + RECORD_HEADER_LEN, key)?;
+
+        let mut offset = record.offset + record.len();
+        while offset < self.end {
+            let Some(later) = self.record_at(offset)? else {
+                break;
+            };
+            if self.key_matches(&later, key)? {
+                return Ok(false);
+            }
+            offset += later.len();
+        }
+        Ok(true)
+    }
+
+    async fn append(&mut self, marker: u8, key: &[u8], value: &[u8]) -> Result<(), KvError> {
+        let mut record = Record {
+            offset: 0,
+            marker,
+            key_len: key.len() as u8,
+            value_len: value.len() as u16,
+            checked: self.checked,
+        };
+        if self.end + record.len() > self.bank + BANK_SIZE {
+            self.compact().await?;
+            // The compaction may have added CRCs.
+            record.checked = self.checked;
+            if self.end + record.len() > self.bank + BANK_SIZE {
+                return Err(KvError::Full);
+            }
+        }
+
+        record.offset = self.end;
+        if let Err(e) = self.write_record(&record, key, value).await {
+            // The same as after a reset: whatever got written is in the way.
+            self.end = self.bank + BANK_SIZE;
+            return Err(e);
+        }
+        self.end = record.offset + record.len();
+        Ok(())
+    }
+
+    /// Payload, CRC, header last.
+    async fn write_record(
+        &mut self,
+        record: &Record,
+        key: &[u8],
+        value: &[u8],
+    ) -> Result<(), KvError> {
+        let header = record.header();
+        let crc = crc32_le(crc32_le(crc32_le(0, &header.to_le_bytes()), key), value);
+        write_padded(
+            &mut self.flash,
+            record.offset + RECORD_HEADER_LEN,
+            &[key, value],
+        )
+        .await?;
+        if record.checked {
+            write_word(&mut self.flash, record.crc_offset(), crc).await?;
+        }
+        write_word(&mut self.flash, record.offset, header).await
+    }
+
+    /// Copies the latest live record of every key into the other bank and
+    /// switches to it.
+    async fn compact(&mut self) -> Result<(), KvError> {
+        let target = if self.bank == BANKS[0] {
+            BANKS[1]
+        } else {
+            BANKS[0]
+        };
+        // The copy counts as part of the burst; it follows the erase closely.
+        let _burst = flash::burst();
+        flash::erase(&mut self.flash, target, target + BANK_SIZE).await?;
+
+        let mut dst = target + BANK_HEADER_LEN;
+        let mut offset = self.bank + BANK_HEADER_LEN;
+        while offset < self.end {
+            let record = match self.record_at(offset) {
+                Ok(Some(record)) => record,
+                // Whatever follows an unreadable record is lost.
+                Ok(None) | Err(KvError::Full) => break,
+                Err(e) => return Err(e),
+            };
+            offset += record.len();
+            if record.marker != MARKER_LIVE || !self.is_latest(&record)? {
+                continue;
+            }
+            // Checked before anything is written: a record without its
+            // header would end the bank there.
+            let crc = self.compute_crc(&record)?;
+            if record.checked && read_word(&mut self.flash, record.crc_offset())? != crc {
+                println!("kv: dropping the corrupt record at {:#x}", record.offset);
+                continue;
+            }
+
+            // Payload, CRC, header last, as for a fresh write.
+            let copy = Record {
+                offset: dst,
+                checked: true,
+                ..record
+            };
+            // Only when adding CRCs can the copies outgrow the originals.
+            if dst + copy.len() > target + BANK_SIZE {
+                return Err(KvError::Full);
+            }
+            let mut chunk = WordBuf([0; 64]);
+            let mut done = RECORD_HEADER_LEN;
+            let payload_end = RECORD_HEADER_LEN + padded(record.payload_len());
+            while done < payload_end {
+                let n = (payload_end - done).min(chunk.0.len() as u32);
+                let part = &mut chunk.0[..n as usize];
+                self.flash.read(record.offset + done, part)?;
+                flash::write(&mut self.flash, dst + done, part).await?;
+                done += n;
+            }
+            write_word(&mut self.flash, copy.crc_offset(), crc).await?;
+            write_word(&mut self.flash, dst, record.header()).await?;
+            dst += copy.len();
+        }
+
+        let generation = self.generation.wrapping_add(1);
+        write_word(&mut self.flash, target, generation).await?;
+        write_word(&mut self.flash, target + WORD, BANK_MAGIC).await?;
+
+        self.bank = target;
+        self.generation = generation;
+        self.end = dst;
+        self.checked = true;
+        println!(
+            "kv: compacted into bank {:#x}, longest flash stall so far {} ms",
+            target,
+            flash::longest_stall().as_millis()
+        );
+        Ok(())
+    }
+}
+
+#[repr(C, align(4))]
+struct WordBuf<const N: usize>([u8; N]);
+
+fn padded(len: u32) -> u32 {
+    len.div_ceil(WORD) * WORD
+}
+
+fn read_word(flash: &mut FlashStorage, offset: u32) -> Result<u32, KvError> {
+    let mut word = WordBuf([0; 4]);
+    flash.read(offset, &mut word.0)?;
+    Ok(u32::from_le_bytes(word.0))
+}
+
+async fn write_word(flash: &mut FlashStorage, offset: u32, value: u32) -> Result<(), KvError> {
+    flash::write(flash, offset, &WordBuf(value.to_le_bytes()).0).await?;
+    Ok(())
+}
+
+/// Writes `parts` back to back starting at `offset`, padding the end with
+/// 0xFF to a whole word.
+async fn write_padded(
+    flash: &mut FlashStorage,
+    offset: u32,
+    parts: &[&[u8]],
+) -> Result<(), KvError> {
+    let mut chunk = WordBuf([0xFF; 64]);
+    let mut filled = 0;
+    let mut offset = offset;
+    for &byte in parts.iter().flat_map(|p| p.iter()) {
+        chunk.0[filled] = byte;
+        filled += 1;
+        if filled == chunk.0.len() {
+            flash::write(flash, offset, &chunk.0).await?;
+            offset += filled as u32;
+            filled = 0;
+        }
+    }
+    if filled > 0 {
+        let len = padded(filled as u32) as usize;
+        chunk.0[filled..len].fill(0xFF);
+        flash::write(flash, offset, &chunk.0[..len]).await?;
+    }
+    Ok(())
+}
+
+async fn format_bank(flash: &mut FlashStorage, bank: u32, generation: u32) -> Result<(), KvError> {
+    flash::erase(flash, bank, bank + BANK_SIZE).await?;
+    write_word(flash, bank, generation).await?;
+    write_word(flash, bank + WORD, BANK_MAGIC).await?;
+    Ok(())
+}
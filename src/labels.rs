@@ -0,0 +1,154 @@
+// Labels that say which group a device belongs to, like its site or
+// hardware revision, so that the server need not keep that mapping: from
+// the settings' `label` lines (src/settings.rs), e.g. `label=site=berlin`,
+// and from LABELS at build time, e.g. `LABELS=site=berlin,rev=b`.
+//
+// The build's are defaults: a label in the settings with the same key
+// replaces one (`merged`). The settings are the persisted ones until the
+// config poll or the API brings others, which are persisted in their turn,
+// so the order is build, then flash, then remote.
+//
+// Readings carry them as `labels`, an object (src/uploader.rs), and the
+// Prometheus exposition on every sample (src/metrics.rs). Hence the rules,
+// which `add` checks: a key is a Prometheus label name in lower case, a
+// letter or `_` and then letters, digits and `_`, up to `MAX_KEY_LEN`
+// bytes. `le` and `phase` are the exposition's own, `device_id` is kept
+// for the server, and `__` starts Prometheus' internal names. A value is
+// letters, digits and `-_.:/`, up to `MAX_VALUE_LEN` bytes, so that neither
+// format needs to escape it.
+//
+// At most `MAX_COUNT` labels, taking up to `MAX_LEN` bytes as `key=value`
+// lines together, so that a settings document with them still fits the KV
+// store.
+
+use core::fmt::{self, Write};
+
+use heapless::String;
+
+pub const MAX_COUNT: usize = 4;
+/// Of all the `key=value\n` lines together.
+pub const MAX_LEN: usize = 56;
+const MAX_KEY_LEN: usize = 16;
+const MAX_VALUE_LEN: usize = 32;
+
+/// Room for `labels` as a field of a reading, leading comma included.
+pub const MAX_JSON_LEN: usize = ",\"labels\":{}".len() + MAX_LEN + 4 * MAX_COUNT;
+
+const BUILD: Option<&str> = option_env!("LABELS");
+
+const RESERVED: [&str; 3] = ["device_id", "le", "phase"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LabelError {
+    /// Not `key=value`.
+    Malformed,
+    /// A character a key cannot have, or too long.
+    Key,
+    /// A character a value cannot have, empty, or too long.
+    Value,
+    Reserved,
+    Duplicate,
+    TooMany,
+    TooLong,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Labels {
+    /// `key=value\n` each.
+    lines: String<MAX_LEN>,
+}
+
+impl Labels {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.lines.lines().filter_map(|line| line.split_once('='))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lines.is_empty()
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.iter().find(|&(k, _)| k == key).map(|(_, value)| value)
+    }
+
+    /// Adds `label`, `key=value`. Nothing changes if it fails.
+    pub fn add(&mut self, label: &str) -> Result<(), LabelError> {
+        let (key, value) = label.split_once('=').ok_or(LabelError::Malformed)?;
+        if !is_key(key) {
+            return Err(LabelError::Key);
+        }
+        if value.is_empty() || value.len() > MAX_VALUE_LEN || !value.bytes().all(is_value_byte) {
+            return Err(LabelError::Value);
+        }
+        if RESERVED.contains(&key) || key.starts_with("__") {
+            return Err(LabelError::Reserved);
+        }
+        if self.get(key).is_some() {
+            return Err(LabelError::Duplicate);
+        }
+        if self.iter().count() == MAX_COUNT {
+            return Err(LabelError::TooMany);
+        }
+        if self.lines.len() + key.len() + value.len() + 2 > MAX_LEN {
+            return Err(LabelError::TooLong);
+        }
+        // Cannot fail: the room was checked above.
+        let _ = writeln!(self.lines, "{}={}", key, value);
+        Ok(())
+    }
+
+    /// These, and those of `defaults` whose key is not among them, as far
+    /// as they fit.
+    pub fn merged(&self, defaults: &Labels) -> Labels {
+        let mut merged = self.clone();
+        for (key, value) in defaults.iter() {
+            if merged.get(key).is_none() {
+                let mut label: String<{ MAX_KEY_LEN + 1 + MAX_VALUE_LEN }> = String::new();
+                let _ = write!(label, "{}={}", key, value);
+                let _ = merged.add(&label);
+            }
+        }
+        merged
+    }
+
+    /// As a JSON object, `{"site":"berlin"}`.
+    pub fn write_json<W: Write>(&self, out: &mut W) -> fmt::Result {
+        out.write_char('{')?;
+        for (i, (key, value)) in self.iter().enumerate() {
+            let comma = if i > 0 { "," } else { "" };
+            write!(out, "{}\"{}\":\"{}\"", comma, key, value)?;
+        }
+        out.write_char('}')
+    }
+}
+
+/// The build's labels, from a comma-separated LABELS. Any that breaks the
+/// rules is left out.
+pub fn defaults() -> Labels {
+    let mut labels = Labels::new();
+    for label in BUILD.unwrap_or("").split(',').map(str::trim) {
+        if !label.is_empty() {
+            let _ = labels.add(label);
+        }
+    }
+    labels
+}
+
+fn is_key(key: &str) -> bool {
+    key.len() <= MAX_KEY_LEN
+        && key
+            .bytes()
+            .next()
+            .is_some_and(|b| b.is_ascii_lowercase() || b == b'_')
+        && key
+            .bytes()
+            .all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || b == b'_')
+}
+
+fn is_value_byte(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || b"-_.:/".contains(&b)
+}
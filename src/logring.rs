@@ -0,0 +1,432 @@
+// The logger, and the last few KB of what it logged.
+//
+// `Logger` stands in for esp-println's: the same colored lines on the
+// console, filtered by the ESP_LOGLEVEL and ESP_LOGTARGETS the firmware was
+// built with, and by `log_level` in the settings. On the way it shows each
+// record to src/wifiheap.rs, which counts failed allocations off them.
+//
+// With the API, each record also goes into `LOG_RING`, which keeps the
+// newest `RING_LEN` bytes of them, dropping whole entries from the front to
+// make room. `log_format` in the settings (`set_log_format`) says how:
+//
+// - `Text`, a line of `<uptime s>.<ms> <LEVEL> <module> - <message>`;
+// - `Binary`, a MessagePack array `[uptime_ms, level, module, message]`,
+//   the level 1 for error to 5 for trace (src/msgpack.rs).
+//
+// Module and message are cut off at `MAX_FIELD_LEN` bytes either way.
+// Binary only saves on the framing: the timestamp's digits, the level's
+// name, the spaces and the dash, some ten bytes an entry. The strings are
+// most of an entry, so over typical lines from esp-wifi and the network
+// stack that is about a tenth, not more. Both sizes are counted for every
+// record (`encoded_bytes`), whichever is kept, to see what it comes to on a
+// device. Switching formats empties the ring.
+//
+// `GET /api/v1/logs` (src/api.rs) answers with the ring: as MessagePack,
+// one array after another, to a request that accepts
+// `application/msgpack` while the ring is binary, and as text otherwise,
+// decoding binary entries on the way out. Only `log` records get there:
+// what the firmware itself prints goes straight to the console with
+// `println!`, not through the logger.
+
+use core::str::FromStr;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use esp_println::println;
+use log::{Level, LevelFilter, Log, Metadata, Record};
+
+use crate::wifiheap;
+
+#[cfg(feature = "api")]
+pub use ring::{encoded_bytes, snapshot, Snapshot};
+
+const LOG_TARGETS: Option<&str> = option_env!("ESP_LOGTARGETS");
+
+/// Whether the ring keeps `LogFormat::Binary`; only loaded and stored.
+static BINARY: AtomicBool = AtomicBool::new(false);
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum LogFormat {
+    #[default]
+    Text,
+    Binary,
+}
+
+impl LogFormat {
+    pub fn parse(text: &str) -> Option<Self> {
+        match text {
+            "text" => Some(LogFormat::Text),
+            "binary" => Some(LogFormat::Binary),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            LogFormat::Text => "text",
+            LogFormat::Binary => "binary",
+        }
+    }
+}
+
+/// What the ring keeps from now on. A change of format empties it.
+pub fn set_log_format(format: LogFormat) {
+    let binary = format == LogFormat::Binary;
+    if BINARY.load(Ordering::Relaxed) != binary {
+        BINARY.store(binary, Ordering::Relaxed);
+        #[cfg(feature = "api")]
+        ring::clear();
+    }
+}
+
+struct Logger;
+
+static LOGGER: Logger = Logger;
+
+/// Instead of `esp_println::logger::init_logger_from_env`.
+pub fn init_logger() {
+    // This target has no compare-and-swap, so there are only the racy
+    // setters, and nothing else runs yet.
+    unsafe {
+        let _ = log::set_logger_racy(&LOGGER);
+        if let Some(level) = option_env!("ESP_LOGLEVEL") {
+            log::set_max_level_racy(LevelFilter::from_str(level).unwrap_or(LevelFilter::Off));
+        }
+    }
+}
+
+impl Log for Logger {
+    fn enabled(&self, _metadata: &Metadata) -> bool {
+        true
+    }
+
+    fn log(&self, record: &Record) {
+        wifiheap::watch(record);
+        if let Some(targets) = LOG_TARGETS {
+            if !targets.split(',').any(|t| record.target().starts_with(t)) {
+                return;
+            }
+        }
+        let color = match record.level() {
+            Level::Error => "\u{001B}[31m",
+            Level::Warn => "\u{001B}[33m",
+            Level::Info => "\u{001B}[32m",
+            Level::Debug => "\u{001B}[34m",
+            Level::Trace => "\u{001B}[35m",
+        };
+        println!("{}{} - {}\u{001B}[0m", color, record.level(), record.args());
+        #[cfg(feature = "api")]
+        ring::record(record, BINARY.load(Ordering::Relaxed));
+    }
+
+    fn flush(&self) {}
+}
+
+#[cfg(feature = "api")]
+mod ring {
+    use core::cell::RefCell;
+    use core::fmt::{self, Write as _};
+    use core::ops::Range;
+    use core::str;
+
+    use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+    use embassy_sync::blocking_mutex::Mutex;
+    use embassy_sync::mutex::{Mutex as AsyncMutex, MutexGuard};
+    use embassy_time::Instant;
+    use heapless::{String, Vec};
+    use log::{Level, Record};
+    use portable_atomic::{AtomicU32, Ordering};
+
+    use crate::msgpack::{self, Reader};
+
+    pub const RING_LEN: usize = 4096;
+    /// Longest module or message kept.
+    const MAX_FIELD_LEN: usize = 160;
+    /// Longest entry in either format: the two fields, and at most 40
+    /// bytes around them.
+    const MAX_ENTRY_LEN: usize = 2 * MAX_FIELD_LEN + 40;
+
+    type Entry = Vec<u8, MAX_ENTRY_LEN>;
+
+    static LOG_RING: Mutex<CriticalSectionRawMutex, RefCell<RingBuffer<RING_LEN>>> =
+        Mutex::new(RefCell::new(RingBuffer::new()));
+
+    /// What every record so far took as text and as binary.
+    static TEXT_BYTES: AtomicU32 = AtomicU32::new(0);
+    static BINARY_BYTES: AtomicU32 = AtomicU32::new(0);
+
+    /// Where `snapshot` copies the ring to, so that sending it does not
+    /// hold up logging. The main server and the debug access point's take
+    /// turns.
+    static COPY: AsyncMutex<CriticalSectionRawMutex, [u8; RING_LEN]> =
+        AsyncMutex::new([0; RING_LEN]);
+
+    /// A circular byte buffer: once it is full, each write overwrites the
+    /// oldest bytes.
+    pub struct RingBuffer<const N: usize> {
+        buf: [u8; N],
+        /// Where the oldest byte is.
+        start: usize,
+        len: usize,
+    }
+
+    impl<const N: usize> RingBuffer<N> {
+        pub const fn new() -> Self {
+            Self {
+                buf: [0; N],
+                start: 0,
+                len: 0,
+            }
+        }
+
+        pub fn space(&self) -> usize {
+            N - self.len
+        }
+
+        pub fn write(&mut self, data: &[u8]) {
+            // Of more than fits, only the end would be left anyway.
+            let data = &data[data.len().saturating_sub(N)..];
+            for &byte in data {
+                self.buf[(self.start + self.len) % N] = byte;
+                if self.len == N {
+                    self.start = (self.start + 1) % N;
+                } else {
+                    self.len += 1;
+                }
+            }
+        }
+
+        /// What it holds, oldest first. Rotates the storage to have it in
+        /// one piece.
+        pub fn read_all(&mut self) -> &[u8] {
+            self.buf.rotate_left(self.start);
+            self.start = 0;
+            &self.buf[..self.len]
+        }
+
+        /// The `i`th byte from the oldest.
+        pub fn peek(&self, i: usize) -> Option<u8> {
+            (i < self.len).then(|| self.buf[(self.start + i) % N])
+        }
+
+        /// Drops the oldest `n` bytes.
+        pub fn discard(&mut self, n: usize) {
+            let n = n.min(self.len);
+            self.start = (self.start + n) % N;
+            self.len -= n;
+        }
+
+        pub fn clear(&mut self) {
+            self.discard(self.len);
+        }
+    }
+
+    /// Keeps what fits and drops the rest, where a `String` would drop
+    /// all of a piece that does not fit.
+    struct Truncate<'a, const N: usize>(&'a mut String<N>);
+
+    impl<const N: usize> fmt::Write for Truncate<'_, N> {
+        fn write_str(&mut self, s: &str) -> fmt::Result {
+            for c in s.chars() {
+                if self.0.push(c).is_err() {
+                    break;
+                }
+            }
+            Ok(())
+        }
+    }
+
+    /// Counts what is written to it.
+    struct Count(usize);
+
+    impl fmt::Write for Count {
+        fn write_str(&mut self, s: &str) -> fmt::Result {
+            self.0 += s.len();
+            Ok(())
+        }
+    }
+
+    fn write_line<W: fmt::Write>(
+        out: &mut W,
+        ms: u64,
+        level: Level,
+        module: &str,
+        message: &str,
+    ) -> fmt::Result {
+        writeln!(
+            out,
+            "{}.{:03} {} {} - {}",
+            ms / 1000,
+            ms % 1000,
+            level,
+            module,
+            message
+        )
+    }
+
+    fn encode(ms: u64, level: Level, module: &str, message: &str) -> Entry {
+        let mut entry = Entry::new();
+        // Cannot overflow: `MAX_ENTRY_LEN` leaves room for the framing.
+        let _ = msgpack::write_array_len(&mut entry, 4)
+            .and_then(|()| msgpack::write_uint(&mut entry, ms))
+            .and_then(|()| msgpack::write_uint(&mut entry, level as u64))
+            .and_then(|()| msgpack::write_str(&mut entry, module))
+            .and_then(|()| msgpack::write_str(&mut entry, message));
+        entry
+    }
+
+    /// Uptime, level and where module and message are, of the binary entry
+    /// `reader` is at.
+    fn decode<F: Fn(usize) -> Option<u8>>(
+        reader: &mut Reader<F>,
+    ) -> Option<(u64, Level, Range<usize>, Range<usize>)> {
+        if reader.array_len()? != 4 {
+            return None;
+        }
+        let ms = reader.uint()?;
+        let level = match reader.uint()? {
+            1 => Level::Error,
+            2 => Level::Warn,
+            3 => Level::Info,
+            4 => Level::Debug,
+            5 => Level::Trace,
+            _ => return None,
+        };
+        Some((ms, level, reader.str_range()?, reader.str_range()?))
+    }
+
+    pub fn record(record: &Record, binary: bool) {
+        let ms = Instant::now().as_millis();
+        let mut module: String<MAX_FIELD_LEN> = String::new();
+        let _ = Truncate(&mut module).write_str(record.target());
+        let mut message: String<MAX_FIELD_LEN> = String::new();
+        let _ = write!(Truncate(&mut message), "{}", record.args());
+
+        let packed = encode(ms, record.level(), &module, &message);
+        let mut line: String<MAX_ENTRY_LEN> = String::new();
+        // Cannot overflow, like `encode`.
+        let _ = write_line(&mut line, ms, record.level(), &module, &message);
+        TEXT_BYTES.fetch_add(line.len() as u32, Ordering::Relaxed);
+        BINARY_BYTES.fetch_add(packed.len() as u32, Ordering::Relaxed);
+        let entry = if binary { &packed[..] } else { line.as_bytes() };
+
+        LOG_RING.lock(|ring| {
+            // Taken only if something logs while the ring is being copied
+            // out; that record is left out.
+            let Ok(mut ring) = ring.try_borrow_mut() else {
+                return;
+            };
+            while ring.space() < entry.len() {
+                let oldest = if binary {
+                    let mut reader = Reader::new(|i| ring.peek(i));
+                    decode(&mut reader).map(|_| reader.pos())
+                } else {
+                    (0..RING_LEN)
+                        .find(|&i| ring.peek(i).map_or(true, |b| b == b'\n'))
+                        .map(|i| i + 1)
+                };
+                // Anything unreadable goes as a whole.
+                ring.discard(oldest.unwrap_or(RING_LEN));
+            }
+            ring.write(entry);
+        });
+    }
+
+    pub fn clear() {
+        LOG_RING.lock(|ring| ring.borrow_mut().clear());
+    }
+
+    /// Bytes all records since boot took as text and as binary, the
+    /// format in use or not.
+    pub fn encoded_bytes() -> (u32, u32) {
+        (
+            TEXT_BYTES.load(Ordering::Relaxed),
+            BINARY_BYTES.load(Ordering::Relaxed),
+        )
+    }
+
+    /// A copy of the ring, held until it is dropped.
+    pub struct Snapshot {
+        copy: MutexGuard<'static, CriticalSectionRawMutex, [u8; RING_LEN]>,
+        len: usize,
+        /// Binary entries, to be sent as text.
+        decode: bool,
+        binary: bool,
+    }
+
+    impl Snapshot {
+        pub fn content_type(&self) -> &'static str {
+            if self.binary && !self.decode {
+                "application/msgpack"
+            } else {
+                "text/plain; charset=utf-8"
+            }
+        }
+
+        /// Of what `write_to` sends.
+        pub fn len(&self) -> usize {
+            if !self.decode {
+                return self.len;
+            }
+            let mut count = Count(0);
+            self.for_each_line(|line| {
+                let _ = count.write_str(line);
+            });
+            count.0
+        }
+
+        pub async fn write_to<W: embedded_io_async::Write>(
+            &self,
+            out: &mut W,
+        ) -> Result<(), W::Error> {
+            if !self.decode {
+                return out.write_all(&self.copy[..self.len]).await;
+            }
+            let mut from = 0;
+            while let Some((line, next)) = self.line_at(from) {
+                out.write_all(line.as_bytes()).await?;
+                from = next;
+            }
+            Ok(())
+        }
+
+        fn for_each_line(&self, mut f: impl FnMut(&str)) {
+            let mut from = 0;
+            while let Some((line, next)) = self.line_at(from) {
+                f(&line);
+                from = next;
+            }
+        }
+
+        /// The binary entry at `from` as a line of text, and where the
+        /// next one starts.
+        fn line_at(&self, from: usize) -> Option<(String<MAX_ENTRY_LEN>, usize)> {
+            let data = &self.copy[from..self.len];
+            let mut reader = Reader::new(|i| data.get(i).copied());
+            let (ms, level, module, message) = decode(&mut reader)?;
+            let module = str::from_utf8(&data[module]).ok()?;
+            let message = str::from_utf8(&data[message]).ok()?;
+            let mut line = String::new();
+            // Cannot overflow, like `encode`.
+            let _ = write_line(&mut line, ms, level, module, message);
+            Some((line, from + reader.pos()))
+        }
+    }
+
+    /// Copies out what the ring holds. Binary entries stay binary only if
+    /// `msgpack` is accepted.
+    pub async fn snapshot(msgpack: bool) -> Snapshot {
+        let mut copy = COPY.lock().await;
+        let (len, binary) = LOG_RING.lock(|ring| {
+            let mut ring = ring.borrow_mut();
+            let data = ring.read_all();
+            copy[..data.len()].copy_from_slice(data);
+            (data.len(), super::BINARY.load(Ordering::Relaxed))
+        });
+        Snapshot {
+            copy,
+            len,
+            decode: binary && !msgpack,
+            binary,
+        }
+    }
+}
@@ -0,0 +1,37 @@
+// The MAC address burned into eFuse at the factory, which tells one device
+// from another in a fleet without anything set up per device.
+//
+// The station interface uses this address as it is, so it is what the
+// access point and the DHCP server see. The boot log prints it, and the mDNS
+// name (src/mdns.rs) is made from its last three bytes: `esp32c3-a1b2c3`,
+// short enough to type and the same across resets and reflashes.
+
+use core::fmt::Write as _;
+
+use esp_hal::efuse::{Efuse, MAC};
+use heapless::String;
+
+/// The factory MAC, `MAC_FACTORY` in eFuse block 1. The field holds it with
+/// the first byte last, hence big-endian.
+pub fn read_mac_address() -> [u8; 6] {
+    Efuse::read_field_be(MAC)
+}
+
+/// `AA:BB:CC:DD:EE:FF`.
+pub fn mac_to_string(mac: &[u8; 6]) -> String<17> {
+    let mut text = String::new();
+    for (i, byte) in mac.iter().enumerate() {
+        let colon = if i > 0 { ":" } else { "" };
+        // Cannot overflow: six bytes in hex and five colons.
+        let _ = write!(text, "{}{:02X}", colon, byte);
+    }
+    text
+}
+
+/// `esp32c3-` and the last three bytes of `mac` in hex.
+#[cfg(feature = "api")]
+pub fn hostname(mac: &[u8; 6]) -> String<14> {
+    let mut host = String::new();
+    let _ = write!(host, "esp32c3-{:02x}{:02x}{:02x}", mac[3], mac[4], mac[5]);
+    host
+}
@@ -1,117 +1,350 @@
 #![no_std]
 #![no_main]
 #![feature(type_alias_impl_trait)]
+// The oneshot build never starts the uploader, which leaves it and the
+// settings, SNTP and maintenance code behind it unused.
+#![cfg_attr(feature = "oneshot", allow(dead_code))]
 
-use core::ptr::addr_of_mut;
 use core::str;
 use embassy_executor::Spawner;
-use embassy_net::{tcp::TcpSocket, Config, Ipv4Address, Stack, StackResources};
-use embassy_time::{Duration, Timer as EmbassyTimer};
-use embedded_io_async::Write;
-use embedded_tls::{Aes128GcmSha256, NoVerify, TlsConfig, TlsConnection, TlsContext};
+use embassy_net::{Config, Stack, StackResources};
+use embassy_sync::mutex::Mutex;
+use embassy_time::{Duration, Instant, Timer as EmbassyTimer};
 use esp_hal::entry;
 use esp_hal::peripherals::TIMG0;
 use esp_hal::prelude::_esp_hal_timer_Timer;
 use esp_hal::prelude::_fugit_ExtU64;
+use esp_hal::prelude::_fugit_RateExtU32;
 use esp_hal::prelude::main;
 use esp_hal::timer::timg::Timer0;
 use esp_hal::timer::timg::TimerX;
 use esp_hal::{
+    analog::adc::{Adc, AdcConfig, Attenuation},
     clock::ClockControl,
+    gpio::{GpioPin, Io},
+    i2c::I2C,
     peripherals::Peripherals,
     rng::Rng,
+    rtc_cntl::Rtc,
+    sha::{Sha, ShaMode},
     system::SystemControl,
     timer::{
         timg::{Timer, TimerGroup},
         OneShotTimer, PeriodicTimer,
     },
 };
+#[cfg(feature = "factory")]
+use esp_hal::spi::master::dma::WithDmaSpi2;
+#[cfg(feature = "console")]
+use esp_hal::uart::Uart;
 use esp_hal_embassy;
 use esp_println::println;
 use esp_wifi::wifi::WifiDevice;
 use esp_wifi::EspWifiInitFor;
 use esp_wifi::{
     initialize,
-    wifi::{ClientConfiguration, Configuration, WifiController, WifiStaDevice},
+    wifi::{AuthMethod, ClientConfiguration, Configuration, WifiController, WifiStaDevice},
 };
 use fugit;
 use heapless::String;
-use panic_halt as _;
-use rand_core::{CryptoRng, Error as RandError, RngCore};
 use static_cell::StaticCell;
 
-// Custom RNG implementation for debugging
-// Custom RNG implementation for debugging
-pub struct SimpleRng {
-    counter: u32, // Simple counter for pseudo-random numbers
-}
-
-impl SimpleRng {
-    pub fn new() -> Self {
-        Self { counter: 0 }
-    }
-}
-
-impl RngCore for SimpleRng {
-    fn next_u32(&mut self) -> u32 {
-        // Simple counter-based pseudo-random number generator
-        self.counter = self.counter.wrapping_add(1);
-        self.counter
-    }
-
-    fn next_u64(&mut self) -> u64 {
-        let upper = self.next_u32() as u64;
-        let lower = self.next_u32() as u64;
-        (upper << 32) | lower
-    }
-
-    fn fill_bytes(&mut self, dest: &mut [u8]) {
-        for chunk in dest.chunks_mut(4) {
-            let rand = self.next_u32();
-            let bytes = rand.to_ne_bytes();
-            for (i, byte) in chunk.iter_mut().enumerate() {
-                *byte = bytes[i];
-            }
-        }
-    }
-
-    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), RandError> {
-        self.fill_bytes(dest);
-        Ok(())
-    }
-}
-
-impl CryptoRng for SimpleRng {}
+#[cfg(feature = "api")]
+mod api;
+mod batch;
+#[cfg(not(feature = "oneshot"))]
+mod beacon;
+mod boot;
+#[cfg(feature = "ota")]
+mod bspatch;
+mod buildinfo;
+mod bus;
+mod canary;
+mod canaryscan;
+mod clock;
+mod codec;
+mod connectivity;
+#[cfg(feature = "console")]
+mod console;
+#[cfg(feature = "api")]
+mod cors;
+mod crash;
+mod crashrecord;
+#[cfg(feature = "api")]
+mod debugap;
+mod deflate;
+mod deviceid;
+#[cfg(feature = "dht22")]
+mod dht22;
+mod diag;
+mod dns;
+mod dryrun;
+#[cfg(feature = "ds18b20")]
+mod ds18b20;
+mod ds3231;
+#[cfg(feature = "encoder")]
+mod encoder;
+mod enterprise;
+mod entropy;
+mod flash;
+mod fleet;
+mod fleetorder;
+#[cfg(feature = "ota")]
+mod gzip;
+mod headers;
+mod housekeeping;
+mod http;
+#[cfg(feature = "api")]
+mod httpd;
+mod https;
+mod httpsbody;
+#[cfg(feature = "dev")]
+mod i2cscan;
+mod integrity;
+mod ip5306;
+mod json;
+mod kv;
+mod labels;
+mod logring;
+mod mac;
+mod maintenance;
+#[cfg(feature = "ota")]
+mod merkle;
+#[cfg(feature = "api")]
+mod mdns;
+mod metrics;
+mod monotime;
+mod monotimeanchor;
+#[cfg(feature = "api")]
+mod msgpack;
+mod multipart;
+mod ntp;
+#[cfg(feature = "oneshot")]
+mod oneshot;
+#[cfg(feature = "oneshot")]
+mod oneshotline;
+#[cfg(feature = "ds18b20")]
+mod onewire;
+#[cfg(feature = "ota")]
+mod ota;
+#[cfg(feature = "ota")]
+mod otaprogress;
+mod partition;
+mod power;
+mod priority;
+mod probe;
+#[cfg(feature = "ota")]
+mod protocol;
+mod queuestats;
+mod quiethours;
+#[cfg(feature = "replay")]
+mod replay;
+#[cfg(feature = "replay")]
+mod replayrecord;
+mod resolver;
+mod restart;
+mod restartnote;
+mod rollup;
+mod safemode;
+mod schema;
+#[cfg(not(feature = "oneshot"))]
+mod selftest;
+#[cfg(feature = "factory")]
+mod selftestloopback;
+#[cfg(not(feature = "oneshot"))]
+mod selftestverdict;
+mod sequence;
+mod sequencecounter;
+mod settings;
+#[cfg(feature = "ota")]
+mod signature;
+mod sntp;
+mod sntpselect;
+mod state;
+mod station;
+#[cfg(feature = "stepper")]
+mod stepper;
+mod telemetry;
+mod telemetryschema;
+mod timerqueue;
+mod timerqueueentries;
+#[cfg(any(feature = "api", feature = "console"))]
+mod tlsinfo;
+mod touch;
+mod uploadanswer;
+mod uploader;
+mod wifiheap;
+mod wifihint;
+mod wire;
+#[cfg(feature = "wiretrace")]
+mod wiretrace;
+mod x509;
+
+#[cfg(not(any(feature = "minimal", feature = "full")))]
+compile_error!("enable one build profile: `minimal` or `full`");
+
+#[cfg(all(feature = "minimal", feature = "full"))]
+compile_error!("`minimal` and `full` are exclusive; build `minimal` with --no-default-features");
+
+#[cfg(all(
+    feature = "minimal",
+    any(feature = "console", feature = "ota", feature = "api")
+))]
+compile_error!("the `minimal` profile must not pull in `console`, `ota` or `api`");
+
+#[cfg(all(feature = "dev", feature = "release-prod"))]
+compile_error!("`dev` and `release-prod` are exclusive");
+
+#[cfg(all(feature = "factory", feature = "oneshot"))]
+compile_error!("`factory` needs the self-test, which `oneshot` builds leave out");
+
+pub type NetStack = Stack<WifiDevice<'static, WifiStaDevice>>;
 
 // WiFi
 const SSID: &str = env!("SSID");
 const PASSWORD: &str = env!("PASSWORD");
 
 const CONNECT_ATTEMPTS: usize = 10;
+
+// Touch pad on GPIO2 that wipes the persisted settings when held.
+const FACTORY_RESET_HOLD_MS: u64 = 5000;
+const TOUCH_THRESHOLD: u32 = 300;
 const RETRY_DELAY_MS: u64 = 5000;
 
 #[main]
 async fn main(spawner: Spawner) {
-    esp_println::logger::init_logger_from_env();
+    canary::paint_stack();
+    logring::init_logger();
 
     println!("Starting program...");
+    println!("{}", buildinfo::BANNER);
+    println!("mac: {}", mac::mac_to_string(&mac::read_mac_address()));
 
     //spawner.spawn(print_int(41)).unwrap();
 
+    // Decided before the store is touched, in case the store is the trouble.
+    let safe_mode = safemode::check();
+    if safe_mode {
+        settings::use_defaults();
+    } else {
+        schema::upgrade();
+        settings::load().await;
+    }
+
     let peripherals = Peripherals::take();
     let system = SystemControl::new(peripherals.SYSTEM);
     let clocks = ClockControl::max(system.clock_control).freeze();
 
+    // Decides between confirming a fresh update and rolling it back, so it
+    // runs before anything that could hang.
+    let mut lpwr = Rtc::new(peripherals.LPWR, None);
+    integrity::init(Sha::new(peripherals.SHA, ShaMode::SHA256, None));
+    boot::check(core::mem::take(&mut lpwr.rwdt)).await;
+    monotime::init(lpwr);
+    match deviceid::init() {
+        Ok(()) => {
+            let mut hex = [0u8; codec::hex_encoded_len(integrity::HASH_LEN)];
+            if let Ok(hex) = codec::hex_encode(&deviceid::device_fingerprint(), &mut hex) {
+                println!("device: {}", hex);
+            }
+        }
+        Err(e) => println!("device: no fingerprint: {:?}", e),
+    }
+    restart::take_note();
+    crash::report_at_boot();
+    https::register_canaries();
+    #[cfg(feature = "api")]
+    httpd::register_canaries();
+    spawner.spawn(housekeeping::housekeeping_task()).unwrap();
+    spawner.spawn(canary::stack_monitor_task()).unwrap();
+
     let mut timer_group = TimerGroup::new(peripherals.TIMG0, &clocks, None);
     let mut timer0 = timer_group.timer0;
 
     let timer = esp_hal::timer::systimer::SystemTimer::new(peripherals.SYSTIMER).alarm0;
 
+    let io = Io::new(peripherals.GPIO, peripherals.IO_MUX);
+
+    let mut adc_config = AdcConfig::new();
+    let touch_pin = adc_config.enable_pin(io.pins.gpio2, Attenuation::Attenuation11dB);
+    let mut reset_button = touch::CapTouchButton::new(
+        Adc::new(peripherals.ADC1, adc_config),
+        touch_pin,
+        TOUCH_THRESHOLD,
+        FACTORY_RESET_HOLD_MS,
+    );
+    #[cfg_attr(feature = "oneshot", allow(unused_variables))]
+    let touch_baseline = reset_button.calibrate();
+    spawner.spawn(factory_reset_task(reset_button)).unwrap();
+
+    static I2C_BUS: StaticCell<bus::SharedI2c> = StaticCell::new();
+    let i2c_bus = &*I2C_BUS.init(Mutex::new(I2C::new_async(
+        peripherals.I2C0,
+        io.pins.gpio4,
+        io.pins.gpio5,
+        100.kHz(),
+        &clocks,
+    )));
+
+    // SPI2 on the self-test fixture (src/selftest.rs): SCK on GPIO6, MOSI
+    // on GPIO7, MISO on GPIO10 and chip select on GPIO3.
+    #[cfg(feature = "factory")]
+    let fixture = {
+        use esp_hal::dma::{Dma, DmaDescriptor, DmaPriority};
+        use esp_hal::gpio::{AnyOutput, Level};
+        use esp_hal::spi::{master::Spi, SpiMode};
+
+        static DESCRIPTORS: StaticCell<([DmaDescriptor; 1], [DmaDescriptor; 1])> =
+            StaticCell::new();
+        let (tx_descriptors, rx_descriptors) =
+            DESCRIPTORS.init(esp_hal::dma_descriptors!(selftestloopback::LOOPBACK_LEN));
+        let channel = Dma::new(peripherals.DMA).channel0.configure_for_async(
+            false,
+            tx_descriptors,
+            rx_descriptors,
+            DmaPriority::Priority0,
+        );
+        selftest::Fixture {
+            spi: Spi::new(peripherals.SPI2, 1.MHz(), SpiMode::Mode0, &clocks)
+                .with_sck(io.pins.gpio6)
+                .with_mosi(io.pins.gpio7)
+                .with_miso(io.pins.gpio10)
+                .with_dma(channel),
+            cs: AnyOutput::new(io.pins.gpio3, Level::High),
+        }
+    };
+
+    #[cfg(feature = "dev")]
+    i2cscan::print(&i2cscan::scan_i2c(&mut *i2c_bus.lock().await).await);
+
+    // Battery-backed RTC, so there is a wall clock before SNTP answers.
+    let mut rtc = ds3231::Ds3231::new(i2c_bus);
+    match rtc.read_time().await {
+        Ok(time) => {
+            println!("RTC time: {:?}", time);
+            let unix_ms = time.to_unix() * 1000;
+            if let Some((synced_ms, uncertainty_ms)) = sntp::last_good() {
+                if unix_ms + (uncertainty_ms as u64) < synced_ms {
+                    println!("RTC reads earlier than the last SNTP sync; suspect until the next one");
+                }
+            }
+            // Whole seconds.
+            sntp::set_time(unix_ms, sntp::Source::Rtc, 1000);
+        }
+        Err(e) => println!("RTC not usable yet: {:?}", e),
+    }
+    spawner.spawn(rtc_task(rtc)).unwrap();
+
+    spawner
+        .spawn(power::power_task(ip5306::Ip5306::new(i2c_bus)))
+        .unwrap();
+
     // Start the timer
     timer0.start();
 
     // Initialize RNG peripherial
-    let rng = Rng::new(peripherals.RNG);
+    let mut rng = Rng::new(peripherals.RNG);
+    entropy::init(rng);
 
     let init = match initialize(
         EspWifiInitFor::Wifi,
@@ -122,15 +355,35 @@ async fn main(spawner: Spawner) {
     ) {
         Ok(init) => {
             println!("Wi-Fi initialization successful.");
+            wifiheap::note_initialized();
             init
         }
         Err(e) => {
             println!("Wi-Fi initialization failed: {:?}", e);
+            #[cfg(feature = "factory")]
+            selftest::report_unreachable(selftestverdict::Check::WifiScan);
+            #[cfg(feature = "oneshot")]
+            oneshot::report(oneshot::URL, &oneshotline::Outcome::NoNetwork, None);
             return;
         }
     };
 
     let wifi = peripherals.WIFI;
+    // The debug access point needs both interfaces from the start.
+    #[cfg(feature = "api")]
+    let (wifi_interface, mut controller, debug_ap) = match debugap::requested() {
+        Some(minutes) => {
+            let (ap_interface, wifi_interface, controller) =
+                esp_wifi::wifi::new_ap_sta(&init, wifi).unwrap();
+            (wifi_interface, controller, Some((ap_interface, minutes)))
+        }
+        None => {
+            let (wifi_interface, controller) =
+                esp_wifi::wifi::new_with_mode(&init, wifi, WifiStaDevice).unwrap();
+            (wifi_interface, controller, None)
+        }
+    };
+    #[cfg(not(feature = "api"))]
     let (wifi_interface, mut controller) =
         esp_wifi::wifi::new_with_mode(&init, wifi, WifiStaDevice).unwrap();
 
@@ -139,22 +392,86 @@ async fn main(spawner: Spawner) {
     ssid.push_str(SSID).unwrap();
     password.push_str(PASSWORD).unwrap();
 
-    let client_config = ClientConfiguration {
-        ssid,
-        password,
-        ..Default::default()
+    // Stored enterprise credentials take the place of PASSWORD, if this
+    // build can use them.
+    let eap = match enterprise::load().await {
+        Some(credentials) => match enterprise::enable(&credentials) {
+            Ok(()) => {
+                println!(
+                    "WPA2-Enterprise as {} (server certificate {})",
+                    credentials.identity,
+                    if enterprise::CHECKS_SERVER { "checked" } else { "not checked" }
+                );
+                true
+            }
+            Err(e) => {
+                println!("WPA2-Enterprise credentials not used: {}", e);
+                false
+            }
+        },
+        None => false,
+    };
+    let wifi_settings = settings::current();
+    let client_config = if eap {
+        ClientConfiguration {
+            ssid,
+            auth_method: AuthMethod::WPA2Enterprise,
+            ..Default::default()
+        }
+    } else {
+        ClientConfiguration {
+            ssid,
+            password,
+            auth_method: wifi_settings.wifi_auth.auth_method(),
+            ..Default::default()
+        }
+    };
+
+    // Where the station last associated, if it is worth going straight
+    // back there (src/wifihint.rs).
+    let hint = wifihint::load(SSID);
+    let max_age_ms = wifihint::max_age_ms();
+    let directed = |attempt| wifihint::directed(hint, attempt, sntp::now_unix_ms(), max_age_ms);
+    let client = |hint: Option<wifihint::Hint>| {
+        let mut client_config = client_config.clone();
+        if let Some(hint) = hint {
+            client_config.bssid = Some(hint.bssid);
+            client_config.channel = Some(hint.channel);
+        }
+        client_config
     };
+    let mut hinted = directed(1);
 
-    controller
-        .set_configuration(&Configuration::Client(client_config))
-        .unwrap();
+    #[cfg(feature = "api")]
+    let configuration = match debug_ap {
+        Some(_) => Configuration::Mixed(client(hinted), debugap::access_point()),
+        None => Configuration::Client(client(hinted)),
+    };
+    #[cfg(not(feature = "api"))]
+    let configuration = Configuration::Client(client(hinted));
+    controller.set_configuration(&configuration).unwrap();
     controller.start().await.unwrap();
     println!("WiFi Started...");
 
+    let connect_started = Instant::now();
     let mut attempts = 0;
     loop {
         attempts += 1;
-        println!("Attempt {}: Connecting to Wi-Fi...", attempts);
+        if directed(attempts) != hinted {
+            hinted = directed(attempts);
+            println!("Wi-Fi: not found where it last was, scanning for it");
+            // Leaves the access point, if there is one, as it is.
+            if let Err(e) = controller.set_configuration(&Configuration::Client(client(hinted))) {
+                println!("Wi-Fi: could not drop the channel hint: {:?}", e);
+            }
+        }
+        match hinted {
+            Some(hint) => println!(
+                "Attempt {}: Connecting to Wi-Fi on channel {}...",
+                attempts, hint.channel
+            ),
+            None => println!("Attempt {}: Connecting to Wi-Fi...", attempts),
+        }
 
         if let Ok(()) = controller.connect().await {
             // After starting Wi-Fi and setting configuration
@@ -167,15 +484,56 @@ async fn main(spawner: Spawner) {
             } else {
                 println!("Error checking Wi-Fi connection status.");
             }
+            let elapsed_ms = connect_started.elapsed().as_millis();
+            match hinted {
+                Some(hint) => println!(
+                    "Wi-Fi: associated in {} ms, directed to channel {}",
+                    elapsed_ms, hint.channel
+                ),
+                None => println!("Wi-Fi: associated in {} ms, after a full scan", elapsed_ms),
+            }
+            let ap = station::find(&mut controller, SSID, wifi_settings.wifi_hidden).await;
+            if let Some(ap) = &ap {
+                let hint = wifihint::Hint {
+                    bssid: ap.bssid,
+                    channel: ap.channel,
+                    unix_ms: sntp::now_unix_ms(),
+                };
+                wifihint::save(&hint, SSID);
+            }
+            match ap.and_then(|ap| ap.auth_method) {
+                Some(auth) => println!(
+                    "Wi-Fi security: {:?} (wifi_auth={})",
+                    auth,
+                    wifi_settings.wifi_auth.as_str()
+                ),
+                None => println!("Wi-Fi security: unknown, the access point did not answer a scan"),
+            }
             break;
         }
 
+        if eap && enterprise::eap_failed() {
+            println!("EAP authentication refused: check the identity, username and password");
+        } else if eap {
+            println!("Not associated: network not found or not answering");
+        } else {
+            println!("Not associated: wrong password, or network not found");
+        }
+
         if attempts >= CONNECT_ATTEMPTS {
             println!(
                 "Failed to connect to Wi-Fi after {} attempts.",
                 CONNECT_ATTEMPTS
             );
+            #[cfg(feature = "factory")]
+            selftest::report_unreachable(selftestverdict::Check::Association);
+            #[cfg(feature = "oneshot")]
+            oneshot::report(oneshot::URL, &oneshotline::Outcome::NoNetwork, None);
+            // Both want the verdict to be the last line, not a reset loop.
+            #[cfg(any(feature = "factory", feature = "oneshot"))]
             return;
+            #[cfg(not(any(feature = "factory", feature = "oneshot")))]
+            restart::wifi_stuck().await;
         }
 
         println!("Retrying in {} ms...", RETRY_DELAY_MS);
@@ -183,14 +541,18 @@ async fn main(spawner: Spawner) {
     }
 
     let config = Config::dhcpv4(Default::default());
-    let seed = 1234;
-
-    static STACK: StaticCell<Stack<WifiDevice<'_, WifiStaDevice>>> = StaticCell::new();
-    static RESOURCES: StaticCell<StackResources<3>> = StaticCell::new();
+    // Random, so the stack's TCP sequence numbers and ports are not known
+    // in advance.
+    let seed = (rng.random() as u64) << 32 | rng.random() as u64;
+
+    static STACK: StaticCell<NetStack> = StaticCell::new();
+    // One more for the discovery beacon (src/beacon.rs), which holds its
+    // socket for good.
+    static RESOURCES: StaticCell<StackResources<7>> = StaticCell::new();
     let stack = &*STACK.init(Stack::new(
         wifi_interface,
         config,
-        RESOURCES.init(StackResources::<3>::new()),
+        RESOURCES.init(StackResources::<7>::new()),
         seed,
     ));
 
@@ -210,96 +572,154 @@ async fn main(spawner: Spawner) {
 
     println!("Stack IP Configuration: {:?}", stack.config_v4());
 
-    /* Original
-    // TLS connection setup
-    static mut RX_BUFFER_TLS: [u8; 16384] = [0; 16384];
-    static mut TX_BUFFER_TLS: [u8; 16384] = [0; 16384];
-
-    // Create a new TCP socket
-    static mut RX_BUFFER_SOCKET: [u8; 1024] = [0; 1024];
-    static mut TX_BUFFER_SOCKET: [u8; 1024] = [0; 1024];
-
-    */
-
-
-    /* TEST */
+    // Console on UART0; the RX timeout makes reads return at the end of a
+    // typed line instead of waiting for the FIFO threshold.
+    #[cfg(feature = "console")]
+    {
+        let mut uart0 = Uart::new_async(peripherals.UART0, &clocks);
+        uart0.set_rx_timeout(Some(10)).unwrap();
+        let (_, uart0_rx) = uart0.split();
+        spawner.spawn(console::console_task(uart0_rx, stack)).unwrap();
+    }
 
-    // TLS connection setup
-    static mut RX_BUFFER_TLS: [u8; 8192] = [0; 8192];
-    static mut TX_BUFFER_TLS: [u8; 8192] = [0; 8192];
+    // Measurement builds stop here: one request, one result line, and no
+    // uploader traffic to skew it.
+    #[cfg(feature = "oneshot")]
+    oneshot::run(stack, &mut controller, SSID).await;
+
+    station::share(controller).await;
+
+    #[cfg(not(feature = "oneshot"))]
+    if !safe_mode {
+        let hardware = selftest::Hardware {
+            i2c: i2c_bus,
+            touch_baseline,
+            touch_threshold: TOUCH_THRESHOLD,
+            #[cfg(feature = "factory")]
+            fixture,
+        };
+        spawner
+            .spawn(selftest::selftest_task(stack, rng, hardware))
+            .unwrap();
+    }
 
-    // Create a new TCP socket
-    static mut RX_BUFFER_SOCKET: [u8; 2048] = [0; 2048];
-    static mut TX_BUFFER_SOCKET: [u8; 2048] = [0; 2048];
+    #[cfg(feature = "api")]
+    if !safe_mode {
+        spawner.spawn(httpd::httpd_task(stack)).unwrap();
+        spawner.spawn(mdns::mdns_task(stack)).unwrap();
+    }
+    #[cfg(feature = "api")]
+    if let Some((ap_interface, minutes)) = debug_ap {
+        let seed = (rng.random() as u64) << 32 | rng.random() as u64;
+        static AP_STACK: StaticCell<debugap::ApStack> = StaticCell::new();
+        static AP_RESOURCES: StaticCell<StackResources<{ debugap::SOCKETS }>> =
+            StaticCell::new();
+        let ap_stack = &*AP_STACK.init(Stack::new(
+            ap_interface,
+            debugap::config(),
+            AP_RESOURCES.init(StackResources::new()),
+            seed,
+        ));
+        spawner.spawn(debugap::ap_net_task(ap_stack)).unwrap();
+        spawner
+            .spawn(debugap::debug_ap_task(ap_stack, stack, minutes))
+            .unwrap();
+        println!(
+            "Debug AP {}: {}/{} for {} min after boot",
+            debugap::ssid(),
+            debugap::ADDRESS,
+            debugap::PREFIX_LEN,
+            minutes
+        );
+    }
 
-    let (rx_buffer_tls, tx_buffer_tls, rx_buffer_socket, tx_buffer_socket) = unsafe {
-        (
-            &mut *addr_of_mut!(RX_BUFFER_TLS),
-            &mut *addr_of_mut!(TX_BUFFER_TLS),
-            &mut *addr_of_mut!(RX_BUFFER_SOCKET),
-            &mut *addr_of_mut!(TX_BUFFER_SOCKET),
-        )
-    };
+    // Find out whether this is a real uplink before anything trusts it.
+    let connectivity = connectivity::probe_and_update(stack).await;
+    spawner
+        .spawn(connectivity::connectivity_task(stack))
+        .unwrap();
 
-    let mut socket = TcpSocket::new(stack, rx_buffer_socket, tx_buffer_socket);
+    #[cfg(not(feature = "oneshot"))]
+    spawner.spawn(uploader::uploader_task(stack)).unwrap();
+    #[cfg(not(feature = "oneshot"))]
+    spawner.spawn(beacon::beacon_task(stack)).unwrap();
 
+    #[cfg(not(feature = "oneshot"))]
+    spawner.spawn(restart::schedule_task()).unwrap();
 
-    if let Err(e) = socket
-        .connect((Ipv4Address::new(142, 250, 203, 100), 443))
-        .await
-    {
-        println!("Failed to connect to the server: {:?}", e);
+    if safe_mode {
         return;
     }
-
-    let config: TlsConfig<'_, Aes128GcmSha256> =
-        TlsConfig::new().with_server_name("www.google.com");
-    let mut tls = TlsConnection::new(socket, rx_buffer_tls, tx_buffer_tls);
-
-    println!("Starting TLS handshake...");
-    if let Err(e) = tls
-        .open::<SimpleRng, NoVerify>(TlsContext::new(&config, &mut SimpleRng::new()))
-        .await
-    {
-        println!("TLS handshake failed: {:?}", e);
+    if let connectivity::Connectivity::CaptivePortal(evidence) = connectivity {
+        println!(
+            "Captive portal detected ({:?}); skipping the test request.",
+            evidence
+        );
         return;
     }
 
-
-    println!("TLS handshake completed successfully.");
-    if let Err(e) = tls
-        .write_all(b"GET / HTTP/1.1\r\nHost: www.google.com\r\n\r\n")
-        .await
-    {
-        println!("Failed to send HTTP request: {:?}", e);
-        return;
-    }
-
-
-    // Use the simplified RNG for debugging
-    //let mut simple_rng = SimpleRng::new();
-
-
-   
     let mut response = [0; 1024];
-    match tls.read(&mut response).await {
-        Ok(size) => {
-            if size == 0 {
+    match https::get(stack, "https://www.google.com/", &mut response).await {
+        Ok(result) => {
+            println!(
+                "TLS request completed with status {} ({}) in {} ms.",
+                result.status,
+                https::CIPHER_SUITE,
+                result.timings.total_ms
+            );
+            let body = result.body(&response);
+            if body.is_empty() {
                 println!("Received no data from the server.");
             } else {
                 println!(
                     "Response: {}",
-                    str::from_utf8(&response[..size]).unwrap_or("Invalid UTF-8 response")
+                    str::from_utf8(body).unwrap_or("Invalid UTF-8 response")
                 );
             }
         }
         Err(e) => {
-            println!("Failed to read from TLS connection: {:?}", e);
+            println!(
+                "Request failed during {}: {}",
+                e.phase().as_str(),
+                diag::classify(&e).describe()
+            );
+        }
+    }
+}
+
+#[embassy_executor::task]
+async fn factory_reset_task(button: touch::CapTouchButton<'static, GpioPin<2>>) {
+    canary::tracked("factory_reset", factory_reset(button)).await
+}
+
+async fn factory_reset(mut button: touch::CapTouchButton<'static, GpioPin<2>>) {
+    if button.wait_for_touch().await {
+        println!("Factory reset: erasing settings and restarting.");
+        if let Err(e) = kv::clear().await {
+            println!("Factory reset failed: {:?}", e);
+            return;
+        }
+        esp_hal::reset::software_reset();
+    }
+}
+
+/// Writes every SNTP result back to the RTC to correct its drift.
+#[embassy_executor::task]
+async fn rtc_task(rtc: ds3231::Ds3231<'static>) {
+    canary::tracked("rtc", update_rtc(rtc)).await
+}
+
+async fn update_rtc(mut rtc: ds3231::Ds3231<'static>) -> ! {
+    loop {
+        let unix_ms = sntp::wait_for_sync().await;
+        let time = ds3231::DateTime::from_unix(unix_ms / 1000);
+        if let Err(e) = rtc.set_time(time).await {
+            println!("RTC update failed: {:?}", e);
         }
     }
 }
 
 #[embassy_executor::task]
-async fn net_task(stack: &'static Stack<WifiDevice<'static, WifiStaDevice>>) {
-    stack.run().await
+async fn net_task(stack: &'static NetStack) {
+    canary::tracked("net", stack.run()).await
 }
\ No newline at end of file
@@ -0,0 +1,90 @@
+// Maintenance window: the daily UTC time range in which risky operations
+// (OTA, applying remote config) may run. Outside it they are deferred, and
+// their callers persist what they were going to do so a reboot keeps it.
+
+use crate::clock::Clock;
+use crate::settings::Settings;
+
+const MINUTES_PER_DAY: u16 = 24 * 60;
+const MS_PER_MINUTE: u64 = 60 * 1000;
+
+/// `start` inclusive, `end` exclusive, both in minutes after midnight UTC.
+/// `start > end` crosses midnight; `start == end` is a window that never
+/// opens, which is how to pause risky operations entirely.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Window {
+    pub start_minute: u16,
+    pub end_minute: u16,
+}
+
+/// What to do while there is no wall clock to check the window against.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NoClockPolicy {
+    /// Hold everything until SNTP has synced.
+    Defer,
+    Allow,
+}
+
+impl Window {
+    /// Parses `HH:MM-HH:MM`.
+    pub fn parse(text: &str) -> Option<Self> {
+        let (start, end) = text.split_once('-')?;
+        Some(Self {
+            start_minute: parse_time(start.trim())?,
+            end_minute: parse_time(end.trim())?,
+        })
+    }
+
+    pub fn contains(&self, minute_of_day: u16) -> bool {
+        let (start, end) = (self.start_minute, self.end_minute);
+        if start <= end {
+            (start..end).contains(&minute_of_day)
+        } else {
+            minute_of_day >= start || minute_of_day < end
+        }
+    }
+}
+
+impl core::fmt::Display for Window {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "{:02}:{:02}-{:02}:{:02}",
+            self.start_minute / 60,
+            self.start_minute % 60,
+            self.end_minute / 60,
+            self.end_minute % 60
+        )
+    }
+}
+
+/// Parses `HH:MM` into minutes after midnight.
+pub fn parse_time(text: &str) -> Option<u16> {
+    let (hours, minutes) = text.split_once(':')?;
+    let hours: u16 = hours.parse().ok()?;
+    let minutes: u16 = minutes.parse().ok()?;
+    if hours > 23 || minutes > 59 {
+        return None;
+    }
+    Some(hours * 60 + minutes)
+}
+
+/// The pure decision: no window means always, no clock means the policy.
+pub fn permits(window: Option<Window>, policy: NoClockPolicy, now_unix_ms: Option<u64>) -> bool {
+    let Some(window) = window else {
+        return true;
+    };
+    match now_unix_ms {
+        Some(ms) => window.contains(((ms / MS_PER_MINUTE) % MINUTES_PER_DAY as u64) as u16),
+        None => policy == NoClockPolicy::Allow,
+    }
+}
+
+/// Whether a risky operation may start now under `settings`, by `clock`.
+pub fn is_open(settings: &Settings, clock: &impl Clock) -> bool {
+    permits(
+        settings.maintenance_window,
+        settings.no_clock_policy,
+        clock.unix_ms(),
+    )
+}
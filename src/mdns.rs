@@ -0,0 +1,426 @@
+// mDNS responder (RFC 6762) with a DNS-SD service (RFC 6763), so the HTTP
+// server can be found on the local network without knowing its address.
+//
+// The device answers as `esp32c3-XXXXXX.local`, the last three bytes of
+// its factory MAC in hex (src/mac.rs), and offers `_http._tcp` under the
+// same instance name with TXT `version`, `model` (DEVICE_MODEL at build
+// time, or `esp32c3`) and `mac`:
+//
+//     _http._tcp.local              PTR  esp32c3-a1b2c3._http._tcp.local
+//     esp32c3-a1b2c3._http._tcp...  SRV  0 0 80 esp32c3-a1b2c3.local
+//     esp32c3-a1b2c3._http._tcp...  TXT  version=0.1.0 model=esp32c3 mac=...
+//     esp32c3-a1b2c3.local          A    192.168.1.40
+//
+// The records are announced twice, a second apart, once there is an
+// address, and again whenever DHCP hands out a different one. Queries to
+// 224.0.0.251:5353 for any of them are answered by multicast, with the
+// records a browser needs next as additional records. Everything else is
+// ignored: other names, responses, and one-shot queries from a plain
+// resolver's port. There is no probing for conflicts; the MAC keeps the
+// name unique.
+//
+// `withdraw` sends the records once more with TTL 0, the goodbye that
+// makes browsers drop the device now rather than when the TTL runs out.
+// `restart::shut_down` calls it; after that the responder stays silent.
+
+use core::fmt::Write as _;
+
+use embassy_futures::select::{select3, Either3};
+use embassy_net::udp::{PacketMetadata, UdpSocket};
+use embassy_net::Ipv4Address;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::signal::Signal;
+use embassy_time::{Duration, Instant, Timer as EmbassyTimer};
+use esp_println::println;
+use heapless::{String, Vec};
+
+use crate::canary;
+use crate::dns::{self, Name};
+use crate::httpd;
+use crate::mac;
+use crate::wire::{Mark, ReadCursor, WireError, WriteCursor};
+use crate::NetStack;
+
+const GROUP: Ipv4Address = Ipv4Address::new(224, 0, 0, 251);
+const PORT: u16 = 5353;
+
+const MODEL: &str = match option_env!("DEVICE_MODEL") {
+    Some(model) => model,
+    None => "esp32c3",
+};
+
+/// RFC 6762 10: 120 s for records with a host name or an address in them,
+/// 75 minutes for the rest.
+const HOST_TTL: u32 = 120;
+const OTHER_TTL: u32 = 4500;
+
+const ANNOUNCEMENTS: u8 = 2;
+const ANNOUNCE_INTERVAL: Duration = Duration::from_secs(1);
+/// How often the address is compared with the announced one.
+const ADDRESS_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+/// Time for the goodbye to leave before `withdraw` returns.
+const GOODBYE_SETTLE: Duration = Duration::from_millis(100);
+
+const MAX_LABEL_LEN: usize = 63;
+const MAX_TXT_LEN: usize = 128;
+const MESSAGE_LEN: usize = 512;
+
+const HEADER_LEN: usize = 12;
+const FLAG_RESPONSE: u16 = 0x8000;
+const FLAG_AUTHORITATIVE: u16 = 0x0400;
+const TYPE_A: u16 = 1;
+const TYPE_PTR: u16 = 12;
+const TYPE_TXT: u16 = 16;
+const TYPE_SRV: u16 = 33;
+const TYPE_ANY: u16 = 255;
+const CLASS_IN: u16 = 1;
+/// In a question, asks for a unicast reply; in a record, says it replaces
+/// what caches hold for the name.
+const CLASS_TOP_BIT: u16 = 0x8000;
+
+static GOODBYE: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+static WITHDRAWN: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+/// The records the device answers for.
+pub struct MdnsResponder {
+    host: String<MAX_LABEL_LEN>,
+    addr: Option<Ipv4Address>,
+    http: Option<Service>,
+}
+
+struct Service {
+    instance: String<MAX_LABEL_LEN>,
+    port: u16,
+    /// Length-prefixed strings, as on the wire.
+    txt: Vec<u8, MAX_TXT_LEN>,
+}
+
+// One bit per record.
+const A: u8 = 1;
+const PTR: u8 = 2;
+const SRV: u8 = 4;
+const TXT: u8 = 8;
+
+impl MdnsResponder {
+    /// `host` without `.local`.
+    pub fn new(host: &str) -> Self {
+        Self {
+            host: label(host),
+            addr: None,
+            http: None,
+        }
+    }
+
+    pub fn host(&self) -> &str {
+        &self.host
+    }
+
+    /// The records a query asks for, and those to add to the answer.
+    fn answers_for(&self, query: &[u8]) -> (u8, u8) {
+        let (mut answers, mut additional) = (0, 0);
+        let mut header = ReadCursor::new(query);
+        let (Ok(_), Ok(flags), Ok(count)) = (header.u16(), header.u16(), header.u16()) else {
+            return (0, 0);
+        };
+        if flags & FLAG_RESPONSE != 0 || (flags >> 11) & 0xf != 0 {
+            return (0, 0);
+        }
+        let mut name = Name::new();
+        let mut pos = HEADER_LEN;
+        for _ in 0..count {
+            let Ok(end) = dns::read_name(query, pos, &mut name) else {
+                break;
+            };
+            let Ok((qtype, qclass)) = ReadCursor::at(query, end)
+                .and_then(|mut question| Ok((question.u16()?, question.u16()?)))
+            else {
+                break;
+            };
+            pos = end + 4;
+            if qclass & !CLASS_TOP_BIT != CLASS_IN {
+                continue;
+            }
+            let wants = |rtype: u16| qtype == rtype || qtype == TYPE_ANY;
+            if self.addr.is_some() && name_is(&name, &[&self.host, "local"]) && wants(TYPE_A) {
+                answers |= A;
+            }
+            let Some(http) = &self.http else {
+                continue;
+            };
+            if name_is(&name, &["_http", "_tcp", "local"]) && wants(TYPE_PTR) {
+                answers |= PTR;
+                additional |= SRV | TXT | A;
+            }
+            if name_is(&name, &[&http.instance, "_http", "_tcp", "local"]) {
+                if wants(TYPE_SRV) {
+                    answers |= SRV;
+                    additional |= A;
+                }
+                if wants(TYPE_TXT) {
+                    answers |= TXT;
+                }
+            }
+        }
+        (answers, additional & !answers)
+    }
+
+    /// Writes a response with those of `answers` and `additional` that
+    /// exist, all with `ttl` if given; its length, or `None` for no records
+    /// or no room.
+    fn write_response(
+        &self,
+        answers: u8,
+        additional: u8,
+        ttl: Option<u32>,
+        out: &mut [u8],
+    ) -> Option<usize> {
+        let address = if self.addr.is_some() { A } else { 0 };
+        let service = if self.http.is_some() {
+            PTR | SRV | TXT
+        } else {
+            0
+        };
+        let (answers, additional) = (
+            answers & (address | service),
+            additional & (address | service),
+        );
+        if answers == 0 {
+            return None;
+        }
+        let mut out = WriteCursor::new(out);
+        let (answer_count, additional_count) =
+            (answers.count_ones() as u16, additional.count_ones() as u16);
+        // ID 0, no questions or authority records.
+        for word in [
+            0,
+            FLAG_RESPONSE | FLAG_AUTHORITATIVE,
+            0,
+            answer_count,
+            0,
+            additional_count,
+        ] {
+            out.u16(word).ok()?;
+        }
+        for records in [answers, additional] {
+            for record in [PTR, SRV, TXT, A] {
+                if records & record != 0 {
+                    self.write_record(record, ttl, &mut out).ok()?;
+                }
+            }
+        }
+        Some(out.len())
+    }
+
+    fn write_record(
+        &self,
+        record: u8,
+        ttl: Option<u32>,
+        out: &mut WriteCursor<'_>,
+    ) -> Result<(), WireError> {
+        let host = [self.host.as_str(), "local"];
+        let data = if record == A {
+            // Checked by `write_response`, as is the service below.
+            let Some(addr) = self.addr else {
+                return Ok(());
+            };
+            write_name(out, &host)?;
+            let data = write_fixed(out, TYPE_A, true, ttl.unwrap_or(HOST_TTL))?;
+            out.bytes(addr.as_bytes())?;
+            data
+        } else {
+            let Some(http) = &self.http else {
+                return Ok(());
+            };
+            let service = ["_http", "_tcp", "local"];
+            let instance = [http.instance.as_str(), "_http", "_tcp", "local"];
+            match record {
+                PTR => {
+                    write_name(out, &service)?;
+                    let data = write_fixed(out, TYPE_PTR, false, ttl.unwrap_or(OTHER_TTL))?;
+                    write_name(out, &instance)?;
+                    data
+                }
+                SRV => {
+                    write_name(out, &instance)?;
+                    let data = write_fixed(out, TYPE_SRV, true, ttl.unwrap_or(HOST_TTL))?;
+                    // Priority and weight 0.
+                    out.u16(0)?;
+                    out.u16(0)?;
+                    out.u16(http.port)?;
+                    write_name(out, &host)?;
+                    data
+                }
+                _ => {
+                    write_name(out, &instance)?;
+                    let data = write_fixed(out, TYPE_TXT, true, ttl.unwrap_or(OTHER_TTL))?;
+                    out.bytes(&http.txt)?;
+                    data
+                }
+            }
+        };
+        out.patch_len_u16(data)
+    }
+}
+
+/// Registers `_http._tcp` as `service_name` on `port`, with the firmware
+/// version, the model and the MAC in its TXT record.
+pub fn advertise_http_service(mdns: &mut MdnsResponder, service_name: &str, port: u16) {
+    let address = mac::read_mac_address();
+    let mut txt = Vec::new();
+    let mut entry: String<64> = String::new();
+    for (key, value) in [("version", env!("CARGO_PKG_VERSION")), ("model", MODEL)] {
+        entry.clear();
+        let _ = write!(entry, "{}={}", key, value);
+        push_string(&mut txt, &entry);
+    }
+    entry.clear();
+    let _ = write!(entry, "mac={}", mac::mac_to_string(&address));
+    push_string(&mut txt, &entry);
+    mdns.http = Some(Service {
+        instance: label(service_name),
+        port,
+        txt,
+    });
+}
+
+/// Sends the goodbye and silences the responder. Returns once it is out;
+/// it waits forever if the responder is not running, so bound it.
+pub async fn withdraw() {
+    GOODBYE.signal(());
+    WITHDRAWN.wait().await;
+}
+
+#[embassy_executor::task]
+pub async fn mdns_task(stack: &'static NetStack) {
+    canary::tracked("mdns", run(stack)).await
+}
+
+async fn run(stack: &'static NetStack) -> ! {
+    let host = mac::hostname(&mac::read_mac_address());
+    let mut mdns = MdnsResponder::new(&host);
+    advertise_http_service(&mut mdns, &host, httpd::PORT);
+
+    if let Err(e) = stack.join_multicast_group(GROUP).await {
+        println!("mdns: cannot join the group: {:?}", e);
+    }
+    let mut rx_meta = [PacketMetadata::EMPTY; 2];
+    let mut tx_meta = [PacketMetadata::EMPTY; 2];
+    let mut rx_buf = [0u8; MESSAGE_LEN];
+    let mut tx_buf = [0u8; MESSAGE_LEN];
+    let mut socket = UdpSocket::new(stack, &mut rx_meta, &mut rx_buf, &mut tx_meta, &mut tx_buf);
+    if let Err(e) = socket.bind(PORT) {
+        println!("mdns: cannot bind port {}: {:?}", PORT, e);
+    }
+    // RFC 6762 11: receivers may drop anything that has been routed.
+    socket.set_hop_limit(Some(255));
+    println!("mdns: answering as {}.local", mdns.host());
+
+    let mut query = [0u8; MESSAGE_LEN];
+    let mut out = [0u8; MESSAGE_LEN];
+    let mut announcements_left = 0;
+    let mut next = Instant::now();
+    loop {
+        if Instant::now() >= next {
+            let addr = stack.config_v4().map(|config| config.address.address());
+            if addr != mdns.addr {
+                mdns.addr = addr;
+                announcements_left = if addr.is_some() { ANNOUNCEMENTS } else { 0 };
+            }
+            if announcements_left > 0 {
+                announcements_left -= 1;
+                if let Some(len) = mdns.write_response(A | PTR | SRV | TXT, 0, None, &mut out) {
+                    let _ = socket.send_to(&out[..len], (GROUP, PORT)).await;
+                }
+            }
+            next = Instant::now()
+                + if announcements_left > 0 {
+                    ANNOUNCE_INTERVAL
+                } else {
+                    ADDRESS_CHECK_INTERVAL
+                };
+        }
+        match select3(
+            socket.recv_from(&mut query),
+            EmbassyTimer::at(next),
+            GOODBYE.wait(),
+        )
+        .await
+        {
+            // Replies to a plain resolver's port would have to be unicast.
+            Either3::First(Ok((len, from))) if from.port == PORT => {
+                let (answers, additional) = mdns.answers_for(&query[..len]);
+                if let Some(len) = mdns.write_response(answers, additional, None, &mut out) {
+                    let _ = socket.send_to(&out[..len], (GROUP, PORT)).await;
+                }
+            }
+            Either3::First(_) | Either3::Second(()) => {}
+            Either3::Third(()) => break,
+        }
+    }
+
+    if let Some(len) = mdns.write_response(A | PTR | SRV | TXT, 0, Some(0), &mut out) {
+        let _ = socket.send_to(&out[..len], (GROUP, PORT)).await;
+        EmbassyTimer::after(GOODBYE_SETTLE).await;
+    }
+    println!("mdns: withdrawn");
+    WITHDRAWN.signal(());
+    loop {
+        EmbassyTimer::after(Duration::from_secs(3600)).await;
+    }
+}
+
+/// `text` as one DNS label: at most 63 bytes, without dots.
+fn label(text: &str) -> String<MAX_LABEL_LEN> {
+    let mut label = String::new();
+    for c in text.chars().map(|c| if c == '.' { '-' } else { c }) {
+        if label.push(c).is_err() {
+            break;
+        }
+    }
+    label
+}
+
+fn push_string(txt: &mut Vec<u8, MAX_TXT_LEN>, s: &str) {
+    let len = s.len().min(u8::MAX as usize);
+    if txt.len() + 1 + len <= MAX_TXT_LEN {
+        let _ = txt.push(len as u8);
+        let _ = txt.extend_from_slice(&s.as_bytes()[..len]);
+    }
+}
+
+/// Whether the dotted `name` is `labels`, ignoring ASCII case.
+fn name_is(name: &[u8], labels: &[&str]) -> bool {
+    let mut parts = name.split(|&b| b == b'.');
+    labels.iter().all(|label| {
+        parts
+            .next()
+            .is_some_and(|part| part.eq_ignore_ascii_case(label.as_bytes()))
+    }) && parts.next().is_none()
+}
+
+/// Uncompressed; the names are short.
+fn write_name(out: &mut WriteCursor<'_>, labels: &[&str]) -> Result<(), WireError> {
+    for label in labels {
+        out.prefixed_u8(label.as_bytes())?;
+    }
+    out.u8(0)
+}
+
+/// Type, class and TTL of a record, and room for its data length, to be
+/// patched in once the data is written.
+fn write_fixed(
+    out: &mut WriteCursor<'_>,
+    rtype: u16,
+    unique: bool,
+    ttl: u32,
+) -> Result<Mark, WireError> {
+    let class = if unique {
+        CLASS_IN | CLASS_TOP_BIT
+    } else {
+        CLASS_IN
+    };
+    out.u16(rtype)?;
+    out.u16(class)?;
+    out.u32(ttl)?;
+    out.reserve_u16()
+}
@@ -0,0 +1,99 @@
+// A Merkle tree over a download in `CHUNK_LEN` chunks, for checking a body
+// against a root the server sends ahead of it (`X-Merkle-Root`, src/ota.rs)
+// without keeping the body.
+//
+// The tree is RFC 6962's: a leaf is the SHA-256 of a zero byte and a chunk,
+// a node that of a one byte and its two children, and a level with an odd
+// one out carries it up as it is. The prefixes keep a leaf from passing for
+// a node. The last chunk may be short; a body of no chunks at all has the
+// SHA-256 of nothing for a root. tools/ota-image.sh works out the same.
+//
+// Chunks are folded in bottom-up as they complete, so that only the nodes
+// still waiting for a right-hand sibling are kept, one per level at most.
+// Hashing is sha2's, in software: a download holds its hasher across every
+// await for as long as it runs, and the accelerator (src/integrity.rs) is
+// one, for whoever needs it briefly.
+
+use heapless::Vec;
+use sha2::{Digest, Sha256};
+
+use crate::integrity::HASH_LEN;
+
+/// One flash sector.
+const CHUNK_LEN: usize = 4096;
+
+const LEAF: u8 = 0;
+const NODE: u8 = 1;
+/// Room for 2^32 - 1 chunks, some 16 TB, at one entry per level.
+const MAX_LEVELS: usize = 32;
+
+type Hash = [u8; HASH_LEN];
+
+pub struct MerkleTree {
+    /// The chunk under way, from its prefix.
+    leaf: Sha256,
+    filled: usize,
+    /// Complete subtrees and their levels, highest first.
+    pending: Vec<(u8, Hash), MAX_LEVELS>,
+}
+
+impl MerkleTree {
+    pub fn new() -> Self {
+        Self {
+            leaf: Sha256::new_with_prefix([LEAF]),
+            filled: 0,
+            pending: Vec::new(),
+        }
+    }
+
+    pub fn update(&mut self, mut data: &[u8]) {
+        while !data.is_empty() {
+            let n = (CHUNK_LEN - self.filled).min(data.len());
+            self.leaf.update(&data[..n]);
+            self.filled += n;
+            data = &data[n..];
+            if self.filled == CHUNK_LEN {
+                self.end_chunk();
+            }
+        }
+    }
+
+    /// The root over everything passed to `update`.
+    pub fn root(mut self) -> Hash {
+        if self.filled > 0 {
+            self.end_chunk();
+        }
+        let Some((_, mut root)) = self.pending.pop() else {
+            return Sha256::digest(b"").into();
+        };
+        // What is left lies to the left, each subtree larger than the last.
+        while let Some((_, left)) = self.pending.pop() {
+            root = node(&left, &root);
+        }
+        root
+    }
+
+    fn end_chunk(&mut self) {
+        let leaf = core::mem::replace(&mut self.leaf, Sha256::new_with_prefix([LEAF]));
+        self.filled = 0;
+        let mut hash: Hash = leaf.finalize().into();
+        let mut level = 0;
+        while let Some(&(top, left)) = self.pending.last() {
+            if top != level {
+                break;
+            }
+            self.pending.pop();
+            hash = node(&left, &hash);
+            level += 1;
+        }
+        // Cannot overflow: one entry per level, each below the last.
+        let _ = self.pending.push((level, hash));
+    }
+}
+
+fn node(left: &Hash, right: &Hash) -> Hash {
+    let mut sha = Sha256::new_with_prefix([NODE]);
+    sha.update(left);
+    sha.update(right);
+    sha.finalize().into()
+}
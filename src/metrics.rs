@@ -0,0 +1,315 @@
+// Request metrics: latency histograms and bytes sent and received, per UTC
+// day. Bytes are HTTP bytes, without the TLS records around them. Alongside,
+// the DNS responses the resolver discarded, by reason.
+//
+// Latency is kept for whole requests and for each phase of one (`PHASES`:
+// DNS, connect, handshake, first byte), in the same logarithmic buckets:
+// 1 ms to 60 s, two per octave, so the relative error of an estimate is
+// about the same in the fast and the slow end. `write_prometheus` gives
+// today's as Prometheus histograms, with cumulative buckets in seconds.
+//
+// The request path only bumps counters (`record_request`), one atomic add
+// each and no lock. Every few seconds the housekeeping task takes them
+// (`roll_up_pending`), swapping each back to zero, and folds them into the
+// `Rollup` under a short critical section; readers get a copy of that
+// (`snapshot`). So data flows one way:
+//
+//     record_request -> PENDING_* atomics -> roll_up -> ROLLUP -> snapshot
+//
+// The day rolls over at UTC midnight once SNTP has synced; before that
+// everything counts towards the current day. Latency is bucketed, so
+// percentiles are estimates (`Histogram::percentile`). The buckets, the
+// histograms and the rollup are src/rollup.rs.
+//
+// Critical records (src/priority.rs) have a histogram of their own, in the
+// same buckets: how long from being raised to being accepted, retries and
+// all (`record_critical_latency`).
+
+use core::cell::RefCell;
+#[cfg(feature = "console")]
+use core::fmt::{self, Write};
+
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use esp_println::println;
+#[cfg(feature = "console")]
+use heapless::String;
+use portable_atomic::{AtomicU32, Ordering};
+
+use crate::dns::Reject;
+use crate::https::Timings;
+#[cfg(feature = "console")]
+use crate::labels::Labels;
+#[cfg(feature = "console")]
+use crate::rollup::BUCKET_BOUNDS_MS;
+use crate::rollup::{bucket_index, DayTotals, Histogram, Rollup, BUCKETS, PHASES};
+#[cfg(feature = "console")]
+use crate::settings;
+use crate::sntp;
+#[cfg(feature = "console")]
+use crate::wifiheap;
+
+const MS_PER_DAY: u64 = 24 * 60 * 60 * 1000;
+
+/// Whole requests, then `PHASES`.
+static PENDING: [PendingHistogram; 1 + PHASES.len()] =
+    [const { PendingHistogram::new() }; 1 + PHASES.len()];
+static PENDING_CRITICAL: PendingHistogram = PendingHistogram::new();
+static PENDING_SENT: AtomicU32 = AtomicU32::new(0);
+static PENDING_RECEIVED: AtomicU32 = AtomicU32::new(0);
+static PENDING_DNS_DISCARDED: [AtomicU32; Reject::COUNT] =
+    [const { AtomicU32::new(0) }; Reject::COUNT];
+
+static ROLLUP: Mutex<CriticalSectionRawMutex, RefCell<Rollup>> = Mutex::new(RefCell::new(Rollup {
+    day: None,
+    today: DayTotals::ZERO,
+    yesterday: DayTotals::ZERO,
+}));
+
+/// A histogram's counters between roll-ups.
+struct PendingHistogram {
+    counts: [AtomicU32; BUCKETS],
+    sum_ms: AtomicU32,
+}
+
+impl PendingHistogram {
+    const fn new() -> Self {
+        Self {
+            counts: [const { AtomicU32::new(0) }; BUCKETS],
+            sum_ms: AtomicU32::new(0),
+        }
+    }
+
+    fn record(&self, ms: u64) {
+        self.counts[bucket_index(ms)].fetch_add(1, Ordering::Relaxed);
+        self.sum_ms
+            .fetch_add(ms.min(u32::MAX as u64) as u32, Ordering::Relaxed);
+    }
+
+    fn take(&self) -> Histogram {
+        let mut histogram = Histogram::EMPTY;
+        for (count, pending) in histogram.counts.iter_mut().zip(&self.counts) {
+            *count = pending.swap(0, Ordering::Relaxed);
+        }
+        histogram.sum_ms = self.sum_ms.swap(0, Ordering::Relaxed) as u64;
+        histogram
+    }
+}
+
+/// Counts one completed request. Cheap enough for any path.
+pub fn record_request(timings: &Timings, sent: usize, received: usize) {
+    let [total, phases @ ..] = &PENDING;
+    total.record(timings.total_ms);
+    let phase_ms = [
+        timings.dns_ms,
+        timings.connect_ms,
+        timings.handshake_ms,
+        timings.first_byte_ms,
+    ];
+    for (phase, ms) in phases.iter().zip(phase_ms) {
+        phase.record(ms);
+    }
+    PENDING_SENT.fetch_add(sent as u32, Ordering::Relaxed);
+    PENDING_RECEIVED.fetch_add(received as u32, Ordering::Relaxed);
+}
+
+/// Counts one critical record the server accepted, `ms` after it was
+/// raised.
+pub fn record_critical_latency(ms: u64) {
+    PENDING_CRITICAL.record(ms);
+}
+
+/// Counts one DNS response the resolver threw away.
+pub fn record_dns_discarded(reason: Reject) {
+    PENDING_DNS_DISCARDED[reason.index()].fetch_add(1, Ordering::Relaxed);
+}
+
+/// Moves the counters into the rollup. For the housekeeping task; `true`
+/// when a new day has begun.
+pub fn roll_up_pending() -> bool {
+    let [total, phases @ ..] = &PENDING;
+    let latency = total.take();
+    let phases = phases.each_ref().map(PendingHistogram::take);
+    let critical = PENDING_CRITICAL.take();
+    let sent = PENDING_SENT.swap(0, Ordering::Relaxed);
+    let received = PENDING_RECEIVED.swap(0, Ordering::Relaxed);
+    let mut dns_discarded = [0; Reject::COUNT];
+    for (count, pending) in dns_discarded.iter_mut().zip(&PENDING_DNS_DISCARDED) {
+        *count = pending.swap(0, Ordering::Relaxed);
+    }
+    let day = sntp::now_unix_ms().map(|ms| (ms / MS_PER_DAY) as u32);
+    ROLLUP.lock(|rollup| {
+        let mut rollup = rollup.borrow_mut();
+        let new_day = rollup.roll_up(day, &latency, &phases, sent, received, &dns_discarded);
+        rollup.today.critical.merge(&critical);
+        new_day
+    })
+}
+
+/// A copy of the rollup, up to the last housekeeping pass.
+pub fn snapshot() -> Rollup {
+    ROLLUP.lock(|rollup| *rollup.borrow())
+}
+
+/// Prints today's and yesterday's totals.
+pub fn print_summary() {
+    let rollup = snapshot();
+    for (name, day) in [("today", rollup.today), ("yesterday", rollup.yesterday)] {
+        let latency = day.latency;
+        let p = |percent| latency.percentile(percent).unwrap_or(0);
+        println!(
+            "requests {}: {}, p50 {} ms, p90 {} ms, p99 {} ms; {} bytes sent, {} received",
+            name,
+            latency.count(),
+            p(50),
+            p(90),
+            p(99),
+            day.bytes_sent,
+            day.bytes_received
+        );
+        let critical = day.critical;
+        if critical.count() > 0 {
+            let p = |percent| critical.percentile(percent).unwrap_or(0);
+            println!(
+                "critical {}: {} delivered, p50 {} ms, p90 {} ms, p99 {} ms",
+                name,
+                critical.count(),
+                p(50),
+                p(90),
+                p(99)
+            );
+        }
+        let [source, id, question, answer, malformed] = day.dns_discarded;
+        if day.dns_discarded.iter().any(|&n| n > 0) {
+            println!(
+                "dns {}: discarded {} from elsewhere, {} wrong id, {} wrong question, {} unrelated answer, {} malformed",
+                name, source, id, question, answer, malformed
+            );
+        }
+    }
+}
+
+/// Today's latency histograms in the Prometheus text format, and the Wi-Fi
+/// heap's gauges (src/wifiheap.rs). Every sample has the device's labels
+/// (src/labels.rs).
+#[cfg(feature = "console")]
+pub fn write_prometheus<W: Write>(out: &mut W) -> fmt::Result {
+    let today = snapshot().today;
+    let labels = settings::labels();
+    let name = "https_request_duration_seconds";
+    writeln!(out, "# HELP {} Whole HTTPS requests, today (UTC).", name)?;
+    writeln!(out, "# TYPE {} histogram", name)?;
+    write_histogram(out, name, &labels, None, &today.latency)?;
+    let name = "https_phase_duration_seconds";
+    writeln!(out, "# HELP {} HTTPS request phases, today (UTC).", name)?;
+    writeln!(out, "# TYPE {} histogram", name)?;
+    for (phase, histogram) in PHASES.iter().zip(&today.phases) {
+        write_histogram(out, name, &labels, Some(phase), histogram)?;
+    }
+    let name = "critical_record_latency_seconds";
+    writeln!(
+        out,
+        "# HELP {} Critical records, raised to accepted, today (UTC).",
+        name
+    )?;
+    writeln!(out, "# TYPE {} histogram", name)?;
+    write_histogram(out, name, &labels, None, &today.critical)?;
+    let name = "wifi_heap_used_bytes";
+    if let Some(used) = wifiheap::used() {
+        writeln!(out, "# HELP {} esp-wifi heap in use.", name)?;
+        writeln!(out, "# TYPE {} gauge", name)?;
+        write_series(out, name, "", &labels, [])?;
+        writeln!(out, " {}", used)?;
+        let name = "wifi_heap_peak_bytes";
+        writeln!(out, "# HELP {} Most esp-wifi heap seen in use.", name)?;
+        writeln!(out, "# TYPE {} gauge", name)?;
+        write_series(out, name, "", &labels, [])?;
+        writeln!(out, " {}", wifiheap::peak())?;
+    }
+    let name = "wifi_heap_size_bytes";
+    writeln!(out, "# HELP {} esp-wifi heap size.", name)?;
+    writeln!(out, "# TYPE {} gauge", name)?;
+    write_series(out, name, "", &labels, [])?;
+    writeln!(out, " {}", wifiheap::HEAP_SIZE)?;
+    let name = "wifi_heap_alloc_failures_total";
+    writeln!(out, "# HELP {} esp-wifi allocations that failed.", name)?;
+    writeln!(out, "# TYPE {} counter", name)?;
+    write_series(out, name, "", &labels, [])?;
+    writeln!(out, " {}", wifiheap::failures())?;
+    Ok(())
+}
+
+#[cfg(feature = "console")]
+fn write_histogram<W: Write>(
+    out: &mut W,
+    name: &str,
+    labels: &Labels,
+    phase: Option<&str>,
+    histogram: &Histogram,
+) -> fmt::Result {
+    let phase = phase.map(|phase| ("phase", phase));
+    let mut cumulative = 0u64;
+    let mut le: String<12> = String::new();
+    for (i, &n) in histogram.counts.iter().enumerate() {
+        cumulative += n as u64;
+        le.clear();
+        match BUCKET_BOUNDS_MS.get(i) {
+            Some(&ms) => write!(le, "{}.{:03}", ms / 1000, ms % 1000)?,
+            None => le.push_str("+Inf").map_err(|_| fmt::Error)?,
+        }
+        let own = phase.into_iter().chain([("le", le.as_str())]);
+        write_series(out, name, "_bucket", labels, own)?;
+        writeln!(out, " {}", cumulative)?;
+    }
+    for (suffix, value) in [("_sum", None), ("_count", Some(cumulative))] {
+        write_series(out, name, suffix, labels, phase)?;
+        match value {
+            Some(count) => writeln!(out, " {}", count)?,
+            None => writeln!(
+                out,
+                " {}.{:03}",
+                histogram.sum_ms / 1000,
+                histogram.sum_ms % 1000
+            )?,
+        }
+    }
+    Ok(())
+}
+
+/// `name` and `suffix`, and `labels` and `own` as its label set if there
+/// are any.
+#[cfg(feature = "console")]
+fn write_series<'a, W: Write>(
+    out: &mut W,
+    name: &str,
+    suffix: &str,
+    labels: &'a Labels,
+    own: impl IntoIterator<Item = (&'a str, &'a str)>,
+) -> fmt::Result {
+    write!(out, "{}{}", name, suffix)?;
+    let mut open = false;
+    for (key, value) in labels.iter().chain(own) {
+        out.write_char(if open { ',' } else { '{' })?;
+        open = true;
+        write!(out, "{}=\"{}\"", key, value)?;
+    }
+    if open {
+        out.write_char('}')?;
+    }
+    Ok(())
+}
+
+/// `write_prometheus` to the console.
+#[cfg(feature = "console")]
+pub fn print_prometheus() {
+    struct Console;
+
+    impl Write for Console {
+        fn write_str(&mut self, s: &str) -> fmt::Result {
+            esp_println::print!("{}", s);
+            Ok(())
+        }
+    }
+
+    let _ = write_prometheus(&mut Console);
+}
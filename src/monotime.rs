@@ -0,0 +1,100 @@
+// A millisecond count that goes on across resets and would across sleep:
+// `uptime_total` is how long the device has been up since it was powered,
+// `since_boot` how long since this boot.
+//
+// Two clocks go into it. The embassy `Instant` runs off the crystal and is
+// accurate, but starts over at every boot and would stand still in light
+// sleep. The RTC timer runs off the slow RC oscillator, a few percent off,
+// but counts from power-up through resets and sleep alike. So the count
+// moves with the `Instant`, and the RTC only makes up a gap the `Instant`
+// did not see (`elapsed_ms`).
+//
+// The housekeeping task anchors the count every pass (`anchor`): the total
+// and the RTC's reading, in RAM and in RTC fast memory. At boot `init`
+// starts from the last anchor plus what the RTC says has passed since: at
+// most a pass's worth of running and then the reset, so the RC's error
+// hardly counts. The record has a magic and a CRC like the restart note;
+// after a power cycle there is none, and the count starts at the RTC's.
+//
+// The firmware does not sleep, so within a boot the intervals it waits,
+// the backoffs and the rate limits keep to the `Instant`, which is what
+// they measure: all of them start over with a boot anyway. The two counts
+// are for what the server sees: `uptime_ms` in readings and the API's
+// status is `since_boot`, and `uptime_total_ms` next to it the total.
+//
+// The arithmetic and the record are in src/monotimeanchor.rs.
+
+use core::cell::{Cell, RefCell};
+use core::ptr::addr_of_mut;
+
+use critical_section::Mutex;
+use embassy_time::Instant;
+use esp_hal::macros::ram;
+use esp_hal::rtc_cntl::Rtc;
+
+use crate::monotimeanchor::{self, Anchor, RECORD_LEN};
+
+#[ram(rtc_fast, uninitialized)]
+static mut RECORD: [u8; RECORD_LEN] = [0; RECORD_LEN];
+
+static RTC: Mutex<RefCell<Option<Rtc<'static>>>> = Mutex::new(RefCell::new(None));
+static LAST: Mutex<Cell<Anchor>> = Mutex::new(Cell::new(Anchor::ZERO));
+static BOOT_TOTAL_MS: Mutex<Cell<u64>> = Mutex::new(Cell::new(0));
+
+/// Takes over the RTC and starts the count. Call once, early at boot.
+pub fn init(rtc: Rtc<'static>) {
+    let rtc_ms = rtc.get_time_ms();
+    critical_section::with(|cs| {
+        let total_ms = monotimeanchor::total_at_boot(
+            monotimeanchor::decode(unsafe { &*addr_of_mut!(RECORD) }),
+            rtc_ms,
+        );
+        let anchor = Anchor {
+            total_ms,
+            rtc_ms,
+            instant_ms: Instant::now().as_millis(),
+        };
+        RTC.borrow_ref_mut(cs).replace(rtc);
+        LAST.borrow(cs).set(anchor);
+        BOOT_TOTAL_MS.borrow(cs).set(total_ms);
+        save(&anchor);
+    });
+}
+
+/// Milliseconds since power-up, resets included.
+pub fn uptime_total() -> u64 {
+    critical_section::with(|cs| now(cs).total_ms)
+}
+
+/// Milliseconds since this boot.
+pub fn since_boot() -> u64 {
+    critical_section::with(|cs| now(cs).total_ms - BOOT_TOTAL_MS.borrow(cs).get())
+}
+
+/// Moves the anchor up to now and keeps it for after a reset.
+pub fn anchor() {
+    critical_section::with(|cs| {
+        let anchor = now(cs);
+        LAST.borrow(cs).set(anchor);
+        save(&anchor);
+    });
+}
+
+fn now(cs: critical_section::CriticalSection<'_>) -> Anchor {
+    let last = LAST.borrow(cs).get();
+    let instant_ms = Instant::now().as_millis();
+    // Before `init` there is no RTC to go by, nor any gap to make up.
+    let rtc_ms = RTC
+        .borrow_ref(cs)
+        .as_ref()
+        .map_or(last.rtc_ms, |rtc| rtc.get_time_ms());
+    Anchor {
+        total_ms: last.total_at(rtc_ms, instant_ms),
+        rtc_ms,
+        instant_ms,
+    }
+}
+
+fn save(anchor: &Anchor) {
+    unsafe { *addr_of_mut!(RECORD) = monotimeanchor::encode(anchor) };
+}
@@ -0,0 +1,82 @@
+// The count behind src/monotime.rs, apart from the clocks it reads: how the
+// two clocks' readings come to a total, within a boot and across a reset,
+// and the record of the last anchor kept in RTC fast memory.
+
+use esp_hal::rom::crc::crc32_le;
+
+const MAGIC: u32 = 0x4d4f_4e4f;
+// magic | total_ms (u64) | rtc_ms (u64) | crc
+pub const RECORD_LEN: usize = 24;
+
+/// The count at one moment, by both clocks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Anchor {
+    pub total_ms: u64,
+    pub rtc_ms: u64,
+    /// The `Instant`, in this boot.
+    pub instant_ms: u64,
+}
+
+impl Anchor {
+    pub const ZERO: Anchor = Anchor {
+        total_ms: 0,
+        rtc_ms: 0,
+        instant_ms: 0,
+    };
+
+    /// The total at `rtc_ms` and `instant_ms`, both read later in the same
+    /// boot.
+    pub fn total_at(&self, rtc_ms: u64, instant_ms: u64) -> u64 {
+        self.total_ms
+            + elapsed_ms(
+                rtc_ms.saturating_sub(self.rtc_ms),
+                instant_ms.saturating_sub(self.instant_ms),
+            )
+    }
+}
+
+/// How long passed between two readings of both clocks: the `Instant`'s
+/// count, unless the RTC's is so much longer that the RC's drift cannot
+/// explain it, which means the `Instant` stood still for part of it.
+pub fn elapsed_ms(rtc_delta_ms: u64, instant_delta_ms: u64) -> u64 {
+    let drift_ms = instant_delta_ms / 8 + 100;
+    if rtc_delta_ms > instant_delta_ms + drift_ms {
+        rtc_delta_ms
+    } else {
+        instant_delta_ms
+    }
+}
+
+/// The total at boot, from the record of the last anchor before the reset
+/// and the RTC now. With no record, or an RTC that went back, which only a
+/// power cycle does, the RTC's own count.
+pub fn total_at_boot(last: Option<(u64, u64)>, rtc_ms: u64) -> u64 {
+    match last {
+        Some((total_ms, last_rtc_ms)) if rtc_ms >= last_rtc_ms => total_ms + (rtc_ms - last_rtc_ms),
+        _ => rtc_ms,
+    }
+}
+
+/// The record of `anchor`; the `Instant` does not survive a reset, so it
+/// is left out.
+pub fn encode(anchor: &Anchor) -> [u8; RECORD_LEN] {
+    let mut raw = [0u8; RECORD_LEN];
+    raw[0..4].copy_from_slice(&MAGIC.to_le_bytes());
+    raw[4..12].copy_from_slice(&anchor.total_ms.to_le_bytes());
+    raw[12..20].copy_from_slice(&anchor.rtc_ms.to_le_bytes());
+    let crc = crc32_le(0, &raw[..RECORD_LEN - 4]);
+    raw[RECORD_LEN - 4..].copy_from_slice(&crc.to_le_bytes());
+    raw
+}
+
+/// `(total_ms, rtc_ms)`, or `None` for anything `encode` did not write.
+pub fn decode(raw: &[u8; RECORD_LEN]) -> Option<(u64, u64)> {
+    let word = |at: usize| u32::from_le_bytes(raw[at..at + 4].try_into().unwrap());
+    if word(0) != MAGIC || word(RECORD_LEN - 4) != crc32_le(0, &raw[..RECORD_LEN - 4]) {
+        return None;
+    }
+    Some((
+        u64::from_le_bytes(raw[4..12].try_into().unwrap()),
+        u64::from_le_bytes(raw[12..20].try_into().unwrap()),
+    ))
+}
@@ -0,0 +1,121 @@
+// Just enough MessagePack for the binary log format (src/logring.rs):
+// arrays, unsigned integers and strings.
+//
+// The encoder picks the shortest form of each, as the spec asks. The
+// reader reads through a function from offset to byte rather than a slice,
+// so it can walk entries where they lie in the log ring, wrapped around
+// its end.
+
+use heapless::Vec;
+
+const FIXARRAY: u8 = 0x90;
+const FIXSTR: u8 = 0xa0;
+const UINT8: u8 = 0xcc;
+const UINT16: u8 = 0xcd;
+const UINT32: u8 = 0xce;
+const UINT64: u8 = 0xcf;
+const STR8: u8 = 0xd9;
+const STR16: u8 = 0xda;
+
+/// Fails only if `out` is full.
+pub fn write_array_len<const N: usize>(out: &mut Vec<u8, N>, len: u8) -> Result<(), ()> {
+    debug_assert!(len < 16);
+    out.push(FIXARRAY | len).map_err(|_| ())
+}
+
+pub fn write_uint<const N: usize>(out: &mut Vec<u8, N>, n: u64) -> Result<(), ()> {
+    match n {
+        0..=0x7f => out.push(n as u8).map_err(|_| ()),
+        0x80..=0xff => out.extend_from_slice(&[UINT8, n as u8]),
+        0x100..=0xffff => {
+            out.push(UINT16).map_err(|_| ())?;
+            out.extend_from_slice(&(n as u16).to_be_bytes())
+        }
+        0x1_0000..=0xffff_ffff => {
+            out.push(UINT32).map_err(|_| ())?;
+            out.extend_from_slice(&(n as u32).to_be_bytes())
+        }
+        _ => {
+            out.push(UINT64).map_err(|_| ())?;
+            out.extend_from_slice(&n.to_be_bytes())
+        }
+    }
+}
+
+/// Up to 64 KiB of string; the caller keeps it shorter.
+pub fn write_str<const N: usize>(out: &mut Vec<u8, N>, s: &str) -> Result<(), ()> {
+    let len = s.len();
+    match len {
+        0..=31 => out.push(FIXSTR | len as u8).map_err(|_| ())?,
+        32..=0xff => out.extend_from_slice(&[STR8, len as u8])?,
+        _ => {
+            let len = u16::try_from(len).map_err(|_| ())?;
+            out.push(STR16).map_err(|_| ())?;
+            out.extend_from_slice(&len.to_be_bytes())?;
+        }
+    }
+    out.extend_from_slice(s.as_bytes())
+}
+
+/// Reads values one after another from `at(0)`, `at(1)`, ...; `None` from
+/// `at` is the end of the input.
+pub struct Reader<F> {
+    at: F,
+    pos: usize,
+}
+
+impl<F: Fn(usize) -> Option<u8>> Reader<F> {
+    pub fn new(at: F) -> Self {
+        Self { at, pos: 0 }
+    }
+
+    /// Where the next value starts.
+    pub fn pos(&self) -> usize {
+        self.pos
+    }
+
+    fn byte(&mut self) -> Option<u8> {
+        let byte = (self.at)(self.pos)?;
+        self.pos += 1;
+        Some(byte)
+    }
+
+    fn be(&mut self, len: usize) -> Option<u64> {
+        (0..len).try_fold(0u64, |n, _| Some(n << 8 | self.byte()? as u64))
+    }
+
+    pub fn array_len(&mut self) -> Option<usize> {
+        match self.byte()? {
+            b if b & 0xf0 == FIXARRAY => Some((b & 0x0f) as usize),
+            _ => None,
+        }
+    }
+
+    pub fn uint(&mut self) -> Option<u64> {
+        match self.byte()? {
+            b @ 0..=0x7f => Some(b as u64),
+            UINT8 => self.be(1),
+            UINT16 => self.be(2),
+            UINT32 => self.be(4),
+            UINT64 => self.be(8),
+            _ => None,
+        }
+    }
+
+    /// Skips a string, giving where its bytes are.
+    pub fn str_range(&mut self) -> Option<core::ops::Range<usize>> {
+        let len = match self.byte()? {
+            b if b & 0xe0 == FIXSTR => (b & 0x1f) as usize,
+            STR8 => self.be(1)? as usize,
+            STR16 => self.be(2)? as usize,
+            _ => return None,
+        };
+        let start = self.pos;
+        // The last byte has to be there too.
+        if len > 0 {
+            (self.at)(start + len - 1)?;
+        }
+        self.pos += len;
+        Some(start..self.pos)
+    }
+}
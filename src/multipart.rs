@@ -0,0 +1,127 @@
+// `multipart/mixed` bodies (RFC 2046 section 5.1), for servers that answer
+// one request with several resources.
+//
+// `MultipartParser` walks the parts of a body held whole in memory and
+// hands out each one's `Content-Type` and content, borrowed from the body.
+// The preamble before the first delimiter and the epilogue after the last
+// are skipped. A body that ends before its close delimiter ends the parts
+// there: a part that was cut off is not handed out. Part headers other
+// than `Content-Type` are not looked at; without one a part is
+// `text/plain`, as the RFC has it.
+//
+// Every part takes at least a delimiter line from the body, so there are
+// no more of them than it has room for, and the parser only ever moves
+// forward through it.
+
+/// The `boundary` parameter of a `multipart/...` content type, with the
+/// quotes taken off if it had any.
+pub fn boundary(content_type: &str) -> Option<&str> {
+    let (kind, params) = content_type.split_once(';')?;
+    let kind = kind.trim();
+    if !kind
+        .get(..10)
+        .is_some_and(|k| k.eq_ignore_ascii_case("multipart/"))
+    {
+        return None;
+    }
+    params.split(';').find_map(|param| {
+        let (name, value) = param.split_once('=')?;
+        if !name.trim().eq_ignore_ascii_case("boundary") {
+            return None;
+        }
+        let value = value.trim();
+        let value = value
+            .strip_prefix('"')
+            .and_then(|v| v.strip_suffix('"'))
+            .unwrap_or(value);
+        // RFC 2046: 1 to 70 characters.
+        (1..=70).contains(&value.len()).then_some(value)
+    })
+}
+
+pub struct MultipartParser<'a> {
+    boundary: &'a str,
+    body: &'a [u8],
+    /// Where the next delimiter's `--boundary` starts; `None` once the
+    /// parts are over.
+    pos: Option<usize>,
+}
+
+impl<'a> MultipartParser<'a> {
+    pub fn new(boundary: &'a str, body: &'a [u8]) -> Self {
+        let mut parser = Self {
+            boundary,
+            body,
+            pos: None,
+        };
+        // The first delimiter may open the body; later ones follow a CRLF.
+        parser.pos = if parser.is_delimiter(0) {
+            Some(0)
+        } else {
+            parser.find_delimiter(0).map(|crlf| crlf + 2)
+        };
+        parser
+    }
+
+    /// The next part's `Content-Type` and content.
+    pub fn next_part(&mut self) -> Option<(&'a str, &'a [u8])> {
+        let start = self.pos.take()?;
+        let mut i = start + 2 + self.boundary.len();
+        if self.body[i..].starts_with(b"--") {
+            return None;
+        }
+        // Transport padding, then the end of the delimiter line.
+        while matches!(self.body.get(i), Some(b' ' | b'\t')) {
+            i += 1;
+        }
+        if !self.body[i..].starts_with(b"\r\n") {
+            return None;
+        }
+        i += 2;
+
+        let (headers, content_start) = if self.body[i..].starts_with(b"\r\n") {
+            ("", i + 2)
+        } else {
+            let end = find(&self.body[i..], b"\r\n\r\n")? + i;
+            (core::str::from_utf8(&self.body[i..end]).ok()?, end + 4)
+        };
+        // The CRLF in front of the delimiter belongs to it, not the content.
+        let crlf = self.find_delimiter(content_start)?;
+        let content = &self.body[content_start..crlf];
+        self.pos = Some(crlf + 2);
+
+        let content_type = headers
+            .split("\r\n")
+            .find_map(|line| {
+                let (name, value) = line.split_once(':')?;
+                name.trim()
+                    .eq_ignore_ascii_case("Content-Type")
+                    .then(|| value.trim())
+            })
+            .unwrap_or("text/plain");
+        Some((content_type, content))
+    }
+
+    /// Whether `--boundary` starts at `at`.
+    fn is_delimiter(&self, at: usize) -> bool {
+        let rest = &self.body[at..];
+        rest.starts_with(b"--") && rest[2..].starts_with(self.boundary.as_bytes())
+    }
+
+    /// The position of the CRLF in front of the next delimiter at or after
+    /// `from`.
+    fn find_delimiter(&self, from: usize) -> Option<usize> {
+        let mut at = from;
+        loop {
+            let crlf = find(&self.body[at..], b"\r\n")? + at;
+            if self.is_delimiter(crlf + 2) {
+                return Some(crlf);
+            }
+            at = crlf + 2;
+        }
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
@@ -0,0 +1,92 @@
+// NTP packets (RFC 5905) as SNTP (src/sntp.rs) sends and reads them: a
+// client request that is a header byte and zeros, and the fields of a
+// server's reply that set the clock.
+//
+// A reply is `PACKET_LEN` bytes of fixed fields, perhaps followed by
+// extensions, which are not looked at; there is nothing in it of variable
+// length to bound. It is refused unless it is from a server (mode 4)
+// whose clock is synchronized (leap indicator other than 3), and unless
+// both of its timestamps are set and fall after 1970: a transmit
+// timestamp of zero is a server that never filled it in (RFC 4330,
+// section 5). fuzz/ runs `parse_response` on the host.
+
+use crate::wire::{ReadCursor, WireError};
+
+pub const PACKET_LEN: usize = 48;
+// LI 0, version 4, mode 3 (client).
+pub const CLIENT_HEADER: u8 = 0b00_100_011;
+const MODE_SERVER: u8 = 4;
+
+// Seconds from the NTP epoch (1900) to the Unix epoch (1970).
+const NTP_UNIX_OFFSET: u64 = 2_208_988_800;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NtpError {
+    /// Shorter than `PACKET_LEN`.
+    Truncated,
+    /// Not in server mode.
+    NotServer,
+    /// The server says its own clock is not synchronized.
+    Unsynchronized,
+    /// A timestamp of zero, or before 1970.
+    BadTimestamp,
+}
+
+impl From<WireError> for NtpError {
+    fn from(_: WireError) -> Self {
+        NtpError::Truncated
+    }
+}
+
+/// What a server reply says; times in Unix milliseconds.
+#[derive(Debug, Clone, Copy)]
+pub struct Reply {
+    pub stratum: u8,
+    pub precision: i8,
+    pub received_ms: u64,
+    pub transmit_ms: u64,
+}
+
+pub fn parse_response(packet: &[u8]) -> Result<Reply, NtpError> {
+    if packet.len() < PACKET_LEN {
+        return Err(NtpError::Truncated);
+    }
+    let mut packet = ReadCursor::new(packet);
+    let header = packet.u8()?;
+    if header & 0b111 != MODE_SERVER {
+        return Err(NtpError::NotServer);
+    }
+    if header >> 6 == 3 {
+        return Err(NtpError::Unsynchronized);
+    }
+    let stratum = packet.u8()?;
+    // Poll interval.
+    packet.skip(1)?;
+    let precision = packet.u8()? as i8;
+    // Root delay and dispersion, reference ID, reference and origin
+    // timestamps.
+    packet.skip(28)?;
+    Ok(Reply {
+        stratum,
+        precision,
+        received_ms: timestamp_ms(packet.u32()?, packet.u32()?)?,
+        transmit_ms: timestamp_ms(packet.u32()?, packet.u32()?)?,
+    })
+}
+
+/// An NTP timestamp as Unix milliseconds.
+fn timestamp_ms(seconds: u32, fraction: u32) -> Result<u64, NtpError> {
+    if seconds == 0 && fraction == 0 {
+        return Err(NtpError::BadTimestamp);
+    }
+    let mut seconds = seconds as u64;
+    // Era 0 ends in 2036. Anything that would be before 1968, the top bit
+    // being clear, is from era 1 (RFC 4330, section 3).
+    if seconds & 0x8000_0000 == 0 {
+        seconds += 1 << 32;
+    }
+    let unix = seconds
+        .checked_sub(NTP_UNIX_OFFSET)
+        .ok_or(NtpError::BadTimestamp)?;
+    Ok(unix * 1000 + ((fraction as u64 * 1000) >> 32))
+}
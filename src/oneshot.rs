@@ -0,0 +1,80 @@
+// Single-shot measurement mode (`oneshot` feature): connect, make one HTTPS
+// request, print one result line, halt. A script on the UART waits for the
+// line, resets the board and repeats.
+//
+// The line is `ONESHOT ` followed by one JSON object, laid out in
+// src/oneshotline.rs.
+
+use embassy_time::{Duration, Timer as EmbassyTimer};
+use esp_println::println;
+use esp_wifi::wifi::WifiController;
+use heapless::String;
+
+use crate::diag;
+use crate::https::{self, Response, CIPHER_SUITE};
+use crate::oneshotline::{self, Completed, Outcome};
+use crate::station;
+use crate::NetStack;
+
+pub const URL: &str = match option_env!("ONESHOT_URL") {
+    Some(url) => url,
+    None => "https://www.google.com/",
+};
+
+/// Large enough for a typical page head; a longer response is truncated,
+/// which does not change the timings up to the first byte.
+const RESPONSE_LEN: usize = 1024;
+
+/// The whole flow once the network is up. Never returns.
+pub async fn run(stack: &NetStack, controller: &mut WifiController<'_>, ssid: &str) -> ! {
+    let mut response = [0u8; RESPONSE_LEN];
+    let result = https::get(stack, URL, &mut response).await;
+    // Measured afterwards so the scan does not disturb the request.
+    let rssi = station::rssi(controller, ssid).await;
+
+    match &result {
+        Ok(response) => report(URL, &Outcome::Completed(&completed(response)), rssi),
+        Err(e) => {
+            let outcome = Outcome::Failed {
+                phase: e.phase().as_str(),
+                error: diag::classify(e).as_str(),
+            };
+            report(URL, &outcome, rssi);
+        }
+    }
+
+    let _ = controller.disconnect().await;
+    halt().await
+}
+
+/// The line's account of `response`.
+fn completed(response: &Response) -> Completed {
+    let t = &response.timings;
+    Completed {
+        status: response.status,
+        cipher: CIPHER_SUITE,
+        dns_ms: t.dns_ms,
+        connect_ms: t.connect_ms,
+        handshake_ms: t.handshake_ms,
+        first_byte_ms: t.first_byte_ms,
+        total_ms: t.total_ms,
+        bytes: response.len,
+    }
+}
+
+/// Prints the result line, complete or not at all.
+pub fn report(url: &str, outcome: &Outcome<'_>, rssi: Option<i8>) {
+    let mut line: String<512> = String::new();
+    if oneshotline::write_result(&mut line, url, outcome, rssi).is_err() {
+        line.clear();
+        let _ = oneshotline::write_url_too_long(&mut line);
+    }
+    println!("ONESHOT {}", line);
+}
+
+/// Parks the main task; the script resets the board for the next run.
+async fn halt() -> ! {
+    loop {
+        EmbassyTimer::after(Duration::from_secs(3600)).await;
+    }
+}
@@ -0,0 +1,105 @@
+// The result line of the single-shot mode (src/oneshot.rs), apart from the
+// request it reports on, so that its format can be checked off the device.
+//
+// The line is `ONESHOT ` followed by one JSON object, so it can be picked out
+// of the boot log with a prefix match. A completed request, whatever its
+// status code:
+//
+//     ONESHOT {"v":1,"ok":true,"url":"https://www.google.com/","status":200,
+//       "cipher":"TLS_AES_128_GCM_SHA256","dns_ms":41,"connect_ms":38,
+//       "handshake_ms":1520,"first_byte_ms":95,"total_ms":1694,"bytes":1024,
+//       "rssi":-58,"heap":null}
+//
+// (wrapped here; on the wire it is a single line). A failed one reports where
+// and why instead of a status and timings:
+//
+//     ONESHOT {"v":1,"ok":false,"url":"https://www.google.com/",
+//       "phase":"handshake","error":"tls_alert","rssi":-58,"heap":null}
+//
+// `phase` is one of `wifi`, `url`, `dns`, `connect`, `handshake`, `request`,
+// `response`; `error` is the `diag::Cause` as snake case, `not_connected`
+// for `wifi`, or `url_too_long` (with `"url":null`) if the URL did not fit
+// the line. `rssi` is in dBm and `null` when it could not be read. `heap`
+// is always `null`: the firmware has no allocator, and the field is there so
+// the format does not change if it ever gets one. Keys keep their order and
+// are only ever added; `v` goes up when one is removed or changes meaning.
+
+use core::fmt::{self, Write};
+
+use crate::codec;
+
+pub const FORMAT_VERSION: u32 = 1;
+
+/// What a completed request's fields say, timings in milliseconds.
+pub struct Completed {
+    pub status: u16,
+    pub cipher: &'static str,
+    pub dns_ms: u64,
+    pub connect_ms: u64,
+    pub handshake_ms: u64,
+    pub first_byte_ms: u64,
+    pub total_ms: u64,
+    /// Of the response, as far as it was read.
+    pub bytes: usize,
+}
+
+pub enum Outcome<'a> {
+    Completed(&'a Completed),
+    /// `phase` and `error` as the line spells them.
+    Failed {
+        phase: &'static str,
+        error: &'static str,
+    },
+    /// Wi-Fi never came up, so there was no request.
+    NoNetwork,
+}
+
+/// Writes the JSON object (without the `ONESHOT ` prefix or a newline).
+pub fn write_result<W: Write>(
+    out: &mut W,
+    url: &str,
+    outcome: &Outcome<'_>,
+    rssi: Option<i8>,
+) -> fmt::Result {
+    let ok = matches!(outcome, Outcome::Completed(_));
+    write!(out, "{{\"v\":{},\"ok\":{},\"url\":", FORMAT_VERSION, ok)?;
+    codec::write_json_str(out, url)?;
+
+    match outcome {
+        Outcome::Completed(c) => write!(
+            out,
+            ",\"status\":{},\"cipher\":\"{}\",\"dns_ms\":{},\"connect_ms\":{},\
+             \"handshake_ms\":{},\"first_byte_ms\":{},\"total_ms\":{},\"bytes\":{}",
+            c.status,
+            c.cipher,
+            c.dns_ms,
+            c.connect_ms,
+            c.handshake_ms,
+            c.first_byte_ms,
+            c.total_ms,
+            c.bytes
+        )?,
+        Outcome::Failed { phase, error } => {
+            write!(out, ",\"phase\":\"{}\",\"error\":\"{}\"", phase, error)?
+        }
+        Outcome::NoNetwork => out.write_str(",\"phase\":\"wifi\",\"error\":\"not_connected\"")?,
+    }
+
+    match rssi {
+        Some(rssi) => write!(out, ",\"rssi\":{}", rssi)?,
+        None => out.write_str(",\"rssi\":null")?,
+    }
+    out.write_str(",\"heap\":null}")
+}
+
+/// The line for a result `write_result` could not fit: only a URL of
+/// several hundred characters gets there, and the script still has to see
+/// a line to stop waiting.
+pub fn write_url_too_long<W: Write>(out: &mut W) -> fmt::Result {
+    write!(
+        out,
+        "{{\"v\":{},\"ok\":false,\"url\":null,\"phase\":\"url\",\"error\":\"url_too_long\",\
+         \"rssi\":null,\"heap\":null}}",
+        FORMAT_VERSION
+    )
+}
@@ -0,0 +1,203 @@
+// 1-Wire bus master, bit-banged on a single open-drain GPIO.
+//
+// esp-hal 0.18 has no `Flex` pin yet; `AnyOutputOpenDrain` gives us the same
+// thing for 1-Wire purposes: we only ever drive the line low or release it,
+// and the input buffer stays connected so the line can be sampled while
+// released. An external 4.7 kOhm pull-up is still required.
+//
+// Slot timings follow Maxim application note 126 ("standard speed").
+//
+// Only built with the `ds18b20` feature, for a board that has the sensors
+// on it: nothing in the firmware samples one, and no reading carries a
+// temperature yet.
+
+use core::cmp::Ordering;
+
+use esp_hal::delay::Delay;
+use esp_hal::gpio::AnyOutputOpenDrain;
+use heapless::Vec;
+
+// ROM commands
+pub const CMD_SEARCH_ROM: u8 = 0xF0;
+pub const CMD_MATCH_ROM: u8 = 0x55;
+pub const CMD_SKIP_ROM: u8 = 0xCC;
+
+pub struct OneWireBus<'a> {
+    pin: AnyOutputOpenDrain<'a>,
+    delay: Delay,
+}
+
+impl<'a> OneWireBus<'a> {
+    /// The pin must be configured open-drain with its initial level high
+    /// (released).
+    pub fn new(pin: AnyOutputOpenDrain<'a>, delay: Delay) -> Self {
+        Self { pin, delay }
+    }
+
+    /// Sends a reset pulse and returns `true` if at least one device answered
+    /// with a presence pulse.
+    pub fn reset_and_presence(&mut self) -> bool {
+        self.pin.set_low();
+        self.delay.delay_micros(480);
+
+        let present = critical_section::with(|_| {
+            self.pin.set_high();
+            self.delay.delay_micros(70);
+            self.pin.is_low()
+        });
+
+        // Let the presence pulse finish before the next slot.
+        self.delay.delay_micros(410);
+        present
+    }
+
+    pub fn write_bit(&mut self, bit: bool) {
+        // The low phase of a slot is timing critical; Wi-Fi interrupts
+        // stretching it would turn a 1 into a 0.
+        critical_section::with(|_| {
+            self.pin.set_low();
+            if bit {
+                self.delay.delay_micros(6);
+                self.pin.set_high();
+                self.delay.delay_micros(64);
+            } else {
+                self.delay.delay_micros(60);
+                self.pin.set_high();
+                self.delay.delay_micros(10);
+            }
+        });
+    }
+
+    pub fn read_bit(&mut self) -> bool {
+        let bit = critical_section::with(|_| {
+            self.pin.set_low();
+            self.delay.delay_micros(6);
+            self.pin.set_high();
+            self.delay.delay_micros(9);
+            self.pin.is_high()
+        });
+        self.delay.delay_micros(55);
+        bit
+    }
+
+    pub fn write_byte(&mut self, byte: u8) {
+        for i in 0..8 {
+            self.write_bit(byte & (1 << i) != 0);
+        }
+    }
+
+    pub fn read_byte(&mut self) -> u8 {
+        let mut byte = 0;
+        for i in 0..8 {
+            if self.read_bit() {
+                byte |= 1 << i;
+            }
+        }
+        byte
+    }
+
+    pub fn write_bytes(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.write_byte(byte);
+        }
+    }
+
+    pub fn read_bytes(&mut self, buf: &mut [u8]) {
+        for byte in buf.iter_mut() {
+            *byte = self.read_byte();
+        }
+    }
+
+    /// Addresses a single device by ROM code, or every device on the bus when
+    /// `rom` is `None`. Returns `false` if nothing answered the reset.
+    pub fn select(&mut self, rom: Option<&[u8; 8]>) -> bool {
+        if !self.reset_and_presence() {
+            return false;
+        }
+        match rom {
+            Some(rom) => {
+                self.write_byte(CMD_MATCH_ROM);
+                self.write_bytes(rom);
+            }
+            None => self.write_byte(CMD_SKIP_ROM),
+        }
+        true
+    }
+
+    /// Runs the ROM search algorithm (Maxim application note 187) and
+    /// collects every ROM code with a valid CRC. Devices beyond the capacity
+    /// of `roms` are ignored.
+    pub fn search<const N: usize>(&mut self, roms: &mut Vec<[u8; 8], N>) {
+        let mut rom = [0u8; 8];
+        let mut last_discrepancy = 0u8;
+        let mut last_device = false;
+
+        while !last_device && !roms.is_full() {
+            if !self.reset_and_presence() {
+                return;
+            }
+            self.write_byte(CMD_SEARCH_ROM);
+
+            let mut discrepancy = 0u8;
+            for bit_number in 1..=64u8 {
+                let byte = ((bit_number - 1) / 8) as usize;
+                let mask = 1u8 << ((bit_number - 1) % 8);
+
+                let bit = self.read_bit();
+                let complement = self.read_bit();
+
+                let direction = match (bit, complement) {
+                    // No device took part in this bit; the search is broken.
+                    (true, true) => return,
+                    (false, false) => {
+                        // Two devices disagree: take the branch chosen last
+                        // time, then the 1 branch once, then 0 from here on.
+                        let direction = match bit_number.cmp(&last_discrepancy) {
+                            Ordering::Equal => true,
+                            Ordering::Greater => false,
+                            Ordering::Less => rom[byte] & mask != 0,
+                        };
+                        if !direction {
+                            discrepancy = bit_number;
+                        }
+                        direction
+                    }
+                    (bit, _) => bit,
+                };
+
+                if direction {
+                    rom[byte] |= mask;
+                } else {
+                    rom[byte] &= !mask;
+                }
+                self.write_bit(direction);
+            }
+
+            last_discrepancy = discrepancy;
+            last_device = last_discrepancy == 0;
+
+            if crc8(&rom) == 0 && roms.push(rom).is_err() {
+                return;
+            }
+        }
+    }
+}
+
+/// Dallas/Maxim CRC-8 (polynomial x^8 + x^5 + x^4 + 1, LSB first) as described
+/// in application note 27. Running it over data followed by its CRC byte
+/// yields 0.
+pub fn crc8(data: &[u8]) -> u8 {
+    let mut crc = 0u8;
+    for &byte in data {
+        let mut byte = byte;
+        for _ in 0..8 {
+            let mix = (crc ^ byte) & 0x01;
+            crc >>= 1;
+            if mix != 0 {
+                crc ^= 0x8C;
+            }
+            byte >>= 1;
+        }
+    }
+    crc
+}
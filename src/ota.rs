@@ -0,0 +1,717 @@
+// Over-the-air update support.
+//
+// `download` streams an image into the slot that is not running, one
+// sector at a time, and selects it for a trial boot once its appended hash
+// checks out. An image served gzipped (`Content-Encoding: gzip`, or a body
+// starting with the gzip magic) is inflated on the way; tools/ota-image.sh
+// builds one. One sent `Transfer-Encoding: chunked` is refused.
+//
+// Updates are signed. Before anything is written, `<url>.sig` is fetched:
+//
+//     "OTAS" | image length (le u32) | major | minor | patch | 0 | SHA-256 of the image | Ed25519 signature
+//
+// with the signature over the first 44 bytes, made with the key whose public
+// half is `signature::OTA_PUBLIC_KEY`. An image too large for RAM cannot be
+// checked before it is written, so the manifest is, and the flashed image
+// must then match it. One that does not is erased again and never selected.
+//
+// Versions only go up, so that an old signed image with known holes cannot
+// be replayed: one older than `current_firmware_version` is refused unless
+// the settings say `allow_downgrade=true`. The version that counts is the
+// signed one in the manifest; an `X-Firmware-Version` header on the image
+// has to agree with it.
+//
+// The image may also come as a delta: a bsdiff patch against the running
+// image (see src/bspatch.rs), plain or gzipped, recognised by its magic.
+// The slot then gets the patched image, and the manifest describes that, so
+// a patch made against some other build fails the check like any other
+// wrong image. Patches are a protocol feature (src/protocol.rs): one from a
+// server that has not negotiated them is refused.
+//
+// A server can also send `X-Merkle-Root`: the root, in hex, of a Merkle tree
+// over the body as served, in 4 KB chunks (src/merkle.rs). The body is then
+// checked against it as soon as it ends, before the last sector is written
+// or the image read back, and one that does not match fails as `Corrupted`.
+// With the root alone a corrupt chunk still only shows at the end, and
+// cannot be told from the rest: finding it and fetching it again would take
+// the leaves as well, and a server that answers ranges.
+//
+// A download that goes quiet is asked for again from where it stopped, with
+// `Range`, and has to come back as a 206 from that byte on; src/otaprogress.rs
+// decides when, and reports the progress.
+
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::mutex::Mutex;
+use embassy_time::Instant;
+use esp_println::println;
+use esp_storage::{FlashStorage, FlashStorageError};
+use heapless::String;
+
+use crate::boot::{self, BootError, FlashPartition};
+use crate::bspatch::{self, PatchError, Patcher};
+use crate::clock;
+use crate::codec;
+use crate::flash::{self, SECTOR_SIZE};
+use crate::gzip::{self, GzipDecoder, GzipError, Inflater};
+use crate::http;
+use crate::https::{self, BodySink, FetchError, StreamError, Timings};
+use crate::integrity::{self, IntegrityError, HASH_LEN};
+use crate::kv;
+use crate::maintenance;
+use crate::merkle::MerkleTree;
+use crate::otaprogress::{self, OtaPhase, OtaProgress, ProgressReporter, Transfer, STALL_TIMEOUT};
+use crate::partition::PartitionEntry;
+use crate::protocol::{self, Feature};
+use crate::restart::{self, Reason};
+use crate::safemode;
+use crate::settings::{self, Settings, MAX_URL_LEN};
+use crate::signature::{self, SignatureError};
+use crate::state;
+use crate::NetStack;
+
+/// Where `ProgressReporter` puts what it reports.
+fn publish(progress: OtaProgress) {
+    state::update(|s| s.ota = Some(progress));
+}
+
+/// URL of an update that arrived outside the maintenance window.
+const KEY_DEFERRED: &str = "ota.deferred";
+
+/// Decides whether an update from `url` may start now. Outside the
+/// maintenance window the request is saved instead and shows up in
+/// `deferred_update`, also after a reboot; inside it any saved request is
+/// dropped, since this one replaces it. Never in safe mode.
+pub async fn may_start(url: &str, settings: &Settings) -> bool {
+    if safemode::active() {
+        println!("ota: not updating to {} in safe mode", url);
+        return false;
+    }
+    if maintenance::is_open(settings, &clock::System) {
+        let _ = kv::remove(KEY_DEFERRED).await;
+        return true;
+    }
+    println!(
+        "ota: update from {} deferred until the maintenance window",
+        url
+    );
+    if let Err(e) = kv::set(KEY_DEFERRED, url.as_bytes()).await {
+        println!("ota: could not save deferred update: {:?}", e);
+    }
+    false
+}
+
+/// URL of an update deferred by `may_start`, read into `buf`.
+pub async fn deferred_update(buf: &mut [u8]) -> Option<&str> {
+    let len = kv::get(KEY_DEFERRED, buf).await.ok()??;
+    core::str::from_utf8(&buf[..len]).ok()
+}
+
+#[derive(Debug)]
+pub enum OtaError {
+    Fetch(#[allow(dead_code)] FetchError),
+    /// The server answered with something other than 200.
+    Status(#[allow(dead_code)] u16),
+    Gzip(#[allow(dead_code)] GzipError),
+    Patch(#[allow(dead_code)] PatchError),
+    Flash(#[allow(dead_code)] FlashStorageError),
+    /// The image does not fit the slot.
+    TooLarge,
+    /// The body ended before `Content-Length` bytes.
+    Truncated,
+    /// The body does not match its `X-Merkle-Root`, or that is not a root.
+    Corrupted,
+    /// The partition table has no entry for the target slot.
+    NoSlot,
+    /// `<url>.sig` could not be fetched or is not a manifest.
+    NoManifest,
+    Signature(#[allow(dead_code)] SignatureError),
+    /// The image is not the one the manifest was signed for.
+    NotSigned,
+    DowngradeNotAllowed {
+        current: Version,
+        proposed: Version,
+    },
+    /// `X-Firmware-Version` is unreadable or not the manifest's version.
+    VersionMismatch,
+    /// A patch from a server that has not negotiated `Feature::DeltaOta`.
+    PatchNotNegotiated,
+    /// No data for `STALL_TIMEOUT`, also right after resuming.
+    Stalled,
+    /// A resumed download did not come back from the byte it stopped at.
+    NotResumable,
+    /// The image came `Transfer-Encoding: chunked`; it has to come as it
+    /// is.
+    Chunked,
+    Integrity(#[allow(dead_code)] IntegrityError),
+    Boot(#[allow(dead_code)] BootError),
+}
+
+impl From<FetchError> for OtaError {
+    fn from(e: FetchError) -> Self {
+        OtaError::Fetch(e)
+    }
+}
+
+impl From<GzipError> for OtaError {
+    fn from(e: GzipError) -> Self {
+        OtaError::Gzip(e)
+    }
+}
+
+impl From<PatchError> for OtaError {
+    fn from(e: PatchError) -> Self {
+        OtaError::Patch(e)
+    }
+}
+
+impl From<FlashStorageError> for OtaError {
+    fn from(e: FlashStorageError) -> Self {
+        OtaError::Flash(e)
+    }
+}
+
+impl From<IntegrityError> for OtaError {
+    fn from(e: IntegrityError) -> Self {
+        OtaError::Integrity(e)
+    }
+}
+
+impl From<SignatureError> for OtaError {
+    fn from(e: SignatureError) -> Self {
+        OtaError::Signature(e)
+    }
+}
+
+impl From<BootError> for OtaError {
+    fn from(e: BootError) -> Self {
+        OtaError::Boot(e)
+    }
+}
+
+impl From<StreamError<OtaError>> for OtaError {
+    fn from(e: StreamError<OtaError>) -> Self {
+        match e {
+            StreamError::Fetch(e) => OtaError::Fetch(e),
+            StreamError::Sink(e) => e,
+            StreamError::Stalled => OtaError::Stalled,
+            StreamError::Cut => OtaError::Truncated,
+            StreamError::Chunked => OtaError::Chunked,
+        }
+    }
+}
+
+#[repr(C, align(4))]
+struct Sector([u8; SECTOR_SIZE as usize]);
+
+/// About 47 KB, too much for the stack; one download at a time.
+struct Scratch {
+    inflater: Inflater,
+    sector: Sector,
+}
+
+static SCRATCH: Mutex<CriticalSectionRawMutex, Scratch> = Mutex::new(Scratch {
+    inflater: Inflater::new(),
+    sector: Sector([0; SECTOR_SIZE as usize]),
+});
+
+/// Runs an update from `url` if `may_start` allows it, resetting into the
+/// new image when it is in place. Returns only if the update was deferred or
+/// failed.
+pub async fn update(stack: &NetStack, url: &str) {
+    if !may_start(url, &settings::current()).await {
+        return;
+    }
+    match download(stack, url).await {
+        Ok(slot) => {
+            println!("ota: {:?} selected, restarting", slot);
+            restart::restart(Reason::Update);
+        }
+        Err(OtaError::DowngradeNotAllowed { current, proposed }) => println!(
+            "ota: update failed: {}.{}.{} is older than the running {}.{}.{}",
+            proposed.0, proposed.1, proposed.2, current.0, current.1, current.2
+        ),
+        Err(e) => println!("ota: update failed: {:?}", e),
+    }
+}
+
+/// Downloads the image at `url` into the slot that is not running and
+/// selects it for the next reset, on trial. Returns that slot; resetting is
+/// left to the caller.
+pub async fn download(stack: &NetStack, url: &str) -> Result<FlashPartition, OtaError> {
+    let target = boot::selected_partition()?
+        .unwrap_or(FlashPartition::Ota0)
+        .other();
+    let slot = target.entry().ok_or(OtaError::NoSlot)?;
+    let manifest = fetch_manifest(stack, url).await?;
+    signature::verify_firmware_signature(
+        manifest.signed(),
+        manifest.signature(),
+        signature::OTA_PUBLIC_KEY,
+    )?;
+    let version = manifest.version();
+    check_version(version, settings::current().allow_downgrade)?;
+    println!(
+        "ota: downloading {} ({}.{}.{}) to {:?} at {:#x}",
+        url, version.0, version.1, version.2, target, slot.offset
+    );
+
+    let patches = protocol::ensure_negotiated(stack, url)
+        .await
+        .supports(Feature::DeltaOta);
+
+    let mut scratch = SCRATCH.lock().await;
+    let Scratch { inflater, sector } = &mut *scratch;
+    let mut output = ImageOutput::new(
+        ImageWriter::new(slot.offset, slot.size, sector),
+        target.other().entry(),
+    );
+    output.patches = patches;
+    let mut sink = ImageSink {
+        output,
+        inflater: Some(inflater),
+        decoder: None,
+        reporter: None,
+        version,
+        expected: None,
+        received: 0,
+        merkle: None,
+    };
+    let result = fetch_into(stack, url, &mut sink).await;
+    // Set once a head was taken; without it only the reports are skipped.
+    let mut reporter = sink.reporter.take();
+    let result = match result {
+        Ok(len) => install(&manifest, &slot, target, len, reporter.as_mut()).await,
+        Err(e) => Err(e),
+    };
+    if let Some(reporter) = reporter {
+        reporter.finish(result.is_ok(), Instant::now());
+    }
+    result.map(|_| target)
+}
+
+/// Checks the `len` bytes in `slot` against `manifest` and the image's
+/// appended hash, then selects `target` for a trial boot.
+async fn install(
+    manifest: &Manifest,
+    slot: &PartitionEntry,
+    target: FlashPartition,
+    len: u32,
+    mut reporter: Option<&mut ProgressReporter>,
+) -> Result<(), OtaError> {
+    if let Some(reporter) = reporter.as_mut() {
+        reporter.set_phase(OtaPhase::Verifying, Instant::now());
+    }
+    if let Err(e) = check_against(manifest, slot.offset, len) {
+        // Not to be left bootable by some later switch.
+        let mut flash = FlashStorage::new();
+        let _ = flash::erase(&mut flash, slot.offset, slot.offset + SECTOR_SIZE).await;
+        return Err(e);
+    }
+    integrity::verify_partition_hash(slot)?;
+    if let Some(reporter) = reporter.as_mut() {
+        reporter.set_phase(OtaPhase::Switching, Instant::now());
+    }
+    boot::mark_update_pending(target).await?;
+    Ok(())
+}
+
+const MANIFEST_MAGIC: &[u8; 4] = b"OTAS";
+const MANIFEST_SIGNED_LEN: usize = 12 + HASH_LEN;
+const MANIFEST_LEN: usize = MANIFEST_SIGNED_LEN + 64;
+
+struct Manifest([u8; MANIFEST_LEN]);
+
+impl Manifest {
+    fn signed(&self) -> &[u8] {
+        &self.0[..MANIFEST_SIGNED_LEN]
+    }
+
+    fn image_len(&self) -> u32 {
+        u32::from_le_bytes(self.0[4..8].try_into().unwrap())
+    }
+
+    fn version(&self) -> Version {
+        (self.0[8], self.0[9], self.0[10])
+    }
+
+    fn image_hash(&self) -> &[u8] {
+        &self.0[12..MANIFEST_SIGNED_LEN]
+    }
+
+    fn signature(&self) -> &[u8; 64] {
+        self.0[MANIFEST_SIGNED_LEN..].try_into().unwrap()
+    }
+}
+
+async fn fetch_manifest(stack: &NetStack, url: &str) -> Result<Manifest, OtaError> {
+    let mut manifest_url: String<{ MAX_URL_LEN + 4 }> = String::new();
+    manifest_url
+        .push_str(url)
+        .and_then(|_| manifest_url.push_str(".sig"))
+        .map_err(|_| OtaError::NoManifest)?;
+    let mut response = [0u8; 512];
+    let result = https::get(stack, &manifest_url, &mut response).await?;
+    if result.status != 200 {
+        return Err(OtaError::NoManifest);
+    }
+    let body = result.body(&response);
+    if body.len() != MANIFEST_LEN || !body.starts_with(MANIFEST_MAGIC) {
+        return Err(OtaError::NoManifest);
+    }
+    Ok(Manifest(body.try_into().unwrap()))
+}
+
+/// `(major, minor, patch)`; compared as a tuple, that is semantic version
+/// order for releases.
+pub type Version = (u8, u8, u8);
+
+const CURRENT_VERSION: Version = parse_version_const(env!("CARGO_PKG_VERSION"));
+
+/// The version in Cargo.toml this firmware was built from.
+pub fn current_firmware_version() -> Version {
+    CURRENT_VERSION
+}
+
+/// Parses `MAJOR.MINOR.PATCH`, with an optional leading `v`. Pre-release and
+/// build suffixes (`-rc.1`, `+abc`) are ignored.
+pub fn parse_version(text: &str) -> Option<Version> {
+    let text = text.trim();
+    let text = text.strip_prefix('v').unwrap_or(text);
+    let core = text.split(['-', '+']).next()?;
+    let mut parts = core.split('.').map(|part| part.parse::<u8>().ok());
+    let version = (parts.next()??, parts.next()??, parts.next()??);
+    parts.next().is_none().then_some(version)
+}
+
+/// `parse_version` for the build's own version, which is known to be valid.
+const fn parse_version_const(text: &str) -> Version {
+    let bytes = text.as_bytes();
+    let mut parts = [0u32; 3];
+    let mut part = 0;
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'0'..=b'9' => parts[part] = parts[part] * 10 + (bytes[i] - b'0') as u32,
+            b'.' if part < 2 => part += 1,
+            _ => break,
+        }
+        i += 1;
+    }
+    assert!(parts[0] < 256 && parts[1] < 256 && parts[2] < 256);
+    (parts[0] as u8, parts[1] as u8, parts[2] as u8)
+}
+
+fn check_version(proposed: Version, allow_downgrade: bool) -> Result<(), OtaError> {
+    let current = current_firmware_version();
+    if proposed < current && !allow_downgrade {
+        return Err(OtaError::DowngradeNotAllowed { current, proposed });
+    }
+    Ok(())
+}
+
+/// Whether the `len` bytes written at `offset` are the image `manifest`
+/// describes.
+fn check_against(manifest: &Manifest, offset: u32, len: u32) -> Result<(), OtaError> {
+    if len != manifest.image_len() || integrity::hash_range(offset, len)? != manifest.image_hash() {
+        return Err(OtaError::NotSigned);
+    }
+    Ok(())
+}
+
+/// The download itself, up to the last byte in flash. Returns the image's
+/// length.
+async fn fetch_into(
+    stack: &NetStack,
+    url: &str,
+    sink: &mut ImageSink<'_>,
+) -> Result<u32, OtaError> {
+    let mut download = Download { stack, url, sink };
+    let timings = otaprogress::resume(&mut download, Instant::now)
+        .await?
+        .ok_or(OtaError::Stalled)?;
+    if let Some((tree, root)) = sink.merkle.take() {
+        if !integrity::constant_time_eq(&tree.root(), &root) {
+            return Err(OtaError::Corrupted);
+        }
+    }
+    if let Some(decoder) = &sink.decoder {
+        decoder.finish()?;
+    }
+    if sink
+        .expected
+        .is_some_and(|expected| sink.received < expected)
+    {
+        return Err(OtaError::Truncated);
+    }
+    let image = sink.output.finish().await?;
+    // How much the compression saves, to decide whether it is worth it.
+    println!(
+        "ota: {} bytes received for {} bytes of image ({}%), in {} ms",
+        sink.received,
+        image,
+        sink.received as u64 * 100 / image.max(1) as u64,
+        timings.total_ms
+    );
+    Ok(image)
+}
+
+struct ImageSink<'s> {
+    output: ImageOutput<'s>,
+    /// Until the first body bytes show whether the image is compressed.
+    inflater: Option<&'s mut Inflater>,
+    decoder: Option<GzipDecoder<'s>>,
+    reporter: Option<ProgressReporter>,
+    /// From the manifest.
+    version: Version,
+    expected: Option<u32>,
+    received: u32,
+    /// The body's tree so far and the root it must come to.
+    merkle: Option<(MerkleTree, [u8; HASH_LEN])>,
+}
+
+/// One attempt at the image, for `otaprogress::resume`.
+struct Download<'a, 's> {
+    stack: &'a NetStack,
+    url: &'a str,
+    sink: &'a mut ImageSink<'s>,
+}
+
+impl Transfer for Download<'_, '_> {
+    type Output = Timings;
+    type Error = OtaError;
+
+    async fn fetch(&mut self, from: u32) -> Result<Option<Timings>, OtaError> {
+        if from > 0 {
+            println!("ota: stalled, resuming from byte {}", from);
+        }
+        match https::get_streamed(self.stack, self.url, from, STALL_TIMEOUT, self.sink).await {
+            Ok(timings) => Ok(Some(timings)),
+            // Either way, there is more to ask for from where it stopped.
+            Err(StreamError::Stalled | StreamError::Cut) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn reporter(&mut self) -> Option<&mut ProgressReporter> {
+        self.sink.reporter.as_mut()
+    }
+}
+
+impl BodySink for ImageSink<'_> {
+    type Error = OtaError;
+
+    async fn head(&mut self, status: u16, head: &str) -> Result<(), OtaError> {
+        if self.reporter.is_some() {
+            return self.check_resumed(status, head);
+        }
+        if status != 200 {
+            return Err(OtaError::Status(status));
+        }
+        if let Some(header) = http::header(head, "x-firmware-version") {
+            let proposed = parse_version(header).ok_or(OtaError::VersionMismatch)?;
+            check_version(proposed, settings::current().allow_downgrade)?;
+            if proposed != self.version {
+                return Err(OtaError::VersionMismatch);
+            }
+        }
+        if let Some(header) = http::header(head, "x-merkle-root") {
+            let mut root = [0u8; HASH_LEN];
+            match codec::hex_decode(header.as_bytes(), &mut root) {
+                Ok(decoded) if decoded.len() == HASH_LEN => {}
+                _ => return Err(OtaError::Corrupted),
+            }
+            self.merkle = Some((MerkleTree::new(), root));
+        }
+        let length = http::content_length(head).map_err(|_| FetchError::MalformedResponse)?;
+        self.expected = length.and_then(|len| u32::try_from(len).ok());
+        let gzipped = http::header(head, "content-encoding")
+            .is_some_and(|encoding| encoding.eq_ignore_ascii_case("gzip"));
+        if gzipped {
+            self.start_inflating();
+        }
+        self.reporter = Some(ProgressReporter::start(
+            self.expected,
+            Instant::now(),
+            publish,
+        ));
+        Ok(())
+    }
+
+    async fn body(&mut self, data: &[u8]) -> Result<(), OtaError> {
+        // Servers that store the file gzipped do not always say so.
+        if self.received == 0 && self.decoder.is_none() && data.starts_with(&gzip::MAGIC) {
+            self.start_inflating();
+        }
+        self.received = self.received.saturating_add(data.len() as u32);
+        if let Some((tree, _)) = &mut self.merkle {
+            tree.update(data);
+        }
+        if let Some(reporter) = &mut self.reporter {
+            reporter.advance(data.len(), Instant::now());
+        }
+
+        let Some(decoder) = &mut self.decoder else {
+            return self.output.write(data).await;
+        };
+        let mut input = data;
+        loop {
+            let (used, out) = decoder.step(input)?;
+            let idle = used == 0 && out.is_empty();
+            self.output.write(out).await?;
+            input = &input[used..];
+            if idle {
+                return Ok(());
+            }
+        }
+    }
+}
+
+impl ImageSink<'_> {
+    /// The head of a response to a `Range` request for the rest of the
+    /// body, which has to be just that: the rest, from where it stopped.
+    fn check_resumed(&self, status: u16, head: &str) -> Result<(), OtaError> {
+        if status != 206 {
+            return Err(OtaError::Status(status));
+        }
+        let start = http::header(head, "content-range")
+            .and_then(|range| range.strip_prefix("bytes "))
+            .and_then(|range| range.split_once('-'))
+            .map(|(start, _)| start.trim());
+        match start {
+            Some(start)
+                if start.bytes().all(|b| b.is_ascii_digit())
+                    && start.parse() == Ok(self.received) =>
+            {
+                Ok(())
+            }
+            _ => Err(OtaError::NotResumable),
+        }
+    }
+
+    fn start_inflating(&mut self) {
+        if let Some(inflater) = self.inflater.take() {
+            self.decoder = Some(GzipDecoder::new(inflater));
+        }
+    }
+}
+
+/// Where the image stream goes: straight to the writer, or through a
+/// patcher if its first bytes say it is a patch.
+struct ImageOutput<'s> {
+    writer: ImageWriter<'s>,
+    /// The running image, which a patch applies to.
+    base: Option<PartitionEntry>,
+    /// Whether a patch is taken at all.
+    patches: bool,
+    patcher: Option<Patcher>,
+    started: bool,
+}
+
+impl<'s> ImageOutput<'s> {
+    fn new(writer: ImageWriter<'s>, base: Option<PartitionEntry>) -> Self {
+        Self {
+            writer,
+            base,
+            patches: true,
+            patcher: None,
+            started: false,
+        }
+    }
+
+    async fn write(&mut self, mut data: &[u8]) -> Result<(), OtaError> {
+        if !self.started && !data.is_empty() {
+            self.started = true;
+            if data.starts_with(bspatch::MAGIC) {
+                if !self.patches {
+                    return Err(OtaError::PatchNotNegotiated);
+                }
+                let base = self.base.as_ref().ok_or(OtaError::NoSlot)?;
+                println!("ota: applying a patch to the image at {:#x}", base.offset);
+                self.patcher = Some(Patcher::new(base));
+            }
+        }
+        let Some(patcher) = &mut self.patcher else {
+            return self.writer.write(data).await;
+        };
+        loop {
+            let (used, out) = patcher.step(data)?;
+            let idle = used == 0 && out.is_empty();
+            self.writer.write(out).await?;
+            data = &data[used..];
+            if idle {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Writes what is left and returns the image's length.
+    async fn finish(&mut self) -> Result<u32, OtaError> {
+        if let Some(patcher) = &self.patcher {
+            patcher.finish()?;
+        }
+        self.writer.finish().await
+    }
+}
+
+/// Collects the image a sector at a time and writes each full one to the
+/// slot, erasing it first.
+struct ImageWriter<'s> {
+    flash: FlashStorage,
+    start: u32,
+    size: u32,
+    /// Bytes of the slot written so far, a whole number of sectors.
+    done: u32,
+    sector: &'s mut Sector,
+    fill: usize,
+}
+
+impl<'s> ImageWriter<'s> {
+    fn new(start: u32, size: u32, sector: &'s mut Sector) -> Self {
+        Self {
+            flash: FlashStorage::new(),
+            start,
+            size,
+            done: 0,
+            sector,
+            fill: 0,
+        }
+    }
+
+    async fn write(&mut self, mut data: &[u8]) -> Result<(), OtaError> {
+        while !data.is_empty() {
+            let n = (self.sector.0.len() - self.fill).min(data.len());
+            self.sector.0[self.fill..self.fill + n].copy_from_slice(&data[..n]);
+            self.fill += n;
+            data = &data[n..];
+            if self.fill == self.sector.0.len() {
+                self.flush().await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes what is left and returns the image's length.
+    async fn finish(&mut self) -> Result<u32, OtaError> {
+        let len = self.done + self.fill as u32;
+        self.flush().await?;
+        Ok(len)
+    }
+
+    async fn flush(&mut self) -> Result<(), OtaError> {
+        if self.fill == 0 {
+            return Ok(());
+        }
+        if self.done + SECTOR_SIZE > self.size {
+            return Err(OtaError::TooLarge);
+        }
+        // Programs are whole words; pad with what erased flash reads as.
+        let len = self.fill.next_multiple_of(4);
+        self.sector.0[self.fill..len].fill(0xFF);
+        let at = self.start + self.done;
+        flash::erase(&mut self.flash, at, at + SECTOR_SIZE).await?;
+        flash::write(&mut self.flash, at, &self.sector.0[..len]).await?;
+        self.done += SECTOR_SIZE;
+        self.fill = 0;
+        Ok(())
+    }
+}
@@ -0,0 +1,209 @@
+// The progress of an update's download, as `AppState` and the console show
+// it, and the resuming of one that stalls, apart from the network and the
+// flash (src/ota.rs has those).
+//
+// Progress is counted in body bytes as the server sends them, compressed
+// or not, since that is what `Content-Length` counts and what a `Range`
+// request asks for. It is published rate limited: at most every
+// `MIN_PUBLISH_INTERVAL`, and then only for `PUBLISH_STEP_PERCENT` more,
+// or every `UNKNOWN_SIZE_PUBLISH_INTERVAL` without a size.
+//
+// A body that goes `STALL_TIMEOUT` without a byte is `Stalled`, and
+// `resume` asks for the rest of it, from `bytes_done` on. It keeps doing
+// so for as long as the attempt before brought something: one that stalls
+// without a byte ends the download.
+
+use embassy_time::{Duration, Instant};
+
+/// No progress publish more often than this, however fast bytes arrive.
+const MIN_PUBLISH_INTERVAL: Duration = Duration::from_millis(500);
+
+/// With a known size, publish every time this many percent have passed.
+const PUBLISH_STEP_PERCENT: u8 = 2;
+
+/// With an unknown size there is no percentage, so publish at most this often.
+const UNKNOWN_SIZE_PUBLISH_INTERVAL: Duration = Duration::from_secs(2);
+
+/// A download without a single new byte for this long counts as stalled.
+pub const STALL_TIMEOUT: Duration = Duration::from_secs(20);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OtaPhase {
+    /// Written to the slot as it arrives.
+    Downloading,
+    Verifying,
+    Switching,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OtaState {
+    Running,
+    /// No data for `STALL_TIMEOUT`; the downloader is expected to resume.
+    Stalled,
+    Done,
+    Failed,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OtaProgress {
+    pub state: OtaState,
+    pub phase: OtaPhase,
+    pub bytes_done: u32,
+    /// `None` when the server sent no `Content-Length`.
+    pub total: Option<u32>,
+}
+
+impl OtaProgress {
+    pub fn percent(&self) -> Option<u8> {
+        match self.total {
+            Some(0) => Some(100),
+            Some(total) => {
+                let done = self.bytes_done.min(total) as u64;
+                Some((done * 100 / total as u64) as u8)
+            }
+            None => None,
+        }
+    }
+}
+
+/// Tracks the progress of one update and hands it to `publish`, rate
+/// limited so that reporting costs nothing next to the download itself.
+///
+/// All methods take the current time so the download loop decides when
+/// "now" is, which also keeps this free of any clock of its own.
+pub struct ProgressReporter {
+    progress: OtaProgress,
+    published: OtaProgress,
+    published_at: Instant,
+    last_data_at: Instant,
+    publish: fn(OtaProgress),
+}
+
+impl ProgressReporter {
+    pub fn start(total: Option<u32>, now: Instant, publish: fn(OtaProgress)) -> Self {
+        let progress = OtaProgress {
+            state: OtaState::Running,
+            phase: OtaPhase::Downloading,
+            bytes_done: 0,
+            total,
+        };
+        publish(progress);
+        Self {
+            progress,
+            published: progress,
+            published_at: now,
+            last_data_at: now,
+            publish,
+        }
+    }
+
+    pub fn progress(&self) -> OtaProgress {
+        self.progress
+    }
+
+    /// Records `bytes` more bytes of the body.
+    pub fn advance(&mut self, bytes: usize, now: Instant) {
+        if bytes == 0 {
+            return;
+        }
+        self.progress.bytes_done = self.progress.bytes_done.saturating_add(bytes as u32);
+        self.last_data_at = now;
+        if self.progress.state == OtaState::Stalled {
+            self.progress.state = OtaState::Running;
+        }
+        self.maybe_publish(now);
+    }
+
+    /// Moves to the next phase. `bytes_done` stays what was downloaded:
+    /// the later phases read nothing more from the server.
+    pub fn set_phase(&mut self, phase: OtaPhase, now: Instant) {
+        self.progress.phase = phase;
+        self.last_data_at = now;
+        self.publish(now);
+    }
+
+    /// Returns `true` the first time the download has gone `STALL_TIMEOUT`
+    /// without data, after which the caller should restart it from
+    /// `progress().bytes_done`.
+    pub fn check_stall(&mut self, now: Instant) -> bool {
+        if self.progress.state != OtaState::Running
+            || now.saturating_duration_since(self.last_data_at) < STALL_TIMEOUT
+        {
+            return false;
+        }
+        self.progress.state = OtaState::Stalled;
+        self.publish(now);
+        true
+    }
+
+    pub fn finish(mut self, success: bool, now: Instant) {
+        self.progress.state = if success {
+            OtaState::Done
+        } else {
+            OtaState::Failed
+        };
+        self.publish(now);
+    }
+
+    fn maybe_publish(&mut self, now: Instant) {
+        let since = now.saturating_duration_since(self.published_at);
+        if since < MIN_PUBLISH_INTERVAL {
+            return;
+        }
+
+        let due = match (self.progress.percent(), self.published.percent()) {
+            (Some(current), Some(published)) => {
+                current >= published.saturating_add(PUBLISH_STEP_PERCENT)
+                    || (current == 100 && published < 100)
+            }
+            _ => since >= UNKNOWN_SIZE_PUBLISH_INTERVAL,
+        };
+
+        if due || self.progress.state != self.published.state {
+            self.publish(now);
+        }
+    }
+
+    fn publish(&mut self, now: Instant) {
+        self.published = self.progress;
+        self.published_at = now;
+        (self.publish)(self.progress);
+    }
+}
+
+/// A body fetched in attempts, each from a byte offset on, for `resume`.
+// Awaited on the one executor only, so nothing needs the future `Send`.
+#[allow(async_fn_in_trait)]
+pub trait Transfer {
+    type Output;
+    type Error;
+
+    /// Fetches the body from byte `from` on, reporting what arrives.
+    /// `Ok(None)` if it stalled: `STALL_TIMEOUT` went by without data.
+    async fn fetch(&mut self, from: u32) -> Result<Option<Self::Output>, Self::Error>;
+
+    /// Once a response has given the body's size.
+    fn reporter(&mut self) -> Option<&mut ProgressReporter>;
+}
+
+/// Runs `transfer` from the start, and again from where it got to each time
+/// it stalls after bringing new bytes. `Ok(None)` if an attempt stalls
+/// without any.
+pub async fn resume<T: Transfer>(
+    transfer: &mut T,
+    now: impl Fn() -> Instant,
+) -> Result<Option<T::Output>, T::Error> {
+    let mut from = 0;
+    loop {
+        if let Some(output) = transfer.fetch(from).await? {
+            return Ok(Some(output));
+        }
+        let Some(reporter) = transfer.reporter() else {
+            return Ok(None);
+        };
+        if !reporter.check_stall(now()) {
+            return Ok(None);
+        }
+        from = reporter.progress().bytes_done;
+    }
+}
@@ -0,0 +1,130 @@
+// Reader for the partition table the bootloader uses, at 0x8000.
+//
+// The table is a run of 32-byte entries:
+//
+//     magic 0x50AA (le) | type | subtype | offset (le) | size (le) | label[16] | flags (le)
+//
+// followed by an entry with magic 0xEBEB whose last 16 bytes are the MD5 of
+// every entry before it, and then erased flash. The table is read one entry
+// at a time so it never has to fit on the stack.
+
+use embedded_storage::nor_flash::ReadNorFlash;
+use esp_hal::rom::md5;
+use esp_println::println;
+use esp_storage::{FlashStorage, FlashStorageError};
+use heapless::{String, Vec};
+
+const TABLE_START: u32 = 0x8000;
+/// Space the bootloader reserves for the table, checksum included.
+const TABLE_MAX_LEN: u32 = 0xC00;
+const ENTRY_LEN: usize = 32;
+
+const MAGIC_ENTRY: [u8; 2] = [0xAA, 0x50];
+const MAGIC_MD5: [u8; 2] = [0xEB, 0xEB];
+
+const TYPE_APP: u8 = 0x00;
+const SUBTYPE_OTA_FIRST: u8 = 0x10;
+const SUBTYPE_OTA_LAST: u8 = 0x1F;
+
+/// Entries kept; the bootloader's own tables rarely have more than eight.
+pub const MAX_PARTITIONS: usize = 16;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PartitionEntry {
+    pub name: String<16>,
+    pub partition_type: u8,
+    pub subtype: u8,
+    pub offset: u32,
+    pub size: u32,
+}
+
+impl PartitionEntry {
+    /// An application slot an update can go to (`ota_0` to `ota_15`).
+    pub fn is_ota_slot(&self) -> bool {
+        self.partition_type == TYPE_APP
+            && (SUBTYPE_OTA_FIRST..=SUBTYPE_OTA_LAST).contains(&self.subtype)
+    }
+}
+
+#[derive(Debug)]
+pub enum PtError {
+    Flash(#[allow(dead_code)] FlashStorageError),
+    /// Entry at this index has neither magic.
+    BadEntry(#[allow(dead_code)] usize),
+    /// The table ends without an MD5 entry.
+    NoChecksum,
+    ChecksumMismatch,
+    /// More than `MAX_PARTITIONS` entries.
+    TooMany,
+}
+
+impl From<FlashStorageError> for PtError {
+    fn from(e: FlashStorageError) -> Self {
+        PtError::Flash(e)
+    }
+}
+
+/// All entries, in table order, after checking them against the MD5 entry.
+pub fn read_partition_table() -> Result<Vec<PartitionEntry, MAX_PARTITIONS>, PtError> {
+    let mut flash = FlashStorage::new();
+    let mut entries = Vec::new();
+    let mut digest = md5::Context::new();
+
+    for index in 0..(TABLE_MAX_LEN as usize / ENTRY_LEN) {
+        let mut raw = Entry([0; ENTRY_LEN]);
+        flash.read(TABLE_START + (index * ENTRY_LEN) as u32, &mut raw.0)?;
+        let raw = &raw.0;
+
+        match [raw[0], raw[1]] {
+            MAGIC_ENTRY => {
+                entries
+                    .push(parse(raw).ok_or(PtError::BadEntry(index))?)
+                    .map_err(|_| PtError::TooMany)?;
+                digest.consume(raw);
+            }
+            MAGIC_MD5 => {
+                return if digest.compute().0 == raw[16..32] {
+                    Ok(entries)
+                } else {
+                    Err(PtError::ChecksumMismatch)
+                };
+            }
+            [0xFF, 0xFF] => return Err(PtError::NoChecksum),
+            _ => return Err(PtError::BadEntry(index)),
+        }
+    }
+    Err(PtError::NoChecksum)
+}
+
+/// The first two application slots, for the update path. Empty if the
+/// table cannot be read; the reason is printed.
+pub fn find_ota_partitions() -> Vec<PartitionEntry, 2> {
+    let mut slots = Vec::new();
+    match read_partition_table() {
+        Ok(entries) => {
+            for entry in entries.into_iter().filter(PartitionEntry::is_ota_slot) {
+                if slots.push(entry).is_err() {
+                    break;
+                }
+            }
+        }
+        Err(e) => println!("partition: table unreadable: {:?}", e),
+    }
+    slots
+}
+
+fn parse(raw: &[u8]) -> Option<PartitionEntry> {
+    let label = &raw[12..28];
+    let len = label.iter().position(|&b| b == 0).unwrap_or(label.len());
+    let name = core::str::from_utf8(&label[..len]).ok()?;
+    Some(PartitionEntry {
+        name: String::try_from(name).ok()?,
+        partition_type: raw[2],
+        subtype: raw[3],
+        offset: u32::from_le_bytes(raw[4..8].try_into().unwrap()),
+        size: u32::from_le_bytes(raw[8..12].try_into().unwrap()),
+    })
+}
+
+#[repr(C, align(4))]
+struct Entry([u8; ENTRY_LEN]);
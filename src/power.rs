@@ -0,0 +1,91 @@
+// Battery state from the PMIC, polled in the background so the uploader can
+// report it and back off when the battery runs low. Going critically low
+// raises an alert, which the uploader sends ahead of any backlog
+// (src/priority.rs).
+//
+// The uploader takes whatever the latest status is when it builds a
+// reading. The cell is only ever held for a copy in or out, so the task
+// polling the PMIC never waits for one in the middle of an upload, and a
+// queue between them would only keep the samples a reading skips.
+
+use core::cell::Cell;
+
+use critical_section::Mutex;
+use embassy_time::{Duration, Timer as EmbassyTimer};
+use esp_println::println;
+
+use crate::canary;
+use crate::clock;
+use crate::ip5306::Ip5306;
+use crate::priority::{self, Alert};
+
+/// Below this the uploader stretches its interval by `LOW_BATTERY_FACTOR`.
+pub const LOW_BATTERY_MV: u32 = 3400;
+pub const LOW_BATTERY_FACTOR: u32 = 10;
+/// Below this, shortly before the PMIC cuts out, `BatteryCritical` is raised.
+pub const CRITICAL_BATTERY_MV: u32 = 3300;
+
+const POLL_INTERVAL: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PowerStatus {
+    pub battery_mv: u32,
+    pub charging: bool,
+}
+
+impl PowerStatus {
+    /// Charging counts as not low whatever the gauge says.
+    pub fn is_low(&self) -> bool {
+        !self.charging && self.battery_mv < LOW_BATTERY_MV
+    }
+
+    pub fn is_critical(&self) -> bool {
+        !self.charging && self.battery_mv < CRITICAL_BATTERY_MV
+    }
+}
+
+static STATUS: Mutex<Cell<Option<PowerStatus>>> = Mutex::new(Cell::new(None));
+
+/// Latest reading, or `None` if the PMIC has not answered yet.
+pub fn status() -> Option<PowerStatus> {
+    critical_section::with(|cs| STATUS.borrow(cs).get())
+}
+
+#[embassy_executor::task]
+pub async fn power_task(pmic: Ip5306<'static>) {
+    canary::tracked("power", run(pmic)).await
+}
+
+async fn run(mut pmic: Ip5306<'static>) -> ! {
+    loop {
+        // The board may simply have no IP5306; then there is never a
+        // reading and nothing backs off.
+        if let Some(status) = read(&mut pmic).await {
+            let was_low = self::status().map_or(false, |s| s.is_low());
+            if status.is_low() != was_low {
+                println!(
+                    "power: battery {} ({} mV)",
+                    if status.is_low() { "low" } else { "ok" },
+                    status.battery_mv
+                );
+            }
+            let was_critical = self::status().map_or(false, |s| s.is_critical());
+            if status.is_critical() && !was_critical {
+                println!("power: battery critical ({} mV)", status.battery_mv);
+                let alert = Alert::BatteryCritical {
+                    battery_mv: status.battery_mv,
+                };
+                priority::raise(alert, &clock::System);
+            }
+            critical_section::with(|cs| STATUS.borrow(cs).set(Some(status)));
+        }
+        EmbassyTimer::after(POLL_INTERVAL).await;
+    }
+}
+
+async fn read(pmic: &mut Ip5306<'_>) -> Option<PowerStatus> {
+    Some(PowerStatus {
+        battery_mv: pmic.battery_voltage_mv().await.ok()?,
+        charging: pmic.is_charging().await.ok()?,
+    })
+}
@@ -0,0 +1,220 @@
+// Two classes of upload: critical records and bulk telemetry.
+//
+// Bulk is the uploader's queue of readings, sent one at a time or in
+// batches (src/uploader.rs, src/batch.rs), which after a captive portal or
+// an outage can be a long backlog. A critical record, an alert such as the
+// battery going critically low (src/power.rs), is `raise`d into a queue of
+// its own and does not wait behind that: the uploader asks `next_slot`
+// before every request, and a due critical record takes the slot. So it
+// waits for at most the one bulk request in flight when it was raised,
+// however large that batch is. Between readings the uploader wakes for it
+// (`raised`) instead of waiting for the next interval.
+//
+// A critical record has its own budget of `CRITICAL_ATTEMPTS` tries,
+// `RETRY_DELAYS_MS` apart, and is dropped after that; bulk readings are
+// kept until the server has answered for them. A record the server refuses
+// for good is dropped at once, as a reading would be, and one it answers
+// 5xx, 408 or 429 counts as a failed try (src/uploadanswer.rs). Raising
+// an alert that is already queued does not queue it twice.
+//
+// The scheduling is pure: `CriticalQueue` takes the time as an argument,
+// in ms since boot, and the uploader owns the clock and the sending. How
+// long critical records take from being raised to being accepted is kept
+// apart from the request latencies (src/metrics.rs).
+//
+// In safe mode (src/safemode.rs) the uploader sends only heartbeats, and
+// alerts wait in the queue, the oldest dropped once it is full.
+
+use core::cell::RefCell;
+
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::signal::Signal;
+use heapless::Deque;
+
+use crate::clock::Clock;
+
+pub const CRITICAL_LEN: usize = 4;
+/// Tries a critical record gets, the first included.
+pub const CRITICAL_ATTEMPTS: u8 = 3;
+/// Wait after the first and second failed try.
+const RETRY_DELAYS_MS: [u64; CRITICAL_ATTEMPTS as usize - 1] = [5_000, 20_000];
+
+static QUEUE: Mutex<CriticalSectionRawMutex, RefCell<CriticalQueue>> =
+    Mutex::new(RefCell::new(CriticalQueue::new()));
+static RAISED: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Alert {
+    BatteryCritical { battery_mv: u32 },
+}
+
+impl Alert {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Alert::BatteryCritical { .. } => "battery_critical",
+        }
+    }
+
+    /// Queued at most once; values aside.
+    fn same_kind(&self, other: &Alert) -> bool {
+        core::mem::discriminant(self) == core::mem::discriminant(other)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Critical {
+    pub alert: Alert,
+    pub raised_ms: u64,
+    /// Tries so far.
+    attempts: u8,
+    /// Not to be tried before this.
+    due_ms: u64,
+}
+
+/// Who gets the next request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Slot {
+    Critical(Critical),
+    Bulk,
+}
+
+/// How a try at a critical record went.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    Accepted,
+    /// The server answered, but not with 2xx.
+    Refused,
+    /// No answer, or one cut short.
+    Failed,
+}
+
+/// What became of a critical record after a try.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Settled {
+    Delivered {
+        latency_ms: u64,
+    },
+    Dropped,
+    /// Still queued, and due again at this time.
+    Retry {
+        due_ms: u64,
+    },
+    /// Out of tries; dropped.
+    GaveUp,
+}
+
+pub struct CriticalQueue {
+    records: Deque<Critical, CRITICAL_LEN>,
+}
+
+impl CriticalQueue {
+    pub const fn new() -> Self {
+        Self {
+            records: Deque::new(),
+        }
+    }
+
+    /// Queues `alert`, due at once. Returns `false` if an alert of the
+    /// kind was already queued, which then stays as it was.
+    pub fn push(&mut self, alert: Alert, now_ms: u64) -> bool {
+        if self.records.iter().any(|r| r.alert.same_kind(&alert)) {
+            return false;
+        }
+        if self.records.is_full() {
+            self.records.pop_front();
+        }
+        let _ = self.records.push_back(Critical {
+            alert,
+            raised_ms: now_ms,
+            attempts: 0,
+            due_ms: now_ms,
+        });
+        true
+    }
+
+    /// When the next record is due; it may be already.
+    pub fn due_ms(&self) -> Option<u64> {
+        self.records.iter().map(|r| r.due_ms).min()
+    }
+
+    /// The next request's slot at `now_ms`: the oldest due critical record,
+    /// else bulk if it has anything to send.
+    pub fn next_slot(&self, now_ms: u64, bulk_ready: bool) -> Option<Slot> {
+        match self.records.iter().find(|r| r.due_ms <= now_ms) {
+            Some(&record) => Some(Slot::Critical(record)),
+            None => bulk_ready.then_some(Slot::Bulk),
+        }
+    }
+
+    /// Books a try at `tried`, which `next_slot` handed out. Should the
+    /// queue have dropped it meanwhile, that stands.
+    pub fn settle(&mut self, tried: &Critical, outcome: Outcome, now_ms: u64) -> Settled {
+        let Some((index, record)) = self
+            .records
+            .iter_mut()
+            .enumerate()
+            .find(|(_, r)| r.alert.same_kind(&tried.alert))
+        else {
+            return Settled::Dropped;
+        };
+        record.attempts += 1;
+        let raised_ms = record.raised_ms;
+        let settled = match outcome {
+            Outcome::Accepted => Settled::Delivered {
+                latency_ms: now_ms.saturating_sub(raised_ms),
+            },
+            Outcome::Refused => Settled::Dropped,
+            Outcome::Failed => match RETRY_DELAYS_MS.get(record.attempts as usize - 1) {
+                Some(&delay_ms) => {
+                    record.due_ms = now_ms + delay_ms;
+                    return Settled::Retry {
+                        due_ms: record.due_ms,
+                    };
+                }
+                None => Settled::GaveUp,
+            },
+        };
+        self.remove(index);
+        settled
+    }
+
+    fn remove(&mut self, index: usize) {
+        // Cannot overflow: what is put back is what was taken out.
+        for _ in 0..index {
+            if let Some(record) = self.records.pop_front() {
+                let _ = self.records.push_back(record);
+            }
+        }
+        self.records.pop_front();
+        for _ in index..self.records.len() {
+            if let Some(record) = self.records.pop_front() {
+                let _ = self.records.push_back(record);
+            }
+        }
+    }
+}
+
+impl Default for CriticalQueue {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Queues `alert` for the uploader, raised at `clock`'s now, and wakes it.
+pub fn raise(alert: Alert, clock: &impl Clock) {
+    let now_ms = clock.now_ms();
+    if QUEUE.lock(|queue| queue.borrow_mut().push(alert, now_ms)) {
+        RAISED.signal(());
+    }
+}
+
+/// Waits for the next `raise`.
+pub async fn raised() {
+    RAISED.wait().await
+}
+
+/// Runs `f` on the queue.
+pub fn with<R>(f: impl FnOnce(&mut CriticalQueue) -> R) -> R {
+    QUEUE.lock(|queue| f(&mut queue.borrow_mut()))
+}
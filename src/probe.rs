@@ -0,0 +1,61 @@
+// Variables for a debug probe to read while the firmware runs: with the
+// `probe` feature, `probe-rs` (or any debugger that can read memory) finds
+// each by its symbol in the ELF and reads it over the debug port, without a
+// breakpoint and without halting the CPU.
+//
+//     PROBE_RSSI           i8   dBm of the access point, 0 while not connected
+//     PROBE_HTTP_STATUS    u16  status of the last HTTPS response
+//     PROBE_READINGS_SENT  u32  readings the server has answered for
+//     PROBE_UPTIME_S       u32  seconds since boot
+//
+// `probe_variable!` defines one where it is set. The housekeeping task sets
+// the RSSI, the count and the uptime on every pass (`sample`), and the HTTPS
+// client the status as each response comes in. Without the feature the
+// macro expands to nothing and the statics do not exist.
+//
+// There is no RTT channel: rtt-target is not among the dependencies, and a
+// debugger reads a static without one. The log stays on the UART.
+
+/// `probe_variable!(NAME: type = value)` stores `value` in a static named
+/// `NAME` that the linker keeps under that name. An integer type no wider
+/// than 32 bits, so that a probe never reads half a store; one call site
+/// per name, or the link fails.
+#[cfg(feature = "probe")]
+macro_rules! probe_variable {
+    ($name:ident: $ty:ty = $value:expr) => {{
+        #[used]
+        #[no_mangle]
+        static mut $name: $ty = 0;
+        // Volatile: nothing in the firmware reads it back.
+        unsafe { core::ptr::addr_of_mut!($name).write_volatile($value) }
+    }};
+}
+
+#[cfg(not(feature = "probe"))]
+macro_rules! probe_variable {
+    ($name:ident: $ty:ty = $value:expr) => {};
+}
+
+pub(crate) use probe_variable;
+
+#[cfg(feature = "probe")]
+extern "C" {
+    fn esp_wifi_sta_get_rssi(rssi: *mut i32) -> i32;
+}
+
+/// Updates the variables that nothing else sets as they change.
+#[cfg(feature = "probe")]
+pub fn sample() {
+    let mut rssi = 0;
+    // Fails while the station is not associated.
+    if unsafe { esp_wifi_sta_get_rssi(&mut rssi) } != 0 {
+        rssi = 0;
+    }
+    probe_variable!(PROBE_RSSI: i8 = rssi as i8);
+    probe_variable!(
+        PROBE_READINGS_SENT: u32 = crate::uploader::queue_stats().dequeued
+    );
+    probe_variable!(
+        PROBE_UPTIME_S: u32 = embassy_time::Instant::now().as_secs() as u32
+    );
+}
@@ -0,0 +1,124 @@
+// Which version of the server protocol the server and this firmware share.
+//
+// `negotiate_protocol` asks `GET /api/negotiate` on the server, sending
+// `X-Client-Protocol: CLIENT_PROTOCOL`; the server answers with
+// `X-Server-Protocol: N`, and the lower of the two is what both speak. It is
+// kept in `NEGOTIATED` for the rest of the boot. 0 means nothing has been
+// negotiated yet: a server that predates negotiation, or one that was not
+// reachable, and anything behind a version stays off.
+//
+// Version 1 is delta updates: an OTA image may come as a bsdiff patch
+// (src/ota.rs). CBOR telemetry and device shadows would be 2 and 3, but
+// this firmware has neither, so it does not claim them.
+
+use core::fmt::Write as _;
+use core::sync::atomic::{AtomicU8, Ordering};
+
+use esp_println::println;
+use heapless::String;
+
+use crate::https::{self, FetchError};
+use crate::settings::MAX_URL_LEN;
+use crate::NetStack;
+
+pub const CLIENT_PROTOCOL: u8 = 1;
+
+const PATH: &str = "/api/negotiate";
+
+/// Only loaded and stored: the C3 has no atomic read-modify-write on bytes.
+static NEGOTIATED: AtomicU8 = AtomicU8::new(0);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ProtocolVersion(pub u8);
+
+/// Something the server has to know about before the device relies on it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Feature {
+    /// OTA images as bsdiff patches against the running image.
+    DeltaOta,
+}
+
+impl Feature {
+    /// The first protocol version that has it.
+    fn since(self) -> ProtocolVersion {
+        match self {
+            Feature::DeltaOta => ProtocolVersion(1),
+        }
+    }
+}
+
+impl ProtocolVersion {
+    pub fn supports(self, feature: Feature) -> bool {
+        self >= feature.since()
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum NegotiateError {
+    InvalidUrl,
+    Fetch(#[allow(dead_code)] FetchError),
+    /// The server answered with something other than 200.
+    Status(#[allow(dead_code)] u16),
+    /// No `X-Server-Protocol`, or not a number.
+    NoVersion,
+}
+
+impl From<FetchError> for NegotiateError {
+    fn from(e: FetchError) -> Self {
+        NegotiateError::Fetch(e)
+    }
+}
+
+/// The version negotiated so far this boot; 0 if none.
+pub fn negotiated() -> ProtocolVersion {
+    ProtocolVersion(NEGOTIATED.load(Ordering::Relaxed))
+}
+
+/// Agrees on a version with the server `server_url` is on, the origin
+/// only, and keeps it for `negotiated`.
+pub async fn negotiate_protocol(
+    stack: &NetStack,
+    server_url: &str,
+) -> Result<ProtocolVersion, NegotiateError> {
+    let server = https::parse_url(server_url).ok_or(NegotiateError::InvalidUrl)?;
+    let mut url: String<{ MAX_URL_LEN + 16 }> = String::new();
+    write!(url, "https://{}:{}{}", server.host, server.port, PATH)
+        .map_err(|_| NegotiateError::InvalidUrl)?;
+
+    let mut client_protocol: String<3> = String::new();
+    // Cannot fail: a u8 has at most three digits.
+    let _ = write!(client_protocol, "{}", CLIENT_PROTOCOL);
+    let headers = [("X-Client-Protocol", client_protocol.as_str())];
+    let mut response = [0u8; 512];
+    let result = https::get_with_headers(stack, &url, &headers, &mut response).await?;
+    if result.status != 200 {
+        return Err(NegotiateError::Status(result.status));
+    }
+    let server_protocol: u8 = result
+        .header(&response, "X-Server-Protocol")
+        .and_then(|value| value.parse().ok())
+        .ok_or(NegotiateError::NoVersion)?;
+
+    let version = ProtocolVersion(server_protocol.min(CLIENT_PROTOCOL));
+    NEGOTIATED.store(version.0, Ordering::Relaxed);
+    println!(
+        "protocol: server speaks {}, using {}",
+        server_protocol, version.0
+    );
+    Ok(version)
+}
+
+/// `negotiated`, asking the server first if nothing has been yet. A failed
+/// negotiation is logged and counts as 0, to be tried again next time.
+pub async fn ensure_negotiated(stack: &NetStack, server_url: &str) -> ProtocolVersion {
+    let version = negotiated();
+    if version.0 != 0 {
+        return version;
+    }
+    negotiate_protocol(stack, server_url)
+        .await
+        .unwrap_or_else(|e| {
+            println!("protocol: negotiation failed: {:?}", e);
+            ProtocolVersion(0)
+        })
+}
@@ -0,0 +1,96 @@
+// Counters for a bounded queue, to tell a pipeline that keeps up from one
+// that falls behind: how many items went in and came out, how many were
+// dropped to make room, and the fullest it has been.
+//
+// The owner of the queue calls `note_*` as it pushes and pops, one atomic
+// add each. The counts are totals since boot; the housekeeping task logs
+// what changed each minute, which gives the rates, and nothing in a minute
+// without any change.
+
+use core::fmt;
+
+use portable_atomic::{AtomicU32, Ordering};
+
+pub struct QueueStats {
+    capacity: u32,
+    enqueued: AtomicU32,
+    dequeued: AtomicU32,
+    dropped: AtomicU32,
+    len: AtomicU32,
+    peak: AtomicU32,
+}
+
+/// A copy of the counters.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Stats {
+    pub capacity: u32,
+    pub enqueued: u32,
+    pub dequeued: u32,
+    pub dropped: u32,
+    pub len: u32,
+    pub peak: u32,
+}
+
+impl QueueStats {
+    pub const fn new(capacity: usize) -> Self {
+        Self {
+            capacity: capacity as u32,
+            enqueued: AtomicU32::new(0),
+            dequeued: AtomicU32::new(0),
+            dropped: AtomicU32::new(0),
+            len: AtomicU32::new(0),
+            peak: AtomicU32::new(0),
+        }
+    }
+
+    /// An item went in; `len` is the queue's length after.
+    pub fn note_enqueued(&self, len: usize) {
+        self.enqueued.fetch_add(1, Ordering::Relaxed);
+        self.len.store(len as u32, Ordering::Relaxed);
+        self.peak.fetch_max(len as u32, Ordering::Relaxed);
+    }
+
+    /// `count` items came out, done with; `len` is the length after.
+    pub fn note_dequeued(&self, count: usize, len: usize) {
+        self.dequeued.fetch_add(count as u32, Ordering::Relaxed);
+        self.len.store(len as u32, Ordering::Relaxed);
+    }
+
+    /// The oldest item was thrown away to make room.
+    pub fn note_dropped(&self) {
+        self.dropped.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn stats(&self) -> Stats {
+        Stats {
+            capacity: self.capacity,
+            enqueued: self.enqueued.load(Ordering::Relaxed),
+            dequeued: self.dequeued.load(Ordering::Relaxed),
+            dropped: self.dropped.load(Ordering::Relaxed),
+            len: self.len.load(Ordering::Relaxed),
+            peak: self.peak.load(Ordering::Relaxed),
+        }
+    }
+}
+
+impl Stats {
+    /// What changed since `earlier`, with this one's levels.
+    pub fn since(&self, earlier: &Stats) -> Stats {
+        Stats {
+            enqueued: self.enqueued.wrapping_sub(earlier.enqueued),
+            dequeued: self.dequeued.wrapping_sub(earlier.dequeued),
+            dropped: self.dropped.wrapping_sub(earlier.dropped),
+            ..*self
+        }
+    }
+}
+
+impl fmt::Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} in, {} out, {} dropped; {} of {} queued, at most {}",
+            self.enqueued, self.dequeued, self.dropped, self.len, self.capacity, self.peak
+        )
+    }
+}
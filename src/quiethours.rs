@@ -0,0 +1,47 @@
+// Quiet hours: the daily UTC hours in which the uploader sends no readings.
+//
+// They are taken and queued as behind a captive portal, the oldest dropped
+// once the queue is full, and go out once the hours are over. Critical
+// records, the config poll and a dry run are not held back, nor are
+// heartbeats in safe mode. Without a wall clock there are no quiet hours.
+//
+// `start_hour` is inclusive and `end_hour` exclusive; `22-6` wraps past
+// midnight, and `start_hour == end_hour` is never quiet. The settings take
+// `quiet_hours=<start>-<end>` or `off` (src/settings.rs).
+
+const MS_PER_HOUR: u64 = 60 * 60 * 1000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QuietHours {
+    pub start_hour: u8,
+    pub end_hour: u8,
+}
+
+impl QuietHours {
+    /// Parses `<start>-<end>`, hours from 0 to 23.
+    pub fn parse(text: &str) -> Option<Self> {
+        let (start, end) = text.split_once('-')?;
+        let hours = Self {
+            start_hour: start.trim().parse().ok()?,
+            end_hour: end.trim().parse().ok()?,
+        };
+        (hours.start_hour <= 23 && hours.end_hour <= 23).then_some(hours)
+    }
+
+    pub fn contains(&self, hour_of_day: u8) -> bool {
+        let (start, end) = (self.start_hour, self.end_hour);
+        if start <= end {
+            (start..end).contains(&hour_of_day)
+        } else {
+            hour_of_day >= start || hour_of_day < end
+        }
+    }
+}
+
+/// The pure decision: whether readings are held back at `now_unix_ms`.
+pub fn holds(quiet: Option<QuietHours>, now_unix_ms: Option<u64>) -> bool {
+    match (quiet, now_unix_ms) {
+        (Some(quiet), Some(ms)) => quiet.contains(((ms / MS_PER_HOUR) % 24) as u8),
+        _ => false,
+    }
+}
@@ -0,0 +1,153 @@
+// A record of the last request that failed, byte for byte, for bug reports:
+// "it fails against my server" is hard to act on without what went over the
+// wire.
+//
+// The HTTPS client hands over each request that fails, or is answered 400
+// or above (`record`, src/https.rs); the console's `lastfail` prints the
+// last one as a line of base64 to paste into an issue. Decoded it reads:
+//
+//     lastfail v1 phase=response cause=- status=401
+//     request 183
+//     <the request head, 183 bytes>
+//     response 96
+//     <the first 96 bytes of the answer>
+//
+// `phase` and `cause` are the failure's (src/diag.rs); with an answer the
+// cause is `-`, and without one the status is. The response is whatever
+// had arrived when the request failed, as it came, often nothing. Up to
+// `MAX_HEAD_LEN` bytes of the head are kept and `MAX_RESPONSE_LEN` of the
+// answer; the request body is not, nor are streamed downloads recorded.
+//
+// Secrets are masked before a record is kept anywhere (`redact`), each byte
+// with `*`, so the lengths and offsets stay those of the wire. They are the
+// values of the settings' headers, which carry the credentials, and of the
+// URL's query. In the head each is masked where it stands, whatever its
+// length. Then, anywhere in the record, the answer included, each of them,
+// each word of a header's value, the user and password of a `Basic` one and
+// each query value decoded are masked wherever they occur, if at least
+// `MIN_SECRET_LEN` bytes long; a shorter one would blank out half the
+// record. Where the head or the answer is cut at its limit, the start of
+// any of them left at the cut is masked too, however short: the cut must
+// not leave a secret's first bytes in the record.
+//
+// The record's layout and the masking are in src/replayrecord.rs.
+//
+// The last record stays in RAM, and goes to the KV store under `lastfail`
+// at most once per `PERSIST_INTERVAL`, so that a server refusing every
+// upload does not wear out the flash. `lastfail` shows the one in RAM, or
+// else the one stored before the reset.
+
+use core::cell::{Cell, RefCell};
+use core::fmt::Write as _;
+
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_time::{Duration, Instant};
+use esp_println::println;
+use heapless::{String, Vec};
+
+use crate::codec;
+use crate::diag;
+use crate::headers::Headers;
+use crate::https::FetchError;
+use crate::kv;
+use crate::replayrecord::{self, Record, MAX_OUTCOME_LEN, MAX_RECORD_LEN};
+use crate::settings;
+
+const FORMAT_VERSION: u32 = 1;
+const KEY: &str = "lastfail";
+const PERSIST_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+static LAST: Mutex<CriticalSectionRawMutex, RefCell<Option<Record>>> =
+    Mutex::new(RefCell::new(None));
+static PERSISTED: Mutex<CriticalSectionRawMutex, Cell<Option<Instant>>> =
+    Mutex::new(Cell::new(None));
+
+/// The record of a request with `head` to `path`, which was answered or
+/// failed as `outcome` says and got `response` back, with the secrets of
+/// `headers` and of the query of `path` masked.
+pub fn build(
+    path: &str,
+    head: &[u8],
+    outcome: Result<u16, &FetchError>,
+    response: &[u8],
+    headers: &Headers,
+) -> Record {
+    let mut line: String<MAX_OUTCOME_LEN> = String::new();
+    // Cannot overflow: the longest phase and cause take 60 bytes with the
+    // rest.
+    let _ = match outcome {
+        Ok(status) => write!(
+            line,
+            "lastfail v{} phase=response cause=- status={}",
+            FORMAT_VERSION, status
+        ),
+        Err(e) => write!(
+            line,
+            "lastfail v{} phase={} cause={} status=-",
+            FORMAT_VERSION,
+            e.phase().as_str(),
+            diag::classify(e).as_str()
+        ),
+    };
+    replayrecord::build(&line, path, head, response, headers)
+}
+
+/// Keeps the record of a failed request, and stores it if the last one
+/// stored is old enough.
+pub async fn record(path: &str, head: &str, outcome: Result<u16, &FetchError>, response: &[u8]) {
+    let record = build(
+        path,
+        head.as_bytes(),
+        outcome,
+        response,
+        &settings::headers(),
+    );
+    LAST.lock(|last| *last.borrow_mut() = Some(record.clone()));
+
+    let due = PERSISTED.lock(|persisted| {
+        let due = persisted
+            .get()
+            .map_or(true, |at| at.elapsed() >= PERSIST_INTERVAL);
+        if due {
+            persisted.set(Some(Instant::now()));
+        }
+        due
+    });
+    if due {
+        if let Err(e) = kv::set(KEY, &record).await {
+            println!("replay: could not store the record: {:?}", e);
+        }
+    }
+}
+
+/// The last record: the one from this boot, or the one stored.
+async fn last() -> Option<Record> {
+    if let Some(record) = LAST.lock(|last| last.borrow().clone()) {
+        return Some(record);
+    }
+    let mut raw = [0u8; MAX_RECORD_LEN];
+    match kv::get(KEY, &mut raw).await {
+        Ok(Some(len)) => Vec::from_slice(&raw[..len]).ok(),
+        Ok(None) => None,
+        Err(e) => {
+            println!("replay: could not read the record: {:?}", e);
+            None
+        }
+    }
+}
+
+/// Prints the last record's first line and then the whole as base64.
+pub async fn print() {
+    let Some(record) = last().await else {
+        println!("lastfail: no failed request recorded");
+        return;
+    };
+    let first_line = record.split(|&b| b == b'\n').next().unwrap_or(&[]);
+    println!("{}", core::str::from_utf8(first_line).unwrap_or("lastfail"));
+    let mut blob = [0u8; codec::STANDARD.encoded_len(MAX_RECORD_LEN)];
+    match codec::STANDARD.encode(&record, &mut blob) {
+        Ok(blob) => println!("{}", blob),
+        Err(e) => println!("lastfail: {:?}", e),
+    }
+}
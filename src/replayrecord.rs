@@ -0,0 +1,167 @@
+// The record src/replay.rs keeps of a failed request, apart from where it
+// is kept: the outcome's line, then the head and the answer, each cut to
+// its limit, with the secrets masked (`redact`).
+
+use core::fmt::Write as _;
+use core::ops::Range;
+
+use heapless::{String, Vec};
+
+use crate::codec;
+use crate::headers::Headers;
+use crate::settings;
+
+pub const MAX_HEAD_LEN: usize = 320;
+pub const MAX_RESPONSE_LEN: usize = 128;
+/// The outcome's line, without its newline.
+pub const MAX_OUTCOME_LEN: usize = 64;
+/// The quoted bytes and the text around them.
+pub const MAX_RECORD_LEN: usize = 96 + MAX_HEAD_LEN + MAX_RESPONSE_LEN;
+const MIN_SECRET_LEN: usize = 4;
+
+pub type Record = Vec<u8, MAX_RECORD_LEN>;
+
+/// The record of a request with `head` to `path`, whose outcome is the
+/// line `outcome` and which got `response` back, with the secrets of
+/// `headers` and of the query of `path` masked.
+pub fn build(outcome: &str, path: &str, head: &[u8], response: &[u8], headers: &Headers) -> Record {
+    let head_len = head.len().min(MAX_HEAD_LEN);
+    let response_len = response.len().min(MAX_RESPONSE_LEN);
+    let mut text: String<24> = String::new();
+    let _ = writeln!(text, "request {}", head_len);
+
+    let mut record = Record::new();
+    // Cannot overflow: `MAX_RECORD_LEN` has room for the text and both.
+    let _ = record.extend_from_slice(&outcome.as_bytes()[..outcome.len().min(MAX_OUTCOME_LEN)]);
+    let _ = record.push(b'\n');
+    let _ = record.extend_from_slice(text.as_bytes());
+    let start = record.len();
+    let _ = record.extend_from_slice(&head[..head_len]);
+    let head_range = start..record.len();
+    text.clear();
+    let _ = writeln!(text, "\nresponse {}", response_len);
+    let _ = record.extend_from_slice(text.as_bytes());
+    let _ = record.extend_from_slice(&response[..response_len]);
+    let response_end = record.len();
+    let _ = record.push(b'\n');
+
+    let mut cuts: Vec<usize, 2> = Vec::new();
+    if head_len < head.len() {
+        let _ = cuts.push(head_range.end);
+    }
+    if response_len < response.len() {
+        let _ = cuts.push(response_end);
+    }
+    let query = path.split_once('?').map_or("", |(_, query)| query);
+    redact(&mut record, head_range, &cuts, headers, query);
+    record
+}
+
+/// Masks the secrets of `headers` and `query` in `data`, whose bytes in
+/// `head` are a request head, and which was cut short at each of `cuts`.
+pub fn redact(data: &mut [u8], head: Range<usize>, cuts: &[usize], headers: &Headers, query: &str) {
+    mask_in_head(data, head, headers);
+
+    for (_, value) in headers.iter() {
+        mask_all(data, cuts, value.as_bytes());
+        for word in value.split([' ', '\t', ',']) {
+            mask_all(data, cuts, word.as_bytes());
+        }
+        let basic = value
+            .split_once(' ')
+            .filter(|(scheme, _)| scheme.eq_ignore_ascii_case("Basic"));
+        if let Some((_, encoded)) = basic {
+            let mut decoded = [0u8; 192];
+            if let Ok(credentials) = codec::STANDARD.decode(encoded.trim().as_bytes(), &mut decoded)
+            {
+                for part in credentials.split(|&b| b == b':') {
+                    mask_all(data, cuts, part);
+                }
+            }
+        }
+    }
+    for param in query.split('&') {
+        let value = param.split_once('=').map_or(param, |(_, value)| value);
+        mask_all(data, cuts, value.as_bytes());
+        let mut decoded = [0u8; settings::MAX_URL_LEN];
+        if let Ok(decoded) = codec::percent_decode(value.as_bytes(), &mut decoded) {
+            mask_all(data, cuts, decoded);
+        }
+    }
+}
+
+/// Masks the query's values in the request line, and the values of the
+/// header lines the settings have a header for.
+fn mask_in_head(data: &mut [u8], head: Range<usize>, headers: &Headers) {
+    let mut start = head.start;
+    while start < head.end {
+        let end = data[start..head.end]
+            .windows(2)
+            .position(|w| w == b"\r\n")
+            .map_or(head.end, |i| start + i);
+        if start == head.start {
+            mask_query(data, start..end);
+        } else if let Some(colon) = data[start..end].iter().position(|&b| b == b':') {
+            let named = core::str::from_utf8(&data[start..start + colon])
+                .is_ok_and(|name| headers.get(name.trim()).is_some());
+            if named {
+                let value = data[start + colon + 1..end]
+                    .iter()
+                    .position(|&b| b != b' ' && b != b'\t')
+                    .map_or(end, |i| start + colon + 1 + i);
+                mask(data, value..end);
+            }
+        }
+        start = end + 2;
+    }
+}
+
+/// Masks each `value` of `?key=value&...` in the request line at `line`.
+fn mask_query(data: &mut [u8], line: Range<usize>) {
+    let Some(question) = data[line.clone()].iter().position(|&b| b == b'?') else {
+        return;
+    };
+    let end = data[line.start + question..line.end]
+        .iter()
+        .position(|&b| b == b' ')
+        .map_or(line.end, |i| line.start + question + i);
+    let mut in_value = false;
+    for b in &mut data[line.start + question + 1..end] {
+        match *b {
+            b'&' => in_value = false,
+            b'=' if !in_value => in_value = true,
+            _ if in_value => *b = b'*',
+            _ => {}
+        }
+    }
+}
+
+/// Masks every occurrence of `secret` in `data`, and the longest start of
+/// it that ends at one of `cuts`, unless it is too short to be one.
+fn mask_all(data: &mut [u8], cuts: &[usize], secret: &[u8]) {
+    if secret.len() < MIN_SECRET_LEN {
+        return;
+    }
+    let mut i = 0;
+    while i + secret.len() <= data.len() {
+        if &data[i..i + secret.len()] == secret {
+            mask(data, i..i + secret.len());
+            i += secret.len();
+        } else {
+            i += 1;
+        }
+    }
+    for &cut in cuts {
+        let start = (1..secret.len())
+            .rev()
+            .filter(|&len| len <= cut)
+            .find(|&len| data[cut - len..cut] == secret[..len]);
+        if let Some(len) = start {
+            mask(data, cut - len..cut);
+        }
+    }
+}
+
+fn mask(data: &mut [u8], range: Range<usize>) {
+    data[range].fill(b'*');
+}
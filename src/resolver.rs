@@ -0,0 +1,114 @@
+// DNS resolver for A records, in place of the network stack's.
+//
+// Every query gets its ID and its source port from the hardware RNG, so an
+// attacker on the LAN cannot predict them. A response is only used when
+// it comes from a server that was queried, carries the query's ID, repeats
+// its question, and answers for the queried name: an A record owned by
+// the name, or by the end of a CNAME chain from it (at most
+// `dns::MAX_CNAME_DEPTH` links). Anything else is discarded, counted in
+// `metrics` by `Reject`, and the resolver keeps waiting for the genuine
+// answer until the timeout. Responses to an earlier query arrive at a port
+// that is closed by then.
+//
+// `dns::check_response` (src/dns.rs) is the whole decision, over the bytes
+// of one message.
+//
+// The query goes to the DHCP-provided servers in turn, resent every
+// `RETRANSMIT` with the same ID, until one answers or `TIMEOUT` is up.
+
+use embassy_net::udp::{PacketMetadata, UdpSocket};
+use embassy_net::{IpAddress, Ipv4Address};
+use embassy_time::{with_deadline, Duration, Instant};
+use esp_println::println;
+
+use crate::dns::{self, Answer, Reject};
+use crate::entropy;
+use crate::metrics;
+use crate::NetStack;
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+const RETRANSMIT: Duration = Duration::from_secs(2);
+
+/// Largest response taken; classic DNS over UDP.
+const MESSAGE_LEN: usize = 512;
+
+const DNS_PORT: u16 = 53;
+const FIRST_EPHEMERAL_PORT: u16 = 49152;
+const BIND_ATTEMPTS: usize = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DnsError {
+    /// Not a host name a query can carry.
+    InvalidName,
+    /// DHCP gave no DNS server.
+    NoServer,
+    Socket,
+    Timeout,
+    /// The name does not exist, or has no address.
+    NotFound,
+    /// The server answered with this error code.
+    Failed(u8),
+}
+
+/// The IPv4 address of `host`. An address literal is returned as it is.
+pub async fn resolve(stack: &NetStack, host: &str) -> Result<Ipv4Address, DnsError> {
+    if let Ok(addr) = host.parse::<Ipv4Address>() {
+        return Ok(addr);
+    }
+    let servers = stack
+        .config_v4()
+        .map(|config| config.dns_servers)
+        .unwrap_or_default();
+    if servers.is_empty() {
+        return Err(DnsError::NoServer);
+    }
+    let id = entropy::random_u32() as u16;
+    let mut query = [0u8; MESSAGE_LEN];
+    let query_len = dns::encode_query(id, host, &mut query).ok_or(DnsError::InvalidName)?;
+
+    let mut rx_meta = [PacketMetadata::EMPTY; 2];
+    let mut tx_meta = [PacketMetadata::EMPTY; 1];
+    let mut rx_buf = [0u8; MESSAGE_LEN];
+    let mut tx_buf = [0u8; MESSAGE_LEN];
+    let mut socket = UdpSocket::new(stack, &mut rx_meta, &mut rx_buf, &mut tx_meta, &mut tx_buf);
+    let range = u16::MAX - FIRST_EPHEMERAL_PORT + 1;
+    (0..BIND_ATTEMPTS)
+        .find(|_| {
+            let port = FIRST_EPHEMERAL_PORT + entropy::random_u32() as u16 % range;
+            socket.bind(port).is_ok()
+        })
+        .ok_or(DnsError::Socket)?;
+
+    let deadline = Instant::now() + TIMEOUT;
+    let mut response = [0u8; MESSAGE_LEN];
+    for attempt in 0.. {
+        let server = servers[attempt % servers.len()];
+        socket
+            .send_to(&query[..query_len], (server, DNS_PORT))
+            .await
+            .map_err(|_| DnsError::Socket)?;
+        let resend = deadline.min(Instant::now() + RETRANSMIT);
+        while let Ok(received) = with_deadline(resend, socket.recv_from(&mut response)).await {
+            let (len, from) = received.map_err(|_| DnsError::Socket)?;
+            let queried = matches!(from.addr, IpAddress::Ipv4(addr) if servers.contains(&addr));
+            let checked = if queried && from.port == DNS_PORT {
+                dns::check_response(&response[..len], id, host)
+            } else {
+                Err(Reject::WrongSource)
+            };
+            match checked {
+                Ok(Answer::Address(addr)) => return Ok(Ipv4Address::from_bytes(&addr)),
+                Ok(Answer::NotFound) => return Err(DnsError::NotFound),
+                Ok(Answer::Failed(rcode)) => return Err(DnsError::Failed(rcode)),
+                Err(reject) => {
+                    println!("dns: discarded a response for {} ({:?})", host, reject);
+                    metrics::record_dns_discarded(reject);
+                }
+            }
+        }
+        if Instant::now() >= deadline {
+            break;
+        }
+    }
+    Err(DnsError::Timeout)
+}
@@ -0,0 +1,194 @@
+// Restarts the firmware makes on purpose, the note that says why, and the
+// daily scheduled restart (`reboot_at` in the settings).
+//
+// `shut_down` is the orderly way down: it waits for a running update to
+// end, says goodbye over mDNS, takes the HTTPS client and the KV store so that no request or write
+// is cut off and none starts, lets a flash burst finish, prints the request
+// metrics (they only live in RAM) and then calls `restart`. None of the
+// waiting outlasts the bound it is given; after that it goes ahead anyway.
+//
+// `restart` leaves a note in RTC fast memory and resets. Like the crash
+// record it has a magic and a CRC, so what the memory holds after power-up
+// is not taken for one. At boot `take_note` reads and clears it, and
+// `last` gives the reason to the status outputs as `reset`; a panic leaves
+// the crash record instead, power-up and the watchdogs nothing. The reason
+// and how the note encodes it are in src/restartnote.rs.
+//
+// A station that has not associated after all of main's attempts at boot
+// may have a wedged driver, and esp-wifi 0.6 cannot be shut down and
+// started again in place, so `wifi_stuck` resets instead. It waits first,
+// `wifi_reset_delay`, longer with each such reset in a row, which the note
+// counts, so a wrong password does not reset the device every minute.
+// Readings carry the count as `wifi_resets` next to `reset`.
+//
+// The schedule needs the wall clock: without an SNTP sync it never fires.
+// It fires when the clock passes `reboot_at` between two readings, so
+// neither a boot nor the first sync after `reboot_at` starts one, and the
+// note carries the UTC day, so a clock set back across `reboot_at` after a
+// scheduled restart does not bring a second one that day.
+
+use core::cell::Cell;
+use core::ptr::addr_of_mut;
+
+use critical_section::Mutex;
+use embassy_time::{with_deadline, Duration, Instant, Timer as EmbassyTimer};
+use esp_hal::macros::ram;
+use esp_println::println;
+
+use crate::canary;
+use crate::flash;
+use crate::https;
+use crate::kv;
+#[cfg(feature = "api")]
+use crate::mdns;
+use crate::metrics;
+#[cfg(feature = "ota")]
+use crate::otaprogress::OtaState;
+use crate::restartnote::NOTE_LEN;
+use crate::settings;
+use crate::sntp;
+#[cfg(feature = "ota")]
+use crate::state;
+
+pub use crate::restartnote::Reason;
+
+/// Longest a scheduled restart waits for work in flight.
+const MAX_DEFERRAL: Duration = Duration::from_secs(10 * 60);
+const CHECK_INTERVAL: Duration = Duration::from_secs(20);
+const MIN_WIFI_RESET_DELAY: Duration = Duration::from_secs(60);
+const MAX_WIFI_RESET_DELAY: Duration = Duration::from_secs(30 * 60);
+#[cfg(feature = "ota")]
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+const MS_PER_MINUTE: u64 = 60 * 1000;
+const MS_PER_DAY: u64 = 24 * 60 * MS_PER_MINUTE;
+
+#[ram(rtc_fast, uninitialized)]
+static mut NOTE: [u8; NOTE_LEN] = [0; NOTE_LEN];
+
+/// The note found at boot.
+static LAST: Mutex<Cell<Option<Reason>>> = Mutex::new(Cell::new(None));
+
+/// Reads and clears the note of the restart before this boot. Call once,
+/// early at boot.
+pub fn take_note() {
+    let reason = critical_section::with(|cs| {
+        let note = unsafe { &mut *addr_of_mut!(NOTE) };
+        let reason = Reason::decode(note);
+        note.fill(0);
+        LAST.borrow(cs).set(reason);
+        reason
+    });
+    if let Some(reason) = reason {
+        println!("restart: reset was deliberate ({})", reason.as_str());
+    }
+}
+
+/// Why the firmware last restarted itself, if the reset before this boot
+/// was one of those.
+pub fn last() -> Option<Reason> {
+    critical_section::with(|cs| LAST.borrow(cs).get())
+}
+
+/// How long to wait before the `resets`th Wi-Fi reset in a row: doubling
+/// from `MIN_WIFI_RESET_DELAY` up to `MAX_WIFI_RESET_DELAY`.
+pub fn wifi_reset_delay(resets: u32) -> Duration {
+    let doublings = resets.saturating_sub(1).min(16);
+    (MIN_WIFI_RESET_DELAY * (1 << doublings)).min(MAX_WIFI_RESET_DELAY)
+}
+
+/// Once the station has failed to associate at boot: waits
+/// `wifi_reset_delay` and `restart`s, for the Wi-Fi driver to start over.
+#[cfg_attr(feature = "factory", allow(dead_code))]
+pub async fn wifi_stuck() -> ! {
+    let resets = match last() {
+        Some(Reason::WifiStuck(resets)) => resets.saturating_add(1),
+        _ => 1,
+    };
+    let delay = wifi_reset_delay(resets);
+    println!(
+        "restart: Wi-Fi reset {} in a row, in {} s",
+        resets,
+        delay.as_secs()
+    );
+    EmbassyTimer::after(delay).await;
+    restart(Reason::WifiStuck(resets))
+}
+
+/// Notes `reason` and resets now.
+pub fn restart(reason: Reason) -> ! {
+    critical_section::with(|_| unsafe { *addr_of_mut!(NOTE) = reason.encode() });
+    esp_hal::reset::software_reset();
+    loop {
+        core::hint::spin_loop();
+    }
+}
+
+/// Lets work in flight finish, for at most `within`, then `restart`s.
+pub async fn shut_down(reason: Reason, within: Duration) -> ! {
+    let deadline = Instant::now() + within;
+    println!("restart: {}, waiting for work in flight", reason.as_str());
+    #[cfg(feature = "ota")]
+    while Instant::now() < deadline
+        && state::current()
+            .ota
+            .is_some_and(|ota| matches!(ota.state, OtaState::Running | OtaState::Stalled))
+    {
+        EmbassyTimer::after(POLL_INTERVAL).await;
+    }
+    #[cfg(feature = "api")]
+    let _ = with_deadline(deadline, mdns::withdraw()).await;
+    // Held until the reset.
+    let _https = with_deadline(deadline, https::hold()).await;
+    let _ = with_deadline(deadline, flash::wait_idle()).await;
+    let _kv = with_deadline(deadline, kv::hold()).await;
+    if Instant::now() >= deadline {
+        println!("restart: still busy, restarting anyway");
+    }
+    metrics::roll_up_pending();
+    metrics::print_summary();
+    restart(reason)
+}
+
+#[embassy_executor::task]
+pub async fn schedule_task() {
+    canary::tracked("restart", run()).await
+}
+
+async fn run() -> ! {
+    let done_day = match last() {
+        Some(Reason::Scheduled(day)) => Some(day),
+        _ => None,
+    };
+    // The previous clock reading; `None` while there is no clock.
+    let mut seen = None;
+    loop {
+        EmbassyTimer::after(CHECK_INTERVAL).await;
+        let Some(now) = sntp::now_unix_ms() else {
+            seen = None;
+            continue;
+        };
+        let due = match (settings::current().reboot_at, seen) {
+            (Some(at), Some(seen)) => passed(at, seen, now),
+            _ => None,
+        };
+        seen = Some(now);
+        let Some(day) = due.filter(|&day| done_day != Some(day)) else {
+            continue;
+        };
+        shut_down(Reason::Scheduled(day), MAX_DEFERRAL).await;
+    }
+}
+
+/// The UTC day on which the clock passed `at` (minutes after midnight)
+/// between `seen` and `now` (Unix ms), if it did. A clock set back passes
+/// nothing.
+fn passed(at: u16, seen: u64, now: u64) -> Option<u32> {
+    let today = now / MS_PER_DAY * MS_PER_DAY + at as u64 * MS_PER_MINUTE;
+    let latest = if today <= now {
+        today
+    } else {
+        today.checked_sub(MS_PER_DAY)?
+    };
+    (latest > seen).then_some((latest / MS_PER_DAY) as u32)
+}
@@ -0,0 +1,69 @@
+// Why the firmware restarted itself, and the note src/restart.rs leaves in
+// RTC fast memory to say so, apart from where it is kept.
+
+use esp_hal::rom::crc::crc32_le;
+
+const MAGIC: u32 = 0x5253_5452;
+// magic | reason | day + 1 (0 for none), or the count of Wi-Fi resets | crc
+pub const NOTE_LEN: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reason {
+    /// `reboot_at`, on this UTC day (days since the Unix epoch).
+    Scheduled(u32),
+    /// Asked for over the API, or by a fleet command.
+    Requested,
+    /// Into a freshly installed image.
+    Update,
+    /// Out of safe mode (src/safemode.rs).
+    SafeModeExit,
+    /// The station never associated at boot; the how manyth such reset in
+    /// a row.
+    WifiStuck(u32),
+}
+
+impl Reason {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Reason::Scheduled(_) => "scheduled",
+            Reason::Requested => "requested",
+            Reason::Update => "update",
+            Reason::SafeModeExit => "safe_mode_exit",
+            Reason::WifiStuck(_) => "wifi_stuck",
+        }
+    }
+
+    /// The note to leave for the next boot.
+    pub fn encode(self) -> [u8; NOTE_LEN] {
+        let (code, day) = match self {
+            Reason::Scheduled(day) => (1u32, day.saturating_add(1)),
+            Reason::Requested => (2, 0),
+            Reason::Update => (3, 0),
+            Reason::SafeModeExit => (4, 0),
+            Reason::WifiStuck(resets) => (5, resets),
+        };
+        let mut raw = [0u8; NOTE_LEN];
+        raw[0..4].copy_from_slice(&MAGIC.to_le_bytes());
+        raw[4..8].copy_from_slice(&code.to_le_bytes());
+        raw[8..12].copy_from_slice(&day.to_le_bytes());
+        let crc = crc32_le(0, &raw[..NOTE_LEN - 4]);
+        raw[NOTE_LEN - 4..].copy_from_slice(&crc.to_le_bytes());
+        raw
+    }
+
+    /// `None` for anything `encode` did not write.
+    pub fn decode(raw: &[u8; NOTE_LEN]) -> Option<Reason> {
+        let word = |at: usize| u32::from_le_bytes(raw[at..at + 4].try_into().unwrap());
+        if word(0) != MAGIC || word(NOTE_LEN - 4) != crc32_le(0, &raw[..NOTE_LEN - 4]) {
+            return None;
+        }
+        match (word(4), word(8)) {
+            (1, day) if day > 0 => Some(Reason::Scheduled(day - 1)),
+            (2, _) => Some(Reason::Requested),
+            (3, _) => Some(Reason::Update),
+            (4, _) => Some(Reason::SafeModeExit),
+            (5, resets) if resets > 0 => Some(Reason::WifiStuck(resets)),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,148 @@
+// The request metrics' numbers (src/metrics.rs), apart from the counters
+// that collect them: the latency buckets, a histogram of them and its
+// percentiles, and a day's totals and the rollup of today and yesterday.
+
+use crate::dns::Reject;
+
+/// Upper bounds of the latency buckets, in ms: powers of the square root of
+/// two, rounded, up to 60 s. One more bucket takes everything slower.
+pub const BUCKET_BOUNDS_MS: [u32; 32] = [
+    1, 2, 3, 4, 6, 8, 11, 16, 23, 32, 45, 64, 91, 128, 181, 256, 362, 512, 724, 1024, 1448, 2048,
+    2896, 4096, 5793, 8192, 11585, 16384, 23170, 32768, 46341, 60000,
+];
+pub const BUCKETS: usize = BUCKET_BOUNDS_MS.len() + 1;
+
+/// The phases of a request with a histogram each, in `DayTotals::phases`
+/// order.
+pub const PHASES: [&str; 4] = ["dns", "connect", "handshake", "first_byte"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Histogram {
+    pub counts: [u32; BUCKETS],
+    /// Of all samples, for the Prometheus `_sum`.
+    pub sum_ms: u64,
+}
+
+impl Histogram {
+    pub const EMPTY: Histogram = Histogram {
+        counts: [0; BUCKETS],
+        sum_ms: 0,
+    };
+
+    pub fn count(&self) -> u32 {
+        self.counts.iter().fold(0, |sum, &n| sum.saturating_add(n))
+    }
+
+    pub fn merge(&mut self, other: &Histogram) {
+        for (count, &n) in self.counts.iter_mut().zip(&other.counts) {
+            *count = count.saturating_add(n);
+        }
+        self.sum_ms = self.sum_ms.saturating_add(other.sum_ms);
+    }
+
+    /// Estimated `percent`-th percentile in ms, interpolating linearly
+    /// within the bucket it falls in. The open last bucket gives its lower
+    /// bound. `None` without samples.
+    pub fn percentile(&self, percent: u8) -> Option<u32> {
+        let total = self.count() as u64;
+        if total == 0 {
+            return None;
+        }
+        // Rank of the sample wanted, 1-based.
+        let rank = (total * percent.min(100) as u64).div_ceil(100).max(1);
+        let mut below = 0u64;
+        for (i, &n) in self.counts.iter().enumerate() {
+            let n = n as u64;
+            if below + n < rank {
+                below += n;
+                continue;
+            }
+            let low = if i == 0 { 0 } else { BUCKET_BOUNDS_MS[i - 1] };
+            let Some(&high) = BUCKET_BOUNDS_MS.get(i) else {
+                return Some(low);
+            };
+            let into = (rank - below) * (high - low) as u64 / n;
+            return Some(low + into as u32);
+        }
+        None
+    }
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Histogram::EMPTY
+    }
+}
+
+/// Bucket a latency of `ms` counts towards.
+pub fn bucket_index(ms: u64) -> usize {
+    BUCKET_BOUNDS_MS.partition_point(|&bound| (bound as u64) < ms)
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct DayTotals {
+    pub latency: Histogram,
+    /// Indexed like `PHASES`.
+    pub phases: [Histogram; PHASES.len()],
+    /// Of critical records, raised to accepted.
+    pub critical: Histogram,
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+    /// Indexed by `Reject::index`.
+    pub dns_discarded: [u32; Reject::COUNT],
+}
+
+impl DayTotals {
+    pub const ZERO: DayTotals = DayTotals {
+        latency: Histogram::EMPTY,
+        phases: [Histogram::EMPTY; PHASES.len()],
+        critical: Histogram::EMPTY,
+        bytes_sent: 0,
+        bytes_received: 0,
+        dns_discarded: [0; Reject::COUNT],
+    };
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Rollup {
+    /// Days since the Unix epoch that `today` covers; `None` until the clock
+    /// has been synced.
+    pub day: Option<u32>,
+    pub today: DayTotals,
+    pub yesterday: DayTotals,
+}
+
+impl Rollup {
+    /// Adds what came in since the last call. `day` is the current UTC day,
+    /// if known; a new one moves `today` to `yesterday` first, and makes this
+    /// return `true`.
+    pub fn roll_up(
+        &mut self,
+        day: Option<u32>,
+        latency: &Histogram,
+        phases: &[Histogram; PHASES.len()],
+        sent: u32,
+        received: u32,
+        dns_discarded: &[u32; Reject::COUNT],
+    ) -> bool {
+        let mut new_day = false;
+        if let Some(day) = day {
+            if self.day.is_some_and(|current| current != day) {
+                self.yesterday = self.today;
+                self.today = DayTotals::default();
+                new_day = true;
+            }
+            self.day = Some(day);
+        }
+        self.today.latency.merge(latency);
+        for (total, phase) in self.today.phases.iter_mut().zip(phases) {
+            total.merge(phase);
+        }
+        self.today.bytes_sent = self.today.bytes_sent.saturating_add(sent as u64);
+        self.today.bytes_received = self.today.bytes_received.saturating_add(received as u64);
+        for (total, &n) in self.today.dns_discarded.iter_mut().zip(dns_discarded) {
+            *total = total.saturating_add(n);
+        }
+        new_day
+    }
+}
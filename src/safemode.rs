@@ -0,0 +1,202 @@
+// Safe mode, for a device caught in a crash loop.
+//
+// A bad config from the server or a damaged KV store can make every boot
+// panic, or hang until a watchdog resets it, and each time round the device
+// writes flash and calls the backend again. `check` counts such resets in a
+// row in RTC fast memory: panics, which the panic handler notes here
+// (src/crash.rs), and watchdog resets. Any other reset, a deliberate
+// restart (src/restart.rs) included, ends the run, and so does being up for
+// `STABLE_UPTIME` (`note_uptime`). Like the crash record the note has a
+// magic and a CRC, and a power cut loses it.
+//
+// After `MAX_ABNORMAL_RESETS` in a row the device boots into safe mode and
+// stays in it across resets, good or bad, until told to leave:
+//
+// - The settings are the build's defaults. Neither the persisted ones nor
+//   the store's schema are touched (src/settings.rs, src/schema.rs). So
+//   the network joined is the build's SSID with its password, whatever
+//   credentials the settings held.
+// - No OTA and no config poll.
+// - No API, mDNS, debug access point or self-test. There is no
+//   provisioning access point to open instead: the firmware has none, and
+//   the build's credentials are the ones it would hand out.
+// - The uploader sends a heartbeat each interval instead of readings, with
+//   `safe_mode` and the crash report, and without `seq`, whose counter
+//   lives in the store (src/uploader.rs).
+//
+// The console's `safemode exit`, or an answer to a heartbeat with
+// `X-Safe-Mode: exit`, leaves it: the note is cleared and the device
+// restarts. So does a power cut, since the note is gone after one; if the
+// loop is still there, it comes back after another run of resets.
+
+use core::cell::Cell;
+use core::ptr::addr_of_mut;
+
+use critical_section::Mutex;
+use embassy_time::{Duration, Instant};
+use esp_hal::macros::ram;
+use esp_hal::reset::get_reset_reason;
+use esp_hal::rom::crc::crc32_le;
+use esp_hal::rtc_cntl::SocResetReason;
+use esp_println::println;
+
+use crate::restart::{self, Reason};
+
+pub const MAX_ABNORMAL_RESETS: u8 = 3;
+/// Up this long, the resets before no longer count as a loop.
+pub const STABLE_UPTIME: Duration = Duration::from_secs(5 * 60);
+
+/// Longest leaving waits for work in flight.
+const EXIT_SETTLE: Duration = Duration::from_secs(30);
+
+const MAGIC: u32 = 0x5341_4645;
+// magic | abnormal resets | safe mode | panicked | unused | crc
+const NOTE_LEN: usize = 12;
+
+#[ram(rtc_fast, uninitialized)]
+static mut NOTE: [u8; NOTE_LEN] = [0; NOTE_LEN];
+
+/// What `check` decided for this boot.
+static ACTIVE: Mutex<Cell<bool>> = Mutex::new(Cell::new(false));
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+struct Note {
+    /// Abnormal resets in a row, up to this boot.
+    resets: u8,
+    safe: bool,
+    /// Set by the panic handler, for the next boot's `check`.
+    panicked: bool,
+}
+
+impl Note {
+    fn encode(self) -> [u8; NOTE_LEN] {
+        let mut raw = [0u8; NOTE_LEN];
+        raw[0..4].copy_from_slice(&MAGIC.to_le_bytes());
+        raw[4] = self.resets;
+        raw[5] = self.safe as u8;
+        raw[6] = self.panicked as u8;
+        let crc = crc32_le(0, &raw[..NOTE_LEN - 4]);
+        raw[NOTE_LEN - 4..].copy_from_slice(&crc.to_le_bytes());
+        raw
+    }
+
+    /// `None` for anything `encode` did not write.
+    fn decode(raw: &[u8; NOTE_LEN]) -> Option<Note> {
+        let word = |at: usize| u32::from_le_bytes(raw[at..at + 4].try_into().unwrap());
+        if word(0) != MAGIC || word(NOTE_LEN - 4) != crc32_le(0, &raw[..NOTE_LEN - 4]) {
+            return None;
+        }
+        Some(Note {
+            resets: raw[4],
+            safe: raw[5] != 0,
+            panicked: raw[6] != 0,
+        })
+    }
+}
+
+/// Runs `f` on the note, a fresh one if there is none, and stores the
+/// result.
+fn update<R>(f: impl FnOnce(&mut Note) -> R) -> R {
+    critical_section::with(|_| {
+        let raw = unsafe { &mut *addr_of_mut!(NOTE) };
+        let mut note = Note::decode(raw).unwrap_or_default();
+        let result = f(&mut note);
+        *raw = note.encode();
+        result
+    })
+}
+
+fn is_watchdog(reason: SocResetReason) -> bool {
+    matches!(
+        reason,
+        SocResetReason::CoreMwdt0
+            | SocResetReason::CoreMwdt1
+            | SocResetReason::CoreRtcWdt
+            | SocResetReason::Cpu0Mwdt0
+            | SocResetReason::Cpu0Mwdt1
+            | SocResetReason::Cpu0RtcWdt
+            | SocResetReason::SysRtcWdt
+            | SocResetReason::SysSuperWdt
+    )
+}
+
+/// Counts the reset before this boot and decides whether this boot is in
+/// safe mode. Call once, first thing at boot: before the settings are
+/// loaded.
+pub fn check() -> bool {
+    let watchdog = get_reset_reason().is_some_and(is_watchdog);
+    let note = update(|note| {
+        let abnormal = note.panicked || watchdog;
+        note.resets = if abnormal {
+            note.resets.saturating_add(1)
+        } else {
+            0
+        };
+        note.panicked = false;
+        if note.resets >= MAX_ABNORMAL_RESETS {
+            note.safe = true;
+        }
+        *note
+    });
+    critical_section::with(|cs| ACTIVE.borrow(cs).set(note.safe));
+    if note.safe {
+        println!(
+            "safemode: ON after {} abnormal resets in a row; `safemode exit` to leave",
+            note.resets
+        );
+    } else if note.resets > 0 {
+        println!(
+            "safemode: {} abnormal resets in a row, safe mode at {}",
+            note.resets, MAX_ABNORMAL_RESETS
+        );
+    }
+    note.safe
+}
+
+/// Whether this boot is in safe mode.
+pub fn active() -> bool {
+    critical_section::with(|cs| ACTIVE.borrow(cs).get())
+}
+
+/// Abnormal resets in a row before this boot.
+pub fn resets() -> u8 {
+    critical_section::with(|_| Note::decode(unsafe { &*addr_of_mut!(NOTE) }))
+        .map_or(0, |note| note.resets)
+}
+
+/// For the panic handler, just before it resets.
+pub fn note_panic() {
+    update(|note| note.panicked = true);
+}
+
+/// Clears the run of resets once the firmware has been up `STABLE_UPTIME`.
+/// Call now and then; safe mode itself stays.
+pub fn note_uptime() {
+    if Instant::now().as_millis() < STABLE_UPTIME.as_millis() {
+        return;
+    }
+    let cleared = update(|note| {
+        let cleared = note.resets > 0 && !note.safe;
+        if cleared {
+            note.resets = 0;
+        }
+        cleared
+    });
+    if cleared {
+        println!(
+            "safemode: up {} min, reset count cleared",
+            STABLE_UPTIME.as_secs() / 60
+        );
+    }
+}
+
+/// Leaves safe mode and restarts. Outside it, only says so.
+pub async fn exit() {
+    if !active() {
+        println!("safemode: not in safe mode");
+        return;
+    }
+    update(|note| *note = Note::default());
+    println!("safemode: leaving");
+    restart::shut_down(Reason::SafeModeExit, EXIT_SETTLE).await
+}
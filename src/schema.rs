@@ -0,0 +1,148 @@
+// Versioning for what the firmware keeps in the KV store.
+//
+// The store holds one version byte under `schema`. At boot `upgrade()`
+// compares it with `CURRENT_SCHEMA_VERSION` and, if the store is older, runs
+// the registered migrations one version at a time, recording each step as it
+// finishes so a reset halfway through resumes where it stopped. A store from
+// newer firmware, or one with no way forward, is wiped instead of being read
+// by code that does not understand it.
+//
+// Migrations run before any task is spawned, so nothing else can hold the
+// store's lock and the blocking wrappers here never actually wait.
+
+use core::cell::RefCell;
+
+use embassy_futures::block_on;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use esp_println::println;
+use heapless::Vec;
+
+use crate::kv::{self, KvError};
+
+/// Bump together with a `register_migration` for the previous version.
+pub const CURRENT_SCHEMA_VERSION: u8 = 1;
+
+const KEY_SCHEMA: &str = "schema";
+/// Present in every store written before versioning existed.
+const KEY_LEGACY_MARKER: &str = "settings";
+
+const MAX_MIGRATIONS: usize = 8;
+
+#[derive(Debug)]
+pub enum MigrateError {
+    Kv(#[allow(dead_code)] KvError),
+    /// No migration is registered out of this version.
+    NoPath(#[allow(dead_code)] u8),
+    /// The store was written by newer firmware.
+    #[allow(dead_code)]
+    Downgrade {
+        from: u8,
+        to: u8,
+    },
+}
+
+impl From<KvError> for MigrateError {
+    fn from(e: KvError) -> Self {
+        MigrateError::Kv(e)
+    }
+}
+
+/// Converts the store from the version it is registered for to the next.
+pub type Migrator = fn() -> Result<(), MigrateError>;
+
+static MIGRATIONS: Mutex<CriticalSectionRawMutex, RefCell<Vec<(u8, Migrator), MAX_MIGRATIONS>>> =
+    Mutex::new(RefCell::new(Vec::new()));
+
+/// Registers `migrator` as the step from `from_version` to `from_version + 1`,
+/// replacing any earlier one for the same version.
+pub fn register_migration(from_version: u8, migrator: Migrator) {
+    MIGRATIONS.lock(|migrations| {
+        let mut migrations = migrations.borrow_mut();
+        migrations.retain(|(version, _)| *version != from_version);
+        if migrations.push((from_version, migrator)).is_err() {
+            panic!("more than {} schema migrations", MAX_MIGRATIONS);
+        }
+    });
+}
+
+/// The version the store is in. A store with no version byte predates
+/// versioning (0) if it has settings, and is simply empty otherwise.
+pub fn config_schema_version() -> Result<u8, MigrateError> {
+    if let Some(version) = stored_version()? {
+        return Ok(version);
+    }
+    // Only whether the key exists matters, not its value.
+    match read(KEY_LEGACY_MARKER, &mut []) {
+        Ok(Some(_)) | Err(KvError::BufferTooSmall(_)) => Ok(0),
+        Ok(None) => Ok(CURRENT_SCHEMA_VERSION),
+        Err(e) => Err(e.into()),
+    }
+}
+
+fn stored_version() -> Result<Option<u8>, KvError> {
+    let mut version = [0u8];
+    Ok(read(KEY_SCHEMA, &mut version)?.map(|_| version[0]))
+}
+
+/// Runs the migrations from `from` up to `to`.
+pub fn migrate_config(from: u8, to: u8) -> Result<(), MigrateError> {
+    if from > to {
+        return Err(MigrateError::Downgrade { from, to });
+    }
+    for version in from..to {
+        let migrator = MIGRATIONS.lock(|migrations| {
+            migrations
+                .borrow()
+                .iter()
+                .find(|(v, _)| *v == version)
+                .map(|(_, migrator)| *migrator)
+        });
+        let migrator = migrator.ok_or(MigrateError::NoPath(version))?;
+        migrator()?;
+        write(KEY_SCHEMA, &[version + 1])?;
+        println!("schema: migrated {} -> {}", version, version + 1);
+    }
+    Ok(())
+}
+
+/// Brings the store to `CURRENT_SCHEMA_VERSION`. Call once at boot, before
+/// anything reads the store.
+pub fn upgrade() {
+    register_migration(0, from_v0);
+
+    let result = config_schema_version().and_then(|version| {
+        migrate_config(version, CURRENT_SCHEMA_VERSION)?;
+        // Stamps stores that were empty until now.
+        if stored_version()?.is_none() {
+            write(KEY_SCHEMA, &[CURRENT_SCHEMA_VERSION])?;
+        }
+        Ok(())
+    });
+    if let Err(e) = result {
+        println!("schema: {:?}, wiping the store", e);
+        if let Err(e) = block_on(kv::clear()) {
+            println!("schema: wipe failed: {:?}", e);
+            return;
+        }
+        if let Err(e) = write(KEY_SCHEMA, &[CURRENT_SCHEMA_VERSION]) {
+            println!("schema: could not record version: {:?}", e);
+        }
+    }
+}
+
+/// Version 0, from before versioning, stored the same keys in the same
+/// format as version 1; only the version byte is new.
+fn from_v0() -> Result<(), MigrateError> {
+    Ok(())
+}
+
+// Blocking access for migrators, which are plain functions.
+
+pub fn read(key: &str, buf: &mut [u8]) -> Result<Option<usize>, KvError> {
+    block_on(kv::get(key, buf))
+}
+
+pub fn write(key: &str, value: &[u8]) -> Result<(), KvError> {
+    block_on(kv::set(key, value))
+}
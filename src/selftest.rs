@@ -0,0 +1,314 @@
+// Self-test for factory and field bring-up: a fixed sequence of checks, each
+// reported with its time as it finishes, then one line for a script to wait
+// for:
+//
+//     SELFTEST {"v":1,"ok":false,"passed":7,"of":10,"failed":"dns","ms":6120}
+//
+// `failed` is the first check that did not pass, by the names below, and
+// `null` when `ok` is true. A hard failure ends the run, since the checks
+// after it depend on it; the soft ones (`i2c`, `adc`, `spi`, `wifi_scan`,
+// `sntp`) only fail the verdict.
+//
+// In order: `rng` (the hardware RNG does not repeat itself), `i2c` (the RTC
+// and the PMIC acknowledge their addresses), `adc` (the touch pad's
+// baseline at boot is high enough for a touch to pull it below the
+// threshold), `spi` (built with `factory` only), `kv` (a scratch key reads
+// back what was written), `wifi_scan` (at least one access point),
+// `association` (connected, reconnecting once if not), `dhcp`, `dns` (the
+// connectivity probe's host), `tls` (the upload URL, or the probe URL
+// without one, gets past the handshake) and `sntp`.
+//
+// `spi` needs the test fixture, which bridges MOSI (GPIO7) to MISO
+// (GPIO10): esp-hal has no internal loopback for SPI2. A 64-byte pattern
+// goes out on SPI2 at 1 MHz with chip select on GPIO3 and has to come back
+// as it was sent (src/selftestloopback.rs). Without the fixture that
+// cannot pass, hence `factory` builds only.
+//
+// The console's `selftest` runs it. Built with `factory`, it also runs at
+// boot until it has passed once; a factory reset starts that over.
+//
+// The checks' names and the verdict line are in src/selftestverdict.rs.
+
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::signal::Signal;
+use embassy_time::{with_timeout, Duration, Instant};
+#[cfg(feature = "factory")]
+use esp_hal::dma::Channel0;
+#[cfg(feature = "factory")]
+use esp_hal::gpio::AnyOutput;
+#[cfg(feature = "factory")]
+use esp_hal::peripherals::SPI2;
+use esp_hal::rng::Rng;
+#[cfg(feature = "factory")]
+use esp_hal::spi::{master::dma::SpiDma, FullDuplexMode};
+#[cfg(feature = "factory")]
+use esp_hal::Async;
+use esp_println::println;
+use heapless::String;
+
+use crate::bus::SharedI2c;
+use crate::canary;
+use crate::connectivity::PROBE_URL;
+use crate::diag::{self, Phase};
+use crate::ds3231;
+use crate::https;
+use crate::ip5306;
+use crate::kv;
+use crate::resolver::{self, DnsError};
+#[cfg(feature = "factory")]
+use crate::selftestloopback;
+use crate::selftestverdict::{Check, Verdict, CHECKS};
+use crate::settings;
+use crate::sntp::{self, Rejected, SntpError};
+use crate::station;
+use crate::NetStack;
+
+const SCRATCH_KEY: &str = "selftest.scratch";
+#[cfg(feature = "factory")]
+const KEY_PASSED: &str = "selftest.passed";
+
+const RNG_SAMPLES: usize = 8;
+const ASSOCIATION_TIMEOUT: Duration = Duration::from_secs(20);
+const DHCP_TIMEOUT: Duration = Duration::from_secs(15);
+
+static REQUESTED: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+/// What the hardware checks look at, handed over by `main`.
+pub struct Hardware {
+    pub i2c: &'static SharedI2c,
+    /// The touch pad's calibrated baseline and the drop that counts as a
+    /// touch.
+    pub touch_baseline: u32,
+    pub touch_threshold: u32,
+    #[cfg(feature = "factory")]
+    pub fixture: Fixture,
+}
+
+#[cfg(feature = "factory")]
+pub type FixtureSpi = SpiDma<'static, SPI2, Channel0, FullDuplexMode, Async>;
+
+/// SPI2 and its chip select, wired to the test fixture.
+#[cfg(feature = "factory")]
+pub struct Fixture {
+    pub spi: FixtureSpi,
+    pub cs: AnyOutput<'static>,
+}
+
+/// Starts a run in the self-test task; its output goes to the log.
+#[cfg(feature = "console")]
+pub fn request() {
+    REQUESTED.signal(());
+}
+
+#[embassy_executor::task]
+pub async fn selftest_task(stack: &'static NetStack, rng: Rng, hardware: Hardware) {
+    canary::tracked("selftest", run(stack, rng, hardware)).await
+}
+
+async fn run(stack: &'static NetStack, rng: Rng, hardware: Hardware) -> ! {
+    let mut tester = Tester {
+        stack,
+        rng,
+        hardware,
+    };
+    #[cfg(feature = "factory")]
+    {
+        let mut flag = [0u8; 1];
+        if !matches!(kv::get(KEY_PASSED, &mut flag).await, Ok(Some(_)))
+            && tester.run_all().await.failed.is_none()
+        {
+            if let Err(e) = kv::set(KEY_PASSED, b"1").await {
+                println!("selftest: could not record the pass: {:?}", e);
+            }
+        }
+    }
+    loop {
+        REQUESTED.wait().await;
+        tester.run_all().await;
+    }
+}
+
+/// Prints the verdict for a run that could not get as far as the self-test,
+/// e.g. because the network never came up at boot.
+#[cfg(feature = "factory")]
+pub fn report_unreachable(failed: Check) {
+    report(&Verdict {
+        failed: Some(failed),
+        ..Verdict::default()
+    });
+}
+
+fn report(verdict: &Verdict) {
+    let mut line: String<128> = String::new();
+    if verdict.write(&mut line).is_ok() {
+        println!("SELFTEST {}", line);
+    }
+}
+
+struct Tester {
+    stack: &'static NetStack,
+    rng: Rng,
+    hardware: Hardware,
+}
+
+impl Tester {
+    async fn run_all(&mut self) -> Verdict {
+        println!("selftest: starting");
+        let start = Instant::now();
+        let mut verdict = Verdict::default();
+        for &check in CHECKS {
+            let mark = Instant::now();
+            let result = self.check(check).await;
+            let ms = mark.elapsed().as_millis();
+            match result {
+                Ok(()) => println!("selftest: {:<11} pass ({} ms)", check.as_str(), ms),
+                Err(why) => {
+                    println!("selftest: {:<11} FAIL ({} ms): {}", check.as_str(), ms, why)
+                }
+            }
+            if !verdict.record(check, result.is_ok()) {
+                break;
+            }
+        }
+        verdict.ms = start.elapsed().as_millis();
+        report(&verdict);
+        verdict
+    }
+
+    async fn check(&mut self, check: Check) -> Result<(), &'static str> {
+        match check {
+            Check::Rng => self.rng(),
+            Check::I2c => self.i2c().await,
+            Check::Adc => self.adc(),
+            #[cfg(feature = "factory")]
+            Check::Spi => {
+                let Fixture { spi, cs } = &mut self.hardware.fixture;
+                selftestloopback::spi_loopback_test(spi, cs).await
+            }
+            Check::Kv => self.kv().await,
+            Check::WifiScan => self.wifi_scan().await,
+            Check::Association => self.association().await,
+            Check::Dhcp => self.dhcp().await,
+            Check::Dns => self.dns().await,
+            Check::Tls => self.tls().await,
+            Check::Sntp => self.sntp().await,
+        }
+    }
+
+    fn rng(&mut self) -> Result<(), &'static str> {
+        let mut samples = [0u32; RNG_SAMPLES];
+        for sample in &mut samples {
+            *sample = self.rng.random();
+        }
+        if samples.iter().all(|&sample| sample == samples[0]) {
+            return Err("the same value every time");
+        }
+        Ok(())
+    }
+
+    async fn i2c(&mut self) -> Result<(), &'static str> {
+        let mut i2c = self.hardware.i2c.lock().await;
+        if i2c.write(ds3231::ADDRESS, &[]).await.is_err() {
+            return Err("no RTC");
+        }
+        if i2c.write(ip5306::ADDRESS, &[]).await.is_err() {
+            return Err("no PMIC");
+        }
+        Ok(())
+    }
+
+    fn adc(&mut self) -> Result<(), &'static str> {
+        // A touch has to take the reading below `baseline - threshold`.
+        if self.hardware.touch_baseline <= self.hardware.touch_threshold {
+            return Err("touch pad baseline too low");
+        }
+        Ok(())
+    }
+
+    async fn kv(&mut self) -> Result<(), &'static str> {
+        let value = self.rng.random().to_le_bytes();
+        if let Err(e) = kv::set(SCRATCH_KEY, &value).await {
+            println!("selftest: kv write: {:?}", e);
+            return Err("write failed");
+        }
+        let mut read = [0u8; 4];
+        let result = kv::get(SCRATCH_KEY, &mut read).await;
+        let _ = kv::remove(SCRATCH_KEY).await;
+        match result {
+            Ok(Some(len)) if read[..len] == value => Ok(()),
+            Ok(_) => Err("read back something else"),
+            Err(e) => {
+                println!("selftest: kv read: {:?}", e);
+                Err("read failed")
+            }
+        }
+    }
+
+    async fn wifi_scan(&mut self) -> Result<(), &'static str> {
+        let mut controller = station::lock().await;
+        let controller = controller.as_mut().ok_or("no controller")?;
+        let (_, found) = controller.scan_n::<1>().await.map_err(|_| "scan failed")?;
+        if found == 0 {
+            return Err("no access points");
+        }
+        Ok(())
+    }
+
+    async fn association(&mut self) -> Result<(), &'static str> {
+        let mut controller = station::lock().await;
+        let controller = controller.as_mut().ok_or("no controller")?;
+        if matches!(controller.is_connected(), Ok(true)) {
+            return Ok(());
+        }
+        with_timeout(ASSOCIATION_TIMEOUT, controller.connect())
+            .await
+            .map_err(|_| "timed out")?
+            .map_err(|_| "not connected")
+    }
+
+    async fn dhcp(&mut self) -> Result<(), &'static str> {
+        if self.stack.config_v4().is_none() {
+            with_timeout(DHCP_TIMEOUT, self.stack.wait_config_up())
+                .await
+                .map_err(|_| "no address")?;
+        }
+        Ok(())
+    }
+
+    async fn dns(&mut self) -> Result<(), &'static str> {
+        let url = https::parse_url(PROBE_URL).ok_or("bad probe URL")?;
+        match resolver::resolve(self.stack, url.host).await {
+            Ok(_) => Ok(()),
+            Err(DnsError::Timeout) => Err("timed out"),
+            Err(DnsError::NotFound) => Err("no address"),
+            Err(_) => Err("lookup failed"),
+        }
+    }
+
+    async fn tls(&mut self) -> Result<(), &'static str> {
+        let settings = settings::current();
+        let url = match settings.upload_url.as_str() {
+            "" => PROBE_URL,
+            url => url,
+        };
+        let mut response = [0u8; 512];
+        match https::get(self.stack, url, &mut response).await {
+            Ok(_) => Ok(()),
+            // Past the handshake; what the server makes of the GET is not
+            // part of the check.
+            Err(e) if matches!(e.phase(), Phase::Request | Phase::Response) => Ok(()),
+            Err(e) => Err(diag::classify(&e).describe()),
+        }
+    }
+
+    async fn sntp(&mut self) -> Result<(), &'static str> {
+        sntp::sync(self.stack).await.map_err(|e| match e {
+            SntpError::Dns(DnsError::Timeout) | SntpError::Timeout => "timed out",
+            SntpError::Dns(_) => "server not found",
+            SntpError::Socket | SntpError::BadResponse => "no usable reply",
+            SntpError::Rejected(Rejected::KissOfDeath) => "server refused to answer",
+            SntpError::Rejected(_) => "server not accurate enough",
+            SntpError::Disagree => "servers disagree",
+        })
+    }
+}
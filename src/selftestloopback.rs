@@ -0,0 +1,41 @@
+// The self-test's `spi` check (src/selftest.rs runs it on the fixture),
+// over any SPI bus and chip select.
+
+use embedded_hal::digital::OutputPin;
+use embedded_hal_async::spi::SpiBus;
+
+pub const LOOPBACK_LEN: usize = 64;
+
+/// What goes out: both levels on every line, a walking one, and each
+/// byte's index, so a stuck or crossed bit and a slipped byte all show.
+pub fn pattern() -> [u8; LOOPBACK_LEN] {
+    core::array::from_fn(|i| match i % 4 {
+        0 => 0x55,
+        1 => 0xAA,
+        2 => 1 << (i / 4 % 8),
+        _ => i as u8,
+    })
+}
+
+/// Sends `LOOPBACK_LEN` bytes with `cs` low and checks that the same bytes
+/// came back, which they only do with MOSI bridged to MISO. `cs` goes high
+/// again whatever the transfer did.
+pub async fn spi_loopback_test<S: SpiBus, P: OutputPin>(
+    spi: &mut S,
+    cs: &mut P,
+) -> Result<(), &'static str> {
+    let sent = pattern();
+    let mut read = [0u8; LOOPBACK_LEN];
+    cs.set_low().map_err(|_| "chip select failed")?;
+    let result = spi.transfer(&mut read, &sent).await;
+    let released = cs.set_high();
+    result.map_err(|_| "transfer failed")?;
+    released.map_err(|_| "chip select failed")?;
+    if read.iter().all(|&b| b == read[0]) {
+        return Err("nothing came back");
+    }
+    if read != sent {
+        return Err("read back something else");
+    }
+    Ok(())
+}
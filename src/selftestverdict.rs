@@ -0,0 +1,102 @@
+// The self-test's checks and its verdict (src/selftest.rs runs them): their
+// names and order, which ones end a run, and the `SELFTEST` line.
+
+use core::fmt::{self, Write};
+
+const FORMAT_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Check {
+    Rng,
+    I2c,
+    Adc,
+    #[cfg(feature = "factory")]
+    Spi,
+    Kv,
+    WifiScan,
+    Association,
+    Dhcp,
+    Dns,
+    Tls,
+    Sntp,
+}
+
+pub const CHECKS: &[Check] = &[
+    Check::Rng,
+    Check::I2c,
+    Check::Adc,
+    #[cfg(feature = "factory")]
+    Check::Spi,
+    Check::Kv,
+    Check::WifiScan,
+    Check::Association,
+    Check::Dhcp,
+    Check::Dns,
+    Check::Tls,
+    Check::Sntp,
+];
+
+impl Check {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Check::Rng => "rng",
+            Check::I2c => "i2c",
+            Check::Adc => "adc",
+            #[cfg(feature = "factory")]
+            Check::Spi => "spi",
+            Check::Kv => "kv",
+            Check::WifiScan => "wifi_scan",
+            Check::Association => "association",
+            Check::Dhcp => "dhcp",
+            Check::Dns => "dns",
+            Check::Tls => "tls",
+            Check::Sntp => "sntp",
+        }
+    }
+
+    /// Whether the checks after this one are pointless once it has failed.
+    pub fn is_hard(self) -> bool {
+        match self {
+            Check::I2c | Check::Adc | Check::WifiScan | Check::Sntp => false,
+            #[cfg(feature = "factory")]
+            Check::Spi => false,
+            _ => true,
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Verdict {
+    pub passed: usize,
+    pub failed: Option<Check>,
+    pub ms: u64,
+}
+
+impl Verdict {
+    /// Counts one check's result; `false` once the run should end here.
+    pub fn record(&mut self, check: Check, passed: bool) -> bool {
+        if passed {
+            self.passed += 1;
+            return true;
+        }
+        self.failed.get_or_insert(check);
+        !check.is_hard()
+    }
+
+    /// Writes the verdict's JSON object (without the `SELFTEST ` prefix).
+    pub fn write<W: Write>(&self, out: &mut W) -> fmt::Result {
+        write!(
+            out,
+            "{{\"v\":{},\"ok\":{},\"passed\":{},\"of\":{},\"failed\":",
+            FORMAT_VERSION,
+            self.failed.is_none(),
+            self.passed,
+            CHECKS.len()
+        )?;
+        match self.failed {
+            Some(check) => write!(out, "\"{}\"", check.as_str())?,
+            None => out.write_str("null")?,
+        }
+        write!(out, ",\"ms\":{}}}", self.ms)
+    }
+}
@@ -0,0 +1,91 @@
+// Upload sequence numbers, so the server can tell when telemetry went
+// missing.
+//
+// Every reading gets the next number as `seq` when it is taken; a resend
+// carries the number it already has. Numbers are reserved in blocks of
+// `BLOCK`: before the first number of a block goes out, the end of the
+// block is written to the KV store under `upload.seq`. After a reset the
+// counter carries on from the stored end and what was left of the last
+// block is skipped: a number is never handed out twice, and a reset skips
+// fewer than `BLOCK`. One that comes between a block's write and its first
+// number skips that block as well.
+//
+// A server that tracks the numbers may answer an upload with
+// `X-Last-Seq`: the highest number up to which it has every reading. When
+// that is lower than the last number just sent, something in between was
+// lost and it is logged. A gap that starts at a boot, when `uptime_ms`
+// goes back to near zero, may just be a skipped block rather than lost
+// readings.
+//
+// The counter itself is in src/sequencecounter.rs.
+
+use core::cell::Cell;
+
+use critical_section::Mutex;
+use esp_println::println;
+
+use crate::kv;
+use crate::sequencecounter::Counter;
+
+const KEY: &str = "upload.seq";
+
+static COUNTER: Mutex<Cell<Option<Counter>>> = Mutex::new(Cell::new(None));
+static LAST_ACKED: Mutex<Cell<Option<u64>>> = Mutex::new(Cell::new(None));
+
+/// The number for a new reading. `None` if the store could not be read or
+/// written; the reading then goes out without one rather than with a
+/// number that could come round again.
+pub async fn next() -> Option<u64> {
+    let mut counter = match critical_section::with(|cs| COUNTER.borrow(cs).get()) {
+        Some(counter) => counter,
+        None => Counter::resume(load().await?),
+    };
+    if let Some(through) = counter.reservation_needed() {
+        if let Err(e) = kv::set(KEY, &through.to_le_bytes()).await {
+            println!("sequence: cannot reserve up to {}: {:?}", through, e);
+            return None;
+        }
+        counter.reserved(through);
+    }
+    let seq = counter.take();
+    critical_section::with(|cs| COUNTER.borrow(cs).set(Some(counter)));
+    Some(seq)
+}
+
+async fn load() -> Option<u64> {
+    let mut buf = [0u8; 8];
+    match kv::get(KEY, &mut buf).await {
+        Ok(None) => Some(0),
+        Ok(Some(8)) => Some(u64::from_le_bytes(buf)),
+        Ok(Some(len)) => {
+            println!("sequence: stored value has {} bytes, not 8", len);
+            None
+        }
+        Err(e) => {
+            println!("sequence: cannot read {}: {:?}", KEY, e);
+            None
+        }
+    }
+}
+
+/// Takes the server's `X-Last-Seq` after it accepted readings numbered up
+/// to `sent_through`.
+pub fn acknowledged(last_seq: &str, sent_through: u64) {
+    let Ok(last_seq) = last_seq.parse::<u64>() else {
+        println!("sequence: ignoring X-Last-Seq {:?}", last_seq);
+        return;
+    };
+    critical_section::with(|cs| LAST_ACKED.borrow(cs).set(Some(last_seq)));
+    if last_seq < sent_through {
+        println!(
+            "sequence: server reports a gap after {} (sent up to {})",
+            last_seq, sent_through
+        );
+    }
+}
+
+/// The last `X-Last-Seq` the server sent, if it sends them.
+#[cfg(feature = "api")]
+pub fn last_acked() -> Option<u64> {
+    critical_section::with(|cs| LAST_ACKED.borrow(cs).get())
+}
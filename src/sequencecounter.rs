@@ -0,0 +1,41 @@
+// The counter behind upload sequence numbers (src/sequence.rs), apart from
+// the KV store it reserves its blocks in: where it carries on after a
+// reset, and when the next block has to be persisted.
+
+pub const BLOCK: u64 = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Counter {
+    next: u64,
+    /// Numbers below this are persisted as possibly used.
+    reserved: u64,
+}
+
+impl Counter {
+    /// After a boot: anything below `stored` may have gone out already.
+    pub fn resume(stored: u64) -> Self {
+        Counter {
+            next: stored,
+            reserved: stored,
+        }
+    }
+
+    /// The end of the block to persist before `next` may go out, if the
+    /// current one is used up.
+    pub fn reservation_needed(&self) -> Option<u64> {
+        (self.next == self.reserved).then(|| self.reserved + BLOCK)
+    }
+
+    /// Once `through`, from `reservation_needed`, is persisted.
+    pub fn reserved(&mut self, through: u64) {
+        self.reserved = through;
+    }
+
+    /// The next number, once `reservation_needed` is `None`.
+    pub fn take(&mut self) -> u64 {
+        debug_assert!(self.next < self.reserved);
+        let seq = self.next;
+        self.next += 1;
+        seq
+    }
+}
@@ -0,0 +1,844 @@
+// Runtime settings, their persisted copy in the KV store, and remote updates.
+//
+// A remote update goes through three steps so the device never runs on a
+// half-applied config:
+//
+// 1. `stage` parses and validates the whole document into a candidate.
+// 2. `apply_pending` swaps it in at a point where no request is in flight.
+// 3. `confirm` persists it once an upload under it has succeeded.
+//
+// Until step 3 the old settings are still the persisted ones, so a device that
+// never gets there boots back into them. The unconfirmed revision is noted in
+// the KV store on apply, so that boot can tell and refuse it from then on.
+//
+// The persisted copy is the only local source. There is no SD card, nor a
+// FAT driver to read one with, so a deployment that does not want the
+// build's defaults sends its settings through the config poll or
+// `PUT /api/v1/config`, and the Wi-Fi credentials come from the build.
+//
+// Persisted and remote settings use the same format, one `key=value` per line:
+//
+//     revision=7
+//     interval_s=60
+//     upload_url=https://example.com/telemetry
+//     config_url=https://example.com/config
+//     verify_config=false
+//     log_level=info
+//     log_format=text
+//     quiet_hours=22-6
+//     maintenance=02:00-04:00
+//     no_clock=defer
+//     batch=ndjson,8,1024,600
+//     allow_downgrade=false
+//     reboot_at=off
+//     wifi_hidden=false
+//     wifi_auth=auto
+//     wifi_debug_ap=off
+//     header=X-Tenant: acme
+//     label=site=berlin
+//     compress=gzip
+//     schema=auto
+//
+// `verify_config`, `log_format`, `maintenance`, `no_clock`, `batch`,
+// `allow_downgrade`, `reboot_at`, the `wifi_` keys, `header`, `label`,
+// `compress` and `schema` came later and may be left out.
+//
+// `quiet_hours` is `<start>-<end>`, in UTC hours, or `off`: the hours in
+// which no readings go out (src/quiethours.rs).
+//
+// With `verify_config=true` a config poll answer has to carry the SHA-256
+// of its body, and match it (src/https.rs).
+//
+// `log_format` is `text` or `binary`, how the log ring keeps entries
+// (src/logring.rs).
+//
+// `header` may come more than once, one line per header to send with every
+// request (src/headers.rs).
+//
+// So may `label`, one `key=value` label each that readings and metrics
+// carry, over the build's LABELS (src/labels.rs).
+//
+// `reboot_at` is a daily restart time, UTC `HH:MM` like `maintenance`
+// (src/restart.rs).
+//
+// `wifi_auth` is `auto`, `wpa2`, `wpa3` or `wpa2wpa3-mixed`; all but `auto`
+// need a PASSWORD of at least 8 characters (src/station.rs).
+//
+// `wifi_debug_ap` is `off` or a number of minutes, up to
+// `MAX_DEBUG_AP_MINUTES`, that every boot keeps a debugging access point up
+// for (src/debugap.rs). The `wifi_` keys take effect at the next boot.
+//
+// `compress` is `gzip` or `off`, the default: whether uploads go gzipped
+// where that makes them smaller (src/uploader.rs).
+//
+// `schema` is `auto`, the default, or the version of the readings' layout
+// to write whatever the server says (src/telemetry.rs).
+
+use core::cell::RefCell;
+use core::fmt::{self, Write as _};
+
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use esp_println::println;
+use heapless::String;
+use log::LevelFilter;
+
+use crate::batch::BatchPolicy;
+use crate::clock;
+use crate::headers::{self, Headers};
+use crate::https;
+use crate::kv;
+use crate::labels::{self, Labels};
+use crate::logring::{self, LogFormat};
+use crate::maintenance::{self, NoClockPolicy, Window};
+use crate::quiethours::QuietHours;
+use crate::station::{self, WifiAuth};
+
+pub const MAX_URL_LEN: usize = 128;
+
+/// Large enough for a `Settings` with both URLs, the headers and the labels
+/// at full length; a `header=` line is six bytes longer than the header, a
+/// `label=` line as much longer than the label. No more than a KV value.
+pub const MAX_DOCUMENT_LEN: usize =
+    640 + headers::MAX_LEN + 6 * headers::MAX_COUNT + labels::MAX_LEN + 6 * labels::MAX_COUNT;
+const _: () = assert!(MAX_DOCUMENT_LEN <= kv::MAX_VALUE_LEN);
+
+pub const MIN_INTERVAL_S: u32 = 10;
+pub const MAX_DEBUG_AP_MINUTES: u16 = 240;
+pub const MAX_INTERVAL_S: u32 = 24 * 60 * 60;
+
+const DEFAULT_INTERVAL_S: u32 = 60;
+/// Verbose on the bench, quiet in the field.
+const DEFAULT_LOG_LEVEL: LevelFilter = if cfg!(feature = "dev") {
+    LevelFilter::Debug
+} else if cfg!(feature = "release-prod") {
+    LevelFilter::Warn
+} else {
+    LevelFilter::Info
+};
+
+const KEY_SETTINGS: &str = "settings";
+/// Revision applied but not yet confirmed.
+const KEY_TRIAL: &str = "settings.trial";
+/// Revision that was never confirmed and must not be applied again.
+const KEY_REJECTED: &str = "settings.rejected";
+/// Staged document held back by the maintenance window.
+const KEY_PENDING: &str = "settings.pending";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SettingsError {
+    Missing(&'static str),
+    Invalid(&'static str),
+    /// Not newer than the running revision, or a revision that failed before.
+    StaleRevision(u32),
+    /// Valid on its own, but not with the rest; says why.
+    Incompatible(&'static str),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Settings {
+    /// Echoed in telemetry so the server can see which config a device runs.
+    pub revision: u32,
+    pub upload_interval_s: u32,
+    /// Empty to disable uploads.
+    pub upload_url: String<MAX_URL_LEN>,
+    /// Empty to disable config polling.
+    pub config_url: String<MAX_URL_LEN>,
+    /// Refuse config poll answers without a matching body digest.
+    pub verify_config: bool,
+    pub log_level: LevelFilter,
+    pub log_format: LogFormat,
+    /// No readings go out in these hours (src/quiethours.rs).
+    pub quiet_hours: Option<QuietHours>,
+    /// `None` lets risky operations run at any time.
+    pub maintenance_window: Option<Window>,
+    pub no_clock_policy: NoClockPolicy,
+    /// `None` sends every reading on its own.
+    pub batch: Option<BatchPolicy>,
+    /// Lets an update install an older version than the running one.
+    pub allow_downgrade: bool,
+    /// Daily restart, in minutes after midnight UTC; `None` for none.
+    pub reboot_at: Option<u16>,
+    /// Scans ask for hidden networks too.
+    pub wifi_hidden: bool,
+    pub wifi_auth: WifiAuth,
+    /// Minutes after boot the debug access point stays up; `None` for none.
+    pub wifi_debug_ap: Option<u16>,
+    /// Sent with every HTTPS request.
+    pub headers: Headers,
+    /// Without the build's; see `labels`.
+    pub labels: Labels,
+    /// Gzip upload bodies.
+    pub compress: bool,
+    /// The readings' layout; `None` to negotiate it.
+    pub schema: Option<u8>,
+}
+
+/// Which groups of fields differ between two `Settings`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Changes {
+    pub interval: bool,
+    pub endpoints: bool,
+    pub log_level: bool,
+    pub log_format: bool,
+    pub quiet_hours: bool,
+    pub maintenance: bool,
+    pub batch: bool,
+    pub allow_downgrade: bool,
+    pub reboot_at: bool,
+    pub wifi: bool,
+    pub headers: bool,
+    pub labels: bool,
+    pub compress: bool,
+    pub schema: bool,
+}
+
+impl Changes {
+    pub fn is_empty(&self) -> bool {
+        *self == Changes::default()
+    }
+}
+
+impl Settings {
+    /// Compile-time defaults, used until something has been persisted.
+    pub fn defaults() -> Self {
+        let mut upload_url = String::new();
+        let mut config_url = String::new();
+        // Too long a URL at build time simply leaves the feature off.
+        let _ = upload_url.push_str(option_env!("UPLOAD_URL").unwrap_or(""));
+        let _ = config_url.push_str(option_env!("CONFIG_URL").unwrap_or(""));
+        Self {
+            revision: 0,
+            upload_interval_s: DEFAULT_INTERVAL_S,
+            upload_url,
+            config_url,
+            verify_config: false,
+            log_level: DEFAULT_LOG_LEVEL,
+            log_format: LogFormat::Text,
+            quiet_hours: None,
+            maintenance_window: None,
+            no_clock_policy: NoClockPolicy::Allow,
+            batch: None,
+            allow_downgrade: false,
+            reboot_at: None,
+            wifi_hidden: false,
+            wifi_auth: WifiAuth::Auto,
+            wifi_debug_ap: None,
+            headers: Headers::new(),
+            labels: Labels::new(),
+            compress: false,
+            schema: None,
+        }
+    }
+
+    /// Parses a complete settings document. Every key must be present except
+    /// the optional ones noted above; unknown keys are ignored so newer
+    /// servers can add fields.
+    pub fn parse(text: &str) -> Result<Self, SettingsError> {
+        let mut revision = None;
+        let mut interval = None;
+        let mut upload_url = None;
+        let mut config_url = None;
+        let mut verify_config = false;
+        let mut log_level = None;
+        let mut log_format = LogFormat::Text;
+        let mut quiet_hours = None;
+        let mut maintenance_window = None;
+        let mut no_clock_policy = NoClockPolicy::Allow;
+        let mut batch = None;
+        let mut allow_downgrade = false;
+        let mut reboot_at = None;
+        let mut wifi_hidden = false;
+        let mut wifi_auth = WifiAuth::Auto;
+        let mut wifi_debug_ap = None;
+        let mut headers = Headers::new();
+        let mut labels = Labels::new();
+        let mut compress = false;
+        let mut schema = None;
+
+        for line in text.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let Some((key, value)) = line.split_once('=') else {
+                return Err(SettingsError::Invalid("line without `=`"));
+            };
+            let value = value.trim();
+            match key.trim() {
+                "revision" => {
+                    revision = Some(
+                        value
+                            .parse()
+                            .map_err(|_| SettingsError::Invalid("revision"))?,
+                    )
+                }
+                "interval_s" => {
+                    interval = Some(
+                        value
+                            .parse()
+                            .map_err(|_| SettingsError::Invalid("interval_s"))?,
+                    )
+                }
+                "upload_url" => upload_url = Some(url(value, "upload_url")?),
+                "config_url" => config_url = Some(url(value, "config_url")?),
+                "verify_config" => {
+                    verify_config = value
+                        .parse()
+                        .map_err(|_| SettingsError::Invalid("verify_config"))?
+                }
+                "log_level" => {
+                    log_level = Some(
+                        value
+                            .parse()
+                            .map_err(|_| SettingsError::Invalid("log_level"))?,
+                    )
+                }
+                "log_format" => {
+                    log_format =
+                        LogFormat::parse(value).ok_or(SettingsError::Invalid("log_format"))?
+                }
+                "quiet_hours" => quiet_hours = Some(parse_quiet_hours(value)?),
+                "maintenance" if value == "off" => maintenance_window = None,
+                "maintenance" => {
+                    maintenance_window =
+                        Some(Window::parse(value).ok_or(SettingsError::Invalid("maintenance"))?)
+                }
+                "no_clock" => {
+                    no_clock_policy = match value {
+                        "defer" => NoClockPolicy::Defer,
+                        "allow" => NoClockPolicy::Allow,
+                        _ => return Err(SettingsError::Invalid("no_clock")),
+                    }
+                }
+                "batch" if value == "off" => batch = None,
+                "batch" => {
+                    batch = Some(BatchPolicy::parse(value).ok_or(SettingsError::Invalid("batch"))?)
+                }
+                "allow_downgrade" => {
+                    allow_downgrade = value
+                        .parse()
+                        .map_err(|_| SettingsError::Invalid("allow_downgrade"))?
+                }
+                "reboot_at" if value == "off" => reboot_at = None,
+                "reboot_at" => {
+                    reboot_at = Some(
+                        maintenance::parse_time(value)
+                            .ok_or(SettingsError::Invalid("reboot_at"))?,
+                    )
+                }
+                "wifi_hidden" => {
+                    wifi_hidden = value
+                        .parse()
+                        .map_err(|_| SettingsError::Invalid("wifi_hidden"))?
+                }
+                "wifi_auth" => {
+                    wifi_auth = WifiAuth::parse(value).ok_or(SettingsError::Invalid("wifi_auth"))?
+                }
+                "wifi_debug_ap" if value == "off" => wifi_debug_ap = None,
+                "wifi_debug_ap" => {
+                    wifi_debug_ap = Some(
+                        value
+                            .parse()
+                            .map_err(|_| SettingsError::Invalid("wifi_debug_ap"))?,
+                    )
+                }
+                "header" => headers
+                    .add(value)
+                    .map_err(|_| SettingsError::Invalid("header"))?,
+                "label" => labels
+                    .add(value)
+                    .map_err(|_| SettingsError::Invalid("label"))?,
+                "compress" => {
+                    compress = match value {
+                        "gzip" => true,
+                        "off" => false,
+                        _ => return Err(SettingsError::Invalid("compress")),
+                    }
+                }
+                "schema" => {
+                    schema = match value {
+                        "auto" => None,
+                        _ => Some(
+                            value
+                                .parse()
+                                .ok()
+                                .filter(|&version| version > 0)
+                                .ok_or(SettingsError::Invalid("schema"))?,
+                        ),
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        let settings = Self {
+            revision: revision.ok_or(SettingsError::Missing("revision"))?,
+            upload_interval_s: interval.ok_or(SettingsError::Missing("interval_s"))?,
+            upload_url: upload_url.ok_or(SettingsError::Missing("upload_url"))?,
+            config_url: config_url.ok_or(SettingsError::Missing("config_url"))?,
+            verify_config,
+            log_level: log_level.ok_or(SettingsError::Missing("log_level"))?,
+            log_format,
+            quiet_hours: quiet_hours.ok_or(SettingsError::Missing("quiet_hours"))?,
+            maintenance_window,
+            no_clock_policy,
+            batch,
+            allow_downgrade,
+            reboot_at,
+            wifi_hidden,
+            wifi_auth,
+            wifi_debug_ap,
+            headers,
+            labels,
+            compress,
+            schema,
+        };
+        settings.validate()?;
+        Ok(settings)
+    }
+
+    pub fn validate(&self) -> Result<(), SettingsError> {
+        if !(MIN_INTERVAL_S..=MAX_INTERVAL_S).contains(&self.upload_interval_s) {
+            return Err(SettingsError::Invalid("interval_s"));
+        }
+        if let Some(quiet) = self.quiet_hours {
+            if quiet.start_hour > 23 || quiet.end_hour > 23 {
+                return Err(SettingsError::Invalid("quiet_hours"));
+            }
+        }
+        if self.wifi_auth.needs_passphrase() && crate::PASSWORD.len() < station::MIN_PASSPHRASE_LEN
+        {
+            return Err(SettingsError::Incompatible(
+                "wifi_auth other than auto needs a PASSWORD of at least 8 characters",
+            ));
+        }
+        if let Some(minutes) = self.wifi_debug_ap {
+            if !(1..=MAX_DEBUG_AP_MINUTES).contains(&minutes) {
+                return Err(SettingsError::Invalid("wifi_debug_ap"));
+            }
+            #[cfg(feature = "api")]
+            if crate::debugap::PASSWORD.is_none() {
+                return Err(SettingsError::Incompatible(
+                    "wifi_debug_ap needs a DEBUG_AP_PASSWORD of 8 to 63 characters",
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn write_to<W: fmt::Write>(&self, out: &mut W) -> fmt::Result {
+        writeln!(out, "revision={}", self.revision)?;
+        writeln!(out, "interval_s={}", self.upload_interval_s)?;
+        writeln!(out, "upload_url={}", self.upload_url)?;
+        writeln!(out, "config_url={}", self.config_url)?;
+        writeln!(out, "verify_config={}", self.verify_config)?;
+        writeln!(out, "log_level={}", self.log_level)?;
+        writeln!(out, "log_format={}", self.log_format.as_str())?;
+        match self.quiet_hours {
+            Some(q) => writeln!(out, "quiet_hours={}-{}", q.start_hour, q.end_hour)?,
+            None => writeln!(out, "quiet_hours=off")?,
+        }
+        match self.maintenance_window {
+            Some(window) => writeln!(out, "maintenance={}", window)?,
+            None => writeln!(out, "maintenance=off")?,
+        }
+        match self.no_clock_policy {
+            NoClockPolicy::Defer => writeln!(out, "no_clock=defer")?,
+            NoClockPolicy::Allow => writeln!(out, "no_clock=allow")?,
+        }
+        match self.batch {
+            Some(policy) => writeln!(out, "batch={}", policy)?,
+            None => writeln!(out, "batch=off")?,
+        }
+        writeln!(out, "allow_downgrade={}", self.allow_downgrade)?;
+        match self.reboot_at {
+            Some(minute) => writeln!(out, "reboot_at={:02}:{:02}", minute / 60, minute % 60)?,
+            None => writeln!(out, "reboot_at=off")?,
+        }
+        writeln!(out, "wifi_hidden={}", self.wifi_hidden)?;
+        writeln!(out, "wifi_auth={}", self.wifi_auth.as_str())?;
+        match self.wifi_debug_ap {
+            Some(minutes) => writeln!(out, "wifi_debug_ap={}", minutes)?,
+            None => writeln!(out, "wifi_debug_ap=off")?,
+        }
+        for (name, value) in self.headers.iter() {
+            writeln!(out, "header={}: {}", name, value)?;
+        }
+        for (key, value) in self.labels.iter() {
+            writeln!(out, "label={}={}", key, value)?;
+        }
+        writeln!(
+            out,
+            "compress={}",
+            if self.compress { "gzip" } else { "off" }
+        )?;
+        match self.schema {
+            Some(version) => writeln!(out, "schema={}", version)?,
+            None => writeln!(out, "schema=auto")?,
+        }
+        Ok(())
+    }
+
+    pub fn diff(&self, other: &Settings) -> Changes {
+        Changes {
+            interval: self.upload_interval_s != other.upload_interval_s,
+            endpoints: self.upload_url != other.upload_url
+                || self.config_url != other.config_url
+                || self.verify_config != other.verify_config,
+            log_level: self.log_level != other.log_level,
+            log_format: self.log_format != other.log_format,
+            quiet_hours: self.quiet_hours != other.quiet_hours,
+            maintenance: self.maintenance_window != other.maintenance_window
+                || self.no_clock_policy != other.no_clock_policy,
+            batch: self.batch != other.batch,
+            allow_downgrade: self.allow_downgrade != other.allow_downgrade,
+            reboot_at: self.reboot_at != other.reboot_at,
+            wifi: self.wifi_hidden != other.wifi_hidden
+                || self.wifi_auth != other.wifi_auth
+                || self.wifi_debug_ap != other.wifi_debug_ap,
+            headers: self.headers != other.headers,
+            labels: self.labels != other.labels,
+            compress: self.compress != other.compress,
+            schema: self.schema != other.schema,
+        }
+    }
+}
+
+fn url(value: &str, key: &'static str) -> Result<String<MAX_URL_LEN>, SettingsError> {
+    if !value.is_empty() && https::parse_url(value).is_none() {
+        return Err(SettingsError::Invalid(key));
+    }
+    let mut url = String::new();
+    url.push_str(value)
+        .map_err(|_| SettingsError::Invalid(key))?;
+    Ok(url)
+}
+
+fn parse_quiet_hours(value: &str) -> Result<Option<QuietHours>, SettingsError> {
+    if value == "off" {
+        return Ok(None);
+    }
+    QuietHours::parse(value)
+        .map(Some)
+        .ok_or(SettingsError::Invalid("quiet_hours"))
+}
+
+struct State {
+    current: Option<Settings>,
+    pending: Option<Settings>,
+    /// Revision applied but not yet confirmed by a successful upload.
+    trial: Option<u32>,
+    rejected: Option<u32>,
+    /// Revision of the staged settings saved under `KEY_PENDING`.
+    pending_saved: Option<u32>,
+}
+
+static STATE: Mutex<CriticalSectionRawMutex, RefCell<State>> = Mutex::new(RefCell::new(State {
+    current: None,
+    pending: None,
+    trial: None,
+    rejected: None,
+    pending_saved: None,
+}));
+
+/// Makes the defaults current without reading the store, for safe mode
+/// (src/safemode.rs). Instead of `load`.
+pub fn use_defaults() {
+    let settings = Settings::defaults();
+    set_log_level(settings.log_level);
+    logring::set_log_format(settings.log_format);
+    println!("settings: safe mode, running the defaults");
+    STATE.lock(|state| state.borrow_mut().current = Some(settings));
+}
+
+/// Loads the persisted settings, or the defaults, and makes them current.
+/// Called once at boot, before anything reads `current`.
+pub async fn load() {
+    let settings = match read_document(KEY_SETTINGS).await {
+        Some(Ok(settings)) => settings,
+        Some(Err(e)) => {
+            println!(
+                "settings: persisted copy unusable ({:?}), using defaults",
+                e
+            );
+            Settings::defaults()
+        }
+        None => Settings::defaults(),
+    };
+
+    let mut rejected = read_revision(KEY_REJECTED).await;
+    if let Some(trial) = read_revision(KEY_TRIAL).await {
+        println!(
+            "settings: revision {} was never confirmed, rolled back to {}",
+            trial, settings.revision
+        );
+        rejected = Some(trial);
+        if let Err(e) = kv::set(KEY_REJECTED, &trial.to_le_bytes()).await {
+            println!("settings: could not record rejected revision: {:?}", e);
+        }
+        let _ = kv::remove(KEY_TRIAL).await;
+    }
+
+    // A staged update that was waiting for the maintenance window.
+    let pending = match read_document(KEY_PENDING).await {
+        Some(Ok(pending))
+            if pending.revision > settings.revision && rejected != Some(pending.revision) =>
+        {
+            println!(
+                "settings: revision {} still waiting for the maintenance window",
+                pending.revision
+            );
+            Some(pending)
+        }
+        Some(_) => {
+            let _ = kv::remove(KEY_PENDING).await;
+            None
+        }
+        None => None,
+    };
+
+    set_log_level(settings.log_level);
+    logring::set_log_format(settings.log_format);
+    println!("settings: running revision {}", settings.revision);
+
+    STATE.lock(|state| {
+        let mut state = state.borrow_mut();
+        state.current = Some(settings);
+        state.rejected = rejected;
+        state.pending_saved = pending.as_ref().map(|p| p.revision);
+        state.pending = pending;
+    });
+}
+
+/// The running settings' `headers`, without copying the rest.
+pub fn headers() -> Headers {
+    STATE.lock(|state| {
+        state
+            .borrow()
+            .current
+            .as_ref()
+            .map(|s| s.headers.clone())
+            .unwrap_or_default()
+    })
+}
+
+/// The running settings' labels over the build's.
+pub fn labels() -> Labels {
+    let own = STATE.lock(|state| {
+        state
+            .borrow()
+            .current
+            .as_ref()
+            .map(|s| s.labels.clone())
+            .unwrap_or_default()
+    });
+    own.merged(&labels::defaults())
+}
+
+pub fn current() -> Settings {
+    STATE.lock(|state| {
+        state
+            .borrow()
+            .current
+            .clone()
+            .unwrap_or_else(Settings::defaults)
+    })
+}
+
+/// Why `merged` could not put values into the running settings.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MergeError {
+    /// Not a key of the settings document.
+    UnknownKey,
+    /// A line break, which would start another line of the document.
+    MultiLine,
+    TooLong,
+}
+
+/// The running settings as a document, with the values in `fields` in
+/// place of their keys', and the next revision unless `fields` sets one.
+/// A key the document has on several lines, like `label`, gets the value
+/// on each.
+pub fn merged(fields: &[(&str, &str)]) -> Result<String<MAX_DOCUMENT_LEN>, MergeError> {
+    let current = current();
+    let mut running: String<MAX_DOCUMENT_LEN> = String::new();
+    // Cannot fail: `MAX_DOCUMENT_LEN` is sized for any `Settings`.
+    let _ = current.write_to(&mut running);
+    for (key, value) in fields {
+        if !running
+            .lines()
+            .any(|line| line.split_once('=').is_some_and(|(k, _)| k == *key))
+        {
+            return Err(MergeError::UnknownKey);
+        }
+        if value.contains(['\n', '\r']) {
+            return Err(MergeError::MultiLine);
+        }
+    }
+
+    let bump = !fields.iter().any(|(key, _)| *key == "revision");
+    let mut document = String::new();
+    running
+        .lines()
+        .try_for_each(|line| {
+            let (key, value) = line.split_once('=').unwrap_or((line, ""));
+            if key == "revision" && bump {
+                return writeln!(document, "revision={}", current.revision.saturating_add(1));
+            }
+            match fields.iter().find(|(k, _)| *k == key) {
+                Some((_, new)) => writeln!(document, "{}={}", key, new),
+                None => writeln!(document, "{}={}", key, value),
+            }
+        })
+        .map_err(|_| MergeError::TooLong)?;
+    Ok(document)
+}
+
+/// Parses a remote settings document and queues it for `apply_pending`.
+/// Returns the differences to the running settings.
+pub fn stage(text: &str) -> Result<Changes, SettingsError> {
+    let candidate = Settings::parse(text)?;
+    STATE.lock(|state| {
+        let mut state = state.borrow_mut();
+        let running = state.current.as_ref().map_or(0, |s| s.revision);
+        let newest = state
+            .pending
+            .as_ref()
+            .map_or(running, |p| p.revision.max(running));
+        if candidate.revision <= newest || state.rejected == Some(candidate.revision) {
+            return Err(SettingsError::StaleRevision(candidate.revision));
+        }
+        let changes = match &state.current {
+            Some(current) => current.diff(&candidate),
+            None => Changes::default(),
+        };
+        state.pending = Some(candidate);
+        Ok(changes)
+    })
+}
+
+/// Makes staged settings current, all fields at once. Callers run this only
+/// between cycles, when nothing is using the old values.
+///
+/// Outside the maintenance window of the running settings nothing changes;
+/// the staged settings are saved so they survive a reboot and are applied by
+/// a later call inside the window.
+pub async fn apply_pending() -> Option<Changes> {
+    if !maintenance::is_open(&current(), &clock::System) {
+        defer_pending().await;
+        return None;
+    }
+    let candidate = STATE.lock(|state| state.borrow_mut().pending.take())?;
+
+    // Noted before the switch, so a crash right after it still counts.
+    if let Err(e) = kv::set(KEY_TRIAL, &candidate.revision.to_le_bytes()).await {
+        println!(
+            "settings: not applying revision {}: {:?}",
+            candidate.revision, e
+        );
+        return None;
+    }
+
+    set_log_level(candidate.log_level);
+    logring::set_log_format(candidate.log_format);
+    let changes = STATE.lock(|state| {
+        let mut state = state.borrow_mut();
+        let changes = state
+            .current
+            .as_ref()
+            .map_or(Changes::default(), |c| c.diff(&candidate));
+        state.trial = Some(candidate.revision);
+        state.current = Some(candidate);
+        state.pending_saved = None;
+        changes
+    });
+    let _ = kv::remove(KEY_PENDING).await;
+    Some(changes)
+}
+
+async fn defer_pending() {
+    let Some(pending) = STATE.lock(|state| {
+        let state = state.borrow();
+        state
+            .pending
+            .clone()
+            .filter(|p| state.pending_saved != Some(p.revision))
+    }) else {
+        return;
+    };
+
+    println!(
+        "settings: revision {} deferred until the maintenance window",
+        pending.revision
+    );
+    match write_document(KEY_PENDING, &pending).await {
+        Ok(()) => STATE.lock(|state| state.borrow_mut().pending_saved = Some(pending.revision)),
+        Err(e) => println!("settings: could not save deferred revision: {:?}", e),
+    }
+}
+
+/// Persists the running settings if they are still on trial. Called after an
+/// upload under them has succeeded.
+pub async fn confirm() {
+    let Some((revision, settings)) = STATE.lock(|state| {
+        let state = state.borrow();
+        state.trial.zip(state.current.clone())
+    }) else {
+        return;
+    };
+
+    if let Err(e) = write_document(KEY_SETTINGS, &settings).await {
+        println!("settings: persisting revision {} failed: {:?}", revision, e);
+        return;
+    }
+    let _ = kv::remove(KEY_TRIAL).await;
+
+    STATE.lock(|state| state.borrow_mut().trial = None);
+    println!("settings: revision {} confirmed", revision);
+}
+
+#[derive(Debug)]
+enum WriteError {
+    TooLarge,
+    Kv(#[allow(dead_code)] kv::KvError),
+}
+
+async fn write_document(key: &str, settings: &Settings) -> Result<(), WriteError> {
+    let mut text: String<MAX_DOCUMENT_LEN> = String::new();
+    settings
+        .write_to(&mut text)
+        .map_err(|_| WriteError::TooLarge)?;
+    kv::set(key, text.as_bytes()).await.map_err(WriteError::Kv)
+}
+
+/// `None` if the key is not set or cannot be read.
+async fn read_document(key: &str) -> Option<Result<Settings, SettingsError>> {
+    let mut buf = [0u8; MAX_DOCUMENT_LEN];
+    match kv::get(key, &mut buf).await {
+        Ok(Some(len)) => Some(
+            core::str::from_utf8(&buf[..len])
+                .map_err(|_| SettingsError::Invalid("encoding"))
+                .and_then(Settings::parse),
+        ),
+        Ok(None) => None,
+        Err(e) => {
+            println!("settings: reading `{}` failed: {:?}", key, e);
+            None
+        }
+    }
+}
+
+fn set_log_level(level: LevelFilter) {
+    // This target has no compare-and-swap, so there is only the racy setter.
+    // It is fine as long as no two calls overlap.
+    critical_section::with(|_| unsafe { log::set_max_level_racy(level) });
+}
+
+async fn read_revision(key: &str) -> Option<u32> {
+    let mut buf = [0u8; 4];
+    match kv::get(key, &mut buf).await {
+        Ok(Some(4)) => Some(u32::from_le_bytes(buf)),
+        _ => None,
+    }
+}
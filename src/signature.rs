@@ -0,0 +1,429 @@
+// Ed25519 signature verification (RFC 8032), for signed updates.
+//
+// Verification only, so nothing here handles a secret and nothing has to
+// run in constant time. Field elements are five 51-bit limbs, points are in
+// extended twisted Edwards coordinates and use the one complete addition
+// formula for doubling too; the scalar multiplications are plain
+// double-and-add. One verification is some ten thousand field
+// multiplications, once per update.
+//
+// The check is the cofactorless one: `[s]B - [k]A` must encode to exactly
+// the `R` in the signature. Non-canonical `s` and `A`, and small-order
+// public keys, are rejected.
+//
+// The OTA key is built in from keys/ota_signing.pub, the raw 32-byte public
+// key. Make a key pair and extract it with
+//
+//     openssl genpkey -algorithm ed25519 -out ota-signing.pem
+//     openssl pkey -in ota-signing.pem -pubout -outform DER | tail -c 32 > keys/ota_signing.pub
+//
+// The file in the tree is all zeros, which is no valid key: until it is
+// replaced every update is refused.
+
+use sha2::{Digest, Sha512};
+
+/// Public key signed updates are checked against.
+pub const OTA_PUBLIC_KEY: &[u8; 32] = include_bytes!("../keys/ota_signing.pub");
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SignatureError {
+    /// The key is not a point of the curve, or of too small an order to
+    /// prove anything.
+    BadPublicKey,
+    /// `s` is not reduced; no signer produces that.
+    Malformed,
+    Mismatch,
+}
+
+/// Checks `signature` over `image` against `public_key`.
+pub fn verify_firmware_signature(
+    image: &[u8],
+    signature: &[u8; 64],
+    public_key: &[u8; 32],
+) -> Result<(), SignatureError> {
+    let a = Point::decode(public_key).ok_or(SignatureError::BadPublicKey)?;
+    if a.mul_by_cofactor().is_identity() {
+        return Err(SignatureError::BadPublicKey);
+    }
+    let (r, s) = signature.split_at(32);
+    let s: &[u8; 32] = s.try_into().unwrap();
+    if !is_reduced(s) {
+        return Err(SignatureError::Malformed);
+    }
+
+    let hash = Sha512::new()
+        .chain_update(r)
+        .chain_update(public_key)
+        .chain_update(image)
+        .finalize();
+    let k = reduce(hash.as_slice().try_into().unwrap());
+
+    let base = Point::decode(&BASE_POINT).unwrap();
+    let check = base.mul(s).add(&a.neg().mul(&k));
+    if check.encode() == r {
+        Ok(())
+    } else {
+        Err(SignatureError::Mismatch)
+    }
+}
+
+/// The standard base point, `y = 4/5` with `x` even.
+const BASE_POINT: [u8; 32] = {
+    let mut encoded = [0x66; 32];
+    encoded[0] = 0x58;
+    encoded
+};
+
+/// `-121665/121666`, the curve constant.
+const D: [u8; 32] = [
+    0xA3, 0x78, 0x59, 0x13, 0xCA, 0x4D, 0xEB, 0x75, 0xAB, 0xD8, 0x41, 0x41, 0x4D, 0x0A, 0x70, 0x00,
+    0x98, 0xE8, 0x79, 0x77, 0x79, 0x40, 0xC7, 0x8C, 0x73, 0xFE, 0x6F, 0x2B, 0xEE, 0x6C, 0x03, 0x52,
+];
+
+/// `2^((p - 1) / 4)`, a square root of -1.
+const SQRT_M1: [u8; 32] = [
+    0xB0, 0xA0, 0x0E, 0x4A, 0x27, 0x1B, 0xEE, 0xC4, 0x78, 0xE4, 0x2F, 0xAD, 0x06, 0x18, 0x43, 0x2F,
+    0xA7, 0xD7, 0xFB, 0x3D, 0x99, 0x00, 0x4D, 0x2B, 0x0B, 0xDF, 0xC1, 0x4F, 0x80, 0x24, 0x83, 0x2B,
+];
+
+/// `p - 2`, for inversion.
+const P_MINUS_2: [u8; 32] = {
+    let mut exponent = [0xFF; 32];
+    exponent[0] = 0xEB;
+    exponent[31] = 0x7F;
+    exponent
+};
+
+/// `(p - 5) / 8`, for square roots.
+const P_MINUS_5_OVER_8: [u8; 32] = {
+    let mut exponent = [0xFF; 32];
+    exponent[0] = 0xFD;
+    exponent[31] = 0x0F;
+    exponent
+};
+
+/// The group order, `2^252 + 27742317777372353535851937790883648493`, in
+/// 64-bit little-endian limbs.
+const L: [u64; 4] = [
+    0x5812_631A_5CF5_D3ED,
+    0x14DE_F9DE_A2F7_9CD6,
+    0x0000_0000_0000_0000,
+    0x1000_0000_0000_0000,
+];
+
+const MASK_51: u64 = (1 << 51) - 1;
+
+/// An element of GF(2^255 - 19): five limbs of 51 bits, each allowed a few
+/// bits of slack between reductions.
+#[derive(Clone, Copy)]
+struct Fe([u64; 5]);
+
+impl Fe {
+    const ZERO: Fe = Fe([0; 5]);
+    const ONE: Fe = Fe([1, 0, 0, 0, 0]);
+
+    /// The low 255 bits of `bytes`, little-endian.
+    fn from_bytes(bytes: &[u8; 32]) -> Fe {
+        let load = |at: usize| u64::from_le_bytes(bytes[at..at + 8].try_into().unwrap());
+        Fe([
+            load(0) & MASK_51,
+            (load(6) >> 3) & MASK_51,
+            (load(12) >> 6) & MASK_51,
+            (load(19) >> 1) & MASK_51,
+            (load(24) >> 12) & MASK_51,
+        ])
+    }
+
+    /// The canonical encoding, fully reduced.
+    fn to_bytes(self) -> [u8; 32] {
+        let mut h = self.carry().carry().0;
+        // 1 if h >= p: adding 19 then carries out of bit 255.
+        let mut q = (h[0] + 19) >> 51;
+        for limb in &h[1..] {
+            q = (limb + q) >> 51;
+        }
+        h[0] += 19 * q;
+        for i in 0..4 {
+            h[i + 1] += h[i] >> 51;
+            h[i] &= MASK_51;
+        }
+        h[4] &= MASK_51;
+
+        let mut bytes = [0u8; 32];
+        let mut acc: u128 = 0;
+        let mut bits = 0;
+        let mut at = 0;
+        for limb in h {
+            acc |= (limb as u128) << bits;
+            bits += 51;
+            while bits >= 8 && at < 32 {
+                bytes[at] = acc as u8;
+                acc >>= 8;
+                bits -= 8;
+                at += 1;
+            }
+        }
+        if at < 32 {
+            bytes[at] = acc as u8;
+        }
+        bytes
+    }
+
+    /// Brings every limb back to 51 bits, plus at most a small carry in the
+    /// lowest.
+    fn carry(self) -> Fe {
+        let mut h = self.0;
+        for i in 0..4 {
+            h[i + 1] += h[i] >> 51;
+            h[i] &= MASK_51;
+        }
+        h[0] += 19 * (h[4] >> 51);
+        h[4] &= MASK_51;
+        Fe(h)
+    }
+
+    fn add(&self, b: &Fe) -> Fe {
+        let (a, b) = (self.0, b.0);
+        Fe([
+            a[0] + b[0],
+            a[1] + b[1],
+            a[2] + b[2],
+            a[3] + b[3],
+            a[4] + b[4],
+        ])
+        .carry()
+    }
+
+    fn sub(&self, b: &Fe) -> Fe {
+        // Adding 4p first keeps every limb positive.
+        let (a, b) = (self.0, b.carry().0);
+        const FOUR_P_LOW: u64 = 4 * ((1 << 51) - 19);
+        const FOUR_P: u64 = 4 * MASK_51;
+        Fe([
+            a[0] + FOUR_P_LOW - b[0],
+            a[1] + FOUR_P - b[1],
+            a[2] + FOUR_P - b[2],
+            a[3] + FOUR_P - b[3],
+            a[4] + FOUR_P - b[4],
+        ])
+        .carry()
+    }
+
+    fn neg(&self) -> Fe {
+        Fe::ZERO.sub(self)
+    }
+
+    fn mul(&self, b: &Fe) -> Fe {
+        let (a, b) = (self.carry().0, b.carry().0);
+        let m = |x: u64, y: u64| x as u128 * y as u128;
+        // 2^255 = 19, so the limbs that overflow come back in times 19.
+        let b19 = [b[1] * 19, b[2] * 19, b[3] * 19, b[4] * 19];
+        let r = [
+            m(a[0], b[0]) + m(a[1], b19[3]) + m(a[2], b19[2]) + m(a[3], b19[1]) + m(a[4], b19[0]),
+            m(a[0], b[1]) + m(a[1], b[0]) + m(a[2], b19[3]) + m(a[3], b19[2]) + m(a[4], b19[1]),
+            m(a[0], b[2]) + m(a[1], b[1]) + m(a[2], b[0]) + m(a[3], b19[3]) + m(a[4], b19[2]),
+            m(a[0], b[3]) + m(a[1], b[2]) + m(a[2], b[1]) + m(a[3], b[0]) + m(a[4], b19[3]),
+            m(a[0], b[4]) + m(a[1], b[3]) + m(a[2], b[2]) + m(a[3], b[1]) + m(a[4], b[0]),
+        ];
+
+        let mut h = [0u64; 5];
+        let mut carry: u128 = 0;
+        for i in 0..5 {
+            let t = r[i] + carry;
+            h[i] = (t as u64) & MASK_51;
+            carry = t >> 51;
+        }
+        h[0] += (carry as u64) * 19;
+        Fe(h).carry()
+    }
+
+    fn square(&self) -> Fe {
+        self.mul(self)
+    }
+
+    /// `self` to the 255-bit little-endian `exponent`.
+    fn pow(&self, exponent: &[u8; 32]) -> Fe {
+        let mut acc = Fe::ONE;
+        for bit in (0..256).rev() {
+            acc = acc.square();
+            if exponent[bit / 8] >> (bit % 8) & 1 == 1 {
+                acc = acc.mul(self);
+            }
+        }
+        acc
+    }
+
+    fn invert(&self) -> Fe {
+        self.pow(&P_MINUS_2)
+    }
+
+    fn is_zero(&self) -> bool {
+        self.to_bytes() == [0; 32]
+    }
+
+    fn equals(&self, b: &Fe) -> bool {
+        self.to_bytes() == b.to_bytes()
+    }
+
+    /// The sign bit of the encoding: odd counts as negative.
+    fn is_negative(&self) -> bool {
+        self.to_bytes()[0] & 1 == 1
+    }
+}
+
+/// A point `(X:Y:Z:T)` with `x = X/Z`, `y = Y/Z` and `xy = T/Z`.
+#[derive(Clone, Copy)]
+struct Point {
+    x: Fe,
+    y: Fe,
+    z: Fe,
+    t: Fe,
+}
+
+impl Point {
+    const IDENTITY: Point = Point {
+        x: Fe::ZERO,
+        y: Fe::ONE,
+        z: Fe::ONE,
+        t: Fe::ZERO,
+    };
+
+    /// `None` unless `bytes` is the canonical encoding of a curve point.
+    fn decode(bytes: &[u8; 32]) -> Option<Point> {
+        let y = Fe::from_bytes(bytes);
+        let x_negative = bytes[31] >> 7 == 1;
+        let mut canonical = y.to_bytes();
+        canonical[31] |= bytes[31] & 0x80;
+        if canonical != *bytes {
+            return None;
+        }
+
+        // x^2 = (y^2 - 1) / (d y^2 + 1), with the root taken as in RFC 8032.
+        let y2 = y.square();
+        let u = y2.sub(&Fe::ONE);
+        let v = Fe::from_bytes(&D).mul(&y2).add(&Fe::ONE);
+        let v3 = v.square().mul(&v);
+        let v7 = v3.square().mul(&v);
+        let mut x = u.mul(&v3).mul(&u.mul(&v7).pow(&P_MINUS_5_OVER_8));
+        let vx2 = v.mul(&x.square());
+        if vx2.equals(&u.neg()) {
+            x = x.mul(&Fe::from_bytes(&SQRT_M1));
+        } else if !vx2.equals(&u) {
+            return None;
+        }
+        if x.is_zero() && x_negative {
+            return None;
+        }
+        if x.is_negative() != x_negative {
+            x = x.neg();
+        }
+        Some(Point {
+            x,
+            y,
+            z: Fe::ONE,
+            t: x.mul(&y),
+        })
+    }
+
+    fn encode(&self) -> [u8; 32] {
+        let z = self.z.invert();
+        let x = self.x.mul(&z);
+        let mut bytes = self.y.mul(&z).to_bytes();
+        bytes[31] |= (x.is_negative() as u8) << 7;
+        bytes
+    }
+
+    /// Complete for this curve, so it doubles as well.
+    fn add(&self, q: &Point) -> Point {
+        let d2 = Fe::from_bytes(&D).add(&Fe::from_bytes(&D));
+        let a = self.y.sub(&self.x).mul(&q.y.sub(&q.x));
+        let b = self.y.add(&self.x).mul(&q.y.add(&q.x));
+        let c = self.t.mul(&d2).mul(&q.t);
+        let d = self.z.add(&self.z).mul(&q.z);
+        let (e, f, g, h) = (b.sub(&a), d.sub(&c), d.add(&c), b.add(&a));
+        Point {
+            x: e.mul(&f),
+            y: g.mul(&h),
+            z: f.mul(&g),
+            t: e.mul(&h),
+        }
+    }
+
+    fn neg(&self) -> Point {
+        Point {
+            x: self.x.neg(),
+            t: self.t.neg(),
+            ..*self
+        }
+    }
+
+    /// `[scalar]self`, with a little-endian scalar.
+    fn mul(&self, scalar: &[u8; 32]) -> Point {
+        let mut acc = Point::IDENTITY;
+        for bit in (0..256).rev() {
+            acc = acc.add(&acc);
+            if scalar[bit / 8] >> (bit % 8) & 1 == 1 {
+                acc = acc.add(self);
+            }
+        }
+        acc
+    }
+
+    fn mul_by_cofactor(&self) -> Point {
+        let p2 = self.add(self);
+        let p4 = p2.add(&p2);
+        p4.add(&p4)
+    }
+
+    fn is_identity(&self) -> bool {
+        self.x.is_zero() && self.y.equals(&self.z)
+    }
+}
+
+/// Whether a little-endian scalar is below `L`.
+fn is_reduced(s: &[u8; 32]) -> bool {
+    for i in (0..4).rev() {
+        let limb = u64::from_le_bytes(s[i * 8..i * 8 + 8].try_into().unwrap());
+        if limb != L[i] {
+            return limb < L[i];
+        }
+    }
+    false
+}
+
+/// A 512-bit little-endian number modulo `L`, one bit at a time.
+fn reduce(wide: &[u8; 64]) -> [u8; 32] {
+    // Stays below L, so doubling it plus one still fits 256 bits.
+    let mut r = [0u64; 4];
+    for bit in (0..512).rev() {
+        let mut carry = wide[bit / 8] as u64 >> (bit % 8) & 1;
+        for limb in &mut r {
+            let next = *limb >> 63;
+            *limb = *limb << 1 | carry;
+            carry = next;
+        }
+        if !less_than_l(&r) {
+            let mut borrow = 0;
+            for (limb, l) in r.iter_mut().zip(L) {
+                let (v, b1) = limb.overflowing_sub(l);
+                let (v, b2) = v.overflowing_sub(borrow);
+                *limb = v;
+                borrow = (b1 | b2) as u64;
+            }
+        }
+    }
+    let mut out = [0u8; 32];
+    for (i, limb) in r.iter().enumerate() {
+        out[i * 8..i * 8 + 8].copy_from_slice(&limb.to_le_bytes());
+    }
+    out
+}
+
+fn less_than_l(r: &[u64; 4]) -> bool {
+    for i in (0..4).rev() {
+        if r[i] != L[i] {
+            return r[i] < L[i];
+        }
+    }
+    false
+}
@@ -0,0 +1,416 @@
+// Wall-clock time from SNTP (RFC 4330).
+//
+// A sync records the server's time next to the local `Instant`; `now_unix_ms`
+// extrapolates from there. The clock can also be seeded from a battery-backed
+// RTC at boot; until either has happened there is no wall clock at all, and
+// callers have to handle that.
+//
+// So the sources come in order: the DS3231 at boot, if it holds a time,
+// then SNTP, which replaces it. Each says how far off it may be, and `now`
+// adds the drift of the local crystal since (`DRIFT_PPM`); setting the clock
+// logs the source and that bound. An RTC time is only good to its whole
+// seconds and to however well it was set, so only an SNTP time is good
+// enough to judge a certificate's validity dates by.
+//
+// A reply only sets the clock if the server is close to a reference clock
+// and says it is: a stratum from 1 to `MAX_STRATUM` (0 is a kiss-o'-death),
+// a precision of `MAX_PRECISION` (2^-10 s, about a millisecond) or finer,
+// and a round trip, less the time the server held the request, of at most
+// `MAX_DELAY`; beyond that the midpoint guess below is too coarse. The
+// servers are NTP_SERVERS at build time, comma-separated, or
+// `DEFAULT_SERVER`. A sync asks the first `MAX_SERVERS` of them that
+// resolve, each of them.
+//
+// One exchange is only as good as its network delay, so the first sync of
+// a boot is a burst: `BURST` rounds `BURST_INTERVAL` apart. A server's
+// samples more than 1.5 standard deviations from their mean are dropped,
+// and the mean of the rest is that server's estimate, give or take the
+// largest distance of a kept sample from it plus half its delay. After
+// that one round every `RESYNC_INTERVAL` keeps the drift down.
+//
+// One server is as good as its word, so with several the estimates are
+// checked against each other (`select`): one more than
+// `MAX_DISAGREEMENT_MS` from the median of them all is taken for a wrong
+// clock, or a liar, and dropped. Unless most of the servers that answered
+// are left, the sync fails and the clock stays as it was: with two that
+// disagree there is no telling which one is right. The clock is set from
+// the median of those left.
+//
+// A correction of up to `STEP_THRESHOLD_MS` is slewed rather than
+// stepped: the clock keeps reading on from where it was, and works the
+// correction in at `MAX_SLEW_PPM`, so a wall-clock deadline does not jump
+// and a time read twice never goes backwards. Until it has, `now` counts
+// what is left of it as uncertainty. Anything larger steps, and so does
+// the very first time.
+//
+// The combining, the selection and the slew are in src/sntpselect.rs.
+//
+// The latest sync's time and uncertainty are also kept in RTC fast memory
+// (`last_good`), with a magic and a CRC like the restart note. Light sleep
+// and resets short of a power cycle leave it alone; main warns when the
+// battery-backed RTC reads earlier than it at boot.
+
+use core::cell::Cell;
+use core::ptr::addr_of_mut;
+
+use critical_section::Mutex;
+use embassy_net::udp::{PacketMetadata, UdpSocket};
+use embassy_net::Ipv4Address;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::signal::Signal;
+use embassy_time::{with_timeout, Duration, Instant, Timer};
+use esp_hal::macros::ram;
+use esp_hal::rom::crc::crc32_le;
+use esp_println::println;
+use heapless::Vec;
+
+use crate::ntp::{self, Reply, PACKET_LEN};
+use crate::resolver::{self, DnsError};
+use crate::sntpselect::{self, Estimate, Sample, BURST, MAX_SERVERS};
+use crate::NetStack;
+
+const DEFAULT_SERVER: &str = "pool.ntp.org";
+const NTP_PORT: u16 = 123;
+
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(5);
+
+const MAX_STRATUM: u8 = 4;
+/// log2 of seconds, as the packet carries it.
+const MAX_PRECISION: i8 = -10;
+const MAX_DELAY: Duration = Duration::from_millis(500);
+
+const BURST_INTERVAL: Duration = Duration::from_secs(2);
+
+/// How fast the local clock may run off: the crystal's tolerance, with
+/// margin.
+#[cfg(feature = "console")]
+const DRIFT_PPM: u64 = 20;
+
+/// A sync older than this is worth repeating; the local clock drifts.
+pub const RESYNC_INTERVAL: Duration = Duration::from_secs(30 * 60);
+
+const LAST_GOOD_MAGIC: u32 = 0x534e_5450;
+// magic | unix_ms (u64) | uncertainty_ms | crc
+const LAST_GOOD_LEN: usize = 20;
+
+#[ram(rtc_fast, uninitialized)]
+static mut LAST_GOOD: [u8; LAST_GOOD_LEN] = [0; LAST_GOOD_LEN];
+
+#[derive(Debug)]
+pub enum SntpError {
+    Dns(DnsError),
+    Socket,
+    Timeout,
+    /// Not a server reply, or from a server that is not synchronized.
+    BadResponse,
+    Rejected(Rejected),
+    /// The servers answered with times too far apart to tell which is
+    /// right.
+    Disagree,
+}
+
+/// Why a well-formed reply was not good enough.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rejected {
+    KissOfDeath,
+    Stratum(u8),
+    Precision(i8),
+    /// Milliseconds.
+    Delay(u64),
+}
+
+/// Where the current time came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Source {
+    Rtc,
+    Sntp,
+}
+
+#[derive(Clone, Copy)]
+struct Anchor {
+    unix_ms: u64,
+    at: Instant,
+    source: Source,
+    uncertainty_ms: u32,
+    /// A correction still to be worked in from `at` on.
+    slew_ms: i64,
+}
+
+impl Anchor {
+    /// The time `elapsed_ms` after `at`.
+    fn unix_ms(&self, elapsed_ms: u64) -> u64 {
+        (self.unix_ms as i64 + elapsed_ms as i64 + sntpselect::slewed(self.slew_ms, elapsed_ms))
+            as u64
+    }
+}
+
+/// The time, where it came from, and how far off it may be.
+#[cfg(feature = "console")]
+#[derive(Debug, Clone, Copy)]
+pub struct Now {
+    pub unix_ms: u64,
+    pub source: Source,
+    pub uncertainty_ms: u64,
+}
+
+static ANCHOR: Mutex<Cell<Option<Anchor>>> = Mutex::new(Cell::new(None));
+
+/// Unix milliseconds of the latest successful sync.
+static SYNCED: Signal<CriticalSectionRawMutex, u64> = Signal::new();
+
+/// Current Unix time in milliseconds, or `None` before the first sync.
+pub fn now_unix_ms() -> Option<u64> {
+    let anchor = critical_section::with(|cs| ANCHOR.borrow(cs).get())?;
+    Some(anchor.unix_ms(anchor.at.elapsed().as_millis()))
+}
+
+#[cfg(feature = "console")]
+pub fn now() -> Option<Now> {
+    let anchor = critical_section::with(|cs| ANCHOR.borrow(cs).get())?;
+    let elapsed_ms = anchor.at.elapsed().as_millis();
+    let unslewed = anchor.slew_ms - sntpselect::slewed(anchor.slew_ms, elapsed_ms);
+    Some(Now {
+        unix_ms: anchor.unix_ms(elapsed_ms),
+        source: anchor.source,
+        uncertainty_ms: anchor.uncertainty_ms as u64
+            + elapsed_ms * DRIFT_PPM / 1_000_000
+            + unslewed.unsigned_abs(),
+    })
+}
+
+/// Sets the clock to `unix_ms` as of now, give or take `uncertainty_ms`.
+pub fn set_time(unix_ms: u64, source: Source, uncertainty_ms: u32) {
+    set_anchor(Anchor {
+        unix_ms,
+        at: Instant::now(),
+        source,
+        uncertainty_ms,
+        slew_ms: 0,
+    });
+}
+
+fn set_anchor(anchor: Anchor) {
+    critical_section::with(|cs| ANCHOR.borrow(cs).set(Some(anchor)));
+    if anchor.slew_ms != 0 {
+        println!(
+            "clock: slewing {} ms from {:?}, +-{} ms",
+            anchor.slew_ms, anchor.source, anchor.uncertainty_ms
+        );
+    } else {
+        println!(
+            "clock: set from {:?}, +-{} ms",
+            anchor.source, anchor.uncertainty_ms
+        );
+    }
+}
+
+/// Whether the clock has not come from SNTP yet, or the last sync is older
+/// than `RESYNC_INTERVAL`.
+pub fn needs_sync() -> bool {
+    match critical_section::with(|cs| ANCHOR.borrow(cs).get()) {
+        Some(anchor) => anchor.source != Source::Sntp || anchor.at.elapsed() >= RESYNC_INTERVAL,
+        None => true,
+    }
+}
+
+/// Waits for the next successful `sync` and returns the time it set.
+pub async fn wait_for_sync() -> u64 {
+    SYNCED.wait().await
+}
+
+/// Sets the clock from SNTP: a burst of rounds if it has not come from
+/// SNTP yet, one round otherwise, each asking every server. Fails with the
+/// last error if no reply was acceptable, and with `Disagree` if the
+/// servers' times do not agree.
+pub async fn sync(stack: &NetStack) -> Result<(), SntpError> {
+    let mut last_error = None;
+    let mut servers: Vec<(&str, Ipv4Address), MAX_SERVERS> = Vec::new();
+    for server in configured() {
+        if servers.is_full() {
+            break;
+        }
+        match resolver::resolve(stack, server).await {
+            Ok(addr) => {
+                let _ = servers.push((server, addr));
+            }
+            Err(e) => {
+                println!("sntp: {}: {:?}", server, e);
+                last_error = Some(SntpError::Dns(e));
+            }
+        }
+    }
+
+    let rounds = if has_sntp_time() { 1 } else { BURST };
+    let mut samples: [Vec<Sample, BURST>; MAX_SERVERS] = Default::default();
+    for i in 0..rounds {
+        if i > 0 {
+            Timer::after(BURST_INTERVAL).await;
+        }
+        let mut answered = false;
+        for (&(server, addr), samples) in servers.iter().zip(samples.iter_mut()) {
+            match exchange(stack, server, addr).await {
+                // Cannot overflow: at most `BURST` rounds.
+                Ok(sample) => {
+                    let _ = samples.push(sample);
+                    answered = true;
+                }
+                Err(e) => {
+                    println!("sntp: {}: {:?}", server, e);
+                    last_error = Some(e);
+                }
+            }
+        }
+        // No server answering at all will not change in a few seconds.
+        if i == 0 && !answered {
+            return Err(last_error.unwrap_or(SntpError::Dns(DnsError::NoServer)));
+        }
+    }
+    let estimates: Vec<Estimate, MAX_SERVERS> = samples
+        .iter()
+        .filter_map(|s| sntpselect::combine(s))
+        .collect();
+    let Some((estimate, agreed)) = sntpselect::select(&estimates) else {
+        println!(
+            "sntp: {} servers disagree: {:?}",
+            estimates.len(),
+            estimates
+        );
+        return Err(SntpError::Disagree);
+    };
+
+    let at = Instant::now();
+    let unix_ms = (at.as_millis() as i64 + estimate.offset_ms) as u64;
+    let current_ms = critical_section::with(|cs| ANCHOR.borrow(cs).get())
+        .map(|anchor| anchor.unix_ms(at.duration_since(anchor.at).as_millis()));
+    let (start_ms, slew_ms) = sntpselect::adjust(current_ms, unix_ms);
+    println!(
+        "sntp: {} of {} servers agree, {} samples kept",
+        agreed,
+        estimates.len(),
+        estimate.kept
+    );
+    set_anchor(Anchor {
+        unix_ms: start_ms,
+        at,
+        source: Source::Sntp,
+        uncertainty_ms: estimate.uncertainty_ms,
+        slew_ms,
+    });
+    store_last_good(unix_ms, estimate.uncertainty_ms);
+    SYNCED.signal(now_unix_ms().unwrap_or(unix_ms));
+    Ok(())
+}
+
+fn has_sntp_time() -> bool {
+    critical_section::with(|cs| ANCHOR.borrow(cs).get()).is_some_and(|a| a.source == Source::Sntp)
+}
+
+/// The servers from NTP_SERVERS, or just `DEFAULT_SERVER`.
+fn configured() -> impl Iterator<Item = &'static str> {
+    CONFIGURED
+        .unwrap_or(DEFAULT_SERVER)
+        .split(',')
+        .map(str::trim)
+        .filter(|server| !server.is_empty())
+}
+
+/// One sample from `server`, at `addr`.
+async fn exchange(stack: &NetStack, server: &str, addr: Ipv4Address) -> Result<Sample, SntpError> {
+    let mut rx_meta = [PacketMetadata::EMPTY; 1];
+    let mut tx_meta = [PacketMetadata::EMPTY; 1];
+    let mut rx_buf = [0u8; PACKET_LEN];
+    let mut tx_buf = [0u8; PACKET_LEN];
+    let mut socket = UdpSocket::new(stack, &mut rx_meta, &mut rx_buf, &mut tx_meta, &mut tx_buf);
+    socket.bind(0).map_err(|_| SntpError::Socket)?;
+
+    let mut packet = [0u8; PACKET_LEN];
+    packet[0] = ntp::CLIENT_HEADER;
+    let sent = Instant::now();
+    socket
+        .send_to(&packet, (addr, NTP_PORT))
+        .await
+        .map_err(|_| SntpError::Socket)?;
+
+    let (len, _) = with_timeout(RESPONSE_TIMEOUT, socket.recv_from(&mut packet))
+        .await
+        .map_err(|_| SntpError::Timeout)?
+        .map_err(|_| SntpError::BadResponse)?;
+    let round_trip = Instant::now() - sent;
+
+    let reply = ntp::parse_response(&packet[..len]).map_err(|_| SntpError::BadResponse)?;
+    let held = Duration::from_millis(reply.transmit_ms.saturating_sub(reply.received_ms));
+    let delay = round_trip
+        .checked_sub(held)
+        .unwrap_or(Duration::from_ticks(0));
+    check_quality(&reply, delay).map_err(SntpError::Rejected)?;
+    // The server stamped its reply after holding the request, somewhere
+    // along the network part of the round trip; assume the middle.
+    let at = sent + delay / 2 + held;
+
+    println!(
+        "sntp: {}: stratum {}, precision 2^{} s, delay {} ms",
+        server,
+        reply.stratum,
+        reply.precision,
+        delay.as_millis()
+    );
+    Ok(Sample {
+        offset_ms: reply.transmit_ms as i64 - at.as_millis() as i64,
+        delay_ms: delay.as_millis(),
+    })
+}
+
+fn check_quality(reply: &Reply, delay: Duration) -> Result<(), Rejected> {
+    if reply.stratum == 0 {
+        return Err(Rejected::KissOfDeath);
+    }
+    if reply.stratum > MAX_STRATUM {
+        return Err(Rejected::Stratum(reply.stratum));
+    }
+    if reply.precision > MAX_PRECISION {
+        return Err(Rejected::Precision(reply.precision));
+    }
+    if delay > MAX_DELAY {
+        return Err(Rejected::Delay(delay.as_millis()));
+    }
+    Ok(())
+}
+
+fn store_last_good(unix_ms: u64, uncertainty_ms: u32) {
+    let mut raw = [0u8; LAST_GOOD_LEN];
+    raw[0..4].copy_from_slice(&LAST_GOOD_MAGIC.to_le_bytes());
+    raw[4..12].copy_from_slice(&unix_ms.to_le_bytes());
+    raw[12..16].copy_from_slice(&uncertainty_ms.to_le_bytes());
+    let crc = crc32_le(0, &raw[..LAST_GOOD_LEN - 4]);
+    raw[LAST_GOOD_LEN - 4..].copy_from_slice(&crc.to_le_bytes());
+    critical_section::with(|_| unsafe { *addr_of_mut!(LAST_GOOD) = raw });
+}
+
+/// Unix milliseconds and uncertainty of the latest SNTP sync, this boot or
+/// before a reset that kept RTC memory.
+pub fn last_good() -> Option<(u64, u32)> {
+    let raw = critical_section::with(|_| unsafe { *addr_of_mut!(LAST_GOOD) });
+    let word = |at: usize| u32::from_le_bytes(raw[at..at + 4].try_into().unwrap());
+    if word(0) != LAST_GOOD_MAGIC
+        || word(LAST_GOOD_LEN - 4) != crc32_le(0, &raw[..LAST_GOOD_LEN - 4])
+    {
+        return None;
+    }
+    let unix_ms = u64::from_le_bytes(raw[4..12].try_into().unwrap());
+    Some((unix_ms, word(12)))
+}
+
+const CONFIGURED: Option<&str> = option_env!("NTP_SERVERS");
+
+// A list with no names in it would leave the clock unset for good.
+const _: () = {
+    let list = match CONFIGURED {
+        Some(list) => list.as_bytes(),
+        None => b"x",
+    };
+    let mut i = 0;
+    while i < list.len() && (list[i] == b',' || list[i] == b' ') {
+        i += 1;
+    }
+    assert!(i < list.len(), "NTP_SERVERS names no server");
+};
@@ -0,0 +1,124 @@
+// How src/sntp.rs gets from a sync's samples to a time, apart from the
+// network it takes them from: each server's samples combined into an
+// estimate (`combine`), the servers' estimates checked against each other
+// (`select`), and a correction slewed or stepped (`adjust`, `slewed`).
+// src/sntp.rs says why each is done the way it is.
+
+use heapless::Vec;
+
+/// Rounds of a first sync.
+pub const BURST: usize = 8;
+
+pub const MAX_SERVERS: usize = 3;
+/// Farther than this from the servers' median, an estimate is dropped.
+pub const MAX_DISAGREEMENT_MS: u64 = 250;
+/// Corrections up to this are slewed, larger ones stepped.
+pub const STEP_THRESHOLD_MS: u64 = 128;
+/// How fast a slew works a correction in: 0.5 ms a second.
+pub const MAX_SLEW_PPM: u64 = 500;
+
+/// One accepted exchange: Unix time less local uptime, and the network
+/// part of the round trip.
+#[derive(Debug, Clone, Copy)]
+pub struct Sample {
+    pub offset_ms: i64,
+    pub delay_ms: u64,
+}
+
+/// What the samples of one server, or of several, come to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Estimate {
+    pub offset_ms: i64,
+    pub uncertainty_ms: u32,
+    /// Samples that went into it.
+    pub kept: usize,
+}
+
+/// The mean offset of the samples within 1.5 standard deviations of the
+/// mean of all, its uncertainty, and how many samples went into it.
+pub fn combine(samples: &[Sample]) -> Option<Estimate> {
+    if samples.is_empty() {
+        return None;
+    }
+    let n = samples.len() as i64;
+    let mean = samples.iter().map(|s| s.offset_ms).sum::<i64>() / n;
+    let variance = samples
+        .iter()
+        .map(|s| (s.offset_ms - mean).pow(2))
+        .sum::<i64>()
+        / n;
+    // d <= 1.5 sigma, squared and without fractions.
+    let mut kept: Vec<Sample, BURST> = samples
+        .iter()
+        .filter(|s| 4 * (s.offset_ms - mean).pow(2) <= 9 * variance)
+        .copied()
+        .collect();
+    // Only the rounding of the integer mean can leave nothing.
+    if kept.is_empty() {
+        kept = samples.iter().copied().collect();
+    }
+    let offset = kept.iter().map(|s| s.offset_ms).sum::<i64>() / kept.len() as i64;
+    let uncertainty = kept
+        .iter()
+        .map(|s| s.offset_ms.abs_diff(offset) + s.delay_ms / 2)
+        .max()?;
+    Some(Estimate {
+        offset_ms: offset,
+        uncertainty_ms: uncertainty.min(u32::MAX as u64) as u32,
+        kept: kept.len(),
+    })
+}
+
+/// The median of the estimates within `MAX_DISAGREEMENT_MS` of the median
+/// of all, and how many those are; `None` unless they are most of them.
+/// Of an even number the lower middle one counts as the median of all, so
+/// two servers have to agree with each other.
+pub fn select(estimates: &[Estimate]) -> Option<(Estimate, usize)> {
+    let mut offsets: Vec<i64, MAX_SERVERS> = estimates.iter().map(|e| e.offset_ms).collect();
+    offsets.sort_unstable();
+    let middle = *offsets.get(offsets.len().checked_sub(1)? / 2)?;
+    let agree: Vec<Estimate, MAX_SERVERS> = estimates
+        .iter()
+        .filter(|e| e.offset_ms.abs_diff(middle) <= MAX_DISAGREEMENT_MS)
+        .copied()
+        .collect();
+    if 2 * agree.len() <= estimates.len() {
+        return None;
+    }
+    let mut offsets: Vec<i64, MAX_SERVERS> = agree.iter().map(|e| e.offset_ms).collect();
+    offsets.sort_unstable();
+    let n = offsets.len();
+    let offset = if n % 2 == 1 {
+        offsets[n / 2]
+    } else {
+        (offsets[n / 2 - 1] + offsets[n / 2]) / 2
+    };
+    let uncertainty = agree
+        .iter()
+        .map(|e| e.uncertainty_ms as u64 + e.offset_ms.abs_diff(offset))
+        .max()?;
+    let estimate = Estimate {
+        offset_ms: offset,
+        uncertainty_ms: uncertainty.min(u32::MAX as u64) as u32,
+        kept: agree.iter().map(|e| e.kept).sum(),
+    };
+    Some((estimate, agree.len()))
+}
+
+/// How much of `slew_ms` is worked in `elapsed_ms` after it began.
+pub fn slewed(slew_ms: i64, elapsed_ms: u64) -> i64 {
+    let limit = (elapsed_ms * MAX_SLEW_PPM / 1_000_000) as i64;
+    slew_ms.clamp(-limit, limit)
+}
+
+/// Where the clock starts from, and what it slews, to go from reading
+/// `current_ms` to `target_ms`: with no current time, or too far off, it
+/// steps.
+pub fn adjust(current_ms: Option<u64>, target_ms: u64) -> (u64, i64) {
+    match current_ms {
+        Some(current_ms) if current_ms.abs_diff(target_ms) <= STEP_THRESHOLD_MS => {
+            (current_ms, target_ms as i64 - current_ms as i64)
+        }
+        _ => (target_ms, 0),
+    }
+}
@@ -0,0 +1,41 @@
+// Device-wide state that other tasks (LED, status output, MQTT) watch for
+// changes. Producers only ever touch it through `update`, which wakes the
+// watchers when something actually changed.
+
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::watch::Watch;
+
+use crate::connectivity::Connectivity;
+#[cfg(feature = "ota")]
+use crate::otaprogress::OtaProgress;
+
+/// Number of tasks that can hold a receiver at the same time.
+pub const MAX_WATCHERS: usize = 4;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct AppState {
+    /// `None` when no update is running.
+    #[cfg(feature = "ota")]
+    pub ota: Option<OtaProgress>,
+    pub connectivity: Connectivity,
+}
+
+static APP_STATE: Watch<CriticalSectionRawMutex, AppState, MAX_WATCHERS> =
+    Watch::new_with(AppState {
+        #[cfg(feature = "ota")]
+        ota: None,
+        connectivity: Connectivity::Unknown,
+    });
+
+pub fn update(f: impl Fn(&mut AppState)) {
+    APP_STATE.sender().send_if_modified(|state| {
+        let state = state.get_or_insert_with(AppState::default);
+        let before = *state;
+        f(state);
+        *state != before
+    });
+}
+
+pub fn current() -> AppState {
+    APP_STATE.try_get().unwrap_or_default()
+}
@@ -0,0 +1,104 @@
+// The Wi-Fi station controller after main has connected it. Whoever needs
+// the radio later, the self-test for its scan or the API for the signal
+// strength, locks it here; only one of them scans at a time.
+//
+// Also what the settings say about the network: `wifi_auth`, the weakest
+// security the station accepts, and `wifi_hidden`. Association does not
+// depend on the SSID showing up in a scan, hidden or not; a hidden network
+// only has to be asked for by name, which the scans here do. Main reads
+// both at boot, so a change takes effect at the next one.
+
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::mutex::{Mutex, MutexGuard};
+use esp_wifi::wifi::{AccessPointInfo, AuthMethod, WifiController};
+
+/// Shortest WPA2 or WPA3 passphrase.
+pub const MIN_PASSPHRASE_LEN: usize = 8;
+
+static CONTROLLER: Mutex<CriticalSectionRawMutex, Option<WifiController<'static>>> =
+    Mutex::new(None);
+
+pub type Guard = MutexGuard<'static, CriticalSectionRawMutex, Option<WifiController<'static>>>;
+
+/// Hands the connected controller over.
+pub async fn share(controller: WifiController<'static>) {
+    *CONTROLLER.lock().await = Some(controller);
+}
+
+/// The controller, `None` before `share`.
+pub async fn lock() -> Guard {
+    CONTROLLER.lock().await
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WifiAuth {
+    /// Whatever esp-wifi does by default: WPA2 or better.
+    Auto,
+    Wpa2,
+    /// SAE only.
+    Wpa3,
+    /// WPA2 or WPA3, for an access point in transition mode.
+    Wpa2Wpa3,
+}
+
+impl WifiAuth {
+    pub fn parse(text: &str) -> Option<WifiAuth> {
+        match text {
+            "auto" => Some(WifiAuth::Auto),
+            "wpa2" => Some(WifiAuth::Wpa2),
+            "wpa3" => Some(WifiAuth::Wpa3),
+            "wpa2wpa3-mixed" => Some(WifiAuth::Wpa2Wpa3),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            WifiAuth::Auto => "auto",
+            WifiAuth::Wpa2 => "wpa2",
+            WifiAuth::Wpa3 => "wpa3",
+            WifiAuth::Wpa2Wpa3 => "wpa2wpa3-mixed",
+        }
+    }
+
+    /// For `ClientConfiguration::auth_method`, which esp-wifi uses as the
+    /// weakest mode to accept.
+    pub fn auth_method(self) -> AuthMethod {
+        match self {
+            WifiAuth::Auto => AuthMethod::default(),
+            WifiAuth::Wpa2 => AuthMethod::WPA2Personal,
+            WifiAuth::Wpa3 => AuthMethod::WPA3Personal,
+            WifiAuth::Wpa2Wpa3 => AuthMethod::WPA2WPA3Personal,
+        }
+    }
+
+    /// Whether the mode is set explicitly, and so needs a passphrase that
+    /// it can use.
+    pub fn needs_passphrase(self) -> bool {
+        self != WifiAuth::Auto
+    }
+}
+
+/// `ssid`'s access point, from a scan for it by name.
+pub async fn find(
+    controller: &mut WifiController<'_>,
+    ssid: &str,
+    hidden: bool,
+) -> Option<AccessPointInfo> {
+    let config = esp_wifi::wifi::ScanConfig {
+        ssid: Some(ssid),
+        show_hidden: hidden,
+        ..Default::default()
+    };
+    let (mut aps, _) = controller.scan_with_config::<1>(config).await.ok()?;
+    aps.pop()
+}
+
+/// Signal strength of `ssid`'s access point in dBm, from a scan for it.
+#[cfg(any(feature = "api", feature = "oneshot"))]
+pub async fn rssi(controller: &mut WifiController<'_>, ssid: &str) -> Option<i8> {
+    let hidden = crate::settings::current().wifi_hidden;
+    find(controller, ssid, hidden)
+        .await
+        .map(|ap| ap.signal_strength)
+}
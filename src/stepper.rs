@@ -0,0 +1,157 @@
+// Unipolar stepper motor (28BYJ-48 and alike) driven through a ULN2003-style
+// board, one output per coil.
+//
+// Moves use the half-step sequence and a linear speed ramp at both ends: the
+// motor's rotor cannot follow a jump straight to full speed and would skip
+// steps instead.
+//
+// Only built with the `stepper` feature, for a board that has a motor on
+// it: nothing in the firmware drives one.
+
+use embassy_time::{Duration, Timer as EmbassyTimer};
+use esp_hal::gpio::{AnyInput, AnyOutput};
+
+// Coils IN1..IN4, one row per half step.
+const HALF_STEPS: [[bool; 4]; 8] = [
+    [true, false, false, false],
+    [true, true, false, false],
+    [false, true, false, false],
+    [false, true, true, false],
+    [false, false, true, false],
+    [false, false, true, true],
+    [false, false, false, true],
+    [true, false, false, true],
+];
+
+/// Half steps per output shaft revolution of a 28BYJ-48 (64:1 gearbox).
+pub const STEPS_PER_REVOLUTION: u32 = 4096;
+
+/// Shortest half-step period the motor follows reliably at 5 V.
+pub const MIN_DELAY_US: u32 = 800;
+
+/// Every move starts and ends at this period.
+const RAMP_START_DELAY_US: u32 = 3000;
+
+/// Half steps spent accelerating, and again decelerating.
+const RAMP_STEPS: u32 = 200;
+
+/// Homing gives up after this many steps, a bit over two revolutions.
+const HOME_MAX_STEPS: u32 = 2 * STEPS_PER_REVOLUTION + 512;
+const HOME_DELAY_US: u32 = 1500;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Clockwise,
+    CounterClockwise,
+}
+
+#[derive(Debug)]
+pub enum StepperError {
+    /// The home sensor did not trigger within `HOME_MAX_STEPS`.
+    HomeNotFound,
+}
+
+pub struct StepperMotor<'a> {
+    coils: [AnyOutput<'a>; 4],
+    phase: usize,
+    /// Half steps from home, clockwise positive.
+    position: i32,
+}
+
+impl<'a> StepperMotor<'a> {
+    /// Pins in driver order, IN1 to IN4.
+    pub fn new(
+        in1: AnyOutput<'a>,
+        in2: AnyOutput<'a>,
+        in3: AnyOutput<'a>,
+        in4: AnyOutput<'a>,
+    ) -> Self {
+        let mut motor = Self {
+            coils: [in1, in2, in3, in4],
+            phase: 0,
+            position: 0,
+        };
+        motor.release();
+        motor
+    }
+
+    pub fn position(&self) -> i32 {
+        self.position
+    }
+
+    /// Moves `steps` half steps at a cruise period of `delay_us` (at least
+    /// `MIN_DELAY_US`), ramping up and down on the way. The coils stay
+    /// energized afterwards to hold the position.
+    pub async fn step(&mut self, direction: Direction, steps: u32, delay_us: u32) {
+        let cruise = delay_us.clamp(MIN_DELAY_US, RAMP_START_DELAY_US);
+        // Short moves never reach cruise speed; they ramp up for half the
+        // distance and straight back down.
+        let ramp = RAMP_STEPS.min(steps / 2);
+
+        for i in 0..steps {
+            let from_edge = i.min(steps - 1 - i);
+            let delay = if from_edge < ramp {
+                RAMP_START_DELAY_US - (RAMP_START_DELAY_US - cruise) * from_edge / RAMP_STEPS
+            } else {
+                cruise
+            };
+            self.advance(direction);
+            EmbassyTimer::after(Duration::from_micros(delay as u64)).await;
+        }
+    }
+
+    /// Moves to `position` half steps from home, as `step` does.
+    pub async fn move_to(&mut self, position: i32, delay_us: u32) {
+        let direction = if position >= self.position {
+            Direction::Clockwise
+        } else {
+            Direction::CounterClockwise
+        };
+        let steps = position.abs_diff(self.position);
+        self.step(direction, steps, delay_us).await;
+    }
+
+    /// Turns clockwise at a slow constant speed until `home_sensor` (a hall
+    /// sensor pulling low at the magnet) triggers, and makes that position
+    /// zero.
+    pub async fn home(&mut self, home_sensor: &mut AnyInput<'_>) -> Result<(), StepperError> {
+        for _ in 0..HOME_MAX_STEPS {
+            if home_sensor.is_low() {
+                self.position = 0;
+                return Ok(());
+            }
+            self.advance(Direction::Clockwise);
+            EmbassyTimer::after(Duration::from_micros(HOME_DELAY_US as u64)).await;
+        }
+        self.release();
+        Err(StepperError::HomeNotFound)
+    }
+
+    /// De-energizes all coils. The 28BYJ-48 gets hot holding position, and
+    /// its gearbox holds well enough on its own for most loads.
+    pub fn release(&mut self) {
+        for coil in &mut self.coils {
+            coil.set_low();
+        }
+    }
+
+    fn advance(&mut self, direction: Direction) {
+        match direction {
+            Direction::Clockwise => {
+                self.phase = (self.phase + 1) % HALF_STEPS.len();
+                self.position = self.position.wrapping_add(1);
+            }
+            Direction::CounterClockwise => {
+                self.phase = (self.phase + HALF_STEPS.len() - 1) % HALF_STEPS.len();
+                self.position = self.position.wrapping_sub(1);
+            }
+        }
+        for (coil, &on) in self.coils.iter_mut().zip(&HALF_STEPS[self.phase]) {
+            if on {
+                coil.set_high();
+            } else {
+                coil.set_low();
+            }
+        }
+    }
+}
@@ -0,0 +1,139 @@
+// Which layout a reading's JSON is written in, so that a server that reads
+// one layout keeps working when a later firmware writes another. The
+// layouts, and the `Formatter` each is, are in src/telemetryschema.rs.
+// `message_id` is a random UUID made when the reading is taken
+// (src/entropy.rs), so a reading sent again after a lost answer has the
+// one it had.
+//
+// Which version to write is negotiated with the upload server. Once per
+// boot, before the first upload, `discover` asks `/schema` on its host,
+// which answers the highest version it reads as a bare number, `2`. An
+// answer without one, a 404 say, leaves it at that; no answer at all, and
+// it asks again the next cycle. A server also says so with
+// `X-Telemetry-Schema: N` in its answer to an upload. Either way
+// `negotiate_schema_version` takes that version if it is known, or else
+// the newest known, with a warning, and the readings taken after it are
+// written that way for the rest of the boot. Until a server has said, they
+// are version 1, which every receiver reads. The settings' `schema`
+// (src/settings.rs) overrides all of it, for a server that cannot say. A
+// reading already queued keeps the version it was taken in, which its
+// field tells.
+//
+// Critical records (src/priority.rs) have one layout; they carry the
+// version in use too, so a server can tell either kind apart by it.
+
+use core::fmt::Write;
+use core::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+
+use esp_println::println;
+use heapless::String;
+
+use crate::https;
+use crate::settings::MAX_URL_LEN;
+use crate::telemetryschema::{self, Reading, Sample, LATEST};
+use crate::NetStack;
+
+/// The version servers negotiated, 1 until one has. Only loaded and
+/// stored: the C3 has no atomic read-modify-write on bytes.
+static SCHEMA: AtomicU8 = AtomicU8::new(1);
+/// The version the settings set, 0 for none.
+static CONFIGURED: AtomicU8 = AtomicU8::new(0);
+static DISCOVERED: AtomicBool = AtomicBool::new(false);
+
+/// The version to write for one that a server or the settings name: that
+/// one if it is known, or else the newest known.
+pub fn negotiate_schema_version(version: u8) -> u8 {
+    let negotiated = telemetryschema::negotiate(version);
+    if negotiated != version {
+        println!(
+            "telemetry: schema {} is unknown, writing {}",
+            version, negotiated
+        );
+    }
+    negotiated
+}
+
+/// The version readings are written in now: the settings', or else the
+/// servers'.
+pub fn schema_version() -> u8 {
+    match CONFIGURED.load(Ordering::Relaxed) {
+        0 => SCHEMA.load(Ordering::Relaxed),
+        // `configure` has warned about one that is unknown.
+        configured => configured.min(LATEST),
+    }
+}
+
+/// Takes the version a server said it reads.
+pub fn server_reads(server_version: u8) {
+    let version = negotiate_schema_version(server_version);
+    if SCHEMA.load(Ordering::Relaxed) != version {
+        SCHEMA.store(version, Ordering::Relaxed);
+        println!(
+            "telemetry: server reads schema {}, writing {}",
+            server_version, version
+        );
+    }
+}
+
+/// Takes the settings' `schema`, `None` for `auto`.
+pub fn configure(schema: Option<u8>) {
+    let configured = schema.unwrap_or(0);
+    if CONFIGURED.load(Ordering::Relaxed) == configured {
+        return;
+    }
+    CONFIGURED.store(configured, Ordering::Relaxed);
+    match schema {
+        Some(schema) => println!(
+            "telemetry: settings set schema {}, writing {}",
+            schema,
+            negotiate_schema_version(schema)
+        ),
+        None => println!("telemetry: schema back to what servers read"),
+    }
+}
+
+/// Asks the upload server's `/schema` which version it reads, once per
+/// boot. Without an answer readings stay as they are, and it asks again
+/// next time.
+pub async fn discover(stack: &NetStack, upload_url: &str) {
+    if DISCOVERED.load(Ordering::Relaxed) {
+        return;
+    }
+    let Some(url) = https::parse_url(upload_url) else {
+        return;
+    };
+    let mut schema_url: String<MAX_URL_LEN> = String::new();
+    if write!(schema_url, "https://{}:{}/schema", url.host, url.port).is_err() {
+        return;
+    }
+    let mut response = [0u8; 512];
+    let result = match https::get(stack, &schema_url, &mut response).await {
+        Ok(result) => result,
+        Err(e) => {
+            println!(
+                "telemetry: no schema from {}: failed during {}",
+                schema_url,
+                e.phase().as_str()
+            );
+            return;
+        }
+    };
+    // Any answer is one: a server without the endpoint keeps the default.
+    DISCOVERED.store(true, Ordering::Relaxed);
+    let version = (result.status == 200)
+        .then(|| core::str::from_utf8(result.body(&response)).ok())
+        .flatten()
+        .and_then(|body| body.trim().parse().ok());
+    match version {
+        Some(version) => server_reads(version),
+        None => println!(
+            "telemetry: {} answered {} without a version",
+            schema_url, result.status
+        ),
+    }
+}
+
+/// `sample` in the version in use.
+pub fn serialize(sample: &Sample) -> Reading {
+    telemetryschema::serialize(schema_version(), sample)
+}
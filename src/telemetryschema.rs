@@ -0,0 +1,194 @@
+// The layout of a reading's JSON, in versions, apart from how the one to
+// write is chosen (src/telemetry.rs). Every reading says which it is in
+// `schema_version`, its first field.
+//
+// Version 1 is the flat layout readings always had:
+//
+//     {"schema_version":1,"uptime_ms":5021,"uptime_total_ms":905021,
+//      "config_revision":7,"seq":12,"reset":"wifi_stuck","wifi_resets":2,
+//      "battery_mv":3710,"charging":false}
+//
+// Version 2 groups what belongs together, so that more can join a group
+// without new names at the top:
+//
+//     {"schema_version":2,"uptime":{"boot_ms":5021,"total_ms":905021},
+//      "config_revision":7,"seq":12,"reset":{"reason":"wifi_stuck",
+//      "wifi_resets":2},"battery":{"mv":3710,"charging":false}}
+//
+// (each on one line). `message_id`, `wifi_alloc_failures` and `batch` are
+// the same in both and left out above. Each layout is a `Formatter`, and
+// both write from the same `Sample`, gathered once, and through the same
+// helpers for the fields they share, so neither can report other values.
+
+use core::fmt::Write;
+
+use heapless::String;
+
+use crate::batch::BatchStats;
+use crate::codec;
+use crate::power::PowerStatus;
+use crate::restartnote::Reason;
+
+/// The newest version known.
+pub const LATEST: u8 = 2;
+/// The longest a reading can be in either version, with every field there
+/// and every number at its largest: 419 bytes, in version 2.
+pub const MAX_READING_LEN: usize = 448;
+
+pub type Reading = String<MAX_READING_LEN>;
+
+/// What a reading reports, gathered when it is taken.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Sample {
+    pub uptime_ms: u64,
+    pub uptime_total_ms: u64,
+    pub config_revision: u32,
+    pub seq: Option<u64>,
+    pub message_id: [u8; 16],
+    pub reset: Option<Reason>,
+    pub wifi_alloc_failures: u32,
+    pub power: Option<PowerStatus>,
+    /// With a batch policy only.
+    pub batch: Option<BatchStats>,
+}
+
+/// One layout of a reading.
+pub trait Formatter {
+    const VERSION: u8;
+
+    /// The fields after `schema_version`, each with its leading comma.
+    fn write_fields(sample: &Sample, body: &mut Reading);
+
+    fn serialize(sample: &Sample) -> Reading {
+        let mut body = String::new();
+        let _ = write!(body, "{{\"schema_version\":{}", Self::VERSION);
+        Self::write_fields(sample, &mut body);
+        let _ = body.push('}');
+        body
+    }
+}
+
+/// The flat layout.
+pub struct V1;
+/// The grouped layout.
+pub struct V2;
+
+/// The version to write for one that a server or the settings name: that
+/// one if it is known, or else the newest known.
+pub fn negotiate(version: u8) -> u8 {
+    if (1..=LATEST).contains(&version) {
+        version
+    } else {
+        LATEST
+    }
+}
+
+/// `sample` in `version`, one that `negotiate` gave.
+pub fn serialize(version: u8, sample: &Sample) -> Reading {
+    match version {
+        2 => V2::serialize(sample),
+        _ => V1::serialize(sample),
+    }
+}
+
+impl Formatter for V1 {
+    const VERSION: u8 = 1;
+
+    fn write_fields(sample: &Sample, body: &mut Reading) {
+        // Cannot overflow: `MAX_READING_LEN` has room for all of it.
+        let _ = write!(
+            body,
+            ",\"uptime_ms\":{},\"uptime_total_ms\":{},\"config_revision\":{}",
+            sample.uptime_ms, sample.uptime_total_ms, sample.config_revision
+        );
+        write_seq(body, sample);
+        write_message_id(body, sample);
+        if let Some(reason) = sample.reset {
+            let _ = write!(body, ",\"reset\":\"{}\"", reason.as_str());
+            write_wifi_resets(body, reason);
+        }
+        write_alloc_failures(body, sample);
+        if let Some(status) = sample.power {
+            let _ = write!(
+                body,
+                ",\"battery_mv\":{},\"charging\":{}",
+                status.battery_mv, status.charging
+            );
+        }
+        write_batch(body, sample);
+    }
+}
+
+impl Formatter for V2 {
+    const VERSION: u8 = 2;
+
+    fn write_fields(sample: &Sample, body: &mut Reading) {
+        // Cannot overflow: `MAX_READING_LEN` has room for all of it.
+        let _ = write!(
+            body,
+            ",\"uptime\":{{\"boot_ms\":{},\"total_ms\":{}}},\"config_revision\":{}",
+            sample.uptime_ms, sample.uptime_total_ms, sample.config_revision
+        );
+        write_seq(body, sample);
+        write_message_id(body, sample);
+        if let Some(reason) = sample.reset {
+            let _ = write!(body, ",\"reset\":{{\"reason\":\"{}\"", reason.as_str());
+            write_wifi_resets(body, reason);
+            let _ = body.push('}');
+        }
+        write_alloc_failures(body, sample);
+        if let Some(status) = sample.power {
+            let _ = write!(
+                body,
+                ",\"battery\":{{\"mv\":{},\"charging\":{}}}",
+                status.battery_mv, status.charging
+            );
+        }
+        write_batch(body, sample);
+    }
+}
+
+fn write_seq(body: &mut Reading, sample: &Sample) {
+    if let Some(seq) = sample.seq {
+        let _ = write!(body, ",\"seq\":{}", seq);
+    }
+}
+
+fn write_wifi_resets(body: &mut Reading, reason: Reason) {
+    if let Reason::WifiStuck(resets) = reason {
+        let _ = write!(body, ",\"wifi_resets\":{}", resets);
+    }
+}
+
+fn write_message_id(body: &mut Reading, sample: &Sample) {
+    let _ = write!(
+        body,
+        ",\"message_id\":\"{}\"",
+        codec::uuid_to_string(&sample.message_id)
+    );
+}
+
+fn write_alloc_failures(body: &mut Reading, sample: &Sample) {
+    if sample.wifi_alloc_failures > 0 {
+        let _ = write!(
+            body,
+            ",\"wifi_alloc_failures\":{}",
+            sample.wifi_alloc_failures
+        );
+    }
+}
+
+fn write_batch(body: &mut Reading, sample: &Sample) {
+    if let Some(stats) = sample.batch {
+        let average = stats.average_tenths();
+        let _ = write!(
+            body,
+            ",\"batch\":{{\"avg\":{}.{},\"count\":{},\"size\":{},\"age\":{}}}",
+            average / 10,
+            average % 10,
+            stats.by_count,
+            stats.by_size,
+            stats.by_age
+        );
+    }
+}
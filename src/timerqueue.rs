@@ -0,0 +1,164 @@
+// The timer queue behind embassy-time, what wakes a task once a `Timer` it
+// awaits is due. It stands in for embassy-time's generic queue so that its
+// size is set here, next to the tasks it has to hold.
+//
+// An entry is a task, not a timer. Every timer a task awaits wakes it with
+// the task's one waker, and the entry for that waker keeps the earliest
+// deadline, so a `select` of two timers or a `with_timeout` around a
+// socket read is one entry all the same. What the queue has to hold is
+// then one entry per task that can wait on a timer: `TIMER_TASKS`, by
+// feature, which `fits` keeps `MARGIN` below `LEN` at compile time. With
+// every feature on that is 16 of 32.
+//
+// Full all the same, it makes room by waking whichever task is due last,
+// the one with the most time to spare, as embassy-time's own queue wakes
+// one. Nothing panics and no task is left asleep: the task woken early
+// finds its timer not due and waits again, at the cost of a poll, and of
+// waking the next one if the queue is still full. The housekeeping task
+// logs how many were woken so; `dev` builds also log every new high-water
+// mark of entries in use:
+//
+//     timers: 14 of 32 queue entries in use at once
+//
+// The entries themselves are in src/timerqueueentries.rs.
+
+use core::cell::RefCell;
+use core::task::Waker;
+
+use critical_section::Mutex;
+use embassy_time_driver::{allocate_alarm, set_alarm, set_alarm_callback, AlarmHandle};
+use embassy_time_queue_driver::TimerQueue;
+use esp_println::println;
+use portable_atomic::{AtomicU32, Ordering};
+
+use crate::timerqueueentries::{Alarm, Entries};
+
+pub const LEN: usize = fits(32);
+/// Entries to spare in the largest build, for a task left out below.
+const MARGIN: usize = 8;
+
+/// The tasks that can wait on a timer, by the names they are
+/// `canary::tracked` as, and `main`. Kept by hand: a task that starts to
+/// await a timer has to be added. fuzz/tests/timerqueueentries.rs checks
+/// that every tracked task is here or known to wait on none.
+const TIMER_TASKS: &[&str] = &[
+    // Until it returns: retries, the first probe and the test request.
+    "main",
+    "housekeeping",
+    "stack monitor",
+    "factory_reset",
+    "power",
+    // embassy-net arms one for smoltcp's next poll.
+    "net",
+    "connectivity",
+    #[cfg(not(feature = "oneshot"))]
+    "uploader",
+    #[cfg(not(feature = "oneshot"))]
+    "beacon",
+    #[cfg(not(feature = "oneshot"))]
+    "restart",
+    #[cfg(not(feature = "oneshot"))]
+    "selftest",
+    // Its commands make requests of their own.
+    #[cfg(feature = "console")]
+    "console",
+    #[cfg(feature = "api")]
+    "httpd",
+    #[cfg(feature = "api")]
+    "mdns",
+    #[cfg(feature = "api")]
+    "ap net",
+    #[cfg(feature = "api")]
+    "debug ap",
+];
+
+/// `len`, once it has been checked to hold `TIMER_TASKS` and `MARGIN`.
+const fn fits(len: usize) -> usize {
+    assert!(
+        TIMER_TASKS.len() + MARGIN <= len,
+        "the timer queue is too short for the tasks that use it"
+    );
+    len
+}
+
+/// Only the housekeeping task writes them.
+static LOGGED_EVICTIONS: AtomicU32 = AtomicU32::new(0);
+#[cfg(feature = "dev")]
+static LOGGED_PEAK: AtomicU32 = AtomicU32::new(0);
+
+impl Alarm for AlarmHandle {
+    fn now(&self) -> u64 {
+        embassy_time_driver::now()
+    }
+
+    fn set(&mut self, at: u64) -> bool {
+        set_alarm(*self, at)
+    }
+}
+
+struct Queue {
+    /// Set up on first use: the alarm is the time driver's, which is
+    /// not ready before `main`.
+    inner: Mutex<RefCell<Option<Entries<AlarmHandle, LEN>>>>,
+}
+
+impl Queue {
+    const fn new() -> Self {
+        Self {
+            inner: Mutex::new(RefCell::new(None)),
+        }
+    }
+
+    fn on_alarm(ctx: *mut ()) {
+        // `ctx` is `QUEUE`, from `schedule_wake`.
+        let queue = unsafe { &*(ctx as *const Queue) };
+        critical_section::with(|cs| {
+            if let Some(entries) = queue.inner.borrow_ref_mut(cs).as_mut() {
+                entries.dispatch();
+            }
+        });
+    }
+}
+
+impl TimerQueue for Queue {
+    fn schedule_wake(&'static self, at: u64, waker: &Waker) {
+        critical_section::with(|cs| {
+            let mut inner = self.inner.borrow_ref_mut(cs);
+            let entries = inner.get_or_insert_with(|| {
+                // Only this queue takes an alarm from the driver.
+                let alarm = unsafe { allocate_alarm() }.expect("no alarm for the timer queue");
+                set_alarm_callback(alarm, Self::on_alarm, self as *const Self as *mut ());
+                Entries::new(alarm)
+            });
+            entries.schedule(at, waker);
+        });
+    }
+}
+
+embassy_time_queue_driver::timer_queue_impl!(static QUEUE: Queue = Queue::new());
+
+/// Logs tasks woken early since the last call and, in `dev` builds, a new
+/// high-water mark.
+pub fn report() {
+    let (evictions, peak) = critical_section::with(|cs| {
+        QUEUE
+            .inner
+            .borrow_ref(cs)
+            .as_ref()
+            .map_or((0, 0), |entries| (entries.evictions(), entries.peak()))
+    });
+    let logged = LOGGED_EVICTIONS.swap(evictions, Ordering::Relaxed);
+    if evictions != logged {
+        println!(
+            "timers: queue full, {} tasks woken early ({} since boot)",
+            evictions.wrapping_sub(logged),
+            evictions
+        );
+    }
+    #[cfg(feature = "dev")]
+    if peak as u32 > LOGGED_PEAK.swap(peak as u32, Ordering::Relaxed) {
+        println!("timers: {} of {} queue entries in use at once", peak, LEN);
+    }
+    #[cfg(not(feature = "dev"))]
+    let _ = peak;
+}
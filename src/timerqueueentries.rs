@@ -0,0 +1,109 @@
+// The entries of the timer queue (src/timerqueue.rs), apart from the time
+// driver: one per task, due at its earliest deadline, room made in a full
+// queue by waking the one due last, and the alarm set for the next
+// deadline. The driver comes in as an `Alarm`.
+
+use core::task::Waker;
+
+use heapless::Vec;
+
+/// The time driver's clock and the queue's alarm on it.
+pub trait Alarm {
+    /// In driver ticks.
+    fn now(&self) -> u64;
+
+    /// Sets the alarm for `at`; `false`, and no alarm, if `at` has passed.
+    fn set(&mut self, at: u64) -> bool;
+}
+
+struct Entry {
+    /// In driver ticks.
+    at: u64,
+    waker: Waker,
+}
+
+pub struct Entries<A, const N: usize> {
+    entries: Vec<Entry, N>,
+    alarm: A,
+    /// Tasks woken early to make room.
+    evictions: u32,
+    /// The most entries in use at once.
+    peak: usize,
+}
+
+impl<A: Alarm, const N: usize> Entries<A, N> {
+    pub const fn new(alarm: A) -> Self {
+        Self {
+            entries: Vec::new(),
+            alarm,
+            evictions: 0,
+            peak: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn evictions(&self) -> u32 {
+        self.evictions
+    }
+
+    pub fn peak(&self) -> usize {
+        self.peak
+    }
+
+    /// Has `waker` woken at `at`, or at the deadline it is already queued
+    /// for if that is earlier.
+    pub fn schedule(&mut self, at: u64, waker: &Waker) {
+        if let Some(entry) = self.entries.iter_mut().find(|e| e.waker.will_wake(waker)) {
+            entry.at = entry.at.min(at);
+        } else if let Err(entry) = self.entries.push(Entry {
+            at,
+            waker: waker.clone(),
+        }) {
+            self.evictions = self.evictions.wrapping_add(1);
+            let last = self
+                .entries
+                .iter()
+                .enumerate()
+                .max_by_key(|(_, e)| e.at)
+                .map(|(i, _)| i)
+                .filter(|&i| self.entries[i].at > at);
+            match last {
+                Some(i) => {
+                    core::mem::replace(&mut self.entries[i], entry).waker.wake();
+                }
+                // The new deadline is the last of them.
+                None => entry.waker.wake(),
+            }
+        }
+        self.peak = self.peak.max(self.entries.len());
+        self.dispatch();
+    }
+
+    /// Wakes what is due and sets the alarm for the next deadline.
+    pub fn dispatch(&mut self) {
+        loop {
+            let now = self.alarm.now();
+            let mut next = u64::MAX;
+            let mut i = 0;
+            while i < self.entries.len() {
+                if self.entries[i].at <= now {
+                    self.entries.swap_remove(i).waker.wake();
+                } else {
+                    next = next.min(self.entries[i].at);
+                    i += 1;
+                }
+            }
+            // The alarm refuses a time that has passed since `now`.
+            if next == u64::MAX || self.alarm.set(next) {
+                return;
+            }
+        }
+    }
+}
@@ -0,0 +1,84 @@
+// `tlsinfo`: a fresh TLS connection to the upload server, or another, and
+// what the handshake settled on, for chasing handshakes that fail now and
+// then. The console's `tlsinfo [url]` prints it; `POST /api/v1/tlsinfo`
+// (src/api.rs) answers it for the upload URL, as JSON:
+//
+//     {"v":1,"ok":true,"host":"example.com","version":"TLS 1.3",
+//      "cipher_suite":"TLS_AES_128_GCM_SHA256","resumed":false,
+//      "certificate":null,"dns_ms":12,"connect_ms":48,"handshake_ms":410}
+//
+// (on one line). A connection that fails has `ok` false, and the `phase`
+// and `cause` (src/diag.rs) in place of what was negotiated.
+//
+// The connection is closed once the handshake is done, with no request on
+// it. It is always a new session: the client keeps no tickets, so nothing
+// is resumed. Nor is there a certificate to show: embedded-tls 0.17 keeps it
+// to the verifier it builds itself, and the server sends it encrypted, so
+// the transport does not see it either. src/x509.rs reads the fields from
+// one for when a verifier can hand it over.
+
+#[cfg(feature = "api")]
+use core::fmt::{self, Write};
+
+#[cfg(feature = "console")]
+use esp_println::println;
+
+use crate::diag;
+use crate::https::{self, FetchError, Timings, CIPHER_SUITE, TLS_VERSION};
+use crate::NetStack;
+
+#[cfg(feature = "api")]
+const FORMAT_VERSION: u32 = 1;
+
+/// Opens and closes a connection to `url`'s host.
+pub async fn run(stack: &NetStack, url: &str) -> Result<Timings, FetchError> {
+    https::handshake(stack, url).await
+}
+
+#[cfg(feature = "api")]
+pub fn write_json<W: Write>(
+    out: &mut W,
+    url: &str,
+    result: Result<&Timings, &FetchError>,
+) -> fmt::Result {
+    write!(out, "{{\"v\":{},\"ok\":{},", FORMAT_VERSION, result.is_ok())?;
+    if let Some(parsed) = https::parse_url(url) {
+        write!(out, "\"host\":\"{}\",", parsed.host)?;
+    }
+    match result {
+        Ok(t) => write!(
+            out,
+            "\"version\":\"{}\",\"cipher_suite\":\"{}\",\"resumed\":false,\"certificate\":null,\
+             \"dns_ms\":{},\"connect_ms\":{},\"handshake_ms\":{}}}",
+            TLS_VERSION, CIPHER_SUITE, t.dns_ms, t.connect_ms, t.handshake_ms
+        ),
+        Err(e) => write!(
+            out,
+            "\"phase\":\"{}\",\"cause\":\"{}\"}}",
+            e.phase().as_str(),
+            diag::classify(e).as_str()
+        ),
+    }
+}
+
+/// Runs it against `url` and prints the outcome.
+#[cfg(feature = "console")]
+pub async fn print(stack: &NetStack, url: &str) {
+    println!("tlsinfo: connecting to {}", url);
+    match run(stack, url).await {
+        Ok(t) => {
+            println!("tlsinfo: {} {}, new session", TLS_VERSION, CIPHER_SUITE);
+            println!("tlsinfo: certificate not available (embedded-tls keeps it)");
+            println!(
+                "tlsinfo: dns {} ms, connect {} ms, handshake {} ms",
+                t.dns_ms, t.connect_ms, t.handshake_ms
+            );
+        }
+        Err(e) => println!(
+            "tlsinfo: failed during {}: {} ({:?})",
+            e.phase().as_str(),
+            diag::classify(&e).describe(),
+            e
+        ),
+    }
+}
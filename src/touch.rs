@@ -0,0 +1,88 @@
+// Capacitive touch pad read through the ADC.
+//
+// The ESP32-C3 has no touch controller. Instead the pad is an ADC pin held
+// up through a high-value resistor (1-10 MΩ), so its voltage settles slowly
+// after each sample. A finger adds capacitance and mains pickup, which pulls
+// the averaged reading down well below the untouched baseline.
+
+use embassy_time::{Duration, Instant, Timer as EmbassyTimer};
+use esp_hal::analog::adc::{Adc, AdcChannel, AdcPin};
+use esp_hal::peripherals::ADC1;
+
+/// Single conversions averaged into one reading.
+const SAMPLES_PER_READING: u32 = 16;
+/// Readings averaged into the baseline.
+const CALIBRATION_READINGS: u32 = 32;
+
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+pub struct CapTouchButton<'a, PIN> {
+    adc: Adc<'a, ADC1>,
+    pin: AdcPin<PIN, ADC1>,
+    threshold: u32,
+    min_touch_duration: Duration,
+    baseline: Option<u32>,
+}
+
+impl<'a, PIN: AdcChannel> CapTouchButton<'a, PIN> {
+    /// A touch is a reading `threshold` below the baseline for at least
+    /// `min_touch_duration_ms`; anything shorter is treated as noise.
+    pub fn new(
+        adc: Adc<'a, ADC1>,
+        pin: AdcPin<PIN, ADC1>,
+        threshold: u32,
+        min_touch_duration_ms: u64,
+    ) -> Self {
+        Self {
+            adc,
+            pin,
+            threshold,
+            min_touch_duration: Duration::from_millis(min_touch_duration_ms),
+            baseline: None,
+        }
+    }
+
+    /// Measures the untouched level. The pad must not be touched meanwhile.
+    pub fn calibrate(&mut self) -> u32 {
+        let sum: u32 = (0..CALIBRATION_READINGS).map(|_| self.read()).sum();
+        let baseline = sum / CALIBRATION_READINGS;
+        self.baseline = Some(baseline);
+        baseline
+    }
+
+    /// Waits for a touch that lasts `min_touch_duration`. Returns `false`
+    /// right away if the button was never calibrated.
+    pub async fn wait_for_touch(&mut self) -> bool {
+        let Some(baseline) = self.baseline else {
+            return false;
+        };
+        let trigger = baseline.saturating_sub(self.threshold);
+
+        let mut touched_since = None;
+        loop {
+            if self.read() < trigger {
+                let since = *touched_since.get_or_insert_with(Instant::now);
+                if since.elapsed() >= self.min_touch_duration {
+                    return true;
+                }
+            } else {
+                touched_since = None;
+            }
+            EmbassyTimer::after(POLL_INTERVAL).await;
+        }
+    }
+
+    fn read(&mut self) -> u32 {
+        let mut sum = 0;
+        for _ in 0..SAMPLES_PER_READING {
+            // The only error is "conversion still running".
+            let value = loop {
+                if let Ok(value) = self.adc.read_oneshot(&mut self.pin) {
+                    break value;
+                }
+            };
+            sum += value as u32;
+        }
+        sum / SAMPLES_PER_READING
+    }
+}
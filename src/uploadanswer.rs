@@ -0,0 +1,80 @@
+// The server's answer to readings, as src/uploader.rs takes it: whether
+// they leave the queue, and how long bulk uploads then hold off.
+//
+// 2xx delivers them. Any other 4xx than 408 and 429 refuses them for good,
+// and they are dropped: sending them again would not change the server's
+// mind. Anything else, a 5xx, 408 Request Timeout, 429 Too Many Requests or
+// a status that is neither, is the server not taking them now: they stay
+// queued, in order, and bulk uploads wait before the next try, from
+// `FIRST_DELAY_MS` doubling with each such answer in a row up to
+// `MAX_DELAY_MS`. Readings are still taken meanwhile, and the queue drops
+// its oldest once full, as behind a captive portal.
+//
+// Pure: the time is an argument, in ms since boot, and the uploader owns the
+// clock.
+
+pub const FIRST_DELAY_MS: u64 = 30_000;
+pub const MAX_DELAY_MS: u64 = 15 * 60_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verdict {
+    Delivered,
+    /// For good; the readings are dropped.
+    Refused,
+    /// For now; the readings are kept.
+    Deferred,
+}
+
+impl Verdict {
+    pub fn of(status: u16) -> Self {
+        match status {
+            200..=299 => Verdict::Delivered,
+            408 | 429 => Verdict::Deferred,
+            400..=499 => Verdict::Refused,
+            _ => Verdict::Deferred,
+        }
+    }
+}
+
+/// Of bulk uploads, after the server deferred them.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Backoff {
+    /// Deferring answers in a row.
+    deferred: u32,
+    /// No bulk upload before this.
+    until_ms: u64,
+}
+
+impl Backoff {
+    pub const fn new() -> Self {
+        Self {
+            deferred: 0,
+            until_ms: 0,
+        }
+    }
+
+    /// Whether a bulk upload may go at `now_ms`.
+    pub fn ready(&self, now_ms: u64) -> bool {
+        now_ms >= self.until_ms
+    }
+
+    /// When the next one may.
+    pub fn until_ms(&self) -> u64 {
+        self.until_ms
+    }
+
+    /// Books the answer to an upload at `now_ms`; returns how long the next
+    /// one waits.
+    pub fn settle(&mut self, verdict: Verdict, now_ms: u64) -> u64 {
+        if verdict != Verdict::Deferred {
+            *self = Self::new();
+            return 0;
+        }
+        let delay_ms = FIRST_DELAY_MS
+            .saturating_mul(1 << self.deferred.min(16))
+            .min(MAX_DELAY_MS);
+        self.deferred = self.deferred.saturating_add(1);
+        self.until_ms = now_ms + delay_ms;
+        delay_ms
+    }
+}
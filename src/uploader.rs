@@ -0,0 +1,876 @@
+// Periodic telemetry upload and config poll.
+//
+// Each cycle starts with the one safe point for switching settings: nothing
+// from the previous cycle is still in flight and nothing in this one has read
+// them yet.
+//
+// Readings go through a small queue. Behind a captive portal nothing is sent
+// and they pile up (oldest dropped first); once the portal is gone the queue
+// drains in order. They pile up the same way in the settings' quiet hours
+// (src/quiethours.rs) and drain once those are over. How the queue fares is
+// counted (`queue_stats`, src/queuestats.rs) and logged every minute by the
+// housekeeping task.
+//
+// With a `batch` policy the readings stay queued until the policy says to
+// flush, and then go out several to a POST (`batch`). Between two readings
+// the loop also wakes when the oldest one reaches the policy's maximum age.
+//
+// A crash report from before the last reset rides along with every reading
+// as `crash` until one of them is accepted; in a batch, with the first.
+// Batched readings also carry `batch`: the average batch size so far and how
+// many flushes each limit triggered. After a restart the firmware made itself,
+// readings carry `reset` with its reason (src/restart.rs). Like a crash
+// report, what the firmware was built from (src/buildinfo.rs) rides along as
+// `build` until an upload after the boot has been accepted.
+//
+// Once esp-wifi has failed to allocate, readings carry
+// `wifi_alloc_failures`, the count so far (src/wifiheap.rs).
+//
+// The device's labels (src/labels.rs) ride along as `labels` with the
+// first reading of every upload, and with every heartbeat.
+//
+// Readings are in the layout the server last said it reads, by its
+// `/schema` or `X-Telemetry-Schema` in an answer, or the one the settings'
+// `schema` sets, and say which in `schema_version` (src/telemetry.rs).
+//
+// Each reading also has `seq`, numbered when it is taken (src/sequence.rs),
+// and `uptime_total_ms` next to `uptime_ms`: the uptime across resets
+// (src/monotime.rs).
+//
+// With `compress=gzip` in the settings, an upload goes gzipped
+// (src/deflate.rs) if that makes it smaller. An endpoint that answers 415,
+// refusing the coding, gets that body again plain, and every body after it
+// until the next boot.
+//
+// Everything goes over HTTPS, one request at a time; there is no broker
+// connection, MQTT or other, that could carry a last will or a retained
+// status. A server learns that a device is gone from the readings
+// stopping, so how soon depends on the upload interval in its settings.
+// A reading leaves the queue when the server has accepted it or refused it
+// for good, not when it was written to the socket, and one sent again
+// after a lost answer has the same `seq`. Short of a refusal, a reading is
+// lost only to the queue running over while the server is out of reach or
+// keeps answering 5xx, 408 or 429 (src/uploadanswer.rs); after one of
+// those the queue is kept and bulk uploads back off.
+//
+// Settings that change the upload URL get a dry run of the new one first
+// (src/dryrun.rs): a `HEAD` with the credentials, reported step by step to
+// the log and, as `dry_run` in a reading without `seq` or extras, to the
+// URL the readings went to until then. The console asks for one with
+// `dryrun`, served between readings. Nothing in a dry run touches the
+// queue, and its answer confirms nothing and acknowledges no `seq`.
+//
+// Critical records, alerts like a critically low battery, have a queue of
+// their own (src/priority.rs) and go first: before each reading or batch,
+// and between readings as soon as they are raised or due again. One is a
+// JSON object with `"priority":"critical"`, the `alert`, and `uptime_ms`
+// from when it was raised; it has no `seq` and carries no extras.
+//
+// In safe mode (src/safemode.rs) there is no config poll and no queue: each
+// interval one heartbeat goes out, a reading without `seq` plus `safe_mode`
+// with the count of abnormal resets, and the crash report and build every
+// time, since nothing is cleared. Accepting one confirms nothing. An answer
+// with `X-Safe-Mode: exit` takes the device out of safe mode.
+//
+// The config poll may be answered `multipart/mixed` (src/multipart.rs), so a
+// server can send more than the settings in one round trip. A `text/plain`
+// part is a settings document, as a plain answer would be, and a
+// `text/uri-list` part the URL of a firmware image to update to, which
+// starts right away (subject to the maintenance window, src/ota.rs). An
+// `application/json` part is a fleet command (src/fleet.rs), run as it
+// comes. Other parts are skipped.
+
+use core::cell::RefCell;
+use core::fmt::Write as _;
+use core::str;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use embassy_futures::select::{select3, Either3};
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_time::{Duration, Instant, Timer as EmbassyTimer};
+use esp_hal::rom::crc::crc32_le;
+use esp_println::println;
+use heapless::{Deque, String, Vec};
+
+use crate::batch::{self, BatchFormat, BatchPolicy, FlushReason, MAX_BATCH_BYTES, MAX_BATCH_COUNT};
+use crate::crash;
+use crate::crashrecord::{self, Crash};
+use crate::entropy::{self, HardwareRng};
+use crate::https::{self, FetchError};
+use crate::labels::{self, Labels};
+use crate::multipart::{self, MultipartParser};
+use crate::priority::{self, Alert, Critical, Outcome, Settled, Slot};
+use crate::queuestats::{QueueStats, Stats};
+use crate::settings::{self, Settings, SettingsError, MAX_DOCUMENT_LEN, MAX_URL_LEN};
+use crate::telemetryschema::{Reading, MAX_READING_LEN};
+use crate::uploadanswer::{Backoff, Verdict};
+use crate::{
+    boot, buildinfo, canary, connectivity, deflate, diag, dryrun, flash, fleet, metrics, monotime,
+    power, quiethours, restart, safemode, sequence, sntp, telemetry, telemetryschema, wifiheap,
+    NetStack,
+};
+
+const QUEUE_LEN: usize = MAX_BATCH_COUNT;
+const MAX_GZIP_REFUSED: usize = 4;
+
+/// Room for `build` in a `Report`.
+const BUILD_FIELD_LEN: usize = ",\"build\":".len() + buildinfo::JSON.len();
+/// A reading with a crash report, the build or the labels added; escaping
+/// can make the report's text several times longer than
+/// `crashrecord::MESSAGE_LEN`.
+type Report = String<{ MAX_READING_LEN + 256 + BUILD_FIELD_LEN + labels::MAX_JSON_LEN }>;
+/// A batch body; a report can take it past the policy's limit.
+type Batch = String<{ MAX_BATCH_BYTES + 512 + BUILD_FIELD_LEN + labels::MAX_JSON_LEN }>;
+
+/// Whether an upload with `build` has been accepted since the boot.
+static BUILD_SENT: AtomicBool = AtomicBool::new(false);
+
+static QUEUE_STATS: QueueStats = QueueStats::new(QUEUE_LEN);
+
+/// Upload URLs, by their CRC-32, that answered a gzipped body 415.
+static GZIP_REFUSED: Mutex<CriticalSectionRawMutex, RefCell<Vec<u32, MAX_GZIP_REFUSED>>> =
+    Mutex::new(RefCell::new(Vec::new()));
+
+struct Queued {
+    taken_at: Instant,
+    seq: Option<u64>,
+    body: Reading,
+}
+
+type Queue = Deque<Queued, QUEUE_LEN>;
+
+#[embassy_executor::task]
+pub async fn uploader_task(stack: &'static NetStack) {
+    canary::tracked("uploader", run(stack)).await
+}
+
+async fn run(stack: &'static NetStack) -> ! {
+    if safemode::active() {
+        heartbeat(stack).await
+    }
+    let mut queue = Queue::new();
+    let mut backoff = Backoff::new();
+    loop {
+        // The maintenance window below needs the wall clock.
+        if sntp::needs_sync() {
+            if let Err(e) = sntp::sync(stack).await {
+                println!("uploader: SNTP sync failed: {:?}", e);
+            }
+        }
+
+        let previous_url = settings::current().upload_url;
+        if let Some(changes) = settings::apply_pending().await {
+            println!(
+                "uploader: now on revision {} ({:?})",
+                settings::current().revision,
+                changes
+            );
+        }
+        let settings = settings::current();
+        telemetry::configure(settings.schema);
+        if settings.upload_url != previous_url && !settings.upload_url.is_empty() {
+            dry_run(stack, &settings, &settings.upload_url, &previous_url).await;
+        }
+
+        let captive = connectivity::is_captive();
+
+        if !settings.upload_url.is_empty() && !captive {
+            telemetry::discover(stack, &settings.upload_url).await;
+        }
+
+        if !settings.config_url.is_empty() && !captive {
+            poll_config(stack, &settings).await;
+        }
+
+        if !settings.upload_url.is_empty() {
+            if queue.is_full() {
+                queue.pop_front();
+                QUEUE_STATS.note_dropped();
+            }
+            let seq = sequence::next().await;
+            let _ = queue.push_back(Queued {
+                taken_at: Instant::now(),
+                seq,
+                body: reading(&settings, seq),
+            });
+            QUEUE_STATS.note_enqueued(queue.len());
+            if captive {
+                println!("uploader: captive portal, holding {} readings", queue.len());
+            } else if quiet(&settings) {
+                println!("uploader: quiet hours, holding {} readings", queue.len());
+            } else {
+                match settings.batch {
+                    Some(policy) => {
+                        flush_due(stack, &settings, &policy, &mut queue, &mut backoff).await;
+                    }
+                    None => drain(stack, &settings, &mut queue, &mut backoff).await,
+                }
+            }
+        }
+
+        let mut interval_s = settings.upload_interval_s;
+        if power::status().map_or(false, |s| s.is_low()) {
+            interval_s = interval_s.saturating_mul(power::LOW_BATTERY_FACTOR);
+        }
+        let next_reading = Instant::now() + Duration::from_secs(interval_s as u64);
+
+        // The oldest reading may come of age before the next one is due.
+        if let Some(policy) = settings.batch {
+            while let Some(deadline) = queue
+                .front()
+                .map(|oldest| oldest.taken_at + Duration::from_secs(policy.max_age_s as u64))
+                .filter(|&deadline| deadline < next_reading)
+            {
+                wait_until(stack, &settings, deadline).await;
+                // On failure the batch is due again at once; wait for the
+                // next reading instead of retrying in a loop.
+                if connectivity::is_captive()
+                    || quiet(&settings)
+                    || !flush_due(stack, &settings, &policy, &mut queue, &mut backoff).await
+                {
+                    break;
+                }
+            }
+        }
+        wait_until(stack, &settings, next_reading).await;
+    }
+}
+
+/// Waits for `until`, sending critical records meanwhile as they are raised
+/// or come due again.
+async fn wait_until(stack: &NetStack, settings: &Settings, until: Instant) {
+    loop {
+        let wake = match priority::with(|queue| queue.due_ms()) {
+            Some(due_ms) if can_send(settings) => until.min(Instant::from_millis(due_ms)),
+            _ => until,
+        };
+        let woken = select3(
+            EmbassyTimer::at(wake),
+            priority::raised(),
+            dryrun::requested(),
+        )
+        .await;
+        if let Either3::Third(url) = woken {
+            let url = if url.is_empty() {
+                &settings.upload_url
+            } else {
+                &url
+            };
+            dry_run(stack, settings, url, "").await;
+        }
+        if Instant::now() >= until {
+            return;
+        }
+        if can_send(settings) {
+            send_critical(stack, settings).await;
+        }
+    }
+}
+
+/// Runs a dry run of `url` and logs the report, and posts it to `report_to`
+/// too unless that is empty.
+async fn dry_run(stack: &NetStack, settings: &Settings, url: &str, report_to: &str) {
+    let report = dryrun::run(stack, url).await;
+    let mut json: String<{ dryrun::MAX_REPORT_LEN }> = String::new();
+    let _ = report.write_json(&mut json);
+    println!("uploader: dry run of {}: {}", url, json);
+    if report_to.is_empty() || connectivity::is_captive() {
+        return;
+    }
+
+    let reading = reading(settings, None);
+    let mut body: String<{ 256 + dryrun::MAX_REPORT_LEN }> = String::new();
+    // Cannot overflow: the sum of both lengths and the field name.
+    let _ = write!(
+        body,
+        "{},\"dry_run\":{}}}",
+        reading.strip_suffix('}').unwrap_or(&reading),
+        json
+    );
+    flash::wait_idle().await;
+    // Not `upload`: the answer must not acknowledge any `seq`.
+    let mut response = [0u8; 256];
+    match https::post(
+        stack,
+        report_to,
+        "application/json",
+        body.as_bytes(),
+        &mut response,
+    )
+    .await
+    {
+        Ok(answer) if !(200..300).contains(&answer.status) => {
+            println!(
+                "uploader: server answered {} to the dry run report",
+                answer.status
+            );
+        }
+        Ok(_) => {}
+        Err(e) => upload_failed(&e),
+    }
+}
+
+/// The readings queue's counters.
+pub fn queue_stats() -> Stats {
+    QUEUE_STATS.stats()
+}
+
+/// Whether it is the settings' quiet hours, by the wall clock.
+fn quiet(settings: &Settings) -> bool {
+    quiethours::holds(settings.quiet_hours, sntp::now_unix_ms())
+}
+
+fn can_send(settings: &Settings) -> bool {
+    !settings.upload_url.is_empty() && !connectivity::is_captive()
+}
+
+/// Sends the critical records that are due, ahead of the next bulk
+/// request. Returns `false` if one could not be delivered for want of an
+/// answer; bulk would fare no better.
+async fn send_critical(stack: &NetStack, settings: &Settings) -> bool {
+    loop {
+        let now_ms = Instant::now().as_millis();
+        let Some(Slot::Critical(record)) = priority::with(|queue| queue.next_slot(now_ms, true))
+        else {
+            return true;
+        };
+        let outcome = try_critical(stack, settings, &record).await;
+        let now_ms = Instant::now().as_millis();
+        let name = record.alert.as_str();
+        match priority::with(|queue| queue.settle(&record, outcome, now_ms)) {
+            Settled::Delivered { latency_ms } => {
+                println!("uploader: sent {} after {} ms", name, latency_ms);
+                metrics::record_critical_latency(latency_ms);
+                delivered().await;
+            }
+            Settled::Dropped => println!("uploader: dropping {}", name),
+            Settled::Retry { due_ms } => {
+                println!(
+                    "uploader: {} not sent, again in {} ms",
+                    name,
+                    due_ms - now_ms
+                );
+                return false;
+            }
+            Settled::GaveUp => {
+                println!(
+                    "uploader: {} not sent in {} tries, dropping it",
+                    name,
+                    priority::CRITICAL_ATTEMPTS
+                );
+                return false;
+            }
+        }
+    }
+}
+
+async fn try_critical(stack: &NetStack, settings: &Settings, record: &Critical) -> Outcome {
+    let mut body: String<128> = String::new();
+    // Cannot overflow: fixed text and a few integers.
+    let _ = write!(
+        body,
+        "{{\"schema_version\":{},\"priority\":\"critical\",\"alert\":\"{}\",\"uptime_ms\":{}",
+        telemetry::schema_version(),
+        record.alert.as_str(),
+        record.raised_ms
+    );
+    let _ = match record.alert {
+        Alert::BatteryCritical { battery_mv } => write!(body, ",\"battery_mv\":{}", battery_mv),
+    };
+    let _ = body.push('}');
+    flash::wait_idle().await;
+    match upload(stack, settings, "application/json", &body, None).await {
+        Ok(answer) if answer.early => Outcome::Failed,
+        Ok(Answer { status, .. }) => match Verdict::of(status) {
+            Verdict::Delivered => Outcome::Accepted,
+            Verdict::Refused => {
+                println!("uploader: server answered {} to a critical record", status);
+                Outcome::Refused
+            }
+            Verdict::Deferred => {
+                println!(
+                    "uploader: server answered {} to a critical record, keeping it",
+                    status
+                );
+                Outcome::Failed
+            }
+        },
+        Err(e) => {
+            upload_failed(&e);
+            Outcome::Failed
+        }
+    }
+}
+
+async fn heartbeat(stack: &NetStack) -> ! {
+    let settings = settings::current();
+    loop {
+        if !settings.upload_url.is_empty() && !connectivity::is_captive() {
+            let mut body = reading(&settings, None);
+            body.pop();
+            // Cannot overflow: `reading` leaves room for far more.
+            let _ = write!(
+                body,
+                ",\"safe_mode\":{{\"resets\":{}}}}}",
+                safemode::resets()
+            );
+            let extras = Extras::pending();
+            let mut report = Report::new();
+            let body = match extras.add_to(&mut report, &body) {
+                Ok(()) => report.as_str(),
+                Err(_) => body.as_str(),
+            };
+            flash::wait_idle().await;
+            match upload(stack, &settings, "application/json", body, None).await {
+                Ok(answer) if answer.safe_mode_exit => safemode::exit().await,
+                Ok(Answer { status, .. }) if !(200..300).contains(&status) => {
+                    println!("uploader: server answered {} to the heartbeat", status);
+                }
+                Ok(_) => {}
+                Err(e) => upload_failed(&e),
+            }
+        }
+        EmbassyTimer::after(Duration::from_secs(settings.upload_interval_s as u64)).await;
+    }
+}
+
+/// Sends batches for as long as `policy` finds one due. Returns `false` if
+/// one could not be delivered, or `backoff` holds it back; it is then still
+/// at the front of the queue.
+async fn flush_due(
+    stack: &NetStack,
+    settings: &Settings,
+    policy: &BatchPolicy,
+    queue: &mut Queue,
+    backoff: &mut Backoff,
+) -> bool {
+    loop {
+        let age_ms = queue
+            .front()
+            .map_or(0, |oldest| oldest.taken_at.elapsed().as_millis());
+        let bytes = batch::encoded_len(policy.format, queue.iter().map(|q| q.body.len()));
+        let Some(reason) = policy.flush_reason(queue.len(), bytes, age_ms) else {
+            return true;
+        };
+        if !send_critical(stack, settings).await || !backoff_over(backoff, queue) {
+            return false;
+        }
+        let count = policy.batch_len(queue.iter().map(|q| q.body.len()));
+        if !send_batch(stack, settings, policy, queue, backoff, count, reason).await {
+            return false;
+        }
+    }
+}
+
+/// Posts the oldest `count` readings as one batch and takes them off the
+/// queue once the server has accepted or refused them for good (`Verdict`).
+/// Returns `false` if they are still queued.
+async fn send_batch(
+    stack: &NetStack,
+    settings: &Settings,
+    policy: &BatchPolicy,
+    queue: &mut Queue,
+    backoff: &mut Backoff,
+    count: usize,
+    reason: FlushReason,
+) -> bool {
+    flash::wait_idle().await;
+    let extras = Extras::pending();
+    let mut body = Batch::new();
+    let mut with_extras = false;
+    for (i, queued) in queue.iter().take(count).enumerate() {
+        let mut report = Report::new();
+        let reading = if i == 0 && extras.add_to(&mut report, &queued.body).is_ok() {
+            with_extras = true;
+            report.as_str()
+        } else {
+            queued.body.as_str()
+        };
+        // Cannot overflow: `batch_len` kept the readings within
+        // `MAX_BATCH_BYTES`, and the room above that is the report's.
+        let _ = match policy.format {
+            BatchFormat::Ndjson => writeln!(body, "{}", reading),
+            BatchFormat::JsonArray => {
+                let open = if i == 0 { '[' } else { ',' };
+                write!(body, "{}{}", open, reading)
+            }
+        };
+    }
+    if policy.format == BatchFormat::JsonArray {
+        let _ = body.push(']');
+    }
+    let sent_through = queue.iter().take(count).filter_map(|q| q.seq).max();
+
+    let answer = upload(
+        stack,
+        settings,
+        policy.format.content_type(),
+        &body,
+        sent_through,
+    )
+    .await;
+    if let (true, Ok(answer)) = (with_extras, &answer) {
+        extras.settle(answer);
+    }
+    match answer {
+        Ok(answer) if answer.early => {
+            println!(
+                "uploader: server answered {} before the whole batch, keeping {} readings",
+                answer.status, count
+            );
+            false
+        }
+        Ok(Answer { status, .. }) => {
+            let verdict = Verdict::of(status);
+            let delay_ms = backoff.settle(verdict, Instant::now().as_millis());
+            match verdict {
+                Verdict::Delivered => {
+                    println!("uploader: sent {} readings ({:?})", count, reason);
+                    batch::record_flush(reason, count);
+                }
+                Verdict::Refused => println!(
+                    "uploader: server answered {}, dropping {} readings",
+                    status, count
+                ),
+                Verdict::Deferred => {
+                    println!(
+                        "uploader: server answered {}, keeping {} readings, again in {} ms",
+                        status, count, delay_ms
+                    );
+                    return false;
+                }
+            }
+            for _ in 0..count {
+                queue.pop_front();
+            }
+            QUEUE_STATS.note_dequeued(count, queue.len());
+            if verdict == Verdict::Delivered {
+                delivered().await;
+            }
+            true
+        }
+        Err(e) => {
+            upload_failed(&e);
+            false
+        }
+    }
+}
+
+/// Sends queued readings oldest first, up to the first that is still
+/// queued after its try.
+async fn drain(stack: &NetStack, settings: &Settings, queue: &mut Queue, backoff: &mut Backoff) {
+    while !queue.is_empty() {
+        if !send_critical(stack, settings).await || !backoff_over(backoff, queue) {
+            return;
+        }
+        let Some(Queued { seq, body, .. }) = queue.front() else {
+            return;
+        };
+        // A flash erase burst stalls the radio; sending into it invites
+        // retransmits and timeouts.
+        flash::wait_idle().await;
+        let extras = Extras::pending();
+        let mut report = Report::new();
+        let with_extras = extras.add_to(&mut report, body).is_ok();
+        let body = if with_extras {
+            report.as_str()
+        } else {
+            body.as_str()
+        };
+        let answer = upload(stack, settings, "application/json", body, *seq).await;
+        if let (true, Ok(answer)) = (with_extras, &answer) {
+            extras.settle(answer);
+        }
+        match answer {
+            Ok(answer) if answer.early => {
+                println!(
+                    "uploader: server answered {} before the whole reading, keeping it",
+                    answer.status
+                );
+                return;
+            }
+            Ok(Answer { status, .. }) => {
+                let verdict = Verdict::of(status);
+                let delay_ms = backoff.settle(verdict, Instant::now().as_millis());
+                match verdict {
+                    Verdict::Delivered => {}
+                    Verdict::Refused => {
+                        println!("uploader: server answered {}, dropping reading", status)
+                    }
+                    Verdict::Deferred => {
+                        println!(
+                            "uploader: server answered {}, keeping {} readings, again in {} ms",
+                            status,
+                            queue.len(),
+                            delay_ms
+                        );
+                        return;
+                    }
+                }
+                queue.pop_front();
+                QUEUE_STATS.note_dequeued(1, queue.len());
+                if verdict == Verdict::Delivered {
+                    delivered().await;
+                }
+            }
+            Err(e) => {
+                upload_failed(&e);
+                return;
+            }
+        }
+    }
+}
+
+/// Whether `backoff` lets bulk uploads go now; says so if not.
+fn backoff_over(backoff: &Backoff, queue: &Queue) -> bool {
+    let now_ms = Instant::now().as_millis();
+    if backoff.ready(now_ms) {
+        return true;
+    }
+    println!(
+        "uploader: holding {} readings, again in {} ms",
+        queue.len(),
+        backoff.until_ms() - now_ms
+    );
+    false
+}
+
+/// After the server has accepted an upload.
+async fn delivered() {
+    settings::confirm().await;
+    // Reaching the server is what proves a new image works.
+    if let Err(e) = boot::mark_update_success().await {
+        println!("uploader: could not confirm update: {:?}", e);
+    }
+}
+
+fn upload_failed(e: &FetchError) {
+    println!(
+        "uploader: upload failed during {}: {}",
+        e.phase().as_str(),
+        diag::classify(e).describe()
+    );
+    if connectivity::tls_was_reset(e) {
+        connectivity::request_probe();
+    }
+}
+
+fn reading(settings: &Settings, seq: Option<u64>) -> Reading {
+    telemetry::serialize(&telemetryschema::Sample {
+        uptime_ms: monotime::since_boot(),
+        uptime_total_ms: monotime::uptime_total(),
+        config_revision: settings.revision,
+        seq,
+        message_id: entropy::uuid_v4(&mut HardwareRng),
+        reset: restart::last(),
+        wifi_alloc_failures: wifiheap::failures(),
+        power: power::status(),
+        batch: settings.batch.map(|_| batch::stats()),
+    })
+}
+
+/// What rides along with the first reading of an upload: the crash report
+/// and the build until one is accepted, the labels always.
+struct Extras {
+    crash: Option<Crash>,
+    build: bool,
+    labels: Labels,
+}
+
+impl Extras {
+    fn pending() -> Self {
+        Self {
+            crash: crash::pending(),
+            build: !BUILD_SENT.load(Ordering::Relaxed),
+            labels: settings::labels(),
+        }
+    }
+
+    /// `reading` with `crash`, `build` and `labels` as more fields. Fails,
+    /// leaving the reading to go as it is, if there is nothing to add.
+    fn add_to(&self, out: &mut Report, reading: &str) -> core::fmt::Result {
+        if self.crash.is_none() && !self.build && self.labels.is_empty() {
+            return Err(core::fmt::Error);
+        }
+        out.push_str(reading.strip_suffix('}').unwrap_or(reading))
+            .map_err(|_| core::fmt::Error)?;
+        if let Some(crash) = &self.crash {
+            out.write_str(",\"crash\":")?;
+            crash.write_json(out)?;
+        }
+        if self.build {
+            write!(out, ",\"build\":{}", buildinfo::JSON)?;
+        }
+        if !self.labels.is_empty() {
+            out.write_str(",\"labels\":")?;
+            self.labels.write_json(out)?;
+        }
+        out.write_char('}')
+    }
+
+    /// After an upload that carried them was answered.
+    fn settle(&self, answer: &Answer) {
+        if self.crash.is_some() {
+            crash::settle(answer.status, answer.early);
+        }
+        if self.build && crashrecord::accepted(answer.status, answer.early) {
+            BUILD_SENT.store(true, Ordering::Relaxed);
+        }
+    }
+}
+
+/// What the server said to an upload.
+struct Answer {
+    status: u16,
+    /// Before the whole body was sent; the readings did not get there.
+    early: bool,
+    /// `X-Safe-Mode: exit`.
+    safe_mode_exit: bool,
+}
+
+/// `sent_through` is the highest `seq` in `body`, to check the server's
+/// `X-Last-Seq` against.
+async fn upload(
+    stack: &NetStack,
+    settings: &Settings,
+    content_type: &str,
+    body: &str,
+    sent_through: Option<u64>,
+) -> Result<Answer, FetchError> {
+    let mut response = [0u8; 256];
+    let url = &settings.upload_url;
+    let gzipped = if settings.compress && !gzip_refused(url) {
+        deflate::gzip_smaller(body.as_bytes())
+    } else {
+        None
+    };
+    let mut result = match &gzipped {
+        Some(gzipped) => https::post_gzip(stack, url, content_type, gzipped, &mut response).await?,
+        None => https::post(stack, url, content_type, body.as_bytes(), &mut response).await?,
+    };
+    // What RFC 7694 answers a content coding the server does not take.
+    if gzipped.is_some() && result.status == 415 {
+        println!(
+            "uploader: {} refused gzip, sending it plain from now on",
+            url
+        );
+        refuse_gzip(url);
+        result = https::post(stack, url, content_type, body.as_bytes(), &mut response).await?;
+    }
+    if (200..300).contains(&result.status) && !result.early {
+        let last_seq = result.header(&response, "X-Last-Seq");
+        if let (Some(last_seq), Some(sent_through)) = (last_seq, sent_through) {
+            sequence::acknowledged(last_seq, sent_through);
+        }
+    }
+    if let Some(version) = result
+        .header(&response, "X-Telemetry-Schema")
+        .and_then(|value| value.parse().ok())
+    {
+        telemetry::server_reads(version);
+    }
+    let safe_mode_exit = result
+        .header(&response, "X-Safe-Mode")
+        .is_some_and(|value| value.eq_ignore_ascii_case("exit"));
+    Ok(Answer {
+        status: result.status,
+        early: result.early,
+        safe_mode_exit,
+    })
+}
+
+/// Whether the endpoint at `url` has refused a gzipped body this boot.
+fn gzip_refused(url: &str) -> bool {
+    let hash = crc32_le(0, url.as_bytes());
+    GZIP_REFUSED.lock(|refused| refused.borrow().contains(&hash))
+}
+
+fn refuse_gzip(url: &str) {
+    let hash = crc32_le(0, url.as_bytes());
+    GZIP_REFUSED.lock(|refused| {
+        let mut refused = refused.borrow_mut();
+        if refused.is_full() {
+            refused.remove(0);
+        }
+        let _ = refused.push(hash);
+    });
+}
+
+async fn poll_config(stack: &NetStack, settings: &Settings) {
+    // Room for the response head in front of a full-size document, and for
+    // the multipart framing and a firmware URL next to it.
+    let mut response = [0u8; MAX_DOCUMENT_LEN + MAX_URL_LEN + 768];
+    let result = if settings.verify_config {
+        https::get_verified(stack, &settings.config_url, None, &mut response).await
+    } else {
+        https::get(stack, &settings.config_url, &mut response).await
+    };
+    let result = match result {
+        Ok(result) => result,
+        Err(e) => {
+            println!(
+                "uploader: config poll failed during {}: {}",
+                e.phase().as_str(),
+                diag::classify(&e).describe()
+            );
+            return;
+        }
+    };
+    if result.status != 200 {
+        println!("uploader: config poll answered {}", result.status);
+        return;
+    }
+
+    let body = result.body(&response);
+    let Some(boundary) = result
+        .header(&response, "Content-Type")
+        .and_then(multipart::boundary)
+    else {
+        stage_config(body);
+        return;
+    };
+    let mut firmware_url = None;
+    let mut commands = fleet::begin().await;
+    let mut parts = MultipartParser::new(boundary, body);
+    while let Some((content_type, content)) = parts.next_part() {
+        let media_type = content_type.split(';').next().unwrap_or("").trim();
+        if media_type.eq_ignore_ascii_case("text/plain") {
+            stage_config(content);
+        } else if media_type.eq_ignore_ascii_case("application/json") {
+            fleet::handle(stack, &mut commands, content).await;
+        } else if media_type.eq_ignore_ascii_case("text/uri-list") {
+            // One URI per line; `#` starts a comment.
+            firmware_url = str::from_utf8(content)
+                .ok()
+                .and_then(|list| {
+                    list.lines()
+                        .map(str::trim)
+                        .find(|line| !line.is_empty() && !line.starts_with('#'))
+                })
+                .or(firmware_url);
+        } else {
+            println!("uploader: skipping a {} part of the config", media_type);
+        }
+    }
+    fleet::finish(commands).await;
+    if let Some(url) = firmware_url {
+        #[cfg(feature = "ota")]
+        crate::ota::update(stack, url).await;
+        #[cfg(not(feature = "ota"))]
+        println!("uploader: ignoring firmware {}: this build has no OTA", url);
+    }
+}
+
+fn stage_config(body: &[u8]) {
+    let Ok(text) = str::from_utf8(body) else {
+        println!("uploader: config is not UTF-8");
+        return;
+    };
+    match settings::stage(text) {
+        Ok(changes) => println!("uploader: staged new config ({:?})", changes),
+        // The server keeps serving the revision we already run.
+        Err(SettingsError::StaleRevision(_)) => {}
+        Err(e) => println!("uploader: rejected config: {:?}", e),
+    }
+}
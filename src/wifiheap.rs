@@ -0,0 +1,170 @@
+// esp-wifi's heap, where the Wi-Fi blobs keep their buffers and control
+// blocks. When it runs out, scans and connects fail and frames go missing,
+// and all that says why is one warning from esp-wifi.
+//
+// esp-wifi 0.6 has no API for it. What it does have is the OS adapter table
+// the blobs call into, `g_wifi_osi_funcs`, exported for them to link
+// against, and its `_get_free_heap_size` entry answers with the heap's free
+// bytes. `free` calls it only if the table's version and magic are the ones
+// its layout here is for, so a newer esp-wifi gives no figures rather than
+// wrong ones. The heap's size is `heap_size` in cfg.toml's `[esp-wifi]`
+// section, which build.rs hands on as WIFI_HEAP_SIZE. The housekeeping task
+// samples the use (`sample`), so the peak is the highest seen every few
+// seconds, not the true one.
+//
+// Failed allocations are counted off esp-wifi's own `Unable to allocate`
+// warning, which the logger (src/logring.rs) shows `watch`. With
+// `log_level` below `warn` it is never logged, and so not counted. After
+// the first one the housekeeping task logs which knobs to turn, and
+// readings carry `wifi_alloc_failures` (src/uploader.rs).
+
+use core::fmt::{self, Write};
+use core::ptr::addr_of;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use esp_println::println;
+use log::{Level, Record};
+use portable_atomic::AtomicU32;
+
+/// In bytes.
+pub const HEAP_SIZE: u32 = parse(env!("WIFI_HEAP_SIZE"));
+
+// `wifi_osi_funcs_t` of esp-wifi-sys 0.3 for the C3: the version, 115
+// function pointers, the magic.
+const OSI_FUNCS_LEN: usize = 117;
+const OSI_VERSION: usize = 8;
+const OSI_MAGIC: usize = 0xDEAD_BEAF;
+const GET_FREE_HEAP_SIZE: usize = 46;
+
+extern "C" {
+    static g_wifi_osi_funcs: [usize; OSI_FUNCS_LEN];
+}
+
+/// Set once esp-wifi has set up the heap; before that it is empty.
+static INITIALIZED: AtomicBool = AtomicBool::new(false);
+/// Only the housekeeping task writes it.
+static PEAK: AtomicU32 = AtomicU32::new(0);
+static FAILURES: AtomicU32 = AtomicU32::new(0);
+static HINTED: AtomicBool = AtomicBool::new(false);
+
+const fn parse(digits: &str) -> u32 {
+    let digits = digits.as_bytes();
+    let mut n = 0;
+    let mut i = 0;
+    while i < digits.len() {
+        n = n * 10 + (digits[i] - b'0') as u32;
+        i += 1;
+    }
+    n
+}
+
+/// Call once `esp_wifi::initialize` has succeeded.
+pub fn note_initialized() {
+    INITIALIZED.store(true, Ordering::Relaxed);
+    sample();
+}
+
+fn free() -> Option<u32> {
+    if !INITIALIZED.load(Ordering::Relaxed) {
+        return None;
+    }
+    let table = unsafe { &*addr_of!(g_wifi_osi_funcs) };
+    let entry = table[GET_FREE_HEAP_SIZE];
+    if table[0] != OSI_VERSION || table[OSI_FUNCS_LEN - 1] != OSI_MAGIC || entry == 0 {
+        return None;
+    }
+    // Checked above: the layout is the expected one and the entry is set.
+    let get_free_heap_size: unsafe extern "C" fn() -> u32 = unsafe { core::mem::transmute(entry) };
+    Some(unsafe { get_free_heap_size() })
+}
+
+/// Bytes in use now, if the table could be read.
+pub fn used() -> Option<u32> {
+    free().map(|free| HEAP_SIZE.saturating_sub(free))
+}
+
+/// The most `sample` has seen in use.
+pub fn peak() -> u32 {
+    PEAK.load(Ordering::Relaxed)
+}
+
+pub fn failures() -> u32 {
+    FAILURES.load(Ordering::Relaxed)
+}
+
+/// Updates the peak, and after the first failed allocation says what to
+/// do about it. Run periodically by the housekeeping task.
+pub fn sample() {
+    if let Some(used) = used() {
+        if used > peak() {
+            PEAK.store(used, Ordering::Relaxed);
+        }
+    }
+    if failures() > 0 && !HINTED.load(Ordering::Relaxed) {
+        HINTED.store(true, Ordering::Relaxed);
+        print_usage();
+        println!(
+            "wifi heap: out of memory; raise heap_size, or lower dynamic_rx_buf_num, \
+             dynamic_tx_buf_num or rx_queue_size, in cfg.toml's [esp-wifi] section"
+        );
+    }
+}
+
+pub fn print_usage() {
+    match used() {
+        Some(used) => println!(
+            "wifi heap: {} of {} bytes used, at most {} seen; {} failed allocations",
+            used,
+            HEAP_SIZE,
+            peak(),
+            failures()
+        ),
+        None => println!("wifi heap: use unknown; {} failed allocations", failures()),
+    }
+}
+
+/// Counts `record` if it is esp-wifi failing to allocate. For the
+/// logger, which shows it every record.
+pub fn watch(record: &Record) {
+    if record.level() == Level::Warn && record.target().starts_with("esp_wifi") {
+        let mut check = StartsWith::new("Unable to allocate");
+        let _ = write!(check, "{}", record.args());
+        if check.matches() {
+            FAILURES.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Whether what is written to it starts with `prefix`, without keeping it.
+struct StartsWith {
+    prefix: &'static str,
+    matched: usize,
+    mismatch: bool,
+}
+
+impl StartsWith {
+    fn new(prefix: &'static str) -> Self {
+        Self {
+            prefix,
+            matched: 0,
+            mismatch: false,
+        }
+    }
+
+    fn matches(&self) -> bool {
+        !self.mismatch && self.matched == self.prefix.len()
+    }
+}
+
+impl Write for StartsWith {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let rest = &self.prefix.as_bytes()[self.matched..];
+        let n = rest.len().min(s.len());
+        if s.as_bytes()[..n] == rest[..n] {
+            self.matched += n;
+        } else {
+            self.mismatch = true;
+        }
+        Ok(())
+    }
+}
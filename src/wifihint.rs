@@ -0,0 +1,114 @@
+// Where the station last associated: the access point's BSSID and channel,
+// kept in RTC fast memory so that the connect after a reset can go straight
+// there instead of scanning every channel first.
+//
+// Main saves a hint after each association (`save`) and loads it before
+// connecting (`load`). For the first `DIRECTED_ATTEMPTS` attempts the
+// station asks for that BSSID on that channel; after that, or with no hint,
+// it connects as it always did, scanning for the SSID. `directed` decides,
+// from the attempt number and the hint's age, and takes the time as an
+// argument.
+//
+// A hint is stale once older than WIFI_HINT_MAX_AGE_S at build time, or
+// `DEFAULT_MAX_AGE_S`; the access point may have moved channel since. Its
+// age needs the wall clock, at saving and at loading both; without it the
+// hint is tried anyway, since a wrong one costs only the directed attempts.
+// A hint for another SSID, from a build with different credentials, is
+// ignored.
+//
+// Like the safe-mode note the record has a magic and a CRC, and a power cut
+// loses it.
+
+use core::ptr::addr_of_mut;
+
+use esp_hal::macros::ram;
+use esp_hal::rom::crc::crc32_le;
+
+/// Attempts with the hint before falling back to a full scan.
+pub const DIRECTED_ATTEMPTS: usize = 2;
+const DEFAULT_MAX_AGE_S: u64 = 60 * 60;
+const MAX_AGE_S: Option<&str> = option_env!("WIFI_HINT_MAX_AGE_S");
+
+const MAGIC: u32 = 0x5748_494e;
+// magic | bssid | channel | clock set | unix_ms (u64) | ssid crc | crc
+const RECORD_LEN: usize = 28;
+
+#[ram(rtc_fast, uninitialized)]
+static mut RECORD: [u8; RECORD_LEN] = [0; RECORD_LEN];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Hint {
+    pub bssid: [u8; 6],
+    pub channel: u8,
+    /// When it was saved, if the clock was set then.
+    pub unix_ms: Option<u64>,
+}
+
+impl Hint {
+    fn encode(&self, ssid: &str) -> [u8; RECORD_LEN] {
+        let mut raw = [0u8; RECORD_LEN];
+        raw[0..4].copy_from_slice(&MAGIC.to_le_bytes());
+        raw[4..10].copy_from_slice(&self.bssid);
+        raw[10] = self.channel;
+        raw[11] = self.unix_ms.is_some() as u8;
+        raw[12..20].copy_from_slice(&self.unix_ms.unwrap_or(0).to_le_bytes());
+        raw[20..24].copy_from_slice(&crc32_le(0, ssid.as_bytes()).to_le_bytes());
+        let crc = crc32_le(0, &raw[..RECORD_LEN - 4]);
+        raw[RECORD_LEN - 4..].copy_from_slice(&crc.to_le_bytes());
+        raw
+    }
+
+    /// `None` for anything `encode` did not write for `ssid`.
+    fn decode(raw: &[u8; RECORD_LEN], ssid: &str) -> Option<Hint> {
+        let word = |at: usize| u32::from_le_bytes(raw[at..at + 4].try_into().unwrap());
+        if word(0) != MAGIC
+            || word(RECORD_LEN - 4) != crc32_le(0, &raw[..RECORD_LEN - 4])
+            || word(20) != crc32_le(0, ssid.as_bytes())
+        {
+            return None;
+        }
+        let unix_ms = u64::from_le_bytes(raw[12..20].try_into().unwrap());
+        Some(Hint {
+            bssid: raw[4..10].try_into().unwrap(),
+            channel: raw[10],
+            unix_ms: (raw[11] != 0).then_some(unix_ms),
+        })
+    }
+
+    /// Whether it is older than `max_age_ms` at `now_unix_ms`. Not if
+    /// either time is unknown.
+    pub fn is_stale(&self, now_unix_ms: Option<u64>, max_age_ms: u64) -> bool {
+        match (self.unix_ms, now_unix_ms) {
+            (Some(saved), Some(now)) => now.saturating_sub(saved) > max_age_ms,
+            _ => false,
+        }
+    }
+}
+
+/// The hint to connect with on attempt `attempt`, counted from 1: `hint`
+/// for the first `DIRECTED_ATTEMPTS` unless it is stale, else none.
+pub fn directed(
+    hint: Option<Hint>,
+    attempt: usize,
+    now_unix_ms: Option<u64>,
+    max_age_ms: u64,
+) -> Option<Hint> {
+    hint.filter(|hint| attempt <= DIRECTED_ATTEMPTS && !hint.is_stale(now_unix_ms, max_age_ms))
+}
+
+pub fn max_age_ms() -> u64 {
+    MAX_AGE_S
+        .and_then(|s| s.parse::<u64>().ok())
+        .unwrap_or(DEFAULT_MAX_AGE_S)
+        * 1000
+}
+
+/// The hint saved for `ssid`, if any.
+pub fn load(ssid: &str) -> Option<Hint> {
+    critical_section::with(|_| Hint::decode(unsafe { &*addr_of_mut!(RECORD) }, ssid))
+}
+
+pub fn save(hint: &Hint, ssid: &str) {
+    let raw = hint.encode(ssid);
+    critical_section::with(|_| unsafe { *addr_of_mut!(RECORD) = raw });
+}
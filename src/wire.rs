@@ -0,0 +1,227 @@
+// Bounds-checked big-endian reading and writing for the protocol codecs:
+// DNS (src/dns.rs), mDNS (src/mdns.rs) and NTP (src/ntp.rs).
+//
+// `ReadCursor` and `WriteCursor` keep a position in a byte slice and check
+// every access against its end, so a short message or a full buffer is a
+// `WireError`, not a panic or a value read from the wrong place. Integers
+// are network byte order. A `Mark` is a position to come back to:
+// `ReadCursor::rewind` jumps back to it, and `WriteCursor` can fill in a
+// length there once what it counts has been written (`reserve_u16` and
+// `patch_len_u16`), or drop everything after it (`rewind`).
+
+// Not every codec uses every width and prefix.
+#![allow(dead_code)]
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WireError {
+    /// Read past the end of the message.
+    Truncated,
+    /// Wrote past the end of the buffer.
+    Overflow,
+    /// Longer than its length prefix can say.
+    TooLong,
+}
+
+/// A position in a cursor's buffer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Mark(usize);
+
+pub struct ReadCursor<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> ReadCursor<'a> {
+    pub fn new(buf: &'a [u8]) -> Self {
+        Self { buf, pos: 0 }
+    }
+
+    /// Starting at `pos`, e.g. a compression pointer's target.
+    pub fn at(buf: &'a [u8], pos: usize) -> Result<Self, WireError> {
+        let mut cursor = Self::new(buf);
+        cursor.seek(pos)?;
+        Ok(cursor)
+    }
+
+    pub fn position(&self) -> usize {
+        self.pos
+    }
+
+    pub fn remaining(&self) -> usize {
+        self.buf.len() - self.pos
+    }
+
+    pub fn seek(&mut self, pos: usize) -> Result<(), WireError> {
+        if pos > self.buf.len() {
+            return Err(WireError::Truncated);
+        }
+        self.pos = pos;
+        Ok(())
+    }
+
+    pub fn mark(&self) -> Mark {
+        Mark(self.pos)
+    }
+
+    pub fn rewind(&mut self, mark: Mark) {
+        self.pos = mark.0;
+    }
+
+    pub fn skip(&mut self, len: usize) -> Result<(), WireError> {
+        self.bytes(len).map(|_| ())
+    }
+
+    pub fn bytes(&mut self, len: usize) -> Result<&'a [u8], WireError> {
+        let end = self.pos.checked_add(len).ok_or(WireError::Truncated)?;
+        let bytes = self.buf.get(self.pos..end).ok_or(WireError::Truncated)?;
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    /// The next byte, left in place.
+    pub fn peek_u8(&self) -> Result<u8, WireError> {
+        self.buf.get(self.pos).copied().ok_or(WireError::Truncated)
+    }
+
+    pub fn u8(&mut self) -> Result<u8, WireError> {
+        Ok(self.array::<1>()?[0])
+    }
+
+    pub fn u16(&mut self) -> Result<u16, WireError> {
+        self.array().map(u16::from_be_bytes)
+    }
+
+    pub fn u32(&mut self) -> Result<u32, WireError> {
+        self.array().map(u32::from_be_bytes)
+    }
+
+    pub fn u64(&mut self) -> Result<u64, WireError> {
+        self.array().map(u64::from_be_bytes)
+    }
+
+    /// Bytes after a one-byte length.
+    pub fn prefixed_u8(&mut self) -> Result<&'a [u8], WireError> {
+        let mark = self.mark();
+        let len = self.u8()? as usize;
+        self.bytes(len).inspect_err(|_| self.rewind(mark))
+    }
+
+    /// Bytes after a two-byte length.
+    pub fn prefixed_u16(&mut self) -> Result<&'a [u8], WireError> {
+        let mark = self.mark();
+        let len = self.u16()? as usize;
+        self.bytes(len).inspect_err(|_| self.rewind(mark))
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], WireError> {
+        let mut out = [0; N];
+        out.copy_from_slice(self.bytes(N)?);
+        Ok(out)
+    }
+}
+
+pub struct WriteCursor<'a> {
+    buf: &'a mut [u8],
+    len: usize,
+}
+
+impl<'a> WriteCursor<'a> {
+    pub fn new(buf: &'a mut [u8]) -> Self {
+        Self { buf, len: 0 }
+    }
+
+    /// Bytes written so far.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn written(&self) -> &[u8] {
+        &self.buf[..self.len]
+    }
+
+    pub fn mark(&self) -> Mark {
+        Mark(self.len)
+    }
+
+    /// Drops everything written after `mark`.
+    pub fn rewind(&mut self, mark: Mark) {
+        self.len = self.len.min(mark.0);
+    }
+
+    pub fn bytes(&mut self, bytes: &[u8]) -> Result<(), WireError> {
+        let end = self.len + bytes.len();
+        self.buf
+            .get_mut(self.len..end)
+            .ok_or(WireError::Overflow)?
+            .copy_from_slice(bytes);
+        self.len = end;
+        Ok(())
+    }
+
+    pub fn u8(&mut self, value: u8) -> Result<(), WireError> {
+        self.bytes(&[value])
+    }
+
+    pub fn u16(&mut self, value: u16) -> Result<(), WireError> {
+        self.bytes(&value.to_be_bytes())
+    }
+
+    pub fn u32(&mut self, value: u32) -> Result<(), WireError> {
+        self.bytes(&value.to_be_bytes())
+    }
+
+    pub fn u64(&mut self, value: u64) -> Result<(), WireError> {
+        self.bytes(&value.to_be_bytes())
+    }
+
+    /// `bytes` after a one-byte length. Nothing is written if it fails.
+    pub fn prefixed_u8(&mut self, bytes: &[u8]) -> Result<(), WireError> {
+        let len = u8::try_from(bytes.len()).map_err(|_| WireError::TooLong)?;
+        self.prefixed(&[len], bytes)
+    }
+
+    /// `bytes` after a two-byte length. Nothing is written if it fails.
+    pub fn prefixed_u16(&mut self, bytes: &[u8]) -> Result<(), WireError> {
+        let len = u16::try_from(bytes.len()).map_err(|_| WireError::TooLong)?;
+        self.prefixed(&len.to_be_bytes(), bytes)
+    }
+
+    fn prefixed(&mut self, prefix: &[u8], bytes: &[u8]) -> Result<(), WireError> {
+        let mark = self.mark();
+        self.bytes(prefix)
+            .and_then(|()| self.bytes(bytes))
+            .inspect_err(|_| self.rewind(mark))
+    }
+
+    /// Writes a placeholder for a two-byte length and returns where it is,
+    /// for `patch_len_u16`.
+    pub fn reserve_u16(&mut self) -> Result<Mark, WireError> {
+        let mark = self.mark();
+        self.u16(0)?;
+        Ok(mark)
+    }
+
+    /// Overwrites the two bytes at `mark` with `value`.
+    pub fn patch_u16(&mut self, mark: Mark, value: u16) -> Result<(), WireError> {
+        if mark.0 + 2 > self.len {
+            return Err(WireError::Overflow);
+        }
+        self.buf[mark.0..mark.0 + 2].copy_from_slice(&value.to_be_bytes());
+        Ok(())
+    }
+
+    /// Fills the placeholder at `mark` with the number of bytes written
+    /// since it.
+    pub fn patch_len_u16(&mut self, mark: Mark) -> Result<(), WireError> {
+        let len = self
+            .len
+            .checked_sub(mark.0 + 2)
+            .ok_or(WireError::Overflow)?;
+        let len = u16::try_from(len).map_err(|_| WireError::TooLong)?;
+        self.patch_u16(mark, len)
+    }
+}
@@ -0,0 +1,124 @@
+// Wire trace: a hexdump of every byte a request writes and reads, for
+// servers that get HTTP framing wrong.
+//
+// The bytes are HTTP, after TLS decryption; the handshake and the records
+// around the data are not shown. `arm` traces the next connection only; the
+// console's `wiretrace` command calls it. Lines carry the connection's
+// number, `>>` or `<<` for the direction and the offset within that
+// direction, from the start of the connection.
+//
+// So the trace cannot flood the UART, a connection shows at most
+// `MAX_SHOWN_BYTES`, and lines beyond `LINES_PER_SECOND` (after a burst of
+// `BURST_LINES`) are dropped rather than waited for, which would stall the
+// request. The summary printed when the connection ends counts what was
+// left out.
+
+use embassy_time::{Duration, Instant};
+use esp_println::println;
+use portable_atomic::{AtomicBool, AtomicU32, Ordering};
+
+use crate::codec::{self, HEXDUMP_ROW};
+
+const MAX_SHOWN_BYTES: usize = 4096;
+const LINES_PER_SECOND: u64 = 20;
+const BURST_LINES: u64 = 64;
+
+static ARMED: AtomicBool = AtomicBool::new(false);
+static CONNECTIONS: AtomicU32 = AtomicU32::new(0);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Sent,
+    Received,
+}
+
+impl Direction {
+    fn marker(self) -> &'static str {
+        match self {
+            Direction::Sent => ">>",
+            Direction::Received => "<<",
+        }
+    }
+}
+
+/// Traces the next connection that opens.
+pub fn arm() {
+    ARMED.store(true, Ordering::Relaxed);
+}
+
+/// A trace for a connection that has just opened, if one was armed.
+pub fn begin() -> Option<Trace> {
+    if !ARMED.swap(false, Ordering::Relaxed) {
+        return None;
+    }
+    let id = CONNECTIONS.fetch_add(1, Ordering::Relaxed) + 1;
+    println!("wire {}: tracing", id);
+    Some(Trace {
+        id,
+        sent: 0,
+        received: 0,
+        shown: 0,
+        hidden: 0,
+        lines: BURST_LINES,
+        refilled: Instant::now(),
+    })
+}
+
+pub struct Trace {
+    id: u32,
+    /// Bytes so far in each direction; the offset of the next.
+    sent: u32,
+    received: u32,
+    shown: usize,
+    hidden: usize,
+    /// Lines that may go out right away.
+    lines: u64,
+    refilled: Instant,
+}
+
+impl Trace {
+    pub fn record(&mut self, direction: Direction, data: &[u8]) {
+        let total = match direction {
+            Direction::Sent => &mut self.sent,
+            Direction::Received => &mut self.received,
+        };
+        let start = *total;
+        *total = total.wrapping_add(data.len() as u32);
+
+        let mut line = [0u8; codec::hexdump_line_len(HEXDUMP_ROW)];
+        for (i, row) in data.chunks(HEXDUMP_ROW).enumerate() {
+            if self.shown + row.len() > MAX_SHOWN_BYTES || !self.take_line() {
+                self.hidden += row.len();
+                continue;
+            }
+            self.shown += row.len();
+            let offset = start.wrapping_add((i * HEXDUMP_ROW) as u32);
+            if let Ok(line) = codec::hexdump_line(offset, row, &mut line) {
+                println!("wire {} {} {}", self.id, direction.marker(), line);
+            }
+        }
+    }
+
+    /// Whether the rate limit lets another line out now.
+    fn take_line(&mut self) -> bool {
+        let earned = self.refilled.elapsed().as_millis() * LINES_PER_SECOND / 1000;
+        if earned > 0 {
+            self.lines = (self.lines + earned).min(BURST_LINES);
+            self.refilled += Duration::from_millis(earned * 1000 / LINES_PER_SECOND);
+        }
+        if self.lines == 0 {
+            return false;
+        }
+        self.lines -= 1;
+        true
+    }
+}
+
+impl Drop for Trace {
+    fn drop(&mut self) {
+        println!(
+            "wire {}: {} bytes sent, {} received, {} not shown",
+            self.id, self.sent, self.received, self.hidden
+        );
+    }
+}
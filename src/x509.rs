@@ -0,0 +1,179 @@
+// The fields of an X.509 certificate (RFC 5280) worth showing about a
+// server: the subject's common name and the subject alternative names, read
+// from the certificate's DER without copying it.
+//
+// Only what leads to them is parsed: the outer SEQUENCE, the TBSCertificate
+// up to the subject, and the extensions for `subjectAltName`. Everything
+// else is skipped by its length, signature included, so an algorithm or an
+// extension this does not know is no error. Lengths are DER's definite
+// ones, up to four bytes of them; anything that runs past its enclosing
+// element is `Malformed`.
+//
+// Nothing feeds it a certificate yet: embedded-tls 0.17 keeps the server's
+// to a verifier of its own making (src/tlsinfo.rs). A verifier that can
+// pass it on brings `tlsinfo` its names, and pinning the DER to hash.
+
+// Unused until then.
+#![allow(dead_code)]
+
+const SEQUENCE: u8 = 0x30;
+const SET: u8 = 0x31;
+const OID: u8 = 0x06;
+const BOOLEAN: u8 = 0x01;
+const OCTET_STRING: u8 = 0x04;
+/// `[0]` and `[3]`, explicit: the version and the extensions.
+const VERSION: u8 = 0xA0;
+const EXTENSIONS: u8 = 0xA3;
+/// The `GeneralName` choices, implicit.
+const DNS_NAME: u8 = 0x82;
+const IP_ADDRESS: u8 = 0x87;
+
+/// 2.5.4.3
+const COMMON_NAME: &[u8] = &[0x55, 0x04, 0x03];
+/// 2.5.29.17
+const SUBJECT_ALT_NAME: &[u8] = &[0x55, 0x1D, 0x11];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DerError {
+    /// A length past the end of its element, or an element not where the
+    /// structure has it.
+    Malformed,
+    /// A length of more than four bytes, or indefinite.
+    Unsupported,
+}
+
+/// A name in `subjectAltName`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AltName<'a> {
+    Dns(&'a str),
+    /// Four bytes, or sixteen.
+    Ip(&'a [u8]),
+    /// Of a kind not shown: email, URI, directory name and the rest.
+    Other(u8),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Certificate<'a> {
+    /// The subject `Name`'s contents.
+    subject: &'a [u8],
+    /// The `subjectAltName` extension's `GeneralNames` contents, if any.
+    alt_names: Option<&'a [u8]>,
+}
+
+impl<'a> Certificate<'a> {
+    pub fn parse(der: &'a [u8]) -> Result<Self, DerError> {
+        let mut outer = Reader::new(der);
+        let mut certificate = Reader::new(outer.expect(SEQUENCE)?);
+        let mut tbs = Reader::new(certificate.expect(SEQUENCE)?);
+        if tbs.peek() == Some(VERSION) {
+            tbs.next()?;
+        }
+        // serialNumber, signature, issuer, validity.
+        for _ in 0..4 {
+            tbs.next()?;
+        }
+        let subject = tbs.expect(SEQUENCE)?;
+        // subjectPublicKeyInfo.
+        tbs.expect(SEQUENCE)?;
+
+        let mut alt_names = None;
+        // The unique IDs may come first.
+        while let Some(tag) = tbs.peek() {
+            let value = tbs.next()?.1;
+            if tag != EXTENSIONS {
+                continue;
+            }
+            let mut extensions = Reader::new(Reader::new(value).expect(SEQUENCE)?);
+            while !extensions.is_empty() {
+                let mut extension = Reader::new(extensions.expect(SEQUENCE)?);
+                let id = extension.expect(OID)?;
+                if extension.peek() == Some(BOOLEAN) {
+                    extension.next()?;
+                }
+                let value = extension.expect(OCTET_STRING)?;
+                if id == SUBJECT_ALT_NAME {
+                    alt_names = Some(Reader::new(value).expect(SEQUENCE)?);
+                }
+            }
+        }
+        Ok(Self { subject, alt_names })
+    }
+
+    /// The subject's first common name, if it is text.
+    pub fn common_name(&self) -> Option<&'a str> {
+        let mut rdns = Reader::new(self.subject);
+        while let Ok((SET, rdn)) = rdns.next() {
+            let mut attributes = Reader::new(rdn);
+            while let Ok((SEQUENCE, attribute)) = attributes.next() {
+                let mut attribute = Reader::new(attribute);
+                if attribute.expect(OID).ok() == Some(COMMON_NAME) {
+                    // UTF8String, PrintableString or whichever: all text.
+                    let (_, value) = attribute.next().ok()?;
+                    return core::str::from_utf8(value).ok();
+                }
+            }
+        }
+        None
+    }
+
+    /// The names `subjectAltName` lists, in its order. Stops early at one
+    /// that is malformed.
+    pub fn alt_names(&self) -> impl Iterator<Item = AltName<'a>> {
+        let mut names = Reader::new(self.alt_names.unwrap_or(&[]));
+        core::iter::from_fn(move || {
+            let (tag, value) = names.next().ok()?;
+            Some(match tag {
+                DNS_NAME => core::str::from_utf8(value).map_or(AltName::Other(tag), AltName::Dns),
+                IP_ADDRESS => AltName::Ip(value),
+                _ => AltName::Other(tag),
+            })
+        })
+    }
+}
+
+/// The elements of one DER element's contents, one after the other.
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.data.first().copied()
+    }
+
+    /// The next element's tag and contents.
+    fn next(&mut self) -> Result<(u8, &'a [u8]), DerError> {
+        let [tag, first, rest @ ..] = self.data else {
+            return Err(DerError::Malformed);
+        };
+        let (len, rest) = match *first {
+            len @ 0..=0x7F => (len as usize, rest),
+            0x80 | 0x85.. => return Err(DerError::Unsupported),
+            long => {
+                let count = (long & 0x7F) as usize;
+                let bytes = rest.get(..count).ok_or(DerError::Malformed)?;
+                let len = bytes.iter().fold(0, |len, &b| len << 8 | b as usize);
+                (len, &rest[count..])
+            }
+        };
+        let value = rest.get(..len).ok_or(DerError::Malformed)?;
+        self.data = &rest[len..];
+        Ok((*tag, value))
+    }
+
+    /// The next element's contents, which must have `tag`.
+    fn expect(&mut self, tag: u8) -> Result<&'a [u8], DerError> {
+        match self.next()? {
+            (found, value) if found == tag => Ok(value),
+            _ => Err(DerError::Malformed),
+        }
+    }
+}
@@ -0,0 +1,39 @@
+#!/bin/sh
+# Lists the discovery beacons (src/beacon.rs) on the local network as they
+# arrive, one line per beacon: address, firmware version, provisioning state
+# and the device fingerprint.
+#
+#     tools/beacon-listen.sh          # port 47474
+#     tools/beacon-listen.sh 50000    # a build with BEACON_PORT=50000
+#
+#     192.168.1.40 0.1.0 unprovisioned 3f9a0c...
+#
+# Needs socat. Anything that is not a beacon is skipped.
+set -eu
+
+if [ "${1:-}" = --decode ]; then
+    # One datagram on stdin. Word-split on purpose: one argument per byte.
+    # shellcheck disable=SC2046
+    set -- $(od -An -v -tu1)
+    # "C3BN", format 1, 48 bytes.
+    if [ $# -ne 48 ] || [ "$1 $2 $3 $4 $5" != "67 51 66 78 1" ]; then
+        exit 0
+    fi
+    flags=$6
+    version="$7.$8.$9"
+    address="${13}.${14}.${15}.${16}"
+    shift 16
+    if [ $((flags & 1)) -ne 0 ]; then
+        state=provisioned
+    else
+        state=unprovisioned
+    fi
+    if [ $((flags & 2)) -ne 0 ]; then
+        state="$state,asked"
+    fi
+    echo "$address $version $state $(printf '%02x' "$@")"
+    exit 0
+fi
+
+port=${1:-47474}
+exec socat -u "UDP-RECVFROM:$port,broadcast,reuseaddr,fork" SYSTEM:"sh '$0' --decode"
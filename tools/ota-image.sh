@@ -0,0 +1,119 @@
+#!/bin/sh
+# Builds the release image, gzips it for `ota <url>`, signs it and
+# optionally copies the files to the update server.
+#
+#     OTA_SIGNING_KEY=ota-signing.pem tools/ota-image.sh
+#     OTA_SIGNING_KEY=ota-signing.pem OTA_UPLOAD=user@host:/srv/ota tools/ota-image.sh
+#     OTA_SIGNING_KEY=ota-signing.pem OTA_DELTA_FROM=old/firmware.bin tools/ota-image.sh
+#
+# This leaves target/ota/firmware.bin and firmware.bin.gz, each with the
+# `.sig` manifest the firmware fetches first (format in src/ota.rs) and a
+# `.merkle` root. Serve the .gz with `Content-Encoding: gzip` or as a plain
+# file; the firmware recognises either. An `X-Firmware-Version` header is
+# optional but must then be the version in Cargo.toml. So is
+# `X-Merkle-Root`, which must then be the contents of the served file's
+# `.merkle` (src/merkle.rs): the .gz has its own. The key is the private half
+# of keys/ota_signing.pub (see src/signature.rs). Extra arguments go to
+# `cargo build`.
+#
+# With OTA_DELTA_FROM, the firmware.bin a device is running, there is also
+# firmware.patch.gz: a delta update to this build (src/bspatch.rs). It needs
+# `bsdiff` from bsdiff 4.3 (github.com/mendsley/bsdiff), not the older
+# BSDIFF40 one; only devices running exactly that image can apply it.
+set -eu
+
+cd "$(dirname "$0")/.."
+
+: "${OTA_SIGNING_KEY:?set OTA_SIGNING_KEY to the Ed25519 private key (PEM)}"
+
+ELF=target/riscv32imc-unknown-none-elf/release/esp32c3_embedded-tls
+OUT=target/ota
+
+cargo build --release "$@"
+mkdir -p "$OUT"
+espflash save-image --chip esp32c3 "$ELF" "$OUT/firmware.bin"
+gzip -9 --no-name --force --keep "$OUT/firmware.bin"
+
+plain=$(wc -c < "$OUT/firmware.bin")
+packed=$(wc -c < "$OUT/firmware.bin.gz")
+echo "firmware.bin: $plain bytes, firmware.bin.gz: $packed bytes ($((packed * 100 / plain))%)"
+
+# "OTAS", the image length as a little-endian u32, the version from
+# Cargo.toml as three bytes and a zero, and the image's SHA-256, signed.
+bytes() {
+    for byte in "$@"; do
+        printf "$(printf '\\%03o' "$byte")"
+    done
+}
+le32() {
+    bytes $(($1 & 255)) $(($1 >> 8 & 255)) $(($1 >> 16 & 255)) $(($1 >> 24 & 255))
+}
+version=$(sed -n 's/^version = "\([0-9]*\)\.\([0-9]*\)\.\([0-9]*\).*"/\1 \2 \3/p' Cargo.toml | head -n 1)
+{
+    printf 'OTAS'
+    le32 "$plain"
+    # Word-split on purpose: major, minor, patch.
+    # shellcheck disable=SC2086
+    bytes $version 0
+    openssl dgst -sha256 -binary "$OUT/firmware.bin"
+} > "$OUT/manifest"
+openssl pkeyutl -sign -rawin -inkey "$OTA_SIGNING_KEY" -in "$OUT/manifest" -out "$OUT/manifest.sig"
+cat "$OUT/manifest" "$OUT/manifest.sig" > "$OUT/firmware.bin.sig"
+cp "$OUT/firmware.bin.sig" "$OUT/firmware.bin.gz.sig"
+rm "$OUT/manifest" "$OUT/manifest.sig"
+
+# The root, in hex, of the tree src/merkle.rs builds over a file's 4 KB
+# chunks: leaves are the SHA-256 of 0x00 and a chunk, nodes that of 0x01 and
+# two children, and an odd one out goes up a level as it is.
+merkle_root() {
+    dir=$(mktemp -d)
+    split -b 4096 -a 4 "$1" "$dir/c"
+    level=""
+    for chunk in "$dir"/c*; do
+        { printf '\000'; cat "$chunk"; } | openssl dgst -sha256 -binary > "$chunk.h"
+        level="$level $chunk.h"
+    done
+    n=0
+    # Word-split on purpose, here and below: one argument per hash.
+    # shellcheck disable=SC2086
+    set -- $level
+    while [ $# -gt 1 ]; do
+        level=""
+        while [ $# -gt 1 ]; do
+            n=$((n + 1))
+            { printf '\001'; cat "$1" "$2"; } | openssl dgst -sha256 -binary > "$dir/n$n"
+            level="$level $dir/n$n"
+            shift 2
+        done
+        # shellcheck disable=SC2086
+        set -- $level "$@"
+    done
+    od -An -v -tx1 "$1" | tr -d ' \n'
+    rm -r "$dir"
+}
+merkle_root "$OUT/firmware.bin" > "$OUT/firmware.bin.merkle"
+merkle_root "$OUT/firmware.bin.gz" > "$OUT/firmware.bin.gz.merkle"
+
+files="$OUT/firmware.bin $OUT/firmware.bin.sig $OUT/firmware.bin.merkle"
+files="$files $OUT/firmware.bin.gz $OUT/firmware.bin.gz.sig $OUT/firmware.bin.gz.merkle"
+if [ -n "${OTA_DELTA_FROM:-}" ]; then
+    bsdiff "$OTA_DELTA_FROM" "$OUT/firmware.bin" "$OUT/patch"
+    # bsdiff writes its 24-byte header and then bzip2, which the firmware
+    # does not read; swap that for gzip over the whole patch.
+    {
+        head -c 24 "$OUT/patch"
+        tail -c +25 "$OUT/patch" | bunzip2
+    } | gzip -9 --no-name > "$OUT/firmware.patch.gz"
+    rm "$OUT/patch"
+    cp "$OUT/firmware.bin.sig" "$OUT/firmware.patch.gz.sig"
+    merkle_root "$OUT/firmware.patch.gz" > "$OUT/firmware.patch.gz.merkle"
+    delta=$(wc -c < "$OUT/firmware.patch.gz")
+    echo "firmware.patch.gz: $delta bytes ($((delta * 100 / plain))%)"
+    files="$files $OUT/firmware.patch.gz $OUT/firmware.patch.gz.sig $OUT/firmware.patch.gz.merkle"
+fi
+
+if [ -n "${OTA_UPLOAD:-}" ]; then
+    # Word-split on purpose: one argument per file.
+    # shellcheck disable=SC2086
+    scp $files "$OTA_UPLOAD"
+fi